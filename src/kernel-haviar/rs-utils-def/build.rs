@@ -0,0 +1,99 @@
+//! Compiles a trusted kdv baseline into the binary, for `kdv check
+//! --embedded` on appliances that should not trust a manifest on disk.
+//!
+//! - `SERIALK_EMBED_BASELINE=FILE` embeds FILE as it is: a kdv JSON
+//!   baseline (e.g. from `kdv init`) or a sha256sum file.
+//! - `SERIALK_EMBED_DIR=DIR` fingerprints every regular file below DIR
+//!   into a baseline rooted at DIR, so the tree checked later must sit at
+//!   the same path unless `kdv check --embedded --root` says otherwise.
+//!   Its `created_at` comes from `SOURCE_DATE_EPOCH` (default 0), so the
+//!   same tree always embeds the same bytes and prints the same hash.
+//!
+//! With neither set the embedded baseline is empty and `--embedded`
+//! refuses to run.
+
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SERIALK_EMBED_BASELINE");
+    println!("cargo:rerun-if-env-changed=SERIALK_EMBED_DIR");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let out = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR")).join("embedded_baseline");
+    let embedded = match (env::var_os("SERIALK_EMBED_BASELINE"), env::var_os("SERIALK_EMBED_DIR")) {
+        (Some(_), Some(_)) => panic!("set SERIALK_EMBED_BASELINE or SERIALK_EMBED_DIR, not both"),
+        (Some(file), None) => {
+            println!("cargo:rerun-if-changed={}", Path::new(&file).display());
+            fs::read(&file).unwrap_or_else(|e| panic!("cannot read SERIALK_EMBED_BASELINE {:?}: {}", file, e))
+        }
+        (None, Some(dir)) => {
+            // Cargo reruns the script when anything below a directory changes.
+            println!("cargo:rerun-if-changed={}", Path::new(&dir).display());
+            fingerprint(Path::new(&dir)).unwrap_or_else(|e| panic!("cannot fingerprint SERIALK_EMBED_DIR {:?}: {}", dir, e))
+        }
+        (None, None) => Vec::new(),
+    };
+    fs::write(&out, embedded).expect("cannot write the embedded baseline");
+}
+
+/// A version 1 kdv baseline of the regular files below `dir`, with paths
+/// relative to it, as `kdv init --root DIR DIR` would write it.
+fn fingerprint(dir: &Path) -> io::Result<Vec<u8>> {
+    let root = fs::canonicalize(dir)?;
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(&root) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&root).expect("walked below the root");
+        let path = relative
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not UTF-8", relative)))?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut fs::File::open(entry.path())?, &mut hasher)?;
+        let digest: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+        entries.push(serde_json::json!({
+            "path": path,
+            "size": size,
+            "algorithm": "sha256",
+            "digest": digest,
+        }));
+    }
+    entries.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+    let epoch = env::var("SOURCE_DATE_EPOCH").ok().and_then(|secs| secs.parse().ok()).unwrap_or(0);
+    let baseline = serde_json::json!({
+        "version": 1,
+        "created_at": timestamp(epoch),
+        "root": root.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "root is not UTF-8"))?,
+        "entries": entries,
+    });
+    Ok(serde_json::to_vec_pretty(&baseline)?)
+}
+
+/// `secs` since the epoch as `YYYY-MM-DDTHH:MM:SSZ`, like `hfs_log::timestamp`.
+fn timestamp(secs: u64) -> String {
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
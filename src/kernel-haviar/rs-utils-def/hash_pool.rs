@@ -0,0 +1,239 @@
+use rayon::ThreadPool;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Files hashed at once unless `--jobs` says otherwise: one per core, but
+/// no more than 4, so a bulk pass leaves a busy host some disk and CPU.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get()).min(4)
+}
+
+/// A pool of `jobs` hashing threads, started on first use and shared by
+/// every later caller asking for as many.
+pub fn pool(jobs: usize) -> Result<Arc<ThreadPool>, String> {
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+    let mut pools = POOLS.get_or_init(Default::default).lock().unwrap();
+    if let Some(pool) = pools.get(&jobs) {
+        return Ok(pool.clone());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| format!("Cannot start {} hashing threads: {}", jobs, e))?;
+    let pool = Arc::new(pool);
+    pools.insert(jobs, pool.clone());
+    Ok(pool)
+}
+
+/// Token bucket shared by every hashing thread, filled at `rate` bytes per
+/// second. A read takes what it got from the bucket afterwards and may
+/// leave it in debt; the reader then sleeps the debt off, so the threads
+/// together stay at the rate. It holds at most a tenth of a second's worth
+/// (and at least one read), so an idle spell does not buy a burst.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    burst: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1);
+        Self {
+            rate,
+            burst: (rate as f64 / 10.0).max(64.0 * 1024.0),
+            bucket: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    /// Charges `bytes` to the bucket and sleeps until they are paid for.
+    pub fn take(&self, bytes: u64) {
+        let debt = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (tokens, refilled) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * self.rate as f64).min(self.burst);
+            *refilled = now;
+            *tokens -= bytes as f64;
+            -*tokens
+        };
+        if debt > 0.0 {
+            thread::sleep(Duration::from_secs_f64(debt / self.rate as f64));
+        }
+    }
+}
+
+/// Bytes read and time spent hashing over a whole run, possibly several
+/// passes, for the summary line operators tune `--jobs` and
+/// `--max-bandwidth` by.
+#[derive(Debug, Default)]
+pub struct Throughput {
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Throughput {
+    pub fn add(&self, bytes: u64, elapsed: Duration) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes() as f64 / self.elapsed().as_secs_f64().max(0.001)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "bytes": self.bytes(),
+            "seconds": self.elapsed().as_secs_f64(),
+            "bytes_per_sec": self.bytes_per_sec().round() as u64,
+        })
+    }
+}
+
+impl std::fmt::Display for Throughput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "Read {:.1} MiB in {:.1}s, {:.1} MiB/s",
+            self.bytes() as f64 / MIB,
+            self.elapsed().as_secs_f64(),
+            self.bytes_per_sec() / MIB
+        )
+    }
+}
+
+/// The I/O scheduling class hashing threads drop to with `--ionice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    /// Disk time only when nothing else wants it.
+    Idle,
+    /// The lowest level of the default class.
+    BestEffort,
+}
+
+impl std::str::FromStr for IoClass {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "idle" => Ok(IoClass::Idle),
+            "best-effort" => Ok(IoClass::BestEffort),
+            _ => Err(format!("unknown I/O class {}", name)),
+        }
+    }
+}
+
+/// CPU and I/O priority for hashing in the background.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Priority {
+    /// Niceness, 0 to 19.
+    pub nice: Option<i32>,
+    pub ionice: Option<IoClass>,
+}
+
+impl Priority {
+    /// Lowers the calling thread to this priority. On Linux both settings
+    /// are per thread and carry over to threads it starts afterwards, so
+    /// calling this before a pool is built covers the whole pool.
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self) -> Result<(), String> {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+                return Err(format!("cannot set nice {}: {}", nice, io::Error::last_os_error()));
+            }
+        }
+        if let Some(class) = self.ionice {
+            let ioprio = match class {
+                IoClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+                IoClass::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | 7,
+            };
+            if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+                return Err(format!("cannot set the I/O class: {}", io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self) -> Result<(), String> {
+        match (self.nice, self.ionice) {
+            (None, None) => Ok(()),
+            _ => Err("--nice and --ionice are only supported on Linux".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Reads `path` in 64 KiB chunks as the hashing threads do, charging
+    /// each to `throttle`.
+    fn read_throttled(path: &std::path::Path, throttle: &Throttle) -> u64 {
+        let mut file = std::fs::File::open(path).unwrap();
+        let mut buf = vec![0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            let read = file.read(&mut buf).unwrap();
+            if read == 0 {
+                return total;
+            }
+            throttle.take(read as u64);
+            total += read as u64;
+        }
+    }
+
+    #[test]
+    fn test_throttle_holds_reads_near_the_cap() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let chunk: Vec<u8> = (0..MIB).map(|i| (i % 251) as u8).collect();
+        for _ in 0..3 {
+            file.write_all(&chunk).unwrap();
+        }
+        let throttle = Throttle::new(4 * MIB);
+
+        let started = Instant::now();
+        assert_eq!(read_throttled(file.path(), &throttle), 3 * MIB);
+        let rate = (3 * MIB) as f64 / started.elapsed().as_secs_f64();
+        assert!(rate <= 4.4 * MIB as f64, "{:.0} bytes/s over a cap of {}", rate, 4 * MIB);
+        assert!(rate >= 3.2 * MIB as f64, "{:.0} bytes/s well under a cap of {}", rate, 4 * MIB);
+    }
+
+    #[test]
+    fn test_threads_share_one_cap() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&vec![7; MIB as usize]).unwrap();
+        let throttle = Throttle::new(4 * MIB);
+
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| read_throttled(file.path(), &throttle));
+            }
+        });
+        let rate = (3 * MIB) as f64 / started.elapsed().as_secs_f64();
+        assert!(rate <= 4.4 * MIB as f64, "{:.0} bytes/s over a cap of {}", rate, 4 * MIB);
+    }
+}
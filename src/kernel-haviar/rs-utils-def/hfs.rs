@@ -1,142 +1,4781 @@
+use notify::{recommended_watcher, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::process::Command;
-use tokio::time::{sleep, Duration};
-use regex::Regex;
+use tokio::time::{sleep, Duration, Instant};
+use regex::RegexSet;
+use crate::serialk_watcher::Severity;
+use crate::hfs_log::HfsLog;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProcessInfo {
     pub pid: i32,
     pub command: String,
+    /// The arguments joined by spaces; empty for kernel threads or when unknown.
+    pub cmdline: String,
+    /// Real user id, where the platform reports one.
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    /// Start time (Linux: clock ticks since boot, macOS: microseconds since
+    /// the epoch); tells a reused PID from the process it was reported under.
+    pub start_time: Option<u64>,
+    /// Remote Desktop session (Windows only).
+    pub session: Option<u32>,
+    /// Full path of the executable (Linux and macOS, where readable).
+    pub path: Option<String>,
 }
 
-pub struct HfsHunter<F>
-where
-    F: Fn(String) + Send + Sync + 'static,
-{
-    pub forbidden_patterns: Vec<String>,
-    pub scan_interval: Duration,
-    pub on_violation: F,
+impl ProcessInfo {
+    /// Stands in where a violation has no process to name.
+    fn nobody() -> Self {
+        Self {
+            pid: 0,
+            command: String::new(),
+            cmdline: String::new(),
+            uid: None,
+            user: None,
+            start_time: None,
+            session: None,
+            path: None,
+        }
+    }
 }
 
-impl<F> HfsHunter<F>
+/// What HFS does to a process matching a forbidden pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HfsAction {
+    LogOnly,
+    /// SIGKILL, or TerminateProcess on Windows.
+    Kill,
+    /// SIGSTOP; the process can be inspected and continued with SIGCONT.
+    Suspend,
+}
+
+impl HfsAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HfsAction::LogOnly => "log",
+            HfsAction::Kill => "kill",
+            HfsAction::Suspend => "suspend",
+        }
+    }
+}
+
+impl std::str::FromStr for HfsAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(HfsAction::LogOnly),
+            "kill" => Ok(HfsAction::Kill),
+            "suspend" => Ok(HfsAction::Suspend),
+            other => Err(format!("unknown HFS action {:?} (expected log, kill or suspend)", other)),
+        }
+    }
+}
+
+/// What the patterns are matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchTarget {
+    /// The executable name only.
+    Comm,
+    /// The full command line, so `python3 -m frida_tools.repl` matches `frida`.
+    Cmdline,
+    /// The executable's full path, e.g. anything under `/usr/local/Cellar/radare2`.
+    Path,
+    /// Name, then path, then command line.
+    Both,
+}
+
+impl std::str::FromStr for MatchTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comm" => Ok(MatchTarget::Comm),
+            "cmdline" => Ok(MatchTarget::Cmdline),
+            "path" => Ok(MatchTarget::Path),
+            "both" => Ok(MatchTarget::Both),
+            other => Err(format!("unknown match target {:?} (expected comm, cmdline, path or both)", other)),
+        }
+    }
+}
+
+/// Violation messages cut command lines longer than this by default.
+pub const DEFAULT_CMDLINE_WIDTH: usize = 200;
+
+/// How many ancestors of an offending process are reported by default.
+pub const DEFAULT_CHAIN_DEPTH: usize = 10;
+
+/// Reaction commands still running past this many are not started, so a
+/// flood of matches cannot fork without bound.
+pub const MAX_RUNNING_COMMANDS: usize = 8;
+
+/// Which check fired the violation callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ViolationKind {
+    /// A process in the table matched a forbidden pattern.
+    #[serde(rename = "process")]
+    ForbiddenProcess,
+    /// A debugger is attached to this process itself.
+    #[serde(rename = "self-trace")]
+    TracerAttached,
+    /// A library matching a forbidden pattern is mapped into a process.
+    #[serde(rename = "maps")]
+    InjectedLibrary,
+    /// The process table could not be read, so nothing could be checked.
+    #[serde(rename = "monitor-error")]
+    MonitorError,
+    /// A process runs with a library-injection variable such as LD_PRELOAD.
+    #[serde(rename = "env")]
+    InjectedEnvironment,
+    /// Something listens on a port debug servers use, whatever it is called.
+    #[serde(rename = "port")]
+    DebugListener,
+    /// Another process has one of the protected files open.
+    #[serde(rename = "fd")]
+    OpenProtectedFile,
+}
+
+/// Environment variables that make the dynamic loader inject a library.
+pub const INJECTION_VARIABLES: &[&str] = &["LD_PRELOAD", "LD_AUDIT", "DYLD_INSERT_LIBRARIES"];
+
+/// A process and its injection variables, `None` when they may not be read.
+pub type EnvReading = (ProcessInfo, Option<Vec<(String, String)>>);
+
+/// Ports `--scan-ports` watches by default: gdbserver (1234, 2345),
+/// frida-server (27042) and the IDA Pro debug server (23946).
+pub const DEFAULT_DEBUG_PORTS: &[u16] = &[1234, 2345, 27042, 23946];
+
+/// A listening TCP port and the process holding the socket, `None` when
+/// it could not be found (another user's process, without root).
+pub type Listener = (u16, Option<i32>);
+
+/// How a file descriptor was opened, from the `flags` line of its fdinfo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FdMode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl FdMode {
+    /// The access mode in octal `open(2)` flags, as fdinfo prints them.
+    pub fn from_flags(flags: &str) -> Option<Self> {
+        const O_ACCMODE: u32 = 0o3;
+        match u32::from_str_radix(flags.trim(), 8).ok()? & O_ACCMODE {
+            0 => Some(FdMode::Read),
+            1 => Some(FdMode::Write),
+            2 => Some(FdMode::ReadWrite),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FdMode::Read => "read",
+            FdMode::Write => "write",
+            FdMode::ReadWrite => "read-write",
+        }
+    }
+}
+
+/// A descriptor a process holds on one of the protected files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenFile {
+    pub fd: i32,
+    pub path: PathBuf,
+    pub mode: FdMode,
+}
+
+/// A process and its descriptors on protected files, `None` when they may
+/// not be read.
+pub type FdReading = (ProcessInfo, Option<Vec<OpenFile>>);
+
+/// How often, in process scans, protected files are checked by default.
+pub const DEFAULT_FDS_EVERY: u64 = 6;
+
+/// One detection, as handed to the violation callback and printed by
+/// `serialkiller hfs --format json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    /// The offending process; for a tracer the platform may not say.
+    pub pid: Option<i32>,
+    pub command: String,
+    pub cmdline: String,
+    /// The forbidden pattern that matched, as the user wrote it.
+    pub pattern: Option<String>,
+    /// The matched rule's action (or `--action`), e.g. "kill" or "command:alert.sh".
+    pub action: Option<String>,
+    pub severity: Option<Severity>,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    /// The executable's full path, where known.
+    pub path: Option<String>,
+    /// The mapped file, for `ViolationKind::InjectedLibrary`.
+    pub library: Option<String>,
+    /// The injection variable and its value, for `ViolationKind::InjectedEnvironment`.
+    pub variable: Option<String>,
+    pub value: Option<String>,
+    /// The listening port, for `ViolationKind::DebugListener`.
+    pub port: Option<u16>,
+    /// The protected file, descriptor and mode, for `ViolationKind::OpenProtectedFile`.
+    pub file: Option<String>,
+    pub fd: Option<i32>,
+    pub fd_mode: Option<FdMode>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// What the action did, e.g. "killed"; absent in log-only mode.
+    pub outcome: Option<String>,
+    /// Parent, grandparent and so on, as far as the chain could be walked.
+    pub parents: Vec<Ancestor>,
+    /// Why enumeration failed, for `ViolationKind::MonitorError`.
+    pub error: Option<String>,
+    /// The match count under an escalation rule, if the pattern has one.
+    pub escalation: Option<EscalationState>,
+    /// Set on the reminder that a reported process still matches: seconds
+    /// since it was first reported.
+    pub reminder_secs: Option<u64>,
+    #[serde(skip)]
+    cmdline_width: usize,
+}
+
+impl Violation {
+    pub fn new(kind: ViolationKind, process: &ProcessInfo, pattern: Option<&str>, cmdline_width: usize) -> Self {
+        Self {
+            kind,
+            pid: Some(process.pid),
+            command: process.command.clone(),
+            cmdline: process.cmdline.clone(),
+            pattern: pattern.map(str::to_string),
+            action: None,
+            severity: None,
+            uid: process.uid,
+            user: process.user.clone(),
+            path: process.path.clone(),
+            library: None,
+            variable: None,
+            value: None,
+            port: None,
+            file: None,
+            fd: None,
+            fd_mode: None,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            outcome: None,
+            parents: Vec::new(),
+            error: None,
+            escalation: None,
+            reminder_secs: None,
+            cmdline_width,
+        }
+    }
+
+    fn monitor_error(error: String) -> Self {
+        let mut violation = Violation::new(ViolationKind::MonitorError, &ProcessInfo::nobody(), None, 0);
+        violation.pid = None;
+        violation.error = Some(error);
+        violation
+    }
+
+    /// ", USER=alice(1000)", or just the uid when it has no name.
+    fn owner(&self) -> String {
+        match (&self.user, self.uid) {
+            (Some(user), Some(uid)) => format!(", USER={}({})", user, uid),
+            (Some(user), None) => format!(", USER={}", user),
+            (None, Some(uid)) => format!(", USER={}", uid),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// One process in a violation's parent chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ancestor {
+    pub pid: i32,
+    pub command: String,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+}
+
+impl fmt::Display for Ancestor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}({}, {})", self.command, self.pid, user),
+            None => write!(f, "{}({})", self.command, self.pid),
+        }
+    }
+}
+
+/// The one-line report HFS has always printed.
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, self.pid) {
+            (ViolationKind::ForbiddenProcess, pid) => {
+                let shown = if self.cmdline.is_empty() { &self.command } else { &self.cmdline };
+                let seen = match self.reminder_secs {
+                    Some(secs) => format!("still present after {} minutes", secs / 60),
+                    None => "detected".to_string(),
+                };
+                write!(
+                    f,
+                    "[HFS] Unauthorized process {}: PID={}{}, CMD={}",
+                    seen,
+                    pid.unwrap_or(0),
+                    self.owner(),
+                    truncate(shown, self.cmdline_width)
+                )?;
+            }
+            (ViolationKind::TracerAttached, Some(pid)) => {
+                write!(f, "[HFS] Debugger attached to this process: TracerPid={}, CMD={}", pid, self.command)?;
+            }
+            (ViolationKind::TracerAttached, None) => write!(f, "[HFS] Debugger attached to this process")?,
+            (ViolationKind::InjectedLibrary, pid) => write!(
+                f,
+                "[HFS] Forbidden library mapped: LIB={}, PID={}{}, CMD={}",
+                self.library.as_deref().unwrap_or_default(),
+                pid.unwrap_or(0),
+                self.owner(),
+                self.command
+            )?,
+            (ViolationKind::InjectedEnvironment, pid) => write!(
+                f,
+                "[HFS] Injection variable set: {}={}, PID={}{}, CMD={}",
+                self.variable.as_deref().unwrap_or_default(),
+                self.value.as_deref().unwrap_or_default(),
+                pid.unwrap_or(0),
+                self.owner(),
+                self.command
+            )?,
+            (ViolationKind::DebugListener, Some(pid)) => write!(
+                f,
+                "[HFS] Debugger port listening: PORT={}, PID={}{}, CMD={}",
+                self.port.unwrap_or_default(),
+                pid,
+                self.owner(),
+                truncate(if self.cmdline.is_empty() { &self.command } else { &self.cmdline }, self.cmdline_width)
+            )?,
+            (ViolationKind::DebugListener, None) => write!(
+                f,
+                "[HFS] Debugger port listening: PORT={}, owner unknown",
+                self.port.unwrap_or_default()
+            )?,
+            (ViolationKind::OpenProtectedFile, pid) => write!(
+                f,
+                "[HFS] Protected file open: FILE={} ({}, fd {}), PID={}{}, CMD={}",
+                self.file.as_deref().unwrap_or_default(),
+                self.fd_mode.map(FdMode::as_str).unwrap_or_default(),
+                self.fd.unwrap_or_default(),
+                pid.unwrap_or(0),
+                self.owner(),
+                self.command
+            )?,
+            (ViolationKind::MonitorError, _) => write!(
+                f,
+                "[HFS] Cannot list processes, nothing was checked: {}",
+                self.error.as_deref().unwrap_or_default()
+            )?,
+        }
+        if let (Some(pattern), Some(action)) = (&self.pattern, &self.action) {
+            write!(f, ", RULE={}, ACTION={}", pattern, action)?;
+        }
+        if !self.parents.is_empty() {
+            let chain: Vec<String> = self.parents.iter().map(Ancestor::to_string).collect();
+            write!(f, ", PARENTS={}", chain.join(" <- "))?;
+        }
+        if let Some(state) = &self.escalation {
+            let stage = if state.escalated { "escalated" } else { "logged" };
+            write!(f, ", COUNT={} in {}s ({})", state.count, state.window_secs, stage)?;
+        }
+        match &self.outcome {
+            Some(outcome) => write!(f, " ({})", outcome),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Adapts a callback taking the formatted message, as `on_violation` took
+/// before violations were structured.
+pub fn message_callback<G>(callback: G) -> impl Fn(Violation) + Send + Sync + 'static
 where
-    F: Fn(String) + Send + Sync + 'static,
+    G: Fn(String) + Send + Sync + 'static,
 {
-    pub fn new(forbidden_patterns: Vec<String>, scan_interval: Duration, on_violation: F) -> Self {
+    move |violation| callback(violation.to_string())
+}
+
+/// Which processes `--scan-maps` reads /proc/<pid>/maps for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapsScope {
+    Off,
+    /// Only this process, the usual target of an injected frida-agent.
+    SelfOnly,
+    All,
+}
+
+/// Maps scanning reads a file per process, so by default it runs on every
+/// sixth scan only.
+pub const DEFAULT_MAPS_EVERY: u64 = 6;
+
+/// Who is tracing us, as far as the platform tells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracerInfo {
+    pub pid: Option<i32>,
+    pub command: Option<String>,
+}
+
+/// How acting on a matched process went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionOutcome {
+    Done,
+    PermissionDenied,
+    /// The process exited between the scan and the signal.
+    Gone,
+    /// Our own process or one of its ancestors; never acted on.
+    Protected,
+    Failed(String),
+}
+
+/// Why a scan could not run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HfsError {
+    /// The process table could not be read.
+    Enumeration(String),
+}
+
+impl fmt::Display for HfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HfsError::Enumeration(e) => write!(f, "cannot list processes: {}", e),
+        }
+    }
+}
+
+/// `serialkiller hfs --once` exits 0 on a clean scan, 2 when something was
+/// found and 1 when the scan itself failed.
+pub fn once_exit_code(result: &Result<Vec<Violation>, HfsError>) -> i32 {
+    match result {
+        Ok(violations) if violations.is_empty() => 0,
+        Ok(_) => 2,
+        Err(_) => 1,
+    }
+}
+
+/// How a plain-text pattern (one without regex syntax) is matched. Regex
+/// patterns are always used as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LiteralMatch {
+    /// Anywhere in the text: `gdb` also matches `gdbus`.
+    Substring,
+    /// As a whole word: `gdb` matches `gdb -p 1` but not `gdbus`. Anything
+    /// but a letter or digit ends a word, so `frida` still matches
+    /// `frida_tools` and `frida-server`; a pattern that starts or ends with
+    /// such a character, like `/Cellar/radare2/`, is open at that end.
+    #[default]
+    WordBoundary,
+    /// Only as the executable itself: the basename of the first token.
+    Exact,
+}
+
+/// How `CommandPatterns::compile` treats the patterns it is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PatternOptions {
+    /// Match patterns that are not valid regexes as plain text.
+    pub literal: bool,
+    pub literal_match: LiteralMatch,
+    /// Plain-text patterns ignore case unless this is set.
+    pub case_sensitive: bool,
+}
+
+impl PatternOptions {
+    /// The regex a plain-text pattern is matched with.
+    fn literal_regex(&self, text: &str) -> String {
+        let escaped = regex::escape(text);
+        let regex = match self.literal_match {
+            LiteralMatch::Substring => escaped,
+            LiteralMatch::WordBoundary => {
+                let open = |c: Option<char>| !c.is_some_and(char::is_alphanumeric);
+                let start = if open(text.chars().next()) { "" } else { "(?:^|[^[:alnum:]])" };
+                let end = if open(text.chars().last()) { "" } else { "(?:[^[:alnum:]]|$)" };
+                format!("{}{}{}", start, escaped, end)
+            }
+            LiteralMatch::Exact => format!(r"^(?:\S*[/\\])?{}(?:\.exe)?(?:\s|$)", escaped),
+        };
+        if self.case_sensitive {
+            regex
+        } else {
+            format!("(?i){}", regex)
+        }
+    }
+}
+
+/// Whether `pattern` has no regex syntax besides `.`, which in a command
+/// name such as `python3.11` is meant literally.
+fn is_plain(pattern: &str) -> bool {
+    !pattern.chars().any(|c| r"\+*?()|[]{}^$".contains(c))
+}
+
+/// What a rule does on a match, in place of `--action`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    Process(HfsAction),
+    /// Runs the command through the shell with the violation in
+    /// SERIALK_HFS_* variables; the process itself is left alone.
+    Command(String),
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAction::Process(action) => f.write_str(action.as_str()),
+            RuleAction::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+impl std::str::FromStr for RuleAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("command:") {
+            Some("") => Err("command: needs a command to run".to_string()),
+            Some(command) => Ok(RuleAction::Command(command.to_string())),
+            None => s.parse().map(RuleAction::Process).map_err(|e: String| e.replace("or suspend", "suspend or command:CMD")),
+        }
+    }
+}
+
+/// A forbidden pattern, written `PATTERN[=ACTION[@SEVERITY]]` on the command
+/// line and in patterns files (`frida=kill`, `strace=log`,
+/// `ghidra=command:/usr/local/bin/alert.sh@critical`), or as a table with
+/// `pattern`, `action` and `severity` keys in hfs.toml.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RuleSpec")]
+pub struct PatternRule {
+    pub regex: String,
+    /// `None` applies `--action`.
+    pub action: Option<RuleAction>,
+    pub severity: Severity,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleSpec {
+    Line(String),
+    Table(RuleTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleTable {
+    pattern: String,
+    action: Option<String>,
+    severity: Option<Severity>,
+}
+
+impl TryFrom<RuleSpec> for PatternRule {
+    type Error = String;
+
+    fn try_from(spec: RuleSpec) -> Result<Self, Self::Error> {
+        match spec {
+            RuleSpec::Line(line) => Ok(PatternRule::parse(&line)),
+            RuleSpec::Table(table) => Ok(PatternRule {
+                regex: table.pattern,
+                action: table.action.map(|action| action.parse()).transpose()?,
+                severity: table.severity.unwrap_or(Severity::Warning),
+            }),
+        }
+    }
+}
+
+impl PatternRule {
+    /// A bare pattern, acted on with `--action`.
+    pub fn new(regex: &str) -> Self {
         Self {
-            forbidden_patterns,
-            scan_interval,
-            on_violation,
+            regex: regex.to_string(),
+            action: None,
+            severity: Severity::Warning,
         }
     }
 
-    pub async fn start_scan(&self) {
-        loop {
-            sleep(self.scan_interval).await;
-
-            let processes = self.get_processes().await;
-
-            for process in processes {
-                for pattern in &self.forbidden_patterns {
-                    if process.command.to_lowercase().contains(&pattern.to_lowercase()) {
-                        (self.on_violation)(format!(
-                            "[HFS] Unauthorized process detected: PID={}, CMD={}",
-                            process.pid, process.command
-                        ));
-                        return;
-                    }
-                }
+    /// Splits at the first `=` followed by a valid action, so a pattern
+    /// such as `--inspect=9229` stays whole and a command may contain `=`.
+    pub fn parse(spec: &str) -> Self {
+        for (at, _) in spec.match_indices('=').filter(|(at, _)| *at > 0) {
+            let rest = &spec[at + 1..];
+            let (action, severity) = match rest.rsplit_once('@') {
+                Some((action, severity)) => match severity.parse() {
+                    Ok(severity) => (action, severity),
+                    Err(_) => (rest, Severity::Warning),
+                },
+                None => (rest, Severity::Warning),
+            };
+            if let Ok(action) = action.parse() {
+                return Self {
+                    regex: spec[..at].to_string(),
+                    action: Some(action),
+                    severity,
+                };
             }
         }
+        Self::new(spec)
     }
+}
 
-    async fn get_processes(&self) -> Vec<ProcessInfo> {
-        if cfg!(target_os = "linux") || cfg!(target_os = "macos") {
-            self.get_processes_unix().await
-        } else if cfg!(target_os = "windows") {
-            self.get_processes_windows().await
-        } else {
-            vec![]
+/// The rule as it would be written on the command line.
+impl fmt::Display for PatternRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.regex)?;
+        if let Some(action) = &self.action {
+            write!(f, "={}", action)?;
+            if self.severity != Severity::Warning {
+                write!(f, "@{}", self.severity)?;
+            }
         }
+        Ok(())
     }
+}
 
-    async fn get_processes_unix(&self) -> Vec<ProcessInfo> {
-        let output = Command::new("ps")
-            .arg("-eo")
-            .arg("pid,comm")
-            .stdout(Stdio::piped())
-            .output()
-            .await;
+/// Forbidden (or allowed) command patterns, compiled once into a single `RegexSet`.
+pub struct CommandPatterns {
+    set: RegexSet,
+    /// The same patterns with plain text matched anywhere, for library
+    /// paths and injection variables: `frida-gadget` is `libfrida-gadget.so`.
+    library_set: RegexSet,
+    rules: Vec<PatternRule>,
+    sources: Vec<String>,
+}
 
-        match output {
-            Ok(output) if output.status.success() => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let mut processes = Vec::new();
+impl CommandPatterns {
+    /// Compiles plain-text patterns as `options` says and every other one as
+    /// a regex. With `options.literal`, patterns that are not valid regexes
+    /// are matched as plain text instead of rejected.
+    pub fn compile(patterns: &[String], options: PatternOptions) -> Result<Self, String> {
+        let rules: Vec<PatternRule> = patterns.iter().map(|pattern| PatternRule::new(pattern)).collect();
+        Self::compile_rules(&rules, options)
+    }
 
-                for line in stdout.lines().skip(1) {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
-                    }
-                    let parts: Vec<&str> = line.split_whitespace().collect();
-                    if parts.len() < 2 {
-                        continue;
-                    }
-                    if let Ok(pid) = parts[0].parse::<i32>() {
-                        let cmd = parts[1..].join(" ");
-                        processes.push(ProcessInfo { pid, command: cmd });
-                    }
-                }
-                processes
+    /// Like `compile`, for forbidden patterns with their own actions.
+    pub fn compile_rules(rules: &[PatternRule], options: PatternOptions) -> Result<Self, String> {
+        let substring = PatternOptions {
+            literal_match: LiteralMatch::Substring,
+            ..options
+        };
+        let mut compiled = Vec::with_capacity(rules.len());
+        let mut library = Vec::with_capacity(rules.len());
+        for pattern in rules.iter().map(|rule| &rule.regex) {
+            let plain = is_plain(pattern)
+                || match regex_syntax::Parser::new().parse(pattern) {
+                    Ok(_) => false,
+                    Err(_) if options.literal => true,
+                    Err(e) => return Err(describe_regex_error(pattern, &e)),
+                };
+            if plain {
+                compiled.push(options.literal_regex(pattern));
+                library.push(substring.literal_regex(pattern));
+            } else {
+                compiled.push(pattern.clone());
+                library.push(pattern.clone());
             }
-            _ => vec![],
         }
+        let set = |compiled: &[String]| RegexSet::new(compiled).map_err(|e| format!("Invalid HFS pattern set: {}", e));
+        Ok(Self {
+            set: set(&compiled)?,
+            library_set: set(&library)?,
+            rules: rules.to_vec(),
+            sources: rules.iter().map(PatternRule::to_string).collect(),
+        })
     }
 
-    async fn get_processes_windows(&self) -> Vec<ProcessInfo> {
-        let output = Command::new("tasklist")
-            .stdout(Stdio::piped())
-            .output()
-            .await;
-
-        match output {
-            Ok(output) if output.status.success() => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                let mut processes = Vec::new();
-                for line in stdout.lines().skip(3) {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
-                    }
-                    let re = Regex::new(r"^(\S+)\s+(\d+)").unwrap();
-                    if let Some(caps) = re.captures(line) {
-                        let cmd = caps.get(1).map_or("", |m| m.as_str());
-                        if let Ok(pid) = caps.get(2).unwrap().as_str().parse::<i32>() {
-                            processes.push(ProcessInfo {
-                                pid,
-                                command: cmd.to_string(),
-                            });
-                        }
-                    }
-                }
-                processes
+    /// The rules as they would be written on the command line.
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// The first pattern matching `command`, as the user wrote it.
+    pub fn matching(&self, command: &str) -> Option<&str> {
+        self.matching_rule(command).map(|rule| rule.regex.as_str())
+    }
+
+    /// The first rule matching `command`.
+    pub fn matching_rule(&self, command: &str) -> Option<&PatternRule> {
+        self.set.matches(command).iter().next().map(|index| &self.rules[index])
+    }
+
+    /// Like `matching_rule`, for a mapped library or an injection variable.
+    pub fn matching_library(&self, library: &str) -> Option<&PatternRule> {
+        self.library_set.matches(library).iter().next().map(|index| &self.rules[index])
+    }
+}
+
+/// Forbidden patterns from a file: one rule per line, as on the command
+/// line; blank lines and lines starting with '#' are skipped. A file with
+/// `command:` rules must be owned by root and writable by nobody else.
+pub fn read_patterns_file(path: &Path) -> Result<Vec<PatternRule>, String> {
+    let cannot_read = |e: std::io::Error| format!("Cannot read patterns file {}: {}", path.display(), e);
+    let mut file = fs::File::open(path).map_err(cannot_read)?;
+    let mut text = String::new();
+    std::io::Read::read_to_string(&mut file, &mut text).map_err(cannot_read)?;
+    let rules: Vec<PatternRule> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PatternRule::parse)
+        .collect();
+    if rules.iter().any(|rule| matches!(rule.action, Some(RuleAction::Command(_)))) {
+        check_command_owner(path, &file.metadata().map_err(cannot_read)?)?;
+    }
+    Ok(rules)
+}
+
+/// Whoever can write a file of `command:` rules can run commands as us.
+#[cfg(unix)]
+fn check_command_owner(path: &Path, meta: &fs::Metadata) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mode = meta.mode() & 0o777;
+    if meta.uid() != 0 || mode & 0o022 != 0 {
+        return Err(format!(
+            "{} has command: rules, so it must be owned by root and not writable by group or others (uid {}, mode {:o})",
+            path.display(),
+            meta.uid(),
+            mode
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_command_owner(_path: &Path, _meta: &fs::Metadata) -> Result<(), String> {
+    Ok(())
+}
+
+/// The (added, removed) patterns of a reload.
+pub type PatternDelta = (Vec<String>, Vec<String>);
+
+/// A `--patterns-file` under watch, and the patterns given alongside it.
+struct PatternsFile {
+    path: PathBuf,
+    extra: Vec<PatternRule>,
+    options: PatternOptions,
+    /// Locked only to keep the hunter `Sync`.
+    changes: Mutex<Receiver<notify::Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+fn describe_regex_error(pattern: &str, error: &regex_syntax::Error) -> String {
+    let (span, kind) = match error {
+        regex_syntax::Error::Parse(e) => (e.span(), e.kind().to_string()),
+        regex_syntax::Error::Translate(e) => (e.span(), e.kind().to_string()),
+        other => return format!("Invalid HFS pattern {:?}: {}", pattern, other),
+    };
+    format!(
+        "Invalid HFS pattern {:?} at position {}: {} (pass --literal to match it as plain text)",
+        pattern, span.start.column, kind
+    )
+}
+
+/// Processes HFS must leave alone, e.g. strace run by our own CI runners.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HfsAllowlist {
+    #[serde(default)]
+    pub pids: Vec<i32>,
+    /// User names or numeric uids.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Command regexes, with the same syntax as the forbidden patterns.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+/// `serialkiller hfs --config hfs.toml`; command-line values extend it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HfsConfig {
+    #[serde(default)]
+    pub patterns: Vec<PatternRule>,
+    #[serde(default)]
+    pub allow: HfsAllowlist,
+    #[serde(default)]
+    pub escalation: Option<HfsEscalation>,
+    /// Files no other process may hold open, e.g. our pself and binary.
+    #[serde(default)]
+    pub protect: Vec<PathBuf>,
+    /// Under `serialk-watcher`, also protect every file it watches.
+    #[serde(default)]
+    pub protect_watched: bool,
+    /// Check the protected files on every Nth scan [default: 6].
+    pub fds_every: Option<u64>,
+    /// Seconds between scans [default: 5].
+    pub interval_secs: Option<u64>,
+    /// Up to this many seconds added at random to each wait [default: 0].
+    pub jitter_secs: Option<u64>,
+    #[serde(default)]
+    pub cooldown: Option<HfsCooldown>,
+}
+
+/// `[escalation]`: log the first matches of a pattern within a window and
+/// only then apply `--action`, e.g. "log twice in ten minutes, then kill".
+/// Patterns without a rule are acted on at once.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HfsEscalation {
+    /// The rule for every pattern not listed under `patterns`.
+    pub log_first: Option<u32>,
+    #[serde(default = "default_escalation_window")]
+    pub window_secs: u64,
+    /// Per-pattern rules, keyed by the pattern as written.
+    #[serde(default)]
+    pub patterns: HashMap<String, EscalationRule>,
+    /// Per-user rules, keyed by user name or uid. They win over pattern
+    /// rules, and each user's matches are counted apart.
+    #[serde(default)]
+    pub users: HashMap<String, EscalationRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationRule {
+    /// Matches within the window that are only logged.
+    pub log_first: u32,
+    #[serde(default = "default_escalation_window")]
+    pub window_secs: u64,
+}
+
+fn default_escalation_window() -> u64 {
+    600
+}
+
+/// Where a match stands against its escalation rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EscalationState {
+    /// Matches of the pattern within the window, this one included.
+    pub count: usize,
+    /// Of those, matches by this PID.
+    pub pid_count: usize,
+    pub log_first: u32,
+    pub window_secs: u64,
+    /// The `users` rule applied, if one was.
+    pub user: Option<String>,
+    /// Whether this match got the configured action.
+    pub escalated: bool,
+}
+
+/// Sliding-window match counts per pattern (and user, under a user rule)
+/// and per (pattern, PID).
+#[derive(Debug, Default)]
+pub struct Escalation {
+    config: HfsEscalation,
+    by_pattern: HashMap<(String, Option<String>), VecDeque<Instant>>,
+    by_pid: HashMap<(String, i32), VecDeque<Instant>>,
+}
+
+impl Escalation {
+    pub fn new(config: HfsEscalation) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// The rule for a match, with the `users` key it came from.
+    fn rule(&self, pattern: &str, user: Option<&str>, uid: Option<u32>) -> Option<(EscalationRule, Option<String>)> {
+        let by_user = user
+            .map(str::to_string)
+            .into_iter()
+            .chain(uid.map(|uid| uid.to_string()))
+            .find_map(|key| Some((self.config.users.get(&key)?.clone(), Some(key))));
+        by_user.or_else(|| {
+            let rule = self.config.patterns.get(pattern).cloned().or_else(|| {
+                self.config.log_first.map(|log_first| EscalationRule {
+                    log_first,
+                    window_secs: self.config.window_secs,
+                })
+            })?;
+            Some((rule, None))
+        })
+    }
+
+    /// Counts a match of `pattern` by `pid`, owned by `user`/`uid`, at `now`;
+    /// `None` if no rule applies and the match is acted on at once.
+    pub fn record(
+        &mut self,
+        pattern: &str,
+        pid: i32,
+        user: Option<&str>,
+        uid: Option<u32>,
+        now: Instant,
+    ) -> Option<EscalationState> {
+        let (rule, rule_user) = self.rule(pattern, user, uid)?;
+        let window = Duration::from_secs(rule.window_secs);
+        let count = |times: &mut VecDeque<Instant>| {
+            while times.front().is_some_and(|t| now.duration_since(*t) >= window) {
+                times.pop_front();
             }
-            _ => vec![],
+            times.push_back(now);
+            times.len()
+        };
+        let total = count(self.by_pattern.entry((pattern.to_string(), rule_user.clone())).or_default());
+        let pid_count = count(self.by_pid.entry((pattern.to_string(), pid)).or_default());
+        // Keep the per-PID map from growing with every short-lived process.
+        self.by_pid
+            .retain(|_, times| times.back().is_some_and(|t| now.duration_since(*t) < window));
+        Some(EscalationState {
+            count: total,
+            pid_count,
+            log_first: rule.log_first,
+            window_secs: rule.window_secs,
+            user: rule_user,
+            escalated: total > rule.log_first as usize,
+        })
+    }
+}
+
+/// `[cooldown]`: what happens when a reported process keeps matching.
+/// Unlike the watcher's per-path alert rate, this is per (PID, pattern):
+/// the process is reported once, its repeats are counted, and it is
+/// reported once more if still there `remind_after_secs` later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct HfsCooldown {
+    /// 0 turns the reminder off.
+    pub remind_after_secs: u64,
+    /// How often an action refused with EPERM is tried again before HFS
+    /// gives up and raises the violation's severity.
+    pub retries: u32,
+    /// Wait before the first retry; each later one waits twice as long.
+    pub retry_backoff_secs: u64,
+}
+
+impl Default for HfsCooldown {
+    fn default() -> Self {
+        Self {
+            remind_after_secs: 600,
+            retries: 3,
+            retry_backoff_secs: 5,
         }
     }
 }
 
-/// ✅ Dışa açık HFS tarayıcı başlatıcısı
-/// forbidden_keywords: yasaklanmış komut içerikleri (örn: vec!["gdb", "strace"])
-pub fn start_hfs_monitor(forbidden_keywords: &[String]) {
-    let patterns = forbidden_keywords.to_vec();
-    let interval = Duration::from_secs(5);
+/// How a process matching a pattern it was already seen matching is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Not seen before: report and act.
+    First,
+    /// Nothing due: only counted.
+    Suppressed,
+    /// Still matching this long after the first report.
+    Reminder(Duration),
+    /// The refused action is due to be tried again, for the nth time.
+    Retry(u32),
+}
 
-    let hunter = HfsHunter::new(patterns, interval, |msg| {
-        println!("{}", msg);
-        // Buraya başka işlemler de ekleyebilirsin (örneğin işlem sonlandırma)
-    });
+#[derive(Debug)]
+struct Sighting {
+    first: Instant,
+    reminded: bool,
+    refused: u32,
+    retry_at: Option<Instant>,
+}
 
-    tokio::spawn(async move {
-        hunter.start_scan().await;
-    });
+/// Sightings per (pid, start time, pattern), kept until the process exits.
+#[derive(Debug, Default)]
+pub struct Repeats {
+    config: HfsCooldown,
+    seen: HashMap<(i32, Option<u64>, String), Sighting>,
+}
+
+impl Repeats {
+    pub fn new(config: HfsCooldown) -> Self {
+        Self {
+            config,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Records a match at `now`. A due retry wins over a due reminder.
+    pub fn sighting(&mut self, key: &(i32, Option<u64>, String), now: Instant) -> Repeat {
+        let Some(seen) = self.seen.get_mut(key) else {
+            let first = Sighting {
+                first: now,
+                reminded: false,
+                refused: 0,
+                retry_at: None,
+            };
+            self.seen.insert(key.clone(), first);
+            return Repeat::First;
+        };
+        if seen.retry_at.is_some_and(|at| now >= at) {
+            seen.retry_at = None;
+            return Repeat::Retry(seen.refused);
+        }
+        let elapsed = now.duration_since(seen.first);
+        let remind_after = self.config.remind_after_secs;
+        if !seen.reminded && remind_after > 0 && elapsed >= Duration::from_secs(remind_after) {
+            seen.reminded = true;
+            return Repeat::Reminder(elapsed);
+        }
+        Repeat::Suppressed
+    }
+
+    /// Records that acting on `key` was refused at `now`: the wait until
+    /// the next retry, or `None` once the retries are used up.
+    pub fn refused(&mut self, key: &(i32, Option<u64>, String), now: Instant) -> Option<Duration> {
+        let seen = self.seen.get_mut(key)?;
+        seen.refused += 1;
+        if seen.refused > self.config.retries {
+            return None;
+        }
+        let wait = Duration::from_secs(self.config.retry_backoff_secs) * 2u32.saturating_pow(seen.refused - 1);
+        seen.retry_at = Some(now + wait);
+        Some(wait)
+    }
+
+    /// Forgets processes that are no longer running.
+    pub fn retain(&mut self, live: &HashSet<(i32, Option<u64>)>) {
+        self.seen.retain(|(pid, start, _), _| live.contains(&(*pid, *start)));
+    }
+}
+
+impl HfsConfig {
+    /// Like a patterns file, a config with `command:` rules must be owned
+    /// by root and writable by nobody else.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let cannot_read = |e: std::io::Error| format!("Cannot read config {}: {}", path.display(), e);
+        let mut file = fs::File::open(path).map_err(cannot_read)?;
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut file, &mut text).map_err(cannot_read)?;
+        let config: Self = toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        if config.patterns.iter().any(|rule| matches!(rule.action, Some(RuleAction::Command(_)))) {
+            check_command_owner(path, &file.metadata().map_err(cannot_read)?)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Counters since the hunter started.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HfsStats {
+    pub scans: u64,
+    pub processes: u64,
+    /// Processes skipped because an allow rule matched them.
+    pub allowed: u64,
+    pub violations: u64,
+    /// Scans that could not read the process table.
+    pub enumeration_errors: u64,
+    /// Processes whose maps could not be read (other users', without root).
+    pub maps_denied: u64,
+    /// Processes whose environment could not be read.
+    pub env_denied: u64,
+    /// Scans whose listening sockets could not be read.
+    pub port_errors: u64,
+    /// Processes whose descriptors could not be read.
+    pub fds_denied: u64,
+    /// Matches of an already reported process that were only counted.
+    pub suppressed: u64,
+    /// "Still present" reports of processes that kept matching.
+    pub reminders: u64,
+}
+
+impl HfsStats {
+    /// The line printed when the monitor stops.
+    pub fn summary(&self) -> String {
+        format!(
+            "[SUMMARY] scans: {}, processes checked: {}, allowed: {}, violations: {}, reminders: {}, repeats suppressed: {}, scan errors: {}",
+            self.scans,
+            self.processes,
+            self.allowed,
+            self.violations,
+            self.reminders,
+            self.suppressed,
+            self.enumeration_errors
+        )
+    }
+}
+
+struct Allow {
+    pids: HashSet<i32>,
+    users: HashSet<String>,
+    patterns: Option<CommandPatterns>,
+}
+
+impl Allow {
+    fn compile(list: &HfsAllowlist, options: PatternOptions) -> Result<Self, String> {
+        let patterns = if list.patterns.is_empty() {
+            None
+        } else {
+            Some(CommandPatterns::compile(&list.patterns, options)?)
+        };
+        Ok(Self {
+            pids: list.pids.iter().copied().collect(),
+            users: list.users.iter().cloned().collect(),
+            patterns,
+        })
+    }
+
+    fn allows(&self, process: &ProcessInfo) -> bool {
+        self.pids.contains(&process.pid)
+            || process.user.as_ref().is_some_and(|user| self.users.contains(user))
+            || process.uid.is_some_and(|uid| self.users.contains(&uid.to_string()))
+            || self.patterns.as_ref().is_some_and(|patterns| {
+                patterns.matching(&process.command).is_some() || patterns.matching(&process.cmdline).is_some()
+            })
+    }
+}
+
+pub struct HfsHunter<F>
+where
+    F: Fn(Violation) + Send + Sync + 'static,
+{
+    pub forbidden_patterns: CommandPatterns,
+    pub scan_interval: Duration,
+    /// Up to this much is added to each wait between scans, so a tool
+    /// cannot time its runs to fall between them.
+    pub scan_jitter: Duration,
+    pub on_violation: F,
+    pub action: HfsAction,
+    pub match_target: MatchTarget,
+    /// Longest command line shown in a violation message.
+    pub cmdline_width: usize,
+    pub scan_maps: MapsScope,
+    /// Scan maps on every Nth scan.
+    pub maps_every: u64,
+    /// Check each process's environment for injection variables (Linux).
+    pub scan_env: bool,
+    /// Flag any injection variable that is set, not only matching ones.
+    pub strict_env: bool,
+    /// Report whatever listens on these TCP ports; empty turns the check off.
+    pub scan_ports: Vec<u16>,
+    /// Check for other processes holding these files open on every Nth
+    /// scan, or at the next scan once `fd_scan_requested` is set.
+    protected_files: HashSet<PathBuf>,
+    pub fds_every: u64,
+    pub fd_scan_requested: Arc<AtomicBool>,
+    /// Ancestors walked for each violation; 0 turns the walk off.
+    pub chain_depth: usize,
+    pub stats: HfsStats,
+    allow: Allow,
+    /// Every live process already reported, with its reminder and retries.
+    repeats: Repeats,
+    /// (pid, start time, library) of every mapping already reported.
+    reported_maps: HashSet<(i32, Option<u64>, String)>,
+    /// (pid, start time, variable) of every injection variable already reported.
+    reported_env: HashSet<(i32, Option<u64>, String)>,
+    /// (port, pid, start time) of every listener already reported.
+    reported_ports: HashSet<(u16, Option<i32>, Option<u64>)>,
+    /// (pid, start time, fd, mode) of every open protected file already reported.
+    reported_fds: HashSet<(i32, Option<u64>, i32, FdMode)>,
+    /// The tracer already reported, until it detaches.
+    traced_by: Option<TracerInfo>,
+    /// The enumeration error already reported, until a scan succeeds.
+    failing: Option<String>,
+    /// Never report this process or anything it spawned (ps, or a child a
+    /// pattern happens to match), whichever processes those are right now.
+    pub exclude_own_tree: bool,
+    /// This process and its ancestors.
+    protected: HashSet<i32>,
+    patterns_file: Option<PatternsFile>,
+    escalation: Option<Escalation>,
+    /// Reaction commands started and not yet exited.
+    running_commands: Arc<AtomicUsize>,
+    /// Where the monitor's own messages go instead of stdout and stderr.
+    pub log: Option<HfsLog>,
+}
+
+impl<F> HfsHunter<F>
+where
+    F: Fn(Violation) + Send + Sync + 'static,
+{
+    #[deprecated(note = "use HfsHunterBuilder, which also checks the configuration")]
+    #[allow(dead_code)]
+    pub fn new(forbidden_patterns: CommandPatterns, scan_interval: Duration, on_violation: F) -> Self {
+        Self::with_compiled(forbidden_patterns, scan_interval, on_violation)
+    }
+
+    fn with_compiled(forbidden_patterns: CommandPatterns, scan_interval: Duration, on_violation: F) -> Self {
+        Self {
+            forbidden_patterns,
+            scan_interval,
+            scan_jitter: Duration::ZERO,
+            on_violation,
+            action: HfsAction::LogOnly,
+            match_target: MatchTarget::Both,
+            cmdline_width: DEFAULT_CMDLINE_WIDTH,
+            scan_maps: MapsScope::Off,
+            maps_every: DEFAULT_MAPS_EVERY,
+            scan_env: false,
+            strict_env: false,
+            scan_ports: Vec::new(),
+            protected_files: HashSet::new(),
+            fds_every: DEFAULT_FDS_EVERY,
+            fd_scan_requested: Arc::new(AtomicBool::new(false)),
+            chain_depth: DEFAULT_CHAIN_DEPTH,
+            stats: HfsStats::default(),
+            allow: Allow {
+                pids: HashSet::new(),
+                users: HashSet::new(),
+                patterns: None,
+            },
+            repeats: Repeats::new(HfsCooldown::default()),
+            reported_maps: HashSet::new(),
+            reported_env: HashSet::new(),
+            reported_ports: HashSet::new(),
+            reported_fds: HashSet::new(),
+            traced_by: None,
+            failing: None,
+            exclude_own_tree: true,
+            protected: own_lineage(),
+            patterns_file: None,
+            escalation: None,
+            running_commands: Arc::new(AtomicUsize::new(0)),
+            log: None,
+        }
+    }
+
+    fn notice(&self, message: &str) {
+        match &self.log {
+            Some(log) => log.line(message),
+            None => println!("{}", message),
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        match &self.log {
+            Some(log) => log.line(message),
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// Installs allow rules, which are checked before the forbidden patterns.
+    pub fn set_allowlist(&mut self, list: &HfsAllowlist, options: PatternOptions) -> Result<(), String> {
+        self.allow = Allow::compile(list, options)?;
+        Ok(())
+    }
+
+    /// Files other processes must not hold open. Paths are resolved as
+    /// /proc/<pid>/fd shows them; those that do not exist are kept as given.
+    pub fn protect_files(&mut self, paths: &[PathBuf]) {
+        self.protected_files = paths
+            .iter()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect();
+    }
+
+    /// Holds `action` back until a pattern has matched often enough.
+    pub fn set_escalation(&mut self, config: HfsEscalation) {
+        self.escalation = Some(Escalation::new(config));
+    }
+
+    /// Replaces the reminder and retry schedule, forgetting what was seen.
+    pub fn set_cooldown(&mut self, config: HfsCooldown) {
+        self.repeats = Repeats::new(config);
+    }
+
+    /// Swaps in a new pattern set, all or nothing: if any pattern does not
+    /// compile, the current set stays. Returns the (added, removed) rules.
+    pub fn replace_patterns(&mut self, rules: &[PatternRule], options: PatternOptions) -> Result<PatternDelta, String> {
+        let compiled = CommandPatterns::compile_rules(rules, options)?;
+        let old: HashSet<&String> = self.forbidden_patterns.sources().iter().collect();
+        let new: HashSet<&String> = compiled.sources().iter().collect();
+        let added = compiled.sources().iter().filter(|p| !old.contains(p)).cloned().collect();
+        let removed = self.forbidden_patterns.sources().iter().filter(|p| !new.contains(p)).cloned().collect();
+        self.forbidden_patterns = compiled;
+        Ok((added, removed))
+    }
+
+    /// Uses the patterns in `path` plus `extra`, and reloads them whenever
+    /// the file changes. The directory is watched, as editors often replace
+    /// the file rather than write to it.
+    pub fn watch_patterns_file(
+        &mut self,
+        path: &Path,
+        extra: &[PatternRule],
+        options: PatternOptions,
+    ) -> Result<(), String> {
+        let mut patterns = read_patterns_file(path)?;
+        patterns.extend(extra.iter().cloned());
+        self.replace_patterns(&patterns, options)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let (tx, rx) = channel();
+        let mut watcher = recommended_watcher(move |res| {
+            if let Ok(event) = res {
+                let _ = tx.send(event);
+            }
+        })
+        .map_err(|e| format!("Cannot watch patterns file {}: {}", path.display(), e))?;
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Cannot watch patterns file {}: {}", path.display(), e))?;
+        self.patterns_file = Some(PatternsFile {
+            path,
+            extra: extra.to_vec(),
+            options,
+            changes: Mutex::new(rx),
+            _watcher: watcher,
+        });
+        Ok(())
+    }
+
+    /// Reloads the patterns file if it changed since the last call, logging
+    /// the delta. A file that no longer reads or compiles is rejected and
+    /// the previous patterns stay active. Returns `None` without a change,
+    /// else the (added, removed) patterns or why the file was rejected.
+    pub fn reload_patterns_if_changed(&mut self) -> Option<Result<PatternDelta, String>> {
+        let file = self.patterns_file.as_ref()?;
+        let changes = file.changes.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = changes.try_iter().filter(|event| event.paths.contains(&file.path)).count() > 0;
+        drop(changes);
+        if !changed {
+            return None;
+        }
+        let (path, options) = (file.path.clone(), file.options);
+        let patterns = read_patterns_file(&path).map(|mut patterns| {
+            patterns.extend(file.extra.iter().cloned());
+            patterns
+        });
+        let result = patterns.and_then(|patterns| self.replace_patterns(&patterns, options));
+        match &result {
+            Ok((added, removed)) if added.is_empty() && removed.is_empty() => {}
+            Ok((added, removed)) => self.notice(&format!(
+                "[HFS] Reloaded patterns from {}: added {:?}, removed {:?}",
+                path.display(),
+                added,
+                removed
+            )),
+            Err(e) => self.warn(&format!(
+                "[HFS] Rejected edit to {}, keeping the previous {} patterns: {}",
+                path.display(),
+                self.forbidden_patterns.sources().len(),
+                e
+            )),
+        }
+        Some(result)
+    }
+
+    pub async fn start_scan(&mut self) {
+        loop {
+            sleep(self.scan_interval + jitter(self.scan_jitter)).await;
+            if let Err(HfsError::Enumeration(e)) = self.scan_once().await {
+                self.report_monitor_error(e);
+            }
+        }
+    }
+
+    /// Checks every exec as the kernel reports it, with a full scan every
+    /// `scan_interval` for everything else. Falls back to `start_scan` where
+    /// the proc connector cannot be used.
+    pub async fn start_events(&mut self) {
+        let mut events = match proc_events() {
+            Ok(events) => events,
+            Err(e) => {
+                self.warn(&format!(
+                    "[HFS] WARNING: cannot listen for exec events ({}; the proc connector needs CAP_NET_ADMIN), \
+                     falling back to polling every {:?}",
+                    e, self.scan_interval
+                ));
+                return self.start_scan().await;
+            }
+        };
+        self.notice("[HFS] Listening for exec events");
+        let proc = Path::new("/proc");
+        let users = read_users(Path::new("/etc/passwd"));
+        let mut ticker = tokio::time::interval(self.scan_interval);
+        loop {
+            tokio::select! {
+                pid = events.recv() => {
+                    let pid = match pid {
+                        Some(Ok(pid)) => pid,
+                        Some(Err(e)) => {
+                            self.warn(&format!("[HFS] WARNING: exec events stopped ({}), falling back to polling", e));
+                            return self.start_scan().await;
+                        }
+                        None => {
+                            self.warn("[HFS] WARNING: exec events stopped, falling back to polling");
+                            return self.start_scan().await;
+                        }
+                    };
+                    // A process that exits right away is left to the next scan.
+                    if let Some(process) = read_proc_process(&proc.join(pid.to_string()), pid, &users) {
+                        self.check_process(&process);
+                    }
+                }
+                _ = ticker.tick() => {
+                    if let Err(HfsError::Enumeration(e)) = self.scan_once().await {
+                        self.report_monitor_error(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// One pass: checks for a tracer on this process, then the process
+    /// table, then (every `maps_every` scans) the libraries mapped into
+    /// processes. Returns the new violations, each also passed to the
+    /// callback, or why the process table could not be read.
+    pub async fn scan_once(&mut self) -> Result<Vec<Violation>, HfsError> {
+        self.reload_patterns_if_changed();
+        let mut found = self.check_tracer(is_being_traced());
+        let processes = self.get_processes().await.map_err(HfsError::Enumeration)?;
+        self.failing = None;
+        found.extend(self.check_processes(&processes));
+        if self.scan_env {
+            let targets: Vec<ProcessInfo> = processes.iter().filter(|p| !self.allow.allows(p)).cloned().collect();
+            let proc = Path::new("/proc");
+            let environ = move |pid: i32| read_injection_env(&proc.join(pid.to_string()).join("environ"));
+            let read = move || read_each(targets, environ);
+            if let Ok(environments) = tokio::task::spawn_blocking(read).await {
+                found.extend(self.check_env(&environments));
+            }
+        }
+        if !self.scan_ports.is_empty() {
+            let ports = self.scan_ports.clone();
+            match tokio::task::spawn_blocking(move || debug_listeners(&ports)).await {
+                Ok(Ok(listeners)) => found.extend(self.check_ports(&processes, &listeners)),
+                Ok(Err(e)) => {
+                    if self.stats.port_errors == 0 {
+                        self.warn(&format!("[HFS] Cannot list listening sockets, --scan-ports is off: {}", e));
+                    }
+                    self.stats.port_errors += 1;
+                }
+                Err(_) => {}
+            }
+        }
+        let fds_due = (self.stats.scans - 1).is_multiple_of(self.fds_every.max(1));
+        if !self.protected_files.is_empty() && (self.fd_scan_requested.swap(false, Ordering::Relaxed) || fds_due) {
+            let targets: Vec<ProcessInfo> = processes.iter().filter(|p| !self.allow.allows(p)).cloned().collect();
+            let protected = self.protected_files.clone();
+            let proc = Path::new("/proc");
+            let read = move || read_each(targets, |pid| read_open_files(&proc.join(pid.to_string()), &protected));
+            if let Ok(readings) = tokio::task::spawn_blocking(read).await {
+                found.extend(self.check_fds(&readings));
+            }
+        }
+        if self.scan_maps == MapsScope::Off || !(self.stats.scans - 1).is_multiple_of(self.maps_every.max(1)) {
+            return Ok(found);
+        }
+        let targets: Vec<ProcessInfo> = match self.scan_maps {
+            MapsScope::SelfOnly => {
+                let pid = std::process::id() as i32;
+                processes.into_iter().filter(|p| p.pid == pid).collect()
+            }
+            _ => processes.into_iter().filter(|p| !self.allow.allows(p)).collect(),
+        };
+        let proc = Path::new("/proc");
+        let read = move || read_each(targets, |pid| read_maps(&proc.join(pid.to_string()).join("maps")));
+        let Ok(maps) = tokio::task::spawn_blocking(read).await else {
+            return Ok(found);
+        };
+        found.extend(self.check_maps(&maps));
+        Ok(found)
+    }
+
+    /// Reports a failed enumeration through the callback, once until a scan
+    /// succeeds again, so a broken monitor does not look like a clean system.
+    pub fn report_monitor_error(&mut self, error: String) -> usize {
+        self.stats.enumeration_errors += 1;
+        if self.failing.as_ref() == Some(&error) {
+            return 0;
+        }
+        self.failing = Some(error.clone());
+        (self.on_violation)(Violation::monitor_error(error));
+        1
+    }
+
+    /// Reports a debugger attached to us once, until it detaches.
+    pub fn check_tracer(&mut self, tracer: Option<TracerInfo>) -> Vec<Violation> {
+        let Some(tracer) = tracer else {
+            self.traced_by = None;
+            return Vec::new();
+        };
+        if self.traced_by.as_ref() == Some(&tracer) {
+            return Vec::new();
+        }
+        self.traced_by = Some(tracer.clone());
+        self.stats.violations += 1;
+
+        let process = ProcessInfo {
+            pid: tracer.pid.unwrap_or(0),
+            command: tracer.command.clone().unwrap_or_else(|| "unknown".to_string()),
+            cmdline: String::new(),
+            uid: None,
+            user: None,
+            start_time: None,
+            session: None,
+            path: None,
+        };
+        let mut violation = Violation::new(ViolationKind::TracerAttached, &process, None, self.cmdline_width);
+        violation.pid = tracer.pid;
+        if let Some(pid) = tracer.pid {
+            violation.parents = parent_chain(pid, self.chain_depth);
+        }
+        if let (Some(pid), true) = (tracer.pid, self.action != HfsAction::LogOnly) {
+            let outcome = self.act(pid, self.action);
+            violation.outcome = Some(describe(self.action, &outcome));
+        }
+        (self.on_violation)(violation.clone());
+        vec![violation]
+    }
+
+    /// The matching half of `scan_once`, for a process list from anywhere.
+    /// Each offending process is reported (and acted on) once, then again
+    /// only as the cooldown says; a process that has exited is forgotten,
+    /// so a reused PID is reported afresh.
+    pub fn check_processes(&mut self, processes: &[ProcessInfo]) -> Vec<Violation> {
+        self.check_processes_at(processes, Instant::now())
+    }
+
+    /// `check_processes` as if run at `now`.
+    pub fn check_processes_at(&mut self, processes: &[ProcessInfo], now: Instant) -> Vec<Violation> {
+        self.stats.scans += 1;
+        let live: HashSet<(i32, Option<u64>)> = processes.iter().map(|p| (p.pid, p.start_time)).collect();
+        self.repeats.retain(&live);
+
+        let mut found = Vec::new();
+        for process in processes {
+            found.extend(self.check_process_at(process, now));
+        }
+        found
+    }
+
+    /// Checks a single process, from a table scan or an exec event, with
+    /// the same allow rules and cooldown.
+    pub fn check_process(&mut self, process: &ProcessInfo) -> Option<Violation> {
+        self.check_process_at(process, Instant::now())
+    }
+
+    fn check_process_at(&mut self, process: &ProcessInfo, now: Instant) -> Option<Violation> {
+        self.stats.processes += 1;
+        if self.allow.allows(process) {
+            self.stats.allowed += 1;
+            return None;
+        }
+        let rule = self.forbidden_by(process)?.clone();
+        if self.exclude_own_tree && in_own_tree(process.pid) {
+            return None;
+        }
+        let key = (process.pid, process.start_time, rule.regex.clone());
+        let repeat = self.repeats.sighting(&key, now);
+        if repeat == Repeat::Suppressed {
+            self.stats.suppressed += 1;
+            return None;
+        }
+        let mut violation =
+            Violation::new(ViolationKind::ForbiddenProcess, process, Some(&rule.regex), self.cmdline_width);
+        // Walked before acting: a killed process has no parent to read.
+        violation.parents = parent_chain(process.pid, self.chain_depth);
+        match repeat {
+            Repeat::First => {
+                self.stats.violations += 1;
+                if let Some((action, ActionOutcome::PermissionDenied)) =
+                    self.respond(&mut violation, process.pid, Some(&rule))
+                {
+                    self.refused(&mut violation, &key, action, now);
+                }
+            }
+            Repeat::Reminder(elapsed) => {
+                self.stats.reminders += 1;
+                let action = rule.action.clone().unwrap_or(RuleAction::Process(self.action));
+                violation.action = Some(action.to_string());
+                violation.severity = Some(rule.severity);
+                violation.reminder_secs = Some(elapsed.as_secs());
+            }
+            Repeat::Retry(attempt) => {
+                // Only signals are retried, so the action is one.
+                let RuleAction::Process(action) = rule.action.clone().unwrap_or(RuleAction::Process(self.action)) else {
+                    return None;
+                };
+                violation.action = Some(action.as_str().to_string());
+                violation.severity = Some(rule.severity);
+                match self.act(process.pid, action) {
+                    ActionOutcome::PermissionDenied => {
+                        if self.refused(&mut violation, &key, action, now) {
+                            self.stats.suppressed += 1;
+                            return None;
+                        }
+                    }
+                    // Gone on its own; nothing left to report.
+                    ActionOutcome::Gone => return None,
+                    outcome => {
+                        violation.outcome = Some(format!("{} on retry {}", describe(action, &outcome), attempt));
+                    }
+                }
+            }
+            Repeat::Suppressed => unreachable!(),
+        }
+        (self.on_violation)(violation.clone());
+        Some(violation)
+    }
+
+    /// Schedules a retry of an action refused with EPERM, or gives up and
+    /// raises the severity once the retries are used up. Returns whether
+    /// a retry is coming.
+    fn refused(
+        &mut self,
+        violation: &mut Violation,
+        key: &(i32, Option<u64>, String),
+        action: HfsAction,
+        now: Instant,
+    ) -> bool {
+        let name = action.as_str();
+        match self.repeats.refused(key, now) {
+            Some(wait) => {
+                violation.outcome = Some(format!("{} failed: permission denied, retrying in {}s", name, wait.as_secs()));
+                true
+            }
+            None => {
+                violation.severity = Some(raised(violation.severity.unwrap_or(Severity::Warning)));
+                let retries = self.repeats.config.retries;
+                violation.outcome = Some(format!("{} failed: permission denied, gave up after {} retries", name, retries));
+                false
+            }
+        }
+    }
+
+    /// The matching half of the maps scan: reports each forbidden library
+    /// once per hosting process. `None` stands for maps we may not read.
+    pub fn check_maps(&mut self, maps: &[(ProcessInfo, Option<Vec<String>>)]) -> Vec<Violation> {
+        let live: HashSet<(i32, Option<u64>)> = maps.iter().map(|(p, _)| (p.pid, p.start_time)).collect();
+        self.reported_maps.retain(|(pid, start, _)| live.contains(&(*pid, *start)));
+
+        let mut found = Vec::new();
+        for (process, libraries) in maps {
+            let Some(libraries) = libraries else {
+                if self.stats.maps_denied == 0 {
+                    self.warn("[HFS] Cannot read maps of other users' processes; they are skipped and counted");
+                }
+                self.stats.maps_denied += 1;
+                continue;
+            };
+            for library in libraries {
+                let key = (process.pid, process.start_time, library.clone());
+                if self.reported_maps.contains(&key) {
+                    continue;
+                }
+                let Some(rule) = self.forbidden_patterns.matching_library(library).cloned() else {
+                    continue;
+                };
+                let mut violation =
+                    Violation::new(ViolationKind::InjectedLibrary, process, Some(&rule.regex), self.cmdline_width);
+                violation.library = Some(library.clone());
+                violation.parents = parent_chain(process.pid, self.chain_depth);
+                self.stats.violations += 1;
+                self.reported_maps.insert(key);
+                self.respond(&mut violation, process.pid, Some(&rule));
+                (self.on_violation)(violation.clone());
+                found.push(violation);
+            }
+        }
+        found
+    }
+
+    /// Flags injection variables whose value matches a forbidden pattern, or
+    /// any that is set at all under `strict_env`, once per process. `None`
+    /// stands for an environment we may not read, which is only counted.
+    pub fn check_env(&mut self, environments: &[EnvReading]) -> Vec<Violation> {
+        let live: HashSet<(i32, Option<u64>)> = environments.iter().map(|(p, _)| (p.pid, p.start_time)).collect();
+        self.reported_env.retain(|(pid, start, _)| live.contains(&(*pid, *start)));
+
+        let mut found = Vec::new();
+        for (process, variables) in environments {
+            let Some(variables) = variables else {
+                self.stats.env_denied += 1;
+                continue;
+            };
+            for (name, value) in variables {
+                let key = (process.pid, process.start_time, name.clone());
+                if value.is_empty() || self.reported_env.contains(&key) {
+                    continue;
+                }
+                let rule = self.forbidden_patterns.matching_library(value).cloned();
+                if rule.is_none() && !self.strict_env {
+                    continue;
+                }
+                let pattern = rule.as_ref().map(|rule| rule.regex.as_str());
+                let mut violation =
+                    Violation::new(ViolationKind::InjectedEnvironment, process, pattern, self.cmdline_width);
+                violation.variable = Some(name.clone());
+                violation.value = Some(value.clone());
+                violation.parents = parent_chain(process.pid, self.chain_depth);
+                self.stats.violations += 1;
+                self.reported_env.insert(key);
+                self.respond(&mut violation, process.pid, rule.as_ref());
+                (self.on_violation)(violation.clone());
+                found.push(violation);
+            }
+        }
+        found
+    }
+
+    /// Flags each descriptor another process holds on a protected file,
+    /// once per (process, fd, mode). `None` stands for descriptors we may
+    /// not read, which are only counted.
+    pub fn check_fds(&mut self, readings: &[FdReading]) -> Vec<Violation> {
+        let live: HashSet<(i32, Option<u64>, i32, FdMode)> = readings
+            .iter()
+            .flat_map(|(p, files)| files.iter().flatten().map(|file| (p.pid, p.start_time, file.fd, file.mode)))
+            .collect();
+        self.reported_fds.retain(|key| live.contains(key));
+
+        let mut found = Vec::new();
+        for (process, files) in readings {
+            let Some(files) = files else {
+                self.stats.fds_denied += 1;
+                continue;
+            };
+            if files.is_empty() || (self.exclude_own_tree && in_own_tree(process.pid)) {
+                continue;
+            }
+            for file in files {
+                let key = (process.pid, process.start_time, file.fd, file.mode);
+                if !self.reported_fds.insert(key) {
+                    continue;
+                }
+                let mut violation = Violation::new(ViolationKind::OpenProtectedFile, process, None, self.cmdline_width);
+                violation.file = Some(file.path.to_string_lossy().into_owned());
+                violation.fd = Some(file.fd);
+                violation.fd_mode = Some(file.mode);
+                violation.parents = parent_chain(process.pid, self.chain_depth);
+                self.stats.violations += 1;
+                self.respond(&mut violation, process.pid, None);
+                (self.on_violation)(violation.clone());
+                found.push(violation);
+            }
+        }
+        found
+    }
+
+    /// Flags each process listening on one of `scan_ports`, once per
+    /// (port, process). A listener whose owner could not be found is still
+    /// reported, without a process to act on.
+    pub fn check_ports(&mut self, processes: &[ProcessInfo], listeners: &[Listener]) -> Vec<Violation> {
+        let owners: HashMap<i32, &ProcessInfo> = processes.iter().map(|p| (p.pid, p)).collect();
+        let keyed: Vec<(Listener, Option<&ProcessInfo>)> =
+            listeners.iter().map(|&(port, pid)| ((port, pid), pid.and_then(|pid| owners.get(&pid).copied()))).collect();
+        let live: HashSet<(u16, Option<i32>, Option<u64>)> = keyed
+            .iter()
+            .map(|((port, pid), owner)| (*port, *pid, owner.and_then(|p| p.start_time)))
+            .collect();
+        self.reported_ports.retain(|key| live.contains(key));
+
+        let mut found = Vec::new();
+        for ((port, pid), owner) in keyed {
+            let key = (port, pid, owner.and_then(|p| p.start_time));
+            if self.reported_ports.contains(&key) || owner.is_some_and(|p| self.allow.allows(p)) {
+                continue;
+            }
+            if self.exclude_own_tree && pid.is_some_and(in_own_tree) {
+                continue;
+            }
+            let mut violation = match (pid, owner) {
+                (_, Some(process)) => Violation::new(ViolationKind::DebugListener, process, None, self.cmdline_width),
+                (Some(pid), None) => {
+                    let process = ProcessInfo { pid, ..ProcessInfo::nobody() };
+                    Violation::new(ViolationKind::DebugListener, &process, None, self.cmdline_width)
+                }
+                (None, None) => {
+                    let mut violation =
+                        Violation::new(ViolationKind::DebugListener, &ProcessInfo::nobody(), None, self.cmdline_width);
+                    violation.pid = None;
+                    violation
+                }
+            };
+            violation.port = Some(port);
+            self.stats.violations += 1;
+            self.reported_ports.insert(key);
+            if let Some(pid) = pid {
+                violation.parents = parent_chain(pid, self.chain_depth);
+                self.respond(&mut violation, pid, None);
+            }
+            (self.on_violation)(violation.clone());
+            found.push(violation);
+        }
+        found
+    }
+
+    /// Counts a pattern match against its escalation rule and applies the
+    /// matched rule's action, or `action`, once the escalation allows it.
+    /// Returns the signal sent and how that went, if one was.
+    fn respond(
+        &mut self,
+        violation: &mut Violation,
+        pid: i32,
+        rule: Option<&PatternRule>,
+    ) -> Option<(HfsAction, ActionOutcome)> {
+        let action = rule.and_then(|rule| rule.action.clone()).unwrap_or(RuleAction::Process(self.action));
+        if let Some(rule) = rule {
+            violation.action = Some(action.to_string());
+            violation.severity = Some(rule.severity);
+        }
+        if let (Some(escalation), Some(pattern)) = (self.escalation.as_mut(), violation.pattern.as_deref()) {
+            let (user, uid) = (violation.user.as_deref(), violation.uid);
+            violation.escalation = escalation.record(pattern, pid, user, uid, Instant::now());
+        }
+        if !violation.escalation.as_ref().is_none_or(|state| state.escalated) {
+            return None;
+        }
+        match action {
+            RuleAction::Process(HfsAction::LogOnly) => None,
+            RuleAction::Process(action) => {
+                let outcome = self.act(pid, action);
+                violation.outcome = Some(describe(action, &outcome));
+                Some((action, outcome))
+            }
+            RuleAction::Command(command) => {
+                violation.outcome = Some(self.run_command(&command, violation));
+                None
+            }
+        }
+    }
+
+    /// Starts a reaction command unless `MAX_RUNNING_COMMANDS` are still
+    /// running. A thread waits for each so none is left a zombie.
+    fn run_command(&self, command: &str, violation: &Violation) -> String {
+        let running = Arc::clone(&self.running_commands);
+        if running.fetch_add(1, Ordering::SeqCst) >= MAX_RUNNING_COMMANDS {
+            running.fetch_sub(1, Ordering::SeqCst);
+            return format!("not run: {} reaction commands still running", MAX_RUNNING_COMMANDS);
+        }
+        match reaction_command(command, violation).spawn() {
+            Ok(mut child) => {
+                std::thread::spawn(move || {
+                    let _ = child.wait();
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+                format!("ran {}", command)
+            }
+            Err(e) => {
+                running.fetch_sub(1, Ordering::SeqCst);
+                format!("command failed: {}", e)
+            }
+        }
+    }
+
+    /// The forbidden rule `process` matches under the match target.
+    fn forbidden_by(&self, process: &ProcessInfo) -> Option<&PatternRule> {
+        let patterns = &self.forbidden_patterns;
+        let comm = || patterns.matching_rule(&process.command);
+        let cmdline = || patterns.matching_rule(&process.cmdline);
+        let path = || process.path.as_deref().and_then(|path| patterns.matching_rule(path));
+        match self.match_target {
+            MatchTarget::Comm => comm(),
+            MatchTarget::Cmdline => cmdline(),
+            MatchTarget::Path => path(),
+            MatchTarget::Both => comm().or_else(path).or_else(cmdline),
+        }
+    }
+
+    fn act(&self, pid: i32, action: HfsAction) -> ActionOutcome {
+        if self.protected.contains(&pid) {
+            return ActionOutcome::Protected;
+        }
+        send_action(pid, action)
+    }
+
+    async fn get_processes(&self) -> Result<Vec<ProcessInfo>, String> {
+        if cfg!(target_os = "linux") {
+            // Minimal containers often have no ps; /proc is always there.
+            match tokio::task::spawn_blocking(|| read_proc(Path::new("/proc"))).await {
+                Ok(Ok(processes)) if !processes.is_empty() => Ok(processes),
+                Ok(Ok(_)) => Err("no processes found in /proc".to_string()),
+                Ok(Err(e)) => Err(format!("cannot read /proc: {}", e)),
+                Err(e) => Err(format!("cannot read /proc: {}", e)),
+            }
+        } else if cfg!(target_os = "macos") {
+            // ps truncates names and may not be allowed to run in a sandbox.
+            match tokio::task::spawn_blocking(libproc_processes).await {
+                Ok(Ok(processes)) if !processes.is_empty() => Ok(processes),
+                _ => self.get_processes_unix().await,
+            }
+        } else if cfg!(unix) {
+            self.get_processes_unix().await
+        } else if cfg!(target_os = "windows") {
+            self.get_processes_windows().await
+        } else {
+            Err("process enumeration is not supported on this platform".to_string())
+        }
+    }
+
+    async fn get_processes_unix(&self) -> Result<Vec<ProcessInfo>, String> {
+        let output = Command::new("ps")
+            .arg("-eo")
+            .arg("pid,uid,user,comm")
+            .stdout(Stdio::piped())
+            .output()
+            .await
+            .map_err(|e| format!("cannot run ps: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("ps exited with {}", output.status));
+        }
+
+        let mut processes = parse_ps(&String::from_utf8_lossy(&output.stdout));
+        if processes.is_empty() {
+            return Err("ps listed no processes".to_string());
+        }
+        // comm and args in one ps call would be ambiguous: both may contain spaces.
+        let mut cmdlines = ps_command_lines().await;
+        for process in &mut processes {
+            process.cmdline = cmdlines.remove(&process.pid).unwrap_or_default();
+        }
+        Ok(processes)
+    }
+
+    #[cfg(windows)]
+    async fn get_processes_windows(&self) -> Result<Vec<ProcessInfo>, String> {
+        let entries = tokio::task::spawn_blocking(|| {
+            let mut entries = toolhelp_processes()?;
+            let mut sessions = wts_sessions();
+            for entry in &mut entries {
+                if let Some((session, user)) = sessions.remove(&entry.pid) {
+                    entry.session = Some(session);
+                    entry.user = user;
+                }
+                if entry.user.is_none() {
+                    entry.user = token_user(entry.pid);
+                }
+            }
+            Ok::<_, std::io::Error>(entries)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("cannot snapshot processes: {}", e))?;
+        let mut cmdlines = wmi_command_lines().await;
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let cmdline = cmdlines.remove(&(entry.pid as i32)).unwrap_or_default();
+                entry.into_process(cmdline)
+            })
+            .collect())
+    }
+
+    #[cfg(not(windows))]
+    async fn get_processes_windows(&self) -> Result<Vec<ProcessInfo>, String> {
+        Err("ToolHelp snapshots are only available on Windows".to_string())
+    }
+}
+
+/// pid -> full command line from `ps -eo pid,args`.
+async fn ps_command_lines() -> HashMap<i32, String> {
+    let Ok(output) = Command::new("ps").args(["-eo", "pid,args"]).stdout(Stdio::piped()).output().await else {
+        return HashMap::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (pid, args) = line.trim_start().split_once(char::is_whitespace)?;
+            Some((pid.parse().ok()?, args.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+/// pid -> CommandLine from WMI (`wmic process get ProcessId,CommandLine`).
+async fn wmi_command_lines() -> HashMap<i32, String> {
+    let output = Command::new("wmic")
+        .args(["process", "get", "ProcessId,CommandLine", "/format:csv"])
+        .stdout(Stdio::piped())
+        .output()
+        .await;
+    let Ok(output) = output else {
+        return HashMap::new();
+    };
+    // Node,CommandLine,ProcessId: the command line itself may contain commas.
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (rest, pid) = line.trim().rsplit_once(',')?;
+            let (_, cmdline) = rest.split_once(',')?;
+            Some((pid.parse().ok()?, cmdline.to_string()))
+        })
+        .collect()
+}
+
+fn describe(action: HfsAction, outcome: &ActionOutcome) -> String {
+    let name = action.as_str();
+    match outcome {
+        ActionOutcome::Done if action == HfsAction::Kill => "killed".to_string(),
+        ActionOutcome::Done => "suspended".to_string(),
+        ActionOutcome::PermissionDenied => format!("{} failed: permission denied", name),
+        ActionOutcome::Gone => "process already gone".to_string(),
+        ActionOutcome::Protected => format!("not {}ed: own process or ancestor", name),
+        ActionOutcome::Failed(e) => format!("{} failed: {}", name, e),
+    }
+}
+
+/// `command` run through the shell with the violation in its environment:
+/// SERIALK_HFS_KIND, _PID, _COMMAND, _PATTERN, _USER, _MESSAGE and _JSON.
+pub fn reaction_command(command: &str, violation: &Violation) -> std::process::Command {
+    let json = serde_json::to_value(violation).unwrap_or_default();
+    let field = |key: &str| match &json[key] {
+        serde_json::Value::String(value) => value.clone(),
+        serde_json::Value::Null => String::new(),
+        value => value.to_string(),
+    };
+    #[cfg(unix)]
+    let mut shell = std::process::Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = std::process::Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    shell
+        .arg(command)
+        .env("SERIALK_HFS_KIND", field("kind"))
+        .env("SERIALK_HFS_PID", field("pid"))
+        .env("SERIALK_HFS_COMMAND", field("command"))
+        .env("SERIALK_HFS_PATTERN", field("pattern"))
+        .env("SERIALK_HFS_USER", field("user"))
+        .env("SERIALK_HFS_MESSAGE", violation.to_string())
+        .env("SERIALK_HFS_JSON", json.to_string());
+    shell
+}
+
+/// A random wait below `max`.
+fn jitter(max: Duration) -> Duration {
+    use rand_core::{OsRng, RngCore};
+    match max.as_nanos() as u64 {
+        0 => Duration::ZERO,
+        nanos => Duration::from_nanos(OsRng.next_u64() % nanos),
+    }
+}
+
+/// One step up from `severity`, for an action HFS could not carry out.
+fn raised(severity: Severity) -> Severity {
+    match severity {
+        Severity::Info => Severity::Warning,
+        Severity::Warning | Severity::Critical => Severity::Critical,
+    }
+}
+
+/// Cuts `text` to at most `width` characters, marking the cut with "...".
+fn truncate(text: &str, width: usize) -> String {
+    match text.char_indices().nth(width) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Command-line settings for `start_hfs_monitor`.
+#[derive(Clone)]
+pub struct HfsOptions {
+    pub interval: Duration,
+    /// Up to this much is added to each wait between scans.
+    pub jitter: Duration,
+    pub action: HfsAction,
+    pub matching: PatternOptions,
+    pub match_target: MatchTarget,
+    pub cmdline_width: usize,
+    pub scan_maps: MapsScope,
+    pub maps_every: u64,
+    pub chain_depth: usize,
+    /// Print each violation as a JSON object instead of a message line.
+    pub json: bool,
+    /// Scan once and exit with `once_exit_code` instead of looping.
+    pub once: bool,
+    /// Check each exec as it happens instead of only polling (Linux).
+    pub events: bool,
+    /// More patterns, reloaded whenever the file changes.
+    pub patterns_file: Option<PathBuf>,
+    pub escalation: Option<HfsEscalation>,
+    pub scan_env: bool,
+    pub strict_env: bool,
+    /// Ports whose listeners are reported; empty leaves them unchecked.
+    pub scan_ports: Vec<u16>,
+    /// Files other processes must not hold open.
+    pub protect: Vec<PathBuf>,
+    pub fds_every: u64,
+    /// Set to check the protected files at the next scan.
+    pub fd_scan_requested: Arc<AtomicBool>,
+    pub cooldown: HfsCooldown,
+    /// Send violations and messages here instead of stdout (`--daemon`).
+    pub log: Option<HfsLog>,
+}
+
+impl Default for HfsOptions {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_SCAN_INTERVAL,
+            jitter: Duration::ZERO,
+            action: HfsAction::LogOnly,
+            matching: PatternOptions::default(),
+            match_target: MatchTarget::Both,
+            cmdline_width: DEFAULT_CMDLINE_WIDTH,
+            scan_maps: MapsScope::Off,
+            maps_every: DEFAULT_MAPS_EVERY,
+            chain_depth: DEFAULT_CHAIN_DEPTH,
+            json: false,
+            once: false,
+            events: false,
+            patterns_file: None,
+            escalation: None,
+            scan_env: false,
+            strict_env: false,
+            scan_ports: Vec::new(),
+            protect: Vec::new(),
+            fds_every: DEFAULT_FDS_EVERY,
+            fd_scan_requested: Arc::new(AtomicBool::new(false)),
+            cooldown: HfsCooldown::default(),
+            log: None,
+        }
+    }
+}
+
+/// Where a monitor started from `HfsOptions` sends its violations.
+pub type Reporter = Box<dyn Fn(Violation) + Send + Sync>;
+
+/// How long the monitor waits between scans unless told otherwise.
+pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Why `HfsHunterBuilder::build` refused a configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// No pattern, patterns file or protected file: nothing to look for.
+    NoPatterns,
+    ZeroInterval,
+    InvalidPattern(String),
+    InvalidAllowlist(String),
+    PatternsFile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoPatterns => write!(f, "No forbidden patterns, patterns file or protected files to look for"),
+            ConfigError::ZeroInterval => write!(f, "The scan interval must be greater than zero"),
+            ConfigError::InvalidPattern(e) | ConfigError::InvalidAllowlist(e) | ConfigError::PatternsFile(e) => {
+                write!(f, "{}", e)
+            }
+        }
+    }
+}
+
+/// Sets up an `HfsHunter`, the one way the CLI, the config file and
+/// `serialk-watcher` build one. Anything not set keeps the
+/// `HfsOptions::default()` value; violations are printed unless
+/// `on_violation` says otherwise.
+#[derive(Default)]
+pub struct HfsHunterBuilder {
+    rules: Vec<PatternRule>,
+    allow: HfsAllowlist,
+    options: HfsOptions,
+    on_violation: Option<Reporter>,
+}
+
+impl HfsHunterBuilder {
+    pub fn patterns(mut self, rules: &[PatternRule]) -> Self {
+        self.rules.extend_from_slice(rules);
+        self
+    }
+
+    /// Adds to the allow rules given so far.
+    pub fn allowlist(mut self, allow: HfsAllowlist) -> Self {
+        self.allow.pids.extend(allow.pids);
+        self.allow.users.extend(allow.users);
+        self.allow.patterns.extend(allow.patterns);
+        self
+    }
+
+    /// Takes every other setting from `options`. Patterns and allow rules
+    /// given before or after are kept.
+    pub fn options(mut self, options: HfsOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn on_violation(mut self, on_violation: impl Fn(Violation) + Send + Sync + 'static) -> Self {
+        self.on_violation = Some(Box::new(on_violation));
+        self
+    }
+
+    pub fn build(self) -> Result<HfsHunter<Reporter>, ConfigError> {
+        let options = self.options;
+        if self.rules.is_empty() && options.patterns_file.is_none() && options.protect.is_empty() {
+            return Err(ConfigError::NoPatterns);
+        }
+        if options.interval.is_zero() {
+            return Err(ConfigError::ZeroInterval);
+        }
+        let patterns =
+            CommandPatterns::compile_rules(&self.rules, options.matching).map_err(ConfigError::InvalidPattern)?;
+        let report = self
+            .on_violation
+            .unwrap_or_else(|| Box::new(message_callback(|msg| println!("{}", msg))));
+        let mut hunter = HfsHunter::with_compiled(patterns, options.interval, report);
+        hunter.scan_jitter = options.jitter;
+        hunter.action = options.action;
+        hunter.match_target = options.match_target;
+        hunter.cmdline_width = options.cmdline_width;
+        hunter.scan_maps = options.scan_maps;
+        hunter.maps_every = options.maps_every;
+        hunter.chain_depth = options.chain_depth;
+        hunter.scan_env = options.scan_env;
+        hunter.strict_env = options.strict_env;
+        hunter.scan_ports = options.scan_ports;
+        hunter.protect_files(&options.protect);
+        hunter.fds_every = options.fds_every;
+        hunter.fd_scan_requested = options.fd_scan_requested;
+        hunter.set_cooldown(options.cooldown);
+        hunter.log = options.log;
+        hunter
+            .set_allowlist(&self.allow, options.matching)
+            .map_err(ConfigError::InvalidAllowlist)?;
+        if let Some(escalation) = options.escalation {
+            hunter.set_escalation(escalation);
+        }
+        if let Some(path) = &options.patterns_file {
+            hunter
+                .watch_patterns_file(path, &self.rules, options.matching)
+                .map_err(ConfigError::PatternsFile)?;
+        }
+        Ok(hunter)
+    }
+}
+
+/// A hunter set up from `options`, reporting to `report`.
+fn build_hunter(
+    forbidden_keywords: &[PatternRule],
+    allow: &HfsAllowlist,
+    options: &HfsOptions,
+    report: Reporter,
+) -> Result<HfsHunter<Reporter>, String> {
+    let builder = HfsHunterBuilder::default()
+        .patterns(forbidden_keywords)
+        .options(options.clone())
+        .allowlist(allow.clone())
+        .on_violation(report);
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Fails as `start_hfs_monitor` would on bad patterns, allow rules or
+/// patterns file, without starting anything: `--daemon` checks first,
+/// while errors can still reach the terminal.
+pub fn check_hfs_monitor(forbidden_keywords: &[PatternRule], allow: &HfsAllowlist, options: &HfsOptions) -> Result<(), String> {
+    build_hunter(forbidden_keywords, allow, options, Box::new(|_| {})).map(drop)
+}
+
+/// Runs the monitor on a thread of its own and hands every violation to
+/// the returned channel instead of printing it, for `serialk-watcher` to
+/// react to. The thread has its own runtime, so it also runs after the
+/// watcher has daemonized.
+pub fn spawn_hfs_monitor(
+    forbidden_keywords: &[PatternRule],
+    allow: &HfsAllowlist,
+    options: HfsOptions,
+) -> Result<tokio::sync::mpsc::UnboundedReceiver<Violation>, String> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let report: Reporter = Box::new(move |violation| {
+        let _ = tx.send(violation);
+    });
+    let mut hunter = build_hunter(forbidden_keywords, allow, &options, report)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Cannot start the HFS monitor: {}", e))?;
+    std::thread::spawn(move || {
+        runtime.block_on(async {
+            if options.events {
+                hunter.start_events().await;
+            } else {
+                hunter.start_scan().await;
+            }
+        })
+    });
+    Ok(rx)
+}
+
+/// ✅ Dışa açık HFS tarayıcı başlatıcısı
+/// forbidden_keywords: yasaklanmış komut içerikleri (örn: vec!["gdb", "strace"])
+pub async fn start_hfs_monitor(forbidden_keywords: &[PatternRule], allow: &HfsAllowlist, options: HfsOptions) {
+    let report: Reporter = match (options.log.clone(), options.json) {
+        (Some(log), true) => Box::new(move |violation| log.line(&serde_json::to_string(&violation).unwrap_or_default())),
+        (Some(log), false) => Box::new(message_callback(move |msg| log.line(&msg))),
+        (None, true) => Box::new(|violation| println!("{}", serde_json::to_string(&violation).unwrap_or_default())),
+        (None, false) => Box::new(message_callback(|msg| println!("{}", msg))),
+    };
+    let mut hunter = match build_hunter(forbidden_keywords, allow, &options, report) {
+        Ok(hunter) => hunter,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if options.once {
+        let result = hunter.scan_once().await;
+        if let Err(e) = &result {
+            eprintln!("[HFS] {}", e);
+        }
+        std::process::exit(once_exit_code(&result));
+    }
+    // Runs until SIGTERM or ctrl-c, then says what it did.
+    tokio::select! {
+        _ = async {
+            if options.events {
+                hunter.start_events().await;
+            } else {
+                hunter.start_scan().await;
+            }
+        } => {}
+        _ = shutdown_signal() => {}
+    }
+    hunter.notice(&hunter.stats.summary());
+}
+
+/// Resolves on SIGTERM or SIGINT (ctrl-c on Windows); never, if those
+/// cannot be caught.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+            (Ok(mut terminate), Ok(mut interrupt)) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = interrupt.recv() => {}
+                }
+            }
+            _ => std::future::pending().await,
+        }
+    }
+    #[cfg(not(unix))]
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Rows of `ps -eo pid,uid,user,comm`. ps puts the uid in the user column
+/// when it has no name for it; such rows keep the uid only.
+fn parse_ps(stdout: &str) -> Vec<ProcessInfo> {
+    stdout
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 4 {
+                return None;
+            }
+            let uid = parts[1].parse().ok();
+            Some(ProcessInfo {
+                pid: parts[0].parse().ok()?,
+                command: parts[3..].join(" "),
+                cmdline: String::new(),
+                uid,
+                user: (parts[2] != parts[1]).then(|| parts[2].to_string()),
+                start_time: None,
+                session: None,
+                path: None,
+            })
+        })
+        .collect()
+}
+
+/// Lists processes from a procfs tree without spawning anything. `comm` is
+/// cut to 15 bytes by the kernel, so the name comes from argv[0] when that
+/// is the untruncated form of it. Processes that exit mid-scan are skipped.
+pub fn read_proc(root: &Path) -> std::io::Result<Vec<ProcessInfo>> {
+    read_proc_with(root, Path::new("/etc/passwd"))
+}
+
+/// `read_proc`, naming users from the `passwd` file given.
+fn read_proc_with(root: &Path, passwd: &Path) -> std::io::Result<Vec<ProcessInfo>> {
+    let users = read_users(passwd);
+    let mut processes = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let Ok(entry) = entry else { continue };
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+            continue;
+        };
+        processes.extend(read_proc_process(&entry.path(), pid, &users));
+    }
+    Ok(processes)
+}
+
+/// One /proc/<pid> directory; `None` once the process is gone.
+fn read_proc_process(dir: &Path, pid: i32, users: &HashMap<u32, String>) -> Option<ProcessInfo> {
+    let comm = fs::read_to_string(dir.join("comm")).ok()?;
+    let comm = comm.trim_end_matches('\n');
+    let cmdline = fs::read(dir.join("cmdline")).unwrap_or_default();
+    let args: Vec<String> = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    let argv0 = args.first().map(String::as_str).unwrap_or_default();
+    let name = argv0.rsplit('/').next().unwrap_or_default();
+    let command = if !comm.is_empty() && name.len() > comm.len() && name.starts_with(comm) {
+        name.to_string()
+    } else {
+        comm.to_string()
+    };
+    let uid = fs::read_to_string(dir.join("status")).ok().and_then(|status| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))
+            .and_then(|ids| ids.split_whitespace().next()?.parse::<u32>().ok())
+    });
+    Some(ProcessInfo {
+        pid,
+        command,
+        cmdline: args.join(" "),
+        uid,
+        user: uid.and_then(|uid| users.get(&uid).cloned()),
+        start_time: fs::read_to_string(dir.join("stat")).ok().and_then(|stat| stat_field(&stat, 22)),
+        session: None,
+        // Only readable for other users' processes as root.
+        path: fs::read_link(dir.join("exe"))
+            .ok()
+            .map(|exe| exe.to_string_lossy().trim_end_matches(" (deleted)").to_string()),
+    })
+}
+
+/// Lists processes through libproc: full executable paths, untruncated
+/// names and argv from KERN_PROCARGS2, without spawning ps.
+#[cfg(target_os = "macos")]
+fn libproc_processes() -> std::io::Result<Vec<ProcessInfo>> {
+    use std::mem::size_of;
+
+    // SAFETY: a null buffer only asks for the number of pids.
+    let count = unsafe { libc::proc_listallpids(std::ptr::null_mut(), 0) };
+    if count <= 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Leave room for processes started in between.
+    let mut pids: Vec<libc::pid_t> = vec![0; count as usize + 64];
+    let bytes = (pids.len() * size_of::<libc::pid_t>()) as libc::c_int;
+    // SAFETY: the kernel writes at most `bytes` bytes of pids.
+    let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr().cast(), bytes) };
+    if count <= 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    pids.truncate(count as usize);
+
+    let argmax = sysctl_argmax().unwrap_or(256 * 1024);
+    let mut users: HashMap<u32, Option<String>> = HashMap::new();
+    let mut processes = Vec::new();
+    for pid in pids {
+        let mut info: libc::proc_bsdinfo = unsafe { std::mem::zeroed() };
+        let size = size_of::<libc::proc_bsdinfo>() as libc::c_int;
+        let buffer: *mut libc::proc_bsdinfo = &mut info;
+        // SAFETY: `info` is exactly `size` bytes.
+        let read = unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDTBSDINFO, 0, buffer.cast(), size) };
+        if read != size {
+            // Gone since the listing.
+            continue;
+        }
+        let mut buffer = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+        // SAFETY: both calls write at most the given number of bytes and return the length.
+        let len = unsafe { libc::proc_pidpath(pid, buffer.as_mut_ptr().cast(), buffer.len() as u32) };
+        let path = (len > 0).then(|| String::from_utf8_lossy(&buffer[..len as usize]).into_owned());
+        let len = unsafe { libc::proc_name(pid, buffer.as_mut_ptr().cast(), buffer.len() as u32) };
+        let name = String::from_utf8_lossy(&buffer[..len.max(0) as usize]).into_owned();
+        let command = match path.as_deref().and_then(|path| path.rsplit('/').next()) {
+            Some(file) if !file.is_empty() => file.to_string(),
+            _ => name,
+        };
+        let uid = info.pbi_ruid;
+        processes.push(ProcessInfo {
+            pid,
+            command,
+            cmdline: procargs(pid, argmax).map(|args| args.join(" ")).unwrap_or_default(),
+            uid: Some(uid),
+            user: users.entry(uid).or_insert_with(|| user_name(uid)).clone(),
+            start_time: Some(info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec),
+            session: None,
+            path,
+        });
+    }
+    Ok(processes)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn libproc_processes() -> std::io::Result<Vec<ProcessInfo>> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// The largest argument buffer KERN_PROCARGS2 can return.
+#[cfg(target_os = "macos")]
+fn sysctl_argmax() -> Option<usize> {
+    let mut argmax: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let mut mib = [libc::CTL_KERN, libc::KERN_ARGMAX];
+    // SAFETY: sysctl writes one c_int into `argmax`.
+    let rc = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            (&mut argmax as *mut libc::c_int).cast(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (rc == 0 && argmax > 0).then_some(argmax as usize)
+}
+
+/// argv of `pid`; `None` for processes of other users unless we are root.
+#[cfg(target_os = "macos")]
+fn procargs(pid: i32, argmax: usize) -> Option<Vec<String>> {
+    let mut buffer = vec![0u8; argmax];
+    let mut size = buffer.len();
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROCARGS2, pid];
+    // SAFETY: sysctl writes at most `size` bytes into `buffer`.
+    let rc = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            buffer.as_mut_ptr().cast(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    parse_procargs2(&buffer[..size])
+}
+
+/// Splits a KERN_PROCARGS2 buffer: argc, the exec path, NUL padding, then
+/// argc arguments (the environment follows them).
+#[cfg(any(target_os = "macos", test))]
+fn parse_procargs2(buffer: &[u8]) -> Option<Vec<String>> {
+    let argc = i32::from_ne_bytes(buffer.get(..4)?.try_into().ok()?);
+    let rest = &buffer[4..];
+    let rest = &rest[rest.iter().position(|b| *b == 0)?..];
+    let Some(start) = rest.iter().position(|b| *b != 0) else {
+        return Some(Vec::new());
+    };
+    Some(
+        rest[start..]
+            .split(|b| *b == 0)
+            .take(argc.max(0) as usize)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
+
+/// The login name of `uid`, from the password database.
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: getpwuid_r only writes into `pwd` and `buffer`.
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: pw_name points into `buffer`, NUL-terminated.
+    Some(unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) }.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+/// New processes (exec'd, or forked off) from the kernel's proc connector,
+/// read on a thread of their own. The error that stops the thread is sent
+/// last, so it is logged where the monitor's other messages go.
+#[cfg(target_os = "linux")]
+fn proc_events() -> std::io::Result<tokio::sync::mpsc::UnboundedReceiver<std::io::Result<i32>>> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    const NETLINK_CONNECTOR: i32 = 11;
+    const CN_IDX_PROC: u32 = 1;
+    const CN_VAL_PROC: u32 = 1;
+    const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+    // SAFETY: plain socket calls on a descriptor we own; the address and
+    // message buffers outlive the calls they are passed to.
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, NETLINK_CONNECTOR);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+        let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+        addr.nl_family = libc::AF_NETLINK as u16;
+        addr.nl_groups = CN_IDX_PROC;
+        let len = std::mem::size_of::<libc::sockaddr_nl>() as u32;
+        if libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr, len) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // nlmsghdr, cn_msg, then the operation.
+        let mut message = Vec::with_capacity(40);
+        message.extend(40u32.to_ne_bytes());
+        message.extend((libc::NLMSG_DONE as u16).to_ne_bytes());
+        message.extend([0u8; 10]);
+        message.extend(CN_IDX_PROC.to_ne_bytes());
+        message.extend(CN_VAL_PROC.to_ne_bytes());
+        message.extend([0u8; 8]);
+        message.extend(4u16.to_ne_bytes());
+        message.extend([0u8; 2]);
+        message.extend(PROC_CN_MCAST_LISTEN.to_ne_bytes());
+        if libc::send(fd, message.as_ptr().cast(), message.len(), 0) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        socket
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            // SAFETY: reads at most buffer.len() bytes into the buffer.
+            let n = unsafe { libc::recv(socket.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+            if n < 0 {
+                let error = std::io::Error::last_os_error();
+                // ENOBUFS: events were dropped; the periodic scan covers them.
+                if matches!(error.raw_os_error(), Some(libc::EINTR) | Some(libc::ENOBUFS)) {
+                    continue;
+                }
+                let _ = tx.send(Err(error));
+                return;
+            }
+            for pid in parse_proc_events(&buffer[..n as usize]) {
+                if tx.send(Ok(pid)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn proc_events() -> std::io::Result<tokio::sync::mpsc::UnboundedReceiver<std::io::Result<i32>>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the proc connector is Linux-only"))
+}
+
+/// The process ids in a datagram of proc connector messages: the process
+/// of every exec, and the child of every fork that is not a new thread.
+fn parse_proc_events(datagram: &[u8]) -> Vec<i32> {
+    const NLMSG_HDRLEN: usize = 16;
+    const CN_MSG_LEN: usize = 20;
+    const PROC_EVENT_FORK: u32 = 0x1;
+    const PROC_EVENT_EXEC: u32 = 0x2;
+
+    let word = |bytes: &[u8], at: usize| -> Option<u32> {
+        Some(u32::from_ne_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+    };
+    let mut pids = Vec::new();
+    let mut offset = 0;
+    while let Some(len) = word(datagram, offset) {
+        let len = len as usize;
+        let Some(message) = datagram.get(offset..offset + len).filter(|_| len >= NLMSG_HDRLEN) else {
+            break;
+        };
+        // proc_event: what, cpu, timestamp, then the event data.
+        let event = &message[(NLMSG_HDRLEN + CN_MSG_LEN).min(len)..];
+        match word(event, 0) {
+            Some(PROC_EVENT_EXEC) => pids.extend(word(event, 20).map(|tgid| tgid as i32)),
+            Some(PROC_EVENT_FORK) => {
+                if let (Some(pid), Some(tgid)) = (word(event, 24), word(event, 28)) {
+                    if pid == tgid {
+                        pids.push(tgid as i32);
+                    }
+                }
+            }
+            _ => {}
+        }
+        offset += (len + 3) & !3;
+    }
+    pids
+}
+
+/// The distinct file paths mapped into a process, from a /proc/<pid>/maps
+/// file. Anonymous and pseudo mappings such as `[heap]` are left out.
+pub fn read_maps(path: &Path) -> std::io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)?;
+    let mut seen = HashSet::new();
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            // address perms offset dev inode pathname; the path may hold spaces.
+            let path = line.splitn(6, char::is_whitespace).nth(5)?.trim_start();
+            path.starts_with('/').then(|| path.trim_end_matches(" (deleted)").to_string())
+        })
+        .filter(|path| seen.insert(path.clone()))
+        .collect())
+}
+
+/// The injection variables set in a /proc/<pid>/environ file.
+pub fn read_injection_env(path: &Path) -> std::io::Result<Vec<(String, String)>> {
+    Ok(fs::read(path)?
+        .split(|b| *b == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (name, value) = entry.split_once('=')?;
+            INJECTION_VARIABLES
+                .contains(&name)
+                .then(|| (name.to_string(), value.to_string()))
+        })
+        .collect())
+}
+
+/// The descriptors a /proc/<pid> directory's process holds on any of
+/// `protected`, with their access mode from fdinfo.
+pub fn read_open_files(dir: &Path, protected: &HashSet<PathBuf>) -> std::io::Result<Vec<OpenFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir.join("fd"))? {
+        let entry = entry?;
+        let Ok(target) = fs::read_link(entry.path()) else {
+            continue;
+        };
+        let target = match target.to_str().and_then(|t| t.strip_suffix(" (deleted)")) {
+            Some(unlinked) => PathBuf::from(unlinked),
+            None => target,
+        };
+        if !protected.contains(&target) {
+            continue;
+        }
+        let Some(fd) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+            continue;
+        };
+        // The fd may be closed between the two reads.
+        let Ok(info) = fs::read_to_string(dir.join("fdinfo").join(fd.to_string())) else {
+            continue;
+        };
+        let mode = info.lines().find_map(|line| FdMode::from_flags(line.strip_prefix("flags:")?));
+        if let Some(mode) = mode {
+            files.push(OpenFile { fd, path: target, mode });
+        }
+    }
+    Ok(files)
+}
+
+/// Listening TCP sockets in a /proc/net/tcp or /proc/net/tcp6 file, as
+/// (port, socket inode).
+pub fn read_listeners(path: &Path) -> std::io::Result<Vec<(u16, u64)>> {
+    const TCP_LISTEN: &str = "0A";
+    let text = fs::read_to_string(path)?;
+    Ok(text
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(3) != Some(&TCP_LISTEN) {
+                return None;
+            }
+            let port = u16::from_str_radix(fields[1].rsplit_once(':')?.1, 16).ok()?;
+            Some((port, fields.get(9)?.parse().ok()?))
+        })
+        .collect())
+}
+
+/// The process holding each socket in `inodes`, from the /proc/<pid>/fd
+/// links. A socket shared after fork goes to the lowest pid, usually the
+/// parent; sockets of processes we may not read are missing.
+#[cfg(target_os = "linux")]
+fn socket_owners(proc: &Path, inodes: &HashSet<u64>) -> HashMap<u64, i32> {
+    let mut owners = HashMap::new();
+    let Ok(entries) = fs::read_dir(proc) else {
+        return owners;
+    };
+    for entry in entries.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<i32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let inode = target
+                .to_str()
+                .and_then(|target| target.strip_prefix("socket:[")?.strip_suffix(']')?.parse::<u64>().ok());
+            if let Some(inode) = inode.filter(|inode| inodes.contains(inode)) {
+                owners.entry(inode).and_modify(|owner: &mut i32| *owner = (*owner).min(pid)).or_insert(pid);
+            }
+        }
+    }
+    owners
+}
+
+/// Listeners on any of `ports`, over IPv4 and IPv6.
+#[cfg(target_os = "linux")]
+pub fn debug_listeners(ports: &[u16]) -> std::io::Result<Vec<Listener>> {
+    let proc = Path::new("/proc");
+    let mut sockets = read_listeners(&proc.join("net/tcp"))?;
+    // There is no tcp6 on a kernel without IPv6.
+    sockets.extend(read_listeners(&proc.join("net/tcp6")).unwrap_or_default());
+    sockets.retain(|(port, _)| ports.contains(port));
+    if sockets.is_empty() {
+        return Ok(Vec::new());
+    }
+    let owners = socket_owners(proc, &sockets.iter().map(|(_, inode)| *inode).collect());
+    let mut listeners: Vec<Listener> = sockets.iter().map(|(port, inode)| (*port, owners.get(inode).copied())).collect();
+    listeners.sort();
+    listeners.dedup();
+    Ok(listeners)
+}
+
+/// Listeners on any of `ports`, over IPv4 and IPv6, from GetExtendedTcpTable.
+#[cfg(windows)]
+pub fn debug_listeners(ports: &[u16]) -> std::io::Result<Vec<Listener>> {
+    use std::ffi::c_void;
+
+    #[link(name = "iphlpapi")]
+    extern "system" {
+        fn GetExtendedTcpTable(table: *mut c_void, size: *mut u32, order: i32, family: u32, class: i32, reserved: u32)
+            -> u32;
+    }
+    const TCP_TABLE_OWNER_PID_LISTENER: i32 = 3;
+    const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+    // (address family, MIB_TCP(6)ROW_OWNER_PID size, offset of dwLocalPort, of dwOwningPid)
+    const TABLES: [(u32, usize, usize, usize); 2] = [(2, 24, 8, 20), (23, 56, 20, 52)];
+
+    let mut listeners = Vec::new();
+    for (family, row_size, port_at, pid_at) in TABLES {
+        let mut size = 0u32;
+        let mut table: Vec<u32> = Vec::new();
+        loop {
+            // SAFETY: the buffer holds `size` bytes, as the call is told;
+            // u32 elements keep it aligned for the table's DWORD fields.
+            let status = unsafe {
+                GetExtendedTcpTable(table.as_mut_ptr().cast(), &mut size, 0, family, TCP_TABLE_OWNER_PID_LISTENER, 0)
+            };
+            match status {
+                0 => break,
+                ERROR_INSUFFICIENT_BUFFER => table = vec![0; (size as usize).div_ceil(4)],
+                error => return Err(std::io::Error::from_raw_os_error(error as i32)),
+            }
+        }
+        let bytes: Vec<u8> = table.iter().flat_map(|word| word.to_ne_bytes()).collect();
+        let Some(count) = table.first() else { continue };
+        for row in bytes[4..].chunks_exact(row_size).take(*count as usize) {
+            // The port sits in network byte order in the low half of a DWORD.
+            let port = u16::from_be_bytes([row[port_at], row[port_at + 1]]);
+            let pid = u32::from_ne_bytes(row[pid_at..pid_at + 4].try_into().unwrap_or_default());
+            if ports.contains(&port) {
+                listeners.push((port, Some(pid as i32)));
+            }
+        }
+    }
+    listeners.sort();
+    listeners.dedup();
+    Ok(listeners)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn debug_listeners(_ports: &[u16]) -> std::io::Result<Vec<Listener>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "listening sockets are only read on Linux and Windows",
+    ))
+}
+
+/// Reads something per process in `targets`, with `None` for those we may
+/// not read. Processes that exit meanwhile are dropped.
+fn read_each<T>(
+    targets: Vec<ProcessInfo>,
+    read: impl Fn(i32) -> std::io::Result<T>,
+) -> Vec<(ProcessInfo, Option<T>)> {
+    targets
+        .into_iter()
+        .filter_map(|process| match read(process.pid) {
+            Ok(value) => Some((process, Some(value))),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Some((process, None)),
+            Err(_) => None,
+        })
+        .collect()
+}
+
+/// Field `n` (1-based, as in proc(5)) of a /proc/<pid>/stat line. The
+/// command name in parentheses may itself contain spaces.
+fn stat_field(stat: &str, n: usize) -> Option<u64> {
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(n - 3)?.parse().ok()
+}
+
+/// uid -> name from a passwd file; empty if it cannot be read.
+fn read_users(passwd: &Path) -> std::collections::HashMap<u32, String> {
+    fs::read_to_string(passwd)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let uid = fields.nth(1)?.parse().ok()?;
+            Some((uid, name.to_string()))
+        })
+        .collect()
+}
+
+/// Whether a debugger is attached to this process, and which one.
+#[cfg(target_os = "linux")]
+pub fn is_being_traced() -> Option<TracerInfo> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let pid: i32 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))?
+        .trim()
+        .parse()
+        .ok()?;
+    if pid == 0 {
+        return None;
+    }
+    let command = fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|comm| comm.trim_end().to_string());
+    Some(TracerInfo {
+        pid: Some(pid),
+        command,
+    })
+}
+
+/// Whether a debugger is attached to this process: P_TRACED in kinfo_proc.
+#[cfg(target_os = "macos")]
+pub fn is_being_traced() -> Option<TracerInfo> {
+    // libc has no kinfo_proc for Apple targets. On 64-bit macOS it is 648
+    // bytes and kp_proc.p_flag sits at offset 32 (see Apple QA1361).
+    const KINFO_PROC_SIZE: usize = 648;
+    const P_FLAG_OFFSET: usize = 32;
+    const P_TRACED: i32 = 0x0000_0800;
+
+    let mut info = [0u8; KINFO_PROC_SIZE];
+    let mut size = info.len();
+    let mut mib = [libc::CTL_KERN, libc::KERN_PROC, libc::KERN_PROC_PID, std::process::id() as i32];
+    // SAFETY: sysctl writes at most `size` bytes into `info`.
+    let rc = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            info.as_mut_ptr().cast(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if rc != 0 || size < P_FLAG_OFFSET + 4 {
+        return None;
+    }
+    let flags = i32::from_ne_bytes(info[P_FLAG_OFFSET..P_FLAG_OFFSET + 4].try_into().ok()?);
+    (flags & P_TRACED != 0).then_some(TracerInfo {
+        pid: None,
+        command: None,
+    })
+}
+
+/// Whether a debugger is attached to this process, locally or remotely.
+#[cfg(windows)]
+pub fn is_being_traced() -> Option<TracerInfo> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn IsDebuggerPresent() -> i32;
+        fn GetCurrentProcess() -> *mut std::ffi::c_void;
+        fn CheckRemoteDebuggerPresent(process: *mut std::ffi::c_void, present: *mut i32) -> i32;
+    }
+    let mut remote = 0;
+    // SAFETY: plain queries about the current process.
+    let traced = unsafe {
+        IsDebuggerPresent() != 0 || (CheckRemoteDebuggerPresent(GetCurrentProcess(), &mut remote) != 0 && remote != 0)
+    };
+    traced.then_some(TracerInfo {
+        pid: None,
+        command: None,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn is_being_traced() -> Option<TracerInfo> {
+    None
+}
+
+/// Our PID followed by the chain of parents up to init.
+fn own_lineage() -> HashSet<i32> {
+    let mut pids = HashSet::new();
+    let mut pid = std::process::id() as i32;
+    while pid > 0 && pids.insert(pid) {
+        match parent_pid(pid) {
+            Some(parent) => pid = parent,
+            None => break,
+        }
+    }
+    pids
+}
+
+/// Whether `pid` is this process or one of its descendants. Walked at
+/// match time, so children come and go from the set as they are spawned
+/// and reaped; a child that was reparented away no longer counts.
+fn in_own_tree(pid: i32) -> bool {
+    let own = std::process::id() as i32;
+    let mut seen = HashSet::new();
+    let mut pid = pid;
+    while pid > 0 && seen.insert(pid) {
+        if pid == own {
+            return true;
+        }
+        match parent_pid(pid) {
+            Some(parent) => pid = parent,
+            None => return false,
+        }
+    }
+    false
+}
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    lookup_process(pid).map(|(ppid, _)| ppid)
+}
+
+/// The ancestors of `pid`, parent first, up to `depth` of them. The walk
+/// ends at init (or System) and, quietly, at a parent that exits meanwhile.
+pub fn parent_chain(pid: i32, depth: usize) -> Vec<Ancestor> {
+    let mut chain = Vec::new();
+    if depth == 0 {
+        return chain;
+    }
+    let mut seen = HashSet::from([pid]);
+    let mut next = parent_pid(pid);
+    while let Some(ppid) = next {
+        // A recycled parent PID could otherwise loop forever.
+        if chain.len() >= depth || ppid <= 0 || !seen.insert(ppid) {
+            break;
+        }
+        let Some((grandparent, ancestor)) = lookup_process(ppid) else {
+            break;
+        };
+        chain.push(ancestor);
+        next = Some(grandparent);
+    }
+    if chain.iter().any(|ancestor| ancestor.user.is_none() && ancestor.uid.is_some()) {
+        let users = read_users(Path::new("/etc/passwd"));
+        for ancestor in &mut chain {
+            if ancestor.user.is_none() {
+                ancestor.user = ancestor.uid.and_then(|uid| users.get(&uid).cloned());
+            }
+        }
+    }
+    chain
+}
+
+/// The parent PID of `pid` and what the chain shows for it.
+#[cfg(target_os = "linux")]
+fn lookup_process(pid: i32) -> Option<(i32, Ancestor)> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let command = &stat[stat.find('(')? + 1..stat.rfind(')')?];
+    let uid = fs::read_to_string(format!("/proc/{}/status", pid)).ok().and_then(|status| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Uid:"))
+            .and_then(|ids| ids.split_whitespace().next()?.parse::<u32>().ok())
+    });
+    let ancestor = Ancestor {
+        pid,
+        command: command.to_string(),
+        uid,
+        user: None,
+    };
+    Some((stat_field(&stat, 4)? as i32, ancestor))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn lookup_process(pid: i32) -> Option<(i32, Ancestor)> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "ppid=,uid=,user=,comm=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let mut fields = line.splitn(4, char::is_whitespace).filter(|field| !field.is_empty());
+    let ppid = fields.next()?.parse().ok()?;
+    let uid = fields.next()?.parse().ok();
+    let user = fields.next().map(str::to_string);
+    let ancestor = Ancestor {
+        pid,
+        command: fields.next()?.trim().to_string(),
+        uid,
+        user,
+    };
+    Some((ppid, ancestor))
+}
+
+#[cfg(windows)]
+fn lookup_process(pid: i32) -> Option<(i32, Ancestor)> {
+    let entry = toolhelp_processes().ok()?.into_iter().find(|entry| entry.pid as i32 == pid)?;
+    let ancestor = Ancestor {
+        pid,
+        command: entry.image_name(),
+        uid: None,
+        user: None,
+    };
+    Some((entry.parent_pid as i32, ancestor))
+}
+
+/// One process from a ToolHelp snapshot, with what WTS adds about it.
+#[cfg(any(windows, test))]
+struct ToolhelpEntry {
+    pid: u32,
+    parent_pid: u32,
+    /// `szExeFile`: NUL-terminated UTF-16.
+    exe_file: Vec<u16>,
+    session: Option<u32>,
+    user: Option<String>,
+}
+
+#[cfg(any(windows, test))]
+impl ToolhelpEntry {
+    /// The whole image name, spaces included, whatever the system locale.
+    fn image_name(&self) -> String {
+        let len = self.exe_file.iter().position(|c| *c == 0).unwrap_or(self.exe_file.len());
+        String::from_utf16_lossy(&self.exe_file[..len])
+    }
+
+    fn into_process(self, cmdline: String) -> ProcessInfo {
+        ProcessInfo {
+            pid: self.pid as i32,
+            command: self.image_name(),
+            cmdline,
+            uid: None,
+            user: self.user,
+            start_time: None,
+            session: self.session,
+            path: None,
+        }
+    }
+}
+
+/// Every process in a ToolHelp snapshot, without session or user yet.
+#[cfg(windows)]
+fn toolhelp_processes() -> std::io::Result<Vec<ToolhelpEntry>> {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct ProcessEntry32W {
+        size: u32,
+        usage: u32,
+        process_id: u32,
+        default_heap_id: usize,
+        module_id: u32,
+        threads: u32,
+        parent_process_id: u32,
+        priority: i32,
+        flags: u32,
+        exe_file: [u16; 260],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateToolhelp32Snapshot(flags: u32, process_id: u32) -> *mut c_void;
+        fn Process32FirstW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+        fn Process32NextW(snapshot: *mut c_void, entry: *mut ProcessEntry32W) -> i32;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+    const TH32CS_SNAPPROCESS: u32 = 0x2;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+    // SAFETY: the snapshot handle is checked and closed; the entry is a
+    // plain C struct with its size field set as Process32FirstW requires.
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut entry: ProcessEntry32W = std::mem::zeroed();
+        entry.size = std::mem::size_of::<ProcessEntry32W>() as u32;
+        let mut processes = Vec::new();
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+        while more {
+            processes.push(ToolhelpEntry {
+                pid: entry.process_id,
+                parent_pid: entry.parent_process_id,
+                exe_file: entry.exe_file.to_vec(),
+                session: None,
+                user: None,
+            });
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+        let error = std::io::Error::last_os_error();
+        CloseHandle(snapshot);
+        if processes.is_empty() {
+            return Err(error);
+        }
+        Ok(processes)
+    }
+}
+
+/// pid -> (session, account name) from the Terminal Services process list.
+/// The account is missing where we may not read the process token.
+#[cfg(windows)]
+fn wts_sessions() -> HashMap<u32, (u32, Option<String>)> {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct WtsProcessInfoW {
+        session_id: u32,
+        process_id: u32,
+        process_name: *mut u16,
+        user_sid: *mut c_void,
+    }
+
+    #[link(name = "wtsapi32")]
+    extern "system" {
+        fn WTSEnumerateProcessesW(
+            server: *mut c_void,
+            reserved: u32,
+            version: u32,
+            info: *mut *mut WtsProcessInfoW,
+            count: *mut u32,
+        ) -> i32;
+        fn WTSFreeMemory(memory: *mut c_void);
+    }
+
+    let mut sessions = HashMap::new();
+    let mut info: *mut WtsProcessInfoW = std::ptr::null_mut();
+    let mut count = 0;
+    // SAFETY: WTS allocates `count` entries at `info`, freed below; SIDs are
+    // only read while that memory is alive.
+    unsafe {
+        if WTSEnumerateProcessesW(std::ptr::null_mut(), 0, 1, &mut info, &mut count) == 0 {
+            return sessions;
+        }
+        for entry in std::slice::from_raw_parts(info, count as usize) {
+            let user = (!entry.user_sid.is_null()).then(|| account_name(entry.user_sid));
+            sessions.insert(entry.process_id, (entry.session_id, user.flatten()));
+        }
+        WTSFreeMemory(info.cast());
+    }
+    sessions
+}
+
+/// The account name of a SID.
+///
+/// # Safety
+/// `sid` must point to a valid SID.
+#[cfg(windows)]
+unsafe fn account_name(sid: *mut std::ffi::c_void) -> Option<String> {
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn LookupAccountSidW(
+            system: *const u16,
+            sid: *mut std::ffi::c_void,
+            name: *mut u16,
+            name_len: *mut u32,
+            domain: *mut u16,
+            domain_len: *mut u32,
+            sid_use: *mut u32,
+        ) -> i32;
+    }
+    let mut name = [0u16; 256];
+    let mut domain = [0u16; 256];
+    let (mut name_len, mut domain_len, mut sid_use) = (name.len() as u32, domain.len() as u32, 0);
+    // SAFETY: fixed-size buffers whose lengths are passed along.
+    let found = unsafe {
+        LookupAccountSidW(
+            std::ptr::null(),
+            sid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut sid_use,
+        )
+    };
+    (found != 0).then(|| String::from_utf16_lossy(&name[..name_len as usize]))
+}
+
+/// The owner of `pid`'s process token, for processes the WTS list gave no
+/// SID for. `None` where we may not open the process.
+#[cfg(windows)]
+fn token_user(pid: u32) -> Option<String> {
+    use std::ffi::c_void;
+
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const TOKEN_QUERY: u32 = 0x0008;
+    const TOKEN_USER: u32 = 1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn OpenProcess(access: u32, inherit: i32, pid: u32) -> *mut c_void;
+        fn CloseHandle(handle: *mut c_void) -> i32;
+    }
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn OpenProcessToken(process: *mut c_void, access: u32, token: *mut *mut c_void) -> i32;
+        fn GetTokenInformation(token: *mut c_void, class: u32, info: *mut c_void, len: u32, returned: *mut u32) -> i32;
+    }
+
+    // SAFETY: both handles are closed before returning. TOKEN_USER starts
+    // with the SID pointer, which points into the same aligned buffer.
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return None;
+        }
+        let mut token = std::ptr::null_mut();
+        let opened = OpenProcessToken(process, TOKEN_QUERY, &mut token);
+        CloseHandle(process);
+        if opened == 0 {
+            return None;
+        }
+        let mut buffer = [0u64; 64];
+        let mut returned = 0;
+        let size = std::mem::size_of_val(&buffer) as u32;
+        let read = GetTokenInformation(token, TOKEN_USER, buffer.as_mut_ptr().cast(), size, &mut returned);
+        CloseHandle(token);
+        if read == 0 {
+            return None;
+        }
+        account_name(*buffer.as_ptr().cast::<*mut c_void>())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lookup_process(_pid: i32) -> Option<(i32, Ancestor)> {
+    None
+}
+
+#[cfg(unix)]
+fn send_action(pid: i32, action: HfsAction) -> ActionOutcome {
+    let signal = match action {
+        HfsAction::LogOnly => return ActionOutcome::Done,
+        HfsAction::Kill => libc::SIGKILL,
+        HfsAction::Suspend => libc::SIGSTOP,
+    };
+    // SAFETY: kill only delivers a signal; the caller has ruled out our own lineage.
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        return ActionOutcome::Done;
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => ActionOutcome::Gone,
+        Some(libc::EPERM) => ActionOutcome::PermissionDenied,
+        _ => ActionOutcome::Failed(err.to_string()),
+    }
+}
+
+#[cfg(not(unix))]
+fn send_action(pid: i32, action: HfsAction) -> ActionOutcome {
+    match action {
+        HfsAction::LogOnly => ActionOutcome::Done,
+        HfsAction::Suspend => ActionOutcome::Failed("suspending is not supported on this platform".to_string()),
+        HfsAction::Kill => {
+            // taskkill /F calls TerminateProcess.
+            let output = std::process::Command::new("taskkill")
+                .args(["/F", "/PID", &pid.to_string()])
+                .output();
+            match output {
+                Ok(output) if output.status.success() => ActionOutcome::Done,
+                Ok(output) => {
+                    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+                    if stderr.contains("not found") {
+                        ActionOutcome::Gone
+                    } else if stderr.contains("access is denied") {
+                        ActionOutcome::PermissionDenied
+                    } else {
+                        ActionOutcome::Failed(stderr.trim().to_string())
+                    }
+                }
+                Err(e) => ActionOutcome::Failed(e.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn test_toolhelp_finds_this_process() {
+        let entries = toolhelp_processes().unwrap();
+        let me = entries.iter().find(|entry| entry.pid == std::process::id()).unwrap();
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(me.image_name(), exe.file_name().unwrap().to_str().unwrap());
+        assert!(wts_sessions().get(&std::process::id()).is_some());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    type Messages = Arc<Mutex<Vec<String>>>;
+
+    /// A `sleep` copied under a name no real process has.
+    fn spawn_sleeper(dir: &std::path::Path, name: &str) -> std::process::Child {
+        let binary = dir.join(name);
+        std::fs::copy("/bin/sleep", &binary).unwrap();
+        let child = std::process::Command::new(&binary).arg("30").spawn().unwrap();
+        // Give ps a moment to see the new comm.
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        child
+    }
+
+    fn hunter(
+        pattern: &str,
+        action: HfsAction,
+    ) -> (HfsHunter<impl Fn(Violation) + Send + Sync>, Messages) {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let log = messages.clone();
+        let patterns = CommandPatterns::compile(&[pattern.to_string()], PatternOptions::default()).unwrap();
+        let report = message_callback(move |msg| log.lock().unwrap().push(msg));
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(1), report);
+        hunter.action = action;
+        // Fixture PIDs may belong to real processes with real parents.
+        hunter.chain_depth = 0;
+        // Test sleepers are children of the test process.
+        hunter.exclude_own_tree = false;
+        (hunter, messages)
+    }
+
+    #[tokio::test]
+    async fn test_kill_mode_terminates_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut child = spawn_sleeper(dir.path(), "hfskillme");
+        let (mut hunter, messages) = hunter("hfskillme", HfsAction::Kill);
+
+        assert_eq!(hunter.scan_once().await.unwrap().len(), 1);
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert!(messages.lock().unwrap()[0].ends_with("(killed)"), "{:?}", messages);
+    }
+
+    #[tokio::test]
+    async fn test_log_only_leaves_match_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut child = spawn_sleeper(dir.path(), "hfslogme");
+        let (mut hunter, messages) = hunter("hfslogme", HfsAction::LogOnly);
+
+        assert_eq!(hunter.scan_once().await.unwrap().len(), 1);
+        assert_eq!(hunter.scan_once().await.unwrap().len(), 0);
+        assert!(child.try_wait().unwrap().is_none());
+        assert!(messages.lock().unwrap()[0].contains(&format!("PID={}", child.id())));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    fn compile(patterns: &[&str], options: PatternOptions) -> Result<CommandPatterns, String> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        CommandPatterns::compile(&patterns, options)
+    }
+
+    #[test]
+    fn test_anchored_pattern_skips_gdbus() {
+        let patterns = compile(&["^gdb$", "frida"], PatternOptions::default()).unwrap();
+        assert_eq!(patterns.matching("gdb"), Some("^gdb$"));
+        assert_eq!(patterns.matching("gdbus"), None);
+        assert_eq!(patterns.matching("frida-server"), Some("frida"));
+        assert_eq!(patterns.matching("bash"), None);
+    }
+
+    #[test]
+    fn test_case_insensitive_flag() {
+        let patterns = compile(&["(?i)^frida"], PatternOptions::default()).unwrap();
+        assert!(patterns.matching("Frida-Server").is_some());
+        assert!(compile(&["^frida"], PatternOptions::default()).unwrap().matching("Frida-Server").is_none());
+    }
+
+    #[test]
+    fn test_literal_match_modes() {
+        use LiteralMatch::*;
+        let modes = |literal_match, case_sensitive| PatternOptions {
+            literal_match,
+            case_sensitive,
+            ..Default::default()
+        };
+        // (pattern, command, hits under substring, word boundary, exact)
+        let table = [
+            ("gdb", "gdb", true, true, true),
+            ("gdb", "/usr/bin/gdb -p 1", true, true, true),
+            ("gdb", "gdbus monitor --system", true, false, false),
+            ("gdb", "vim notes-on-gdb.txt", true, true, false),
+            ("strace", "strace -f ./a.out", true, true, true),
+            ("strace", "strace-log-merge out", true, true, false),
+            ("strace", "/usr/bin/strace-log-merge", true, true, false),
+            ("frida", "frida-server", true, true, false),
+            ("frida", "python3 -m frida_tools.repl", true, true, false),
+            ("frida", "fridge", false, false, false),
+            ("frida", "Frida.exe", true, true, true),
+            ("frida", "C:\\Tools\\frida.exe -U", true, true, true),
+            ("/Cellar/radare2/", "/usr/local/Cellar/radare2/5.9.0/bin/r2", true, true, false),
+        ];
+        for (pattern, command, substring, word, exact) in table {
+            for (mode, expected) in [(Substring, substring), (WordBoundary, word), (Exact, exact)] {
+                let patterns = compile(&[pattern], modes(mode, false)).unwrap();
+                let hit = patterns.matching(command).is_some();
+                assert_eq!(hit, expected, "{} in {:?} under {:?}", pattern, command, mode);
+            }
+        }
+
+        // Case only matters when asked for, and regexes are used as written.
+        let sensitive = compile(&["frida"], modes(WordBoundary, true)).unwrap();
+        assert!(sensitive.matching("Frida.exe").is_none());
+        assert!(sensitive.matching("frida-server").is_some());
+        let regex = compile(&["^gdb"], modes(Exact, false)).unwrap();
+        assert!(regex.matching("gdbus").is_some());
+        assert!(regex.matching("GDB").is_none());
+        assert_eq!(PatternOptions::default().literal_match, WordBoundary);
+
+        // Library files carry a lib prefix, so they are matched as substrings.
+        let gadget = compile(&["frida-gadget"], modes(Exact, false)).unwrap();
+        assert!(gadget.matching("/data/local/tmp/libfrida-gadget.so").is_none());
+        assert!(gadget.matching_library("/data/local/tmp/libfrida-gadget.so").is_some());
+    }
+
+    #[test]
+    fn test_invalid_pattern_reported_unless_literal() {
+        let err = compile(&["gdb", "ida(64"], PatternOptions::default()).err().unwrap();
+        assert!(err.contains("\"ida(64\" at position 4"), "{}", err);
+
+        let patterns = compile(&["ida(64"], PatternOptions { literal: true, ..Default::default() }).unwrap();
+        assert_eq!(patterns.matching("ida(64).exe"), Some("ida(64"));
+        assert!(patterns.matching("ida64").is_none());
+    }
+
+    fn process(pid: i32, command: &str, uid: u32, user: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            command: command.to_string(),
+            cmdline: command.to_string(),
+            uid: Some(uid),
+            user: Some(user.to_string()),
+            start_time: Some(1),
+            session: None,
+            path: None,
+        }
+    }
+
+    #[test]
+    fn test_allow_rules_skip_but_count() {
+        let (mut hunter, messages) = hunter("^strace", HfsAction::LogOnly);
+        let allow = HfsAllowlist {
+            pids: vec![40],
+            users: vec!["ci".to_string(), "1500".to_string()],
+            patterns: vec!["--ci-trace$".to_string()],
+        };
+        hunter.set_allowlist(&allow, PatternOptions::default()).unwrap();
+
+        let processes = [
+            process(10, "strace -p 1", 1000, "ci"),
+            process(11, "strace -p 1", 1000, "alice"),
+            process(20, "strace -p 2", 1500, "build"),
+            process(30, "strace --ci-trace", 0, "root"),
+            process(40, "strace -f make", 0, "root"),
+            process(50, "strace -f make", 0, "root"),
+        ];
+        assert_eq!(hunter.check_processes(&processes).len(), 2);
+        let messages = messages.lock().unwrap();
+        assert!(messages[0].contains("PID=11,"), "{:?}", messages);
+        assert!(messages[1].contains("PID=50,"), "{:?}", messages);
+        assert_eq!(hunter.stats.allowed, 4);
+        assert_eq!(hunter.stats.violations, 2);
+    }
+
+    #[test]
+    fn test_allowlist_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hfs.toml");
+        fs::write(&path, "patterns = [\"^gdb$\"]\n\n[allow]\nusers = [\"ci\"]\npids = [7]\n").unwrap();
+        let config = HfsConfig::load(&path).unwrap();
+        assert_eq!(config.patterns, [PatternRule::new("^gdb$")]);
+        assert_eq!(config.allow.users, ["ci"]);
+        assert_eq!(config.allow.pids, [7]);
+
+        fs::write(&path, "[allow]\nuser = [\"ci\"]\n").unwrap();
+        assert!(HfsConfig::load(&path).err().unwrap().contains("user"));
+    }
+
+    #[test]
+    fn test_pattern_rule_syntax() {
+        let rule = |regex: &str, action: Option<&str>, severity| PatternRule {
+            regex: regex.to_string(),
+            action: action.map(|action| action.parse().unwrap()),
+            severity,
+        };
+        let cases = [
+            ("frida=kill", rule("frida", Some("kill"), Severity::Warning)),
+            ("strace=log@info", rule("strace", Some("log"), Severity::Info)),
+            (
+                "ghidra=command:/usr/local/bin/alert.sh --tag=re@critical",
+                rule("ghidra", Some("command:/usr/local/bin/alert.sh --tag=re"), Severity::Critical),
+            ),
+            // No valid action after any '=': the whole spec is the pattern.
+            ("node --inspect=9229", rule("node --inspect=9229", None, Severity::Warning)),
+            ("frida=kil", rule("frida=kil", None, Severity::Warning)),
+            ("x=command:", rule("x=command:", None, Severity::Warning)),
+            ("=kill", rule("=kill", None, Severity::Warning)),
+            ("a=b=suspend", rule("a=b", Some("suspend"), Severity::Warning)),
+        ];
+        for (spec, expected) in cases {
+            let parsed = PatternRule::parse(spec);
+            assert_eq!(parsed, expected, "{}", spec);
+            assert_eq!(parsed.to_string(), spec);
+        }
+        assert_eq!(PatternRule::parse("gdb=log@fatal").action, None);
+
+        let config: HfsConfig = toml::from_str(
+            "patterns = [\"frida=kill\", { pattern = \"ghidra\", action = \"command:alert.sh\", severity = \"critical\" }]",
+        )
+        .unwrap();
+        assert_eq!(
+            config.patterns,
+            [
+                rule("frida", Some("kill"), Severity::Warning),
+                rule("ghidra", Some("command:alert.sh"), Severity::Critical)
+            ]
+        );
+        let error = toml::from_str::<HfsConfig>("patterns = [{ pattern = \"gdb\", action = \"stop\" }]").unwrap_err();
+        assert!(error.to_string().contains("unknown HFS action"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_rules_apply_their_own_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut killed = spawn_sleeper(dir.path(), "hfsrulekill");
+        let mut logged = spawn_sleeper(dir.path(), "hfsrulelog");
+        let mut alerted = spawn_sleeper(dir.path(), "hfsrulecmd");
+        let mut defaulted = spawn_sleeper(dir.path(), "hfsruledefault");
+        let alert = dir.path().join("alert.txt");
+        let rules = [
+            PatternRule::parse("hfsrulekill=kill"),
+            PatternRule::parse("hfsrulelog=log@info"),
+            PatternRule::parse(&format!(
+                "hfsrulecmd=command:echo \"$SERIALK_HFS_PID $SERIALK_HFS_PATTERN\" > {}@critical",
+                alert.display()
+            )),
+            PatternRule::new("hfsruledefault"),
+        ];
+        let (mut hunter, messages) = hunter("unused", HfsAction::Suspend);
+        hunter.replace_patterns(&rules, PatternOptions::default()).unwrap();
+
+        let mut found = hunter.scan_once().await.unwrap();
+        found.sort_by_key(|violation| violation.pattern.clone());
+        let summary: Vec<_> = found
+            .iter()
+            .map(|v| {
+                let action = v.action.as_deref().unwrap().split(':').next().unwrap();
+                (v.pattern.as_deref().unwrap(), action, v.severity.unwrap())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("hfsrulecmd", "command", Severity::Critical),
+                ("hfsruledefault", "suspend", Severity::Warning),
+                ("hfsrulekill", "kill", Severity::Warning),
+                ("hfsrulelog", "log", Severity::Info),
+            ]
+        );
+
+        assert!(!killed.wait().unwrap().success());
+        assert!(logged.try_wait().unwrap().is_none());
+        assert!(alerted.try_wait().unwrap().is_none());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !fs::read_to_string(&alert).is_ok_and(|text| text.ends_with('\n')) {
+            assert!(std::time::Instant::now() < deadline, "alert command never ran");
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(fs::read_to_string(&alert).unwrap(), format!("{} hfsrulecmd\n", alerted.id()));
+
+        let messages = messages.lock().unwrap();
+        let line = |pattern: &str| messages.iter().find(|m| m.contains(&format!("RULE={},", pattern))).unwrap().clone();
+        assert!(line("hfsrulekill").ends_with(", ACTION=kill (killed)"), "{:?}", messages);
+        assert!(line("hfsrulelog").ends_with(", ACTION=log"), "{:?}", messages);
+        assert!(line("hfsruledefault").ends_with(", ACTION=suspend (suspended)"), "{:?}", messages);
+        assert!(line("hfsrulecmd").contains(" (ran echo "), "{:?}", messages);
+
+        for child in [&mut logged, &mut alerted, &mut defaulted] {
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_proc_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let add = |pid: &str, comm: &str, cmdline: &[u8], uid: u32| {
+            let proc_dir = root.join(pid);
+            fs::create_dir(&proc_dir).unwrap();
+            fs::write(proc_dir.join("comm"), format!("{}\n", comm)).unwrap();
+            fs::write(proc_dir.join("cmdline"), cmdline).unwrap();
+            let status = format!("Name:\t{}\nUid:\t{u}\t{u}\t{u}\t{u}\n", comm, u = uid);
+            fs::write(proc_dir.join("status"), status).unwrap();
+        };
+        add("100", "frida-server-x8", b"/usr/bin/frida-server-x86_64\0--listen\0", 0);
+        add("2", "kthreadd", b"", 0);
+        add("300", "bash", b"-bash\0", 4242);
+        fs::create_dir(root.join("sys")).unwrap();
+        // A process that exited between read_dir and the reads.
+        fs::create_dir(root.join("400")).unwrap();
+
+        let mut processes = read_proc_with(root, Path::new("tests/fixtures/passwd")).unwrap();
+        processes.sort_by_key(|p| p.pid);
+        let summary: Vec<(i32, &str, &str, Option<u32>)> = processes
+            .iter()
+            .map(|p| (p.pid, p.command.as_str(), p.cmdline.as_str(), p.uid))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, "kthreadd", "", Some(0)),
+                (100, "frida-server-x86_64", "/usr/bin/frida-server-x86_64 --listen", Some(0)),
+                (300, "bash", "-bash", Some(4242))
+            ]
+        );
+        assert_eq!(processes[0].user.as_deref(), Some("root"));
+        // No passwd entry for 4242: the uid alone is kept.
+        assert_eq!(processes[2].user, None);
+        assert!(Violation::new(ViolationKind::ForbiddenProcess, &processes[2], None, 200)
+            .to_string()
+            .contains("PID=300, USER=4242, CMD="));
+    }
+
+    #[test]
+    fn test_parse_ps_fixture() {
+        let stdout = "  PID   UID USER     COMM\n\
+                      \x20   1     0 root     /sbin/launchd\n\
+                      \x20 412   501 alice    /Applications/Frida.app/frida helper\n\
+                      \x20 977  1234 1234     gdb\n\
+                      \n\
+                      garbage line\n";
+        let summary: Vec<(i32, String, Option<u32>, Option<String>)> = parse_ps(stdout)
+            .into_iter()
+            .map(|p| (p.pid, p.command, p.uid, p.user))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, "/sbin/launchd".to_string(), Some(0), Some("root".to_string())),
+                (412, "/Applications/Frida.app/frida helper".to_string(), Some(501), Some("alice".to_string())),
+                (977, "gdb".to_string(), Some(1234), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_bad_configuration() {
+        let gdb = [PatternRule::new("^gdb")];
+        let error = |builder: HfsHunterBuilder| builder.build().err().unwrap();
+        assert_eq!(error(HfsHunterBuilder::default()), ConfigError::NoPatterns);
+        let stopped = HfsOptions {
+            interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(error(HfsHunterBuilder::default().patterns(&gdb).options(stopped)), ConfigError::ZeroInterval);
+        assert!(matches!(
+            error(HfsHunterBuilder::default().patterns(&[PatternRule::new("(unclosed")])),
+            ConfigError::InvalidPattern(_)
+        ));
+        let allow = HfsAllowlist {
+            patterns: vec!["[".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            error(HfsHunterBuilder::default().patterns(&gdb).allowlist(allow)),
+            ConfigError::InvalidAllowlist(_)
+        ));
+        let options = HfsOptions {
+            patterns_file: Some(PathBuf::from("/nonexistent/hfs-patterns")),
+            ..Default::default()
+        };
+        assert!(matches!(
+            error(HfsHunterBuilder::default().options(options)),
+            ConfigError::PatternsFile(_)
+        ));
+
+        // A protected file alone is something to look for.
+        let options = HfsOptions {
+            protect: vec![PathBuf::from("/etc/hostname")],
+            ..Default::default()
+        };
+        assert!(HfsHunterBuilder::default().options(options).build().is_ok());
+    }
+
+    #[test]
+    fn test_built_hunter_matches_old_construction() {
+        // PIDs above any pid_max, so the signals find no process.
+        let processes = [
+            process(1, "bash", 0, "root"),
+            process(2_000_000_010, "gdb -p 1", 0, "root"),
+            process(2_000_000_020, "gdb -p 1", 1000, "alice"),
+            process(2_000_000_030, "strace -p 1", 0, "root"),
+        ];
+        let run = |hunter: &mut HfsHunter<Reporter>, messages: &Messages| {
+            hunter.chain_depth = 0;
+            hunter.exclude_own_tree = false;
+            hunter.check_processes(&processes);
+            hunter.check_processes(&processes);
+            (messages.lock().unwrap().clone(), hunter.stats.clone())
+        };
+
+        let messages: Messages = Arc::new(Mutex::new(Vec::new()));
+        let log = messages.clone();
+        let rules = [PatternRule::new("^gdb"), PatternRule::parse("strace=kill")];
+        let alice = HfsAllowlist {
+            users: vec!["alice".to_string()],
+            ..Default::default()
+        };
+        let options = HfsOptions {
+            interval: Duration::from_secs(2),
+            jitter: Duration::from_millis(500),
+            action: HfsAction::Suspend,
+            scan_env: true,
+            ..Default::default()
+        };
+        // Options given last keep the patterns and allow rules given first.
+        let built = HfsHunterBuilder::default()
+            .patterns(&rules)
+            .allowlist(alice.clone())
+            .options(options)
+            .on_violation(message_callback(move |msg| log.lock().unwrap().push(msg)));
+        let mut built = built.build().unwrap();
+        assert_eq!(
+            (built.scan_interval, built.scan_jitter, built.action, built.scan_env),
+            (Duration::from_secs(2), Duration::from_millis(500), HfsAction::Suspend, true)
+        );
+        let from_builder = run(&mut built, &messages);
+
+        let messages: Messages = Arc::new(Mutex::new(Vec::new()));
+        let log = messages.clone();
+        let patterns = CommandPatterns::compile_rules(&rules, PatternOptions::default()).unwrap();
+        let report: Reporter = Box::new(message_callback(move |msg| log.lock().unwrap().push(msg)));
+        #[allow(deprecated)]
+        let mut old = HfsHunter::new(patterns, DEFAULT_SCAN_INTERVAL, report);
+        old.action = HfsAction::Suspend;
+        old.scan_env = true;
+        old.set_allowlist(&alice, PatternOptions::default()).unwrap();
+        let from_new = run(&mut old, &messages);
+
+        assert_eq!(from_builder, from_new);
+        assert_eq!(from_builder.0.len(), 2);
+        assert_eq!(from_builder.1.allowed, 2);
+    }
+
+    #[test]
+    fn test_jitter_stays_below_max() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+        assert!((0..100).all(|_| jitter(Duration::from_millis(10)) < Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_each_process_reported_once() {
+        let (mut hunter, messages) = hunter("^gdb", HfsAction::LogOnly);
+        let shell = process(1, "bash", 0, "root");
+        let first = process(10, "gdb -p 1", 0, "root");
+        let second = process(20, "gdb -p 1", 0, "root");
+
+        assert_eq!(hunter.check_processes(&[shell.clone(), first.clone()]).len(), 1);
+        assert_eq!(hunter.check_processes(&[shell.clone(), first.clone(), second.clone()]).len(), 1);
+        for _ in 0..3 {
+            assert_eq!(hunter.check_processes(&[shell.clone(), first.clone(), second.clone()]).len(), 0);
+        }
+        assert_eq!(messages.lock().unwrap().len(), 2);
+
+        // PID 10 is reused by a new debugger: a new start time, a new report.
+        let reused = ProcessInfo {
+            start_time: Some(2),
+            session: None,
+            path: None,
+            ..first.clone()
+        };
+        assert_eq!(hunter.check_processes(&[shell.clone(), reused, second.clone()]).len(), 1);
+        // Gone and back with the same identity (no start time known) is new as well.
+        assert_eq!(hunter.check_processes(std::slice::from_ref(&shell)).len(), 0);
+        assert_eq!(hunter.check_processes(&[shell, second]).len(), 1);
+        assert_eq!(messages.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_persistent_violation_reported_then_reminded() {
+        let (mut hunter, messages) = hunter("^gdb", HfsAction::LogOnly);
+        let processes = [process(1, "bash", 0, "root"), process(10, "gdb -p 1", 0, "root")];
+        let start = Instant::now();
+        // Half an hour of scans every five seconds.
+        let reports: Vec<u64> = (0..360)
+            .filter(|tick| !hunter.check_processes_at(&processes, start + Duration::from_secs(tick * 5)).is_empty())
+            .map(|tick| tick * 5)
+            .collect();
+        assert_eq!(reports, [0, 600]);
+        assert_eq!(
+            messages.lock().unwrap().as_slice(),
+            [
+                "[HFS] Unauthorized process detected: PID=10, USER=root(0), CMD=gdb -p 1, RULE=^gdb, ACTION=log",
+                "[HFS] Unauthorized process still present after 10 minutes: PID=10, USER=root(0), CMD=gdb -p 1, RULE=^gdb, ACTION=log",
+            ]
+        );
+        assert_eq!((hunter.stats.violations, hunter.stats.reminders, hunter.stats.suppressed), (1, 1, 358));
+
+        hunter.set_cooldown(HfsCooldown {
+            remind_after_secs: 0,
+            ..HfsCooldown::default()
+        });
+        let later = start + Duration::from_secs(3_600);
+        assert_eq!(hunter.check_processes_at(&processes, later).len(), 1);
+        assert!((1..100).all(|hour| hunter.check_processes_at(&processes, later + Duration::from_secs(hour * 3_600)).is_empty()));
+    }
+
+    #[test]
+    fn test_refused_action_retry_schedule() {
+        let mut repeats = Repeats::new(HfsCooldown {
+            remind_after_secs: 60,
+            retries: 3,
+            retry_backoff_secs: 5,
+        });
+        let key = (10, Some(1), "^gdb".to_string());
+        let start = Instant::now();
+        let mut schedule = Vec::new();
+        let mut waits = Vec::new();
+        for tick in 0..=120 {
+            let now = start + Duration::from_secs(tick);
+            let repeat = repeats.sighting(&key, now);
+            if matches!(repeat, Repeat::First | Repeat::Retry(_)) {
+                // Every attempt is refused with EPERM.
+                waits.push(repeats.refused(&key, now).map(|wait| wait.as_secs()));
+            }
+            if repeat != Repeat::Suppressed {
+                schedule.push((tick, repeat));
+            }
+        }
+        assert_eq!(
+            schedule,
+            [
+                (0, Repeat::First),
+                (5, Repeat::Retry(1)),
+                (15, Repeat::Retry(2)),
+                (35, Repeat::Retry(3)),
+                (60, Repeat::Reminder(Duration::from_secs(60))),
+            ]
+        );
+        assert_eq!(waits, [Some(5), Some(10), Some(20), None]);
+
+        // An exited process is forgotten and starts over.
+        repeats.retain(&HashSet::new());
+        assert_eq!(repeats.sighting(&key, start), Repeat::First);
+    }
+
+    #[test]
+    fn test_raised_severity() {
+        assert_eq!(raised(Severity::Info), Severity::Warning);
+        assert_eq!(raised(Severity::Warning), Severity::Critical);
+        assert_eq!(raised(Severity::Critical), Severity::Critical);
+    }
+
+    #[test]
+    fn test_match_targets_and_argument_only_tokens() {
+        let debuggers = [
+            ProcessInfo {
+                pid: 1,
+                command: "python3".to_string(),
+                cmdline: "python3 -m frida_tools.repl --attach 4242".to_string(),
+                uid: None,
+                user: None,
+                start_time: None,
+                session: None,
+                path: None,
+            },
+            ProcessInfo {
+                pid: 2,
+                command: "qemu-x86_64".to_string(),
+                cmdline: "qemu-x86_64 -g 1234 ./target".to_string(),
+                uid: None,
+                user: None,
+                start_time: None,
+                session: None,
+                path: None,
+            },
+        ];
+        let radare = ProcessInfo {
+            pid: 3,
+            command: "r2".to_string(),
+            cmdline: "r2 -d ./target".to_string(),
+            uid: None,
+            user: None,
+            start_time: None,
+            session: None,
+            path: Some("/usr/local/Cellar/radare2/5.9.0/bin/r2".to_string()),
+        };
+        for (target, expected) in [(MatchTarget::Comm, 0), (MatchTarget::Path, 1), (MatchTarget::Both, 1)] {
+            let (mut hunter, _) = hunter("/Cellar/radare2/", HfsAction::LogOnly);
+            hunter.match_target = target;
+            assert_eq!(hunter.check_processes(std::slice::from_ref(&radare)).len(), expected, "{:?}", target);
+        }
+        for (target, expected) in [(MatchTarget::Comm, 0), (MatchTarget::Cmdline, 2), (MatchTarget::Both, 2)] {
+            let (mut hunter, _) = hunter(r"frida|^qemu\S* .*-g \d+", HfsAction::LogOnly);
+            hunter.match_target = target;
+            assert_eq!(hunter.check_processes(&debuggers).len(), expected, "{:?}", target);
+        }
+
+        let (mut hunter, messages) = hunter("frida", HfsAction::LogOnly);
+        hunter.cmdline_width = 20;
+        hunter.check_processes(&debuggers);
+        let message = messages.lock().unwrap()[0].clone();
+        assert!(message.ends_with("CMD=python3 -m frida_too..., RULE=frida, ACTION=log"), "{}", message);
+    }
+
+    /// Not a correctness test: `cargo test hfs_scan_latency -- --ignored --nocapture`
+    /// compares reading /proc with spawning ps.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore]
+    async fn hfs_scan_latency() {
+        let (hunter, _) = hunter("unused", HfsAction::LogOnly);
+        let rounds = 50;
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            read_proc(Path::new("/proc")).unwrap();
+        }
+        let proc_time = start.elapsed() / rounds;
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            hunter.get_processes_unix().await.unwrap();
+        }
+        let ps_time = start.elapsed() / rounds;
+        println!("/proc scan: {:?}, ps scan: {:?}", proc_time, ps_time);
+    }
+
+    #[test]
+    fn test_tracer_reported_once_per_attach() {
+        let violations = Arc::new(Mutex::new(Vec::new()));
+        let log = violations.clone();
+        let patterns = CommandPatterns::compile(&["unused".to_string()], PatternOptions::default()).unwrap();
+        let report = move |violation: Violation| log.lock().unwrap().push(violation);
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(1), report);
+        hunter.chain_depth = 0;
+        hunter.exclude_own_tree = false;
+        let gdb = TracerInfo {
+            pid: Some(4242),
+            command: Some("gdb".to_string()),
+        };
+        assert_eq!(hunter.check_tracer(Some(gdb.clone())).len(), 1);
+        assert_eq!(hunter.check_tracer(Some(gdb.clone())).len(), 0);
+        assert_eq!(hunter.check_tracer(None).len(), 0);
+        assert_eq!(hunter.check_tracer(Some(gdb)).len(), 1);
+
+        let violations = violations.lock().unwrap();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].kind, ViolationKind::TracerAttached);
+        assert_eq!(violations[0].to_string(), "[HFS] Debugger attached to this process: TracerPid=4242, CMD=gdb");
+        assert_eq!(violations[0].pid, Some(4242));
+    }
+
+    #[tokio::test]
+    async fn test_enumerator_finds_this_process() {
+        let (hunter, _) = hunter("unused", HfsAction::LogOnly);
+        let processes = hunter.get_processes().await.unwrap();
+        let me = processes.iter().find(|p| p.pid == std::process::id() as i32).unwrap();
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_name().unwrap().to_str().unwrap();
+        // macOS ps reports the executable's full path.
+        assert_eq!(me.command.rsplit('/').next(), Some(name), "{:?}", me);
+        if cfg!(target_os = "linux") {
+            assert_eq!(me.path.as_deref().map(Path::new), Some(exe.as_path()));
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_libproc_finds_this_process_by_path() {
+        let processes = libproc_processes().unwrap();
+        let me = processes.iter().find(|p| p.pid == std::process::id() as i32).unwrap();
+        let exe = std::env::current_exe().unwrap().canonicalize().unwrap();
+        assert_eq!(me.path.as_deref().map(Path::new), Some(exe.as_path()), "{:?}", me);
+        assert_eq!(me.command, exe.file_name().unwrap().to_str().unwrap());
+        assert_eq!(me.uid, Some(unsafe { libc::getuid() }));
+        assert!(me.cmdline.starts_with(std::env::args().next().unwrap().as_str()), "{:?}", me);
+    }
+
+    #[test]
+    fn test_parse_procargs2() {
+        let mut buffer = 2i32.to_ne_bytes().to_vec();
+        buffer.extend_from_slice(b"/usr/local/bin/frida\0\0\0\0frida\0-U\0HOME=/Users/a\0");
+        assert_eq!(parse_procargs2(&buffer).unwrap(), ["frida", "-U"]);
+        assert_eq!(parse_procargs2(&0i32.to_ne_bytes()), None);
+        assert!(parse_procargs2(&[0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_toolhelp_entry_conversion() {
+        let mut exe_file: Vec<u16> = "Microsoft Teams.exe".encode_utf16().collect();
+        exe_file.resize(260, 0);
+        let entry = ToolhelpEntry {
+            pid: 4242,
+            parent_pid: 1000,
+            exe_file,
+            session: Some(1),
+            user: Some("alice".to_string()),
+        };
+        assert_eq!(entry.parent_pid, 1000);
+        let process = entry.into_process("\"C:\\Program Files\\Teams\\Microsoft Teams.exe\" --system".to_string());
+        assert_eq!(process.pid, 4242);
+        assert_eq!(process.command, "Microsoft Teams.exe");
+        assert_eq!(process.session, Some(1));
+        assert_eq!(process.user.as_deref(), Some("alice"));
+        assert!(process.cmdline.ends_with("--system"));
+
+        // No terminating NUL in a full buffer, and a lone surrogate.
+        let entry = ToolhelpEntry {
+            pid: 4,
+            parent_pid: 0,
+            exe_file: vec![b'X' as u16, 0xD800, b'.' as u16],
+            session: None,
+            user: None,
+        };
+        assert_eq!(entry.image_name(), "X\u{FFFD}.");
+    }
+
+    /// A proc connector datagram: nlmsghdr, cn_msg, then proc_event.
+    fn proc_event(what: u32, data: &[u32]) -> Vec<u8> {
+        let mut event = Vec::new();
+        event.extend(what.to_ne_bytes());
+        event.extend([0u8; 12]);
+        for word in data {
+            event.extend(word.to_ne_bytes());
+        }
+        let len = (16 + 20 + event.len()) as u32;
+        let mut message = Vec::new();
+        message.extend(len.to_ne_bytes());
+        message.extend([0u8; 12 + 20]);
+        message.extend(event);
+        message
+    }
+
+    #[test]
+    fn test_parse_proc_events() {
+        let mut datagram = proc_event(0x2, &[4242, 4242]);
+        // A new thread, then a new process.
+        datagram.extend(proc_event(0x1, &[100, 100, 4243, 4242]));
+        datagram.extend(proc_event(0x1, &[100, 100, 4244, 4244]));
+        // Exit events carry no new process.
+        datagram.extend(proc_event(0x80000000, &[4242, 4242, 0, 0]));
+        assert_eq!(parse_proc_events(&datagram), [4242, 4244]);
+        // A message cut short ends parsing without a panic.
+        assert_eq!(parse_proc_events(&datagram[..30]), Vec::<i32>::new());
+    }
+
+    /// Needs root for the proc connector, so it skips elsewhere.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_exec_event_detected_fast() {
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } != 0 || proc_events().is_err() {
+            eprintln!("proc connector unavailable; skipping");
+            return;
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        let patterns = CommandPatterns::compile(&["^hfsexecme$".to_string()], PatternOptions::default()).unwrap();
+        let report = move |violation: Violation| {
+            let _ = tx.send((std::time::Instant::now(), violation));
+        };
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(60), report);
+        hunter.chain_depth = 0;
+        hunter.exclude_own_tree = false;
+        let events = tokio::spawn(async move { hunter.start_events().await });
+        // Let the first full scan and the subscription settle.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("hfsexecme");
+        std::fs::copy("/bin/sleep", &binary).unwrap();
+        let started = std::time::Instant::now();
+        let mut child = std::process::Command::new(&binary).arg("30").spawn().unwrap();
+        let (seen, violation) = tokio::task::spawn_blocking(move || rx.recv_timeout(Duration::from_secs(3)))
+            .await
+            .unwrap()
+            .expect("exec was not reported");
+        child.kill().unwrap();
+        child.wait().unwrap();
+        events.abort();
+
+        assert_eq!(violation.pid, Some(child.id() as i32));
+        let latency = seen - started;
+        assert!(latency < Duration::from_secs(1), "took {:?}", latency);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_rules_need_a_root_owned_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("forbidden.txt");
+        fs::write(&file, "gdb=kill\nghidra=command:/usr/local/bin/alert.sh\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o664)).unwrap();
+        let err = read_patterns_file(&file).unwrap_err();
+        assert!(err.contains("must be owned by root and not writable by group or others"), "{}", err);
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } == 0 {
+            assert_eq!(read_patterns_file(&file).unwrap().len(), 2);
+            std::os::unix::fs::chown(&file, Some(1000), None).unwrap();
+            assert!(read_patterns_file(&file).is_err());
+        }
+
+        // Without command: rules anyone's file will do.
+        fs::write(&file, "gdb=kill\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(read_patterns_file(&file).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reaction_commands_are_bounded() {
+        let (hunter, _) = hunter("unused", HfsAction::LogOnly);
+        let violation = Violation::new(ViolationKind::ForbiddenProcess, &process(7, "gdb", 0, "root"), None, 200);
+        let outcomes: Vec<String> = (0..MAX_RUNNING_COMMANDS + 1).map(|_| hunter.run_command("sleep 5", &violation)).collect();
+        assert!(outcomes[..MAX_RUNNING_COMMANDS].iter().all(|outcome| outcome == "ran sleep 5"), "{:?}", outcomes);
+        assert_eq!(outcomes[MAX_RUNNING_COMMANDS], format!("not run: {} reaction commands still running", MAX_RUNNING_COMMANDS));
+
+        hunter.running_commands.fetch_sub(1, Ordering::SeqCst);
+        assert_eq!(hunter.run_command("true", &violation), "ran true");
+    }
+
+    #[test]
+    fn test_patterns_file_hot_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("forbidden.txt");
+        fs::write(&file, "# debuggers\n^gdb$\n\n  # tracers\n^strace$\n").unwrap();
+        assert_eq!(read_patterns_file(&file).unwrap(), [PatternRule::new("^gdb$"), PatternRule::new("^strace$")]);
+
+        let (mut hunter, _) = hunter("unused", HfsAction::LogOnly);
+        hunter.watch_patterns_file(&file, &[PatternRule::new("frida")], PatternOptions::default()).unwrap();
+        assert_eq!(hunter.forbidden_patterns.sources(), ["^gdb$", "^strace$", "frida"]);
+
+        // Editors may save in several steps; wait for the edit to land whole.
+        let wait_for_reload = |hunter: &mut HfsHunter<_>, settled: &dyn Fn(&Result<_, String>) -> bool| {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while std::time::Instant::now() < deadline {
+                match hunter.reload_patterns_if_changed() {
+                    Some(result) if settled(&result) => return result,
+                    _ => std::thread::sleep(std::time::Duration::from_millis(20)),
+                }
+            }
+            panic!("no reload");
+        };
+        fs::write(&file, "^gdb$\n^ltrace$\n").unwrap();
+        let (added, removed) = wait_for_reload(&mut hunter, &|result| result.is_ok()).unwrap();
+        assert_eq!((added, removed), (vec!["^ltrace$".to_string()], vec!["^strace$".to_string()]));
+        assert_eq!(hunter.forbidden_patterns.sources(), ["^gdb$", "^ltrace$", "frida"]);
+        assert_eq!(hunter.check_processes(&[process(20, "ltrace", 0, "root")]).len(), 1);
+
+        // A bad edit is rejected and the previous set keeps matching.
+        fs::write(&file, "^gdb$\n^(ltrace\n").unwrap();
+        let error = wait_for_reload(&mut hunter, &|result| result.is_err()).unwrap_err();
+        assert!(error.contains("Invalid HFS pattern \"^(ltrace\""), "{}", error);
+        assert_eq!(hunter.forbidden_patterns.sources(), ["^gdb$", "^ltrace$", "frida"]);
+        assert_eq!(hunter.check_processes(&[process(21, "ltrace", 0, "root")]).len(), 1);
+
+        let (added, removed) = hunter.replace_patterns(&[PatternRule::new("^gdb$")], PatternOptions::default()).unwrap();
+        assert!(added.is_empty());
+        assert_eq!(removed, ["^ltrace$", "frida"]);
+    }
+
+    #[test]
+    fn test_escalation_window() {
+        let config: HfsConfig = toml::from_str(
+            "[escalation]\nlog_first = 2\nwindow_secs = 600\n\n\
+             [escalation.patterns.\"(?i)frida\"]\nlog_first = 0\n",
+        )
+        .unwrap();
+        let mut escalation = Escalation::new(config.escalation.unwrap());
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        let states: Vec<EscalationState> = [(0, 10), (60, 11), (120, 10), (180, 12)]
+            .iter()
+            .map(|&(secs, pid)| escalation.record("^gdb$", pid, None, None, at(secs)).unwrap())
+            .collect();
+        let counts: Vec<(usize, usize, bool)> = states.iter().map(|s| (s.count, s.pid_count, s.escalated)).collect();
+        assert_eq!(counts, [(1, 1, false), (2, 1, false), (3, 2, true), (4, 1, true)]);
+
+        // Ten minutes after the first two matches, only the last two count.
+        let state = escalation.record("^gdb$", 13, None, None, at(660)).unwrap();
+        assert_eq!((state.count, state.escalated), (3, true));
+        let state = escalation.record("^gdb$", 13, None, None, at(1500)).unwrap();
+        assert_eq!((state.count, state.escalated), (1, false));
+
+        // A per-pattern rule overrides the global one.
+        let state = escalation.record("(?i)frida", 20, None, None, at(0)).unwrap();
+        assert_eq!((state.count, state.log_first, state.window_secs, state.escalated), (1, 0, 600, true));
+
+        // Without a global rule, unlisted patterns are acted on at once.
+        let mut escalation = Escalation::new(HfsEscalation::default());
+        assert_eq!(escalation.record("^gdb$", 10, None, None, at(0)), None);
+
+        // A developer's own gdb is only logged; root's is acted on at once,
+        // and the developer's matches do not count towards it.
+        let config: HfsConfig = toml::from_str(
+            "[escalation.users.alice]\nlog_first = 100\n\n[escalation.users.0]\nlog_first = 0\n",
+        )
+        .unwrap();
+        let mut escalation = Escalation::new(config.escalation.unwrap());
+        let state = escalation.record("^gdb$", 30, Some("alice"), Some(1000), at(0)).unwrap();
+        assert_eq!((state.count, state.user.as_deref(), state.escalated), (1, Some("alice"), false));
+        let state = escalation.record("^gdb$", 31, Some("root"), Some(0), at(1)).unwrap();
+        assert_eq!((state.count, state.user.as_deref(), state.escalated), (1, Some("0"), true));
+        assert_eq!(escalation.record("^gdb$", 32, Some("bob"), Some(1001), at(2)), None);
+    }
+
+    #[test]
+    fn test_escalation_holds_action_back() {
+        let (mut hunter, messages) = hunter("^gdb$", HfsAction::Kill);
+        hunter.set_escalation(HfsEscalation {
+            log_first: Some(2),
+            window_secs: 600,
+            patterns: HashMap::new(),
+            users: HashMap::new(),
+        });
+        // PIDs that cannot exist, so the kill finds nothing to kill.
+        for pid in [i32::MAX - 2, i32::MAX - 1, i32::MAX] {
+            hunter.check_processes(&[process(pid, "gdb", 0, "root")]);
+        }
+        let messages = messages.lock().unwrap();
+        assert!(messages[0].ends_with(", COUNT=1 in 600s (logged)"), "{}", messages[0]);
+        assert!(messages[1].ends_with(", COUNT=2 in 600s (logged)"), "{}", messages[1]);
+        assert!(messages[2].ends_with(", COUNT=3 in 600s (escalated) (process already gone)"), "{}", messages[2]);
+    }
+
+    #[tokio::test]
+    async fn test_preloaded_child_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("hfsenvchild");
+        std::fs::copy("/bin/sleep", &binary).unwrap();
+        let preload = dir.path().join("libfrida-gadget.so");
+        let mut child = std::process::Command::new(&binary)
+            .arg("30")
+            .env("LD_PRELOAD", &preload)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        let violations = Arc::new(Mutex::new(Vec::new()));
+        let log = violations.clone();
+        let patterns = CommandPatterns::compile(&["frida-gadget".to_string()], PatternOptions::default()).unwrap();
+        let report = move |violation: Violation| log.lock().unwrap().push(violation);
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(1), report);
+        hunter.exclude_own_tree = false;
+        hunter.scan_env = true;
+        hunter.scan_once().await.unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let violations = violations.lock().unwrap();
+        let ours: Vec<&Violation> = violations.iter().filter(|v| v.pid == Some(child.id() as i32)).collect();
+        assert_eq!(ours.len(), 1, "{:?}", violations);
+        assert_eq!(ours[0].kind, ViolationKind::InjectedEnvironment);
+        assert_eq!(ours[0].variable.as_deref(), Some("LD_PRELOAD"));
+        assert_eq!(ours[0].value.as_deref(), preload.to_str());
+        assert_eq!(ours[0].pattern.as_deref(), Some("frida-gadget"));
+        assert!(ours[0].to_string().starts_with("[HFS] Injection variable set: LD_PRELOAD="));
+    }
+
+    #[test]
+    fn test_strict_env_and_denied_reads() {
+        let (mut hunter, messages) = hunter("frida", HfsAction::LogOnly);
+        let audited = vec![("LD_AUDIT".to_string(), "/opt/tools/audit.so".to_string())];
+        let environments = [
+            (process(30, "app", 1000, "alice"), Some(audited)),
+            (process(31, "root-daemon", 0, "root"), None),
+        ];
+        assert!(hunter.check_env(&environments).is_empty());
+        assert_eq!(hunter.stats.env_denied, 1);
+
+        hunter.strict_env = true;
+        assert_eq!(hunter.check_env(&environments).len(), 1);
+        assert_eq!(hunter.check_env(&environments).len(), 0);
+        assert_eq!(
+            messages.lock().unwrap().as_slice(),
+            ["[HFS] Injection variable set: LD_AUDIT=/opt/tools/audit.so, PID=30, USER=alice(1000), CMD=app"]
+        );
+    }
+
+    #[test]
+    fn test_read_listeners_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let tcp = dir.path().join("tcp");
+        fs::write(
+            &tcp,
+            concat!(
+                "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n",
+                "   0: 00000000:69A2 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 41235 1 0\n",
+                "   1: 0100007F:04D2 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 51234 1 0\n",
+                "   2: 0100007F:04D2 0100007F:A1B2 01 00000000:00000000 00:00000000 00000000  1000        0 51299 1 0\n",
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_listeners(&tcp).unwrap(), [(27042, 41235), (1234, 51234)]);
+        let tcp6 = dir.path().join("tcp6");
+        fs::write(
+            &tcp6,
+            concat!(
+                "  sl  local_address                         remote_address                        st tx_queue rx_queue ",
+                "tr tm->when retrnsmt   uid  timeout inode\n",
+                "   0: 00000000000000000000000001000000:5D8A 00000000000000000000000000000000:0000 0A 00000000:00000000 ",
+                "00:00000000 00000000     0        0 61234 1 0\n",
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_listeners(&tcp6).unwrap(), [(23946, 61234)]);
+    }
+
+    #[test]
+    fn test_port_listeners_reported_once() {
+        let (mut hunter, messages) = hunter("unused", HfsAction::LogOnly);
+        hunter.scan_ports = DEFAULT_DEBUG_PORTS.to_vec();
+        let mut updater = process(40, "updater", 1000, "alice");
+        updater.cmdline = "./updater :1234 /opt/app/bin".to_string();
+        let processes = [updater, process(41, "frida-helper", 0, "root")];
+        let listeners = [(1234, Some(40)), (27042, None)];
+        assert_eq!(hunter.check_ports(&processes, &listeners).len(), 2);
+        assert!(hunter.check_ports(&processes, &listeners).is_empty());
+        assert_eq!(
+            messages.lock().unwrap().as_slice(),
+            [
+                "[HFS] Debugger port listening: PORT=1234, PID=40, USER=alice(1000), CMD=./updater :1234 /opt/app/bin",
+                "[HFS] Debugger port listening: PORT=27042, owner unknown",
+            ]
+        );
+
+        // Gone and back: reported again.
+        assert!(hunter.check_ports(&processes, &[]).is_empty());
+        assert_eq!(hunter.check_ports(&processes, &listeners[..1]).len(), 1);
+
+        let alice = HfsAllowlist {
+            users: vec!["alice".to_string()],
+            ..Default::default()
+        };
+        hunter.set_allowlist(&alice, PatternOptions::default()).unwrap();
+        assert!(hunter.check_ports(&processes, &[]).is_empty());
+        assert!(hunter.check_ports(&processes, &listeners[..1]).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_own_listener_is_attributed_to_us() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let pid = std::process::id() as i32;
+        assert_eq!(debug_listeners(&[port]).unwrap(), [(port, Some(pid))]);
+        assert!(debug_listeners(&[]).unwrap().is_empty());
+
+        let (mut hunter, _) = hunter("unused", HfsAction::LogOnly);
+        let own: Vec<ProcessInfo> = read_proc(Path::new("/proc")).unwrap().into_iter().filter(|p| p.pid == pid).collect();
+        let found = hunter.check_ports(&own, &debug_listeners(&[port]).unwrap());
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].kind, found[0].pid, found[0].port), (ViolationKind::DebugListener, Some(pid), Some(port)));
+        assert_eq!(found[0].command, own[0].command);
+        let json = serde_json::to_value(&found[0]).unwrap();
+        assert_eq!((&json["kind"], &json["port"]), (&"port".into(), &port.into()));
+
+        hunter.exclude_own_tree = true;
+        hunter.reported_ports.clear();
+        assert!(hunter.check_ports(&own, &debug_listeners(&[port]).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_fd_mode_from_fdinfo_flags() {
+        assert_eq!(FdMode::from_flags("\t0100000"), Some(FdMode::Read));
+        assert_eq!(FdMode::from_flags("\t02102001"), Some(FdMode::Write));
+        assert_eq!(FdMode::from_flags("02"), Some(FdMode::ReadWrite));
+        assert_eq!(FdMode::from_flags("0100003"), None);
+        assert_eq!(FdMode::from_flags("rw"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_child_holding_protected_files_is_flagged_with_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, pself) = (dir.path().join("app.conf"), dir.path().join("app.pself"));
+        fs::write(&config, "x").unwrap();
+        fs::write(&pself, "x").unwrap();
+        let reader = fs::File::open(&config).unwrap();
+        let writer = fs::OpenOptions::new().read(true).write(true).open(&pself).unwrap();
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .stdin(reader)
+            .stdout(writer)
+            .spawn()
+            .unwrap();
+
+        let (mut hunter, messages) = hunter("unused", HfsAction::LogOnly);
+        hunter.protect_files(&[config.clone(), pself.clone(), dir.path().join("missing")]);
+        let pid = child.id() as i32;
+        let targets: Vec<ProcessInfo> = read_proc(Path::new("/proc")).unwrap().into_iter().filter(|p| p.pid == pid).collect();
+        let protected = hunter.protected_files.clone();
+        let readings = read_each(targets, |pid| read_open_files(&Path::new("/proc").join(pid.to_string()), &protected));
+        let mut found = hunter.check_fds(&readings);
+        found.sort_by_key(|violation| violation.fd);
+        let summary: Vec<_> = found.iter().map(|v| (v.pid, v.fd, v.fd_mode, v.file.clone())).collect();
+        let canonical = |path: &Path| Some(fs::canonicalize(path).unwrap().to_string_lossy().into_owned());
+        assert_eq!(
+            summary,
+            [
+                (Some(pid), Some(0), Some(FdMode::Read), canonical(&config)),
+                (Some(pid), Some(1), Some(FdMode::ReadWrite), canonical(&pself)),
+            ]
+        );
+        assert!(hunter.check_fds(&readings).is_empty());
+        let message = messages.lock().unwrap()[1].clone();
+        assert!(message.starts_with("[HFS] Protected file open: FILE="), "{}", message);
+        assert!(message.contains("app.pself (read-write, fd 1), PID="), "{}", message);
+        assert_eq!(serde_json::to_value(&found[0]).unwrap()["fd_mode"], "read");
+
+        // Only every `fds_every` scans, unless asked for.
+        hunter.fds_every = 1000;
+        hunter.reported_fds.clear();
+        hunter.stats.scans = 1;
+        let ours = |found: &[Violation]| found.iter().filter(|v| v.kind == ViolationKind::OpenProtectedFile).count();
+        assert_eq!(ours(&hunter.scan_once().await.unwrap()), 0);
+        hunter.fd_scan_requested.store(true, Ordering::Relaxed);
+        assert_eq!(ours(&hunter.scan_once().await.unwrap()), 2);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_once_exit_codes() {
+        let (mut hunter, _) = hunter("^gdb$", HfsAction::LogOnly);
+        let clean = hunter.check_processes(&[process(10, "bash", 1000, "alice")]);
+        assert_eq!(once_exit_code(&Ok(clean)), 0);
+        let found = hunter.check_processes(&[process(10, "bash", 1000, "alice"), process(11, "gdb", 1000, "alice")]);
+        assert_eq!(once_exit_code(&Ok(found)), 2);
+        let error = HfsError::Enumeration("cannot run ps: not found".to_string());
+        assert_eq!(error.to_string(), "cannot list processes: cannot run ps: not found");
+        assert_eq!(once_exit_code(&Err(error)), 1);
+    }
+
+    #[test]
+    fn test_enumeration_failure_is_reported_not_silent() {
+        let (mut hunter, messages) = hunter("unused", HfsAction::LogOnly);
+        assert_eq!(hunter.report_monitor_error("cannot run ps: not found".to_string()), 1);
+        assert_eq!(hunter.report_monitor_error("cannot run ps: not found".to_string()), 0);
+        assert_eq!(hunter.stats.enumeration_errors, 2);
+        assert_eq!(
+            messages.lock().unwrap().as_slice(),
+            ["[HFS] Cannot list processes, nothing was checked: cannot run ps: not found"]
+        );
+    }
+
+    #[test]
+    fn test_violation_json_schema() {
+        let violations = Arc::new(Mutex::new(Vec::new()));
+        let log = violations.clone();
+        let patterns = ["^gdb$".to_string(), "frida".to_string()];
+        let patterns = CommandPatterns::compile(&patterns, PatternOptions::default()).unwrap();
+        let report = move |violation: Violation| log.lock().unwrap().push(violation);
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(1), report);
+        hunter.chain_depth = 0;
+        hunter.exclude_own_tree = false;
+        let mut frida = process(4242, "python3", 1000, "alice");
+        frida.cmdline = "python3 -m frida_tools.repl".to_string();
+        assert_eq!(hunter.check_processes(&[process(7, "gdb", 0, "root"), frida]).len(), 2);
+
+        let violations = violations.lock().unwrap();
+        let json = serde_json::to_value(&violations[1]).unwrap();
+        let mut keys: Vec<&str> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "action",
+                "cmdline",
+                "command",
+                "error",
+                "escalation",
+                "fd",
+                "fd_mode",
+                "file",
+                "kind",
+                "library",
+                "outcome",
+                "parents",
+                "path",
+                "pattern",
+                "pid",
+                "port",
+                "reminder_secs",
+                "severity",
+                "timestamp",
+                "uid",
+                "user",
+                "value",
+                "variable"
+            ]
+        );
+        assert_eq!(json["kind"], "process");
+        assert_eq!(json["pid"], 4242);
+        assert_eq!(json["command"], "python3");
+        assert_eq!(json["cmdline"], "python3 -m frida_tools.repl");
+        assert_eq!(json["pattern"], "frida");
+        assert_eq!(json["action"], "log");
+        assert_eq!(json["severity"], "warning");
+        assert_eq!(json["uid"], 1000);
+        assert_eq!(json["user"], "alice");
+        assert!(json["library"].is_null() && json["outcome"].is_null());
+        assert!(json["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(serde_json::to_value(&violations[0]).unwrap()["pattern"], "^gdb$");
+        assert_eq!(
+            violations[1].to_string(),
+            concat!(
+                "[HFS] Unauthorized process detected: PID=4242, USER=alice(1000), ",
+                "CMD=python3 -m frida_tools.repl, RULE=frida, ACTION=log"
+            )
+        );
+    }
+
+    /// Run by `test_ptrace_attach_is_detected` in a child process: waits to
+    /// be traced and fails if it never is.
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn traced_child() {
+        // The tracer is the parent's test thread, not the parent process.
+        let Some(tracer_tid) = std::env::var("HFS_TRACED_CHILD").ok().and_then(|tid| tid.parse().ok()) else {
+            return;
+        };
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while std::time::Instant::now() < deadline {
+            if let Some(tracer) = is_being_traced() {
+                assert_eq!(tracer.pid, Some(tracer_tid));
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        panic!("never saw a tracer");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ptrace_attach_is_detected() {
+        assert_eq!(is_being_traced(), None);
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "hfs::tests::traced_child", "--ignored", "--test-threads=1"])
+            // SAFETY: gettid has no preconditions.
+            .env("HFS_TRACED_CHILD", unsafe { libc::gettid() }.to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: PTRACE_SEIZE attaches without stopping the child; it is ours.
+        let rc = unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid, 0, 0) };
+        assert_eq!(rc, 0, "ptrace: {}", std::io::Error::last_os_error());
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_maps_fixture_reports_injected_library_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let maps = dir.path().join("maps");
+        fs::write(
+            &maps,
+            "55d0c0a00000-55d0c0a02000 r--p 00000000 fd:01 1310 /usr/bin/victim\n\
+             7f1c2a000000-7f1c2a200000 r-xp 00000000 fd:01 2210 /tmp/re.frida.server/frida-agent-64.so\n\
+             7f1c2a200000-7f1c2a400000 r--p 00200000 fd:01 2210 /tmp/re.frida.server/frida-agent-64.so\n\
+             7f1c2b000000-7f1c2b100000 r-xp 00000000 fd:01 3301 /usr/lib/x86_64-linux-gnu/libc.so.6\n\
+             7f1c2c000000-7f1c2c021000 rw-p 00000000 00:00 0    [heap]\n\
+             7f1c2d000000-7f1c2d001000 r-xp 00000000 fd:01 4401 /opt/my libs/gadget.so (deleted)\n\
+             7f1c2e000000-7f1c2e001000 rw-p 00000000 00:00 0\n",
+        )
+        .unwrap();
+        let libraries = read_maps(&maps).unwrap();
+        assert_eq!(
+            libraries,
+            [
+                "/usr/bin/victim",
+                "/tmp/re.frida.server/frida-agent-64.so",
+                "/usr/lib/x86_64-linux-gnu/libc.so.6",
+                "/opt/my libs/gadget.so",
+            ]
+        );
+
+        let (mut hunter, messages) = hunter("frida-(agent|gadget)", HfsAction::LogOnly);
+        let victim = process(4242, "victim", 1000, "alice");
+        let scan = [(victim.clone(), Some(libraries)), (process(1, "init", 0, "root"), None)];
+        assert_eq!(hunter.check_maps(&scan).len(), 1);
+        assert_eq!(hunter.check_maps(&scan).len(), 0);
+        assert_eq!(hunter.stats.maps_denied, 2);
+        assert_eq!(
+            messages.lock().unwrap()[0],
+            concat!(
+                "[HFS] Forbidden library mapped: LIB=/tmp/re.frida.server/frida-agent-64.so, ",
+                "PID=4242, USER=alice(1000), CMD=victim, RULE=frida-(agent|gadget), ACTION=log"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_violation_reports_parent_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("hfschained");
+        std::fs::copy("/bin/sleep", &binary).unwrap();
+        // The trailing `true`s keep each shell from exec'ing its last command.
+        let script = format!("sh -c '{} 30; true'; true", binary.display());
+        let mut shell = std::process::Command::new("sh").args(["-c", &script]).spawn().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(300));
+
+        let violations = Arc::new(Mutex::new(Vec::new()));
+        let log = violations.clone();
+        let patterns = CommandPatterns::compile(&["^hfschained$".to_string()], PatternOptions::default()).unwrap();
+        let report = move |violation: Violation| log.lock().unwrap().push(violation);
+        let mut hunter = HfsHunter::with_compiled(patterns, Duration::from_secs(1), report);
+        hunter.action = HfsAction::Kill;
+        hunter.exclude_own_tree = false;
+        assert_eq!(hunter.scan_once().await.unwrap().len(), 1);
+        assert!(shell.wait().unwrap().success());
+
+        let violation = violations.lock().unwrap()[0].clone();
+        let commands: Vec<&str> = violation.parents.iter().map(|a| a.command.as_str()).collect();
+        assert!(commands.len() >= 3, "{:?}", violation.parents);
+        assert_eq!(commands[..2], ["sh", "sh"]);
+        assert_eq!(violation.parents[1].pid, shell.id() as i32);
+        assert!(violation.parents.len() <= DEFAULT_CHAIN_DEPTH);
+        assert!(violation.parents.iter().all(|a| a.user.is_some()), "{:?}", violation.parents);
+        assert!(violation.to_string().contains(", PARENTS=sh("), "{}", violation);
+
+        assert_eq!(parent_chain(std::process::id() as i32, 1).len(), 1);
+        assert!(parent_chain(i32::MAX, DEFAULT_CHAIN_DEPTH).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_own_tree_is_never_matched() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut child = spawn_sleeper(dir.path(), "hfsownchild");
+        assert!(in_own_tree(std::process::id() as i32));
+        assert!(in_own_tree(child.id() as i32));
+        assert!(!in_own_tree(1));
+
+        let (mut hunter, messages) = hunter("hfsownchild", HfsAction::Kill);
+        hunter.exclude_own_tree = true;
+        assert!(hunter.scan_once().await.unwrap().is_empty());
+        assert!(child.try_wait().unwrap().is_none());
+        assert!(messages.lock().unwrap().is_empty());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_never_acts_on_own_lineage() {
+        let (hunter, _) = hunter("unused", HfsAction::Kill);
+        assert_eq!(hunter.act(std::process::id() as i32, HfsAction::Kill), ActionOutcome::Protected);
+        #[cfg(target_os = "linux")]
+        let parent = parent_pid(std::process::id() as i32).unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(hunter.act(parent, HfsAction::Kill), ActionOutcome::Protected);
+        assert_eq!(send_action(i32::MAX, HfsAction::Kill), ActionOutcome::Gone);
+    }
 }
@@ -0,0 +1,233 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where `serialkiller hfs --daemon` logs unless `--log-file` says otherwise.
+pub const DEFAULT_LOG_FILE: &str = "/var/log/serialkiller-hfs.log";
+
+/// Size at which the log is rotated unless `--log-max-size` says otherwise.
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept next to the live log unless `--log-keep` says otherwise.
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+/// Lines waiting for the writer thread before new ones are dropped.
+pub const LOG_QUEUE: usize = 1024;
+
+/// A log file that is moved to `PATH.1` (and `PATH.1` to `PATH.2`, up to
+/// `keep` files) before a line would take it past `max_size`.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = open_private(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let line = format!("{}\n", line);
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = open_private(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Opens `path` for appending, creating it readable by its owner only: the
+/// log names users, commands and what was done to them.
+fn open_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Timestamps lines and hands them to a writer thread, so a slow disk never
+/// holds up a scan. While the queue is full lines are dropped and counted,
+/// and a "lost N log lines" marker is written once there is room again.
+#[derive(Clone)]
+pub struct HfsLog {
+    sender: SyncSender<String>,
+    lost: Arc<AtomicU64>,
+}
+
+impl HfsLog {
+    /// Starts the writer thread. It ends, after writing what is queued, once
+    /// every clone of the returned log is dropped.
+    pub fn spawn<W>(mut write: W, queue: usize) -> (Self, JoinHandle<()>)
+    where
+        W: FnMut(&str) + Send + 'static,
+    {
+        let (sender, receiver) = sync_channel::<String>(queue);
+        let writer = std::thread::spawn(move || {
+            for line in receiver {
+                write(&line);
+            }
+        });
+        let log = Self {
+            sender,
+            lost: Arc::new(AtomicU64::new(0)),
+        };
+        (log, writer)
+    }
+
+    /// Writes to `file` on the writer thread. A line that cannot be written
+    /// is gone: in daemon mode there is nowhere left to report it.
+    pub fn to_file(mut file: RotatingFile, queue: usize) -> (Self, JoinHandle<()>) {
+        Self::spawn(move |line| drop(file.write_line(line)), queue)
+    }
+
+    pub fn line(&self, message: &str) {
+        let lost = self.lost.swap(0, Ordering::Relaxed);
+        // Behind a marker that did not fit, this line would read as kept.
+        if lost > 0 && !self.send(&format!("[HFS] Log queue full, lost {} log lines", lost)) {
+            self.lost.fetch_add(lost + 1, Ordering::Relaxed);
+            return;
+        }
+        if !self.send(message) {
+            self.lost.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn send(&self, message: &str) -> bool {
+        let line = format!("{} {}", timestamp(SystemTime::now()), message);
+        self.sender.try_send(line).is_ok()
+    }
+}
+
+/// `time` in UTC as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, rest) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since the epoch, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rotation_keeps_n_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hfs.log");
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        for n in 0..20 {
+            log.write_line(&format!("line {:02} ..........", n)).unwrap();
+        }
+        drop(log);
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        assert!(rotated(1).exists() && rotated(2).exists());
+        assert!(!rotated(3).exists());
+        for file in [rotated(2), rotated(1), path.clone()] {
+            assert!(fs::metadata(&file).unwrap().len() <= 100, "{}", file.display());
+            #[cfg(unix)]
+            assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&file).unwrap().permissions()) & 0o777, 0o600);
+        }
+        assert!(fs::read_to_string(&path).unwrap().ends_with("line 19 ..........\n"));
+        assert!(fs::read_to_string(rotated(1)).unwrap().ends_with("line 14 ..........\n"));
+
+        // Reopening continues the live file and rotates on its size.
+        let mut log = RotatingFile::open(&path, 100, 2).unwrap();
+        log.write_line("line 20 ..........").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 20 ..........\n");
+        assert!(fs::read_to_string(rotated(1)).unwrap().ends_with("line 19 ..........\n"));
+    }
+
+    #[test]
+    fn test_full_queue_drops_lines_and_says_so() {
+        let (lines, written) = channel();
+        let (unblock, blocked) = channel::<()>();
+        let (log, writer) = HfsLog::spawn(
+            move |line| {
+                let _ = blocked.recv();
+                lines.send(line.to_string()).unwrap();
+            },
+            2,
+        );
+        // The writer takes one line and blocks on it; two more fill the queue.
+        for n in 0..8 {
+            log.line(&format!("message {}", n));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        for _ in 0..3 {
+            unblock.send(()).unwrap();
+        }
+        let first: Vec<String> = (0..3).map(|_| written.recv().unwrap()).collect();
+        assert!(first[0].ends_with("Z message 0") && first[2].ends_with(" message 2"), "{:?}", first);
+        log.line("message 8");
+        drop(unblock);
+        drop(log);
+        writer.join().unwrap();
+
+        let rest: Vec<String> = written.try_iter().collect();
+        let messages: Vec<&str> = rest.iter().map(|line| line.split_once(' ').unwrap().1).collect();
+        assert_eq!(messages, ["[HFS] Log queue full, lost 5 log lines", "message 8"]);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let at = |secs: u64| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_792_238_645), "2026-10-17T12:04:05Z");
+    }
+}
@@ -1,87 +1,4069 @@
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hmac::{Hmac, Mac};
+use object::{Object, ObjectSection, SectionKind};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::hash_pool::{self, Throttle, Throughput};
+use crate::hfs_log::timestamp;
+use crate::serialk_baseline;
+use crate::kdv_merkle::{self, MerkleProof};
+use crate::kdv_sums::{self, SumsFormat};
+use crate::permission_capability::Capability;
+use crate::runner::PselfRunner;
+use crate::serialk_watcher::matches_any;
+use crate::serialk_webhook;
+
+/// What a `permission-manager` session needs for `kdv init` and
+/// `kdv update`, which write the baseline.
+pub const UPDATE_CAPABILITY: Capability = Capability::UpdateBaseline;
+
+/// Version of the `kdv init` manifest format, bumped when old readers
+/// would misread a new manifest.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Size of the reads files are hashed in, so memory stays the same for a
+/// file of any size.
+const HASH_CHUNK: usize = 1 << 20;
+
+/// The baseline `build.rs` compiled in from `SERIALK_EMBED_BASELINE` or
+/// `SERIALK_EMBED_DIR`, for `kdv check --embedded`; empty when the build
+/// set neither.
+pub static EMBEDDED_BASELINE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/embedded_baseline"));
+
+/// How often the progress line on stderr is redrawn.
+const PROGRESS_EVERY: Duration = Duration::from_millis(500);
+
+/// The digest a manifest entry was made with. Unknown names in a manifest
+/// are an error, never read as the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+    /// Several times faster than SHA-256 on large files.
+    Blake3,
+    /// Keyed with `--key-file`, so whoever can rewrite the baseline still
+    /// cannot forge an entry for a modified file.
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+}
+
+impl HashAlgo {
+    /// The unkeyed algorithms, which `--algo` chooses from.
+    pub const ALL: [HashAlgo; 3] = [HashAlgo::Sha256, HashAlgo::Sha512, HashAlgo::Blake3];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Sha512 => "sha512",
+            HashAlgo::Blake3 => "blake3",
+            HashAlgo::HmacSha256 => "hmac-sha256",
+        }
+    }
+
+    pub fn is_keyed(self) -> bool {
+        self == HashAlgo::HmacSha256
+    }
+
+    /// The digest of `data`, for content already in memory. Unkeyed
+    /// algorithms only.
+    pub fn hash(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(self, None).expect("hash() is for the unkeyed algorithms");
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Hashes everything `reader` yields, `HASH_CHUNK` bytes at a time. `key`
+    /// is used by the keyed algorithms only, which fail without one.
+    pub fn hash_reader(self, reader: &mut impl Read, key: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        Ok(Hasher::new(self, key)?.feed(reader)?.finalize())
+    }
+
+    pub fn hash_file(self, path: &Path, key: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        self.hash_reader(&mut fs::File::open(path)?, key)
+    }
+
+    /// Whether `reader` still hashes to `expected`. Keyed digests are
+    /// compared in constant time.
+    pub fn verify_reader(self, reader: &mut impl Read, key: Option<&[u8]>, expected: &[u8]) -> io::Result<bool> {
+        Ok(Hasher::new(self, key)?.feed(reader)?.verify(expected))
+    }
+}
+
+/// A digest in progress.
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    HmacSha256(Hmac<Sha256>),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo, key: Option<&[u8]>) -> io::Result<Self> {
+        Ok(match algo {
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgo::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgo::Blake3 => Hasher::Blake3(Box::default()),
+            HashAlgo::HmacSha256 => {
+                let key =
+                    key.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "hmac-sha256 needs --key-file"))?;
+                Hasher::HmacSha256(Hmac::new_from_slice(key).expect("HMAC accepts any key length"))
+            }
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => Digest::update(hasher, data),
+            Hasher::Sha512(hasher) => Digest::update(hasher, data),
+            Hasher::Blake3(hasher) => drop(hasher.update(data)),
+            Hasher::HmacSha256(mac) => mac.update(data),
+        }
+    }
+
+    /// Reads `reader` to the end into the digest, through one reused buffer.
+    fn feed(mut self, reader: &mut impl Read) -> io::Result<Self> {
+        let mut buffer = vec![0; HASH_CHUNK];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(self),
+                Ok(read) => self.update(&buffer[..read]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::HmacSha256(mac) => mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn verify(self, expected: &[u8]) -> bool {
+        match self {
+            Hasher::HmacSha256(mac) => mac.verify_slice(expected).is_ok(),
+            hasher => hasher.finalize() == expected,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        HashAlgo::ALL
+            .into_iter()
+            .find(|algo| algo.as_str() == name)
+            .ok_or_else(|| format!("unknown hash algorithm '{}' (expected sha256, sha512 or blake3)", name))
+    }
+}
+
+/// The digest of one program section, recorded by `kdv init --by-section`
+/// with the entry's algorithm.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SectionFingerprint {
+    #[serde(rename = "name")]
     pub section_name: String,
+    #[serde(rename = "digest", with = "hex")]
     pub hash: Vec<u8>,
 }
 
+/// Fingerprints the code and data sections of an ELF, PE or Mach-O image,
+/// in file order. None if `data` is none of those.
+pub fn section_fingerprints(data: &[u8], algo: HashAlgo, key: Option<&[u8]>) -> Option<Vec<SectionFingerprint>> {
+    let file = object::File::parse(data).ok()?;
+    let mut sections = Vec::new();
+    for section in file.sections() {
+        let program = matches!(
+            section.kind(),
+            SectionKind::Text
+                | SectionKind::Data
+                | SectionKind::ReadOnlyData
+                | SectionKind::ReadOnlyDataWithRel
+                | SectionKind::ReadOnlyString
+                | SectionKind::Tls
+        );
+        let (true, Ok(name), Ok(mut content)) = (program, section.name(), section.data()) else {
+            continue;
+        };
+        sections.push(SectionFingerprint {
+            section_name: name.to_string(),
+            hash: algo.hash_reader(&mut content, key).ok()?,
+        });
+    }
+    Some(sections)
+}
+
 pub struct KdvVerifier {
     pub fingerprints: HashMap<String, Vec<u8>>,
+    /// What each fingerprint was made with; SHA-256 for those not listed.
+    pub algorithms: HashMap<String, HashAlgo>,
+    /// For the keyed fingerprints.
+    pub key: Option<Vec<u8>>,
+}
+
+/// How a file's current content compares to its approved baseline hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaselineCheck {
+    Matches,
+    Diverges,
+    /// The file has no entry in the baseline.
+    Unknown,
+}
+
+impl BaselineCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BaselineCheck::Matches => "matches baseline",
+            BaselineCheck::Diverges => "diverges from baseline",
+            BaselineCheck::Unknown => "not in baseline",
+        }
+    }
+}
+
+/// What `KdvVerifier::verify_content` found for one named piece of content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "detail", rename_all = "lowercase")]
+pub enum VerifyResult {
+    Verified,
+    Mismatch {
+        #[serde(with = "hex")]
+        expected: Vec<u8>,
+        #[serde(with = "hex")]
+        actual: Vec<u8>,
+    },
+    /// No fingerprint under that name.
+    Unknown,
+    /// Fingerprinted, but the file is gone.
+    Missing,
+    /// Fingerprinted, but the file could not be read.
+    Unreadable(String),
+}
+
+impl VerifyResult {
+    /// The result without the digests, as the watcher grades events.
+    pub fn check(&self) -> BaselineCheck {
+        match self {
+            VerifyResult::Verified => BaselineCheck::Matches,
+            VerifyResult::Mismatch { .. } | VerifyResult::Missing | VerifyResult::Unreadable(_) => BaselineCheck::Diverges,
+            VerifyResult::Unknown => BaselineCheck::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for VerifyResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyResult::Verified => f.write_str("verified"),
+            VerifyResult::Mismatch { expected, actual } => {
+                write!(f, "mismatch: expected {}, got {}", hex::encode(expected), hex::encode(actual))
+            }
+            VerifyResult::Unknown => f.write_str("unknown"),
+            VerifyResult::Missing => f.write_str("missing"),
+            VerifyResult::Unreadable(e) => write!(f, "unreadable: {}", e),
+        }
+    }
+}
+
+/// The results of verifying several named pieces of content.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    #[serde(with = "named_results")]
+    pub results: Vec<(String, VerifyResult)>,
+    /// Wall time the verification took.
+    #[serde(rename = "seconds", with = "seconds")]
+    pub duration: Duration,
+    pub bytes_hashed: u64,
+    /// Entries left unchecked after a fail-fast stop.
+    pub skipped: usize,
+    /// From `VerifyOptions::allow_unknown`.
+    pub unknown_allowed: bool,
+}
+
+/// How `KdvVerifier::verify_all` goes about it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyOptions {
+    /// Stop at the first entry that does not pass. With more than one job
+    /// the entries already being hashed still finish.
+    pub fail_fast: bool,
+    /// Entries hashed at once; `None` for `hash_pool::default_jobs`. With
+    /// one they are checked in order and fail-fast stops right at the first.
+    pub jobs: Option<usize>,
+    /// Let names without a fingerprint pass instead of failing the report.
+    pub allow_unknown: bool,
+}
+
+/// Where `verify_all` gets an entry's content from.
+#[derive(Debug, Clone, Copy)]
+pub enum VerifyInput<'a> {
+    Content(&'a [u8]),
+    /// Hashed in chunks; gone reads as missing.
+    Path(&'a Path),
+}
+
+impl<'a> From<&'a [u8]> for VerifyInput<'a> {
+    fn from(content: &'a [u8]) -> Self {
+        VerifyInput::Content(content)
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for VerifyInput<'a> {
+    fn from(content: &'a Vec<u8>) -> Self {
+        VerifyInput::Content(content)
+    }
+}
+
+impl<'a> From<&'a Path> for VerifyInput<'a> {
+    fn from(path: &'a Path) -> Self {
+        VerifyInput::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for VerifyInput<'a> {
+    fn from(path: &'a PathBuf) -> Self {
+        VerifyInput::Path(path)
+    }
+}
+
+impl VerifyReport {
+    pub fn verified(&self) -> usize {
+        self.count(|result| *result == VerifyResult::Verified)
+    }
+
+    pub fn mismatched(&self) -> usize {
+        self.count(|result| matches!(result, VerifyResult::Mismatch { .. }))
+    }
+
+    pub fn unknown(&self) -> usize {
+        self.count(|result| *result == VerifyResult::Unknown)
+    }
+
+    pub fn missing(&self) -> usize {
+        self.count(|result| *result == VerifyResult::Missing)
+    }
+
+    pub fn unreadable(&self) -> usize {
+        self.count(|result| matches!(result, VerifyResult::Unreadable(_)))
+    }
+
+    /// Whether everything verified, unknown names aside if they are allowed.
+    pub fn is_clean(&self) -> bool {
+        self.skipped == 0 && self.results.iter().all(|(_, result)| passes(result, self.unknown_allowed))
+    }
+
+    fn count(&self, wanted: impl Fn(&VerifyResult) -> bool) -> usize {
+        self.results.iter().filter(|(_, result)| wanted(result)).count()
+    }
+}
+
+/// A line per entry that did not verify, then the counts.
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, result) in &self.results {
+            if *result != VerifyResult::Verified {
+                writeln!(f, "{}: {}", name, result)?;
+            }
+        }
+        write!(f, "{} verified, {} mismatched, {} unknown", self.verified(), self.mismatched(), self.unknown())?;
+        for (count, what) in [(self.missing(), "missing"), (self.unreadable(), "unreadable"), (self.skipped, "skipped")] {
+            if count > 0 {
+                write!(f, ", {} {}", count, what)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn passes(result: &VerifyResult, unknown_allowed: bool) -> bool {
+    match result {
+        VerifyResult::Verified => true,
+        VerifyResult::Unknown => unknown_allowed,
+        _ => false,
+    }
+}
+
+/// `VerifyReport::results` as `[{"name": ..., "status": ...}]`.
+mod named_results {
+    use super::VerifyResult;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Named {
+        name: String,
+        #[serde(flatten)]
+        result: VerifyResult,
+    }
+
+    pub fn serialize<S: Serializer>(results: &[(String, VerifyResult)], serializer: S) -> Result<S::Ok, S::Error> {
+        let named: Vec<Named> =
+            results.iter().map(|(name, result)| Named { name: name.clone(), result: result.clone() }).collect();
+        named.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, VerifyResult)>, D::Error> {
+        let named = Vec::<Named>::deserialize(deserializer)?;
+        Ok(named.into_iter().map(|named| (named.name, named.result)).collect())
+    }
+}
+
+/// A `Duration` as fractional seconds, like `Throughput::to_json`.
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
 }
 
 impl KdvVerifier {
     pub fn new() -> Self {
         Self {
             fingerprints: HashMap::new(),
+            algorithms: HashMap::new(),
+            key: None,
         }
     }
 
-    pub fn load_initial_fingerprints(&mut self, sections: &HashMap<String, Vec<u8>>) {
+    /// A verifier of every entry of `manifest`, keyed by its path and hashed
+    /// with its own algorithm. `key` is for the keyed entries.
+    pub fn from_baseline(manifest: &KdvManifest, key: Option<&[u8]>) -> Result<Self, String> {
+        let mut verifier = Self::new();
+        for entry in &manifest.entries {
+            let digest = hex::decode(&entry.digest).map_err(|_| format!("digest of {} in the baseline is not hex", entry.path))?;
+            verifier.fingerprints.insert(entry.path.clone(), digest);
+            verifier.algorithms.insert(entry.path.clone(), entry.algorithm);
+        }
+        verifier.key = key.map(<[u8]>::to_vec);
+        Ok(verifier)
+    }
+
+    /// A verifier with a fingerprint of each of `sections`, by name.
+    #[allow(dead_code)]
+    pub fn from_sections(sections: &HashMap<String, Vec<u8>>) -> Self {
+        let mut verifier = Self::new();
+        verifier.load_fingerprints(sections);
+        verifier
+    }
+
+    /// Fingerprints each of `sections` under its name, replacing any
+    /// fingerprint already there.
+    pub fn load_fingerprints(&mut self, sections: &HashMap<String, Vec<u8>>) {
         for (name, content) in sections {
-            let hash = Self::compute_hash(content);
-            self.fingerprints.insert(name.clone(), hash);
-            println!("[INIT] Loaded fingerprint for {}", name);
+            self.fingerprints.insert(name.clone(), Self::compute_hash(content));
+        }
+    }
+
+    #[allow(dead_code)]
+    #[deprecated(note = "use load_fingerprints, which does not print")]
+    pub fn load_initial_fingerprints(&mut self, sections: &HashMap<String, Vec<u8>>) {
+        self.load_fingerprints(sections);
+    }
+
+    /// Fingerprints the file at `path` under its path, hashing it in chunks
+    /// instead of loading it whole.
+    pub fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let hash = Self::hash_file(path)?;
+        self.fingerprints.insert(path.to_string_lossy().into_owned(), hash);
+        Ok(())
+    }
+
+    /// For content already in memory, like a section of this executable.
+    pub fn verify_content(&self, name: &str, content: &[u8]) -> VerifyResult {
+        self.verify_input(name, VerifyInput::Content(content), &AtomicU64::new(0), None)
+    }
+
+    /// Verifies each named entry of `entries`, content in memory or a file
+    /// to hash, and reports them in the order given. Names without a
+    /// fingerprint are not hashed at all. Fails only if the hashing threads
+    /// cannot be started.
+    pub fn verify_all<'a, N, S, I>(&self, entries: I, options: &VerifyOptions) -> Result<VerifyReport, String>
+    where
+        N: Into<String>,
+        S: Into<VerifyInput<'a>>,
+        I: IntoIterator<Item = (N, S)>,
+    {
+        self.verify_tracked(entries, options, None)
+    }
+
+    /// `verify_all`, with the files read counted in `progress` and charged
+    /// to its throttle, if given.
+    fn verify_tracked<'a, N, S, I>(&self, entries: I, options: &VerifyOptions, progress: Option<&Progress>) -> Result<VerifyReport, String>
+    where
+        N: Into<String>,
+        S: Into<VerifyInput<'a>>,
+        I: IntoIterator<Item = (N, S)>,
+    {
+        let started = Instant::now();
+        let hashed = AtomicU64::new(0);
+        let stop = AtomicBool::new(false);
+        let check = |name: String, input: VerifyInput| {
+            let result = self.verify_input(&name, input, &hashed, progress);
+            if let Some(progress) = progress {
+                progress.files_done.fetch_add(1, Ordering::Relaxed);
+            }
+            if options.fail_fast && !passes(&result, options.allow_unknown) {
+                stop.store(true, Ordering::Relaxed);
+            }
+            (name, result)
+        };
+        let jobs = options.jobs.unwrap_or_else(hash_pool::default_jobs);
+        let (results, skipped) = if jobs <= 1 {
+            let mut entries = entries.into_iter();
+            let mut results = Vec::new();
+            for (name, input) in entries.by_ref() {
+                results.push(check(name.into(), input.into()));
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+            (results, entries.count())
+        } else {
+            let entries: Vec<(String, VerifyInput)> =
+                entries.into_iter().map(|(name, input)| (name.into(), input.into())).collect();
+            let pool = hash_pool::pool(jobs)?;
+            let checked: Vec<Option<(String, VerifyResult)>> = pool.install(|| {
+                entries
+                    .into_par_iter()
+                    .map(|(name, input)| (!stop.load(Ordering::Relaxed)).then(|| check(name, input)))
+                    .collect()
+            });
+            let total = checked.len();
+            let results: Vec<(String, VerifyResult)> = checked.into_iter().flatten().collect();
+            let skipped = total - results.len();
+            (results, skipped)
+        };
+        Ok(VerifyReport {
+            results,
+            duration: started.elapsed(),
+            bytes_hashed: hashed.into_inner(),
+            skipped,
+            unknown_allowed: options.allow_unknown,
+        })
+    }
+
+    fn verify_input(&self, name: &str, input: VerifyInput, hashed: &AtomicU64, progress: Option<&Progress>) -> VerifyResult {
+        if !self.fingerprints.contains_key(name) {
+            return VerifyResult::Unknown;
         }
+        let hashing = match input {
+            VerifyInput::Content(mut content) => {
+                hashed.fetch_add(content.len() as u64, Ordering::Relaxed);
+                self.hash_as(name, &mut content)
+            }
+            VerifyInput::Path(path) => fs::File::open(path).and_then(|file| match progress {
+                Some(progress) => self.hash_as(name, &mut Counting { inner: progress.counting(file), bytes: hashed, throttle: None }),
+                None => self.hash_as(name, &mut Counting { inner: file, bytes: hashed, throttle: None }),
+            }),
+        };
+        match hashing {
+            Ok(actual) => self.compare(name, actual),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VerifyResult::Missing,
+            Err(e) => VerifyResult::Unreadable(e.to_string()),
+        }
+    }
+
+    /// Hashes `reader` the way the fingerprint under `name` was made.
+    fn hash_as(&self, name: &str, reader: &mut impl Read) -> io::Result<Vec<u8>> {
+        let algo = self.algorithms.get(name).copied().unwrap_or_default();
+        algo.hash_reader(reader, self.key.as_deref())
     }
 
+    /// Whether `content` verified.
+    #[allow(dead_code)]
+    #[deprecated(note = "use verify_content, which returns a VerifyResult")]
     pub fn verify(&self, name: &str, content: &[u8]) -> bool {
-        let current_hash = Self::compute_hash(content);
+        self.verify_content(name, content) == VerifyResult::Verified
+    }
+
+    /// Like `verify_content`, but hashes `reader` in chunks instead of needing the
+    /// content in memory.
+    pub fn verify_reader(&self, name: &str, reader: &mut impl Read) -> io::Result<VerifyResult> {
+        let hash = self.hash_as(name, reader)?;
+        Ok(self.compare(name, hash))
+    }
+
+    /// `verify_reader` on the file at `path`, under its path.
+    pub fn verify_path(&self, path: &Path) -> io::Result<VerifyResult> {
+        self.verify_reader(&path.to_string_lossy(), &mut fs::File::open(path)?)
+    }
+
+    /// Checks every section of the pself container at `path` against the
+    /// hash its section table records, reported in table order. A section
+    /// whose bytes lie outside the container has nothing to hash and is
+    /// unknown.
+    pub fn verify_pself(path: &Path, options: &VerifyOptions) -> Result<VerifyReport, String> {
+        let runner = read_pself(path)?;
+        let mut verifier = Self::new();
+        let mut sections = Vec::new();
+        for section in &runner.sections {
+            let content = section
+                .offset
+                .checked_add(section.length)
+                .and_then(|end| runner.data.get(section.offset..end));
+            // Left without a fingerprint, it comes out unknown unhashed.
+            if content.is_some() {
+                verifier.fingerprints.insert(section.name.clone(), section.hash.to_vec());
+            }
+            sections.push((section.name.as_str(), content.unwrap_or_default()));
+        }
+        verifier.verify_all(sections, options)
+    }
 
+    fn compare(&self, name: &str, actual: Vec<u8>) -> VerifyResult {
         match self.fingerprints.get(name) {
-            None => {
-                println!("[ERROR] Unknown section: {}", name);
-                false
+            None => VerifyResult::Unknown,
+            Some(expected) if *expected == actual => VerifyResult::Verified,
+            Some(expected) => VerifyResult::Mismatch {
+                expected: expected.clone(),
+                actual,
+            },
+        }
+    }
+
+    /// Loads a `sha256sum`-style manifest: one `<hex digest>  <path>` per
+    /// line. Entries are also keyed by their canonical path when it resolves,
+    /// so relative and absolute spellings of a watched file both match.
+    pub fn from_manifest(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut verifier = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            Some(expected_hash) => {
-                if *expected_hash != current_hash {
-                    println!("[ALERT] Integrity violation in section: {}", name);
-                    false
-                } else {
-                    println!("[OK] Section verified: {}", name);
-                    true
-                }
+            let invalid = |reason: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), number + 1, reason),
+                )
+            };
+            let (digest, name) = line.split_once(char::is_whitespace).ok_or_else(|| invalid("expected '<sha256>  <path>'"))?;
+            let name = name.trim_start().trim_start_matches('*');
+            let hash = hex::decode(digest).map_err(|_| invalid("digest is not hex"))?;
+            if hash.len() != 32 || name.is_empty() {
+                return Err(invalid("expected '<sha256>  <path>'"));
+            }
+            if let Ok(canonical) = fs::canonicalize(name) {
+                verifier.fingerprints.insert(canonical.to_string_lossy().into_owned(), hash.clone());
             }
+            verifier.fingerprints.insert(name.to_string(), hash);
+        }
+        Ok(verifier)
+    }
+
+    /// Hashes the file at `path` in constant memory and grades it against
+    /// the fingerprint under its path or its canonical spelling. A file that
+    /// could not be read diverges.
+    pub fn check_file(&self, path: &Path) -> BaselineCheck {
+        let presented = path.to_string_lossy().into_owned();
+        let name = if self.fingerprints.contains_key(&presented) {
+            Some(presented)
+        } else {
+            fs::canonicalize(path)
+                .ok()
+                .map(|canonical| canonical.to_string_lossy().into_owned())
+                .filter(|canonical| self.fingerprints.contains_key(canonical))
+        };
+        match name {
+            Some(name) => self.verify_input(&name, VerifyInput::Path(path), &AtomicU64::new(0), None).check(),
+            None => BaselineCheck::Unknown,
         }
     }
 
+    pub fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+        HashAlgo::Sha256.hash_file(path, None)
+    }
+
     pub fn compute_hash(data: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.finalize().to_vec()
+        HashAlgo::Sha256.hash(data)
     }
 }
 
-pub fn load_files_as_sections(paths: &[String]) -> HashMap<String, Vec<u8>> {
-    let mut map = HashMap::new();
-    for path in paths {
-        if Path::new(path).exists() {
-            match fs::read(path) {
-                Ok(bytes) => {
-                    map.insert(path.clone(), bytes);
-                }
-                Err(e) => {
-                    eprintln!("[ERROR] Failed to read {}: {}", path, e);
+/// One fingerprinted file in a `kdv init` manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Absolute with the directories resolved, or relative to the manifest's root.
+    pub path: String,
+    pub size: u64,
+    pub algorithm: HashAlgo,
+    /// Hex digest of the content.
+    pub digest: String,
+    /// Nanoseconds since the epoch, for `kdv check --quick`. Absent from
+    /// baselines written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ns: Option<u64>,
+    /// Recorded with `--by-section` for executables, so a change can be
+    /// pinned to the sections it touched.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<SectionFingerprint>,
+    /// Recorded with `--with-metadata`, so check also catches a file that
+    /// kept its content but changed hands or permissions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<EntryMetadata>,
+}
+
+/// Who owns a file and who may write it: mode, uid and gid on Unix, the
+/// read-only flag elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntryMetadata {
+    /// Permission bits, with setuid, setgid and sticky.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// setuid and setgid: a file gaining either is flagged even in a baseline
+/// recorded without metadata.
+const PRIVILEGE_BITS: [(u32, &str); 2] = [(0o4000, "setuid"), (0o2000, "setgid")];
+
+impl EntryMetadata {
+    pub fn of(meta: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode() & 0o7777), Some(meta.uid()), Some(meta.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        Self {
+            mode,
+            uid,
+            gid,
+            readonly: meta.permissions().readonly(),
+        }
+    }
+
+    /// How `now` differs from what was `recorded`. Without a record only a
+    /// setuid or setgid bit that appeared counts.
+    pub fn violations(recorded: Option<&Self>, now: &Self) -> Vec<MetadataViolation> {
+        let mut violations = Vec::new();
+        let Some(recorded) = recorded else {
+            let mode = now.mode.unwrap_or(0);
+            for (bit, name) in PRIVILEGE_BITS {
+                if mode & bit != 0 {
+                    violations.push(MetadataViolation::new(name, "unset", "set"));
                 }
             }
+            return violations;
+        };
+        let octal = |mode: Option<u32>| mode.map_or("-".to_string(), |mode| format!("{:04o}", mode));
+        let id = |id: Option<u32>| id.map_or("-".to_string(), |id| id.to_string());
+        if recorded.mode != now.mode {
+            violations.push(MetadataViolation::new("mode", &octal(recorded.mode), &octal(now.mode)));
+        }
+        if recorded.uid != now.uid {
+            violations.push(MetadataViolation::new("uid", &id(recorded.uid), &id(now.uid)));
+        }
+        if recorded.gid != now.gid {
+            violations.push(MetadataViolation::new("gid", &id(recorded.gid), &id(now.gid)));
+        }
+        // On Unix the mode already says so.
+        if recorded.mode.is_none() && recorded.readonly != now.readonly {
+            violations.push(MetadataViolation::new("readonly", &recorded.readonly.to_string(), &now.readonly.to_string()));
+        }
+        violations
+    }
+}
+
+/// One attribute of a file that no longer matches the baseline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MetadataViolation {
+    pub attribute: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl MetadataViolation {
+    fn new(attribute: &'static str, expected: &str, actual: &str) -> Self {
+        Self {
+            attribute,
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MetadataViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} -> {}", self.attribute, self.expected, self.actual)
+    }
+}
+
+/// The baseline `kdv init` writes and `kdv check` compares against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KdvManifest {
+    pub version: u32,
+    /// UTC, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created_at: String,
+    /// The `--root` entry paths are relative to; without one they are absolute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    /// Sorted by path.
+    pub entries: Vec<ManifestEntry>,
+    /// Made with `--key-file`: every entry must be hmac-sha256, so an entry
+    /// rewritten to a plain digest is refused rather than trusted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyed: bool,
+    /// Each `kdv update` that rewrote the baseline, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub updates: Vec<BaselineUpdate>,
+    /// Hex Merkle root over the entries as last created or saved; see
+    /// `merkle_root`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    /// Written by `kdv init --sign-key`; see `sign`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+}
+
+/// An ed25519 signature over the rest of the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSignature {
+    /// Hex key of the signer, so a wrong key is told from an edited manifest.
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// What `kdv check` asks of a baseline's signature: none without a key,
+/// else a valid one by that key unless `insecure`.
+#[derive(Default)]
+pub struct BaselineTrust {
+    pub public_key: Option<VerifyingKey>,
+    pub insecure: bool,
+}
+
+/// Who re-trusted how many entries with `kdv update`, and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BaselineUpdate {
+    pub by: String,
+    /// UTC, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub at: String,
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Where a file stands against the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "lowercase")]
+pub enum FileStatus {
+    Verified,
+    /// Size and mtime match, so `--quick` trusted the entry without hashing.
+    Trusted,
+    /// Modified since the baseline by mtime, but the content is the same.
+    Touched,
+    /// Size or digest differ from the manifest.
+    Changed,
+    /// Changed, for a file fingerprinted by section: which of them did.
+    #[serde(rename = "changed")]
+    SectionsChanged(SectionChanges),
+    /// The content matches, but the owner or permissions do not; see
+    /// `EntryMetadata::violations`.
+    #[serde(rename = "metadata")]
+    MetadataChanged(Vec<MetadataViolation>),
+    /// In the manifest, gone from disk.
+    Missing,
+    /// On disk and asked about, but not in the manifest.
+    Added,
+    /// Could not be read.
+    Error(String),
+}
+
+impl FileStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Verified => "OK",
+            FileStatus::Trusted => "QUICK",
+            FileStatus::Touched => "TOUCHED",
+            FileStatus::Changed | FileStatus::SectionsChanged(_) => "CHANGED",
+            FileStatus::MetadataChanged(_) => "METADATA",
+            FileStatus::Missing => "MISSING",
+            FileStatus::Added => "ADDED",
+            FileStatus::Error(_) => "ERROR",
+        }
+    }
+
+    /// Like `as_str`, but "OK" for every file whose content matched.
+    pub fn health(&self) -> &'static str {
+        match self {
+            FileStatus::Trusted | FileStatus::Touched => "OK",
+            status => status.as_str(),
+        }
+    }
+}
+
+/// The sections of a changed file that differ from the manifest, and
+/// those that do not. A section gone from the file, or new in it, counts as
+/// changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SectionChanges {
+    pub changed: Vec<String>,
+    pub intact: Vec<String>,
+}
+
+impl fmt::Display for SectionChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.changed.is_empty() {
+            true => write!(f, "changed outside its sections")?,
+            false => write!(f, "{} changed", self.changed.join(", "))?,
+        }
+        if !self.intact.is_empty() {
+            write!(f, "; {} intact", self.intact.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of `kdv check --json`.
+#[derive(Debug, Serialize)]
+pub struct FileReport<'a> {
+    pub path: &'a str,
+    #[serde(flatten)]
+    pub status: &'a FileStatus,
+}
+
+/// What a `kdv check` found, counted by status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CheckSummary {
+    pub verified: usize,
+    pub changed: usize,
+    pub missing: usize,
+    pub added: usize,
+    pub errors: usize,
+    /// Same content, other owner or permissions.
+    pub metadata: usize,
+    pub touched: usize,
+    /// Passed on size and mtime alone; see `FileStatus::Trusted`.
+    pub quick_passed: usize,
+    /// Files left unchecked after `--fail-fast` stopped at a failure.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub skipped: usize,
+    /// With `--allow-unknown`, added files do not fail the check.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unknown_allowed: bool,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+impl CheckSummary {
+    pub fn of(results: &[(String, FileStatus)]) -> Self {
+        let mut summary = Self::default();
+        for (_, status) in results {
+            match status {
+                FileStatus::Verified => summary.verified += 1,
+                FileStatus::Trusted => summary.quick_passed += 1,
+                FileStatus::Touched => summary.touched += 1,
+                FileStatus::Changed | FileStatus::SectionsChanged(_) => summary.changed += 1,
+                FileStatus::MetadataChanged(_) => summary.metadata += 1,
+                FileStatus::Missing => summary.missing += 1,
+                FileStatus::Added => summary.added += 1,
+                FileStatus::Error(_) => summary.errors += 1,
+            }
+        }
+        summary
+    }
+
+    /// 0 when everything passed, 2 for changed or added files, else 3 for
+    /// missing ones, else 5 for owner or permission changes, else 1 for
+    /// files that could not be checked. The worst finding wins, so a script
+    /// never mistakes tampering for a read error.
+    pub fn exit_code(&self) -> i32 {
+        let added = if self.unknown_allowed { 0 } else { self.added };
+        if self.changed + added > 0 {
+            2
+        } else if self.missing > 0 {
+            3
+        } else if self.metadata > 0 {
+            5
+        } else if self.errors > 0 {
+            1
         } else {
-            eprintln!("[ERROR] File not found: {}", path);
+            0
+        }
+    }
+}
+
+impl fmt::Display for CheckSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} verified, {} changed, {} missing, {} added, {} errors, {} metadata changed, {} touched, {} quick-passed",
+            self.verified,
+            self.changed,
+            self.missing,
+            self.added,
+            self.errors,
+            self.metadata,
+            self.touched,
+            self.quick_passed
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Entries with the same content, for `kdv dupes`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DupeGroup {
+    pub digest: String,
+    /// Of each copy.
+    pub size: u64,
+    /// Taken by every copy but one.
+    pub wasted: u64,
+    /// Sorted.
+    pub paths: Vec<String>,
+}
+
+/// An entry that differs between two baselines, for `kdv diff`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum Drift {
+    Added {
+        path: String,
+        size: u64,
+        digest: String,
+    },
+    Removed {
+        path: String,
+    },
+    /// Same size and algorithm, different content.
+    HashChanged {
+        path: String,
+        old_digest: String,
+        new_digest: String,
+    },
+    SizeChanged {
+        path: String,
+        old_size: u64,
+        new_size: u64,
+    },
+    /// Rehashed with another algorithm; the digests say nothing about
+    /// whether the content changed.
+    AlgorithmChanged {
+        path: String,
+        old_algorithm: HashAlgo,
+        new_algorithm: HashAlgo,
+    },
+}
+
+impl Drift {
+    pub fn path(&self) -> &str {
+        match self {
+            Drift::Added { path, .. }
+            | Drift::Removed { path }
+            | Drift::HashChanged { path, .. }
+            | Drift::SizeChanged { path, .. }
+            | Drift::AlgorithmChanged { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Added { path, size, .. } => write!(f, "[ADDED] {} ({} bytes)", path, size),
+            Drift::Removed { path } => write!(f, "[REMOVED] {}", path),
+            Drift::HashChanged {
+                path,
+                old_digest,
+                new_digest,
+            } => write!(f, "[HASH] {}: {} -> {}", path, old_digest, new_digest),
+            Drift::SizeChanged {
+                path,
+                old_size,
+                new_size,
+            } => write!(f, "[SIZE] {}: {} -> {} bytes", path, old_size, new_size),
+            Drift::AlgorithmChanged {
+                path,
+                old_algorithm,
+                new_algorithm,
+            } => write!(f, "[ALGORITHM] {}: {} -> {}", path, old_algorithm, new_algorithm),
         }
     }
-    map
 }
 
-pub fn run_kdv(paths: &[String]) {
-    println!("[KDV] Başlatılıyor...");
-    let sections = load_files_as_sections(paths);
+/// What `kdv diff` found between two baselines.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BaselineDiff {
+    /// Entries with the same size, algorithm and digest on both sides.
+    pub unchanged: usize,
+    /// Sorted by path.
+    pub differences: Vec<Drift>,
+}
+
+/// A file whose health changed between two rounds of `kdv check --watch`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Transition {
+    pub path: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// What `kdv check --watch` remembers between rounds: the health of each
+/// file that is not OK. A file not listed is OK.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchState {
+    pub files: BTreeMap<String, String>,
+}
+
+impl WatchState {
+    /// An empty state if `path` does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| format!("Invalid watch state {}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Cannot read watch state {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Written next to `path` and renamed into place, like a baseline.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        write_private(Path::new(&temporary), (json + "\n").as_bytes())?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Takes in one round of results and returns the files whose health
+    /// differs from the round before, sorted by path.
+    pub fn advance(&mut self, results: &[(String, FileStatus)]) -> Vec<Transition> {
+        let files: BTreeMap<String, String> = results
+            .iter()
+            .filter(|(_, status)| status.health() != "OK")
+            .map(|(path, status)| (path.clone(), status.health().to_string()))
+            .collect();
+        let paths: BTreeMap<&String, ()> = self.files.keys().chain(files.keys()).map(|path| (path, ())).collect();
+        let transitions = paths
+            .into_keys()
+            .filter_map(|path| {
+                let from = self.files.get(path).map_or("OK", String::as_str);
+                let to = files.get(path).map_or("OK", String::as_str);
+                (from != to).then(|| Transition {
+                    path: path.clone(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+            })
+            .collect();
+        self.files = files;
+        transitions
+    }
+}
+
+/// How `kdv check --watch` runs and where it sends its alerts.
+#[derive(Debug, Clone, Default)]
+pub struct WatchOptions {
+    pub interval: Duration,
+    /// Keeps the state across restarts; otherwise it lives in memory.
+    pub state_file: Option<PathBuf>,
+    /// Print transitions as JSON lines.
+    pub json: bool,
+    /// POST each transition as JSON to these URLs.
+    pub webhooks: Vec<String>,
+}
+
+/// How `kdv` expands the directories it is given.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Matched against the full path and the file name; an excluded
+    /// directory is not descended into.
+    pub excludes: Vec<glob::Pattern>,
+    /// Directory levels to descend below each given directory.
+    pub max_depth: Option<usize>,
+    pub skip_symlinks: bool,
+}
+
+/// Where `kdv check --incremental` keeps what it saw, and how often it
+/// rehashes everything regardless.
+#[derive(Debug, Clone)]
+pub struct Incremental {
+    pub state_file: PathBuf,
+    /// Every Nth run is a full one; `None` for never after the first.
+    pub full_every: Option<u32>,
+}
+
+const CHECK_STATE_KIND: &str = "kdv-check-state";
+
+/// A file as it was when it last verified: the digest it verified against
+/// and its size, mtime and inode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Observed {
+    pub digest: String,
+    pub size: u64,
+    pub mtime_ns: Option<u64>,
+    pub inode: u64,
+}
+
+impl Observed {
+    fn of(entry: &ManifestEntry, meta: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Self {
+            digest: entry.digest.clone(),
+            size: meta.len(),
+            mtime_ns: mtime_ns(meta),
+            inode,
+        }
+    }
+}
+
+/// The state file of `kdv check --incremental`. It is only a cache of
+/// what the last run saw: `kind` keeps it from being taken for a baseline
+/// or a baseline for it, and it is tied to the baseline's Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckState {
+    pub kind: String,
+    /// Merkle root of the baseline the files verified against.
+    pub baseline: String,
+    /// Incremental runs since the last full one.
+    pub since_full: u32,
+    pub files: BTreeMap<String, Observed>,
+}
+
+impl CheckState {
+    /// `None` if there is no state yet. A file that is not a state file is
+    /// an error, so it is never overwritten.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Cannot read check state {}: {}", path.display(), e)),
+        };
+        match serde_json::from_str::<Self>(&text) {
+            Ok(state) if state.kind == CHECK_STATE_KIND => Ok(Some(state)),
+            _ => Err(format!("{} is not a kdv check state file; remove it or pick another", path.display())),
+        }
+    }
+
+    /// Written next to `path` and renamed into place, like a baseline.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        write_private(Path::new(&temporary), (json + "\n").as_bytes())?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// The results of an incremental check, the state to save after it and
+/// how many files it hashed.
+pub type IncrementalRun = (Vec<(String, FileStatus)>, CheckState, usize);
+
+/// One incremental run: the files it may skip, those it saw verify, and
+/// how many it hashed.
+struct Tracker {
+    known: BTreeMap<String, Observed>,
+    seen: Mutex<BTreeMap<String, Observed>>,
+    hashed: AtomicUsize,
+}
+
+impl Incremental {
+    /// Checks like `KdvManifest::check`, skipping files unchanged since the
+    /// state file saw them verify. Without a state file, or with one from
+    /// another baseline or a full run due, every file is hashed. Returns
+    /// the results, the state to save and how many files were hashed.
+    pub fn check(
+        &self,
+        manifest: &KdvManifest,
+        paths: &[PathBuf],
+        walk: &WalkOptions,
+        root: Option<&Path>,
+        hashing: &Hashing,
+    ) -> Result<IncrementalRun, String> {
+        let baseline = manifest.merkle_root();
+        let (previous, since_full) = match CheckState::load(&self.state_file)? {
+            Some(state) if state.baseline != baseline => (BTreeMap::new(), 0),
+            Some(state) if self.full_every.is_some_and(|every| state.since_full + 1 >= every) => (BTreeMap::new(), 0),
+            Some(state) => (state.files, state.since_full + 1),
+            None => (BTreeMap::new(), 0),
+        };
+        let tracker = Tracker {
+            known: previous,
+            seen: Mutex::default(),
+            hashed: AtomicUsize::new(0),
+        };
+        let results = manifest.check_with(paths, walk, root, hashing, Some(&tracker));
+        // Files left out of this run keep what the last run saw.
+        let mut files = tracker.known;
+        for (path, _) in &results {
+            files.remove(path);
+        }
+        files.extend(tracker.seen.into_inner().expect("tracker poisoned"));
+        let state = CheckState {
+            kind: CHECK_STATE_KIND.to_string(),
+            baseline,
+            since_full,
+            files,
+        };
+        Ok((results, state, tracker.hashed.into_inner()))
+    }
+}
+
+/// How `kdv` hashes files, whatever the algorithm.
+#[derive(Clone, Default)]
+pub struct Hashing {
+    /// For the keyed algorithms, from `--key-file`.
+    pub key: Option<Vec<u8>>,
+    /// Files hashed at once; `None` for `hash_pool::default_jobs`.
+    pub jobs: Option<usize>,
+    /// Caps the bytes read per second across all hashing threads.
+    pub throttle: Option<Arc<Throttle>>,
+    /// What every pass of this run read and how long it took.
+    pub throughput: Arc<Throughput>,
+    /// Redraw files done, throughput and ETA on stderr while hashing.
+    pub progress: bool,
+    /// Trust entries whose size and mtime still match instead of rehashing
+    /// them. Content rewritten with its mtime put back goes unnoticed.
+    pub quick: bool,
+    /// When creating a manifest, also fingerprint each program section of
+    /// executables. Those are read whole instead of streamed.
+    pub by_section: bool,
+    /// When creating a manifest, also record each file's owner and
+    /// permissions; see `EntryMetadata`.
+    pub with_metadata: bool,
+    /// For `kdv check --incremental`: skip files whose stat has not changed
+    /// since they last verified.
+    pub incremental: Option<Incremental>,
+}
+
+impl Hashing {
+    /// Runs `work` over `items` on the hashing threads and returns the
+    /// results in input order, whichever finishes first. `size` weighs each
+    /// item for the progress line. Files are read in small chunks, so memory
+    /// does not grow with their size.
+    fn run<T, R>(
+        &self,
+        items: &[T],
+        size: impl Fn(&T) -> u64,
+        work: impl Fn(&T, &Progress) -> R + Send + Sync,
+    ) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+    {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.unwrap_or_else(hash_pool::default_jobs))
+            .build()
+            .expect("cannot start the hashing threads");
+        self.tracked(items.len(), items.iter().map(size).sum(), |progress| {
+            pool.install(|| {
+                items
+                    .par_iter()
+                    .map(|item| {
+                        let result = work(item, progress);
+                        progress.files_done.fetch_add(1, Ordering::Relaxed);
+                        result
+                    })
+                    .collect()
+            })
+        })
+    }
+
+    /// Runs `work` with the `Progress` of a pass over `files` files of
+    /// `bytes` bytes, redrawn on stderr meanwhile if asked to, and adds what
+    /// it read to the throughput.
+    fn tracked<R>(&self, files: usize, bytes: u64, work: impl FnOnce(&Progress) -> R) -> R {
+        let progress = Progress {
+            files,
+            bytes,
+            files_done: AtomicUsize::new(0),
+            bytes_done: AtomicU64::new(0),
+            started: Instant::now(),
+            throttle: self.throttle.clone(),
+        };
+        let (finished, waiting) = channel::<()>();
+        std::thread::scope(|scope| {
+            let progress = &progress;
+            if self.progress {
+                scope.spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = waiting.recv_timeout(PROGRESS_EVERY) {
+                        eprint!("\r{}\x1b[K", progress.line());
+                    }
+                    eprintln!("\r{}\x1b[K", progress.line());
+                });
+            }
+            let result = work(progress);
+            drop(finished);
+            self.throughput.add(progress.bytes_done.load(Ordering::Relaxed), progress.started.elapsed());
+            result
+        })
+    }
+}
+
+/// What the hashing threads have got through so far.
+struct Progress {
+    files: usize,
+    bytes: u64,
+    files_done: AtomicUsize,
+    bytes_done: AtomicU64,
+    started: Instant,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl Progress {
+    /// `inner`, with what is read from it counted as done and charged to
+    /// the throttle, if any.
+    fn counting<R: Read>(&self, inner: R) -> Counting<'_, R> {
+        Counting {
+            inner,
+            bytes: &self.bytes_done,
+            throttle: self.throttle.as_deref(),
+        }
+    }
+
+    fn line(&self) -> String {
+        const MIB: f64 = 1024.0 * 1024.0;
+        let bytes_done = self.bytes_done.load(Ordering::Relaxed);
+        let rate = bytes_done as f64 / self.started.elapsed().as_secs_f64().max(0.001);
+        let eta = match self.bytes.saturating_sub(bytes_done) {
+            0 => "0s".to_string(),
+            _ if rate < 1.0 => "?".to_string(),
+            left => format!("{}s", (left as f64 / rate).ceil() as u64),
+        };
+        format!(
+            "[KDV] {}/{} files, {:.0}/{:.0} MiB, {:.1} MiB/s, ETA {}",
+            self.files_done.load(Ordering::Relaxed),
+            self.files,
+            bytes_done as f64 / MIB,
+            self.bytes as f64 / MIB,
+            rate / MIB,
+            eta
+        )
+    }
+}
+
+struct Counting<'a, R> {
+    inner: R,
+    bytes: &'a AtomicU64,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<R: Read> Read for Counting<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        if let Some(throttle) = self.throttle {
+            throttle.take(read as u64);
+        }
+        Ok(read)
+    }
+}
+
+/// `paths` with each directory replaced by the regular files below it, in
+/// walk order. Sockets, fifos and devices are skipped; so are symlinks with
+/// `skip_symlinks`, while other links to files stand for their target.
+/// Directories that cannot be read are returned apart.
+pub fn collect_files(paths: &[PathBuf], options: &WalkOptions) -> (Vec<PathBuf>, Vec<(PathBuf, io::Error)>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut walk = walkdir::WalkDir::new(path).sort_by_file_name();
+        if let Some(depth) = options.max_depth {
+            walk = walk.max_depth(depth + 1);
+        }
+        let entries = walk
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !matches_any(&options.excludes, entry.path()));
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let failed = e.path().unwrap_or(path).to_path_buf();
+                    errors.push((failed, e.into()));
+                    continue;
+                }
+            };
+            let kind = entry.file_type();
+            let is_file = if kind.is_symlink() {
+                !options.skip_symlinks && entry.path().is_file()
+            } else {
+                kind.is_file()
+            };
+            if is_file {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    (files, errors)
+}
+
+/// The paths listed on `input` for a `-` argument: one per line, or with
+/// `null` separated by NUL bytes as `find -print0` writes them. A path is
+/// left out if it or a directory above it matches an exclude, as a walk
+/// would have pruned it.
+pub fn read_paths(mut input: impl Read, null: bool, options: &WalkOptions) -> io::Result<Vec<PathBuf>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    let separator = if null { b'\0' } else { b'\n' };
+    let paths = bytes
+        .split(|&byte| byte == separator)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            #[cfg(unix)]
+            let path = PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(name));
+            #[cfg(not(unix))]
+            let path = PathBuf::from(String::from_utf8_lossy(name).trim_end_matches('\r'));
+            path
+        })
+        .filter(|path| !path.ancestors().any(|above| matches_any(&options.excludes, above)))
+        .collect();
+    Ok(paths)
+}
+
+/// `path` made absolute with the symlinks leading to it resolved, but a
+/// symlink in the last component kept, so a link has an entry of its own.
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (path.parent().map(fs::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => path,
+    }
+}
+
+/// The key `path` is recorded under: absolute, or relative to `root`.
+fn manifest_key(path: &Path, root: Option<&Path>) -> io::Result<String> {
+    let path = absolute(path);
+    let Some(root) = root else {
+        return Ok(path.to_string_lossy().into_owned());
+    };
+    let relative = path.strip_prefix(root).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("not under the root {}", root.display()))
+    })?;
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+impl KdvManifest {
+    /// Fingerprints `paths` with `algo`, walking directories, with entry
+    /// paths relative to `root` if one is given. With a key every entry is an
+    /// HMAC-SHA256 instead. Files that cannot be read are returned apart.
+    pub fn create(
+        paths: &[PathBuf],
+        walk: &WalkOptions,
+        root: Option<&Path>,
+        algo: HashAlgo,
+        hashing: &Hashing,
+    ) -> (Self, Vec<(PathBuf, io::Error)>) {
+        let key = hashing.key.as_deref();
+        let algo = if key.is_some() { HashAlgo::HmacSha256 } else { algo };
+        let root = root.map(absolute);
+        let (files, mut errors) = collect_files(paths, walk);
+        // Sized up front, so the progress line knows the total.
+        let mut pending = Vec::new();
+        for path in files {
+            let stat = manifest_key(&path, root.as_deref()).and_then(|name| {
+                let meta = fs::metadata(&path)?;
+                if !meta.is_file() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+                }
+                let metadata = hashing.with_metadata.then(|| EntryMetadata::of(&meta));
+                Ok((name, meta.len(), mtime_ns(&meta), metadata))
+            });
+            match stat {
+                Ok((name, size, mtime_ns, metadata)) => pending.push((path, name, size, mtime_ns, metadata)),
+                Err(e) => errors.push((path, e)),
+            }
+        }
+        let digests = hashing.run(
+            &pending,
+            |(_, _, size, _, _)| *size,
+            |(path, _, _, _, _), progress| {
+                let mut file = progress.counting(fs::File::open(path)?);
+                if !hashing.by_section {
+                    return Ok((algo.hash_reader(&mut file, key)?, Vec::new()));
+                }
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                let sections = section_fingerprints(&data, algo, key).unwrap_or_default();
+                Ok((algo.hash_reader(&mut data.as_slice(), key)?, sections))
+            },
+        );
+        let mut entries = Vec::new();
+        for ((path, name, size, mtime_ns, metadata), digest) in pending.into_iter().zip(digests) {
+            match digest {
+                Ok((digest, sections)) => entries.push(ManifestEntry {
+                    path: name,
+                    size,
+                    algorithm: algo,
+                    digest: hex::encode(digest),
+                    mtime_ns,
+                    sections,
+                    metadata,
+                }),
+                Err(e) => errors.push((path, e)),
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries.dedup_by(|a, b| a.path == b.path);
+        let mut manifest = Self {
+            version: MANIFEST_VERSION,
+            created_at: timestamp(SystemTime::now()),
+            root: root.map(|root| root.to_string_lossy().into_owned()),
+            entries,
+            keyed: key.is_some(),
+            updates: Vec::new(),
+            merkle_root: None,
+            signature: None,
+        };
+        manifest.merkle_root = Some(manifest.merkle_root());
+        (manifest, errors)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read baseline {}: {}", path.display(), e))?;
+        Self::parse(&text, &path.display().to_string())
+    }
+
+    /// The baseline compiled in as `data` (see `EMBEDDED_BASELINE`): a JSON
+    /// baseline, or a checksum file read like `kdv check -b` reads one.
+    pub fn from_embedded(data: &[u8]) -> Result<Self, String> {
+        if data.is_empty() {
+            return Err("No baseline was embedded; build with SERIALK_EMBED_BASELINE or SERIALK_EMBED_DIR set".to_string());
+        }
+        let text = std::str::from_utf8(data).map_err(|_| "The embedded baseline is not UTF-8".to_string())?;
+        if !kdv_sums::is_sums(text) {
+            return Self::parse(text, "(embedded)");
+        }
+        let lines = kdv_sums::parse(text).map_err(|e| format!("Invalid embedded checksum file: {}", e))?;
+        Ok(Self::from_sums(&lines, SumsFormat::guess(&lines).algorithm(), None).0)
+    }
+
+    /// `text` as a JSON baseline, `name` standing for it in errors.
+    fn parse(text: &str, name: &str) -> Result<Self, String> {
+        let manifest: Self = serde_json::from_str(text).map_err(|e| format!("Invalid baseline {}: {}", name, e))?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(format!(
+                "Baseline {} has format version {}, this kdv reads version {}",
+                name, manifest.version, MANIFEST_VERSION
+            ));
+        }
+        if manifest.merkle_root.as_ref().is_some_and(|root| *root != manifest.merkle_root()) {
+            return Err(format!(
+                "Baseline {} does not match its merkle_root; its entries were edited after it was written",
+                name
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Signs the manifest as it is now with `key`; any later change to it
+    /// breaks the signature.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.merkle_root = Some(self.merkle_root());
+        let signature = key.sign(&self.canonical_bytes());
+        self.signature = Some(ManifestSignature {
+            public_key: hex::encode(key.verifying_key().to_bytes()),
+            signature: hex::encode(signature.to_bytes()),
+        });
+    }
+
+    /// Whether the manifest carries a signature by `key` that still holds.
+    pub fn verify_signature(&self, key: &VerifyingKey) -> Result<(), String> {
+        let Some(signed) = &self.signature else {
+            return Err("is not signed".to_string());
+        };
+        if signed.public_key != hex::encode(key.to_bytes()) {
+            return Err(format!("is signed by another key ({})", signed.public_key));
+        }
+        let signature = hex::decode(&signed.signature)
+            .ok()
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .ok_or("has a signature that is not 64 hex bytes")?;
+        key.verify_strict(&self.canonical_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| "does not match its signature; it was edited after it was signed".to_string())
+    }
+
+    /// What a signature covers: the manifest without its signature and
+    /// with its Merkle root, as canonical JSON. The formatting of the file
+    /// itself does not matter, so re-saving keeps the signature valid.
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let unsigned = Self { merkle_root: Some(self.merkle_root()), signature: None, ..self.clone() };
+        let mut json = String::new();
+        write_canonical(&serde_json::to_value(&unsigned).expect("manifest serializes"), &mut json);
+        json.into_bytes()
+    }
+
+    /// The entries' leaf hashes sorted by path, and the entries in that order.
+    fn merkle_leaves(&self) -> (Vec<[u8; 32]>, Vec<&ManifestEntry>) {
+        let mut entries: Vec<&ManifestEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let leaves = entries.iter().map(|entry| kdv_merkle::leaf(&entry.path, &entry.digest)).collect();
+        (leaves, entries)
+    }
+
+    /// The hex Merkle root over the entries sorted by path, one value that
+    /// changes with any path or digest in the baseline.
+    pub fn merkle_root(&self) -> String {
+        hex::encode(kdv_merkle::root(&self.merkle_leaves().0))
+    }
+
+    /// The inclusion proof for the entry named `path`, if there is one.
+    pub fn prove(&self, path: &str) -> Option<MerkleProof> {
+        let (leaves, entries) = self.merkle_leaves();
+        let index = entries.iter().position(|entry| entry.path == path)?;
+        Some(MerkleProof::new(&leaves, index, path, &entries[index].digest))
+    }
+
+    /// A baseline of the section hashes recorded in the pself container at
+    /// `path`, one entry per section under its name, for tools that do not
+    /// read pself.
+    pub fn from_pself(path: &Path) -> Result<Self, String> {
+        let runner = read_pself(path)?;
+        let entries = runner.sections.iter().map(|section| ManifestEntry {
+            path: section.name.clone(),
+            size: section.length as u64,
+            algorithm: HashAlgo::Sha256,
+            digest: hex::encode(section.hash),
+            mtime_ns: None,
+            sections: Vec::new(),
+            metadata: None,
+        });
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            created_at: timestamp(SystemTime::now()),
+            root: None,
+            entries: entries.collect(),
+            keyed: false,
+            updates: Vec::new(),
+            merkle_root: None,
+            signature: None,
+        })
+    }
+
+    /// Writes the manifest with its Merkle root next to `path` and renames
+    /// it into place, so a crash never leaves half a baseline.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let manifest = Self { merkle_root: Some(self.merkle_root()), ..self.clone() };
+        let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+        write_private(Path::new(&temporary), (json + "\n").as_bytes())?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Re-hashes the files the manifest lists, or only `paths` if any are
+    /// given. Files found there but not in the manifest are reported as
+    /// added, and entries below a given directory that are gone as missing.
+    /// `root` stands in for the manifest's own, e.g. on another machine.
+    /// The key verifies the keyed entries; with the wrong one they all read
+    /// as changed. Results come in the order of the manifest or of `paths`.
+    pub fn check(
+        &self,
+        paths: &[PathBuf],
+        walk: &WalkOptions,
+        root: Option<&Path>,
+        hashing: &Hashing,
+    ) -> Vec<(String, FileStatus)> {
+        self.check_with(paths, walk, root, hashing, None)
+    }
+
+    /// `check`, skipping and recording files through `tracker` if given.
+    fn check_with(
+        &self,
+        paths: &[PathBuf],
+        walk: &WalkOptions,
+        root: Option<&Path>,
+        hashing: &Hashing,
+        tracker: Option<&Tracker>,
+    ) -> Vec<(String, FileStatus)> {
+        let root = root.map(absolute).or_else(|| self.root.as_ref().map(PathBuf::from));
+        let locate = |entry: &ManifestEntry| match &root {
+            Some(root) => root.join(&entry.path),
+            None => PathBuf::from(&entry.path),
+        };
+        if paths.is_empty() {
+            let statuses = hashing.run(
+                &self.entries,
+                |entry| entry.size,
+                |entry, progress| audit(entry, &locate(entry), track(tracker, entry, &locate(entry), hashing, progress)),
+            );
+            return self.entries.iter().map(|entry| entry.path.clone()).zip(statuses).collect();
+        }
+        let (files, errors) = collect_files(paths, walk);
+        // Each file's entry to hash against, or its status already.
+        let mut pending: Vec<(String, Result<&ManifestEntry, FileStatus>)> = Vec::new();
+        let mut seen = HashSet::new();
+        for path in files {
+            let name = match manifest_key(&path, root.as_deref()) {
+                Ok(name) => name,
+                Err(e) => {
+                    pending.push((path.to_string_lossy().into_owned(), Err(FileStatus::Error(e.to_string()))));
+                    continue;
+                }
+            };
+            let job = match self.entries.iter().find(|entry| entry.path == name) {
+                Some(entry) => Ok(entry),
+                None if path.exists() => Err(FileStatus::Added),
+                None => Err(FileStatus::Error("no such file".to_string())),
+            };
+            seen.insert(name.clone());
+            pending.push((name, job));
+        }
+        let statuses = hashing.run(
+            &pending,
+            |(_, job)| job.as_ref().map_or(0, |entry| entry.size),
+            |(_, job), progress| match job {
+                Ok(entry) => audit(entry, &locate(entry), track(tracker, entry, &locate(entry), hashing, progress)),
+                Err(status) => status.clone(),
+            },
+        );
+        let mut results: Vec<(String, FileStatus)> = pending.into_iter().map(|(name, _)| name).zip(statuses).collect();
+        let dirs: Vec<PathBuf> = paths.iter().filter(|path| path.is_dir()).map(|path| absolute(path)).collect();
+        for entry in &self.entries {
+            let location = locate(entry);
+            if !seen.contains(&entry.path) && !location.exists() && dirs.iter().any(|dir| location.starts_with(dir)) {
+                results.push((entry.path.clone(), FileStatus::Missing));
+            }
+        }
+        for (path, e) in errors {
+            results.push((path.to_string_lossy().into_owned(), FileStatus::Error(e.to_string())));
+        }
+        results
+    }
+
+    /// `check` through `KdvVerifier::verify_all`, which can stop at the
+    /// first failure and lets `options` decide about files not in the
+    /// baseline. Every file is hashed, whatever `hashing.quick` says. The
+    /// report has the time taken and the bytes read, which are added to
+    /// `hashing.throughput` too.
+    pub fn verify(
+        &self,
+        paths: &[PathBuf],
+        walk: &WalkOptions,
+        root: Option<&Path>,
+        hashing: &Hashing,
+        options: &VerifyOptions,
+    ) -> Result<(Vec<(String, FileStatus)>, VerifyReport), String> {
+        let verifier = KdvVerifier::from_baseline(self, hashing.key.as_deref())?;
+        let root = root.map(absolute).or_else(|| self.root.as_ref().map(PathBuf::from));
+        let locate = |entry: &ManifestEntry| match &root {
+            Some(root) => root.join(&entry.path),
+            None => PathBuf::from(&entry.path),
+        };
+        // What to hash, by name, and what was settled without hashing.
+        let mut targets: Vec<(String, PathBuf)> = Vec::new();
+        let mut settled = Vec::new();
+        if paths.is_empty() {
+            targets.extend(self.entries.iter().map(|entry| (entry.path.clone(), locate(entry))));
+        } else {
+            let (files, errors) = collect_files(paths, walk);
+            for path in files {
+                match manifest_key(&path, root.as_deref()) {
+                    Ok(name) => targets.push((name, path)),
+                    Err(e) => settled.push((path.to_string_lossy().into_owned(), FileStatus::Error(e.to_string()))),
+                }
+            }
+            let seen: HashSet<&str> = targets.iter().map(|(name, _)| name.as_str()).collect();
+            let dirs: Vec<PathBuf> = paths.iter().filter(|path| path.is_dir()).map(|path| absolute(path)).collect();
+            for entry in &self.entries {
+                let location = locate(entry);
+                if !seen.contains(entry.path.as_str()) && !location.exists() && dirs.iter().any(|dir| location.starts_with(dir)) {
+                    settled.push((entry.path.clone(), FileStatus::Missing));
+                }
+            }
+            settled.extend(errors.into_iter().map(|(path, e)| (path.to_string_lossy().into_owned(), FileStatus::Error(e.to_string()))));
+        }
+        let entries: HashMap<&str, &ManifestEntry> = self.entries.iter().map(|entry| (entry.path.as_str(), entry)).collect();
+        let bytes = targets.iter().filter_map(|(name, _)| entries.get(name.as_str())).map(|entry| entry.size).sum();
+        let report = hashing.tracked(targets.len(), bytes, |progress| {
+            let targets = targets.iter().map(|(name, path)| (name.as_str(), path));
+            verifier.verify_tracked(targets, options, Some(progress))
+        })?;
+        // After a fail-fast stop the report has only some of the targets.
+        let located: HashMap<&str, &PathBuf> = targets.iter().map(|(name, path)| (name.as_str(), path)).collect();
+        let mut results: Vec<(String, FileStatus)> = report
+            .results
+            .iter()
+            .map(|(name, result)| {
+                let path = located[name.as_str()];
+                let status = match (result, entries.get(name.as_str())) {
+                    (VerifyResult::Verified, Some(entry)) => {
+                        let mtime = fs::metadata(path).ok().and_then(|meta| mtime_ns(&meta));
+                        let touched = entry.mtime_ns.is_some() && entry.mtime_ns != mtime;
+                        audit(entry, path, if touched { FileStatus::Touched } else { FileStatus::Verified })
+                    }
+                    (VerifyResult::Mismatch { .. }, Some(entry)) => pin_change(entry, path, hashing),
+                    (VerifyResult::Missing, _) => FileStatus::Missing,
+                    (VerifyResult::Unreadable(e), _) => FileStatus::Error(e.clone()),
+                    (VerifyResult::Unknown, _) if path.exists() => FileStatus::Added,
+                    _ => FileStatus::Error("no such file".to_string()),
+                };
+                (name.clone(), status)
+            })
+            .collect();
+        results.extend(settled);
+        Ok((results, report))
+    }
+
+    /// This baseline with the `changes` a check found taken in: changed and
+    /// added files hashed again, missing ones dropped, and the update
+    /// recorded; the result is unsigned. `root` is the one the check ran
+    /// with. Files that cannot be hashed keep their old entry and are
+    /// returned apart.
+    pub fn update(
+        &self,
+        changes: &[(String, FileStatus)],
+        root: Option<&Path>,
+        hashing: &Hashing,
+    ) -> (Self, Vec<(PathBuf, io::Error)>) {
+        let root = root.map(absolute).or_else(|| self.root.as_ref().map(PathBuf::from));
+        let mut manifest = self.clone();
+        let mut errors = Vec::new();
+        let mut record = BaselineUpdate {
+            by: operator(),
+            at: timestamp(SystemTime::now()),
+            changed: 0,
+            added: 0,
+            removed: 0,
+        };
+        // Added files take the algorithm most of the baseline uses.
+        let common = self.entries.first().map_or(HashAlgo::default(), |entry| entry.algorithm);
+        for (name, status) in changes {
+            let old = manifest.entries.iter().position(|entry| entry.path == *name);
+            if *status == FileStatus::Missing {
+                if let Some(old) = old {
+                    manifest.entries.remove(old);
+                    record.removed += 1;
+                }
+                continue;
+            }
+            let path = match &root {
+                Some(root) => root.join(name),
+                None => PathBuf::from(name),
+            };
+            let old_entry = old.map(|old| &manifest.entries[old]);
+            let hashing = Hashing {
+                by_section: old_entry.is_some_and(|entry| !entry.sections.is_empty()),
+                // Re-trusting a setuid bit means recording it.
+                with_metadata: old_entry.is_some_and(|entry| entry.metadata.is_some())
+                    || matches!(status, FileStatus::MetadataChanged(_)),
+                progress: false,
+                ..hashing.clone()
+            };
+            let algo = old_entry.map_or(common, |entry| entry.algorithm);
+            let (fresh, failed) = Self::create(&[path], &WalkOptions::default(), root.as_deref(), algo, &hashing);
+            errors.extend(failed);
+            let Some(entry) = fresh.entries.into_iter().next() else {
+                continue;
+            };
+            match old {
+                Some(old) => {
+                    manifest.entries[old] = entry;
+                    record.changed += 1;
+                }
+                None => {
+                    manifest.entries.push(entry);
+                    record.added += 1;
+                }
+            }
+        }
+        manifest.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        manifest.updates.push(record);
+        manifest.merkle_root = Some(manifest.merkle_root());
+        manifest.signature = None;
+        (manifest, errors)
+    }
+
+    /// The entries of at least `min_size` bytes that share their digest with
+    /// another, grouped and sorted by the bytes the copies waste.
+    pub fn duplicates(&self, min_size: u64) -> Vec<DupeGroup> {
+        let mut by_digest: HashMap<(HashAlgo, &str), Vec<&ManifestEntry>> = HashMap::new();
+        for entry in self.entries.iter().filter(|entry| entry.size >= min_size) {
+            by_digest.entry((entry.algorithm, &entry.digest)).or_default().push(entry);
+        }
+        let mut groups: Vec<DupeGroup> = by_digest
+            .into_iter()
+            .filter(|(_, entries)| entries.len() > 1)
+            .map(|((_, digest), entries)| {
+                let size = entries[0].size;
+                let mut paths: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+                paths.sort();
+                DupeGroup {
+                    digest: digest.to_string(),
+                    size,
+                    wasted: size * (paths.len() as u64 - 1),
+                    paths,
+                }
+            })
+            .collect();
+        groups.sort_by(|a, b| b.wasted.cmp(&a.wasted).then_with(|| a.paths.cmp(&b.paths)));
+        groups
+    }
+
+    /// How `newer` differs from this baseline, matching entries by path and
+    /// leaving out those matching `ignore` on either side.
+    pub fn diff(&self, newer: &KdvManifest, ignore: &[glob::Pattern]) -> BaselineDiff {
+        let kept = |manifest: &KdvManifest| -> BTreeMap<String, ManifestEntry> {
+            let entries = manifest.entries.iter().filter(|entry| !matches_any(ignore, Path::new(&entry.path)));
+            entries.map(|entry| (entry.path.clone(), entry.clone())).collect()
+        };
+        let (old, new) = (kept(self), kept(newer));
+        let mut diff = BaselineDiff::default();
+        for (path, before) in &old {
+            let Some(after) = new.get(path) else {
+                diff.differences.push(Drift::Removed { path: path.clone() });
+                continue;
+            };
+            let path = path.clone();
+            if before.algorithm != after.algorithm {
+                diff.differences.push(Drift::AlgorithmChanged {
+                    path,
+                    old_algorithm: before.algorithm,
+                    new_algorithm: after.algorithm,
+                });
+            } else if before.size != after.size {
+                diff.differences.push(Drift::SizeChanged {
+                    path,
+                    old_size: before.size,
+                    new_size: after.size,
+                });
+            } else if before.digest != after.digest {
+                diff.differences.push(Drift::HashChanged {
+                    path,
+                    old_digest: before.digest.clone(),
+                    new_digest: after.digest.clone(),
+                });
+            } else {
+                diff.unchanged += 1;
+            }
+        }
+        for (path, entry) in new {
+            if !old.contains_key(&path) {
+                diff.differences.push(Drift::Added {
+                    path,
+                    size: entry.size,
+                    digest: entry.digest,
+                });
+            }
+        }
+        diff.differences.sort_by(|a, b| a.path().cmp(b.path()));
+        diff
+    }
+
+    /// Whether the manifest, or any entry, needs `--key-file` to be checked.
+    pub fn is_keyed(&self) -> bool {
+        self.keyed || self.entries.iter().any(|entry| entry.algorithm.is_keyed())
+    }
+
+    /// With a key, every entry must be keyed: a plain digest is one anyone
+    /// who changed the file could have written.
+    fn check_keyed(&self, hashing: &Hashing) -> Result<(), String> {
+        if hashing.key.is_none() && !self.keyed {
+            return Ok(());
+        }
+        match self.entries.iter().find(|entry| !entry.algorithm.is_keyed()) {
+            Some(entry) => Err(format!("entry {} is {}, not hmac-sha256", entry.path, entry.algorithm)),
+            None => Ok(()),
+        }
+    }
+
+    /// A baseline of the `(digest, path)` lines of a checksum file. Paths
+    /// are read from the current directory like `sha256sum -c` does and
+    /// recorded like `create` would, relative to `root` if given. Sizes
+    /// come from the files; those that cannot be read are listed with size
+    /// 0 and returned apart.
+    pub fn from_sums(
+        lines: &[(String, String)],
+        algorithm: HashAlgo,
+        root: Option<&Path>,
+    ) -> (Self, Vec<(PathBuf, io::Error)>) {
+        let root = root.map(absolute);
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for (digest, name) in lines {
+            let path = PathBuf::from(name);
+            let key = match manifest_key(&path, root.as_deref()) {
+                Ok(key) => key,
+                Err(e) => {
+                    errors.push((path, e));
+                    continue;
+                }
+            };
+            let size = match fs::metadata(&path) {
+                Ok(meta) => meta.len(),
+                Err(e) => {
+                    errors.push((path, e));
+                    0
+                }
+            };
+            entries.push(ManifestEntry {
+                path: key,
+                size,
+                algorithm,
+                digest: digest.clone(),
+                mtime_ns: None,
+                sections: Vec::new(),
+                metadata: None,
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries.dedup_by(|a, b| a.path == b.path);
+        let mut manifest = Self {
+            version: MANIFEST_VERSION,
+            created_at: timestamp(SystemTime::now()),
+            root: root.map(|root| root.to_string_lossy().into_owned()),
+            entries,
+            keyed: false,
+            updates: Vec::new(),
+            merkle_root: None,
+            signature: None,
+        };
+        manifest.merkle_root = Some(manifest.merkle_root());
+        (manifest, errors)
+    }
+
+    /// The baseline as a checksum file in `format`, sorted by path. Every
+    /// entry must have been made with the format's algorithm.
+    pub fn to_sums(&self, format: SumsFormat) -> Result<String, String> {
+        let mut entries: Vec<&ManifestEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut text = String::new();
+        for entry in entries {
+            if entry.algorithm != format.algorithm() {
+                return Err(format!(
+                    "Entry {} is {}; a {} file holds {} digests only",
+                    entry.path,
+                    entry.algorithm.as_str(),
+                    format.as_str(),
+                    format.algorithm().as_str()
+                ));
+            }
+            text.push_str(&kdv_sums::format_line(&entry.digest, &entry.path));
+        }
+        Ok(text)
+    }
+}
+
+/// The extended attribute `kdv seal` keeps a file's digest in. On Windows
+/// it names an NTFS alternate data stream instead.
+pub const SEAL_ATTRIBUTE: &str = "user.serialk.sha256";
+
+/// A digest stored with the file itself by `kdv seal`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Seal {
+    pub algorithm: HashAlgo,
+    pub digest: String,
+    pub sealed_at: String,
+}
+
+impl Seal {
+    /// The seal the file at `path` reads now. Keyed algorithms cannot seal:
+    /// the key would have to travel with the file.
+    pub fn of(path: &Path, algorithm: HashAlgo) -> io::Result<Self> {
+        Ok(Self {
+            algorithm,
+            digest: hex::encode(algorithm.hash_file(path, None)?),
+            sealed_at: timestamp(SystemTime::now()),
+        })
+    }
+
+    /// The seal stored with `path`, or None if it was never sealed. Fails
+    /// with `ErrorKind::Unsupported` where the filesystem cannot store one.
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        match seal_store::get(path)? {
+            Some(value) => serde_json::from_slice(&value)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid seal: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        seal_store::set(path, &serde_json::to_vec(self).map_err(io::Error::other)?)
+    }
+
+    /// Whether `path` still hashes to the sealed digest.
+    pub fn check(&self, path: &Path) -> FileStatus {
+        let expected = match hex::decode(&self.digest) {
+            Ok(expected) => expected,
+            Err(e) => return FileStatus::Error(format!("invalid seal digest: {}", e)),
+        };
+        let verified = fs::File::open(path).and_then(|mut file| self.algorithm.verify_reader(&mut file, None, &expected));
+        match verified {
+            Ok(true) => FileStatus::Verified,
+            Ok(false) => FileStatus::Changed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => FileStatus::Missing,
+            Err(e) => FileStatus::Error(e.to_string()),
+        }
+    }
+}
+
+/// Seals `path` with `algorithm`. Refuses a file that is already sealed
+/// unless `reseal` is set, so a seal is only ever refreshed on purpose.
+pub fn seal_file(path: &Path, algorithm: HashAlgo, reseal: bool) -> Result<Seal, String> {
+    let explain = |e: io::Error| match e.kind() {
+        io::ErrorKind::Unsupported => format!(
+            "{}: the filesystem does not support extended attributes (e.g. FAT, or tmpfs without them); use kdv init for a baseline file instead",
+            path.display()
+        ),
+        _ => format!("{}: {}", path.display(), e),
+    };
+    if !reseal && Seal::read(path).map_err(explain)?.is_some() {
+        return Err(format!("{} is already sealed; use kdv reseal to refresh the seal", path.display()));
+    }
+    let seal = Seal::of(path, algorithm).map_err(explain)?;
+    seal.write(path).map_err(explain)?;
+    Ok(seal)
+}
+
+/// Checks `path` against its seal.
+pub fn check_seal(path: &Path) -> FileStatus {
+    match Seal::read(path) {
+        Ok(Some(seal)) => seal.check(path),
+        Ok(None) => FileStatus::Error("not sealed; run kdv seal first".to_string()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => FileStatus::Missing,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => FileStatus::Error(
+            "the filesystem does not support extended attributes, so the file cannot carry a seal".to_string(),
+        ),
+        Err(e) => FileStatus::Error(e.to_string()),
+    }
+}
+
+/// Reads and writes the seal attribute.
+#[cfg(target_os = "linux")]
+mod seal_store {
+    use super::SEAL_ATTRIBUTE;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn error() -> io::Error {
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENOTSUP) => io::Error::new(io::ErrorKind::Unsupported, e),
+            _ => e,
+        }
+    }
+
+    pub fn get(path: &Path) -> io::Result<Option<Vec<u8>>> {
+        let (path, name) = (c_path(path)?, CString::new(SEAL_ATTRIBUTE).unwrap());
+        // A seal is a short JSON object; anything larger is not ours.
+        let mut value = vec![0u8; 4096];
+        let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if read < 0 {
+            return match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENODATA) => Ok(None),
+                _ => Err(error()),
+            };
+        }
+        value.truncate(read as usize);
+        Ok(Some(value))
+    }
+
+    pub fn set(path: &Path, value: &[u8]) -> io::Result<()> {
+        let (path, name) = (c_path(path)?, CString::new(SEAL_ATTRIBUTE).unwrap());
+        let set = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if set < 0 {
+            return Err(error());
+        }
+        Ok(())
+    }
+}
+
+/// Reads and writes the seal stream, `FILE:user.serialk.sha256`.
+#[cfg(windows)]
+mod seal_store {
+    use super::SEAL_ATTRIBUTE;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    fn stream(path: &Path) -> PathBuf {
+        let mut stream = OsString::from(path.as_os_str());
+        stream.push(":");
+        stream.push(SEAL_ATTRIBUTE);
+        PathBuf::from(stream)
+    }
+
+    pub fn get(path: &Path) -> io::Result<Option<Vec<u8>>> {
+        // The stream is missing for an unsealed file, so tell the two apart.
+        fs::metadata(path)?;
+        match fs::read(stream(path)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            // FAT and other non-NTFS volumes reject stream names.
+            Err(e) if e.raw_os_error() == Some(123) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set(path: &Path, value: &[u8]) -> io::Result<()> {
+        fs::metadata(path)?;
+        fs::write(stream(path), value).map_err(|e| match e.raw_os_error() {
+            Some(123) => io::Error::new(io::ErrorKind::Unsupported, e),
+            _ => e,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod seal_store {
+    use std::io;
+    use std::path::Path;
+
+    pub fn get(_path: &Path) -> io::Result<Option<Vec<u8>>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn set(_path: &Path, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// JSON with the keys of every object sorted and no whitespace. Manifests
+/// hold no floats, so each number prints as the integer it is.
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// Who runs this, for the record of a `kdv update`: the user behind sudo
+/// if there is one.
+fn operator() -> String {
+    ["SUDO_USER", "USER", "LOGNAME", "USERNAME"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok().filter(|name| !name.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn read_pself(path: &Path) -> Result<PselfRunner, String> {
+    let data = fs::read(path).map_err(|e| format!("Cannot read pself {}: {}", path.display(), e))?;
+    PselfRunner::new(data).map_err(|e| format!("Invalid pself {}: {}", path.display(), e))
+}
+
+fn mtime_ns(meta: &fs::Metadata) -> Option<u64> {
+    let since_epoch = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// `check_entry`, unless `tracker` saw the file verify with the same stat
+/// last time. Files that verify are recorded with the stat taken before
+/// hashing, so a write during the hash shows on the next run.
+fn track(
+    tracker: Option<&Tracker>,
+    entry: &ManifestEntry,
+    path: &Path,
+    hashing: &Hashing,
+    progress: &Progress,
+) -> FileStatus {
+    let Some(tracker) = tracker else {
+        return check_entry(entry, path, hashing, progress);
+    };
+    let Ok(meta) = fs::metadata(path) else {
+        return check_entry(entry, path, hashing, progress);
+    };
+    let observed = Observed::of(entry, &meta);
+    let record = |observed| tracker.seen.lock().expect("tracker poisoned").insert(entry.path.clone(), observed);
+    if tracker.known.get(&entry.path) == Some(&observed) {
+        record(observed);
+        return FileStatus::Trusted;
+    }
+    tracker.hashed.fetch_add(1, Ordering::Relaxed);
+    let status = check_entry(entry, path, hashing, progress);
+    if matches!(status, FileStatus::Verified | FileStatus::Touched) {
+        record(observed);
+    }
+    status
+}
+
+/// `status` for a file whose content passed, unless its owner or
+/// permissions moved off the entry's; the incremental state never sees
+/// those, so this runs after `track` either way.
+fn audit(entry: &ManifestEntry, path: &Path, status: FileStatus) -> FileStatus {
+    if !matches!(status, FileStatus::Verified | FileStatus::Trusted | FileStatus::Touched) {
+        return status;
+    }
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) => return FileStatus::Error(e.to_string()),
+    };
+    let violations = EntryMetadata::violations(entry.metadata.as_ref(), &EntryMetadata::of(&meta));
+    match violations.is_empty() {
+        true => status,
+        false => FileStatus::MetadataChanged(violations),
+    }
+}
+
+fn check_entry(entry: &ManifestEntry, path: &Path, hashing: &Hashing, progress: &Progress) -> FileStatus {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return FileStatus::Missing,
+        Err(e) => return FileStatus::Error(e.to_string()),
+    };
+    if meta.len() != entry.size {
+        return pin_change(entry, path, hashing);
+    }
+    // Without a recorded mtime there is nothing to trust, or to call touched.
+    let touched = entry.mtime_ns.is_some() && entry.mtime_ns != mtime_ns(&meta);
+    if hashing.quick && entry.mtime_ns.is_some() && !touched {
+        return FileStatus::Trusted;
+    }
+    let Ok(expected) = hex::decode(&entry.digest) else {
+        return FileStatus::Error("digest in the baseline is not hex".to_string());
+    };
+    let key = hashing.key.as_deref();
+    if key.is_some() && !entry.algorithm.is_keyed() {
+        return FileStatus::Error(format!("{} entry in a keyed check; expected hmac-sha256", entry.algorithm));
+    }
+    // Each entry with its own algorithm, so a baseline can mix them.
+    let verified = fs::File::open(path)
+        .and_then(|file| entry.algorithm.verify_reader(&mut progress.counting(file), key, &expected));
+    match verified {
+        Ok(true) if touched => FileStatus::Touched,
+        Ok(true) => FileStatus::Verified,
+        Ok(false) => pin_change(entry, path, hashing),
+        Err(e) => FileStatus::Error(e.to_string()),
+    }
+}
+
+/// A changed file, pinned down to its sections if it was fingerprinted by
+/// section and still parses as an executable.
+fn pin_change(entry: &ManifestEntry, path: &Path, hashing: &Hashing) -> FileStatus {
+    if entry.sections.is_empty() {
+        return FileStatus::Changed;
+    }
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => return FileStatus::Error(e.to_string()),
+    };
+    let Some(current) = section_fingerprints(&data, entry.algorithm, hashing.key.as_deref()) else {
+        return FileStatus::Changed;
+    };
+    let mut changes = SectionChanges {
+        changed: Vec::new(),
+        intact: Vec::new(),
+    };
+    for recorded in &entry.sections {
+        match current.iter().find(|now| now.section_name == recorded.section_name) {
+            Some(now) if now.hash == recorded.hash => changes.intact.push(recorded.section_name.clone()),
+            _ => changes.changed.push(recorded.section_name.clone()),
+        }
+    }
+    let added = current.iter().filter(|now| entry.sections.iter().all(|recorded| recorded.section_name != now.section_name));
+    changes.changed.extend(added.map(|now| now.section_name.clone()));
+    FileStatus::SectionsChanged(changes)
+}
+
+/// `serialkiller kdv init`: fingerprints `paths` into `baseline`. Returns
+/// the exit code.
+pub fn run_kdv_init(
+    baseline: &Path,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    algo: HashAlgo,
+    hashing: &Hashing,
+    sign_key: Option<&SigningKey>,
+) -> i32 {
+    let (mut manifest, errors) = KdvManifest::create(paths, walk, root, algo, hashing);
+    if let Some(key) = sign_key {
+        manifest.sign(key);
+    }
+    if let Err(e) = manifest.save(baseline) {
+        eprintln!("[ERROR] Cannot write baseline {}: {}", baseline.display(), e);
+        return 1;
+    }
+    println!("[KDV] {} files fingerprinted into {}", manifest.entries.len(), baseline.display());
+    println!("[KDV] {}", hashing.throughput);
+    println!("[KDV] Merkle root: {}", manifest.merkle_root());
+    // Collected while walking, reported once the scan is done.
+    for (path, e) in &errors {
+        eprintln!("[ERROR] {}: {}", path.display(), e);
+    }
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// `serialkiller kdv check`: compares files against `baseline` and prints
+/// what differs and a summary, or with `json` every result as one JSON
+/// document. Returns the summary's exit code, or 1 if nothing was checked.
+#[allow(clippy::too_many_arguments)]
+pub fn run_kdv_check(
+    baseline: &Path,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    hashing: &Hashing,
+    trust: &BaselineTrust,
+    options: &VerifyOptions,
+    json: bool,
+) -> i32 {
+    let manifest = match load_for_check(baseline, hashing, trust) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    check_against(&manifest, paths, walk, root, hashing, options, json)
+}
+
+/// `serialkiller kdv check --embedded`: like `run_kdv_check` against the
+/// baseline compiled into this binary, whose SHA-256 is printed first so
+/// the run can be tied to the build that made it.
+pub fn run_kdv_check_embedded(
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    hashing: &Hashing,
+    options: &VerifyOptions,
+    json: bool,
+) -> i32 {
+    let manifest = match KdvManifest::from_embedded(EMBEDDED_BASELINE) {
+        Ok(manifest) if manifest.is_keyed() && hashing.key.is_none() => {
+            eprintln!("[ERROR] The embedded baseline has hmac-sha256 entries; pass --key-file");
+            return 1;
+        }
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let attestation = format!("[KDV] Embedded baseline sha256: {}", hex::encode(Sha256::digest(EMBEDDED_BASELINE)));
+    // Kept off stdout when that is one JSON document.
+    match json {
+        true => eprintln!("{}", attestation),
+        false => println!("{}", attestation),
+    }
+    check_against(&manifest, paths, walk, root, hashing, options, json)
+}
+
+/// The shared end of `kdv check`: checks against `manifest` through
+/// `KdvManifest::verify`, or by stat with `--quick` and `--incremental`,
+/// and reports.
+fn check_against(
+    manifest: &KdvManifest,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    hashing: &Hashing,
+    options: &VerifyOptions,
+    json: bool,
+) -> i32 {
+    let root_hash = manifest.merkle_root();
+    let Some(incremental) = &hashing.incremental else {
+        if hashing.quick {
+            let results = manifest.check(paths, walk, root, hashing);
+            return report_check(&results, None, json, Some(&root_hash), Some(&hashing.throughput));
+        }
+        return match manifest.verify(paths, walk, root, hashing, options) {
+            Ok((results, report)) => report_check(&results, Some(&report), json, Some(&root_hash), Some(&hashing.throughput)),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                1
+            }
+        };
+    };
+    let (results, state, hashed) = match incremental.check(manifest, paths, walk, root, hashing) {
+        Ok(run) => run,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let code = report_check(&results, None, json, Some(&root_hash), Some(&hashing.throughput));
+    if !json {
+        println!("[KDV] Rehashed {} of {} files; state in {}", hashed, results.len(), incremental.state_file.display());
+    }
+    if let Err(e) = state.save(&incremental.state_file) {
+        eprintln!("[ERROR] Cannot write check state {}: {}", incremental.state_file.display(), e);
+        return 1;
+    }
+    code
+}
+
+/// `serialkiller kdv check --xattr`: checks each of `paths` against its
+/// seal, reporting and returning like `run_kdv_check`.
+pub fn run_kdv_check_seals(paths: &[PathBuf], json: bool) -> i32 {
+    let results: Vec<(String, FileStatus)> =
+        paths.iter().map(|path| (path.display().to_string(), check_seal(path))).collect();
+    report_check(&results, None, json, None, None)
+}
+
+/// `serialkiller kdv seal` and `reseal`: stores each file's digest with the
+/// file. Returns 0, or 1 if any file could not be sealed.
+pub fn run_kdv_seal(paths: &[PathBuf], algorithm: HashAlgo, reseal: bool) -> i32 {
+    let mut code = 0;
+    for path in paths {
+        match seal_file(path, algorithm, reseal) {
+            Ok(seal) => println!("[SEALED] {} {}:{}", path.display(), seal.algorithm, seal.digest),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Prints `results` and their summary, with the fail-fast and unknown-name
+/// outcome of `report` if they went through `verify_all`, and returns the
+/// exit code.
+fn report_check(
+    results: &[(String, FileStatus)],
+    report: Option<&VerifyReport>,
+    json: bool,
+    merkle_root: Option<&str>,
+    throughput: Option<&Throughput>,
+) -> i32 {
+    let mut summary = CheckSummary::of(results);
+    if let Some(report) = report {
+        summary.skipped = report.skipped;
+        summary.unknown_allowed = report.unknown_allowed;
+    }
+    if json {
+        let files: Vec<FileReport> = results.iter().map(|(path, status)| FileReport { path, status }).collect();
+        let mut report = serde_json::json!({ "summary": summary, "files": files });
+        if let Some(root) = merkle_root {
+            report["merkle_root"] = root.into();
+        }
+        if let Some(throughput) = throughput {
+            report["throughput"] = throughput.to_json();
+        }
+        println!("{}", serde_json::to_string_pretty(&report).expect("results serialize"));
+        return summary.exit_code();
+    }
+    for (path, status) in results {
+        match status {
+            FileStatus::Verified | FileStatus::Trusted => {}
+            FileStatus::Error(e) => println!("[ERROR] {}: {}", path, e),
+            FileStatus::SectionsChanged(changes) => println!("[CHANGED] {}: {}", path, changes),
+            FileStatus::MetadataChanged(violations) => {
+                let violations: Vec<String> = violations.iter().map(|violation| violation.to_string()).collect();
+                println!("[METADATA] {}: {}", path, violations.join(", "));
+            }
+            status => println!("[{}] {}", status.as_str(), path),
+        }
+    }
+    println!("[KDV] {}", summary);
+    if let Some(throughput) = throughput {
+        println!("[KDV] {}", throughput);
+    }
+    if let Some(root) = merkle_root {
+        println!("[KDV] Baseline Merkle root: {}", root);
+    }
+    summary.exit_code()
+}
+
+/// `serialkiller kdv update`: checks the files like `run_kdv_check`, lists
+/// the changed, added and missing ones (only those matching `only`, if
+/// given) and, once confirmed on stdin or with `yes`, rewrites `baseline`
+/// to trust them, keeping the old one as `baseline.bak.<time>`. Returns 0
+/// when updated or up to date, 2 when the update was declined, and 1 on
+/// errors.
+#[allow(clippy::too_many_arguments)]
+pub fn run_kdv_update(
+    baseline: &Path,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    hashing: &Hashing,
+    only: &[glob::Pattern],
+    yes: bool,
+    sign_key: Option<&SigningKey>,
+) -> i32 {
+    // Updating would rewrite a checksum file as JSON.
+    let loaded = match read_sums(baseline) {
+        Ok(Some(_)) => Err(format!("{} is a checksum file; kdv import it to update it as a baseline", baseline.display())),
+        _ => load_for_check(baseline, hashing, &BaselineTrust::default()),
+    };
+    let manifest = match loaded {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let results = manifest.check(paths, walk, root, hashing);
+    let (mut changes, mut left) = (Vec::new(), 0);
+    for (name, status) in results {
+        match status {
+            FileStatus::Changed
+            | FileStatus::SectionsChanged(_)
+            | FileStatus::MetadataChanged(_)
+            | FileStatus::Added
+            | FileStatus::Missing => {}
+            FileStatus::Error(e) => {
+                eprintln!("[ERROR] {}: {}", name, e);
+                continue;
+            }
+            _ => continue,
+        }
+        if !only.is_empty() && !matches_any(only, Path::new(&name)) {
+            left += 1;
+            continue;
+        }
+        let label = match status {
+            FileStatus::Missing => "REMOVE",
+            FileStatus::Added => "ADD",
+            _ => "RETRUST",
+        };
+        println!("[{}] {}", label, name);
+        changes.push((name, status));
+    }
+    if left > 0 {
+        println!("[KDV] {} other changes do not match --only and stay untrusted", left);
+    }
+    if changes.is_empty() {
+        println!("[KDV] Nothing to update in {}", baseline.display());
+        return 0;
+    }
+    if !yes {
+        print!("Update {} entries in {}? [y/N] ", changes.len(), baseline.display());
+        let _ = io::Write::flush(&mut io::stdout());
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("[KDV] Baseline left unchanged");
+            return 2;
+        }
+    }
+    let (mut updated, errors) = manifest.update(&changes, root, hashing);
+    match sign_key {
+        Some(key) => updated.sign(key),
+        None if manifest.signature.is_some() => {
+            eprintln!("[WARN] {} was signed; the updated baseline is not (pass --sign-key)", baseline.display())
+        }
+        None => {}
+    }
+    let mut backup = baseline.as_os_str().to_owned();
+    backup.push(format!(".bak.{}", timestamp(SystemTime::now()).replace(':', "")));
+    if let Err(e) = fs::copy(baseline, &backup) {
+        eprintln!("[ERROR] Cannot back up {} to {}: {}", baseline.display(), Path::new(&backup).display(), e);
+        return 1;
+    }
+    if let Err(e) = updated.save(baseline) {
+        eprintln!("[ERROR] Cannot write baseline {}: {}", baseline.display(), e);
+        return 1;
+    }
+    let record = updated.updates.last().expect("update recorded");
+    println!(
+        "[KDV] Updated {} ({} changed, {} added, {} removed); previous baseline kept as {}",
+        baseline.display(),
+        record.changed,
+        record.added,
+        record.removed,
+        Path::new(&backup).display()
+    );
+    for (path, e) in &errors {
+        eprintln!("[ERROR] {}: {}", path.display(), e);
+    }
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// `serialkiller kdv check --watch`: checks the files every
+/// `options.interval` until `shutdown` is set, alerting only on files whose
+/// health changed since the round before. Prints a summary on the way out
+/// and returns the exit code of the last round, or 1 if it cannot start.
+#[allow(clippy::too_many_arguments)]
+pub fn run_kdv_watch(
+    baseline: &Path,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    root: Option<&Path>,
+    hashing: &Hashing,
+    trust: &BaselineTrust,
+    options: &WatchOptions,
+    shutdown: &AtomicBool,
+) -> i32 {
+    let loaded = load_for_check(baseline, hashing, trust).and_then(|manifest| {
+        let state = match &options.state_file {
+            Some(path) => WatchState::load(path)?,
+            None => WatchState::default(),
+        };
+        Ok((manifest, state))
+    });
+    let (manifest, mut state) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let (mut rounds, mut alerts, mut webhook_jobs) = (0, 0, Vec::new());
+    let mut last = CheckSummary::default();
+    while !shutdown.load(Ordering::SeqCst) {
+        let results = manifest.check(paths, walk, root, hashing);
+        last = CheckSummary::of(&results);
+        rounds += 1;
+        for transition in state.advance(&results) {
+            alerts += 1;
+            let line = serde_json::json!({
+                "event": "kdv",
+                "path": transition.path,
+                "from": transition.from,
+                "to": transition.to,
+                "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            });
+            if options.json {
+                println!("{}", line);
+            } else {
+                println!("[{}] {} (was {})", transition.to, transition.path, transition.from);
+            }
+            for url in &options.webhooks {
+                webhook_jobs.push(serialk_webhook::spawn_post(url.clone(), line.to_string()));
+            }
+        }
+        webhook_jobs.retain(|job| !job.is_finished());
+        if let Some(path) = &options.state_file {
+            if let Err(e) = state.save(path) {
+                eprintln!("[WARN] Cannot write watch state {}: {}", path.display(), e);
+            }
+        }
+        let next = Instant::now() + options.interval;
+        while !shutdown.load(Ordering::SeqCst) && Instant::now() < next {
+            std::thread::sleep(Duration::from_millis(100).min(next - Instant::now()));
+        }
+    }
+    for job in webhook_jobs {
+        let _ = job.join();
+    }
+    println!("[KDV] Stopped after {} rounds and {} alerts; last round: {}", rounds, alerts, last);
+    last.exit_code()
+}
+
+/// The baseline at `path`, if `hashing` can check it and its signature
+/// satisfies `trust`.
+pub fn load_for_check(path: &Path, hashing: &Hashing, trust: &BaselineTrust) -> Result<KdvManifest, String> {
+    let manifest = match read_sums(path)? {
+        Some(_) if hashing.key.is_some() => {
+            return Err(format!("Baseline {} is a checksum file, which holds no keyed digests; refusing it with --key-file", path.display()));
+        }
+        Some(lines) => KdvManifest::from_sums(&lines, SumsFormat::guess(&lines).algorithm(), None).0,
+        None => KdvManifest::load(path)?,
+    };
+    if manifest.is_keyed() && hashing.key.is_none() {
+        return Err(format!("Baseline {} has hmac-sha256 entries; pass --key-file", path.display()));
+    }
+    if let Err(e) = manifest.check_keyed(hashing) {
+        return Err(format!("Baseline {} is keyed but its {}; refusing to check against it", path.display(), e));
+    }
+    if let Some(key) = &trust.public_key {
+        if let Err(e) = manifest.verify_signature(key) {
+            if !trust.insecure {
+                return Err(format!("Baseline {} {}; refusing to check against it", path.display(), e));
+            }
+            eprintln!("[WARN] Baseline {} {}; checking anyway (--insecure)", path.display(), e);
+        }
+    }
+    Ok(manifest)
+}
+
+/// The lines of the checksum file at `path`, or `None` if it holds a JSON
+/// baseline instead.
+fn read_sums(path: &Path) -> Result<Option<Vec<(String, String)>>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read baseline {}: {}", path.display(), e))?;
+    if !kdv_sums::is_sums(&text) {
+        return Ok(None);
+    }
+    kdv_sums::parse(&text).map(Some).map_err(|e| format!("Invalid checksum file {}: {}", path.display(), e))
+}
+
+/// `serialkiller kdv --pself`: verifies each section of `pself` and reports
+/// those that do not match, optionally exporting the recorded hashes to
+/// `export` as a baseline, or with `json` prints the whole report as JSON.
+/// Returns 0 when every section matched, 2 when one did not or lies outside
+/// the container, and 1 if it cannot be read.
+pub fn run_kdv_pself(pself: &Path, export: Option<&Path>, options: &VerifyOptions, json: bool) -> i32 {
+    let report = match KdvVerifier::verify_pself(pself, options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    if let Some(export) = export {
+        let saved = KdvManifest::from_pself(pself).and_then(|manifest| {
+            manifest
+                .save(export)
+                .map(|()| manifest.entries.len())
+                .map_err(|e| format!("Cannot write baseline {}: {}", export.display(), e))
+        });
+        match saved {
+            Ok(count) if json => eprintln!("[KDV] {} section hashes exported into {}", count, export.display()),
+            Ok(count) => println!("[KDV] {} section hashes exported into {}", count, export.display()),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                return 1;
+            }
+        }
+    }
+    let code = if report.is_clean() { 0 } else { 2 };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).expect("report serializes"));
+        return code;
+    }
+    for (name, result) in &report.results {
+        match result {
+            VerifyResult::Verified => {}
+            VerifyResult::Mismatch { .. } => println!("[MISMATCH] {}", name),
+            VerifyResult::Unknown => println!("[OUT OF RANGE] {}", name),
+            VerifyResult::Missing => println!("[MISSING] {}", name),
+            VerifyResult::Unreadable(e) => println!("[ERROR] {}: {}", name, e),
+        }
+    }
+    println!(
+        "[KDV] {} sections verified, {} mismatched, {} out of range",
+        report.verified(),
+        report.mismatched(),
+        report.unknown()
+    );
+    if report.skipped > 0 {
+        println!("[KDV] Stopped at the first failure; {} sections not checked", report.skipped);
+    }
+    code
+}
+
+/// `serialkiller kdv diff`: prints how baseline `new` differs from `old`,
+/// or with `json` the same as one JSON document. Returns 0 without
+/// differences, 2 with, and 1 if either baseline cannot be read.
+pub fn run_kdv_diff(old: &Path, new: &Path, ignore: &[glob::Pattern], json: bool) -> i32 {
+    let (old, new) = match (KdvManifest::load(old), KdvManifest::load(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let diff = old.diff(&new, ignore);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff).expect("differences serialize"));
+    } else {
+        for drift in &diff.differences {
+            println!("{}", drift);
+        }
+        let count = |change: fn(&Drift) -> bool| diff.differences.iter().filter(|drift| change(drift)).count();
+        println!(
+            "[KDV] {} unchanged, {} added, {} removed, {} hash changed, {} size changed, {} algorithm changed",
+            diff.unchanged,
+            count(|drift| matches!(drift, Drift::Added { .. })),
+            count(|drift| matches!(drift, Drift::Removed { .. })),
+            count(|drift| matches!(drift, Drift::HashChanged { .. })),
+            count(|drift| matches!(drift, Drift::SizeChanged { .. })),
+            count(|drift| matches!(drift, Drift::AlgorithmChanged { .. }))
+        );
+    }
+    if diff.differences.is_empty() {
+        0
+    } else {
+        2
+    }
+}
+
+/// `serialkiller kdv dupes`: hashes the files of at least `min_size` bytes
+/// under `paths` and prints the groups with the same content, most wasted
+/// bytes first, or with `json` the same as one JSON document. Returns 0,
+/// or 1 if some files could not be read.
+pub fn run_kdv_dupes(paths: &[PathBuf], walk: &WalkOptions, hashing: &Hashing, min_size: u64, json: bool) -> i32 {
+    let (files, mut errors) = collect_files(paths, walk);
+    // Sized up before hashing, so small files cost a stat and no read.
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|file| fs::metadata(file).map_or(true, |meta| meta.len() >= min_size))
+        .collect();
+    let (manifest, failed) = KdvManifest::create(&files, walk, None, HashAlgo::Sha256, hashing);
+    errors.extend(failed);
+    let groups = manifest.duplicates(min_size);
+    let wasted: u64 = groups.iter().map(|group| group.wasted).sum();
+    if json {
+        let report = serde_json::json!({ "groups": groups, "wasted": wasted });
+        println!("{}", serde_json::to_string_pretty(&report).expect("groups serialize"));
+    } else {
+        for group in &groups {
+            println!("[DUPES] {} copies of {} bytes, {} wasted:", group.paths.len(), group.size, group.wasted);
+            for path in &group.paths {
+                println!("  {}", path);
+            }
+        }
+        println!(
+            "[KDV] {} files hashed, {} sets of duplicates, {} bytes wasted",
+            manifest.entries.len(),
+            groups.len(),
+            wasted
+        );
+    }
+    for (path, e) in &errors {
+        eprintln!("[ERROR] {}: {}", path.display(), e);
+    }
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// `serialkiller kdv import`: turns the checksum file `sums` into the
+/// baseline at `baseline`, or merges it into the one there, replacing
+/// entries with the same path. Every listed file must exist, for its size.
+/// Returns 0, or 1 without writing anything on errors.
+pub fn run_kdv_import(sums: &Path, format: SumsFormat, baseline: &Path, root: Option<&Path>) -> i32 {
+    let imported = read_sums(sums).and_then(|lines| {
+        lines.ok_or_else(|| format!("{} is a JSON baseline, not a {} file", sums.display(), format.as_str()))
+    });
+    let existing = match baseline.exists() {
+        true => KdvManifest::load(baseline).map(Some),
+        false => Ok(None),
+    };
+    let (lines, existing) = match (imported, existing) {
+        (Ok(lines), Ok(existing)) => (lines, existing),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    if existing.as_ref().is_some_and(KdvManifest::is_keyed) {
+        eprintln!(
+            "[ERROR] Baseline {} holds hmac-sha256 entries; unkeyed {} digests cannot be imported into it",
+            baseline.display(),
+            format.as_str()
+        );
+        return 1;
+    }
+    let root = root.map(Path::to_path_buf).or_else(|| existing.as_ref()?.root.as_ref().map(PathBuf::from));
+    let (imported, errors) = KdvManifest::from_sums(&lines, format.algorithm(), root.as_deref());
+    if !errors.is_empty() {
+        for (path, e) in &errors {
+            eprintln!("[ERROR] {}: {}", path.display(), e);
+        }
+        eprintln!("[ERROR] Nothing imported; every file {} lists must be readable", sums.display());
+        return 1;
+    }
+    let count = imported.entries.len();
+    let manifest = match existing {
+        Some(mut manifest) => {
+            if manifest.signature.take().is_some() {
+                eprintln!("[WARN] {} was signed; after the import it is not", baseline.display());
+            }
+            manifest.entries.retain(|entry| !imported.entries.iter().any(|new| new.path == entry.path));
+            manifest.entries.extend(imported.entries);
+            manifest.entries.sort_by(|a, b| a.path.cmp(&b.path));
+            manifest
+        }
+        None => imported,
+    };
+    if let Err(e) = manifest.save(baseline) {
+        eprintln!("[ERROR] Cannot write baseline {}: {}", baseline.display(), e);
+        return 1;
+    }
+    println!("[KDV] {} entries imported from {} into {}", count, sums.display(), baseline.display());
+    0
+}
+
+/// `serialkiller kdv export`: prints `baseline` as a checksum file in
+/// `format`. Returns 0, or 1 if it cannot be read or holds digests of
+/// another algorithm.
+pub fn run_kdv_export(baseline: &Path, format: SumsFormat) -> i32 {
+    match KdvManifest::load(baseline).and_then(|manifest| manifest.to_sums(format)) {
+        Ok(text) => {
+            print!("{}", text);
+            0
+        }
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            1
+        }
+    }
+}
+
+/// `serialkiller kdv keygen`: writes a new signing key to `path` for
+/// `kdv init --sign-key`, and its public half to `<path>.pub` for
+/// `kdv check --pubkey`. Returns 0, or 1 if it cannot be written.
+pub fn run_kdv_keygen(path: &Path) -> i32 {
+    match serialk_baseline::generate_signing_key(path) {
+        Ok(_) => {
+            let public = serialk_baseline::public_key_path(path);
+            println!("[KDV] Wrote signing key {} and public key {}", path.display(), public.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            1
+        }
+    }
+}
+
+/// `serialkiller kdv prove`: prints as JSON the proof that `path` is in
+/// `baseline`, found by its entry path or, failing that, as a file under
+/// `root` (the manifest's own by default). Returns 0, or 1 if the baseline
+/// cannot be read or has no such entry.
+pub fn run_kdv_prove(baseline: &Path, path: &str, root: Option<&Path>) -> i32 {
+    let manifest = match KdvManifest::load(baseline) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let root = root.map(absolute).or_else(|| manifest.root.as_ref().map(PathBuf::from));
+    let proof = manifest
+        .prove(path)
+        .or_else(|| manifest.prove(&manifest_key(Path::new(path), root.as_deref()).ok()?));
+    match proof {
+        Some(proof) => {
+            println!("{}", serde_json::to_string_pretty(&proof).expect("proof serializes"));
+            0
+        }
+        None => {
+            eprintln!("[ERROR] {} has no entry for {}", baseline.display(), path);
+            1
+        }
+    }
+}
+
+/// `serialkiller kdv verify-proof`: checks the proof in `proof` against
+/// the hex Merkle root `root`. Returns 0 if it holds, 2 if not, and 1 if
+/// either cannot be read.
+pub fn run_kdv_verify_proof(proof: &Path, root: &str) -> i32 {
+    let root = match kdv_merkle::parse_root(root) {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    let proof: MerkleProof = match fs::read_to_string(proof)
+        .map_err(|e| format!("Cannot read proof {}: {}", proof.display(), e))
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| format!("Invalid proof {}: {}", proof.display(), e)))
+    {
+        Ok(proof) => proof,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return 1;
+        }
+    };
+    if proof.verify(&root) {
+        println!("[VERIFIED] {} {}", proof.path, proof.digest);
+        0
+    } else {
+        println!("[MISMATCH] {} is not under Merkle root {}", proof.path, hex::encode(root));
+        2
+    }
+}
+
+/// Writes `contents` to `path`, which is replaced if it exists, readable
+/// by its owner only. Callers rename it into place afterwards.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let _ = fs::remove_file(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_round_trip_and_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept.conf");
+        let edited = dir.path().join("edited.conf");
+        let removed = dir.path().join("removed.conf");
+        for file in [&kept, &edited, &removed] {
+            fs::write(file, "setting = 1\n").unwrap();
+        }
+        let files = [kept.clone(), edited.clone(), removed.clone()];
+        let (manifest, errors) = KdvManifest::create(&files, &WalkOptions::default(), None, HashAlgo::Sha256, &Hashing::default());
+        assert!(errors.is_empty());
+        assert_eq!(manifest.entries.len(), 3);
+        assert_eq!(manifest.entries[0].digest, hex::encode(KdvVerifier::compute_hash(b"setting = 1\n")));
+
+        let baseline = dir.path().join("baseline.json");
+        manifest.save(&baseline).unwrap();
+        let loaded = KdvManifest::load(&baseline).unwrap();
+        assert_eq!(loaded, manifest);
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&baseline).unwrap().permissions()) & 0o777, 0o600);
+
+        // Same size, different content: only the digest tells.
+        fs::write(&edited, "setting = 2\n").unwrap();
+        fs::remove_file(&removed).unwrap();
+        let added = dir.path().join("added.conf");
+        fs::write(&added, "new\n").unwrap();
+        let walk = WalkOptions::default();
+        let statuses: Vec<FileStatus> = loaded.check(&[], &walk, None, &Hashing::default()).into_iter().map(|(_, status)| status).collect();
+        assert_eq!(statuses, [FileStatus::Changed, FileStatus::Verified, FileStatus::Missing]);
+        let statuses: Vec<FileStatus> = loaded.check(&[kept, added], &walk, None, &Hashing::default()).into_iter().map(|(_, status)| status).collect();
+        assert_eq!(statuses, [FileStatus::Verified, FileStatus::Added]);
+    }
+
+    #[test]
+    fn test_tree_walk_with_excludes_and_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in ["etc/app.conf", "etc/app.conf.swp", "etc/cache/blob", "etc/ssl/certs/ca.pem", "etc/ssl/key.pem"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::net::UnixListener::bind(root.join("etc/app.sock")).unwrap();
+            std::os::unix::fs::symlink("app.conf", root.join("etc/link.conf")).unwrap();
+        }
+        let walk = WalkOptions {
+            excludes: vec![glob::Pattern::new("*.swp").unwrap(), glob::Pattern::new("cache").unwrap()],
+            ..Default::default()
+        };
+        let (manifest, errors) = KdvManifest::create(&[root.join("etc")], &walk, Some(root), HashAlgo::Sha256, &Hashing::default());
+        assert!(errors.is_empty(), "{:?}", errors);
+        let paths: Vec<&str> = manifest.entries.iter().map(|entry| entry.path.as_str()).collect();
+        let mut expected = vec!["etc/app.conf", "etc/ssl/certs/ca.pem", "etc/ssl/key.pem"];
+        if cfg!(unix) {
+            expected.insert(1, "etc/link.conf");
+        }
+        assert_eq!(paths, expected);
+
+        let shallow = WalkOptions {
+            max_depth: Some(1),
+            skip_symlinks: true,
+            ..walk.clone()
+        };
+        let (files, _) = collect_files(&[root.join("etc")], &shallow);
+        let names: Vec<String> = files.iter().map(|f| f.strip_prefix(root).unwrap().display().to_string()).collect();
+        assert_eq!(names, ["etc/app.conf", "etc/ssl/key.pem"]);
+
+        // The same tree under another mount point checks clean with --root.
+        let moved = tempfile::tempdir().unwrap();
+        fs::rename(root.join("etc"), moved.path().join("etc")).unwrap();
+        let relocated = manifest.check(&[], &walk, Some(moved.path()), &Hashing::default());
+        assert!(relocated.iter().all(|(_, status)| *status == FileStatus::Verified), "{:?}", relocated);
+
+        // A directory check finds what was added and what went away.
+        fs::write(moved.path().join("etc/ssl/new.pem"), "new").unwrap();
+        fs::remove_file(moved.path().join("etc/ssl/key.pem")).unwrap();
+        let results = manifest.check(&[moved.path().join("etc/ssl")], &walk, Some(moved.path()), &Hashing::default());
+        assert_eq!(
+            results,
+            [
+                ("etc/ssl/certs/ca.pem".to_string(), FileStatus::Verified),
+                ("etc/ssl/new.pem".to_string(), FileStatus::Added),
+                ("etc/ssl/key.pem".to_string(), FileStatus::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest_version_is_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let baseline = dir.path().join("baseline.json");
+        fs::write(&baseline, r#"{"version": 99, "created_at": "2026-01-01T00:00:00Z", "entries": []}"#).unwrap();
+        let error = KdvManifest::load(&baseline).unwrap_err();
+        assert!(error.contains("format version 99"), "{}", error);
+    }
+
+    #[test]
+    fn test_each_algorithm_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("image.bin");
+        // Over one BLAKE3 chunk, so its tree hashing is exercised too.
+        fs::write(&file, vec![0x5a; 5000]).unwrap();
+        let baseline = dir.path().join("baseline.json");
+        let walk = WalkOptions::default();
+        for (algo, digest_len) in [(HashAlgo::Sha256, 32), (HashAlgo::Sha512, 64), (HashAlgo::Blake3, 32)] {
+            let (manifest, _) = KdvManifest::create(std::slice::from_ref(&file), &walk, None, algo, &Hashing::default());
+            manifest.save(&baseline).unwrap();
+            let loaded = KdvManifest::load(&baseline).unwrap();
+            assert_eq!(loaded.entries[0].algorithm, algo);
+            assert_eq!(loaded.entries[0].digest.len(), digest_len * 2);
+            assert_eq!(loaded.check(&[], &walk, None, &Hashing::default())[0].1, FileStatus::Verified, "{}", algo);
+        }
+        assert_eq!(
+            hex::encode(HashAlgo::Blake3.hash(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!("blake3".parse::<HashAlgo>(), Ok(HashAlgo::Blake3));
+        assert!("md5".parse::<HashAlgo>().is_err());
+    }
+
+    #[test]
+    fn test_mixed_algorithms_and_unknown_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.conf");
+        let new = dir.path().join("new.conf");
+        fs::write(&old, "old").unwrap();
+        fs::write(&new, "new").unwrap();
+        let walk = WalkOptions::default();
+        let (mut manifest, _) = KdvManifest::create(std::slice::from_ref(&old), &walk, None, HashAlgo::Sha256, &Hashing::default());
+        let (added, _) = KdvManifest::create(std::slice::from_ref(&new), &walk, None, HashAlgo::Blake3, &Hashing::default());
+        manifest.entries.extend(added.entries);
+        let statuses: Vec<FileStatus> = manifest.check(&[], &walk, None, &Hashing::default()).into_iter().map(|(_, status)| status).collect();
+        assert_eq!(statuses, [FileStatus::Verified, FileStatus::Verified]);
+        fs::write(&new, "NEW").unwrap();
+        assert_eq!(manifest.check(&[new], &walk, None, &Hashing::default())[0].1, FileStatus::Changed);
+
+        let baseline = dir.path().join("baseline.json");
+        manifest.save(&baseline).unwrap();
+        let text = fs::read_to_string(&baseline).unwrap().replace("\"blake3\"", "\"md5\"");
+        fs::write(&baseline, text).unwrap();
+        let error = KdvManifest::load(&baseline).unwrap_err();
+        assert!(error.contains("unknown variant `md5`"), "{}", error);
+    }
+
+    #[test]
+    fn test_keyed_baseline_needs_the_right_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [dir.path().join("a.conf"), dir.path().join("b.conf")];
+        for file in &files {
+            fs::write(file, "setting = 1\n").unwrap();
+        }
+        let walk = WalkOptions::default();
+        let keyed = |key: &[u8]| Hashing {
+            key: Some(key.to_vec()),
+            ..Default::default()
+        };
+        let (manifest, _) = KdvManifest::create(&files, &walk, None, HashAlgo::Blake3, &keyed(b"right key"));
+        assert!(manifest.is_keyed());
+        assert!(manifest.entries.iter().all(|entry| entry.algorithm == HashAlgo::HmacSha256));
+        assert_ne!(manifest.entries[0].digest, hex::encode(KdvVerifier::compute_hash(b"setting = 1\n")));
+
+        let statuses = |hashing: &Hashing| -> Vec<FileStatus> {
+            manifest.check(&[], &walk, None, hashing).into_iter().map(|(_, status)| status).collect()
+        };
+        assert_eq!(statuses(&keyed(b"right key")), [FileStatus::Verified, FileStatus::Verified]);
+        assert_eq!(statuses(&keyed(b"wrong key")), [FileStatus::Changed, FileStatus::Changed]);
+        let unkeyed = statuses(&Hashing::default());
+        assert!(matches!(&unkeyed[0], FileStatus::Error(e) if e.contains("--key-file")));
+
+        // Whoever changed a.conf rewrites its entry as a plain digest.
+        fs::write(&files[0], "setting = 2\n").unwrap();
+        let mut downgraded = manifest.clone();
+        downgraded.entries[0].algorithm = HashAlgo::Sha256;
+        downgraded.entries[0].digest = hex::encode(HashAlgo::Sha256.hash(b"setting = 2\n"));
+        let baseline = dir.path().join("baseline.json");
+        downgraded.save(&baseline).unwrap();
+        let err = load_for_check(&baseline, &keyed(b"right key"), &BaselineTrust::default()).unwrap_err();
+        assert!(err.contains("a.conf is sha256, not hmac-sha256"), "{}", err);
+        let checked = downgraded.check(&[], &walk, None, &keyed(b"right key"));
+        assert!(matches!(&checked[0].1, FileStatus::Error(e) if e.contains("keyed check")), "{:?}", checked);
+        // Nor can every entry be downgraded and the key dropped.
+        downgraded.entries.iter_mut().for_each(|entry| entry.algorithm = HashAlgo::Sha256);
+        downgraded.save(&baseline).unwrap();
+        assert!(load_for_check(&baseline, &Hashing::default(), &BaselineTrust::default()).unwrap_err().contains("--key-file"));
+
+        let sums = dir.path().join("SHA256SUMS");
+        fs::write(&sums, format!("{}  {}\n", hex::encode(HashAlgo::Sha256.hash(b"setting = 2\n")), files[0].display())).unwrap();
+        assert!(load_for_check(&sums, &keyed(b"right key"), &BaselineTrust::default()).unwrap_err().contains("checksum file"));
+    }
+
+    fn entry(path: &str, size: u64, algorithm: HashAlgo, digest: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: path.to_string(),
+            size,
+            algorithm,
+            digest: digest.to_string(),
+            mtime_ns: None,
+            sections: Vec::new(),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_quick_mode_trusts_unmodified_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["same", "touched", "edited"].iter().map(|name| dir.path().join(name)).collect();
+        for file in &files {
+            fs::write(file, "content\n").unwrap();
+        }
+        let walk = WalkOptions::default();
+        let (manifest, _) = KdvManifest::create(&files, &walk, None, HashAlgo::Sha256, &Hashing::default());
+        assert!(manifest.entries.iter().all(|entry| entry.mtime_ns.is_some()));
+
+        let later = |file: &Path, secs: u64| {
+            let modified = fs::metadata(file).unwrap().modified().unwrap() + Duration::from_secs(secs);
+            fs::File::options().write(true).open(file).unwrap().set_modified(modified).unwrap();
+        };
+        later(&files[1], 10);
+        fs::write(&files[2], "CONTENT\n").unwrap();
+        later(&files[2], 20);
+
+        let statuses = |quick: bool| -> Vec<FileStatus> {
+            let hashing = Hashing {
+                quick,
+                ..Default::default()
+            };
+            manifest.check(&[], &walk, None, &hashing).into_iter().map(|(_, status)| status).collect()
+        };
+        // Entries sort by path: edited, same, touched.
+        assert_eq!(statuses(false), [FileStatus::Changed, FileStatus::Verified, FileStatus::Touched]);
+        assert_eq!(statuses(true), [FileStatus::Changed, FileStatus::Trusted, FileStatus::Touched]);
+
+        let results = manifest.check(&[], &walk, None, &Hashing { quick: true, ..Default::default() });
+        let summary = CheckSummary::of(&results);
+        assert_eq!((summary.quick_passed, summary.touched, summary.changed), (1, 1, 1));
+        assert!(summary.to_string().ends_with("1 touched, 1 quick-passed"), "{}", summary);
+
+        // A baseline without mtimes is always hashed.
+        let mut old = manifest.clone();
+        old.entries.iter_mut().for_each(|entry| entry.mtime_ns = None);
+        let results = old.check(&[], &walk, None, &Hashing { quick: true, ..Default::default() });
+        let statuses: Vec<FileStatus> = results.into_iter().map(|(_, status)| status).collect();
+        assert_eq!(statuses, [FileStatus::Changed, FileStatus::Verified, FileStatus::Verified]);
+    }
+
+    #[test]
+    fn test_pself_sections_verify_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.pself");
+        // Sections linux, windows and macos, 8, 8 and 4 bytes long.
+        let mut data = include_bytes!("tests/fixtures/sections.pself").to_vec();
+        data[12 + 3 * 73 + 8 + 1] ^= 0xff;
+        // The last section now claims bytes past the end of the container.
+        let macos_length = 12 + 2 * 73 + 37;
+        data[macos_length..macos_length + 4].copy_from_slice(&100_u32.to_be_bytes());
+        fs::write(&path, &data).unwrap();
+
+        let report = KdvVerifier::verify_pself(&path, &VerifyOptions::default()).unwrap();
+        let checks: Vec<(&str, BaselineCheck)> =
+            report.results.iter().map(|(name, result)| (name.as_str(), result.check())).collect();
+        assert_eq!(
+            checks,
+            [("linux", BaselineCheck::Matches), ("windows", BaselineCheck::Diverges), ("macos", BaselineCheck::Unknown)]
+        );
+
+        let manifest = KdvManifest::from_pself(&path).unwrap();
+        let names: Vec<&str> = manifest.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(names, ["linux", "windows", "macos"]);
+        assert_eq!(manifest.entries[1].digest, hex::encode(KdvVerifier::compute_hash(b"MZ......")));
+
+        fs::write(&path, b"PSEL").unwrap();
+        assert!(KdvVerifier::verify_pself(&path, &VerifyOptions::default()).unwrap_err().contains("Invalid pself"));
+    }
+
+    #[test]
+    fn test_watch_state_reports_transitions_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "port = 80").unwrap();
+        let (manifest, _) =
+            KdvManifest::create(std::slice::from_ref(&file), &WalkOptions::default(), None, HashAlgo::Sha256, &Hashing::default());
+        let round = |state: &mut WatchState| {
+            state.advance(&manifest.check(&[], &WalkOptions::default(), None, &Hashing::default()))
+        };
+        let name = manifest.entries[0].path.clone();
+        let changed = Transition { path: name.clone(), from: "OK".into(), to: "CHANGED".into() };
+        let restored = Transition { path: name, from: "CHANGED".into(), to: "OK".into() };
+
+        let mut state = WatchState::default();
+        assert_eq!(round(&mut state), []);
+        fs::write(&file, "port = 81").unwrap();
+        assert_eq!(round(&mut state), [changed]);
+        assert_eq!(round(&mut state), []);
+
+        let saved = dir.path().join("state.json");
+        state.save(&saved).unwrap();
+        let mut reloaded = WatchState::load(&saved).unwrap();
+        assert_eq!(reloaded, state);
+        assert_eq!(round(&mut reloaded), []);
+        fs::write(&file, "port = 80").unwrap();
+        assert_eq!(round(&mut reloaded), [restored]);
+        assert_eq!(WatchState::load(&dir.path().join("none.json")).unwrap(), WatchState::default());
+    }
+
+    #[test]
+    fn test_sections_of_executables_only() {
+        let elf = include_bytes!("tests/fixtures/sections.elf");
+        let sections = section_fingerprints(elf, HashAlgo::Sha256, None).unwrap();
+        let names: Vec<&str> = sections.iter().map(|section| section.section_name.as_str()).collect();
+        assert_eq!(names, [".text", ".rodata", ".data"]);
+        assert_eq!(sections[1].hash, HashAlgo::Sha256.hash(b"serialk fixture\n"));
+        assert_eq!(section_fingerprints(b"port = 8080\n", HashAlgo::Sha256, None), None);
+    }
+
+    #[test]
+    fn test_diff_reports_every_kind_of_drift() {
+        let manifest = |entries: Vec<ManifestEntry>| KdvManifest {
+            version: MANIFEST_VERSION,
+            created_at: "2026-09-17T00:00:00Z".to_string(),
+            root: None,
+            entries,
+            keyed: false,
+            updates: Vec::new(),
+            merkle_root: None,
+            signature: None,
+        };
+        let old = manifest(vec![
+            entry("bin/algo", 10, HashAlgo::Sha256, "aa"),
+            entry("bin/app", 10, HashAlgo::Sha256, "aa"),
+            entry("bin/grown", 10, HashAlgo::Sha256, "aa"),
+            entry("bin/same", 10, HashAlgo::Sha256, "aa"),
+            entry("bin/gone", 10, HashAlgo::Sha256, "aa"),
+            entry("var/app.log", 10, HashAlgo::Sha256, "aa"),
+        ]);
+        let new = manifest(vec![
+            entry("bin/algo", 10, HashAlgo::Blake3, "bb"),
+            entry("bin/app", 10, HashAlgo::Sha256, "bb"),
+            entry("bin/grown", 12, HashAlgo::Sha256, "cc"),
+            entry("bin/new", 4, HashAlgo::Sha256, "dd"),
+            entry("bin/same", 10, HashAlgo::Sha256, "aa"),
+            entry("var/app.log", 99, HashAlgo::Sha256, "ee"),
+        ]);
+        let diff = old.diff(&new, &[glob::Pattern::new("*.log").unwrap()]);
+        assert_eq!(diff.unchanged, 1);
+        let path = |p: &str| p.to_string();
+        assert_eq!(
+            diff.differences,
+            [
+                Drift::AlgorithmChanged {
+                    path: path("bin/algo"),
+                    old_algorithm: HashAlgo::Sha256,
+                    new_algorithm: HashAlgo::Blake3,
+                },
+                Drift::HashChanged {
+                    path: path("bin/app"),
+                    old_digest: path("aa"),
+                    new_digest: path("bb"),
+                },
+                Drift::Removed { path: path("bin/gone") },
+                Drift::SizeChanged {
+                    path: path("bin/grown"),
+                    old_size: 10,
+                    new_size: 12,
+                },
+                Drift::Added {
+                    path: path("bin/new"),
+                    size: 4,
+                    digest: path("dd"),
+                },
+            ]
+        );
+        assert_eq!(diff.differences[1].to_string(), "[HASH] bin/app: aa -> bb");
+        let json = serde_json::to_value(&diff.differences[0]).unwrap();
+        assert_eq!(json["change"], "algorithm-changed");
+        assert_eq!(json["new_algorithm"], "blake3");
+        assert!(old.diff(&old, &[]).differences.is_empty());
+    }
+
+    #[test]
+    fn test_parallel_results_match_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        for n in 0..40 {
+            let path = dir.path().join(format!("d{}/f{:02}", n % 4, n));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, vec![n as u8; 100 + n * 997]).unwrap();
+        }
+        let walk = WalkOptions::default();
+        let serial = Hashing {
+            jobs: Some(1),
+            ..Default::default()
+        };
+        let parallel = Hashing {
+            jobs: Some(8),
+            ..Default::default()
+        };
+        let tree = [dir.path().to_path_buf()];
+        let (one, _) = KdvManifest::create(&tree, &walk, None, HashAlgo::Sha256, &serial);
+        let (many, _) = KdvManifest::create(&tree, &walk, None, HashAlgo::Sha256, &parallel);
+        assert_eq!(one.entries.len(), 40);
+        assert_eq!(one.entries, many.entries);
+
+        for n in [3, 17, 29] {
+            fs::write(dir.path().join(format!("d{}/f{:02}", n % 4, n)), "edited").unwrap();
+        }
+        fs::remove_file(dir.path().join("d0/f08")).unwrap();
+        for paths in [&[][..], &tree[..]] {
+            let expected = one.check(paths, &walk, None, &serial);
+            assert_eq!(one.check(paths, &walk, None, &parallel), expected);
+            let changed = expected.iter().filter(|(_, status)| *status == FileStatus::Changed).count();
+            assert_eq!(changed, 3);
+        }
+    }
+
+    #[test]
+    fn test_verify_all_reports_each_section() {
+        let sections: HashMap<String, Vec<u8>> = [("text", "code"), ("data", "tables"), ("bss", "")]
+            .into_iter()
+            .map(|(name, content)| (name.to_string(), content.as_bytes().to_vec()))
+            .collect();
+        let verifier = KdvVerifier::from_sections(&sections);
+        let report = verifier.verify_all(&sections, &VerifyOptions::default()).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "3 verified, 0 mismatched, 0 unknown");
+        assert_eq!(report.bytes_hashed, 10);
+
+        let patched: [(&str, &[u8]); 4] = [("bss", b""), ("data", b"patched"), ("extra", b"new"), ("text", b"code")];
+        let report = verifier.verify_all(patched, &VerifyOptions::default()).unwrap();
+        let names: Vec<&str> = report.results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["bss", "data", "extra", "text"]);
+        assert_eq!(
+            report.results[1].1,
+            VerifyResult::Mismatch {
+                expected: KdvVerifier::compute_hash(b"tables"),
+                actual: KdvVerifier::compute_hash(b"patched"),
+            }
+        );
+        assert_eq!(report.results[2].1, VerifyResult::Unknown);
+        assert_eq!((report.verified(), report.mismatched(), report.unknown()), (2, 1, 1));
+        assert!(!report.is_clean());
+        let expected = format!(
+            "data: mismatch: expected {}, got {}\nextra: unknown\n2 verified, 1 mismatched, 1 unknown",
+            hex::encode(KdvVerifier::compute_hash(b"tables")),
+            hex::encode(KdvVerifier::compute_hash(b"patched"))
+        );
+        assert_eq!(report.to_string(), expected);
+
+        #[allow(deprecated)]
+        let verified = (verifier.verify("text", b"code"), verifier.verify("data", b"patched"));
+        assert_eq!(verified, (true, false));
+
+        // Paths are hashed from disk, and a gone file is missing.
+        let dir = tempfile::tempdir().unwrap();
+        let (text, gone) = (dir.path().join("text"), dir.path().join("gone"));
+        fs::write(&text, "code").unwrap();
+        let verifier = KdvVerifier::from_sections(&[("text".to_string(), b"code".to_vec()), ("gone".to_string(), Vec::new())].into());
+        let report = verifier.verify_all([("text", &text), ("gone", &gone)], &VerifyOptions::default()).unwrap();
+        assert_eq!(report.results[1].1, VerifyResult::Missing);
+        assert_eq!((report.verified(), report.missing(), report.bytes_hashed), (1, 1, 4));
+        assert_eq!(report.to_string(), "gone: missing\n1 verified, 0 mismatched, 0 unknown, 1 missing");
+    }
+
+    #[test]
+    fn test_verify_all_fails_fast() {
+        let contents: Vec<(String, Vec<u8>)> = (0..10).map(|i| (format!("s{}", i), vec![i as u8; 100])).collect();
+        let verifier = KdvVerifier::from_sections(&contents.iter().cloned().collect());
+        let mut tampered = contents.clone();
+        tampered[2].1 = b"patched".to_vec();
+        // Counts the entries verify_all takes content from.
+        struct Counted<'a>(&'a [u8], &'a AtomicUsize);
+        impl<'a> From<Counted<'a>> for VerifyInput<'a> {
+            fn from(Counted(content, taken): Counted<'a>) -> Self {
+                taken.fetch_add(1, Ordering::Relaxed);
+                VerifyInput::Content(content)
+            }
+        }
+        let taken = AtomicUsize::new(0);
+        let entries = || tampered.iter().map(|(name, content)| (name.as_str(), Counted(content, &taken)));
+
+        let serial = VerifyOptions { fail_fast: true, jobs: Some(1), ..Default::default() };
+        let report = verifier.verify_all(entries(), &serial).unwrap();
+        assert_eq!(report.results.len(), 3);
+        assert!(matches!(report.results[2].1, VerifyResult::Mismatch { .. }));
+        assert_eq!((report.skipped, report.bytes_hashed), (7, 207));
+        // The rest were counted, never hashed.
+        assert_eq!(taken.load(Ordering::Relaxed), 3);
+        assert!(!report.is_clean());
+        assert!(report.to_string().ends_with("2 verified, 1 mismatched, 0 unknown, 7 skipped"), "{}", report);
+
+        let full = verifier.verify_all(entries(), &VerifyOptions { jobs: Some(1), ..Default::default() }).unwrap();
+        assert_eq!((full.results.len(), full.skipped, full.mismatched()), (10, 0, 1));
+        let parallel = verifier.verify_all(entries(), &VerifyOptions { fail_fast: true, jobs: Some(4), ..Default::default() }).unwrap();
+        assert_eq!(parallel.results.len() + parallel.skipped, 10);
+        assert_eq!(parallel.mismatched(), 1);
+    }
+
+    #[test]
+    fn test_verify_all_unknown_name_policy() {
+        let verifier = KdvVerifier::from_sections(&[("known".to_string(), b"k".to_vec())].into());
+        let entries: [(&str, &[u8]); 3] = [("known", b"k"), ("stray", b"s"), ("later", b"l")];
+        let entries = || entries.iter().copied();
+
+        let strict = verifier.verify_all(entries(), &VerifyOptions { fail_fast: true, jobs: Some(1), ..Default::default() }).unwrap();
+        assert_eq!((strict.results.len(), strict.unknown(), strict.skipped), (2, 1, 1));
+        assert!(!strict.is_clean());
+
+        let lenient = VerifyOptions { fail_fast: true, jobs: Some(1), allow_unknown: true };
+        let report = verifier.verify_all(entries(), &lenient).unwrap();
+        assert_eq!((report.results.len(), report.unknown(), report.skipped), (3, 2, 0));
+        assert!(report.is_clean());
+        // Unknown names are not hashed.
+        assert_eq!(report.bytes_hashed, 1);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["results"][1], serde_json::json!({ "name": "stray", "status": "unknown" }));
+        assert_eq!((json["bytes_hashed"].as_u64(), json["unknown_allowed"].as_bool()), (Some(1), Some(true)));
+        let back: VerifyReport = serde_json::from_value(json).unwrap();
+        assert_eq!(back.results, report.results);
+    }
+
+    #[test]
+    fn test_verifier_hashes_each_entry_with_its_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.bin");
+        fs::write(&file, "code").unwrap();
+        let mut verifier = KdvVerifier::new();
+        verifier.key = Some(b"key".to_vec());
+        for algo in [HashAlgo::Sha512, HashAlgo::Blake3, HashAlgo::HmacSha256] {
+            let digest = algo.hash_reader(&mut &b"code"[..], Some(b"key")).unwrap();
+            verifier.fingerprints.insert(algo.as_str().to_string(), digest);
+            verifier.algorithms.insert(algo.as_str().to_string(), algo);
+        }
+        let report = verifier
+            .verify_all([("sha512", &file), ("blake3", &file), ("hmac-sha256", &file)], &VerifyOptions::default())
+            .unwrap();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(verifier.verify_reader("blake3", &mut fs::File::open(&file).unwrap()).unwrap(), VerifyResult::Verified);
+        assert_eq!(verifier.verify_content("hmac-sha256", b"code"), VerifyResult::Verified);
+        assert!(matches!(verifier.verify_content("sha512", b"edited"), VerifyResult::Mismatch { .. }));
+
+        let (manifest, _) = KdvManifest::create(std::slice::from_ref(&file), &WalkOptions::default(), None, HashAlgo::Blake3, &Hashing::default());
+        let verifier = KdvVerifier::from_baseline(&manifest, None).unwrap();
+        let report = verifier.verify_all([(manifest.entries[0].path.as_str(), &file)], &VerifyOptions::default()).unwrap();
+        assert_eq!(report.verified(), 1);
+    }
+
+    #[test]
+    fn test_streamed_and_in_memory_digests_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut verifier = KdvVerifier::new();
+        // Empty, short, exactly one chunk, and across chunk boundaries.
+        for len in [0, 3, HASH_CHUNK, 2 * HASH_CHUNK + 17] {
+            let content: Vec<u8> = (0..len).map(|n| (n % 251) as u8).collect();
+            let path = dir.path().join(format!("{}.bin", len));
+            fs::write(&path, &content).unwrap();
+            for algo in HashAlgo::ALL {
+                assert_eq!(algo.hash_file(&path, None).unwrap(), algo.hash(&content), "{} of {} bytes", algo, len);
+            }
+            verifier.add_file(&path).unwrap();
+            assert_eq!(verifier.verify_content(&path.to_string_lossy(), &content), VerifyResult::Verified);
+            let streamed = verifier.verify_reader(&path.to_string_lossy(), &mut &content[..]).unwrap();
+            assert_eq!(streamed, VerifyResult::Verified);
+        }
+        let last = dir.path().join(format!("{}.bin", 2 * HASH_CHUNK + 17));
+        fs::write(&last, "shorter").unwrap();
+        assert_eq!(verifier.verify_path(&last).unwrap().check(), BaselineCheck::Diverges);
+    }
+
+    /// Not a correctness test: `cargo test kdv_parallel_scaling -- --ignored --nocapture`
+    /// times `kdv check` on a generated tree with 1, 2, 4, ... jobs up to the core count.
+    #[test]
+    #[ignore]
+    fn kdv_parallel_scaling() {
+        let dir = tempfile::tempdir().unwrap();
+        let block = vec![0x5a; 4 << 20];
+        for n in 0..64 {
+            fs::write(dir.path().join(format!("file{:02}", n)), &block).unwrap();
+        }
+        let walk = WalkOptions::default();
+        let tree = [dir.path().to_path_buf()];
+        let (manifest, _) = KdvManifest::create(&tree, &walk, None, HashAlgo::Sha256, &Hashing::default());
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let mut jobs = 1;
+        while jobs <= cores {
+            let hashing = Hashing {
+                jobs: Some(jobs),
+                ..Default::default()
+            };
+            let start = Instant::now();
+            manifest.check(&[], &walk, None, &hashing);
+            println!("{} jobs: {:?}", jobs, start.elapsed());
+            jobs *= 2;
+        }
+    }
+
+    /// Not a correctness test: `cargo test kdv_hash_throughput -- --ignored --nocapture`
+    /// compares the algorithms on a generated 256 MiB file.
+    #[test]
+    #[ignore]
+    fn kdv_hash_throughput() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("large.bin");
+        let block: Vec<u8> = (0..1 << 20).map(|n: u32| (n.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut out = fs::File::create(&file).unwrap();
+        for _ in 0..256 {
+            io::Write::write_all(&mut out, &block).unwrap();
+        }
+        drop(out);
+        for algo in HashAlgo::ALL {
+            let start = std::time::Instant::now();
+            algo.hash_file(&file, None).unwrap();
+            let elapsed = start.elapsed();
+            println!("{}: {:?} ({:.0} MiB/s)", algo, elapsed, 256.0 / elapsed.as_secs_f64());
+        }
+    }
+
+    #[test]
+    fn test_merkle_root_survives_reserialization_and_tracks_every_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..5).map(|i| dir.path().join(format!("f{}", i))).collect();
+        for file in &files {
+            fs::write(file, file.to_string_lossy().as_bytes()).unwrap();
+        }
+        let (manifest, _) = KdvManifest::create(&files, &WalkOptions::default(), Some(dir.path()), HashAlgo::Sha256, &Hashing::default());
+        let root = manifest.merkle_root();
+
+        let baseline = dir.path().join("baseline.json");
+        manifest.save(&baseline).unwrap();
+        let loaded = KdvManifest::load(&baseline).unwrap();
+        assert_eq!(loaded.merkle_root.as_deref(), Some(root.as_str()));
+        loaded.save(&baseline).unwrap();
+        assert_eq!(KdvManifest::load(&baseline).unwrap().merkle_root(), root);
+
+        let mut shuffled = loaded.clone();
+        shuffled.entries.reverse();
+        assert_eq!(shuffled.merkle_root(), root);
+
+        let trusted = kdv_merkle::parse_root(&root).unwrap();
+        for entry in &loaded.entries {
+            let proof = loaded.prove(&entry.path).unwrap();
+            let json = serde_json::to_string(&proof).unwrap();
+            assert!(serde_json::from_str::<MerkleProof>(&json).unwrap().verify(&trusted));
+        }
+        assert!(loaded.prove("f9").is_none());
+
+        let mut changed = loaded.clone();
+        changed.entries[2].digest = "00".repeat(32);
+        assert_ne!(changed.merkle_root(), root);
+        let mut renamed = loaded.clone();
+        renamed.entries[2].path = "g2".into();
+        assert_ne!(renamed.merkle_root(), root);
+
+        let removed_proof = loaded.prove("f3").unwrap();
+        let mut removed = loaded.clone();
+        removed.entries.retain(|entry| entry.path != "f3");
+        let new_root = kdv_merkle::parse_root(&removed.merkle_root()).unwrap();
+        assert!(!removed_proof.verify(&new_root));
+        assert!(removed.prove("f1").unwrap().verify(&new_root));
+
+        changed.merkle_root = Some(root);
+        fs::write(&baseline, serde_json::to_string(&changed).unwrap()).unwrap();
+        assert!(KdvManifest::load(&baseline).unwrap_err().contains("does not match its merkle_root"));
+    }
+
+    #[test]
+    fn test_signed_manifest_refuses_edits_and_other_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "port = 80").unwrap();
+        let (mut manifest, _) =
+            KdvManifest::create(std::slice::from_ref(&file), &WalkOptions::default(), None, HashAlgo::Sha256, &Hashing::default());
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert_eq!(manifest.verify_signature(&key.verifying_key()).unwrap_err(), "is not signed");
+        manifest.sign(&key);
+        assert_eq!(manifest.verify_signature(&key.verifying_key()), Ok(()));
+        assert!(manifest.verify_signature(&other).unwrap_err().contains("signed by another key"));
+
+        // Formatting and key order are not signed, only the content.
+        let baseline = dir.path().join("baseline.json");
+        let value = serde_json::to_value(&manifest).unwrap();
+        fs::write(&baseline, serde_json::to_string(&value).unwrap()).unwrap();
+        let reloaded = KdvManifest::load(&baseline).unwrap();
+        assert_eq!(reloaded.verify_signature(&key.verifying_key()), Ok(()));
+        reloaded.save(&baseline).unwrap();
+        assert_eq!(KdvManifest::load(&baseline).unwrap().verify_signature(&key.verifying_key()), Ok(()));
+
+        let mut edited = reloaded.clone();
+        edited.created_at = "2020-01-01T00:00:00Z".to_string();
+        assert!(edited.verify_signature(&key.verifying_key()).unwrap_err().contains("edited after it was signed"));
+        let mut edited = reloaded;
+        edited.entries[0].digest = "00".repeat(32);
+        assert!(edited.verify_signature(&key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn test_incremental_check_rehashes_only_what_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..4).map(|i| dir.path().join(format!("f{}", i))).collect();
+        for file in &files {
+            fs::write(file, "same").unwrap();
+        }
+        let walk = WalkOptions::default();
+        let (manifest, _) = KdvManifest::create(&files, &walk, None, HashAlgo::Sha256, &Hashing::default());
+        let incremental = Incremental { state_file: dir.path().join("state.json"), full_every: Some(3) };
+        let run = || {
+            let (results, state, hashed) = incremental.check(&manifest, &[], &walk, None, &Hashing::default()).unwrap();
+            state.save(&incremental.state_file).unwrap();
+            (results.into_iter().map(|(_, status)| status).collect::<Vec<_>>(), hashed)
+        };
+
+        // No state yet: a full check, never a skip.
+        assert_eq!(run(), (vec![FileStatus::Verified; 4], 4));
+        fs::write(&files[1], "other content").unwrap();
+        let (statuses, hashed) = run();
+        assert_eq!(hashed, 1);
+        assert_eq!(statuses, [FileStatus::Trusted, FileStatus::Changed, FileStatus::Trusted, FileStatus::Trusted]);
+        // The changed file was not recorded, so it is hashed again; every third run is full.
+        assert_eq!(run().1, 1);
+        assert_eq!(run().1, 4);
+
+        fs::remove_file(&incremental.state_file).unwrap();
+        assert_eq!(run().1, 4);
+        let other = KdvManifest { entries: manifest.entries[1..].to_vec(), ..manifest.clone() };
+        let (_, _, hashed) = incremental.check(&other, &[], &walk, None, &Hashing::default()).unwrap();
+        assert_eq!(hashed, 3);
+
+        let baseline = dir.path().join("baseline.json");
+        manifest.save(&baseline).unwrap();
+        assert!(CheckState::load(&baseline).unwrap_err().contains("not a kdv check state file"));
+        assert!(KdvManifest::load(&incremental.state_file).is_err());
+    }
+
+    #[test]
+    fn test_duplicates_grouped_by_wasted_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = [
+            ("a/key.pem", "secret key material"),
+            ("b/key.pem", "secret key material"),
+            ("c/copy", "secret key material"),
+            ("big1", "0123456789012345678901234567890123456789"),
+            ("big2", "0123456789012345678901234567890123456789"),
+            ("unique", "only once"),
+            ("tiny1", "x"),
+            ("tiny2", "x"),
+        ];
+        for (name, content) in tree {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let (manifest, _) =
+            KdvManifest::create(&[dir.path().to_path_buf()], &WalkOptions::default(), Some(dir.path()), HashAlgo::Sha256, &Hashing::default());
+
+        let groups = manifest.duplicates(2);
+        let summary: Vec<(Vec<&str>, u64, u64)> =
+            groups.iter().map(|group| (group.paths.iter().map(String::as_str).collect(), group.size, group.wasted)).collect();
+        assert_eq!(summary, [(vec!["big1", "big2"], 40, 40), (vec!["a/key.pem", "b/key.pem", "c/copy"], 19, 38)]);
+        assert_eq!(groups[1].digest, hex::encode(KdvVerifier::compute_hash(b"secret key material")));
+        assert_eq!(manifest.duplicates(1).len(), 3);
+        assert!(manifest.duplicates(41).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_changes_are_reported_apart_from_content() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["app.conf", "tool"] {
+            fs::write(dir.path().join(name), name).unwrap();
+            fs::set_permissions(dir.path().join(name), fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        let paths = [dir.path().to_path_buf()];
+        let (walk, root) = (WalkOptions::default(), Some(dir.path()));
+        let recorded = Hashing { with_metadata: true, ..Default::default() };
+        let (manifest, _) = KdvManifest::create(&paths, &walk, root, HashAlgo::Sha256, &recorded);
+        let (bare, _) = KdvManifest::create(&paths, &walk, root, HashAlgo::Sha256, &Hashing::default());
+        assert_eq!(manifest.entries[0].metadata.as_ref().and_then(|metadata| metadata.mode), Some(0o644));
+        assert!(bare.entries.iter().all(|entry| entry.metadata.is_none()));
+
+        fs::set_permissions(dir.path().join("app.conf"), fs::Permissions::from_mode(0o666)).unwrap();
+        let results = manifest.check(&[], &walk, None, &Hashing::default());
+        let violation = MetadataViolation::new("mode", "0644", "0666");
+        assert_eq!(results[0].1, FileStatus::MetadataChanged(vec![violation]));
+        assert_eq!(results[1].1, FileStatus::Verified);
+        let summary = CheckSummary::of(&results);
+        assert_eq!((summary.metadata, summary.changed, summary.exit_code()), (1, 0, 5));
+        // Unrecorded, a looser mode passes...
+        assert!(bare.check(&[], &walk, None, &Hashing::default()).iter().all(|(_, status)| *status == FileStatus::Verified));
+
+        // ...but a setuid bit never does, and content changes still win.
+        fs::set_permissions(dir.path().join("tool"), fs::Permissions::from_mode(0o4755)).unwrap();
+        let results = bare.check(&[], &walk, None, &Hashing { quick: true, ..Default::default() });
+        assert_eq!(results[1].1, FileStatus::MetadataChanged(vec![MetadataViolation::new("setuid", "unset", "set")]));
+        fs::write(dir.path().join("tool"), "rewritten").unwrap();
+        assert_eq!(bare.check(&[], &walk, None, &Hashing::default())[1].1, FileStatus::Changed);
+    }
+
+    #[test]
+    fn test_embedded_baseline_checks_a_matching_and_a_deviating_tree() {
+        // Compiled in the way build.rs embeds EMBEDDED_BASELINE.
+        static FIXTURE: &[u8] = include_bytes!("tests/fixtures/embedded/baseline.json");
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["plain.txt", "with space.txt", "back\\slash.txt"] {
+            fs::copy(Path::new("tests/fixtures/sums").join(name), dir.path().join(name)).unwrap();
+        }
+        let manifest = KdvManifest::from_embedded(FIXTURE).unwrap();
+        let check = || manifest.check(&[], &WalkOptions::default(), Some(dir.path()), &Hashing::default());
+        assert!(check().iter().all(|(_, status)| *status == FileStatus::Verified), "{:?}", check());
 
-    let mut verifier = KdvVerifier::new();
-    verifier.load_initial_fingerprints(&sections);
+        fs::write(dir.path().join("plain.txt"), "plain files\n").unwrap();
+        let deviating: Vec<(String, FileStatus)> =
+            check().into_iter().filter(|(_, status)| *status != FileStatus::Verified).collect();
+        assert_eq!(deviating, [("plain.txt".to_string(), FileStatus::Changed)]);
 
-    println!("[KDV] Doğrulama turu başlatılıyor...");
-    for (name, content) in &sections {
-        verifier.verify(name, content);
+        assert!(KdvManifest::from_embedded(&[]).unwrap_err().contains("SERIALK_EMBED_BASELINE"));
     }
 }
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::hfs_log::timestamp;
+use crate::kdv::HashAlgo;
+
+/// Where `kdv bench` leaves its results for `kdv init --algo auto` unless
+/// `--bench-file` says otherwise.
+pub const DEFAULT_BENCH_FILE: &str = "/var/cache/serialkiller/kdv/bench.json";
+
+/// Tells a bench file from a baseline or any other JSON lying around.
+const KIND: &str = "kdv-bench";
+
+/// One algorithm's speed on this machine, in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlgoThroughput {
+    pub algorithm: HashAlgo,
+    pub single_thread: f64,
+    /// With `BenchReport::threads` hashing at once, all together.
+    pub multi_thread: f64,
+}
+
+/// What `kdv bench` measured, and the algorithm it suggests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchReport {
+    pub kind: String,
+    /// UTC, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created_at: String,
+    /// Bytes per buffer hashed.
+    pub size: usize,
+    pub threads: usize,
+    pub results: Vec<AlgoThroughput>,
+    pub recommended: HashAlgo,
+}
+
+impl BenchReport {
+    /// Hashes an in-memory buffer of `size` bytes with each unkeyed
+    /// algorithm over and over for `duration`, first on one thread and then
+    /// on `threads` at once, so the disk plays no part. The recommendation
+    /// is the fastest on `threads`, as `kdv init --jobs` hashes.
+    pub fn run(size: usize, duration: Duration, threads: usize) -> Self {
+        let buffer: Vec<u8> = (0..size).map(|i| (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15).to_le_bytes()[7]).collect();
+        let threads = threads.max(1);
+        let results: Vec<AlgoThroughput> = HashAlgo::ALL
+            .into_iter()
+            .map(|algorithm| AlgoThroughput {
+                algorithm,
+                single_thread: throughput(algorithm, &buffer, duration, 1),
+                multi_thread: throughput(algorithm, &buffer, duration, threads),
+            })
+            .collect();
+        let recommended = results
+            .iter()
+            .max_by(|a, b| a.multi_thread.total_cmp(&b.multi_thread))
+            .map_or(HashAlgo::default(), |fastest| fastest.algorithm);
+        Self {
+            kind: KIND.to_string(),
+            created_at: timestamp(SystemTime::now()),
+            size,
+            threads,
+            results,
+            recommended,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read bench results {}: {}", path.display(), e))?;
+        let report: Self =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid bench results {}: {}", path.display(), e))?;
+        if report.kind != KIND {
+            return Err(format!("{} is not a kdv bench file", path.display()));
+        }
+        Ok(report)
+    }
+
+    /// Writes the report next to `path` and renames it into place, creating
+    /// the directory if needed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&temporary, json + "\n")?;
+        fs::rename(&temporary, path)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        let many = format!("{} THREADS", self.threads);
+        writeln!(f, "{:<10} {:>14} {:>14}", "ALGORITHM", "1 THREAD", many)?;
+        for result in &self.results {
+            writeln!(
+                f,
+                "{:<10} {:>9.1} MiB/s {:>9.1} MiB/s",
+                result.algorithm.as_str(),
+                result.single_thread / MIB,
+                result.multi_thread / MIB
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes per second `threads` threads together hash `buffer` at with
+/// `algorithm`, each going for at least `duration` and one pass.
+fn throughput(algorithm: HashAlgo, buffer: &[u8], duration: Duration, threads: usize) -> f64 {
+    let started = Instant::now();
+    let bytes: u64 = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut bytes = 0;
+                    loop {
+                        std::hint::black_box(algorithm.hash(buffer));
+                        bytes += buffer.len() as u64;
+                        if started.elapsed() >= duration {
+                            return bytes;
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().expect("bench thread panicked")).sum()
+    });
+    bytes as f64 / started.elapsed().as_secs_f64().max(1e-9)
+}
+
+/// The algorithm `kdv init --algo auto` records with: the one the bench at
+/// `path` recommended, or the default with a warning if there is none.
+pub fn recommended(path: &Path) -> HashAlgo {
+    match BenchReport::load(path) {
+        Ok(report) => report.recommended,
+        Err(e) => {
+            eprintln!("[WARN] {}; run kdv bench first. Using {}", e, HashAlgo::default());
+            HashAlgo::default()
+        }
+    }
+}
+
+/// `serialkiller kdv bench`: measures each algorithm, prints the table and
+/// the suggestion, and saves them to `path`. Returns the exit code.
+pub fn run_kdv_bench(size: usize, duration: Duration, threads: usize, path: &Path) -> i32 {
+    println!(
+        "[KDV] Hashing {} byte buffers in memory for {:.1}s per run, on 1 and {} threads",
+        size,
+        duration.as_secs_f64(),
+        threads
+    );
+    let report = BenchReport::run(size, duration, threads);
+    print!("{}", report);
+    if let Err(e) = report.save(path) {
+        eprintln!("[ERROR] Cannot write bench results {}: {}", path.display(), e);
+        return 1;
+    }
+    println!("[KDV] Recommended: {} (saved to {} for kdv init --algo auto)", report.recommended, path.display());
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_algorithm_is_measured_and_the_fastest_recommended() {
+        let report = BenchReport::run(64 * 1024, Duration::from_millis(20), 2);
+        let measured: Vec<HashAlgo> = report.results.iter().map(|result| result.algorithm).collect();
+        assert_eq!(measured, HashAlgo::ALL);
+        assert!(report.results.iter().all(|result| result.single_thread > 0.0 && result.multi_thread > 0.0));
+        let fastest = report.results.iter().map(|result| result.multi_thread).fold(0.0, f64::max);
+        let best = report.results.iter().find(|result| result.algorithm == report.recommended).unwrap();
+        assert_eq!(best.multi_thread, fastest);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache/bench.json");
+        report.save(&path).unwrap();
+        let loaded = BenchReport::load(&path).unwrap();
+        assert_eq!((loaded.results.len(), loaded.threads, loaded.recommended), (3, 2, report.recommended));
+        assert_eq!(recommended(&path), report.recommended);
+        assert_eq!(recommended(&dir.path().join("absent.json")), HashAlgo::default());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::kdv::{self, BaselineTrust, FileStatus, Hashing, KdvManifest, VerifyReport, VerifyResult};
+
+/// Time between rounds unless `--kdv-daemon-interval` says otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// What the daemon hands the watch loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KdvDaemonEvent {
+    /// A round finished; `report` holds only the entries whose result
+    /// changed since the round before, so a lasting divergence is reported
+    /// once and again only when it clears.
+    Round { report: VerifyReport, checked: usize },
+    /// A round came due while the previous one was still hashing.
+    Skipped,
+}
+
+/// What the watch loop asks of the daemon, from the control socket.
+pub enum KdvDaemonCommand {
+    VerifyNow,
+    /// Check against this baseline from the next round on.
+    Reload(KdvManifest),
+}
+
+/// Periodic deep verification of a kdv baseline alongside the watcher:
+/// every `interval` each entry is hashed again, and what changed is sent
+/// to `alerts` for the watch loop to report like its own events.
+pub struct KdvDaemon {
+    pub interval: Duration,
+    pub baseline: PathBuf,
+    pub hashing: Hashing,
+    pub alerts: UnboundedSender<KdvDaemonEvent>,
+}
+
+/// The watch loop's end of a running daemon.
+pub struct KdvDaemonHandle {
+    pub baseline: PathBuf,
+    hashing: Hashing,
+    commands: UnboundedSender<KdvDaemonCommand>,
+}
+
+impl KdvDaemonHandle {
+    /// Starts a round now, unless one is running.
+    pub fn verify_now(&self) -> Result<(), String> {
+        self.send(KdvDaemonCommand::VerifyNow)
+    }
+
+    /// Reads the baseline again and has the daemon check against it.
+    /// Returns its entry count; a baseline that cannot be read leaves the
+    /// daemon on the one it had.
+    pub fn reload(&self) -> Result<usize, String> {
+        let manifest = kdv::load_for_check(&self.baseline, &self.hashing, &BaselineTrust::default())?;
+        let entries = manifest.entries.len();
+        self.send(KdvDaemonCommand::Reload(manifest))?;
+        Ok(entries)
+    }
+
+    fn send(&self, command: KdvDaemonCommand) -> Result<(), String> {
+        self.commands.send(command).map_err(|_| "the kdv daemon has stopped".to_string())
+    }
+}
+
+impl KdvDaemon {
+    /// Loads the baseline and runs the daemon on a thread of its own with
+    /// its own runtime, like the HFS monitor, so it also runs after the
+    /// watcher has daemonized. The first round starts right away.
+    pub fn spawn(self) -> Result<KdvDaemonHandle, String> {
+        let manifest = kdv::load_for_check(&self.baseline, &self.hashing, &BaselineTrust::default())?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Cannot start the kdv daemon: {}", e))?;
+        let (commands, rx) = unbounded_channel();
+        let handle = KdvDaemonHandle {
+            baseline: self.baseline.clone(),
+            hashing: self.hashing.clone(),
+            commands,
+        };
+        std::thread::spawn(move || runtime.block_on(self.run(manifest, rx)));
+        Ok(handle)
+    }
+
+    async fn run(self, manifest: KdvManifest, mut commands: UnboundedReceiver<KdvDaemonCommand>) {
+        let mut manifest = Arc::new(manifest);
+        let previous = Arc::new(Mutex::new(HashMap::new()));
+        let running = Arc::new(AtomicBool::new(false));
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                command = commands.recv() => match command {
+                    Some(KdvDaemonCommand::VerifyNow) => {}
+                    Some(KdvDaemonCommand::Reload(fresh)) => {
+                        manifest = Arc::new(fresh);
+                        continue;
+                    }
+                    // The watcher is gone.
+                    None => return,
+                },
+            }
+            if running.swap(true, Ordering::SeqCst) {
+                if self.alerts.send(KdvDaemonEvent::Skipped).is_err() {
+                    return;
+                }
+                continue;
+            }
+            let (manifest, previous, running) = (Arc::clone(&manifest), Arc::clone(&previous), Arc::clone(&running));
+            let (hashing, alerts) = (self.hashing.clone(), self.alerts.clone());
+            tokio::task::spawn_blocking(move || {
+                let results = verify(&manifest, &hashing);
+                let report = transitions(&mut previous.lock().unwrap(), &results);
+                running.store(false, Ordering::SeqCst);
+                let _ = alerts.send(KdvDaemonEvent::Round {
+                    report,
+                    checked: results.len(),
+                });
+            });
+        }
+    }
+}
+
+/// One round over every entry of `manifest`. Files that cannot be read
+/// for another reason than being gone are left out, with a warning, so
+/// their last result stands.
+fn verify(manifest: &KdvManifest, hashing: &Hashing) -> Vec<(String, VerifyResult)> {
+    let root = manifest.root.as_deref().map(Path::new);
+    let statuses = manifest.check(&[], &kdv::WalkOptions::default(), None, hashing);
+    let mut results = Vec::new();
+    for ((name, status), entry) in statuses.into_iter().zip(&manifest.entries) {
+        let result = match status {
+            FileStatus::Missing => VerifyResult::Missing,
+            FileStatus::Changed | FileStatus::SectionsChanged(_) => {
+                let path = root.map_or_else(|| PathBuf::from(&entry.path), |root| root.join(&entry.path));
+                match entry.algorithm.hash_file(&path, hashing.key.as_deref()) {
+                    Ok(actual) => VerifyResult::Mismatch {
+                        expected: hex::decode(&entry.digest).unwrap_or_default(),
+                        actual,
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => VerifyResult::Missing,
+                    Err(e) => {
+                        eprintln!("[WARN] kdv daemon cannot read {}: {}", name, e);
+                        continue;
+                    }
+                }
+            }
+            FileStatus::Error(e) => {
+                eprintln!("[WARN] kdv daemon cannot read {}: {}", name, e);
+                continue;
+            }
+            _ => VerifyResult::Verified,
+        };
+        results.push((name, result));
+    }
+    results
+}
+
+/// The entries of `results` that differ from `previous`, which then takes
+/// them in. An entry not seen before counts as having verified.
+fn transitions(previous: &mut HashMap<String, VerifyResult>, results: &[(String, VerifyResult)]) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    for (name, result) in results {
+        let before = previous.insert(name.clone(), result.clone()).unwrap_or(VerifyResult::Verified);
+        if before != *result {
+            report.results.push((name.clone(), result.clone()));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round(previous: &mut HashMap<String, VerifyResult>, results: &[(&str, VerifyResult)]) -> Vec<String> {
+        let results: Vec<(String, VerifyResult)> =
+            results.iter().map(|(name, result)| (name.to_string(), result.clone())).collect();
+        transitions(previous, &results).results.into_iter().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn test_a_divergence_is_reported_when_it_starts_and_when_it_clears() {
+        let mismatch = VerifyResult::Mismatch {
+            expected: vec![1],
+            actual: vec![2],
+        };
+        let mut previous = HashMap::new();
+        assert!(round(&mut previous, &[("a", VerifyResult::Verified), ("b", VerifyResult::Verified)]).is_empty());
+        assert_eq!(round(&mut previous, &[("a", mismatch.clone()), ("b", VerifyResult::Verified)]), ["a"]);
+        assert!(round(&mut previous, &[("a", mismatch.clone()), ("b", VerifyResult::Verified)]).is_empty());
+        assert_eq!(round(&mut previous, &[("a", VerifyResult::Verified), ("b", VerifyResult::Missing)]), ["a", "b"]);
+        // Already diverging when the daemon starts.
+        assert_eq!(round(&mut HashMap::new(), &[("c", mismatch)]), ["c"]);
+    }
+}
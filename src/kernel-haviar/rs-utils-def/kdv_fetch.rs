@@ -0,0 +1,158 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Where `kdv check -b URL` keeps verified copies unless `--cache-dir` says otherwise.
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/serialkiller/kdv";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether `-b` names a baseline to download rather than a file.
+pub fn is_url(baseline: &str) -> bool {
+    baseline.starts_with("https://") || baseline.starts_with("http://")
+}
+
+/// A baseline served over HTTP(S), trusted only if its bytes hash to `pin`
+/// and, with `public_key`, if `URL.sig` (a hex ed25519 signature, as
+/// `--write-baseline` writes) verifies them.
+pub struct RemoteBaseline {
+    pub url: String,
+    pub pin: [u8; 32],
+    pub public_key: Option<VerifyingKey>,
+    pub cache_dir: PathBuf,
+    /// A cached copy younger than this is used without a download.
+    pub max_age: Duration,
+    /// Fall back to an older cached copy when the server cannot be reached.
+    /// A download that fails the pin or the signature never falls back.
+    pub allow_stale: bool,
+}
+
+impl RemoteBaseline {
+    /// Parses `--pin-sha256`, the hex SHA-256 of the manifest bytes.
+    pub fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+        hex::decode(pin.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("--pin-sha256 {} is not a 64-digit hex SHA-256", pin))
+    }
+
+    /// The cached copy of the manifest, one file per URL.
+    pub fn cache_path(&self) -> PathBuf {
+        let name = hex::encode(&Sha256::digest(self.url.as_bytes())[..8]);
+        self.cache_dir.join(format!("{}.json", name))
+    }
+
+    /// Returns the path of a local copy of the manifest that passed the pin
+    /// (and signature): the cache if it is fresh, else a new download, else
+    /// with `allow_stale` the cache whatever its age.
+    pub fn fetch(&self) -> Result<PathBuf, String> {
+        let cache = self.cache_path();
+        let cached_age = self.verified_cache(&cache).ok();
+        if cached_age.is_some_and(|age| age <= self.max_age) {
+            return Ok(cache);
+        }
+        let (manifest, signature) = match self.download() {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                return match cached_age {
+                    Some(age) if self.allow_stale => {
+                        eprintln!(
+                            "[WARN] Cannot fetch baseline {}: {}; using the copy cached {}s ago",
+                            self.url,
+                            e,
+                            age.as_secs()
+                        );
+                        Ok(cache)
+                    }
+                    _ => Err(format!("Cannot fetch baseline {}: {}", self.url, e)),
+                }
+            }
+        };
+        self.verify(&manifest, signature.as_deref())
+            .map_err(|e| format!("Baseline {} {}; refusing to use it", self.url, e))?;
+        store(&cache, &manifest, signature.as_deref())
+            .map_err(|e| format!("Cannot cache baseline {} in {}: {}", self.url, cache.display(), e))?;
+        Ok(cache)
+    }
+
+    /// The age of the cached copy, if it still passes the pin and signature.
+    fn verified_cache(&self, cache: &Path) -> Result<Duration, String> {
+        let manifest = fs::read(cache).map_err(|e| e.to_string())?;
+        let signature = match self.public_key {
+            Some(_) => Some(fs::read(signature_path(cache)).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        self.verify(&manifest, signature.as_deref())?;
+        let modified = fs::metadata(cache).and_then(|meta| meta.modified()).map_err(|e| e.to_string())?;
+        Ok(SystemTime::now().duration_since(modified).unwrap_or_default())
+    }
+
+    fn verify(&self, manifest: &[u8], signature: Option<&[u8]>) -> Result<(), String> {
+        if Sha256::digest(manifest)[..] != self.pin {
+            return Err("does not match --pin-sha256".to_string());
+        }
+        let (Some(key), Some(signature)) = (&self.public_key, signature) else {
+            return Ok(());
+        };
+        let signature = std::str::from_utf8(signature)
+            .ok()
+            .and_then(|text| hex::decode(text.trim()).ok())
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .ok_or("signature is not a 64-byte hex ed25519 signature")?;
+        key.verify_strict(manifest, &Signature::from_bytes(&signature))
+            .map_err(|_| "does not verify against its signature and --pubkey".to_string())
+    }
+
+    /// The manifest and, with a public key, its signature. The blocking
+    /// client runs on its own thread, clear of the async runtime.
+    fn download(&self) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+        let url = self.url.clone();
+        let signed = self.public_key.is_some();
+        thread::spawn(move || {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?;
+            let get = |url: &str| -> Result<Vec<u8>, String> {
+                let response = client.get(url).send().and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?;
+                response.bytes().map(|bytes| bytes.to_vec()).map_err(|e| e.to_string())
+            };
+            let manifest = get(&url)?;
+            let signature = match signed {
+                true => Some(get(&format!("{}.sig", url))?),
+                false => None,
+            };
+            Ok((manifest, signature))
+        })
+        .join()
+        .map_err(|_| "download thread panicked".to_string())?
+    }
+}
+
+fn signature_path(cache: &Path) -> PathBuf {
+    let mut path = cache.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Written next to `cache` and renamed into place, signature first, so a
+/// reader never pairs a new manifest with an old signature.
+fn store(cache: &Path, manifest: &[u8], signature: Option<&[u8]>) -> io::Result<()> {
+    if let Some(parent) = cache.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let write = |path: &Path, content: &[u8]| {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, path)
+    };
+    if let Some(signature) = signature {
+        write(&signature_path(cache), signature)?;
+    }
+    write(cache, manifest)
+}
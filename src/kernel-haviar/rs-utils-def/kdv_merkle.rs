@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The hash of one baseline entry: SHA-256 over a 0 byte, the path, a 0
+/// byte and the hex digest. The leading byte keeps a leaf from passing for
+/// an inner node; the path cannot hold a 0 byte, so the split is unambiguous.
+pub fn leaf(path: &str, digest: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    hasher.update(digest.as_bytes());
+    hasher.finalize().into()
+}
+
+fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The next level up: pairs hashed together, an odd last node carried up
+/// as it is.
+fn level(nodes: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    nodes
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// The root over `leaves` in order; SHA-256 of nothing for no leaves.
+pub fn root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    let mut nodes = leaves.to_vec();
+    while nodes.len() > 1 {
+        nodes = level(&nodes);
+    }
+    nodes[0]
+}
+
+/// What `kdv prove` prints: enough to tie one entry to a Merkle root
+/// without the rest of the baseline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MerkleProof {
+    pub path: String,
+    pub digest: String,
+    /// The entry's position among the baseline's entries sorted by path.
+    pub index: usize,
+    pub leaves: usize,
+    /// Hex hashes of the siblings from the leaf up; levels where the node
+    /// is carried up have none.
+    pub siblings: Vec<String>,
+    /// The hex root the proof was made against, for reference only:
+    /// `verify` takes the root to trust from the caller.
+    pub root: String,
+}
+
+impl MerkleProof {
+    /// The proof for the leaf at `index`, with `path` and `digest` being
+    /// what that leaf hashes.
+    pub fn new(leaves: &[[u8; 32]], index: usize, path: &str, digest: &str) -> Self {
+        let mut siblings = Vec::new();
+        let mut nodes = leaves.to_vec();
+        let mut position = index;
+        while nodes.len() > 1 {
+            let sibling = position ^ 1;
+            if sibling < nodes.len() {
+                siblings.push(hex::encode(nodes[sibling]));
+            }
+            nodes = level(&nodes);
+            position /= 2;
+        }
+        Self {
+            path: path.to_string(),
+            digest: digest.to_string(),
+            index,
+            leaves: leaves.len(),
+            siblings,
+            root: hex::encode(root(leaves)),
+        }
+    }
+
+    /// Whether the entry hashes up to `root` along the siblings.
+    pub fn verify(&self, root: &[u8; 32]) -> bool {
+        if self.index >= self.leaves {
+            return false;
+        }
+        let mut hash = leaf(&self.path, &self.digest);
+        let mut siblings = self.siblings.iter();
+        let (mut position, mut width) = (self.index, self.leaves);
+        while width > 1 {
+            if position ^ 1 < width {
+                let Some(sibling) = siblings.next().and_then(|sibling| <[u8; 32]>::try_from(hex::decode(sibling).ok()?).ok())
+                else {
+                    return false;
+                };
+                hash = match position % 2 {
+                    0 => node(&hash, &sibling),
+                    _ => node(&sibling, &hash),
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+/// Parses a hex Merkle root as `kdv verify-proof --merkle-root` takes it.
+pub fn parse_root(root: &str) -> Result<[u8; 32], String> {
+    hex::decode(root.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Merkle root {} is not a 64-digit hex SHA-256", root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<[u8; 32]> {
+        (0..count).map(|i| leaf(&format!("file{}", i), "00")).collect()
+    }
+
+    #[test]
+    fn test_every_leaf_proves_against_the_root_for_any_width() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = root(&leaves);
+            for index in 0..count {
+                let proof = MerkleProof::new(&leaves, index, &format!("file{}", index), "00");
+                assert!(proof.verify(&root), "{} of {}", index, count);
+                let forged = MerkleProof { digest: "01".into(), ..proof.clone() };
+                assert!(!forged.verify(&root));
+                let moved = MerkleProof { index: (index + 1) % count, ..proof };
+                assert!(count == 1 || !moved.verify(&root));
+            }
+        }
+    }
+
+    #[test]
+    fn test_leaf_and_node_hashes_do_not_mix() {
+        let two = leaves(2);
+        assert_ne!(root(&two), leaf("file0", "00"));
+        assert_ne!(root(&[]), root(&leaves(1)));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::kdv::HashAlgo;
+
+/// The checksum-file flavours `kdv import` and `kdv export` speak: one
+/// `<hex>  <path>` line per file, as GNU coreutils and b3sum write them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SumsFormat {
+    Sha256sum,
+    Sha512sum,
+    B3sum,
+}
+
+impl SumsFormat {
+    pub const NAMES: [&'static str; 3] = ["sha256sum", "sha512sum", "b3sum"];
+
+    pub fn algorithm(self) -> HashAlgo {
+        match self {
+            SumsFormat::Sha256sum => HashAlgo::Sha256,
+            SumsFormat::Sha512sum => HashAlgo::Sha512,
+            SumsFormat::B3sum => HashAlgo::Blake3,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SumsFormat::Sha256sum => "sha256sum",
+            SumsFormat::Sha512sum => "sha512sum",
+            SumsFormat::B3sum => "b3sum",
+        }
+    }
+
+    /// The flavour a sums file handed to `kdv check` most likely is, by the
+    /// length of its first digest. A 64-digit digest is taken for SHA-256.
+    pub fn guess(lines: &[(String, String)]) -> Self {
+        match lines.first() {
+            Some((digest, _)) if digest.len() == 128 => SumsFormat::Sha512sum,
+            _ => SumsFormat::Sha256sum,
+        }
+    }
+}
+
+impl std::str::FromStr for SumsFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "sha256sum" => Ok(SumsFormat::Sha256sum),
+            "sha512sum" => Ok(SumsFormat::Sha512sum),
+            "b3sum" => Ok(SumsFormat::B3sum),
+            _ => Err(format!("unknown checksum format {}", name)),
+        }
+    }
+}
+
+/// Whether `text` is a checksum file rather than a JSON baseline.
+pub fn is_sums(text: &str) -> bool {
+    !text.trim_start().starts_with('{')
+}
+
+/// The `(digest, path)` pairs of a checksum file. Both the text (`  `) and
+/// binary (` *`) markers are read, file names escaped by a leading `\` are
+/// unescaped, and blank lines and `#` comments are skipped.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut lines = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("line {} is not a <hex>  <path> checksum line", number + 1);
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (digest, rest) = line.split_once(' ').ok_or_else(invalid)?;
+        let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*')).ok_or_else(invalid)?;
+        if digest.is_empty() || !digest.bytes().all(|byte| byte.is_ascii_hexdigit()) || name.is_empty() {
+            return Err(invalid());
+        }
+        let name = match escaped {
+            true => unescape(name).ok_or_else(invalid)?,
+            false => name.to_string(),
+        };
+        lines.push((digest.to_ascii_lowercase(), name));
+    }
+    Ok(lines)
+}
+
+/// One line as coreutils writes it: a file name holding a backslash, a
+/// newline or a carriage return is escaped, and the line marked with `\`.
+pub fn format_line(digest: &str, name: &str) -> String {
+    if !name.contains(['\\', '\n', '\r']) {
+        return format!("{}  {}\n", digest, name);
+    }
+    let escaped = name.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+    format!("\\{}  {}\n", digest, escaped)
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next()? {
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // As GNU coreutils 9.1 `sha256sum *` printed them.
+    const COREUTILS: &str = "\
+\\3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d  back\\\\slash.txt
+\\2e7d2c03a9507ae265ecf5b5356885a53393a2029d241394997265a1a25aefc6  new\\nline.txt
+18ac3e7343f016890c510e93f935261169d9e3f565436429830faf0934f4f8e4  plain.txt
+ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb  with space.txt
+";
+
+    #[test]
+    fn test_coreutils_escapes_round_trip() {
+        let lines = parse(COREUTILS).unwrap();
+        let names: Vec<&str> = lines.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, ["back\\slash.txt", "new\nline.txt", "plain.txt", "with space.txt"]);
+        let written: String = lines.iter().map(|(digest, name)| format_line(digest, name)).collect();
+        assert_eq!(written, COREUTILS);
+    }
+
+    #[test]
+    fn test_binary_marker_and_bad_lines() {
+        let lines = parse("# comment\n\nABCD *bin/app\n").unwrap();
+        assert_eq!(lines, [("abcd".to_string(), "bin/app".to_string())]);
+        assert_eq!(parse("abcd bin/app\n").unwrap_err(), "line 1 is not a <hex>  <path> checksum line");
+        assert!(parse("\\abcd  bad\\qescape\n").is_err());
+        assert!(parse("SHA256 (app) = abcd\n").is_err());
+    }
+}
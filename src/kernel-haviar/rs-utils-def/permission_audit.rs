@@ -0,0 +1,215 @@
+use rand_core::{OsRng, RngCore};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hfs::user_name;
+use crate::permission_group::GroupCheck;
+use crate::permission_manager::AuthError;
+use crate::permission_privilege::{Identity, ProcessUids, UidSource};
+use crate::serialk_audit::{AuditLog, DEFAULT_AUDIT_KEEP};
+use crate::serialk_gate;
+
+/// Where `permission-manager` records requests unless `--audit-log` says
+/// otherwise.
+pub const DEFAULT_AUDIT_LOG: &str = "/var/log/serialkiller/permission-audit.log";
+
+/// The key of its HMAC chain unless `--audit-key` says otherwise; made on
+/// first use.
+pub const DEFAULT_AUDIT_KEY: &str = "/etc/serialkiller/permission-audit.key";
+
+/// Takes one line per permission request. A trait so tests can make writes
+/// fail.
+pub trait AuditSink: Send {
+    fn record(&mut self, entry: Value) -> io::Result<()>;
+}
+
+impl AuditSink for AuditLog {
+    /// Synced before returning: a grant is only as good as its record.
+    fn record(&mut self, entry: Value) -> io::Result<()> {
+        self.append(entry, true)
+    }
+}
+
+/// Opens the HMAC-chained log at `path`, creating its directory and, if
+/// there is none yet, a random key at `key_path` readable by root only.
+pub fn open_log(path: &Path, key_path: &Path) -> Result<AuditLog, String> {
+    if !key_path.exists() {
+        create_key(key_path).map_err(|e| format!("Cannot create audit key {}: {}", key_path.display(), e))?;
+    }
+    let key = serialk_gate::read_key_file(key_path, "audit key")?;
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| format!("Cannot open audit log {}: {}", path.display(), e))?;
+    }
+    AuditLog::open(path, key, None, DEFAULT_AUDIT_KEEP).map_err(|e| format!("Cannot open audit log {}: {}", path.display(), e))
+}
+
+/// Writes a random 256-bit key, as hex, readable by its owner only.
+pub fn create_key(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(hex::encode(key).as_bytes())
+}
+
+/// Who is asking: the effective user, and the one behind sudo if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requester {
+    pub euid: Option<u32>,
+    /// `SUDO_UID`.
+    pub sudo_uid: Option<u32>,
+    /// The real invoker: `SUDO_USER`, else the effective user's name.
+    pub user: String,
+}
+
+impl Requester {
+    pub fn current() -> Self {
+        Self::of(&ProcessUids)
+    }
+
+    pub fn of(uids: &dyn UidSource) -> Self {
+        let euid = match uids.identity() {
+            Ok(Identity::Uid(euid)) => Some(euid),
+            _ => None,
+        };
+        let (sudo_uid, sudo_user) = uids.sudo();
+        let user = sudo_user
+            .or_else(|| sudo_uid.and_then(user_name))
+            .or_else(|| euid.and_then(user_name))
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_else(|| "unknown".to_string());
+        Self { euid, sudo_uid, user }
+    }
+}
+
+/// Writes each request's outcome to an `AuditSink`.
+pub struct Auditor {
+    sink: Box<dyn AuditSink>,
+    requester: Requester,
+    binary: PathBuf,
+    /// Requests so far per user.
+    attempts: HashMap<String, usize>,
+    /// Whether a grant stands when its record cannot be written.
+    pub allow_unlogged: bool,
+}
+
+impl Auditor {
+    pub fn new(sink: Box<dyn AuditSink>, requester: Requester, allow_unlogged: bool) -> Self {
+        Self {
+            sink,
+            requester,
+            binary: std::env::current_exe().unwrap_or_default(),
+            attempts: HashMap::new(),
+            allow_unlogged,
+        }
+    }
+
+    /// Records a request for `user`, numbered among this process's
+    /// requests for them, how it ended, and how they fared against the
+    /// required group if they were checked.
+    pub fn record(&mut self, user: &str, outcome: &Result<(), AuthError>, group: Option<&GroupCheck>) -> io::Result<()> {
+        let attempt = self.attempts.entry(user.to_string()).or_insert(0);
+        *attempt += 1;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut line = serde_json::json!({
+            "event": "permission-request",
+            "timestamp": timestamp,
+            "requester": self.requester.user,
+            "user": user,
+            "outcome": match outcome {
+                Ok(()) => "granted",
+                Err(AuthError::Locked { .. }) => "locked",
+                Err(_) => "denied",
+            },
+            "attempt": *attempt,
+            "binary": self.binary.to_string_lossy(),
+        });
+        if let Some(euid) = self.requester.euid {
+            line["euid"] = euid.into();
+        }
+        if let Some(sudo_uid) = self.requester.sudo_uid {
+            line["sudo_uid"] = sudo_uid.into();
+        }
+        if let Some(check) = group {
+            line["group"] = check.group.clone().into();
+            if let Some(member) = check.member {
+                line["in_group"] = member.into();
+            }
+        }
+        if let Err(e) = outcome {
+            line["reason"] = e.to_string().into();
+        }
+        self.sink.record(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_capability::Capability;
+    use crate::permission_manager::PermissionManager;
+    use crate::serialk_audit;
+    use crate::test_support::Fixed;
+
+    /// A log on a full disk.
+    struct Unwritable;
+
+    impl AuditSink for Unwritable {
+        fn record(&mut self, _entry: Value) -> io::Result<()> {
+            Err(io::Error::other("no space left on device"))
+        }
+    }
+
+    fn requester() -> Requester {
+        Requester {
+            euid: Some(0),
+            sudo_uid: Some(1000),
+            user: "operator".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_grants_and_denials_are_chained_into_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, key) = (dir.path().join("log/audit.log"), dir.path().join("keys/audit.key"));
+        let log = open_log(&path, &key).unwrap();
+        let manager = PermissionManager::with_authenticator(Box::new(Fixed))
+            .with_audit(Auditor::new(Box::new(log), requester(), false));
+        assert_eq!(manager.request_permission("alice", "battery", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+        assert!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+        drop(manager);
+
+        let key = serialk_gate::read_key_file(&key, "audit key").unwrap();
+        assert_eq!(serialk_audit::verify_files(std::slice::from_ref(&path), &key), Ok(3));
+        let lines: Vec<Value> = fs::read_to_string(&path).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines[1]["outcome"], "denied");
+        assert_eq!(lines[1]["reason"], "authentication failed");
+        assert_eq!((&lines[2]["outcome"], &lines[2]["attempt"]), (&"granted".into(), &2.into()));
+        assert_eq!((&lines[2]["requester"], &lines[2]["user"], &lines[2]["euid"]), (&"operator".into(), &"alice".into(), &0.into()));
+        assert_eq!(lines[2]["sudo_uid"], 1000);
+        assert!(lines[2]["binary"].as_str().is_some_and(|binary| !binary.is_empty()));
+    }
+
+    #[test]
+    fn test_a_grant_that_cannot_be_logged_is_refused() {
+        let manager = PermissionManager::with_authenticator(Box::new(Fixed))
+            .with_audit(Auditor::new(Box::new(Unwritable), requester(), false));
+        assert_eq!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]), Err(AuthError::Unlogged));
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_err());
+        // A denial stands either way.
+        assert_eq!(manager.request_permission("alice", "battery", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+
+        let manager = PermissionManager::with_authenticator(Box::new(Fixed))
+            .with_audit(Auditor::new(Box::new(Unwritable), requester(), true));
+        assert!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+    }
+}
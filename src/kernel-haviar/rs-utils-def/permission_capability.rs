@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::permission_session::SessionToken;
+
+/// Something a session may be granted, from least to most sensitive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Watcher status, lists and verification runs.
+    ReadStatus,
+    /// Add, remove, re-arm, pause or resume watches.
+    ModifyWatchSet,
+    /// Write a kdv baseline.
+    UpdateBaseline,
+    /// Run a pself file.
+    ExecutePself,
+    /// Add users and change their passwords and grants.
+    ManageUsers,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::ReadStatus,
+        Capability::ModifyWatchSet,
+        Capability::UpdateBaseline,
+        Capability::ExecutePself,
+        Capability::ManageUsers,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::ReadStatus => "read-status",
+            Capability::ModifyWatchSet => "modify-watch-set",
+            Capability::UpdateBaseline => "update-baseline",
+            Capability::ExecutePself => "execute-pself",
+            Capability::ManageUsers => "manage-users",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        Capability::ALL.into_iter().find(|capability| capability.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Capability::ALL.iter().map(|capability| capability.name()).collect();
+            format!("unknown capability {} (expected one of {})", name, names.join(", "))
+        })
+    }
+}
+
+pub type Capabilities = BTreeSet<Capability>;
+
+/// Which commands need which capability, for `permission-manager --help`.
+pub fn required_by() -> Vec<(&'static str, Capability)> {
+    vec![
+        ("serialk-watcher status, ctl list|status|kdv-verify", Capability::ReadStatus),
+        ("serialk-watcher ctl (other commands)", Capability::ModifyWatchSet),
+        ("serialkiller kdv init|update", crate::kdv::UPDATE_CAPABILITY),
+        ("serialkiller run", crate::runner::REQUIRED_CAPABILITY),
+        ("permission-manager add-user", crate::permission_users::REQUIRED_CAPABILITY),
+    ]
+}
+
+/// Whose capabilities `PermissionManager::check_capability` looks at: any
+/// live session of a user, or one session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Holder<'a> {
+    User(&'a str),
+    Token(&'a SessionToken),
+}
+
+impl<'a> From<&'a str> for Holder<'a> {
+    fn from(user: &'a str) -> Self {
+        Holder::User(user)
+    }
+}
+
+impl<'a> From<&'a SessionToken> for Holder<'a> {
+    fn from(token: &'a SessionToken) -> Self {
+        Holder::Token(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_lockout::LockoutPolicy;
+    use crate::permission_manager::AuthError;
+    use crate::permission_users::{UsersFile, UsersFileAuthenticator};
+    use crate::test_support::{start_manager, MockClock};
+
+    #[test]
+    fn test_only_allowed_capabilities_are_granted() {
+        let mut file = UsersFile::default();
+        file.set_password("alice", "correct horse", &[], Some(&[Capability::ReadStatus, Capability::ModifyWatchSet]))
+            .unwrap();
+        file.set_password("root", "battery staple", &[], None).unwrap();
+        let authenticator = UsersFileAuthenticator::new(file).unwrap();
+        let manager = start_manager(authenticator, LockoutPolicy::default(), &MockClock::new(), None);
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        assert_eq!(manager.check_capability(&token, Capability::ReadStatus), Ok(()));
+        // Allowed, but not asked for.
+        assert_eq!(
+            manager.check_capability(&token, Capability::ModifyWatchSet),
+            Err(AuthError::MissingCapability(Capability::ModifyWatchSet))
+        );
+
+        let refused = manager.request_permission("alice", "correct horse", &[Capability::ModifyWatchSet, Capability::UpdateBaseline]);
+        assert_eq!(refused, Err(AuthError::MissingCapability(Capability::UpdateBaseline)));
+        assert_eq!(refused.unwrap_err().to_string(), "missing capability update-baseline");
+        assert_eq!(
+            manager.check_capability("alice", Capability::UpdateBaseline),
+            Err(AuthError::MissingCapability(Capability::UpdateBaseline))
+        );
+        assert_eq!(manager.check_capability("alice", Capability::ReadStatus), Ok(()));
+        assert_eq!(manager.check_capability("bob", Capability::ReadStatus), Err(AuthError::NoSession));
+
+        // A user without a list of capabilities may have any.
+        let token = manager.request_permission("root", "battery staple", &Capability::ALL).unwrap();
+        assert_eq!(manager.check_capability(&token, Capability::ManageUsers), Ok(()));
+        assert!(manager.revoke(&token));
+        assert_eq!(manager.check_capability(&token, Capability::ReadStatus), Err(AuthError::NoSession));
+    }
+
+    #[test]
+    fn test_names_parse_back() {
+        for capability in Capability::ALL {
+            assert_eq!(capability.name().parse(), Ok(capability));
+        }
+        assert!("root".parse::<Capability>().unwrap_err().contains("expected one of read-status"));
+    }
+}
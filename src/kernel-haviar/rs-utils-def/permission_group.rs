@@ -0,0 +1,273 @@
+use crate::permission_manager::AuthError;
+
+/// The group a user must be in to be granted anything, unless the users
+/// file or `--required-group` names another.
+pub const DEFAULT_REQUIRED_GROUP: &str = "serialk-admins";
+
+/// Looks up which groups a user is in, behind a trait so tests need no
+/// real accounts.
+pub trait GroupResolver: Send + Sync {
+    /// The names of `user`'s groups; none for a user the system does not
+    /// know.
+    fn groups(&self, user: &str) -> Result<Vec<String>, String>;
+}
+
+/// The system's groups: the primary and supplementary groups on Linux, the
+/// local groups, direct or through a global group, on Windows.
+pub struct SystemGroups;
+
+impl GroupResolver for SystemGroups {
+    #[cfg(target_os = "linux")]
+    fn groups(&self, user: &str) -> Result<Vec<String>, String> {
+        unix::groups(user)
+    }
+
+    #[cfg(windows)]
+    fn groups(&self, user: &str) -> Result<Vec<String>, String> {
+        windows::groups(user)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    fn groups(&self, _user: &str) -> Result<Vec<String>, String> {
+        Err("group membership cannot be checked on this platform".to_string())
+    }
+}
+
+/// How a user fared against the required group, for the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupCheck {
+    pub group: String,
+    /// `None` if their groups could not be looked up.
+    pub member: Option<bool>,
+}
+
+/// Only members of `name` may be granted permission.
+pub struct RequiredGroup {
+    pub name: String,
+    resolver: Box<dyn GroupResolver>,
+}
+
+impl RequiredGroup {
+    pub fn new(name: &str, resolver: Box<dyn GroupResolver>) -> Self {
+        Self {
+            name: name.to_string(),
+            resolver,
+        }
+    }
+
+    /// Whether `user` is a member. A lookup that fails refuses them too.
+    pub fn check(&self, user: &str) -> (GroupCheck, Result<(), AuthError>) {
+        let member = match self.resolver.groups(user) {
+            Ok(groups) => groups.iter().any(|group| same_group(group, &self.name)),
+            Err(e) => {
+                eprintln!("[ERROR] Cannot look up the groups of {}: {}", user, e);
+                let check = GroupCheck {
+                    group: self.name.clone(),
+                    member: None,
+                };
+                return (check, Err(AuthError::ServiceUnavailable));
+            }
+        };
+        let check = GroupCheck {
+            group: self.name.clone(),
+            member: Some(member),
+        };
+        (check, if member { Ok(()) } else { Err(AuthError::NotInGroup) })
+    }
+}
+
+/// Windows group names are case-insensitive.
+fn same_group(a: &str, b: &str) -> bool {
+    match cfg!(windows) {
+        true => a.eq_ignore_ascii_case(b),
+        false => a == b,
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod unix {
+    use std::ffi::{CStr, CString};
+    use std::io;
+
+    pub fn groups(user: &str) -> Result<Vec<String>, String> {
+        let Ok(name) = CString::new(user) else {
+            return Ok(Vec::new());
+        };
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buffer = vec![0 as libc::c_char; 4096];
+        let mut result = std::ptr::null_mut();
+        // SAFETY: getpwnam_r only writes into `pwd` and `buffer`.
+        let rc = unsafe { libc::getpwnam_r(name.as_ptr(), &mut pwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc).to_string());
+        }
+        if result.is_null() {
+            return Ok(Vec::new());
+        }
+
+        let mut gids: Vec<libc::gid_t> = vec![0; 32];
+        loop {
+            let mut count = gids.len() as libc::c_int;
+            // SAFETY: `gids` holds `count` entries; on -1 `count` is how many
+            // are needed.
+            if unsafe { libc::getgrouplist(name.as_ptr(), pwd.pw_gid, gids.as_mut_ptr(), &mut count) } >= 0 {
+                gids.truncate(count as usize);
+                break;
+            }
+            gids.resize((count as usize).max(gids.len() * 2), 0);
+        }
+        gids.into_iter().map(group_name).collect()
+    }
+
+    /// The name of `gid`, or the number for a group without one.
+    fn group_name(gid: libc::gid_t) -> Result<String, String> {
+        let mut grp: libc::group = unsafe { std::mem::zeroed() };
+        let mut buffer = vec![0 as libc::c_char; 4096];
+        loop {
+            let mut result = std::ptr::null_mut();
+            // SAFETY: getgrgid_r only writes into `grp` and `buffer`.
+            let rc = unsafe { libc::getgrgid_r(gid, &mut grp, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+            match rc {
+                libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+                0 if result.is_null() => return Ok(gid.to_string()),
+                // SAFETY: gr_name points into `buffer`, NUL-terminated.
+                0 => return Ok(unsafe { CStr::from_ptr(grp.gr_name) }.to_string_lossy().into_owned()),
+                rc => return Err(io::Error::from_raw_os_error(rc).to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use windows_sys::Win32::NetworkManagement::NetManagement::{
+        NetApiBufferFree, NetUserGetLocalGroups, LG_INCLUDE_INDIRECT, LOCALGROUP_USERS_INFO_0, MAX_PREFERRED_LENGTH,
+        NERR_Success, NERR_UserNotFound,
+    };
+
+    pub fn groups(user: &str) -> Result<Vec<String>, String> {
+        let user: Vec<u16> = user.encode_utf16().chain(Some(0)).collect();
+        let mut buffer: *mut u8 = std::ptr::null_mut();
+        let (mut read, mut total) = (0u32, 0u32);
+        // SAFETY: a null server is the local machine; `buffer` is freed below.
+        let rc = unsafe {
+            NetUserGetLocalGroups(
+                std::ptr::null(),
+                user.as_ptr(),
+                0,
+                LG_INCLUDE_INDIRECT,
+                &mut buffer,
+                MAX_PREFERRED_LENGTH,
+                &mut read,
+                &mut total,
+            )
+        };
+        match rc {
+            NERR_Success => {}
+            NERR_UserNotFound => return Ok(Vec::new()),
+            rc => return Err(format!("NetUserGetLocalGroups failed with error {}", rc)),
+        }
+        // SAFETY: on success `buffer` holds `read` entries of level 0.
+        let entries = unsafe { std::slice::from_raw_parts(buffer as *const LOCALGROUP_USERS_INFO_0, read as usize) };
+        let names = entries
+            .iter()
+            .map(|entry| {
+                // SAFETY: each name is a NUL-terminated wide string in `buffer`.
+                let len = (0..).take_while(|&i| unsafe { *entry.lgrui0_name.add(i) } != 0).count();
+                String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(entry.lgrui0_name, len) })
+            })
+            .collect();
+        unsafe { NetApiBufferFree(buffer as *const std::ffi::c_void) };
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_audit::{AuditSink, Auditor, Requester};
+    use crate::permission_capability::Capability;
+    use crate::permission_manager::PermissionManager;
+    use crate::test_support::Fixed;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// Groups from a fixture; `None` is a directory that cannot be reached.
+    struct MockGroups(Option<HashMap<&'static str, Vec<&'static str>>>);
+
+    impl GroupResolver for MockGroups {
+        fn groups(&self, user: &str) -> Result<Vec<String>, String> {
+            let fixture = self.0.as_ref().ok_or("LDAP server unreachable")?;
+            Ok(fixture.get(user).into_iter().flatten().map(|group| group.to_string()).collect())
+        }
+    }
+
+    fn fixture() -> MockGroups {
+        MockGroups(Some(HashMap::from([
+            ("alice", vec!["alice", "serialk-admins"]),
+            ("bob", vec!["bob", "wheel"]),
+        ])))
+    }
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<Value>>>);
+
+    impl AuditSink for Lines {
+        fn record(&mut self, entry: Value) -> io::Result<()> {
+            self.0.lock().unwrap().push(entry);
+            Ok(())
+        }
+    }
+
+    fn requester() -> Requester {
+        Requester {
+            euid: Some(0),
+            sudo_uid: None,
+            user: "root".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_only_members_are_granted_and_the_check_is_audited() {
+        let lines = Lines::default();
+        let manager = PermissionManager::with_authenticator(Box::new(Fixed))
+            .with_required_group(RequiredGroup::new(DEFAULT_REQUIRED_GROUP, Box::new(fixture())))
+            .with_audit(Auditor::new(Box::new(lines.clone()), requester(), false));
+        let read = [Capability::ReadStatus];
+        assert!(manager.request_permission("alice", "correct horse", &read).is_ok());
+        let refused = manager.request_permission("bob", "correct horse", &read);
+        assert_eq!(refused, Err(AuthError::NotInGroup));
+        assert_eq!(refused.unwrap_err().to_string(), "not a member of the required group");
+        // Unknown to the system, known to the authenticator.
+        assert_eq!(manager.request_permission("mallory", "correct horse", &read), Err(AuthError::NotInGroup));
+        // Knowing the group says nothing about the password.
+        assert_eq!(manager.request_permission("alice", "battery", &read), Err(AuthError::AuthFailed));
+
+        let lines = lines.0.lock().unwrap();
+        assert_eq!((&lines[0]["group"], &lines[0]["in_group"]), (&"serialk-admins".into(), &true.into()));
+        assert_eq!((&lines[1]["outcome"], &lines[1]["in_group"]), (&"denied".into(), &false.into()));
+        assert_eq!(lines[1]["reason"], "not a member of the required group");
+        assert!(lines[3].get("in_group").is_none(), "no check without the password");
+    }
+
+    #[test]
+    fn test_a_failed_lookup_refuses() {
+        let manager = PermissionManager::with_authenticator(Box::new(Fixed))
+            .with_required_group(RequiredGroup::new("wheel", Box::new(MockGroups(None))));
+        assert_eq!(
+            manager.request_permission("bob", "correct horse", &[Capability::ReadStatus]),
+            Err(AuthError::ServiceUnavailable)
+        );
+        let (check, _) = RequiredGroup::new("wheel", Box::new(fixture())).check("bob");
+        assert_eq!(check.member, Some(true));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_system_groups_include_the_primary_group() {
+        assert!(SystemGroups.groups("root").unwrap().iter().any(|group| group == "root"));
+        assert_eq!(SystemGroups.groups("no-such-user-serialk").unwrap(), Vec::<String>::new());
+    }
+}
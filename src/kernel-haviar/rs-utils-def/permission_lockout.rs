@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::permission_manager::MAX_ATTEMPTS;
+
+/// The time, so tests can move it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// `max_failures` wrong passwords within `window` lock the user for
+/// `duration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    pub max_failures: usize,
+    pub window: Duration,
+    pub duration: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: MAX_ATTEMPTS,
+            window: Duration::from_secs(15 * 60),
+            duration: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// One user's record, in seconds since the epoch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserRecord {
+    /// Failures within the window, oldest first.
+    #[serde(default)]
+    failures: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locked_until: Option<u64>,
+}
+
+/// Every user's failures and lock, as saved in the permission state file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockoutState {
+    #[serde(default)]
+    users: BTreeMap<String, UserRecord>,
+}
+
+impl LockoutState {
+    /// Forgets `user`'s failures and lock. Returns whether there was
+    /// anything to forget.
+    pub fn clear(&mut self, user: &str) -> bool {
+        self.users.remove(user).is_some()
+    }
+}
+
+/// Failed attempts per user. `PermissionManager` saves them to its state
+/// file after every change so a restart does not wipe them.
+pub struct Lockout {
+    policy: LockoutPolicy,
+    clock: Box<dyn Clock>,
+    state: LockoutState,
+}
+
+impl Lockout {
+    pub fn new(policy: LockoutPolicy, clock: Box<dyn Clock>) -> Self {
+        Self {
+            policy,
+            clock,
+            state: LockoutState::default(),
+        }
+    }
+
+    pub fn state(&self) -> &LockoutState {
+        &self.state
+    }
+
+    /// Continues from a saved `state`.
+    pub fn restore(&mut self, state: LockoutState) {
+        self.state = state;
+    }
+
+    /// When `user`'s lock ends, if they are locked now.
+    pub fn locked_until(&self, user: &str) -> Option<SystemTime> {
+        let now = secs(self.clock.now());
+        let until = self.state.users.get(user)?.locked_until?;
+        (until > now).then(|| UNIX_EPOCH + Duration::from_secs(until))
+    }
+
+    /// Counts a wrong password for `user`. Returns when the lock ends if
+    /// this failure locked them.
+    pub fn record_failure(&mut self, user: &str) -> Option<SystemTime> {
+        let now = secs(self.clock.now());
+        let window = self.policy.window.as_secs();
+        let record = self.state.users.entry(user.to_string()).or_default();
+        if record.locked_until.is_some_and(|until| until <= now) {
+            record.locked_until = None;
+        }
+        record.failures.retain(|&failure| now.saturating_sub(failure) < window);
+        record.failures.push(now);
+        let until = (record.failures.len() >= self.policy.max_failures.max(1)).then(|| now + self.policy.duration.as_secs());
+        if until.is_some() {
+            record.failures.clear();
+            record.locked_until = until;
+        }
+        until.map(|until| UNIX_EPOCH + Duration::from_secs(until))
+    }
+
+    /// Forgets `user`'s failures and lock, after a success or by an admin.
+    /// Returns whether there was anything to forget.
+    pub fn clear(&mut self, user: &str) -> bool {
+        self.state.clear(user)
+    }
+}
+
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// `duration` as e.g. `14m 05s`, for telling a locked user how long to wait.
+pub fn remaining(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_capability::Capability;
+    use crate::permission_manager::AuthError;
+    use crate::test_support::{start_manager, state_file, Fixed, MockClock};
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 3,
+            window: Duration::from_secs(60),
+            duration: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn test_lock_expires_and_survives_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, policy(), &clock, Some(dir.path()));
+        for _ in 1..policy().max_failures {
+            assert_eq!(manager.request_permission("alice", "battery", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+            clock.advance(Duration::from_secs(10));
+        }
+        let locked = manager.request_permission("alice", "battery", &[Capability::ReadStatus]);
+        let until = clock.now() + policy().duration;
+        assert_eq!(locked, Err(AuthError::Locked { until }));
+        drop(manager);
+
+        // A new process reads the lock back and still refuses the right password.
+        clock.advance(Duration::from_secs(299));
+        let restarted = start_manager(Fixed, policy(), &clock, Some(dir.path()));
+        assert_eq!(restarted.request_permission("alice", "correct horse", &[Capability::ReadStatus]), Err(AuthError::Locked { until }));
+        assert!(restarted.request_permission("bob", "correct horse", &[Capability::ReadStatus]).is_ok());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(restarted.request_permission("alice", "battery", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+        assert!(restarted.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+        assert_eq!(state_file(dir.path()).load().unwrap().lockout, LockoutState::default(), "a success clears the record");
+    }
+
+    #[test]
+    fn test_unlock_clears_the_saved_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, policy(), &clock, Some(dir.path()));
+        for _ in 0..policy().max_failures {
+            let _ = manager.request_permission("alice", "battery", &[Capability::ReadStatus]);
+        }
+        assert!(matches!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]), Err(AuthError::Locked { .. })));
+        drop(manager);
+
+        let admin = start_manager(Fixed, policy(), &clock, Some(dir.path()));
+        assert_eq!(admin.unlock("alice"), Ok(true));
+        assert_eq!(admin.unlock("alice"), Ok(false));
+        drop(admin);
+        let restarted = start_manager(Fixed, policy(), &clock, Some(dir.path()));
+        assert!(restarted.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+    }
+
+    #[test]
+    fn test_failures_outside_the_window_do_not_lock() {
+        let clock = MockClock::new();
+        let mut lockout = Lockout::new(policy(), Box::new(clock.clone()));
+        for _ in 0..5 {
+            assert_eq!(lockout.record_failure("alice"), None);
+            clock.advance(Duration::from_secs(31));
+        }
+        assert_eq!(lockout.locked_until("alice"), None);
+    }
+
+    #[test]
+    fn test_remaining_time_reads_naturally() {
+        assert_eq!(remaining(Duration::from_secs(7)), "7s");
+        assert_eq!(remaining(Duration::from_secs(845)), "14m 05s");
+        assert_eq!(remaining(Duration::from_secs(3725)), "1h 02m 05s");
+    }
+}
@@ -1,61 +1,707 @@
 use std::sync::Mutex;
-use std::collections::HashMap;
-use std::process::Command;
+use std::fmt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::hfs_log::timestamp;
+use crate::permission_audit::Auditor;
+use crate::permission_capability::{Capabilities, Capability, Holder};
+use crate::permission_group::{RequiredGroup, SystemGroups, DEFAULT_REQUIRED_GROUP};
+use crate::permission_lockout::{Lockout, LockoutPolicy, SystemClock};
+use crate::permission_session::{SessionStatus, SessionToken, Sessions, DEFAULT_SESSION_TTL};
+use crate::permission_state::{PermissionState, StateFile};
+use crate::permission_users::{UsersFile, UsersFileAuthenticator};
+
+/// Passwords the CLI asks for, and by default the failures that lock a
+/// user out.
+pub const MAX_ATTEMPTS: usize = 2;
+
+/// The PAM service `PermissionManager::new` authenticates against; see
+/// `pam.d/serialkiller` for a configuration to install as
+/// `/etc/pam.d/serialkiller`.
+pub const PAM_SERVICE: &str = "serialkiller";
+
+/// Why a permission request was refused. Only the category is kept, so
+/// nothing about the account leaks beyond what the user needs to act.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// Wrong password, or no such user: the two are not told apart.
+    AuthFailed,
+    AccountExpired,
+    /// The password is right but has to be changed first.
+    PasswordExpired,
+    /// The account may not be used now, e.g. outside its allowed hours.
+    AccountDenied,
+    /// Too many failed attempts; asking again before `until` is refused
+    /// without checking the password.
+    Locked { until: SystemTime },
+    /// libpam or the service's configuration could not be used.
+    ServiceUnavailable,
+    /// The password was right but the grant could not be written to the
+    /// audit log.
+    Unlogged,
+    /// The password was right but the user is not in the required group.
+    NotInGroup,
+    /// The user may not be granted this capability, or was not.
+    MissingCapability(Capability),
+    /// No session that has not expired.
+    NoSession,
+    /// No way to authenticate on this platform.
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    NotSupported,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::AuthFailed => f.write_str("authentication failed"),
+            AuthError::AccountExpired => f.write_str("account expired"),
+            AuthError::PasswordExpired => f.write_str("password expired"),
+            AuthError::AccountDenied => f.write_str("account not permitted"),
+            AuthError::Locked { until } => write!(f, "too many failed attempts; locked until {}", timestamp(*until)),
+            AuthError::ServiceUnavailable => f.write_str("authentication service unavailable"),
+            AuthError::Unlogged => f.write_str("the grant could not be written to the audit log"),
+            AuthError::NotInGroup => f.write_str("not a member of the required group"),
+            AuthError::MissingCapability(capability) => write!(f, "missing capability {}", capability),
+            AuthError::NoSession => f.write_str("no valid session"),
+            AuthError::NotSupported => f.write_str("password authentication is not supported on this platform"),
+        }
+    }
+}
+
+/// Checks a user's password.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, user: &str, password: &str) -> Result<(), AuthError>;
+
+    /// What `user` may be granted once authenticated. PAM and Windows
+    /// accounts have no such list, so by default anyone may have anything.
+    fn capabilities(&self, _user: &str) -> Capabilities {
+        Capability::ALL.into_iter().collect()
+    }
+}
+
+/// Status codes of Linux-PAM.
+pub mod pam_code {
+    use std::ffi::c_int;
+
+    pub const SUCCESS: c_int = 0;
+    pub const PERM_DENIED: c_int = 6;
+    pub const AUTH_ERR: c_int = 7;
+    pub const CRED_INSUFFICIENT: c_int = 8;
+    pub const USER_UNKNOWN: c_int = 10;
+    pub const MAXTRIES: c_int = 11;
+    pub const NEW_AUTHTOK_REQD: c_int = 12;
+    pub const ACCT_EXPIRED: c_int = 13;
+    pub const CONV_ERR: c_int = 19;
+}
+
+/// One PAM transaction for a user, its conversation answering every
+/// password prompt with the password it was started with. A trait so the
+/// mapping of PAM's answers can be tested without libpam or root.
+pub trait PamConversation {
+    /// `pam_authenticate`.
+    fn authenticate(&mut self) -> std::ffi::c_int;
+    /// `pam_acct_mgmt`, after a successful `authenticate`.
+    fn account(&mut self) -> std::ffi::c_int;
+}
+
+/// Authenticates and then checks the account, as a login would.
+pub fn pam_verdict(pam: &mut dyn PamConversation) -> Result<(), AuthError> {
+    match pam.authenticate() {
+        pam_code::SUCCESS => {}
+        // An unknown user fails like a wrong password.
+        pam_code::AUTH_ERR | pam_code::CRED_INSUFFICIENT | pam_code::USER_UNKNOWN | pam_code::MAXTRIES => {
+            return Err(AuthError::AuthFailed)
+        }
+        _ => return Err(AuthError::ServiceUnavailable),
+    }
+    match pam.account() {
+        pam_code::SUCCESS => Ok(()),
+        pam_code::ACCT_EXPIRED => Err(AuthError::AccountExpired),
+        pam_code::NEW_AUTHTOK_REQD => Err(AuthError::PasswordExpired),
+        pam_code::PERM_DENIED | pam_code::USER_UNKNOWN => Err(AuthError::AccountDenied),
+        _ => Err(AuthError::ServiceUnavailable),
+    }
+}
+
+/// Authenticates through PAM with `service`'s configuration. Linux only.
+pub struct PamAuthenticator {
+    pub service: String,
+}
+
+impl Authenticator for PamAuthenticator {
+    #[cfg(target_os = "linux")]
+    fn authenticate(&self, user: &str, password: &str) -> Result<(), AuthError> {
+        let mut session = libpam::Session::start(&self.service, user, password)?;
+        pam_verdict(&mut session)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn authenticate(&self, _user: &str, _password: &str) -> Result<(), AuthError> {
+        Err(AuthError::NotSupported)
+    }
+}
+
+/// Error codes `LogonUserW` leaves in `GetLastError`.
+pub mod logon_code {
+    pub const LOGON_FAILURE: u32 = 1326;
+    pub const ACCOUNT_RESTRICTION: u32 = 1327;
+    pub const INVALID_LOGON_HOURS: u32 = 1328;
+    pub const PASSWORD_EXPIRED: u32 = 1330;
+    pub const ACCOUNT_DISABLED: u32 = 1331;
+    pub const ACCOUNT_EXPIRED: u32 = 1793;
+    pub const PASSWORD_MUST_CHANGE: u32 = 1907;
+    pub const ACCOUNT_LOCKED_OUT: u32 = 1909;
+}
+
+/// What a failed `LogonUserW` means, by its `GetLastError`.
+#[cfg_attr(not(all(windows, feature = "windows-logon")), allow(dead_code))]
+pub fn logon_verdict(error: u32) -> AuthError {
+    match error {
+        logon_code::LOGON_FAILURE => AuthError::AuthFailed,
+        logon_code::ACCOUNT_EXPIRED => AuthError::AccountExpired,
+        logon_code::PASSWORD_EXPIRED | logon_code::PASSWORD_MUST_CHANGE => AuthError::PasswordExpired,
+        logon_code::ACCOUNT_RESTRICTION
+        | logon_code::INVALID_LOGON_HOURS
+        | logon_code::ACCOUNT_DISABLED
+        | logon_code::ACCOUNT_LOCKED_OUT => AuthError::AccountDenied,
+        _ => AuthError::ServiceUnavailable,
+    }
+}
+
+/// Authenticates local Windows accounts with a network logon, which checks
+/// the password and the account without loading a profile.
+#[cfg(all(windows, feature = "windows-logon"))]
+pub struct LogonAuthenticator;
+
+#[cfg(all(windows, feature = "windows-logon"))]
+impl Authenticator for LogonAuthenticator {
+    fn authenticate(&self, user: &str, password: &str) -> Result<(), AuthError> {
+        use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, HANDLE};
+        use windows_sys::Win32::Security::{LogonUserW, LOGON32_LOGON_NETWORK, LOGON32_PROVIDER_DEFAULT};
+        use zeroize::Zeroizing;
+
+        let wide = |text: &str| text.encode_utf16().chain(Some(0)).collect::<Vec<u16>>();
+        let (user, domain) = (wide(user), wide("."));
+        let password = Zeroizing::new(wide(password));
+        let mut token: HANDLE = std::ptr::null_mut();
+        // SAFETY: the strings are NUL-terminated and outlive the call.
+        let ok = unsafe {
+            LogonUserW(
+                user.as_ptr(),
+                domain.as_ptr(),
+                password.as_ptr(),
+                LOGON32_LOGON_NETWORK,
+                LOGON32_PROVIDER_DEFAULT,
+                &mut token,
+            )
+        };
+        if ok == 0 {
+            return Err(logon_verdict(unsafe { GetLastError() }));
+        }
+        unsafe { CloseHandle(token) };
+        Ok(())
+    }
+}
+
+/// libpam, opened at run time so building needs no PAM headers and a
+/// system without PAM only loses this subcommand.
+#[cfg(target_os = "linux")]
+mod libpam {
+    use super::{pam_code, AuthError, PamConversation};
+    use std::ffi::{c_char, c_int, c_void, CStr, CString};
+    use std::ptr;
+    use zeroize::Zeroizing;
+
+    const PAM_PROMPT_ECHO_OFF: c_int = 1;
+    const PAM_PROMPT_ECHO_ON: c_int = 2;
+
+    #[repr(C)]
+    struct Message {
+        msg_style: c_int,
+        msg: *const c_char,
+    }
+
+    #[repr(C)]
+    struct Response {
+        resp: *mut c_char,
+        resp_retcode: c_int,
+    }
+
+    type ConvFn = extern "C" fn(c_int, *mut *const Message, *mut *mut Response, *mut c_void) -> c_int;
+
+    #[repr(C)]
+    struct Conv {
+        conv: ConvFn,
+        appdata_ptr: *mut c_void,
+    }
+
+    type StartFn = unsafe extern "C" fn(*const c_char, *const c_char, *const Conv, *mut *mut c_void) -> c_int;
+    type StepFn = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+
+    /// Answers prompts for a password with the NUL-terminated one at
+    /// `appdata` and anything else with nothing. Responses are `malloc`ed;
+    /// PAM frees them.
+    extern "C" fn converse(count: c_int, messages: *mut *const Message, responses: *mut *mut Response, appdata: *mut c_void) -> c_int {
+        if count <= 0 || messages.is_null() || responses.is_null() || appdata.is_null() {
+            return pam_code::CONV_ERR;
+        }
+        let password = appdata as *const c_char;
+        let answers = unsafe { libc::calloc(count as usize, std::mem::size_of::<Response>()) } as *mut Response;
+        if answers.is_null() {
+            return pam_code::CONV_ERR;
+        }
+        for i in 0..count as usize {
+            // Linux-PAM passes an array of pointers to messages.
+            let message = unsafe { &**messages.add(i) };
+            if matches!(message.msg_style, PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON) {
+                unsafe { (*answers.add(i)).resp = libc::strdup(password) };
+            }
+        }
+        unsafe { *responses = answers };
+        pam_code::SUCCESS
+    }
+
+    pub struct Session {
+        library: *mut c_void,
+        handle: *mut c_void,
+        authenticate: StepFn,
+        acct_mgmt: StepFn,
+        end: StepFn,
+        last: c_int,
+        // Borrowed by PAM until `pam_end`; the password is wiped after.
+        _conv: Box<Conv>,
+        _password: Zeroizing<Vec<u8>>,
+    }
+
+    impl Session {
+        pub fn start(service: &str, user: &str, password: &str) -> Result<Self, AuthError> {
+            // A NUL cannot be part of a real name or password.
+            let (Ok(service), Ok(user), Ok(password)) = (CString::new(service), CString::new(user), CString::new(password)) else {
+                return Err(AuthError::AuthFailed);
+            };
+            let library = unsafe { libc::dlopen(c"libpam.so.0".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+            if library.is_null() {
+                return Err(AuthError::ServiceUnavailable);
+            }
+            let symbol = |name: &CStr| {
+                let symbol = unsafe { libc::dlsym(library, name.as_ptr()) };
+                (!symbol.is_null()).then_some(symbol)
+            };
+            let symbols = (symbol(c"pam_start"), symbol(c"pam_authenticate"), symbol(c"pam_acct_mgmt"), symbol(c"pam_end"));
+            let (Some(start), Some(authenticate), Some(acct_mgmt), Some(end)) = symbols else {
+                unsafe { libc::dlclose(library) };
+                return Err(AuthError::ServiceUnavailable);
+            };
+            let (start, authenticate, acct_mgmt, end) = unsafe {
+                (
+                    std::mem::transmute::<*mut c_void, StartFn>(start),
+                    std::mem::transmute::<*mut c_void, StepFn>(authenticate),
+                    std::mem::transmute::<*mut c_void, StepFn>(acct_mgmt),
+                    std::mem::transmute::<*mut c_void, StepFn>(end),
+                )
+            };
+            let password = Zeroizing::new(password.into_bytes_with_nul());
+            let conv = Box::new(Conv {
+                conv: converse,
+                appdata_ptr: password.as_ptr() as *mut c_void,
+            });
+            let mut handle = ptr::null_mut();
+            let started = unsafe { start(service.as_ptr(), user.as_ptr(), &*conv, &mut handle) };
+            if started != pam_code::SUCCESS || handle.is_null() {
+                unsafe { libc::dlclose(library) };
+                return Err(AuthError::ServiceUnavailable);
+            }
+            Ok(Self {
+                library,
+                handle,
+                authenticate,
+                acct_mgmt,
+                end,
+                last: started,
+                _conv: conv,
+                _password: password,
+            })
+        }
+    }
+
+    impl PamConversation for Session {
+        fn authenticate(&mut self) -> c_int {
+            self.last = unsafe { (self.authenticate)(self.handle, 0) };
+            self.last
+        }
+
+        fn account(&mut self) -> c_int {
+            self.last = unsafe { (self.acct_mgmt)(self.handle, 0) };
+            self.last
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            unsafe {
+                (self.end)(self.handle, self.last);
+                libc::dlclose(self.library);
+            }
+        }
+    }
+}
 
 pub struct PermissionManager {
-    permissions: Mutex<HashMap<String, bool>>, // permission status
-    password_attempts: Mutex<HashMap<String, usize>>, // number of attempts per user
+    sessions: Mutex<Sessions>,
+    lockout: Mutex<Lockout>,
+    audit: Option<Mutex<Auditor>>,
+    required_group: Option<RequiredGroup>,
+    state_file: Option<StateFile>,
+    authenticator: Box<dyn Authenticator>,
 }
 
 impl PermissionManager {
+    /// Authenticates through PAM's `serialkiller` service, or on Windows
+    /// built with the `windows-logon` feature, against local accounts. Only
+    /// members of `DEFAULT_REQUIRED_GROUP` are granted permission.
     pub fn new() -> Self {
+        #[cfg(all(windows, feature = "windows-logon"))]
+        let manager = Self::with_authenticator(Box::new(LogonAuthenticator));
+        #[cfg(not(all(windows, feature = "windows-logon")))]
+        let manager = Self::with_authenticator(Box::new(PamAuthenticator {
+            service: PAM_SERVICE.to_string(),
+        }));
+        manager.with_required_group(RequiredGroup::new(DEFAULT_REQUIRED_GROUP, Box::new(SystemGroups)))
+    }
+
+    /// Authenticates against the argon2id hashes of a users file instead,
+    /// for systems without PAM. The file's `required_group` replaces the
+    /// default; an empty one requires none.
+    pub fn with_users_file(path: &Path) -> Result<Self, String> {
+        let file = UsersFile::load(path)?;
+        let group = file.required_group.clone().unwrap_or_else(|| DEFAULT_REQUIRED_GROUP.to_string());
+        let manager = Self::with_authenticator(Box::new(UsersFileAuthenticator::new(file)?));
+        Ok(match group.is_empty() {
+            true => manager,
+            false => manager.with_required_group(RequiredGroup::new(&group, Box::new(SystemGroups))),
+        })
+    }
+
+    /// Locks users out by the default policy, grants sessions of the
+    /// default TTL, keeps both in memory only until `with_state_file` says
+    /// otherwise, and requires no group.
+    pub fn with_authenticator(authenticator: Box<dyn Authenticator>) -> Self {
         Self {
-            permissions: Mutex::new(HashMap::new()),
-            password_attempts: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(Sessions::new(DEFAULT_SESSION_TTL, Box::new(SystemClock))),
+            lockout: Mutex::new(Lockout::new(LockoutPolicy::default(), Box::new(SystemClock))),
+            audit: None,
+            required_group: None,
+            state_file: None,
+            authenticator,
         }
     }
 
-    // 🔓 Now public: accessible from other modules
-    pub fn is_root_user() -> bool {
-        match Command::new("id").arg("-u").output() {
-            Ok(output) => output.stdout == b"0\n",
-            Err(_) => false,
+    pub fn with_lockout(mut self, lockout: Lockout) -> Self {
+        self.lockout = Mutex::new(lockout);
+        self
+    }
+
+    /// Only grants members of `group`, or anyone if `None`.
+    pub fn with_required_group(mut self, group: impl Into<Option<RequiredGroup>>) -> Self {
+        self.required_group = group.into();
+        self
+    }
+
+    pub fn with_sessions(mut self, sessions: Sessions) -> Self {
+        self.sessions = Mutex::new(sessions);
+        self
+    }
+
+    /// Continues from the lockouts and sessions saved in `file`, and saves
+    /// them there after every change. Comes after `with_lockout` and
+    /// `with_sessions`, which would start them afresh.
+    pub fn with_state_file(mut self, file: StateFile) -> Result<Self, String> {
+        let state = file.load()?;
+        self.lockout.get_mut().unwrap().restore(state.lockout);
+        self.sessions.get_mut().unwrap().restore(state.sessions);
+        self.state_file = Some(file);
+        Ok(self)
+    }
+
+    /// Records every request from now on; a grant that cannot be recorded
+    /// is refused unless the auditor allows unlogged grants.
+    pub fn with_audit(mut self, auditor: Auditor) -> Self {
+        self.audit = Some(Mutex::new(auditor));
+        self
+    }
+
+    /// Starts a session for `user` with the `requested` capabilities if
+    /// `password` authenticates them, they are in the required group, and
+    /// they may have them all. Enough
+    /// wrong passwords within the lockout policy's window lock the user out
+    /// until the returned time, without their password being checked, and
+    /// end their sessions; a success forgets earlier failures. With an
+    /// audit log every request is recorded, and a grant that cannot be is
+    /// `Unlogged`.
+    pub fn request_permission(&self, user: &str, password: &str, requested: &[Capability]) -> Result<SessionToken, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut group_check = None;
+        let outcome = self.authorize(user, password).and_then(|()| {
+            // Checked only once the password is known to be right, so the
+            // answer says nothing to someone guessing.
+            if let Some(group) = &self.required_group {
+                let (check, member) = group.check(user);
+                group_check = Some(check);
+                member?;
+            }
+            let allowed = self.authenticator.capabilities(user);
+            match requested.iter().find(|capability| !allowed.contains(capability)) {
+                Some(&missing) => Err(AuthError::MissingCapability(missing)),
+                None => Ok(()),
+            }
+        });
+        let outcome = match (&self.audit, outcome) {
+            (None, outcome) => outcome,
+            (Some(audit), outcome) => {
+                let mut audit = audit.lock().unwrap();
+                match (audit.record(user, &outcome, group_check.as_ref()), outcome) {
+                    (Ok(()), outcome) => outcome,
+                    (Err(e), Ok(())) if !audit.allow_unlogged => {
+                        eprintln!("[ERROR] Cannot write audit log: {}", e);
+                        Err(AuthError::Unlogged)
+                    }
+                    (Err(e), outcome) => {
+                        eprintln!("[WARN] Cannot write audit log: {}", e);
+                        outcome
+                    }
+                }
+            }
+        };
+        let granted = match outcome {
+            Ok(()) => Ok(sessions.issue(user, requested.iter().copied().collect())),
+            Err(e @ (AuthError::Locked { .. } | AuthError::Unlogged)) => {
+                sessions.revoke_user(user);
+                Err(e)
+            }
+            Err(e) => Err(e),
+        };
+        self.save(&sessions);
+        granted
+    }
+
+    fn authorize(&self, user: &str, password: &str) -> Result<(), AuthError> {
+        let mut lockout = self.lockout.lock().unwrap();
+        if let Some(until) = lockout.locked_until(user) {
+            return Err(AuthError::Locked { until });
+        }
+
+        match self.authenticator.authenticate(user, password) {
+            Ok(()) => {
+                lockout.clear(user);
+                Ok(())
+            }
+            // Only a wrong password counts; the others say nothing about the guess.
+            Err(AuthError::AuthFailed) => match lockout.record_failure(user) {
+                Some(until) => Err(AuthError::Locked { until }),
+                None => Err(AuthError::AuthFailed),
+            },
+            Err(e) => Err(e),
         }
     }
 
-    pub fn request_permission(&self, user: &str, password: &str) -> bool {
-        const MAX_ATTEMPTS: usize = 2;
-        const VALID_PASSWORD: &str = "s3cretpass";
+    /// Lifts `user`'s lock and forgets their failures. Returns whether
+    /// there were any. Unlike a request, an unlock that cannot be saved
+    /// fails: it would not outlive the process.
+    pub fn unlock(&self, user: &str) -> Result<bool, String> {
+        let sessions = self.sessions.lock().unwrap();
+        let cleared = self.lockout.lock().unwrap().clear(user);
+        if cleared {
+            self.write_state(&sessions)?;
+        }
+        Ok(cleared)
+    }
 
-        if !Self::is_root_user() {
-            println!("Error: Root permission is required.");
-            return false;
+    /// Whether a session of `holder` that has not expired was granted
+    /// `capability`.
+    pub fn check_capability<'a>(&self, holder: impl Into<Holder<'a>>, capability: Capability) -> Result<(), AuthError> {
+        let granted = match holder.into() {
+            Holder::User(user) => self.sessions.lock().unwrap().capabilities_of(user),
+            Holder::Token(token) => match self.validate(token) {
+                SessionStatus::Valid { capabilities, .. } => Some(capabilities),
+                SessionStatus::Expired | SessionStatus::Unknown => None,
+            },
+        };
+        match granted {
+            None => Err(AuthError::NoSession),
+            Some(granted) if granted.contains(&capability) => Ok(()),
+            Some(_) => Err(AuthError::MissingCapability(capability)),
         }
+    }
 
-        let mut attempts = self.password_attempts.lock().unwrap();
-        let mut perms = self.permissions.lock().unwrap();
+    /// What `token` is worth now. An expired session is dropped, and the
+    /// state file saved without it.
+    pub fn validate(&self, token: &SessionToken) -> SessionStatus {
+        let mut sessions = self.sessions.lock().unwrap();
+        let status = sessions.validate(token);
+        if status == SessionStatus::Expired {
+            self.save(&sessions);
+        }
+        status
+    }
 
-        let count = attempts.entry(user.to_string()).or_insert(0);
+    /// Ends a session before its TTL. Returns whether it was live.
+    pub fn revoke(&self, token: &SessionToken) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        let revoked = sessions.revoke(token);
+        if revoked {
+            self.save(&sessions);
+        }
+        revoked
+    }
 
-        if *count >= MAX_ATTEMPTS {
-            println!("User {} has exceeded the maximum password attempts!", user);
-            perms.insert(user.to_string(), false);
-            return false;
+    /// Drops expired sessions; see `permission_session::spawn_pruner`.
+    pub fn prune_sessions(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let pruned = sessions.prune();
+        if pruned > 0 {
+            self.save(&sessions);
         }
+        pruned
+    }
 
-        if password == VALID_PASSWORD {
-            perms.insert(user.to_string(), true);
-            attempts.insert(user.to_string(), 0);
-            true
-        } else {
-            *count += 1;
-            println!("Invalid password attempt {} for user {}", count, user);
-            false
+    /// Writes the lockouts and `sessions`, whose lock the caller holds, to
+    /// the state file if there is one. What cannot be saved still holds
+    /// until the process ends, so this warns rather than failing the
+    /// request.
+    fn save(&self, sessions: &Sessions) {
+        if let Err(e) = self.write_state(sessions) {
+            eprintln!("[WARN] {}", e);
         }
     }
 
-    pub fn check_permission(&self, user: &str) -> bool {
-        let perms = self.permissions.lock().unwrap();
-        perms.get(user).cloned().unwrap_or(false)
+    fn write_state(&self, sessions: &Sessions) -> Result<(), String> {
+        let Some(file) = &self.state_file else {
+            return Ok(());
+        };
+        let state = PermissionState {
+            lockout: self.lockout.lock().unwrap().state().clone(),
+            sessions: sessions.saved(),
+        };
+        file.save(&state)
+            .map_err(|e| format!("Cannot save permission state {}: {}", file.path().display(), e))
+    }
+
+    #[cfg(test)]
+    pub fn session_count(&self) -> usize {
+        self.sessions.lock().unwrap().prune();
+        self.sessions.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_int;
+
+    /// A PAM stack whose answers are fixed up front.
+    struct MockPam {
+        password: String,
+        expected: &'static str,
+        account: c_int,
+        accounted: bool,
+    }
+
+    impl PamConversation for MockPam {
+        fn authenticate(&mut self) -> c_int {
+            match self.password == self.expected {
+                true => pam_code::SUCCESS,
+                false => pam_code::AUTH_ERR,
+            }
+        }
+
+        fn account(&mut self) -> c_int {
+            self.accounted = true;
+            self.account
+        }
+    }
+
+    /// Answers as a PAM stack knowing only "alice" would.
+    struct MockAuthenticator {
+        account: c_int,
+    }
+
+    impl Authenticator for MockAuthenticator {
+        fn authenticate(&self, user: &str, password: &str) -> Result<(), AuthError> {
+            if user != "alice" {
+                return Err(AuthError::AuthFailed);
+            }
+            pam_verdict(&mut mock(password, self.account))
+        }
+    }
+
+    fn mock(password: &str, account: c_int) -> MockPam {
+        MockPam {
+            password: password.to_string(),
+            expected: "correct horse",
+            account,
+            accounted: false,
+        }
+    }
+
+    #[test]
+    fn test_pam_answers_map_to_categories() {
+        assert_eq!(pam_verdict(&mut mock("correct horse", pam_code::SUCCESS)), Ok(()));
+        let mut wrong = mock("battery", pam_code::SUCCESS);
+        assert_eq!(pam_verdict(&mut wrong), Err(AuthError::AuthFailed));
+        assert!(!wrong.accounted, "the account is only checked after authenticating");
+        assert_eq!(pam_verdict(&mut mock("correct horse", pam_code::ACCT_EXPIRED)), Err(AuthError::AccountExpired));
+        assert_eq!(pam_verdict(&mut mock("correct horse", pam_code::NEW_AUTHTOK_REQD)), Err(AuthError::PasswordExpired));
+        assert_eq!(pam_verdict(&mut mock("correct horse", pam_code::PERM_DENIED)), Err(AuthError::AccountDenied));
+        assert_eq!(pam_verdict(&mut mock("correct horse", pam_code::CONV_ERR)), Err(AuthError::ServiceUnavailable));
+    }
+
+    #[test]
+    fn test_wrong_passwords_lock_the_user_out() {
+        let manager = PermissionManager::with_authenticator(Box::new(MockAuthenticator {
+            account: pam_code::SUCCESS,
+        }));
+        assert_eq!(manager.request_permission("alice", "battery", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+        assert!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_ok());
+
+        // A success resets the count; two failures in a row lock.
+        assert_eq!(manager.request_permission("alice", "staple", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+        let Err(AuthError::Locked { until }) = manager.request_permission("alice", "staple", &[Capability::ReadStatus]) else {
+            panic!("the second failure in a row locks");
+        };
+        assert_eq!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]), Err(AuthError::Locked { until }));
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_err());
+        assert_eq!(manager.unlock("alice"), Ok(true));
+        assert!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).is_ok());
+        assert_eq!(manager.request_permission("mallory", "correct horse", &[Capability::ReadStatus]), Err(AuthError::AuthFailed));
+    }
+
+    #[test]
+    fn test_account_problems_do_not_count_as_attempts() {
+        let manager = PermissionManager::with_authenticator(Box::new(MockAuthenticator {
+            account: pam_code::ACCT_EXPIRED,
+        }));
+        for _ in 0..=MAX_ATTEMPTS {
+            assert_eq!(manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]), Err(AuthError::AccountExpired));
+        }
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_err());
+    }
+
+    #[test]
+    fn test_windows_logon_errors_map_to_categories() {
+        assert_eq!(logon_verdict(logon_code::LOGON_FAILURE), AuthError::AuthFailed);
+        assert_eq!(logon_verdict(logon_code::ACCOUNT_EXPIRED), AuthError::AccountExpired);
+        assert_eq!(logon_verdict(logon_code::PASSWORD_MUST_CHANGE), AuthError::PasswordExpired);
+        assert_eq!(logon_verdict(logon_code::ACCOUNT_LOCKED_OUT), AuthError::AccountDenied);
+        // ERROR_NO_LOGON_SERVERS
+        assert_eq!(logon_verdict(1311), AuthError::ServiceUnavailable);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[test]
+    fn test_pam_is_not_supported_off_linux() {
+        let pam = PamAuthenticator { service: PAM_SERVICE.to_string() };
+        assert_eq!(pam.authenticate("alice", "correct horse"), Err(AuthError::NotSupported));
     }
 }
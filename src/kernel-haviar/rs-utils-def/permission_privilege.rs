@@ -0,0 +1,296 @@
+use std::fmt;
+
+/// Something the process may need to be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    /// Effective uid 0, or an elevated administrator on Windows.
+    Root,
+    /// Bypass file read, write and execute permission checks.
+    DacOverride,
+}
+
+impl Privilege {
+    /// The Linux capability's bit in `CapEff`, if this is one.
+    pub fn capability(self) -> Option<u32> {
+        match self {
+            Privilege::Root => None,
+            Privilege::DacOverride => Some(1),
+        }
+    }
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Privilege::Root => "root",
+            Privilege::DacOverride => "CAP_DAC_OVERRIDE",
+        })
+    }
+}
+
+/// What the process runs as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identity {
+    /// The effective uid.
+    Uid(u32),
+    /// A Windows token, which is elevated or not; Windows has no uids or
+    /// capabilities, so every privilege needs elevation.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    Windows { elevated: bool },
+}
+
+/// Why a privilege check failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrivilegeError {
+    /// The process lacks `privilege`, running as `identity`.
+    Missing { privilege: Privilege, identity: Identity },
+    /// Privileges cannot be checked on this platform.
+    #[cfg_attr(any(unix, windows), allow(dead_code))]
+    NotSupported,
+}
+
+impl fmt::Display for PrivilegeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrivilegeError::Missing {
+                identity: Identity::Windows { .. },
+                ..
+            } => f.write_str("must run elevated (Run as administrator)"),
+            PrivilegeError::Missing {
+                privilege: Privilege::Root,
+                identity: Identity::Uid(euid),
+            } => write!(f, "must run as root (effective uid is {})", euid),
+            PrivilegeError::Missing {
+                privilege,
+                identity: Identity::Uid(euid),
+            } => write!(f, "missing {} (effective uid {})", privilege, euid),
+            PrivilegeError::NotSupported => f.write_str("privilege checks are not supported on this platform"),
+        }
+    }
+}
+
+/// Who the process runs as, behind a trait so tests can be anyone.
+pub trait UidSource {
+    fn identity(&self) -> Result<Identity, PrivilegeError>;
+    /// The effective capability set, where the platform has one.
+    fn capabilities(&self) -> Option<u64>;
+    /// `SUDO_UID` and `SUDO_USER`: who ran sudo to get here.
+    fn sudo(&self) -> (Option<u32>, Option<String>);
+}
+
+/// The running process.
+pub struct ProcessUids;
+
+impl UidSource for ProcessUids {
+    #[cfg(unix)]
+    fn identity(&self) -> Result<Identity, PrivilegeError> {
+        Ok(Identity::Uid(unsafe { libc::geteuid() }))
+    }
+
+    #[cfg(windows)]
+    fn identity(&self) -> Result<Identity, PrivilegeError> {
+        Ok(Identity::Windows {
+            elevated: token_is_elevated(),
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn identity(&self) -> Result<Identity, PrivilegeError> {
+        Err(PrivilegeError::NotSupported)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn capabilities(&self) -> Option<u64> {
+        parse_cap_eff(&std::fs::read_to_string("/proc/self/status").ok()?)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn capabilities(&self) -> Option<u64> {
+        None
+    }
+
+    fn sudo(&self) -> (Option<u32>, Option<String>) {
+        let var = |name| std::env::var(name).ok().filter(|value: &String| !value.is_empty());
+        (var("SUDO_UID").and_then(|uid| uid.parse().ok()), var("SUDO_USER"))
+    }
+}
+
+/// Whether this process's token is elevated. A token that cannot be read
+/// counts as not.
+#[cfg(windows)]
+fn token_is_elevated() -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    let mut token: HANDLE = std::ptr::null_mut();
+    // SAFETY: the pseudo-handle of the current process needs no closing;
+    // `token` is closed below once opened.
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return false;
+    }
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    let mut returned = 0u32;
+    // SAFETY: `elevation` is the size passed and outlives the call.
+    let ok = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut std::ffi::c_void,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut returned,
+        )
+    };
+    unsafe { CloseHandle(token) };
+    ok != 0 && elevation.TokenIsElevated != 0
+}
+
+/// The `CapEff` mask of a `/proc/<pid>/status`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cap_eff(status: &str) -> Option<u64> {
+    let line = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(line.trim(), 16).ok()
+}
+
+/// Whether `uids` hold `privilege`. A capability counts if it is in the
+/// effective set, whatever the uid; where there is no capability set,
+/// only root holds them. On Windows an elevated token holds them all.
+pub fn check_privilege_of(uids: &dyn UidSource, privilege: Privilege) -> Result<(), PrivilegeError> {
+    let identity = uids.identity()?;
+    let held = match (identity, privilege.capability(), uids.capabilities()) {
+        (Identity::Windows { elevated }, _, _) => elevated,
+        (Identity::Uid(euid), None, _) => euid == 0,
+        (Identity::Uid(_), Some(bit), Some(effective)) => effective & (1 << bit) != 0,
+        (Identity::Uid(euid), Some(_), None) => euid == 0,
+    };
+    match held {
+        true => Ok(()),
+        false => Err(PrivilegeError::Missing { privilege, identity }),
+    }
+}
+
+/// `check_privilege_of` this process, for the first of `privileges` it
+/// lacks.
+pub fn check_privileges(privileges: &[Privilege]) -> Result<(), PrivilegeError> {
+    privileges.iter().try_for_each(|&privilege| check_privilege_of(&ProcessUids, privilege))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_audit::Requester;
+
+    struct MockUids {
+        identity: Identity,
+        capabilities: Option<u64>,
+        sudo: (Option<u32>, Option<String>),
+    }
+
+    impl Default for MockUids {
+        fn default() -> Self {
+            Self {
+                identity: Identity::Uid(0),
+                capabilities: None,
+                sudo: (None, None),
+            }
+        }
+    }
+
+    impl UidSource for MockUids {
+        fn identity(&self) -> Result<Identity, PrivilegeError> {
+            Ok(self.identity)
+        }
+
+        fn capabilities(&self) -> Option<u64> {
+            self.capabilities
+        }
+
+        fn sudo(&self) -> (Option<u32>, Option<String>) {
+            self.sudo.clone()
+        }
+    }
+
+    #[test]
+    fn test_capabilities_are_checked_apart_from_the_uid() {
+        let dac_override = 1 << 1;
+        let service = MockUids {
+            identity: Identity::Uid(998),
+            capabilities: Some(dac_override),
+            ..Default::default()
+        };
+        assert_eq!(check_privilege_of(&service, Privilege::DacOverride), Ok(()));
+        assert_eq!(
+            check_privilege_of(&service, Privilege::Root),
+            Err(PrivilegeError::Missing {
+                privilege: Privilege::Root,
+                identity: Identity::Uid(998)
+            })
+        );
+
+        // Root in a container that dropped CAP_DAC_OVERRIDE.
+        let boxed_root = MockUids {
+            capabilities: Some(!dac_override),
+            ..Default::default()
+        };
+        assert_eq!(check_privilege_of(&boxed_root, Privilege::Root), Ok(()));
+        let missing = check_privilege_of(&boxed_root, Privilege::DacOverride).unwrap_err();
+        assert_eq!(missing.to_string(), "missing CAP_DAC_OVERRIDE (effective uid 0)");
+
+        // Without a capability set only root has them.
+        assert_eq!(check_privilege_of(&MockUids::default(), Privilege::DacOverride), Ok(()));
+        let user = MockUids {
+            identity: Identity::Uid(1000),
+            ..Default::default()
+        };
+        assert!(check_privilege_of(&user, Privilege::DacOverride).is_err());
+    }
+
+    #[test]
+    fn test_windows_needs_an_elevated_token() {
+        let limited = MockUids {
+            identity: Identity::Windows { elevated: false },
+            ..Default::default()
+        };
+        for privilege in [Privilege::Root, Privilege::DacOverride] {
+            let missing = check_privilege_of(&limited, privilege).unwrap_err();
+            assert_eq!(missing.to_string(), "must run elevated (Run as administrator)");
+        }
+        let elevated = MockUids {
+            identity: Identity::Windows { elevated: true },
+            ..Default::default()
+        };
+        assert_eq!(check_privilege_of(&elevated, Privilege::Root), Ok(()));
+        assert_eq!(Requester::of(&elevated).euid, None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_this_process_is_checked_through_its_token() {
+        let Ok(Identity::Windows { elevated }) = ProcessUids.identity() else {
+            panic!("Windows processes have a token, not a uid");
+        };
+        match check_privileges(&[Privilege::Root]) {
+            Ok(()) => assert!(elevated),
+            Err(missing) => assert_eq!(missing.to_string(), "must run elevated (Run as administrator)"),
+        }
+    }
+
+    #[test]
+    fn test_the_invoker_behind_sudo_is_the_requester() {
+        let sudo = MockUids {
+            sudo: (Some(1000), Some("alice".to_string())),
+            ..Default::default()
+        };
+        let requester = Requester::of(&sudo);
+        assert_eq!((requester.euid, requester.sudo_uid, requester.user.as_str()), (Some(0), Some(1000), "alice"));
+        // A uid of 0 without sudo is the root user itself.
+        assert_eq!(Requester::of(&MockUids::default()).sudo_uid, None);
+    }
+
+    #[test]
+    fn test_cap_eff_is_read_from_proc_status() {
+        let status = "Name:\tserialkiller\nCapPrm:\t000001ffffffffff\nCapEff:\t0000000000000002\nCapBnd:\t000001ffffffffff\n";
+        assert_eq!(parse_cap_eff(status), Some(2));
+        assert_eq!(parse_cap_eff("Name:\tserialkiller\n"), None);
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{self, BufRead, IsTerminal};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, AtomicPtr, Ordering};
+use zeroize::Zeroizing;
+
+/// A password as typed, wiped from memory when dropped.
+pub type Password = Zeroizing<String>;
+
+/// Where `permission-manager` reads passwords from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// The controlling terminal, without echo.
+    Terminal,
+    /// One line of stdin per password, for scripts.
+    Stdin,
+}
+
+impl PasswordSource {
+    /// The terminal when stdin is one. Otherwise stdin, with a warning
+    /// unless `password_stdin` says that is intended.
+    pub fn detect(password_stdin: bool) -> Self {
+        if password_stdin {
+            return PasswordSource::Stdin;
+        }
+        if io::stdin().is_terminal() {
+            return PasswordSource::Terminal;
+        }
+        eprintln!("[WARN] stdin is not a terminal; reading passwords from it. Pass --password-stdin if that is intended");
+        PasswordSource::Stdin
+    }
+
+    /// Prompts on the terminal and reads without echo, or reads a line of
+    /// stdin without prompting. Ctrl-C, SIGTERM or SIGHUP at the prompt put
+    /// echo back on and end the process with 128 + the signal; the state
+    /// and audit log are already saved by then, as each request saves them.
+    pub fn read(self, prompt: &str) -> io::Result<Password> {
+        match self {
+            PasswordSource::Terminal => {
+                let _restore = restore_terminal_on_signals()
+                    .inspect_err(|e| eprintln!("[WARN] Cannot watch for Ctrl-C: {}", e))
+                    .ok();
+                rpassword::prompt_password(prompt).map(Zeroizing::new)
+            }
+            PasswordSource::Stdin => read_line(&mut io::stdin().lock()),
+        }
+    }
+}
+
+/// One line of `reader` without its line ending. The buffer is sized up
+/// front and trimmed in place, so no copy of the password is left behind
+/// unwiped.
+pub fn read_line(reader: &mut impl BufRead) -> io::Result<Password> {
+    let mut line = Zeroizing::new(String::with_capacity(256));
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no password on stdin"));
+    }
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+/// Puts the terminal back as it is now and exits with 128 + the signal if
+/// SIGINT, SIGTERM or SIGHUP arrive while the returned guard lives, e.g.
+/// Ctrl-C at a password prompt that turned echo off. Dropping the guard
+/// gives the signals back the handlers they had, so once the prompt is
+/// done they end the process as they would have without it.
+#[cfg(unix)]
+fn restore_terminal_on_signals() -> io::Result<Option<TerminalGuard>> {
+    use std::os::unix::io::AsRawFd;
+
+    let tty = match std::fs::File::open("/dev/tty") {
+        Ok(tty) => tty,
+        // No terminal, nothing to restore.
+        Err(_) => return Ok(None),
+    };
+    let mut saved = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(tty.as_raw_fd(), saved.as_mut_ptr()) } != 0 {
+        return Ok(None);
+    }
+    // SAFETY: tcgetattr succeeded, so it filled the struct in.
+    let saved = Box::into_raw(Box::new(unsafe { saved.assume_init() }));
+    TTY.store(tty.as_raw_fd(), Ordering::SeqCst);
+    SAVED.store(saved, Ordering::SeqCst);
+    let mut guard = TerminalGuard {
+        _tty: tty,
+        saved,
+        previous: Vec::new(),
+    };
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: both structs are zeroed before use, and the handler only
+        // makes async-signal-safe calls.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = restore_and_exit as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, &action, &mut previous) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            guard.previous.push((signal, previous));
+        }
+    }
+    Ok(Some(guard))
+}
+
+#[cfg(unix)]
+static TTY: AtomicI32 = AtomicI32::new(-1);
+#[cfg(unix)]
+static SAVED: AtomicPtr<libc::termios> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Runs in the signal handler, so it only restores, writes and exits.
+#[cfg(unix)]
+extern "C" fn restore_and_exit(signal: libc::c_int) {
+    let saved = SAVED.load(Ordering::SeqCst);
+    unsafe {
+        if !saved.is_null() {
+            libc::tcsetattr(TTY.load(Ordering::SeqCst), libc::TCSANOW, saved);
+            libc::write(libc::STDERR_FILENO, b"\n".as_ptr().cast(), 1);
+        }
+        libc::_exit(128 + signal);
+    }
+}
+
+/// The terminal's saved state and the handlers to put back.
+#[cfg(unix)]
+struct TerminalGuard {
+    _tty: std::fs::File,
+    saved: *mut libc::termios,
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+#[cfg(unix)]
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        for (signal, previous) in &self.previous {
+            unsafe { libc::sigaction(*signal, previous, std::ptr::null_mut()) };
+        }
+        SAVED.store(std::ptr::null_mut(), Ordering::SeqCst);
+        TTY.store(-1, Ordering::SeqCst);
+        // SAFETY: made by Box::into_raw above, and no handler can see it now.
+        drop(unsafe { Box::from_raw(self.saved) });
+    }
+}
+
+#[cfg(not(unix))]
+fn restore_terminal_on_signals() -> io::Result<Option<()>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zeroize::Zeroize;
+
+    /// Checks on drop that wiping the password clears its whole buffer,
+    /// the trimmed line ending included.
+    struct DropCheck(Password);
+
+    impl Drop for DropCheck {
+        fn drop(&mut self) {
+            let (buffer, capacity) = (self.0.as_ptr(), self.0.capacity());
+            // What `Zeroizing` does when it drops; the allocation stays
+            // live until the `String` itself is dropped after this.
+            self.0.zeroize();
+            assert_eq!(self.0.capacity(), capacity, "zeroizing must not reallocate");
+            let wiped = unsafe { std::slice::from_raw_parts(buffer, capacity) };
+            assert!(wiped.iter().all(|&byte| byte == 0), "password bytes left in memory");
+        }
+    }
+
+    #[test]
+    fn test_passwords_are_read_from_piped_stdin() {
+        let mut piped = Cursor::new(&b"correct horse\r\nbattery staple\n"[..]);
+        assert_eq!(read_line(&mut piped).unwrap().as_str(), "correct horse");
+        assert_eq!(read_line(&mut piped).unwrap().as_str(), "battery staple");
+        assert_eq!(read_line(&mut piped).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        // Spaces are part of the password.
+        assert_eq!(read_line(&mut Cursor::new(&b" pad \n"[..])).unwrap().as_str(), " pad ");
+    }
+
+    #[test]
+    fn test_passwords_are_wiped_on_drop() {
+        let password = read_line(&mut Cursor::new(&b"correct horse\n"[..])).unwrap();
+        assert_eq!(password.as_str(), "correct horse");
+        assert!(password.capacity() > password.len(), "the line ending sits in the same buffer");
+        drop(DropCheck(password));
+    }
+}
@@ -0,0 +1,263 @@
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, SystemTime};
+
+use crate::permission_capability::Capabilities;
+use crate::permission_lockout::Clock;
+use crate::permission_manager::PermissionManager;
+
+/// How long a grant lasts unless `--session-ttl` says otherwise.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// How often `spawn_pruner` drops expired sessions.
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Proof of a grant: 128 random bits, shown as 32 hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionToken([u8; 16]);
+
+impl SessionToken {
+    fn random() -> Self {
+        let mut id = [0u8; 16];
+        OsRng.fill_bytes(&mut id);
+        Self(id)
+    }
+}
+
+impl fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for SessionToken {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut id = [0u8; 16];
+        hex::decode_to_slice(text.trim(), &mut id).map_err(|_| "a session token is 32 hex digits".to_string())?;
+        Ok(Self(id))
+    }
+}
+
+/// As its hex, so a saved session map is keyed by it.
+impl Serialize for SessionToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SessionToken {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// What a token is worth now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionStatus {
+    Valid {
+        user: String,
+        expires: SystemTime,
+        capabilities: Capabilities,
+    },
+    /// Issued, but its TTL has passed.
+    Expired,
+    /// Never issued, revoked, or pruned after expiring.
+    Unknown,
+}
+
+/// One grant. Its times are wall-clock, so a saved session expires when
+/// it would have had the process kept running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    user: String,
+    capabilities: Capabilities,
+    created: SystemTime,
+    expires: SystemTime,
+}
+
+/// The sessions granted so far, each valid for `ttl` after it was issued.
+/// Expired ones are dropped as they are found and by `prune`.
+pub struct Sessions {
+    ttl: Duration,
+    clock: Box<dyn Clock>,
+    sessions: HashMap<SessionToken, Session>,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration, clock: Box<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Starts a session for `user` with `capabilities`.
+    pub fn issue(&mut self, user: &str, capabilities: Capabilities) -> SessionToken {
+        let created = self.clock.now();
+        let token = SessionToken::random();
+        self.sessions.insert(
+            token,
+            Session {
+                user: user.to_string(),
+                capabilities,
+                created,
+                expires: created + self.ttl,
+            },
+        );
+        token
+    }
+
+    pub fn validate(&mut self, token: &SessionToken) -> SessionStatus {
+        let now = self.clock.now();
+        match self.sessions.get(token) {
+            None => SessionStatus::Unknown,
+            Some(session) if session.expires <= now => {
+                self.sessions.remove(token);
+                SessionStatus::Expired
+            }
+            Some(session) => SessionStatus::Valid {
+                user: session.user.clone(),
+                expires: session.expires,
+                capabilities: session.capabilities.clone(),
+            },
+        }
+    }
+
+    /// Ends a session early. Returns whether it was live.
+    pub fn revoke(&mut self, token: &SessionToken) -> bool {
+        self.sessions.remove(token).is_some()
+    }
+
+    /// Ends all of `user`'s sessions.
+    pub fn revoke_user(&mut self, user: &str) {
+        self.sessions.retain(|_, session| session.user != user);
+    }
+
+    /// What `user`'s sessions that have not expired were granted between
+    /// them, if they have any.
+    pub fn capabilities_of(&mut self, user: &str) -> Option<Capabilities> {
+        self.prune();
+        let mut sessions = self.sessions.values().filter(|session| session.user == user).peekable();
+        sessions.peek()?;
+        Some(sessions.flat_map(|session| session.capabilities.iter().copied()).collect())
+    }
+
+    /// The sessions to save, expired or not.
+    pub fn saved(&self) -> HashMap<SessionToken, Session> {
+        self.sessions.clone()
+    }
+
+    /// Continues the `saved` sessions that have not expired since.
+    pub fn restore(&mut self, saved: HashMap<SessionToken, Session>) {
+        self.sessions = saved;
+        self.prune();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Drops expired sessions; returns how many.
+    pub fn prune(&mut self) -> usize {
+        let (now, before) = (self.clock.now(), self.sessions.len());
+        self.sessions.retain(|_, session| session.expires > now);
+        before - self.sessions.len()
+    }
+}
+
+/// Prunes `manager`'s expired sessions every `every` until it is dropped.
+pub fn spawn_pruner(manager: &Arc<PermissionManager>, every: Duration) -> std::thread::JoinHandle<()> {
+    let manager: Weak<PermissionManager> = Arc::downgrade(manager);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(every);
+        match manager.upgrade() {
+            Some(manager) => {
+                manager.prune_sessions();
+            }
+            None => return,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_capability::Capability;
+    use crate::permission_lockout::LockoutPolicy;
+    use crate::test_support::{start_manager, Fixed, MockClock};
+
+    #[test]
+    fn test_sessions_expire_after_their_ttl() {
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, None);
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        let expires = clock.now() + Duration::from_secs(60);
+        let capabilities = [Capability::ReadStatus].into();
+        assert_eq!(manager.validate(&token), SessionStatus::Valid { user: "alice".to_string(), expires, capabilities });
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_ok());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_err());
+        assert_eq!(manager.validate(&token), SessionStatus::Unknown, "pruned by the check");
+
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(manager.validate(&token), SessionStatus::Expired);
+        assert_eq!(manager.validate(&token), SessionStatus::Unknown);
+    }
+
+    #[test]
+    fn test_revoked_sessions_are_gone() {
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, None);
+        let first = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        let second = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        assert_ne!(first, second);
+        assert!(manager.revoke(&first));
+        assert!(!manager.revoke(&first));
+        assert_eq!(manager.validate(&first), SessionStatus::Unknown);
+        // The other session still stands.
+        assert!(manager.check_capability("alice", Capability::ReadStatus).is_ok());
+        assert!(matches!(manager.validate(&second), SessionStatus::Valid { .. }));
+    }
+
+    #[test]
+    fn test_forged_tokens_are_not_valid() {
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, None);
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        let text = token.to_string();
+        assert_eq!(text.len(), 32);
+        assert_eq!(text.parse::<SessionToken>(), Ok(token));
+
+        let mut forged = text.into_bytes();
+        forged[0] = if forged[0] == b'0' { b'1' } else { b'0' };
+        let forged: SessionToken = String::from_utf8(forged).unwrap().parse().unwrap();
+        assert_eq!(manager.validate(&forged), SessionStatus::Unknown);
+        assert!("not a token".parse::<SessionToken>().is_err());
+        assert!("00".parse::<SessionToken>().is_err());
+    }
+
+    #[test]
+    fn test_the_pruner_stops_with_the_manager() {
+        let clock = MockClock::new();
+        let manager = Arc::new(start_manager(Fixed, LockoutPolicy::default(), &clock, None));
+        manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        let pruner = spawn_pruner(&manager, Duration::from_millis(10));
+        clock.advance(Duration::from_secs(61));
+        while manager.session_count() > 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(manager);
+        pruner.join().unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use crate::permission_audit;
+use crate::permission_lockout::LockoutState;
+use crate::permission_session::{Session, SessionToken};
+use crate::serialk_gate;
+
+/// Where `permission-manager` keeps lockouts and sessions unless
+/// `--state-file` says otherwise.
+#[cfg(not(windows))]
+pub const DEFAULT_STATE_FILE: &str = "/var/lib/serialkiller/permission-state";
+#[cfg(windows)]
+pub const DEFAULT_STATE_FILE: &str = r"C:\ProgramData\serialkiller\permission-state";
+
+/// The secret the state file's key is derived from unless
+/// `--machine-secret` says otherwise; made on first use.
+#[cfg(not(windows))]
+pub const DEFAULT_MACHINE_SECRET: &str = "/etc/serialkiller/machine-secret";
+#[cfg(windows)]
+pub const DEFAULT_MACHINE_SECRET: &str = r"C:\ProgramData\serialkiller\machine-secret";
+
+/// Starts every state file, and is authenticated along with its contents.
+const MAGIC: &[u8] = b"SKPS1";
+const NONCE_LEN: usize = 12;
+/// HKDF's `info`, so the secret yields a different key for anything else.
+const KEY_INFO: &[u8] = b"serialkiller permission state v1";
+
+/// Everything `PermissionManager` has to remember across restarts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermissionState {
+    #[serde(default)]
+    pub lockout: LockoutState,
+    #[serde(default)]
+    pub sessions: HashMap<SessionToken, Session>,
+}
+
+/// The permission state, sealed with AES-256-GCM under a key derived from
+/// the machine secret: reading it does not show who holds a session, and
+/// editing it cannot lift a lock or forge one.
+pub struct StateFile {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+}
+
+impl StateFile {
+    /// The state at `path`, which need not exist yet, keyed by the secret
+    /// at `secret_path`. A missing secret is made, readable by root only;
+    /// one others may read is refused.
+    pub fn open(path: &Path, secret_path: &Path) -> Result<Self, String> {
+        if !secret_path.exists() {
+            permission_audit::create_key(secret_path)
+                .map_err(|e| format!("Cannot create machine secret {}: {}", secret_path.display(), e))?;
+        }
+        let secret = Zeroizing::new(serialk_gate::read_key_file(secret_path, "machine secret")?);
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(None, &secret)
+            .expand(KEY_INFO, key.as_mut_slice())
+            .expect("32 bytes is a valid HKDF-SHA256 output");
+        Ok(Self {
+            path: path.to_path_buf(),
+            cipher: Aes256Gcm::new_from_slice(key.as_slice()).expect("an AES-256 key is 32 bytes"),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The saved state, or an empty one if nothing was saved yet. A file
+    /// that cannot be decrypted or parsed, being corrupt or sealed with
+    /// another secret, is moved aside and the state starts empty: a lost
+    /// lockout is better than a permission manager that will not start.
+    pub fn load(&self) -> Result<PermissionState, String> {
+        let sealed = match fs::read(&self.path) {
+            Ok(sealed) => sealed,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(PermissionState::default()),
+            Err(e) => return Err(format!("Cannot read permission state {}: {}", self.path.display(), e)),
+        };
+        let why = match self.unseal(&sealed) {
+            Ok(state) => return Ok(state),
+            Err(why) => why,
+        };
+        let aside = self.quarantine()?;
+        eprintln!("[WARN] ************************************************************");
+        eprintln!("[WARN] Permission state {} is unusable: {}.", self.path.display(), why);
+        eprintln!("[WARN] Moved it to {}; every lockout and session in it is forgotten.", aside.display());
+        eprintln!("[WARN] ************************************************************");
+        Ok(PermissionState::default())
+    }
+
+    /// Replaces the file with `state`, readable by its owner only from the
+    /// start.
+    pub fn save(&self, state: &PermissionState) -> io::Result<()> {
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let sealed = self.seal(state)?;
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let _ = fs::remove_file(&temporary);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temporary)?;
+        file.write_all(&sealed)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)
+    }
+
+    /// `MAGIC`, a random nonce, then the JSON encrypted and tagged.
+    fn seal(&self, state: &PermissionState) -> io::Result<Vec<u8>> {
+        let json = Zeroizing::new(serde_json::to_vec(state).map_err(io::Error::other)?);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: &json,
+            aad: MAGIC,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::other("cannot encrypt the permission state"))?;
+        Ok([MAGIC, &nonce, &ciphertext].concat())
+    }
+
+    fn unseal(&self, sealed: &[u8]) -> Result<PermissionState, String> {
+        let rest = sealed.strip_prefix(MAGIC).ok_or("not a permission state file")?;
+        if rest.len() < NONCE_LEN {
+            return Err("truncated".to_string());
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: MAGIC,
+        };
+        let json = Zeroizing::new(
+            self.cipher
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .map_err(|_| "it does not decrypt; it is corrupt or was sealed with another machine secret")?,
+        );
+        serde_json::from_slice(&json).map_err(|e| format!("invalid contents: {}", e))
+    }
+
+    /// Moves the file to `<path>.corrupt-<seconds since the epoch>`.
+    fn quarantine(&self) -> Result<PathBuf, String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut aside = self.path.as_os_str().to_owned();
+        aside.push(format!(".corrupt-{}", now));
+        let aside = PathBuf::from(aside);
+        fs::rename(&self.path, &aside)
+            .map_err(|e| format!("Cannot move unusable permission state {} aside: {}", self.path.display(), e))?;
+        Ok(aside)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_capability::Capability;
+    use crate::permission_lockout::{Clock, Lockout, LockoutPolicy};
+    use crate::permission_session::SessionStatus;
+    use crate::test_support::{start_manager, Fixed, MockClock};
+    use std::time::Duration;
+
+    fn quarantined(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir.join("state"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains("permission-state.corrupt-"))
+            .collect()
+    }
+
+    #[test]
+    fn test_state_round_trips_sealed() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        manager.request_permission("alice", "battery", &[Capability::ReadStatus]).unwrap_err();
+        manager.request_permission("bob", "correct horse", &[Capability::ReadStatus]).unwrap();
+
+        let file = StateFile::open(&dir.path().join("state/permission-state"), &dir.path().join("machine-secret")).unwrap();
+        let state = file.load().unwrap();
+        assert_eq!(state.sessions.len(), 1);
+        let mut lockout = Lockout::new(LockoutPolicy::default(), Box::new(clock.clone()));
+        lockout.restore(state.lockout.clone());
+        assert!(lockout.clear("alice"), "alice's failure was saved");
+        file.save(&state).unwrap();
+        assert_eq!(file.load().unwrap(), state);
+
+        let sealed = fs::read(file.path()).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        for plain in [&b"alice"[..], b"bob", b"read-status"] {
+            assert!(!sealed.windows(plain.len()).any(|window| window == plain), "{:?} in the clear", plain);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(file.path()).unwrap().permissions().mode() & 0o777, 0o600);
+            assert_eq!(fs::metadata(dir.path().join("machine-secret")).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_sessions_keep_their_expiry_across_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ModifyWatchSet]).unwrap();
+        let revoked = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        assert!(manager.revoke(&revoked));
+        drop(manager);
+
+        clock.advance(Duration::from_secs(59));
+        let restarted = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        let SessionStatus::Valid { expires, .. } = restarted.validate(&token) else {
+            panic!("the session outlives the process");
+        };
+        assert_eq!(expires, clock.now() + Duration::from_secs(1), "the TTL is not restarted");
+        assert_eq!(restarted.check_capability(&token, Capability::ModifyWatchSet), Ok(()));
+        assert_eq!(restarted.validate(&revoked), SessionStatus::Unknown);
+        drop(restarted);
+
+        clock.advance(Duration::from_secs(1));
+        let restarted = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        assert_eq!(restarted.validate(&token), SessionStatus::Unknown, "expired while stopped");
+    }
+
+    #[test]
+    fn test_corrupt_state_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let path = dir.path().join("state/permission-state");
+        let manager = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        let token = manager.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        drop(manager);
+
+        let mut sealed = fs::read(&path).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        fs::write(&path, &sealed).unwrap();
+        let restarted = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        assert_eq!(restarted.validate(&token), SessionStatus::Unknown);
+        let aside = quarantined(dir.path());
+        assert_eq!(aside.len(), 1);
+        assert_eq!(fs::read(&aside[0]).unwrap(), sealed, "kept as it was found");
+        assert!(!path.exists(), "nothing has changed since");
+
+        // A fresh file works again.
+        let token = restarted.request_permission("alice", "correct horse", &[Capability::ReadStatus]).unwrap();
+        drop(restarted);
+        let restarted = start_manager(Fixed, LockoutPolicy::default(), &clock, Some(dir.path()));
+        assert!(matches!(restarted.validate(&token), SessionStatus::Valid { .. }));
+
+        for garbage in [&b""[..], b"SKPS1", b"{\"lockout\": {}}"] {
+            fs::write(&path, garbage).unwrap();
+            let file = StateFile::open(&path, &dir.path().join("machine-secret")).unwrap();
+            assert_eq!(file.load(), Ok(PermissionState::default()));
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn test_another_secret_cannot_read_the_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/permission-state");
+        let mine = StateFile::open(&path, &dir.path().join("machine-secret")).unwrap();
+        let mut state = PermissionState::default();
+        let mut lockout = Lockout::new(LockoutPolicy::default(), Box::new(MockClock::new()));
+        lockout.record_failure("alice");
+        state.lockout = lockout.state().clone();
+        mine.save(&state).unwrap();
+
+        let theirs = StateFile::open(&path, &dir.path().join("other-secret")).unwrap();
+        assert_eq!(theirs.load(), Ok(PermissionState::default()));
+        assert_eq!(quarantined(dir.path()).len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_secret_others_can_read_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("machine-secret");
+        fs::write(&secret, "0123456789abcdef").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o644)).unwrap();
+        let refused = StateFile::open(&dir.path().join("permission-state"), &secret);
+        assert!(refused.err().unwrap().contains("it must be 0600"));
+    }
+}
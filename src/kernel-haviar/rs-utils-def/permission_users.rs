@@ -0,0 +1,304 @@
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::permission_capability::{Capabilities, Capability};
+use crate::permission_manager::{AuthError, Authenticator};
+
+/// Where `permission-manager add-user` writes unless `--users-file` says
+/// otherwise.
+pub const DEFAULT_USERS_FILE: &str = "/etc/serialkiller/users.toml";
+
+/// What a `permission-manager` session needs to add users or change them.
+pub const REQUIRED_CAPABILITY: Capability = Capability::ManageUsers;
+
+/// One `[[user]]` table of a users file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserEntry {
+    pub name: String,
+    /// An argon2id PHC string, `$argon2id$v=19$m=...,t=...,p=...$salt$hash`.
+    pub hash: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// What sessions of the user may be granted; all of them if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+/// Users allowed to request permission without PAM:
+///
+/// ```toml
+/// required_group = "serialk-admins"
+///
+/// [[user]]
+/// name = "alice"
+/// hash = "$argon2id$v=19$m=19456,t=2,p=1$..."
+/// roles = ["admin"]
+/// capabilities = ["read-status", "modify-watch-set"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsersFile {
+    /// Only its members are granted permission; `serialk-admins` if unset,
+    /// none if empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_group: Option<String>,
+    #[serde(default, rename = "user")]
+    pub users: Vec<UserEntry>,
+}
+
+impl UsersFile {
+    /// Reads and checks `path`. A file anyone but its owner can write is
+    /// refused, as is a hash that is not argon2id or a name given twice.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        check_permissions(path)?;
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read users file {}: {}", path.display(), e))?;
+        let file: Self = toml::from_str(&text).map_err(|e| format!("Invalid users file {}: {}", path.display(), e))?;
+        for (i, user) in file.users.iter().enumerate() {
+            let hash = PasswordHash::new(&user.hash)
+                .map_err(|e| format!("Invalid users file {}: user {}: {}", path.display(), user.name, e))?;
+            if hash.algorithm != argon2::ARGON2ID_IDENT {
+                return Err(format!(
+                    "Invalid users file {}: user {} is hashed with {}, not argon2id",
+                    path.display(),
+                    user.name,
+                    hash.algorithm
+                ));
+            }
+            if file.users[..i].iter().any(|other| other.name == user.name) {
+                return Err(format!("Invalid users file {}: user {} is listed twice", path.display(), user.name));
+            }
+        }
+        Ok(file)
+    }
+
+    /// `load`, or an empty file if `path` does not exist yet.
+    pub fn load_or_default(path: &Path) -> Result<Self, String> {
+        match path.try_exists() {
+            Ok(false) => Ok(Self::default()),
+            _ => Self::load(path),
+        }
+    }
+
+    /// Hashes `password` for `name`, adding the user if they are new.
+    /// `roles` replace the user's roles unless empty, and `capabilities`
+    /// their capabilities if given. Returns whether the user was added.
+    pub fn set_password(
+        &mut self,
+        name: &str,
+        password: &str,
+        roles: &[String],
+        capabilities: Option<&[Capability]>,
+    ) -> Result<bool, String> {
+        let hash = hash_password(password)?;
+        match self.users.iter_mut().find(|user| user.name == name) {
+            Some(user) => {
+                user.hash = hash;
+                if !roles.is_empty() {
+                    user.roles = roles.to_vec();
+                }
+                if let Some(capabilities) = capabilities {
+                    user.capabilities = Some(capabilities.to_vec());
+                }
+                Ok(false)
+            }
+            None => {
+                self.users.push(UserEntry {
+                    name: name.to_string(),
+                    hash,
+                    roles: roles.to_vec(),
+                    capabilities: capabilities.map(<[Capability]>::to_vec),
+                });
+                Ok(true)
+            }
+        }
+    }
+
+    /// Writes the file next to `path`, readable by its owner only, and
+    /// renames it into place, creating the directory if needed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        let _ = fs::remove_file(&temporary);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&temporary)?.write_all(text.as_bytes())?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// An argon2id PHC string of `password` with a fresh salt and the crate's
+/// default cost.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("Cannot hash password: {}", e))
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = fs::metadata(path).map_err(|e| format!("Cannot stat users file {}: {}", path.display(), e))?;
+    let mode = meta.permissions().mode() & 0o777;
+    if mode & 0o022 != 0 {
+        return Err(format!(
+            "Users file {} has mode {:o}; it must not be writable by group or others",
+            path.display(),
+            mode
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Checks passwords against the hashes of a users file.
+pub struct UsersFileAuthenticator {
+    hashes: HashMap<String, String>,
+    capabilities: HashMap<String, Capabilities>,
+    /// Hash of a random password that an unknown user's guess is checked
+    /// against, so it costs as long as a wrong password for a real one.
+    decoy: String,
+}
+
+impl UsersFileAuthenticator {
+    pub fn new(file: UsersFile) -> Result<Self, String> {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let capabilities = file
+            .users
+            .iter()
+            .map(|user| {
+                let allowed = match &user.capabilities {
+                    Some(capabilities) => capabilities.iter().copied().collect(),
+                    None => Capability::ALL.into_iter().collect(),
+                };
+                (user.name.clone(), allowed)
+            })
+            .collect();
+        Ok(Self {
+            hashes: file.users.into_iter().map(|user| (user.name, user.hash)).collect(),
+            capabilities,
+            decoy: hash_password(&hex::encode(secret))?,
+        })
+    }
+}
+
+impl Authenticator for UsersFileAuthenticator {
+    fn authenticate(&self, user: &str, password: &str) -> Result<(), AuthError> {
+        let (hash, known) = match self.hashes.get(user) {
+            Some(hash) => (hash, true),
+            None => (&self.decoy, false),
+        };
+        // `load` checked every hash parses.
+        let hash = PasswordHash::new(hash).map_err(|_| AuthError::ServiceUnavailable)?;
+        // The digest comparison inside is constant-time.
+        let verified = Argon2::default().verify_password(password.as_bytes(), &hash).is_ok();
+        match verified && known {
+            true => Ok(()),
+            false => Err(AuthError::AuthFailed),
+        }
+    }
+
+    fn capabilities(&self, user: &str) -> Capabilities {
+        self.capabilities.get(user).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_file_round_trip_and_authentication() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("etc/users.toml");
+        let mut file = UsersFile::load_or_default(&path).unwrap();
+        assert!(file.set_password("alice", "correct horse", &["admin".to_string()], None).unwrap());
+        file.save(&path).unwrap();
+
+        let loaded = UsersFile::load(&path).unwrap();
+        assert_eq!(loaded.users.len(), 1);
+        assert_eq!(loaded.users[0].roles, ["admin"]);
+        assert!(loaded.users[0].hash.starts_with("$argon2id$"));
+
+        let authenticator = UsersFileAuthenticator::new(loaded).unwrap();
+        assert_eq!(authenticator.authenticate("alice", "correct horse"), Ok(()));
+        assert_eq!(authenticator.authenticate("alice", "battery"), Err(AuthError::AuthFailed));
+        // Unknown users fail the same way, even with a password that works for someone.
+        assert_eq!(authenticator.authenticate("mallory", "correct horse"), Err(AuthError::AuthFailed));
+    }
+
+    #[test]
+    fn test_changing_a_password_keeps_roles_unless_given() {
+        let mut file = UsersFile::default();
+        file.set_password("alice", "one", &["admin".to_string()], Some(&[Capability::ReadStatus])).unwrap();
+        assert!(!file.set_password("alice", "two", &[], None).unwrap());
+        assert_eq!(file.users.len(), 1);
+        assert_eq!(file.users[0].roles, ["admin"]);
+        assert_eq!(file.users[0].capabilities, Some(vec![Capability::ReadStatus]));
+    }
+
+    #[test]
+    fn test_capabilities_are_saved_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        let mut file = UsersFile::default();
+        file.set_password("alice", "one", &[], Some(&[Capability::ReadStatus, Capability::UpdateBaseline])).unwrap();
+        file.set_password("root", "two", &[], None).unwrap();
+        file.save(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains(r#"capabilities = ["read-status", "update-baseline"]"#), "{}", text);
+        assert_eq!(text.matches("capabilities").count(), 1, "unset stays unset");
+
+        let authenticator = UsersFileAuthenticator::new(UsersFile::load(&path).unwrap()).unwrap();
+        assert_eq!(authenticator.capabilities("alice"), [Capability::ReadStatus, Capability::UpdateBaseline].into());
+        assert_eq!(authenticator.capabilities("root"), Capability::ALL.into());
+        assert!(authenticator.capabilities("mallory").is_empty());
+
+        fs::write(&path, text.replace("update-baseline", "root")).unwrap();
+        assert!(UsersFile::load(&path).unwrap_err().contains("unknown variant `root`"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_writable_or_malformed_users_files_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.toml");
+        let mut file = UsersFile::default();
+        file.set_password("alice", "correct horse", &[], None).unwrap();
+        file.save(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        for mode in [0o620, 0o602] {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            let e = UsersFile::load(&path).unwrap_err();
+            assert!(e.contains("must not be writable"), "{}", e);
+        }
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(UsersFile::load(&path).is_ok());
+
+        fs::write(&path, "[[user]]\nname = \"bob\"\nhash = \"$argon2i$v=19$m=16,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2hoYXNo\"\n").unwrap();
+        assert!(UsersFile::load(&path).unwrap_err().contains("not argon2id"));
+    }
+}
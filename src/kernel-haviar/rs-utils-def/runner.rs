@@ -1,9 +1,13 @@
 use sha2::{Digest, Sha256};
 use std::{fs, io};
 
+use crate::permission_capability::Capability;
+
 const MAGIC: u32 = 0x5053454C; // 'PSEL' ASCII
 const SECTION_SIZE: usize = 73;
 
+/// What a `permission-manager` session needs for `serialkiller run`.
+pub const REQUIRED_CAPABILITY: Capability = Capability::ExecutePself;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SectionType {
     Elf = 0,
@@ -102,7 +106,7 @@ pub struct PselfRunner {
 
 impl PselfRunner {
     pub fn new(data: Vec<u8>) -> Result<Self, String> {
-        let header = PselfHeader::from_bytes(&data[0..12])?;
+        let header = PselfHeader::from_bytes(&data)?;
 
         let mut sections = Vec::new();
         let start = 12;
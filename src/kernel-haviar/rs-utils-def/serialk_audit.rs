@@ -0,0 +1,423 @@
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `prev` of the very first line of a new audit trail.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Rotated files kept next to the live log unless `--audit-keep` says otherwise.
+pub const DEFAULT_AUDIT_KEEP: usize = 5;
+
+/// HMAC of one line: keyed over the previous line's HMAC and the line
+/// without its own `hmac` field.
+pub fn chain_hmac(key: &[u8], prev: &str, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(prev.as_bytes());
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Where the head checkpoint of the log at `path` is kept: `<path>.head`.
+pub fn head_path(path: &Path) -> PathBuf {
+    let mut head = path.as_os_str().to_owned();
+    head.push(".head");
+    PathBuf::from(head)
+}
+
+/// MAC of a head checkpoint, so only the key's holder can move it.
+fn head_mac(key: &[u8], seq: u64, hmac: &str) -> String {
+    chain_hmac(key, "audit-head", &format!("{}:{}", seq, hmac))
+}
+
+/// The `seq` and `hmac` of the last line the head checkpoint at `path`
+/// vouches for, if there is one.
+fn read_head(path: &Path, key: &[u8]) -> Result<Option<(u64, String)>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    let value: Value = serde_json::from_str(&text).map_err(|e| format!("{}: not JSON: {}", path.display(), e))?;
+    let (Some(seq), Some(hmac)) = (value["seq"].as_u64(), value["hmac"].as_str()) else {
+        return Err(format!("{}: missing seq or hmac", path.display()));
+    };
+    if value["mac"].as_str() != Some(head_mac(key, seq, hmac).as_str()) {
+        return Err(format!("{}: mac mismatch (head checkpoint forged)", path.display()));
+    }
+    Ok(Some((seq, hmac.to_string())))
+}
+
+/// Whether a log whose last line is `seq`/`hmac` still reaches the head
+/// checkpoint. A log ahead of its head only lost the race with a crash;
+/// one behind it had lines cut off the end.
+fn reaches_head(seq: u64, hmac: &str, head: &(u64, String)) -> Result<(), String> {
+    if seq < head.0 || (seq == head.0 && hmac != head.1) {
+        return Err(format!(
+            "ends at seq {} but its head checkpoint vouches for seq {} (lines cut off the end)",
+            seq, head.0
+        ));
+    }
+    Ok(())
+}
+
+/// Creates or appends to `path`, readable by its owner only.
+fn open_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Append-only ndjson record of watcher events. Every line carries a `seq`
+/// and an `hmac` chained to the line before it, so edits, deletions and
+/// reordering break verification. Each file starts with an `audit-start`
+/// header whose `prev` is the last HMAC of the file it was rotated from.
+/// The chain cannot show lines cut off the end, so after every line a
+/// head checkpoint (`head_path`) records the last `seq` and `hmac`, MACed
+/// with the same key.
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    key: Vec<u8>,
+    prev: String,
+    seq: u64,
+    size: u64,
+    pub max_size: Option<u64>,
+    pub keep: usize,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, continuing the chain from its last line,
+    /// or starts a new trail with a header.
+    pub fn open(path: &Path, key: Vec<u8>, max_size: Option<u64>, keep: usize) -> io::Result<Self> {
+        let (prev, seq) = match last_line(path)? {
+            Some(line) => {
+                let value: Value = serde_json::from_str(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: last line: {}", path.display(), e)))?;
+                let prev = value["hmac"].as_str().unwrap_or_default().to_string();
+                (prev, value["seq"].as_u64().unwrap_or_default())
+            }
+            None => (GENESIS.to_string(), 0),
+        };
+        // Continuing a truncated log would move its head past the gap.
+        let head = read_head(&head_path(path), &key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(head) = head {
+            reaches_head(seq, &prev, &head)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} {}", path.display(), e)))?;
+        }
+        let file = open_private(path)?;
+        let size = file.metadata()?.len();
+        let mut log = Self {
+            path: path.to_path_buf(),
+            file,
+            key,
+            prev,
+            seq,
+            size,
+            max_size,
+            keep,
+        };
+        if log.size == 0 {
+            log.write_header()?;
+        }
+        Ok(log)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let header = serde_json::json!({
+            "event": "audit-start",
+            "prev": self.prev,
+            "timestamp": timestamp,
+        });
+        self.write_line(header, true)
+    }
+
+    /// Appends one event; `sync` forces it to disk before returning.
+    pub fn append(&mut self, entry: Value, sync: bool) -> io::Result<()> {
+        if let Some(max) = self.max_size {
+            if self.size > 0 && self.size + entry.to_string().len() as u64 > max {
+                self.rotate()?;
+            }
+        }
+        self.write_line(entry, sync)
+    }
+
+    fn write_line(&mut self, mut entry: Value, sync: bool) -> io::Result<()> {
+        self.seq += 1;
+        entry["seq"] = self.seq.into();
+        let hmac = chain_hmac(&self.key, &self.prev, &entry.to_string());
+        entry["hmac"] = hmac.as_str().into();
+        let line = format!("{}\n", entry);
+        self.file.write_all(line.as_bytes())?;
+        if sync {
+            self.file.sync_data()?;
+        }
+        self.size += line.len() as u64;
+        self.prev = hmac;
+        self.write_head(sync)
+    }
+
+    /// Atomically replaces the head checkpoint with the line just written.
+    fn write_head(&self, sync: bool) -> io::Result<()> {
+        let head = head_path(&self.path);
+        let mut temporary = head.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let _ = fs::remove_file(&temporary);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&temporary)?;
+        let checkpoint = serde_json::json!({
+            "seq": self.seq,
+            "hmac": self.prev,
+            "mac": head_mac(&self.key, self.seq, &self.prev),
+        });
+        file.write_all(format!("{}\n", checkpoint).as_bytes())?;
+        if sync {
+            file.sync_data()?;
+        }
+        fs::rename(&temporary, &head)
+    }
+
+    /// Shifts `log.1 .. log.N` up by one, moves the live file to `log.1`
+    /// and starts a new file whose header carries the chain forward.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = open_private(&self.path)?;
+        self.size = 0;
+        self.write_header()
+    }
+}
+
+fn last_line(path: &Path) -> io::Result<Option<String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    Ok(last)
+}
+
+/// Where verification failed: the file and its 1-based line number.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuditError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.reason)
+    }
+}
+
+/// Verifies one audit file and returns the `seq` and HMAC of its last line
+/// and how many lines it holds. With `prev`, the file's header must
+/// continue from it.
+pub fn verify_file(path: &Path, key: &[u8], prev: Option<&str>) -> Result<(u64, String, usize), AuditError> {
+    let fail = |line: usize, reason: String| AuditError {
+        path: path.to_path_buf(),
+        line,
+        reason,
+    };
+    let file = File::open(path).map_err(|e| fail(0, e.to_string()))?;
+
+    let mut chain: Option<String> = None;
+    let mut seq = 0u64;
+    let mut count = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let number = i + 1;
+        let line = line.map_err(|e| fail(number, e.to_string()))?;
+        let mut value: Value = serde_json::from_str(&line).map_err(|e| fail(number, format!("not JSON: {}", e)))?;
+        let Some(hmac) = value.as_object_mut().and_then(|o| o.remove("hmac")) else {
+            return Err(fail(number, "missing hmac".to_string()));
+        };
+        let hmac = hmac.as_str().unwrap_or_default().to_string();
+
+        let expected_prev = match &chain {
+            Some(last) => last.clone(),
+            None => {
+                if value["event"] != "audit-start" {
+                    return Err(fail(number, "first line is not an audit-start header".to_string()));
+                }
+                let header_prev = value["prev"].as_str().unwrap_or_default().to_string();
+                if let Some(prev) = prev {
+                    if header_prev != prev {
+                        return Err(fail(number, "header does not continue the previous file".to_string()));
+                    }
+                }
+                seq = value["seq"].as_u64().unwrap_or(1).saturating_sub(1);
+                header_prev
+            }
+        };
+        if value["seq"].as_u64() != Some(seq + 1) {
+            return Err(fail(number, format!("expected seq {}, found {}", seq + 1, value["seq"])));
+        }
+        if chain_hmac(key, &expected_prev, &value.to_string()) != hmac {
+            return Err(fail(number, "hmac mismatch (line edited, removed or reordered)".to_string()));
+        }
+        seq += 1;
+        count += 1;
+        chain = Some(hmac);
+    }
+    match chain {
+        Some(last) => Ok((seq, last, count)),
+        None => Err(fail(0, "empty audit log".to_string())),
+    }
+}
+
+/// Verifies rotated files given oldest first, checking each header against
+/// the end of the file before it, and the last file against the head
+/// checkpoint of the live log it must be. A missing checkpoint fails too:
+/// deleting it must not hide a truncation.
+pub fn verify_files(paths: &[PathBuf], key: &[u8]) -> Result<usize, AuditError> {
+    let mut prev: Option<String> = None;
+    let mut last = None;
+    let mut total = 0;
+    for path in paths {
+        let (seq, hmac, count) = verify_file(path, key, prev.as_deref())?;
+        prev = Some(hmac.clone());
+        last = Some((path, seq, hmac, count));
+        total += count;
+    }
+    if let Some((path, seq, hmac, count)) = last {
+        let fail = |reason: String| AuditError {
+            path: path.clone(),
+            line: count,
+            reason,
+        };
+        let head = head_path(path);
+        match read_head(&head, key).map_err(fail)? {
+            Some(head) => reaches_head(seq, &hmac, &head).map_err(fail)?,
+            None => return Err(fail(format!("no head checkpoint {}; the end may be cut off", head.display()))),
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(n: usize) -> Value {
+        json!({ "event": "modified", "path": format!("/etc/app{}.conf", n), "message": "changed", "timestamp": n })
+    }
+
+    #[test]
+    fn test_corrupted_line_is_pinpointed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path, b"key".to_vec(), None, DEFAULT_AUDIT_KEEP).unwrap();
+        for n in 0..5 {
+            log.append(event(n), n == 4).unwrap();
+        }
+        drop(log);
+        assert_eq!(verify_file(&path, b"key", None).unwrap().2, 6);
+        assert!(verify_file(&path, b"other", None).is_err());
+
+        let text = fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+        lines[3] = lines[3].replace("app2.conf", "app9.conf");
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(verify_file(&path, b"key", None).unwrap_err().line, 4);
+
+        // Dropping a line breaks the chain at the line after the gap.
+        lines = text.lines().map(String::from).collect();
+        lines.remove(2);
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(verify_file(&path, b"key", None).unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn test_rotation_carries_the_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path, b"key".to_vec(), Some(400), 2).unwrap();
+        for n in 0..12 {
+            log.append(event(n), false).unwrap();
+        }
+        drop(log);
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+        assert!(rotated(2).exists());
+        assert!(!rotated(3).exists());
+
+        // Reopening continues the chain of the live file.
+        let mut log = AuditLog::open(&path, b"key".to_vec(), Some(400), 2).unwrap();
+        log.append(event(99), false).unwrap();
+        drop(log);
+
+        let files = [rotated(2), rotated(1), path.clone()];
+        assert!(verify_files(&files, b"key").is_ok());
+        let err = verify_files(&[rotated(2), path.clone()], b"key").unwrap_err();
+        assert_eq!((err.path, err.line), (path, 1));
+    }
+
+    #[test]
+    fn test_cutting_off_the_end_is_caught_by_the_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path, b"key".to_vec(), None, DEFAULT_AUDIT_KEEP).unwrap();
+        for n in 0..5 {
+            log.append(event(n), false).unwrap();
+        }
+        drop(log);
+        let files = [path.clone()];
+        assert_eq!(verify_files(&files, b"key"), Ok(6));
+        #[cfg(unix)]
+        for file in [&path, &head_path(&path)] {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // What is left is a valid chain, but shorter than the head says.
+        let text = fs::read_to_string(&path).unwrap();
+        let kept: Vec<&str> = text.lines().take(4).collect();
+        fs::write(&path, kept.join("\n") + "\n").unwrap();
+        assert_eq!(verify_file(&path, b"key", None).unwrap().2, 4);
+        let err = verify_files(&files, b"key").unwrap_err();
+        assert!(err.reason.contains("cut off"), "{}", err);
+        // Nor can the log be continued past the gap.
+        assert!(AuditLog::open(&path, b"key".to_vec(), None, DEFAULT_AUDIT_KEEP).is_err());
+
+        // A head moved back by hand, or deleted, does not help.
+        let head: Value = serde_json::from_str(&fs::read_to_string(head_path(&path)).unwrap()).unwrap();
+        let forged = json!({ "seq": 4, "hmac": head["hmac"], "mac": head["mac"] });
+        fs::write(head_path(&path), forged.to_string()).unwrap();
+        assert!(verify_files(&files, b"key").unwrap_err().reason.contains("forged"));
+        fs::remove_file(head_path(&path)).unwrap();
+        assert!(verify_files(&files, b"key").unwrap_err().reason.contains("no head checkpoint"));
+    }
+}
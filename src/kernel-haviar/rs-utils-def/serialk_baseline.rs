@@ -0,0 +1,261 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::kdv::{BaselineCheck, KdvVerifier};
+use crate::serialk_gate::read_key_file;
+use crate::serialk_watcher::{Severity, WatchManager};
+
+/// The detached signature lives next to the manifest: `baseline.sha256.sig`.
+pub fn signature_path(manifest: &Path) -> PathBuf {
+    let mut path = manifest.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// The public half of a generated key: `baseline.key.pub`.
+pub fn public_key_path(key: &Path) -> PathBuf {
+    let mut path = key.as_os_str().to_owned();
+    path.push(".pub");
+    PathBuf::from(path)
+}
+
+/// Loads the local ed25519 signing key (a hex seed, mode 0600). A missing
+/// key is generated, with its public half written to `<key>.pub` so the
+/// manifest can later be pinned with `--require-signed-baseline`.
+pub fn load_or_create_signing_key(path: &Path) -> Result<SigningKey, String> {
+    if !path.exists() {
+        let key = generate_signing_key(path)?;
+        let public = public_key_path(path);
+        println!("[INIT] Generated baseline signing key {} (public key {})", path.display(), public.display());
+        return Ok(key);
+    }
+    load_signing_key(path)
+}
+
+/// Writes a new signing key to `path` and its public half to `<key>.pub`.
+/// An existing key is never overwritten.
+pub fn generate_signing_key(path: &Path) -> Result<SigningKey, String> {
+    let key = SigningKey::generate(&mut OsRng);
+    write_private(path, hex::encode(key.to_bytes()).as_bytes())
+        .map_err(|e| format!("Cannot create baseline signing key {}: {}", path.display(), e))?;
+    let public = public_key_path(path);
+    fs::write(&public, format!("{}\n", hex::encode(key.verifying_key().to_bytes())))
+        .map_err(|e| format!("Cannot write baseline public key {}: {}", public.display(), e))?;
+    Ok(key)
+}
+
+/// Loads a signing key as `generate_signing_key` writes it.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, String> {
+    let text = read_key_file(path, "baseline signing key")?;
+    let seed = decode_hex::<32>(&text)
+        .ok_or_else(|| format!("Baseline signing key {} is not a 32-byte hex ed25519 seed", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Loads a hex ed25519 public key as written to `<key>.pub`.
+pub fn load_public_key(path: &Path) -> Result<VerifyingKey, String> {
+    let text = fs::read(path).map_err(|e| format!("Cannot read baseline public key {}: {}", path.display(), e))?;
+    let bytes = decode_hex::<32>(&text)
+        .ok_or_else(|| format!("Baseline public key {} is not a 32-byte hex ed25519 key", path.display()))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|_| format!("Baseline public key {} is not a valid ed25519 point", path.display()))
+}
+
+/// Checks `<manifest>.sig` against the manifest bytes and loads the manifest.
+pub fn load_signed_manifest(manifest: &Path, key: &VerifyingKey) -> Result<KdvVerifier, String> {
+    let content = fs::read(manifest).map_err(|e| format!("Cannot read baseline {}: {}", manifest.display(), e))?;
+    let sig_path = signature_path(manifest);
+    let text = fs::read(&sig_path)
+        .map_err(|e| format!("Cannot read baseline signature {}: {}", sig_path.display(), e))?;
+    let signature = decode_hex::<64>(&text)
+        .map(|bytes| Signature::from_bytes(&bytes))
+        .ok_or_else(|| format!("Baseline signature {} is not a 64-byte hex ed25519 signature", sig_path.display()))?;
+    key.verify_strict(&content, &signature).map_err(|_| {
+        format!(
+            "Baseline {} does not verify against signature {} and the pinned public key",
+            manifest.display(),
+            sig_path.display()
+        )
+    })?;
+    KdvVerifier::from_manifest(manifest).map_err(|e| format!("kdv_baseline {}: {}", manifest.display(), e))
+}
+
+/// Writes a `sha256sum`-style manifest of `paths` and its signature.
+pub fn write_signed_manifest(manifest: &Path, paths: &[PathBuf], key: &SigningKey) -> io::Result<()> {
+    let mut text = String::from("# serialk-watcher trust-on-first-use baseline\n");
+    for path in paths {
+        let hash = KdvVerifier::hash_file(path)?;
+        text.push_str(&format!("{}  {}\n", hex::encode(hash), path.display()));
+    }
+    fs::write(manifest, &text)?;
+    let signature = key.sign(text.as_bytes());
+    fs::write(signature_path(manifest), format!("{}\n", hex::encode(signature.to_bytes())))
+}
+
+fn decode_hex<const N: usize>(text: &[u8]) -> Option<[u8; N]> {
+    let text = std::str::from_utf8(text).ok()?;
+    hex::decode(text.trim()).ok()?.try_into().ok()
+}
+
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(content)
+}
+
+impl WatchManager {
+    /// Trust on first use: records every watched file except the manifest
+    /// itself in a signed baseline for later `--require-signed-baseline` runs.
+    pub fn write_baseline(&self, manifest: &Path, key: &SigningKey) -> Result<(), String> {
+        let skip = [manifest.to_path_buf(), signature_path(manifest)];
+        let mut paths: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|path| !skip.iter().any(|s| fs::canonicalize(s).is_ok_and(|s| s == **path)))
+            .cloned()
+            .collect();
+        paths.sort();
+        write_signed_manifest(manifest, &paths, key)
+            .map_err(|e| format!("Cannot write baseline {}: {}", manifest.display(), e))?;
+        println!("[INIT] Wrote signed baseline of {} files to {}", paths.len(), manifest.display());
+        Ok(())
+    }
+
+    /// Pre-approved mode: every watched file must match the signed baseline.
+    /// Each one that does not is reported as a critical alert; the error
+    /// tells the caller to refuse to start.
+    pub fn check_signed_baseline(&mut self, manifest: &Path) -> Result<(), String> {
+        let Some(verifier) = &self.baseline else {
+            return Ok(());
+        };
+        let manifest = fs::canonicalize(manifest).unwrap_or_else(|_| manifest.to_path_buf());
+        let mut paths: Vec<PathBuf> = self.files.keys().filter(|path| **path != manifest).cloned().collect();
+        paths.sort();
+        let mismatched: Vec<(PathBuf, BaselineCheck)> = paths
+            .into_iter()
+            .map(|path| {
+                let check = verifier.check_file(&path);
+                (path, check)
+            })
+            .filter(|(_, check)| *check != BaselineCheck::Matches)
+            .collect();
+        for (path, check) in &mismatched {
+            self.emit_event(
+                "CRITICAL",
+                path,
+                &format!("{} {} (signed baseline)", path.display(), check.as_str()),
+                SystemTime::now(),
+                Some(Severity::Critical),
+            );
+        }
+        if mismatched.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "Refusing to start: {} watched file(s) do not match the signed baseline {}",
+            mismatched.len(),
+            manifest.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(dir: &Path) -> (PathBuf, PathBuf, WatchManager) {
+        let watched = dir.join("app.conf");
+        fs::write(&watched, "port = 80\n").unwrap();
+        let key = dir.join("baseline.key");
+        let manifest = dir.join("baseline.sha256");
+
+        let mut wm = WatchManager::new();
+        wm.add_file(watched.clone(), None).unwrap();
+        let signing = load_or_create_signing_key(&key).unwrap();
+        wm.write_baseline(&manifest, &signing).unwrap();
+        (key, manifest, wm)
+    }
+
+    #[test]
+    fn test_good_signature_verifies_and_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (key, manifest, mut wm) = setup(dir.path());
+
+        // Reloading the generated key signs identically.
+        let reloaded = load_or_create_signing_key(&key).unwrap();
+        let public = load_public_key(&public_key_path(&key)).unwrap();
+        assert_eq!(reloaded.verifying_key(), public);
+
+        wm.baseline = Some(load_signed_manifest(&manifest, &public).unwrap());
+        assert!(wm.check_signed_baseline(&manifest).is_ok());
+    }
+
+    #[test]
+    fn test_bad_signature_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (key, manifest, _) = setup(dir.path());
+        let public = load_public_key(&public_key_path(&key)).unwrap();
+
+        // Pinned to some other key.
+        let other = SigningKey::generate(&mut OsRng);
+        let err = load_signed_manifest(&manifest, &other.verifying_key()).err().unwrap();
+        assert!(err.contains("does not verify"), "{}", err);
+
+        // A manifest edited after signing.
+        let mut text = fs::read_to_string(&manifest).unwrap();
+        text.push_str(&format!("{}  /etc/extra\n", "00".repeat(32)));
+        fs::write(&manifest, text).unwrap();
+        let err = load_signed_manifest(&manifest, &public).err().unwrap();
+        assert!(err.contains("does not verify"), "{}", err);
+
+        fs::write(signature_path(&manifest), "not hex\n").unwrap();
+        let err = load_signed_manifest(&manifest, &public).err().unwrap();
+        assert!(err.contains("is not a 64-byte hex ed25519 signature"), "{}", err);
+
+        fs::remove_file(signature_path(&manifest)).unwrap();
+        let err = load_signed_manifest(&manifest, &public).err().unwrap();
+        assert!(err.starts_with("Cannot read baseline signature"), "{}", err);
+    }
+
+    #[test]
+    fn test_drifted_file_refuses_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let (key, manifest, mut wm) = setup(dir.path());
+        let public = load_public_key(&public_key_path(&key)).unwrap();
+        wm.baseline = Some(load_signed_manifest(&manifest, &public).unwrap());
+
+        fs::write(dir.path().join("app.conf"), "port = 8080\n").unwrap();
+        let err = wm.check_signed_baseline(&manifest).unwrap_err();
+        assert!(err.starts_with("Refusing to start: 1 watched file(s)"), "{}", err);
+
+        // Files outside the signed set are refused as well.
+        fs::write(dir.path().join("app.conf"), "port = 80\n").unwrap();
+        let extra = dir.path().join("extra.conf");
+        fs::write(&extra, "x").unwrap();
+        wm.add_file(extra, None).unwrap();
+        assert!(wm.check_signed_baseline(&manifest).is_err());
+    }
+
+    #[test]
+    fn test_key_loading_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let public = dir.path().join("key.pub");
+        let err = load_public_key(&public).err().unwrap();
+        assert!(err.starts_with("Cannot read baseline public key"), "{}", err);
+
+        fs::write(&public, "abcd\n").unwrap();
+        let err = load_public_key(&public).err().unwrap();
+        assert!(err.contains("is not a 32-byte hex ed25519 key"), "{}", err);
+
+        let key = dir.path().join("baseline.key");
+        write_private(&key, b"zz").unwrap();
+        let err = load_or_create_signing_key(&key).err().unwrap();
+        assert!(err.contains("is not a 32-byte hex ed25519 seed"), "{}", err);
+    }
+}
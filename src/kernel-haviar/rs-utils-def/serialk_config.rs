@@ -0,0 +1,515 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::serialk_rate::AlertLimiter;
+use crate::serialk_scan::BACKGROUND_SCAN_THRESHOLD;
+use crate::hash_pool::{self, IoClass, Priority, Throttle};
+use crate::hfs::HfsConfig;
+use crate::kdv::KdvVerifier;
+use crate::serialk_audit::{AuditLog, DEFAULT_AUDIT_KEEP};
+use crate::serialk_baseline::{load_public_key, load_signed_manifest};
+use crate::serialk_gate::read_key_file;
+use crate::serialk_watcher::{
+    parse_duration, parse_line_watch, parse_liner_street, split_severity, LargeFileMode, LargeFilePolicy, LineWatch,
+    PausedMode, Severity, TamperAction, UnbaselinedPolicy, ViolationResponse, WatchManager,
+};
+
+/// On-disk watcher configuration (`serialk-watcher --config watcher.toml`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatcherConfig {
+    /// Paths or globs, optionally suffixed with a level: `/etc/shadow=critical`.
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Extra patterns on top of the built-in editor temp file list.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Drop the built-in list (`*.swp`, `*~`, `4913`, ...).
+    #[serde(default)]
+    pub no_default_ignores: bool,
+    #[serde(default)]
+    pub liner_street: Vec<LinerStreetEntry>,
+    /// Directory levels watched below an included directory (default: all).
+    pub max_depth: Option<usize>,
+    /// Whether changes made while paused are absorbed (default) or held.
+    pub paused_mode: Option<PausedMode>,
+    /// Resume on its own after a pause of this many seconds.
+    pub max_pause_secs: Option<u64>,
+    /// Files above this many bytes are hashed in streamed chunks.
+    pub large_file_threshold: Option<u64>,
+    /// `hash` (default) or `stat`: skip hashing large files whose size and mtime are unchanged.
+    pub large_file_mode: Option<LargeFileMode>,
+    /// Files the initial fingerprint pass hashes at once (default: cores, at most 4).
+    pub scan_jobs: Option<usize>,
+    /// Bytes per second the initial fingerprint pass may read.
+    pub scan_max_bandwidth: Option<u64>,
+    /// Niceness (0 to 19) of the initial fingerprint pass, on Linux.
+    pub scan_nice: Option<i32>,
+    /// `idle` or `best-effort` I/O class for the initial fingerprint pass, on Linux.
+    pub scan_ionice: Option<IoClass>,
+    pub output: Option<PathBuf>,
+    pub debounce_ms: Option<u64>,
+    pub tamper_action: Option<TamperAction>,
+    /// Level for includes that do not name one (default critical).
+    pub default_severity: Option<Severity>,
+    /// Lowest level that triggers the tamper action (default critical).
+    pub tamper_threshold: Option<Severity>,
+    /// Alerts per path and minute before further ones are suppressed; 0 disables.
+    pub alert_rate: Option<u32>,
+    /// `sha256sum`-style manifest of approved file hashes.
+    pub kdv_baseline: Option<PathBuf>,
+    /// kdv baseline (JSON or checksum file) re-checked in full every
+    /// `kdv_daemon_interval_secs`, alerting through the watcher's sinks.
+    pub kdv_daemon: Option<PathBuf>,
+    /// Default one hour.
+    pub kdv_daemon_interval_secs: Option<u64>,
+    /// Alert on (default) or ignore changes to files missing from `kdv_baseline`.
+    pub unbaselined: Option<UnbaselinedPolicy>,
+    /// ed25519 public key `kdv_baseline` must be signed with; the watcher
+    /// refuses to start unless every watched file matches it.
+    pub require_signed_baseline: Option<PathBuf>,
+    /// Trust on first use: write the startup hashes here, signed with `baseline_signing_key`.
+    pub write_baseline: Option<PathBuf>,
+    /// Hex ed25519 seed (mode 0600), generated along with `<key>.pub` if missing.
+    pub baseline_signing_key: Option<PathBuf>,
+    /// Append-only, HMAC-chained ndjson record of every event.
+    pub audit_log: Option<PathBuf>,
+    /// Secret key (mode 0600) for the audit log chain.
+    pub audit_key: Option<PathBuf>,
+    /// Rotate the audit log once it would grow past this many bytes.
+    pub audit_max_size: Option<u64>,
+    /// Rotated audit files to keep (default 5).
+    pub audit_keep: Option<usize>,
+    /// Do not watch the watcher's own binary, config and baseline.
+    #[serde(default)]
+    pub no_self_protect: bool,
+    pub quarantine_dir: Option<PathBuf>,
+    pub snapshot_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    /// Refuse to start if any path cannot be watched instead of skipping it.
+    #[serde(default)]
+    pub strict: bool,
+    /// Emit events as JSON lines instead of `[KIND] message`.
+    #[serde(default)]
+    pub json: bool,
+    pub rearm_after_secs: Option<u64>,
+    /// Also alert on mode, ownership and mtime changes.
+    #[serde(default)]
+    pub check_metadata: bool,
+    /// Watch the targets of symlinks (and alert on retargeting) instead of refusing them.
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    pub summary_interval_secs: Option<u64>,
+    /// How often files that could not get a native watch are re-hashed.
+    pub poll_interval_secs: Option<u64>,
+    /// JSON status document rewritten every `status_interval_secs`.
+    pub status_file: Option<PathBuf>,
+    pub status_interval_secs: Option<u64>,
+    /// Run the HFS process monitor alongside, with the keys of an hfs.toml.
+    pub hfs: Option<HfsConfig>,
+    /// What an HFS violation makes the watcher do (default: verify every file).
+    pub on_violation: Option<ViolationResponse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinerStreetEntry {
+    pub path: PathBuf,
+    pub count: Option<usize>,
+    #[serde(default)]
+    pub forever: bool,
+    /// Watch window such as "30m" or "2h".
+    pub duration: Option<String>,
+    pub severity: Option<Severity>,
+}
+
+impl LinerStreetEntry {
+    pub fn from_watch(path: PathBuf, watch: &LineWatch) -> Self {
+        match watch {
+            LineWatch::Count(count) => Self {
+                path,
+                count: Some(*count),
+                forever: false,
+                duration: None,
+                severity: None,
+            },
+            LineWatch::Forever => Self {
+                path,
+                count: None,
+                forever: true,
+                duration: None,
+                severity: None,
+            },
+            LineWatch::Duration(window) => Self {
+                path,
+                count: None,
+                forever: false,
+                duration: Some(format!("{}s", window.as_secs())),
+                severity: None,
+            },
+        }
+    }
+
+    pub fn watch(&self) -> Result<LineWatch, String> {
+        if self.forever {
+            Ok(LineWatch::Forever)
+        } else if let Some(window) = &self.duration {
+            parse_duration(window)
+                .map(LineWatch::Duration)
+                .map_err(|e| format!("liner_street {}: duration: {}", self.path.display(), e))
+        } else {
+            Ok(LineWatch::Count(self.count.unwrap_or(1)))
+        }
+    }
+}
+
+impl WatcherConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// toml reports the offending key together with its line and column.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Reads an `--include-from` list from a file, or from stdin for `-`.
+    pub fn read_include_list(&mut self, source: &str) -> Result<(), String> {
+        let text = if source == "-" {
+            io::read_to_string(io::stdin()).map_err(|e| format!("Cannot read include list from stdin: {}", e))?
+        } else {
+            fs::read_to_string(source).map_err(|e| format!("Cannot read include list {}: {}", source, e))?
+        };
+        self.add_include_list(source, &text)
+    }
+
+    /// One path or glob per line, with the same `=level` suffix as
+    /// `--include`. Lines ending in a liner-street value (`path:3`,
+    /// `path:forever-all-day`, `path:30m`) become liner-street entries.
+    /// Blank lines and `#` comments are skipped.
+    pub fn add_include_list(&mut self, source: &str, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (spec, severity) = split_severity(line);
+            let liner = match spec.rsplit_once(':') {
+                Some((_, value)) if value.bytes().all(|b| b.is_ascii_digit()) => true,
+                Some((_, value)) => parse_line_watch(value).is_ok(),
+                None => false,
+            };
+            if !liner {
+                self.include.push(line.to_string());
+                continue;
+            }
+            let (path, watch) = parse_liner_street(spec).map_err(|e| format!("{}:{}: {}", source, number + 1, e))?;
+            self.liner_street.push(LinerStreetEntry {
+                severity,
+                ..LinerStreetEntry::from_watch(path, &watch)
+            });
+        }
+        Ok(())
+    }
+
+    /// Expands glob entries; plain paths are passed through untouched. A
+    /// `=level` suffix applies to every path the entry expands to.
+    pub fn include_paths(&self) -> Result<Vec<(PathBuf, Option<Severity>)>, String> {
+        let mut paths = Vec::new();
+        for spec in &self.include {
+            let (entry, severity) = split_severity(spec);
+            if entry.contains(['*', '?', '[']) {
+                let matches = glob::glob(entry).map_err(|e| format!("include: invalid glob {}: {}", entry, e))?;
+                paths.extend(matches.filter_map(Result::ok).map(|path| (path, severity)));
+            } else {
+                paths.push((PathBuf::from(entry), severity));
+            }
+        }
+        Ok(paths)
+    }
+}
+
+impl WatchManager {
+    pub fn from_config(config: &WatcherConfig) -> Result<Self, String> {
+        let mut wm = WatchManager::new();
+
+        if let Some(output) = &config.output {
+            wm.output_path = output.clone();
+        }
+        if let Some(response) = &config.on_violation {
+            wm.violation_response = response.clone();
+        }
+        if let Some(ms) = config.debounce_ms {
+            wm.set_debounce(Duration::from_millis(ms));
+        }
+        for pattern in &config.exclude {
+            wm.add_exclude(pattern)
+                .map_err(|e| format!("exclude: invalid pattern {}: {}", pattern, e))?;
+        }
+        if config.no_default_ignores {
+            wm.ignores.clear();
+        }
+        for pattern in &config.ignore {
+            wm.add_ignore(pattern)
+                .map_err(|e| format!("ignore: invalid pattern {}: {}", pattern, e))?;
+        }
+
+        match (config.tamper_action, &config.quarantine_dir) {
+            (Some(TamperAction::Quarantine) | None, Some(dir)) => {
+                wm.enable_quarantine(dir.clone(), config.snapshot_dir.clone())
+                    .map_err(|e| format!("quarantine_dir: {}", e))?;
+            }
+            (Some(TamperAction::Quarantine), None) => {
+                return Err("tamper_action = \"quarantine\" requires quarantine_dir".to_string());
+            }
+            (Some(action), _) => wm.tamper_action = action,
+            (None, None) => {}
+        }
+        if let Some(severity) = config.default_severity {
+            wm.default_severity = severity;
+        }
+        if let Some(threshold) = config.tamper_threshold {
+            wm.tamper_threshold = threshold;
+        }
+        match (&config.kdv_baseline, &config.require_signed_baseline) {
+            (Some(path), Some(key)) => {
+                let key = load_public_key(key)?;
+                wm.baseline = Some(load_signed_manifest(path, &key)?);
+            }
+            (Some(path), None) => {
+                let verifier =
+                    KdvVerifier::from_manifest(path).map_err(|e| format!("kdv_baseline {}: {}", path.display(), e))?;
+                wm.baseline = Some(verifier);
+            }
+            (None, Some(_)) => return Err("require_signed_baseline requires kdv_baseline".to_string()),
+            (None, None) => {}
+        }
+        if config.write_baseline.is_some() {
+            if config.require_signed_baseline.is_some() {
+                return Err("write_baseline cannot be combined with require_signed_baseline".to_string());
+            }
+            if config.baseline_signing_key.is_none() {
+                return Err("write_baseline requires baseline_signing_key".to_string());
+            }
+        }
+        if let Some(policy) = config.unbaselined {
+            wm.unbaselined = policy;
+        }
+        if let Some(path) = &config.audit_log {
+            let key_path = config
+                .audit_key
+                .as_ref()
+                .ok_or_else(|| "audit_log requires audit_key".to_string())?;
+            let key = read_key_file(key_path, "audit key")?;
+            let keep = config.audit_keep.unwrap_or(DEFAULT_AUDIT_KEEP);
+            let audit = AuditLog::open(path, key, config.audit_max_size, keep)
+                .map_err(|e| format!("audit_log {}: {}", path.display(), e))?;
+            wm.audit = Some(audit);
+        }
+        match config.alert_rate {
+            Some(0) => wm.alert_limiter = None,
+            Some(rate) => wm.alert_limiter = Some(AlertLimiter::new(rate)),
+            None => {}
+        }
+
+        wm.webhooks = config.webhooks.clone();
+        wm.json = config.json;
+        wm.rearm_after = config.rearm_after_secs.map(Duration::from_secs);
+        wm.check_metadata = config.check_metadata;
+        wm.follow_symlinks = config.follow_symlinks;
+        wm.max_depth = config.max_depth;
+        wm.large_file = match (config.large_file_threshold, config.large_file_mode) {
+            (Some(threshold), mode) => Some(LargeFilePolicy {
+                threshold,
+                mode: mode.unwrap_or(LargeFileMode::Hash),
+            }),
+            (None, Some(_)) => return Err("large_file_mode requires large_file_threshold".to_string()),
+            (None, None) => None,
+        };
+        if let Some(mode) = config.paused_mode {
+            wm.paused_mode = mode;
+        }
+        wm.max_pause = config.max_pause_secs.map(Duration::from_secs);
+        wm.summary_interval = config.summary_interval_secs.map(Duration::from_secs);
+        if let Some(secs) = config.poll_interval_secs {
+            wm.poll_interval = Duration::from_secs(secs);
+        }
+        wm.status_file = config.status_file.clone();
+        if let Some(secs) = config.status_interval_secs {
+            wm.status_interval = Duration::from_secs(secs);
+        }
+
+        let liner = config
+            .liner_street
+            .iter()
+            .map(|entry| Ok((entry.path.clone(), entry.watch()?, entry.severity)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let (jobs, mut failures) = wm.plan_scan(&config.include_paths()?, &liner);
+
+        // Large trees keep fingerprinting while the watch loop already runs;
+        // strict mode has to see every failure before it can start, and the
+        // signed baseline modes need the whole watch set.
+        let whole_set = config.strict || config.require_signed_baseline.is_some() || config.write_baseline.is_some();
+        let background = !whole_set && jobs.len() > BACKGROUND_SCAN_THRESHOLD;
+        if let Some(nice) = config.scan_nice.filter(|nice| !(0..=19).contains(nice)) {
+            return Err(format!("scan_nice {} is not between 0 and 19", nice));
+        }
+        wm.scan_priority = Priority {
+            nice: config.scan_nice,
+            ionice: config.scan_ionice,
+        };
+        wm.scan_throttle = config.scan_max_bandwidth.map(|rate| Arc::new(Throttle::new(rate)));
+        wm.start_initial_scan(jobs, config.scan_jobs.unwrap_or_else(hash_pool::default_jobs));
+        if !background {
+            failures.extend(wm.drain_scan(true));
+        }
+
+        if !failures.is_empty() {
+            let report: Vec<String> = failures.iter().map(|e| e.to_string()).collect();
+            if config.strict {
+                return Err(format!("Cannot watch:\n  {}", report.join("\n  ")));
+            }
+            for line in report {
+                eprintln!("[SKIPPED] {}", line);
+            }
+        }
+
+        Ok(wm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("conf.d")).unwrap();
+        fs::write(root.join("conf.d/a.conf"), "a").unwrap();
+        fs::write(root.join("conf.d/b.conf"), "b").unwrap();
+        fs::write(root.join("conf.d/b.conf.swp"), "swap").unwrap();
+        fs::write(root.join("passwd"), "root:x:0:0\n").unwrap();
+
+        let text = format!(
+            r#"
+include = ["{root}/conf.d"]
+exclude = ["*.swp"]
+output = "{root}/out.pself"
+debounce_ms = 250
+tamper_action = "log"
+webhooks = ["http://127.0.0.1:9/hook"]
+
+[[liner_street]]
+path = "{root}/passwd"
+count = 3
+
+[hfs]
+patterns = ["(?i)frida"]
+
+[on_violation]
+export = true
+run = "logger hfs"
+"#,
+            root = root.display()
+        );
+        let config = WatcherConfig::parse(&text).unwrap();
+        let wm = WatchManager::from_config(&config).unwrap();
+
+        assert_eq!(wm.files.len(), 3);
+        assert!(!wm.files.contains_key(&root.join("conf.d/b.conf.swp")));
+        assert!(wm.dirs.contains(&root.join("conf.d")));
+        assert_eq!(wm.output_path, root.join("out.pself"));
+        assert_eq!(wm.debounce, Duration::from_millis(250));
+        assert_eq!(wm.tamper_action, TamperAction::Log);
+        assert_eq!(wm.webhooks.len(), 1);
+        assert!(matches!(
+            wm.files[&root.join("passwd")].liner_watch,
+            Some(LineWatch::Count(3))
+        ));
+        assert_eq!(config.hfs.as_ref().unwrap().patterns, [crate::hfs::PatternRule::new("(?i)frida")]);
+        assert!(wm.violation_response.verify && wm.violation_response.export);
+        assert_eq!(wm.violation_response.run.as_deref(), Some("logger hfs"));
+    }
+
+    #[test]
+    fn test_missing_include_is_skipped_unless_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.conf");
+        fs::write(&good, "ok").unwrap();
+
+        let mut config = WatcherConfig {
+            include: vec![
+                good.display().to_string(),
+                dir.path().join("nope.conf").display().to_string(),
+            ],
+            ..Default::default()
+        };
+        let wm = WatchManager::from_config(&config).unwrap();
+        assert_eq!(wm.files.len(), 1);
+        assert!(wm.files.contains_key(&good));
+
+        config.strict = true;
+        let err = WatchManager::from_config(&config).err().unwrap();
+        assert!(err.contains("nope.conf"), "{}", err);
+    }
+
+    #[test]
+    fn test_include_list_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join("d")).unwrap();
+        for name in ["a.conf", "b.conf", "notes.txt", "passwd", "d/keep.rules", "d/skip.rules"] {
+            fs::write(root.join(name), "x\ny\n").unwrap();
+        }
+        let list = root.join("watch.list");
+        fs::write(
+            &list,
+            format!(
+                "# generated by deploy\n\n{root}/*.conf=warning\n   # indented comment\n{root}/missing.conf\n{root}/d\n{root}/passwd:2\n",
+                root = root.display()
+            ),
+        )
+        .unwrap();
+
+        let mut config = WatcherConfig {
+            include: vec![root.join("notes.txt").display().to_string()],
+            exclude: vec!["skip.rules".to_string()],
+            ..Default::default()
+        };
+        config.read_include_list(list.to_str().unwrap()).unwrap();
+        assert_eq!(config.include.len(), 4);
+        assert_eq!(config.liner_street.len(), 1);
+
+        let wm = WatchManager::from_config(&config).unwrap();
+        let mut watched: Vec<_> = wm.files.keys().map(|p| p.file_name().unwrap().to_owned()).collect();
+        watched.sort();
+        assert_eq!(watched, ["a.conf", "b.conf", "keep.rules", "notes.txt", "passwd"]);
+        assert_eq!(wm.files[&root.join("a.conf")].severity, Severity::Warning);
+        assert!(matches!(wm.files[&root.join("passwd")].liner_watch, Some(LineWatch::Count(2))));
+
+        config.strict = true;
+        let err = WatchManager::from_config(&config).err().unwrap();
+        assert!(err.contains("missing.conf"), "{}", err);
+
+        let err = config.add_include_list("bad.list", "# ok\n/etc/passwd:0\n").unwrap_err();
+        assert!(err.starts_with("bad.list:2:"), "{}", err);
+    }
+
+    #[test]
+    fn test_invalid_config_names_key_and_line() {
+        let err = WatcherConfig::parse("include = []\n\ndebounce = 5\n").unwrap_err();
+        assert!(err.contains("debounce"), "{}", err);
+        assert!(err.contains("line 3"), "{}", err);
+    }
+}
@@ -0,0 +1,442 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::permission_capability::Capability;
+use crate::permission_manager::PermissionManager;
+use crate::permission_session::SessionToken;
+use crate::serialk_watcher::{parse_line_watch, Severity, WatchManager};
+
+/// One line of the control protocol, e.g. `{"cmd": "add", "path": "/etc/hosts"}`.
+/// The line also carries the sender's `permission-manager` session as
+/// `"token"`, which `authorized` takes off before parsing the rest.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ControlCommand {
+    Add {
+        path: PathBuf,
+        liner: Option<String>,
+        severity: Option<Severity>,
+    },
+    Remove { path: PathBuf },
+    List,
+    Status,
+    Rearm { path: PathBuf },
+    ExportNow,
+    Pause,
+    Resume,
+    /// Check the HFS protected files for open descriptors at the next scan.
+    ScanFds,
+    /// Start a kdv daemon round now.
+    KdvVerify,
+    /// Re-read the kdv daemon's baseline.
+    KdvReload,
+}
+
+impl ControlCommand {
+    /// What a `permission-manager` session needs to send this command.
+    pub fn capability(&self) -> Capability {
+        match self {
+            ControlCommand::List | ControlCommand::Status | ControlCommand::KdvVerify => Capability::ReadStatus,
+            ControlCommand::Add { .. }
+            | ControlCommand::Remove { .. }
+            | ControlCommand::Rearm { .. }
+            | ControlCommand::ExportNow
+            | ControlCommand::Pause
+            | ControlCommand::Resume
+            | ControlCommand::ScanFds
+            | ControlCommand::KdvReload => Capability::ModifyWatchSet,
+        }
+    }
+}
+
+/// A command handed from the socket thread to the watch loop, which owns the
+/// manager and answers on `reply`.
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: Sender<Value>,
+}
+
+/// Opens the sessions the watcher checks control lines against, afresh for
+/// every line so that sessions granted since it started count.
+pub type OpenSessions = Box<dyn Fn() -> Result<PermissionManager, String> + Send>;
+
+/// The command on `line`, if the session it presents is live and was
+/// granted the command's capability.
+fn authorized(line: &str, sessions: &OpenSessions) -> Result<ControlCommand, String> {
+    let mut value: Value = serde_json::from_str(line).map_err(|e| format!("bad command: {}", e))?;
+    let token = value.as_object_mut().and_then(|fields| fields.remove("token"));
+    let command: ControlCommand = serde_json::from_value(value).map_err(|e| format!("bad command: {}", e))?;
+    let token: SessionToken = match token {
+        Some(Value::String(token)) => token.parse()?,
+        Some(_) => return Err("a session token is 32 hex digits".to_string()),
+        None => return Err("No session; get one from permission-manager and pass --token or --token-file".to_string()),
+    };
+    sessions()?
+        .check_capability(&token, command.capability())
+        .map_err(|e| format!("Permission denied: {}", e))?;
+    Ok(command)
+}
+
+fn error(message: impl std::fmt::Display) -> Value {
+    json!({ "ok": false, "error": message.to_string() })
+}
+
+impl WatchManager {
+    /// Answers every control command queued since the last loop iteration.
+    pub fn process_control(&mut self) {
+        let requests: Vec<ControlRequest> = match &self.control {
+            Some(rx) => rx.try_iter().collect(),
+            None => return,
+        };
+        for request in requests {
+            let response = self.handle_control(request.command);
+            let _ = request.reply.send(response);
+        }
+    }
+
+    pub fn handle_control(&mut self, command: ControlCommand) -> Value {
+        match command {
+            ControlCommand::Add { path, liner, severity } => {
+                let watch = match liner.as_deref().map(parse_line_watch).transpose() {
+                    Ok(watch) => watch,
+                    Err(e) => return error(e),
+                };
+                let severity = severity.unwrap_or(self.default_severity);
+                let result = match watch {
+                    Some(watch) => self.add_file_as(path.clone(), Some(watch), severity),
+                    None => self.add_path_as(&path, severity),
+                };
+                match result {
+                    Ok(()) => {
+                        self.export();
+                        json!({ "ok": true, "path": path.to_string_lossy() })
+                    }
+                    Err(e) => error(e),
+                }
+            }
+            ControlCommand::Remove { path } => {
+                let path = self.tracked_key(&path);
+                if self.protected.contains(&path) {
+                    return error(format!("{} is self-protected", path.display()));
+                }
+                if !self.remove_file(&path) {
+                    return error(format!("{} is not watched", path.display()));
+                }
+                self.export();
+                json!({ "ok": true, "path": path.to_string_lossy() })
+            }
+            ControlCommand::List => {
+                let files: Vec<String> = self
+                    .export_paths()
+                    .iter()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .collect();
+                json!({ "ok": true, "files": files })
+            }
+            ControlCommand::Status => {
+                let mut status = self.status_json();
+                status["ok"] = true.into();
+                status
+            }
+            ControlCommand::Rearm { path } => {
+                let path = self.tracked_key(&path);
+                if self.rearm(&path) {
+                    json!({ "ok": true, "path": path.to_string_lossy() })
+                } else {
+                    error(format!("{} is not watched", path.display()))
+                }
+            }
+            ControlCommand::ExportNow => {
+                self.export_now();
+                json!({ "ok": true, "output": self.output_path.to_string_lossy() })
+            }
+            ControlCommand::Pause => {
+                if !self.pause("control socket") {
+                    return error("already paused");
+                }
+                json!({ "ok": true, "paused": true })
+            }
+            ControlCommand::Resume => {
+                if !self.resume("control socket") {
+                    return error("not paused");
+                }
+                json!({ "ok": true, "paused": false })
+            }
+            ControlCommand::ScanFds => match &self.hfs_fd_scan {
+                Some(requested) => {
+                    requested.store(true, std::sync::atomic::Ordering::Relaxed);
+                    json!({ "ok": true, "scheduled": true })
+                }
+                None => error("no HFS monitor is running"),
+            },
+            ControlCommand::KdvVerify => match self.kdv_daemon.as_ref().map(|daemon| daemon.verify_now()) {
+                Some(Ok(())) => json!({ "ok": true, "scheduled": true }),
+                Some(Err(e)) => error(e),
+                None => error("no kdv daemon is running"),
+            },
+            ControlCommand::KdvReload => match self.kdv_daemon.as_ref().map(|daemon| daemon.reload()) {
+                Some(Ok(entries)) => json!({ "ok": true, "entries": entries }),
+                Some(Err(e)) => error(e),
+                None => error("no kdv daemon is running"),
+            },
+        }
+    }
+}
+
+/// Renders a status document (from the socket or `--status-file`) as a table.
+pub fn render_status(status: &Value) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let ago = |v: &Value| match v.as_u64() {
+        Some(t) => format!("{}s ago", now.saturating_sub(t)),
+        None => "never".to_string(),
+    };
+
+    let mut rows = vec![[
+        "PATH".to_string(),
+        "WATCH".to_string(),
+        "SEVERITY".to_string(),
+        "EVENTS".to_string(),
+        "ALERTS".to_string(),
+        "LAST EVENT".to_string(),
+        "ADDED".to_string(),
+        "FINGERPRINT".to_string(),
+    ]];
+    for entry in status["entries"].as_array().into_iter().flatten() {
+        rows.push([
+            entry["path"].as_str().unwrap_or_default().to_string(),
+            entry["watch"].as_str().unwrap_or_default().to_string(),
+            entry["severity"].as_str().unwrap_or_default().to_string(),
+            entry["events"].to_string(),
+            entry["alerts"].to_string(),
+            ago(&entry["last_event"]),
+            ago(&entry["added_at"]),
+            entry["fingerprint"].as_str().unwrap_or_default().to_string(),
+        ]);
+    }
+
+    let mut widths = [0usize; 8];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row.iter().zip(widths).map(|(cell, w)| format!("{:<w$}", cell, w = w)).collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "{} files ({} polled), {} events, {} modifications, {} alerts ({} suppressed), {} exports\n",
+        status["files"],
+        status["polled"],
+        status["events"],
+        status["modified"],
+        status["alerts"],
+        status["suppressed"],
+        status["exports"]
+    ));
+    if let Some(pause) = status["paused"].as_object() {
+        out.push_str(&format!(
+            "PAUSED for {}s (mode {}, {} changes absorbed)\n",
+            pause["secs"],
+            pause["mode"].as_str().unwrap_or_default(),
+            pause["absorbed"]
+        ));
+    }
+    out
+}
+
+#[cfg(unix)]
+pub use self::unix::{listen, send};
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Binds the control socket (owner-only, mode 0600) and serves it from a
+    /// background thread. Commands whose session `sessions` allows reach the
+    /// watch loop through the returned receiver.
+    pub fn listen(path: &Path, sessions: OpenSessions) -> io::Result<Receiver<ControlRequest>> {
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another watcher", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+
+        // Create the socket without group/other bits so there is no window
+        // in which someone else could connect.
+        // SAFETY: umask only swaps the process file mode mask.
+        let old_mask = unsafe { libc::umask(0o177) };
+        let bound = UnixListener::bind(path);
+        unsafe { libc::umask(old_mask) };
+        let listener = bound?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = serve(stream, &tx, &sessions) {
+                            eprintln!("[WARN] Control connection failed: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("[WARN] Control socket accept failed: {}", e),
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    fn serve(stream: UnixStream, tx: &Sender<ControlRequest>, sessions: &OpenSessions) -> io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match authorized(&line, sessions) {
+                Ok(command) => {
+                    let (reply, answer) = channel();
+                    if tx.send(ControlRequest { command, reply }).is_err() {
+                        return Ok(());
+                    }
+                    answer
+                        .recv_timeout(Duration::from_secs(30))
+                        .unwrap_or_else(|_| error("watcher did not answer"))
+                }
+                Err(e) => error(e),
+            };
+            writeln!(writer, "{}", response)?;
+        }
+        Ok(())
+    }
+
+    /// Client side: sends one command and returns the watcher's answer.
+    pub fn send(path: &Path, command: &Value) -> io::Result<Value> {
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "{}", command)?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permission_lockout::LockoutPolicy;
+    use crate::test_support::{start_manager, Fixed, MockClock};
+    use std::fs;
+
+    #[test]
+    fn test_only_reading_commands_need_just_read_status() {
+        let capability = |line: &str| serde_json::from_str::<ControlCommand>(line).unwrap().capability();
+        assert_eq!(capability(r#"{"cmd": "status"}"#), Capability::ReadStatus);
+        assert_eq!(capability(r#"{"cmd": "list"}"#), Capability::ReadStatus);
+        assert_eq!(capability(r#"{"cmd": "remove", "path": "/etc/hosts"}"#), Capability::ModifyWatchSet);
+        assert_eq!(capability(r#"{"cmd": "pause"}"#), Capability::ModifyWatchSet);
+    }
+
+    #[test]
+    fn test_a_read_status_session_may_not_edit_the_watch_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = MockClock::new();
+        let state = dir.path().to_path_buf();
+        let sessions: OpenSessions =
+            Box::new(move || Ok(start_manager(Fixed, LockoutPolicy::default(), &clock, Some(&state))));
+        let token = sessions()
+            .unwrap()
+            .request_permission("carol", "correct horse", &[Capability::ReadStatus])
+            .unwrap();
+
+        let status = format!(r#"{{"cmd": "status", "token": "{}"}}"#, token);
+        assert!(matches!(authorized(&status, &sessions), Ok(ControlCommand::Status)));
+        let add = format!(r#"{{"cmd": "add", "path": "/etc/hosts", "token": "{}"}}"#, token);
+        let reload = format!(r#"{{"cmd": "kdv-reload", "token": "{}"}}"#, token);
+        for line in [add, reload] {
+            assert_eq!(
+                authorized(&line, &sessions).unwrap_err(),
+                "Permission denied: missing capability modify-watch-set"
+            );
+        }
+        assert!(authorized(r#"{"cmd": "status"}"#, &sessions).unwrap_err().starts_with("No session"));
+        let stranger = format!(r#"{{"cmd": "status", "token": "{}"}}"#, "0".repeat(32));
+        assert_eq!(authorized(&stranger, &sessions).unwrap_err(), "Permission denied: no valid session");
+    }
+
+    #[test]
+    fn test_control_commands_edit_watch_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.conf");
+        let b = dir.path().join("b.conf");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.output_path = dir.path().join("out.pself");
+        wm.add_file(a.clone(), None).unwrap();
+
+        let add: ControlCommand =
+            serde_json::from_str(&format!(r#"{{"cmd": "add", "path": "{}", "liner": "3"}}"#, b.display())).unwrap();
+        assert_eq!(wm.handle_control(add)["ok"], true);
+        let remove: ControlCommand =
+            serde_json::from_str(&format!(r#"{{"cmd": "remove", "path": "{}"}}"#, a.display())).unwrap();
+        assert_eq!(wm.handle_control(remove)["ok"], true);
+
+        let list = wm.handle_control(ControlCommand::List);
+        assert_eq!(list["files"], json!([b.to_string_lossy()]));
+        assert_eq!(wm.handle_control(ControlCommand::Remove { path: a })["ok"], false);
+        assert!(serde_json::from_str::<ControlCommand>(r#"{"cmd": "reboot"}"#).is_err());
+    }
+
+    #[test]
+    fn test_pause_and_resume_commands() {
+        let mut wm = WatchManager::new();
+        let pause: ControlCommand = serde_json::from_str(r#"{"cmd": "pause"}"#).unwrap();
+        assert_eq!(wm.handle_control(pause)["ok"], true);
+        assert_eq!(wm.handle_control(ControlCommand::Pause)["ok"], false);
+        assert!(render_status(&wm.status_json()).contains("PAUSED for 0s (mode rebaseline, 0 changes absorbed)"));
+
+        assert_eq!(wm.handle_control(ControlCommand::Resume)["ok"], true);
+        assert_eq!(wm.handle_control(ControlCommand::Resume)["ok"], false);
+        assert!(!render_status(&wm.status_json()).contains("PAUSED"));
+    }
+
+    #[test]
+    fn test_scan_fds_needs_a_monitor() {
+        let mut wm = WatchManager::new();
+        let scan: ControlCommand = serde_json::from_str(r#"{"cmd": "scan-fds"}"#).unwrap();
+        assert_eq!(wm.handle_control(scan)["ok"], false);
+
+        let requested = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        wm.hfs_fd_scan = Some(requested.clone());
+        assert_eq!(wm.handle_control(ControlCommand::ScanFds)["ok"], true);
+        assert!(requested.load(std::sync::atomic::Ordering::Relaxed));
+    }
+}
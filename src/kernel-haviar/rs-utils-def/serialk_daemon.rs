@@ -0,0 +1,130 @@
+use daemonize::Daemonize;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub struct DaemonOptions {
+    /// Names the program in "already running" errors.
+    pub name: &'static str,
+    pub pid_file: PathBuf,
+    pub log_file: Option<PathBuf>,
+}
+
+/// Whether a live instance holds the lock on `pid_file`. The lock is released
+/// by the kernel when the daemon dies, so a pid file without it is stale.
+pub fn lock_held(pid_file: &Path) -> io::Result<bool> {
+    match File::open(pid_file) {
+        Ok(file) => is_locked(&file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the pid recorded in `pid_file` if the instance that wrote it still
+/// holds its lock.
+pub fn running_pid(pid_file: &Path) -> io::Result<Option<i32>> {
+    if !lock_held(pid_file)? {
+        return Ok(None);
+    }
+    let pid = fs::read_to_string(pid_file)?
+        .trim()
+        .parse::<i32>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("bad pid file {}: {}", pid_file.display(), e)))?;
+    Ok(Some(pid))
+}
+
+fn is_locked(file: &File) -> io::Result<bool> {
+    let fd = file.as_raw_fd();
+    // SAFETY: fd is a valid descriptor owned by `file` for the whole call.
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        unsafe { libc::flock(fd, libc::LOCK_UN) };
+        return Ok(false);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(true)
+    } else {
+        Err(err)
+    }
+}
+
+/// Detaches from the terminal, writes and locks the pid file and sends output
+/// to the log file, or discards it without one. Must run before any threads
+/// are started.
+pub fn daemonize(options: &DaemonOptions) -> Result<(), String> {
+    match running_pid(&options.pid_file) {
+        Ok(Some(pid)) => {
+            return Err(format!(
+                "{} is already running (pid {}, {})",
+                options.name,
+                pid,
+                options.pid_file.display()
+            ))
+        }
+        Ok(None) => {}
+        Err(e) => return Err(format!("Cannot check pid file {}: {}", options.pid_file.display(), e)),
+    }
+
+    let cwd = std::env::current_dir().map_err(|e| format!("Cannot read working directory: {}", e))?;
+    let mut daemon = Daemonize::new()
+        .pid_file(&options.pid_file)
+        .working_directory(cwd);
+    if let Some(log) = &options.log_file {
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Cannot open log file {}: {}", path.display(), e))
+        };
+        daemon = daemon.stdout(open(log)?).stderr(open(log)?);
+    }
+    daemon.start().map_err(|e| format!("Cannot daemonize: {}", e))
+}
+
+/// Removes the pid file on a clean exit; the lock goes away with the process.
+pub fn remove_pid_file(pid_file: &Path) {
+    if let Err(e) = fs::remove_file(pid_file) {
+        if e.kind() != io::ErrorKind::NotFound {
+            eprintln!("[WARN] Cannot remove pid file {}: {}", pid_file.display(), e);
+        }
+    }
+}
+
+/// Sends SIGTERM to the daemon recorded in `pid_file` and waits for it to exit.
+pub fn stop(pid_file: &Path, timeout: Duration) -> Result<(), String> {
+    let pid = match running_pid(pid_file) {
+        Ok(Some(pid)) => pid,
+        Ok(None) => {
+            if pid_file.exists() {
+                remove_pid_file(pid_file);
+                println!("[DAEMON] Removed stale pid file {}", pid_file.display());
+            } else {
+                println!("[DAEMON] Not running ({} does not exist)", pid_file.display());
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Cannot read pid file {}: {}", pid_file.display(), e)),
+    };
+
+    // SAFETY: kill has no memory-safety preconditions.
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(format!("Cannot signal pid {}: {}", pid, io::Error::last_os_error()));
+    }
+    println!("[DAEMON] Sent SIGTERM to {}", pid);
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match lock_held(pid_file) {
+            Ok(true) => std::thread::sleep(Duration::from_millis(100)),
+            _ => {
+                remove_pid_file(pid_file);
+                println!("[DAEMON] Stopped");
+                return Ok(());
+            }
+        }
+    }
+    Err(format!("pid {} did not exit within {}s", pid, timeout.as_secs()))
+}
@@ -0,0 +1,345 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::serialk_watcher::is;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Decides whether a detected modification was authorized.
+pub enum RecoveryGate {
+    /// No recovery possible: every modification is treated as tampering.
+    Closed,
+    /// Legacy `SERIALK_KEY=AUTHORIZED` check, only with `--insecure-env-gate`.
+    InsecureEnv,
+    Hmac(HmacGate),
+}
+
+/// What the gate says about one modification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateVerdict {
+    Authorized,
+    Denied,
+    /// A challenge is out; `RecoveryGate::poll` decides it later.
+    Pending,
+}
+
+impl RecoveryGate {
+    /// Decides at once where it can. An HMAC challenge is only issued here,
+    /// so the watch loop keeps handling events while the operator answers.
+    pub fn open(&mut self, path: &Path) -> GateVerdict {
+        match self {
+            RecoveryGate::Closed => GateVerdict::Denied,
+            RecoveryGate::InsecureEnv if is::itdefine::pass_recovery_gate() => GateVerdict::Authorized,
+            RecoveryGate::InsecureEnv => GateVerdict::Denied,
+            RecoveryGate::Hmac(gate) => gate.open(path),
+        }
+    }
+
+    /// The challenges decided since the last call, each with whether it was
+    /// authorized. At `shutdown` every open challenge is denied.
+    pub fn poll(&mut self, now: Instant, shutdown: bool) -> Vec<(PathBuf, bool)> {
+        match self {
+            RecoveryGate::Hmac(gate) => gate.poll(now, shutdown),
+            RecoveryGate::Closed | RecoveryGate::InsecureEnv => Vec::new(),
+        }
+    }
+
+    /// Whether a challenge is waiting for its response.
+    pub fn is_waiting(&self) -> bool {
+        matches!(self, RecoveryGate::Hmac(gate) if !gate.pending.is_empty())
+    }
+}
+
+/// A single recovery request: the operator must answer with
+/// hex(HMAC-SHA256(key, len(path) || path || timestamp)), where len is the
+/// path's length in bytes as a big-endian u64 so no two splits of the same
+/// bytes sign alike.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub path: PathBuf,
+    pub timestamp: u128,
+}
+
+impl Challenge {
+    pub fn message(&self) -> Vec<u8> {
+        let path = self.path.to_string_lossy();
+        let mut msg = (path.len() as u64).to_be_bytes().to_vec();
+        msg.extend_from_slice(path.as_bytes());
+        msg.extend_from_slice(self.timestamp.to_string().as_bytes());
+        msg
+    }
+}
+
+pub struct HmacGate {
+    key: Vec<u8>,
+    pub response_path: PathBuf,
+    pub timeout: Duration,
+    used: HashSet<Vec<u8>>,
+    /// Challenges issued and not yet decided, with their deadlines.
+    pending: Vec<(Challenge, Instant)>,
+}
+
+impl HmacGate {
+    pub fn new(key: Vec<u8>, response_path: PathBuf, timeout: Duration) -> Self {
+        Self {
+            key,
+            response_path,
+            timeout,
+            used: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Loads the secret key, refusing files readable by anyone but the owner.
+    pub fn from_key_file(key_path: &Path, response_path: PathBuf, timeout: Duration) -> Result<Self, String> {
+        let key = read_key_file(key_path, "recovery key")?;
+        Ok(Self::new(key, response_path, timeout))
+    }
+
+    pub fn challenge(&self, path: &Path) -> Challenge {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Challenge {
+            path: path.to_path_buf(),
+            timestamp,
+        }
+    }
+
+    pub fn sign(key: &[u8], challenge: &Challenge) -> String {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(&challenge.message());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Constant-time check of a response; each accepted response is burned so
+    /// it can never be replayed.
+    #[cfg(test)]
+    pub fn verify(&mut self, challenge: &Challenge, response: &str) -> bool {
+        self.answered(std::slice::from_ref(challenge), response).is_some()
+    }
+
+    /// Which of `challenges` `response` answers, burning it if one does.
+    fn answered(&mut self, challenges: &[Challenge], response: &str) -> Option<usize> {
+        let tag = hex::decode(response.trim()).ok()?;
+        if self.used.contains(&tag) {
+            println!("[GATE] Rejected replayed recovery response");
+            return None;
+        }
+        let signed = |challenge: &Challenge| {
+            let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+            mac.update(&challenge.message());
+            mac.verify_slice(&tag).is_ok()
+        };
+        let index = challenges.iter().position(signed)?;
+        self.used.insert(tag);
+        Some(index)
+    }
+
+    /// Issues a challenge for `path` unless one is already out. The
+    /// operator has `timeout` to write the response file.
+    pub fn open(&mut self, path: &Path) -> GateVerdict {
+        if self.pending.iter().any(|(challenge, _)| challenge.path == path) {
+            return GateVerdict::Pending;
+        }
+        if self.pending.is_empty() {
+            // A response left over from an earlier event must not count.
+            let _ = fs::remove_file(&self.response_path);
+        }
+        let challenge = self.challenge(path);
+        println!(
+            "[CHALLENGE] path={} ts={} -> write hex HMAC-SHA256(key, len(path)||path||ts) to {} within {}s (see --respond)",
+            challenge.path.display(),
+            challenge.timestamp,
+            self.response_path.display(),
+            self.timeout.as_secs()
+        );
+        self.pending.push((challenge, Instant::now() + self.timeout));
+        GateVerdict::Pending
+    }
+
+    /// Reads the response file once. A valid response authorizes the
+    /// challenge it signs; an invalid one denies them all. Challenges past
+    /// their deadline, or all of them at `shutdown`, are denied.
+    pub fn poll(&mut self, now: Instant, shutdown: bool) -> Vec<(PathBuf, bool)> {
+        let mut decided = Vec::new();
+        if self.pending.is_empty() {
+            return decided;
+        }
+        if shutdown {
+            for (challenge, _) in self.pending.drain(..) {
+                println!("[GATE] Shutting down; no recovery for {}", challenge.path.display());
+                decided.push((challenge.path, false));
+            }
+            return decided;
+        }
+        if let Ok(response) = fs::read_to_string(&self.response_path) {
+            let _ = fs::remove_file(&self.response_path);
+            let challenges: Vec<Challenge> = self.pending.iter().map(|(challenge, _)| challenge.clone()).collect();
+            match self.answered(&challenges, &response) {
+                Some(index) => {
+                    let (challenge, _) = self.pending.remove(index);
+                    println!("[GATE] Recovery authorized for {}", challenge.path.display());
+                    decided.push((challenge.path, true));
+                }
+                None => {
+                    for (challenge, _) in self.pending.drain(..) {
+                        println!("[GATE] Invalid recovery response for {}", challenge.path.display());
+                        decided.push((challenge.path, false));
+                    }
+                }
+            }
+        }
+        let (expired, waiting) = self.pending.drain(..).partition(|(_, deadline)| *deadline <= now);
+        self.pending = waiting;
+        for (challenge, _) in expired {
+            println!("[GATE] No recovery response for {}", challenge.path.display());
+            decided.push((challenge.path, false));
+        }
+        decided
+    }
+}
+
+/// Reads a non-empty secret key, refusing files readable by anyone but the
+/// owner. `what` names the key in errors, e.g. "recovery key".
+pub fn read_key_file(path: &Path, what: &str) -> Result<Vec<u8>, String> {
+    check_key_permissions(path, what)?;
+    let key = fs::read(path).map_err(|e| format!("Cannot read {} {}: {}", what, path.display(), e))?;
+    if key.is_empty() {
+        return Err(format!("{} {} is empty", capitalize(what), path.display()));
+    }
+    Ok(key)
+}
+
+fn capitalize(what: &str) -> String {
+    let mut chars = what.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(unix)]
+fn check_key_permissions(path: &Path, what: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = fs::metadata(path).map_err(|e| format!("Cannot stat {} {}: {}", what, path.display(), e))?;
+    let mode = meta.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "{} {} has mode {:o}; it must be 0600",
+            capitalize(what),
+            path.display(),
+            mode
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_key_permissions(path: &Path, what: &str) -> Result<(), String> {
+    fs::metadata(path)
+        .map(|_| ())
+        .map_err(|e| format!("Cannot stat {} {}: {}", what, path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The response file sits in `dir`, which must outlive the gate.
+    fn gate(dir: &Path, key: &[u8]) -> HmacGate {
+        HmacGate::new(key.to_vec(), dir.join("response"), Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_correct_hmac_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut gate = gate(dir.path(), b"secret");
+        let challenge = gate.challenge(Path::new("/etc/app.conf"));
+        let response = HmacGate::sign(b"secret", &challenge);
+        assert!(gate.verify(&challenge, &response));
+    }
+
+    #[test]
+    fn test_wrong_key_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut gate = gate(dir.path(), b"secret");
+        let challenge = gate.challenge(Path::new("/etc/app.conf"));
+        let response = HmacGate::sign(b"guess", &challenge);
+        assert!(!gate.verify(&challenge, &response));
+    }
+
+    #[test]
+    fn test_replayed_response_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut gate = gate(dir.path(), b"secret");
+        let old = Challenge {
+            path: PathBuf::from("/etc/app.conf"),
+            timestamp: 1,
+        };
+        let response = HmacGate::sign(b"secret", &old);
+        assert!(gate.verify(&old, &response));
+        assert!(!gate.verify(&old, &response));
+
+        let fresh = Challenge {
+            path: PathBuf::from("/etc/app.conf"),
+            timestamp: 2,
+        };
+        assert!(!gate.verify(&fresh, &response));
+    }
+
+    #[test]
+    fn test_fields_cannot_shift_between_path_and_timestamp() {
+        let split = |path: &str, timestamp| Challenge { path: PathBuf::from(path), timestamp };
+        assert_ne!(split("/etc/app.conf1", 23).message(), split("/etc/app.conf", 123).message());
+        let dir = tempfile::tempdir().unwrap();
+        let mut gate = gate(dir.path(), b"secret");
+        let response = HmacGate::sign(b"secret", &split("/etc/app.conf1", 23));
+        assert!(!gate.verify(&split("/etc/app.conf", 123), &response));
+    }
+
+    #[test]
+    fn test_challenges_are_decided_without_blocking() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut gate = RecoveryGate::Hmac(HmacGate::new(b"secret".to_vec(), dir.path().join("response"), Duration::from_secs(30)));
+        let (conf, hosts) = (Path::new("/etc/app.conf"), Path::new("/etc/hosts"));
+        assert_eq!(gate.open(conf), GateVerdict::Pending);
+        assert_eq!(gate.open(hosts), GateVerdict::Pending);
+        assert_eq!(gate.open(conf), GateVerdict::Pending, "one challenge per path");
+        let now = Instant::now();
+        assert!(gate.poll(now, false).is_empty());
+
+        let RecoveryGate::Hmac(hmac) = &gate else { unreachable!() };
+        let response = HmacGate::sign(b"secret", &hmac.pending[1].0);
+        fs::write(dir.path().join("response"), response).unwrap();
+        assert_eq!(gate.poll(now, false), [(hosts.to_path_buf(), true)]);
+        assert!(gate.is_waiting());
+
+        assert_eq!(gate.poll(now + Duration::from_secs(30), false), [(conf.to_path_buf(), false)]);
+        assert!(!gate.is_waiting());
+        assert_eq!(gate.open(conf), GateVerdict::Pending);
+        assert_eq!(gate.poll(now, true), [(conf.to_path_buf(), false)]);
+        assert_eq!(RecoveryGate::Closed.open(conf), GateVerdict::Denied);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_file_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("recovery.key");
+        fs::write(&key, b"secret").unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(HmacGate::from_key_file(&key, dir.path().join("r"), Duration::from_secs(1)).is_err());
+
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(HmacGate::from_key_file(&key, dir.path().join("r"), Duration::from_secs(1)).is_ok());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Alerts allowed per path and minute unless `--alert-rate` says otherwise.
+pub const DEFAULT_ALERT_RATE: u32 = 5;
+
+const REFILL_PERIOD: Duration = Duration::from_secs(60);
+
+/// Token bucket for one path. It holds up to `rate` tokens and refills at
+/// `rate` per minute, so a burst of `rate` alerts goes through at once.
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    suppressed: usize,
+}
+
+/// Per-path alert rate limiter. Suppressed alerts are counted until the
+/// path's bucket has a token again, then reported once by `take_suppressed`.
+#[derive(Debug, Clone)]
+pub struct AlertLimiter {
+    rate: u32,
+    buckets: HashMap<PathBuf, Bucket>,
+}
+
+impl AlertLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn refill(&mut self, path: &Path, now: Instant) -> &mut Bucket {
+        let capacity = self.rate as f64;
+        let bucket = self.buckets.entry(path.to_path_buf()).or_insert(Bucket {
+            tokens: capacity,
+            refilled: now,
+            suppressed: 0,
+        });
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = (bucket.tokens + capacity * elapsed.as_secs_f64() / REFILL_PERIOD.as_secs_f64()).min(capacity);
+        bucket.refilled = now;
+        bucket
+    }
+
+    /// Takes a token for `path`; returns false (and counts the alert as
+    /// suppressed) when the bucket is empty.
+    pub fn allow(&mut self, path: &Path, now: Instant) -> bool {
+        let bucket = self.refill(path, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            bucket.suppressed += 1;
+            false
+        }
+    }
+
+    /// Paths whose suppression window is over, with how many alerts were
+    /// dropped for each. The counters are reset.
+    pub fn take_suppressed(&mut self, now: Instant) -> Vec<(PathBuf, usize)> {
+        let waiting: Vec<PathBuf> = self
+            .buckets
+            .iter()
+            .filter(|(_, bucket)| bucket.suppressed > 0)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut done = Vec::new();
+        for path in waiting {
+            let bucket = self.refill(&path, now);
+            if bucket.tokens >= 1.0 {
+                done.push((path, std::mem::take(&mut bucket.suppressed)));
+            }
+        }
+        done.sort();
+        done
+    }
+
+    /// Every pending suppression count regardless of the window, for shutdown.
+    pub fn drain_suppressed(&mut self) -> Vec<(PathBuf, usize)> {
+        let mut all: Vec<(PathBuf, usize)> = self
+            .buckets
+            .iter_mut()
+            .filter(|(_, bucket)| bucket.suppressed > 0)
+            .map(|(path, bucket)| (path.clone(), std::mem::take(&mut bucket.suppressed)))
+            .collect();
+        all.sort();
+        all
+    }
+
+    /// Drops the bucket of a path that is no longer watched.
+    pub fn forget(&mut self, path: &Path) {
+        self.buckets.remove(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_limits_and_refills() {
+        let mut limiter = AlertLimiter::new(5);
+        let path = PathBuf::from("/etc/hosts");
+        let start = Instant::now();
+
+        let allowed = (0..100).filter(|_| limiter.allow(&path, start)).count();
+        assert_eq!(allowed, 5);
+        assert!(limiter.take_suppressed(start).is_empty());
+
+        // One token comes back every 12 seconds at 5 per minute.
+        let later = start + Duration::from_secs(12);
+        assert_eq!(limiter.take_suppressed(later), [(path.clone(), 95)]);
+        assert!(limiter.allow(&path, later));
+        assert!(!limiter.allow(&path, later));
+        assert_eq!(limiter.drain_suppressed(), [(path, 1)]);
+    }
+}
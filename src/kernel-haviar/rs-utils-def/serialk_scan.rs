@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use crate::hash_pool::{Priority, Throttle};
+use crate::serialk_watcher::{
+    canonical_path, take_snapshot, FileEntry, FileMetadata, LargeFilePolicy, LineWatch, Remediation, Severity, WatcherError,
+};
+
+/// Include sets larger than this are fingerprinted in the background while
+/// the watch loop already serves the files registered so far.
+pub const BACKGROUND_SCAN_THRESHOLD: usize = 1000;
+
+/// One file to fingerprint. `top_level` files were named explicitly, so a
+/// failure is reported like a bad include rather than skipped with a warning.
+#[derive(Debug, Clone)]
+pub struct ScanJob {
+    pub path: PathBuf,
+    pub liner: Option<LineWatch>,
+    pub severity: Severity,
+    pub top_level: bool,
+}
+
+/// The settings a worker needs to build an entry without touching the manager.
+#[derive(Debug, Clone, Default)]
+pub struct EntryOptions {
+    pub follow_symlinks: bool,
+    pub check_metadata: bool,
+    /// `Some` when quarantine is enabled: take a snapshot where it says.
+    pub snapshot: Option<Remediation>,
+    pub large_file: Option<LargeFilePolicy>,
+}
+
+/// A fingerprinted entry and whether its path is a followed symlink.
+pub type BuiltEntry = (FileEntry, bool);
+
+pub struct ScanResult {
+    pub job: ScanJob,
+    pub entry: Result<BuiltEntry, WatcherError>,
+}
+
+/// Reads, fingerprints and optionally snapshots one file. The entry is keyed
+/// by the canonical path and remembers `path` as presented.
+pub fn build_entry(
+    presented: &Path,
+    liner: Option<LineWatch>,
+    severity: Severity,
+    options: &EntryOptions,
+) -> Result<BuiltEntry, WatcherError> {
+    let path = &canonical_path(presented);
+    let is_link = fs::symlink_metadata(path)
+        .map_err(|e| WatcherError::from_io(path, e))?
+        .file_type()
+        .is_symlink();
+    if is_link && !options.follow_symlinks {
+        return Err(WatcherError::Symlink(path.clone()));
+    }
+    let mut entry = FileEntry::from_path_with(path, options.large_file).map_err(|e| WatcherError::from_io(path, e))?;
+    entry.presented = presented.to_path_buf();
+    entry.severity = severity;
+    if options.check_metadata {
+        entry.metadata = Some(FileMetadata::capture(path).map_err(|e| WatcherError::from_io(path, e))?);
+    }
+    if let Some(liner_mode) = liner {
+        entry.set_liner_watch(liner_mode);
+    }
+    if let Some(remediation) = &options.snapshot {
+        match take_snapshot(path, remediation) {
+            Ok(snapshot) => entry.snapshot = Some(snapshot),
+            Err(e) => eprintln!("[WARN] Could not snapshot {}: {}", path.display(), e),
+        }
+    }
+    Ok((entry, is_link))
+}
+
+/// A pool of workers fingerprinting the initial include set. Results arrive
+/// in completion order; the manager registers them as they come in.
+pub struct InitialScan {
+    pub rx: Receiver<ScanResult>,
+    pub total: usize,
+    pub done: usize,
+    pub started: Instant,
+    pub last_progress: Instant,
+}
+
+impl InitialScan {
+    /// Starts `threads` workers over `jobs`. Later duplicates of a path are
+    /// dropped so the first include entry decides its line-watch mode no
+    /// matter which worker finishes first. The workers run at `priority`
+    /// and charge each file's size to `throttle` before reading it; the
+    /// watch loop itself keeps its priority and pace.
+    pub fn spawn(
+        jobs: Vec<ScanJob>,
+        options: EntryOptions,
+        threads: usize,
+        throttle: Option<Arc<Throttle>>,
+        priority: Priority,
+    ) -> Self {
+        let mut seen = HashSet::new();
+        let jobs: Arc<Vec<ScanJob>> =
+            Arc::new(jobs.into_iter().filter(|job| seen.insert(canonical_path(&job.path))).collect());
+        let total = jobs.len();
+        let next = Arc::new(AtomicUsize::new(0));
+        let options = Arc::new(options);
+        let (tx, rx) = channel();
+
+        for worker in 0..threads.clamp(1, total.max(1)) {
+            let jobs = Arc::clone(&jobs);
+            let next = Arc::clone(&next);
+            let options = Arc::clone(&options);
+            let throttle = throttle.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(e) = priority.apply() {
+                    if worker == 0 {
+                        eprintln!("[WARN] Initial scan runs at normal priority: {}", e);
+                    }
+                }
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(job) = jobs.get(i) else {
+                        break;
+                    };
+                    if let Some(throttle) = &throttle {
+                        throttle.take(fs::metadata(&job.path).map_or(0, |meta| meta.len()));
+                    }
+                        let entry = build_entry(&job.path, job.liner.clone(), job.severity, &options);
+                    if tx.send(ScanResult { job: job.clone(), entry }).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let now = Instant::now();
+        Self {
+            rx,
+            total,
+            done: 0,
+            started: now,
+            last_progress: now,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done >= self.total
+    }
+
+    pub fn progress(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+        format!(
+            "[SCAN] {}/{} files fingerprinted ({:.0} files/s, {} remaining)",
+            self.done,
+            self.total,
+            self.done as f64 / elapsed,
+            self.total - self.done
+        )
+    }
+}
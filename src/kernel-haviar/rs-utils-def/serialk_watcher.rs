@@ -1,9 +1,25 @@
-use notify::{RecommendedWatcher, RecursiveMode, Watcher, Event, recommended_watcher};
-use std::collections::HashMap;
+use crate::serialk_webhook;
+use crate::hfs::{self, Violation};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher, Event, EventKind, recommended_watcher};
+use sha2::{Digest, Sha256};
+use crate::serialk_control::ControlRequest;
+use crate::hash_pool::{Priority, Throttle};
+use crate::serialk_scan::{self, build_entry, EntryOptions, InitialScan, ScanJob};
+use crate::kdv::{BaselineCheck, KdvVerifier, VerifyResult};
+use crate::kdv_daemon::{KdvDaemonEvent, KdvDaemonHandle};
+use crate::serialk_audit::AuditLog;
+use crate::serialk_gate::{GateVerdict, RecoveryGate};
+use crate::serialk_rate::{AlertLimiter, DEFAULT_ALERT_RATE};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub mod is {
     pub mod itdefine {
@@ -39,72 +55,1031 @@ pub mod serialk {
 
 pub type LineValue = u64;
 
+/// Binaries are fingerprinted in fixed-size chunks so a change can be
+/// attributed to a byte range.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How files above `--large-file-threshold` are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LargeFileMode {
+    /// Stream the whole file through the hasher on every event.
+    Hash,
+    /// Skip hashing while size and mtime are unchanged since the last read.
+    Stat,
+}
+
+/// Files bigger than `threshold` bytes are hashed chunk by chunk from a
+/// stream instead of being read into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LargeFilePolicy {
+    pub threshold: u64,
+    pub mode: LargeFileMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fingerprint {
+    Lines(Vec<LineValue>),
+    Chunks { size: usize, hashes: Vec<[u8; 32]> },
+}
+
+/// What differs between two fingerprints: 1-based line numbers for text,
+/// byte ranges for binaries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FingerprintDiff {
+    Lines(Vec<usize>),
+    Chunks(Vec<std::ops::Range<usize>>),
+    KindChanged,
+}
+
+impl Fingerprint {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if !bytes.contains(&0) => {
+                Fingerprint::Lines(text.lines().map(FileEntry::line_value).collect())
+            }
+            _ => Fingerprint::Chunks {
+                size: bytes.len(),
+                hashes: bytes
+                    .chunks(CHUNK_SIZE)
+                    .map(|chunk| Sha256::digest(chunk).into())
+                    .collect(),
+            },
+        }
+    }
+
+    /// Chunk hashes of a stream, read `CHUNK_SIZE` bytes at a time so memory
+    /// use does not depend on its length. Matches `from_bytes` for binaries.
+    pub fn from_reader(mut reader: impl io::Read) -> io::Result<Self> {
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut size = 0;
+        let mut hashes = Vec::new();
+        loop {
+            let mut filled = 0;
+            while filled < CHUNK_SIZE {
+                match reader.read(&mut buf[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            size += filled;
+            hashes.push(Sha256::digest(&buf[..filled]).into());
+            if filled < CHUNK_SIZE {
+                break;
+            }
+        }
+        Ok(Fingerprint::Chunks { size, hashes })
+    }
+
+    /// Fingerprints the file at `path`, streaming it when it is above the
+    /// large-file threshold.
+    pub fn from_file(path: &Path, large_file: Option<LargeFilePolicy>) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        if large_file.is_some_and(|policy| len > policy.threshold) {
+            return Self::from_reader(file);
+        }
+        let mut content = Vec::with_capacity(len as usize);
+        io::Read::read_to_end(&mut file, &mut content)?;
+        Ok(Self::from_bytes(&content))
+    }
+
+    pub fn diff(&self, new: &Fingerprint) -> FingerprintDiff {
+        match (self, new) {
+            (Fingerprint::Lines(old), Fingerprint::Lines(new)) => {
+                let lines = (0..old.len().max(new.len()))
+                    .filter(|&i| old.get(i) != new.get(i))
+                    .map(|i| i + 1)
+                    .collect();
+                FingerprintDiff::Lines(lines)
+            }
+            (
+                Fingerprint::Chunks { size: old_size, hashes: old },
+                Fingerprint::Chunks { size: new_size, hashes: new },
+            ) => {
+                let end = *old_size.max(new_size);
+                let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+                for i in (0..old.len().max(new.len())).filter(|&i| old.get(i) != new.get(i)) {
+                    let start = i * CHUNK_SIZE;
+                    let stop = ((i + 1) * CHUNK_SIZE).min(end);
+                    match ranges.last_mut() {
+                        Some(last) if last.end == start => last.end = stop,
+                        _ => ranges.push(start..stop),
+                    }
+                }
+                FingerprintDiff::Chunks(ranges)
+            }
+            _ => FingerprintDiff::KindChanged,
+        }
+    }
+
+    /// Short hex digest of the fingerprint, for status output.
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        match self {
+            Fingerprint::Lines(values) => {
+                hasher.update(b"lines");
+                for value in values {
+                    hasher.update(value.to_le_bytes());
+                }
+            }
+            Fingerprint::Chunks { size, hashes } => {
+                hasher.update(b"chunks");
+                hasher.update((*size as u64).to_le_bytes());
+                for hash in hashes {
+                    hasher.update(hash);
+                }
+            }
+        }
+        hex::encode(&hasher.finalize()[..8])
+    }
+}
+
+impl std::fmt::Display for FingerprintDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FingerprintDiff::Lines(lines) => {
+                let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+                write!(f, "lines {}", lines.join(","))
+            }
+            FingerprintDiff::Chunks(ranges) => {
+                let ranges: Vec<String> = ranges
+                    .iter()
+                    .map(|r| format!("{:#x}..{:#x}", r.start, r.end))
+                    .collect();
+                write!(f, "bytes {}", ranges.join(","))
+            }
+            FingerprintDiff::KindChanged => write!(f, "switched between text and binary"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WatcherError {
+    NotFound(PathBuf),
+    PermissionDenied(PathBuf),
+    Io(PathBuf, String),
+    NotifyError(PathBuf, String),
+    Symlink(PathBuf),
+}
+
+impl WatcherError {
+    pub fn from_io(path: &Path, e: io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::NotFound => WatcherError::NotFound(path.to_path_buf()),
+            ErrorKind::PermissionDenied => WatcherError::PermissionDenied(path.to_path_buf()),
+            _ => WatcherError::Io(path.to_path_buf(), e.to_string()),
+        }
+    }
+
+    pub fn from_notify(path: &Path, e: notify::Error) -> Self {
+        match e.kind {
+            notify::ErrorKind::PathNotFound => WatcherError::NotFound(path.to_path_buf()),
+            notify::ErrorKind::Io(io_err) => WatcherError::from_io(path, io_err),
+            other => WatcherError::NotifyError(path.to_path_buf(), format!("{:?}", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for WatcherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatcherError::NotFound(path) => write!(f, "{}: no such file or directory", path.display()),
+            WatcherError::PermissionDenied(path) => write!(f, "{}: permission denied", path.display()),
+            WatcherError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            WatcherError::NotifyError(path, e) => write!(f, "{}: cannot watch ({})", path.display(), e),
+            WatcherError::Symlink(path) => write!(
+                f,
+                "{}: is a symbolic link (use --follow-symlinks to watch its target)",
+                path.display()
+            ),
+        }
+    }
+}
+
 pub struct FileEntry {
+    /// Canonical path, the key the entry is tracked under.
     pub path: PathBuf,
-    pub line_values: Vec<LineValue>,
+    /// The path as the user spelled it when adding the file.
+    pub presented: PathBuf,
+    pub fingerprint: Fingerprint,
+    pub last_diff: Option<FingerprintDiff>,
     pub liner_watch: Option<LineWatch>,
+    pub liner_limit: usize,
+    pub liner_deadline: Option<Instant>,
+    pub exhausted_at: Option<Instant>,
+    pub snapshot: Option<Snapshot>,
+    /// Attribute baseline, only kept when metadata checking is enabled.
+    pub metadata: Option<FileMetadata>,
+    pub stats: FileStats,
+    pub severity: Severity,
+    pub large_file: Option<LargeFilePolicy>,
+    /// Size and mtime seen right before the last read, for `LargeFileMode::Stat`.
+    pub last_stat: Option<(u64, Option<SystemTime>)>,
+}
+
+/// Per-file counters shown by `status`. They live beside the fingerprint so
+/// re-baselining a file does not reset them.
+#[derive(Debug, Clone)]
+pub struct FileStats {
+    pub added_at: SystemTime,
+    pub last_event: Option<SystemTime>,
+    pub events: usize,
+    pub alerts: usize,
+    /// Alerts dropped by the `--alert-rate` limiter.
+    pub suppressed: usize,
+}
+
+impl Default for FileStats {
+    fn default() -> Self {
+        Self {
+            added_at: SystemTime::now(),
+            last_event: None,
+            events: 0,
+            alerts: 0,
+            suppressed: 0,
+        }
+    }
+}
+
+/// The attributes compared by `--check-metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub readonly: bool,
+    pub mtime: Option<SystemTime>,
+    pub size: u64,
+}
+
+/// One attribute that differs from the baseline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataChange {
+    pub attribute: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+impl std::fmt::Display for MetadataChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", self.attribute, self.old, self.new)
+    }
+}
+
+impl MetadataChange {
+    /// mtime and size move with every content write and say nothing on their own then.
+    fn follows_content(&self) -> bool {
+        matches!(self.attribute, "mtime" | "size")
+    }
+}
+
+impl FileMetadata {
+    pub fn capture(path: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode() & 0o7777), Some(meta.uid()), Some(meta.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        Ok(Self {
+            mode,
+            uid,
+            gid,
+            readonly: meta.permissions().readonly(),
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+        })
+    }
+
+    pub fn diff(&self, new: &FileMetadata) -> Vec<MetadataChange> {
+        let mut changes = Vec::new();
+        let mut push = |attribute, old: String, new: String| {
+            if old != new {
+                changes.push(MetadataChange { attribute, old, new });
+            }
+        };
+        let opt = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_default();
+        let secs = |t: Option<SystemTime>| {
+            t.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| format!("{}.{:09}", d.as_secs(), d.subsec_nanos()))
+                .unwrap_or_default()
+        };
+
+        push(
+            "mode",
+            self.mode.map(|m| format!("{:04o}", m)).unwrap_or_default(),
+            new.mode.map(|m| format!("{:04o}", m)).unwrap_or_default(),
+        );
+        push("uid", opt(self.uid), opt(new.uid));
+        push("gid", opt(self.gid), opt(new.gid));
+        // On Unix the readonly flag is derived from the mode bits already compared above.
+        if self.mode.is_none() {
+            push("readonly", self.readonly.to_string(), new.readonly.to_string());
+        }
+        push("mtime", secs(self.mtime), secs(new.mtime));
+        push("size", self.size.to_string(), new.size.to_string());
+        changes
+    }
+}
+
+/// Trusted copy of a file taken when it was added to the watch set, with
+/// the mode and owner a restore puts back.
+pub struct Snapshot {
+    data: SnapshotData,
+    permissions: fs::Permissions,
+    #[cfg(unix)]
+    owner: (u32, u32),
+}
+
+enum SnapshotData {
+    Memory(Vec<u8>),
+    /// A copy readable by its owner only.
+    Disk(PathBuf),
+}
+
+impl Snapshot {
+    pub fn content(&self) -> io::Result<Vec<u8>> {
+        match &self.data {
+            SnapshotData::Memory(bytes) => Ok(bytes.clone()),
+            SnapshotData::Disk(path) => fs::read(path),
+        }
+    }
+
+    /// Atomically replaces `path` with `baseline`, the snapshot's content,
+    /// under the mode and owner it was taken with. It is never readable by
+    /// others before that mode is back.
+    fn restore_to(&self, path: &Path, baseline: &[u8]) -> io::Result<()> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.serialk-restore", file_name));
+        let _ = fs::remove_file(&tmp);
+        let mut file = private_file(&tmp)?;
+        file.write_all(baseline)?;
+        // Ownership first: a chown clears setuid and setgid bits.
+        #[cfg(unix)]
+        if let Err(e) = std::os::unix::fs::fchown(&file, Some(self.owner.0), Some(self.owner.1)) {
+            eprintln!("[WARN] Cannot give {} back to {}:{}: {}", path.display(), self.owner.0, self.owner.1, e);
+        }
+        file.set_permissions(self.permissions.clone())?;
+        drop(file);
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Creates the directory `path`, if missing, open to its owner only.
+fn private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)
+}
+
+/// Creates `path`, which must not exist, readable by its owner only.
+fn private_file(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Response once a modification fails the recovery gate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TamperAction {
+    Exit,
+    Quarantine,
+    Log,
+}
+
+/// `[on_violation]`: what the watcher does when the HFS monitor running
+/// alongside it (`[hfs]`) reports a violation.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViolationResponse {
+    /// Re-hash every tracked file at once instead of waiting for events.
+    pub verify: bool,
+    /// Write the pself right away, even when exports are batched.
+    pub export: bool,
+    /// Shell command run with the violation in `SERIALK_HFS_*` variables.
+    pub run: Option<String>,
+}
+
+impl Default for ViolationResponse {
+    fn default() -> Self {
+        Self { verify: true, export: false, run: None }
+    }
+}
+
+/// How much a change to a path matters. The tamper action only fires at or
+/// above the manager's `tamper_threshold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("invalid severity '{}' (expected info, warning or critical)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Splits a `path=level` include spec. The suffix is only taken when it names
+/// a level, so paths that merely contain `=` are left alone.
+pub fn split_severity(spec: &str) -> (&str, Option<Severity>) {
+    match spec.rsplit_once('=') {
+        Some((path, level)) if !path.is_empty() => match level.parse() {
+            Ok(severity) => (path, Some(severity)),
+            Err(_) => (spec, None),
+        },
+        _ => (spec, None),
+    }
+}
+
+/// Alert counts broken down by severity.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SeverityCounts {
+    pub critical: usize,
+    pub warning: usize,
+    pub info: usize,
+}
+
+impl SeverityCounts {
+    pub fn record(&mut self, severity: Severity) {
+        match severity {
+            Severity::Critical => self.critical += 1,
+            Severity::Warning => self.warning += 1,
+            Severity::Info => self.info += 1,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "critical": self.critical,
+            "warning": self.warning,
+            "info": self.info,
+        })
+    }
+}
+
+impl std::fmt::Display for SeverityCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "critical: {}, warning: {}, info: {}", self.critical, self.warning, self.info)
+    }
+}
+
+/// What to do with changes to files that have no entry in the kdv baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnbaselinedPolicy {
+    Alert,
+    Ignore,
+}
+
+/// What happens to changes while watching is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PausedMode {
+    /// Fingerprints keep following the files without alerting, so the state
+    /// at resume becomes the new baseline.
+    Rebaseline,
+    /// Events are held and evaluated against the old baseline on resume.
+    Freeze,
+}
+
+impl PausedMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PausedMode::Rebaseline => "rebaseline",
+            PausedMode::Freeze => "freeze",
+        }
+    }
+}
+
+/// A pause in progress: when it started and how many changes it absorbed.
+#[derive(Debug, Clone)]
+pub struct Pause {
+    pub since: Instant,
+    pub absorbed: usize,
+}
+
+/// Snapshots of files larger than this go to disk even without a
+/// `snapshot_dir`, so a large watch set cannot fill memory.
+pub const MEMORY_SNAPSHOT_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Where tampered files are moved and where baselines are kept on disk.
+#[derive(Debug, Clone)]
+pub struct Remediation {
+    pub quarantine_dir: PathBuf,
+    pub snapshot_dir: Option<PathBuf>,
+    /// Files up to this size are snapshotted in memory when there is no
+    /// `snapshot_dir`; larger ones go to `quarantine_dir/.snapshots`.
+    pub memory_limit: u64,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub enum LineWatch {
     Count(usize),
     Forever,
+    /// Watch closely until the window ends, then fall back to normal watching.
+    Duration(Duration),
 }
 
 impl FileEntry {
-    pub fn from_path(path: &PathBuf) -> Self {
-        let content = fs::read_to_string(path).unwrap_or_default();
-        let lines: Vec<&str> = content.lines().collect();
-        let line_values = lines.iter().map(|line| Self::line_value(line)).collect();
-        Self {
-            path: path.clone(),
-            line_values,
+    pub fn from_path_with(path: &Path, large_file: Option<LargeFilePolicy>) -> io::Result<Self> {
+        let last_stat = stat(path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            presented: path.to_path_buf(),
+            fingerprint: Fingerprint::from_file(path, large_file)?,
+            last_diff: None,
             liner_watch: None,
-        }
+            liner_limit: 0,
+            liner_deadline: None,
+            exhausted_at: None,
+            snapshot: None,
+            metadata: None,
+            stats: FileStats::default(),
+            severity: Severity::Critical,
+            large_file,
+            last_stat,
+        })
+    }
+
+    /// With `LargeFileMode::Stat`, a large file whose size and mtime match
+    /// the last read is taken as unchanged without hashing it.
+    fn unchanged_by_stat(&self) -> bool {
+        let Some(policy) = self.large_file.filter(|policy| policy.mode == LargeFileMode::Stat) else {
+            return false;
+        };
+        let current = stat(&self.path);
+        current.is_some_and(|(len, _)| len > policy.threshold) && current == self.last_stat
+    }
+
+    /// The fingerprint on disk now; a file that vanished compares as empty.
+    fn read_fingerprint(&mut self) -> Fingerprint {
+        // Stat before reading: a write landing in between shows up as a
+        // different stat next time instead of being skipped.
+        self.last_stat = stat(&self.path);
+        Fingerprint::from_file(&self.path, self.large_file).unwrap_or_else(|_| Fingerprint::from_bytes(&[]))
     }
 
     pub fn line_value(line: &str) -> LineValue {
         line.bytes().map(|b| b as u64).sum()
     }
 
+    /// Re-reads the file and reports whether the change should be alerted on.
+    /// The baseline always moves to the new content; a `Count` watch stops
+    /// alerting once its budget is spent until it is re-armed.
     pub fn update(&mut self) -> bool {
-        let new = FileEntry::from_path(&self.path);
-        let changed = new.line_values != self.line_values;
-
-        if let Some(ref mut mode) = self.liner_watch {
-            match mode {
-                LineWatch::Count(ref mut count) => {
-                    if changed {
-                        if *count > 0 {
-                            *count -= 1;
-                            if *count == 0 {
-                                println!("[NOTICE] Reached watch limit for: {}", self.path.display());
-                            }
-                        }
-                        return *count > 0;
-                    }
-                }
-                LineWatch::Forever => return changed,
+        if self.unchanged_by_stat() {
+            return false;
+        }
+        // A file that vanished compares as empty so the removal is reported.
+        let new = self.read_fingerprint();
+        if new == self.fingerprint || self.is_exhausted() {
+            return false;
+        }
+
+        self.last_diff = Some(self.fingerprint.diff(&new));
+        self.fingerprint = new;
+
+        if let Some(LineWatch::Count(count)) = self.liner_watch.as_mut() {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.exhausted_at = Some(Instant::now());
             }
-            false
-        } else {
-            if changed {
-                self.line_values = new.line_values;
+        }
+        true
+    }
+
+    /// Compares attributes against the baseline and moves it forward. Returns
+    /// nothing when metadata checking is off or the file cannot be stat'ed.
+    pub fn update_metadata(&mut self) -> Vec<MetadataChange> {
+        if self.is_exhausted() {
+            return Vec::new();
+        }
+        let Some(old) = self.metadata.as_ref() else {
+            return Vec::new();
+        };
+        let Ok(new) = FileMetadata::capture(&self.path) else {
+            return Vec::new();
+        };
+        let changes = old.diff(&new);
+        self.metadata = Some(new);
+        changes
+    }
+
+    /// Cheap check used by the polling fallback before taking the full update path.
+    pub fn differs_from_disk(&self) -> bool {
+        if !self.unchanged_by_stat() {
+            let current =
+                Fingerprint::from_file(&self.path, self.large_file).unwrap_or_else(|_| Fingerprint::from_bytes(&[]));
+            if current != self.fingerprint {
                 return true;
             }
-            false
         }
+        match (&self.metadata, FileMetadata::capture(&self.path)) {
+            (Some(old), Ok(new)) => *old != new,
+            _ => false,
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted_at.is_some()
+    }
+
+    /// Human-readable line-watch state, e.g. `count 2/3` or `exhausted 0/3`.
+    pub fn watch_state(&self) -> String {
+        match &self.liner_watch {
+            None => "normal".to_string(),
+            Some(LineWatch::Forever) => "forever".to_string(),
+            Some(LineWatch::Count(left)) if self.is_exhausted() => format!("exhausted {}/{}", left, self.liner_limit),
+            Some(LineWatch::Count(left)) => format!("count {}/{}", left, self.liner_limit),
+            Some(LineWatch::Duration(_)) => match self.liner_deadline {
+                Some(deadline) => format!(
+                    "duration {}s left",
+                    deadline.saturating_duration_since(Instant::now()).as_secs()
+                ),
+                None => "duration".to_string(),
+            },
+        }
+    }
+
+    pub fn status_json(&self) -> serde_json::Value {
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        serde_json::json!({
+            "path": self.path.to_string_lossy(),
+            "presented": self.presented.to_string_lossy(),
+            "added_at": secs(self.stats.added_at),
+            "last_event": self.stats.last_event.map(secs),
+            "events": self.stats.events,
+            "alerts": self.stats.alerts,
+            "suppressed": self.stats.suppressed,
+            "watch": self.watch_state(),
+            "severity": self.severity.as_str(),
+            "fingerprint": self.fingerprint.digest(),
+        })
+    }
+
+    /// Restores the original count of a `Count` watch.
+    pub fn rearm(&mut self) {
+        if let Some(LineWatch::Count(count)) = self.liner_watch.as_mut() {
+            *count = self.liner_limit;
+        }
+        self.exhausted_at = None;
     }
 
     pub fn set_liner_watch(&mut self, watch: LineWatch) {
+        self.set_liner_watch_at(watch, Instant::now());
+    }
+
+    pub fn set_liner_watch_at(&mut self, watch: LineWatch, now: Instant) {
+        match watch {
+            LineWatch::Count(count) => self.liner_limit = count,
+            LineWatch::Duration(window) => self.liner_deadline = Some(now + window),
+            LineWatch::Forever => {}
+        }
         self.liner_watch = Some(watch);
     }
+
+    /// Ends a `Duration` watch whose window has passed; the entry keeps being
+    /// watched as a normal file. Returns true when the window just expired.
+    pub fn expire_if_due(&mut self, now: Instant) -> bool {
+        match self.liner_deadline {
+            Some(deadline) if now >= deadline => {
+                self.liner_watch = None;
+                self.liner_deadline = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Modified,
+    Deleted,
+    Added,
+    Metadata,
+    Retargeted,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Modified => "modified",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Added => "added",
+            ChangeKind::Metadata => "metadata",
+            ChangeKind::Retargeted => "retargeted",
+        }
+    }
+}
+
+/// A settled change handed to every matching handler.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub severity: Severity,
+    pub timestamp: SystemTime,
+    pub diff: Option<FingerprintDiff>,
+    /// Attribute changes seen together with this event (`--check-metadata`,
+    /// or the old and new `target` of a retargeted symlink).
+    pub metadata: Vec<MetadataChange>,
+    /// Set by `dispatch` when the path is over its alert rate: the change is
+    /// still checked for tampering but not reported.
+    pub suppressed: bool,
+    /// Comparison against `--kdv-baseline` for content changes to tracked files.
+    pub baseline: Option<BaselineCheck>,
+}
+
+/// Handlers get the manager back so they can query or change the watch set.
+pub type ChangeHandler = Box<dyn FnMut(&mut WatchManager, &ChangeEvent) + Send>;
+
+struct RegisteredHandler {
+    /// `None` for handlers registered with `on_any`.
+    path: Option<PathBuf>,
+    handler: ChangeHandler,
+}
+
+/// Events collected for one path while waiting for it to go quiet.
+pub struct PendingEvent {
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    pub removal: bool,
+}
+
+impl PendingEvent {
+    /// Removals are due a fixed window after the first event so that a burst
+    /// of follow-up events cannot postpone a deletion alert.
+    fn due_at(&self, window: Duration) -> Instant {
+        let since = if self.removal { self.first_seen } else { self.last_seen };
+        since + window
+    }
+
+    fn is_due(&self, now: Instant, window: Duration) -> bool {
+        now >= self.due_at(window)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WatchStats {
+    pub events: usize,
+    /// Events for editor temp files and other `ignores` matches.
+    pub ignored: usize,
+    pub modified: usize,
+    pub alerts: usize,
+    pub alerts_by_severity: SeverityCounts,
+    pub suppressed: usize,
+    pub exports: usize,
+}
+
+/// How many of the busiest paths a periodic summary lists.
+pub const SUMMARY_TOP_PATHS: usize = 5;
+
+/// What happened since the last `--summary-interval` heartbeat.
+#[derive(Debug, Default, Clone)]
+pub struct IntervalSummary {
+    pub events_by_kind: BTreeMap<&'static str, usize>,
+    pub changes: HashMap<PathBuf, usize>,
+    pub alerts: usize,
+    pub alerts_by_severity: SeverityCounts,
+    pub suppressed: usize,
+    pub exports: usize,
+    pub last_export: Option<SystemTime>,
+}
+
+impl IntervalSummary {
+    pub fn record_event(&mut self, kind: &EventKind) {
+        let name = match kind {
+            EventKind::Access(_) => "access",
+            EventKind::Create(_) => "create",
+            EventKind::Modify(_) => "modify",
+            EventKind::Remove(_) => "remove",
+            EventKind::Any | EventKind::Other => "other",
+        };
+        *self.events_by_kind.entry(name).or_insert(0) += 1;
+    }
+
+    /// Most changed paths first; ties are broken by path for stable output.
+    pub fn top_changed(&self, n: usize) -> Vec<(PathBuf, usize)> {
+        let mut top: Vec<(PathBuf, usize)> = self.changes.iter().map(|(p, c)| (p.clone(), *c)).collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    pub fn to_json(&self, window: Duration) -> serde_json::Value {
+        let top: Vec<serde_json::Value> = self
+            .top_changed(SUMMARY_TOP_PATHS)
+            .into_iter()
+            .map(|(path, count)| serde_json::json!({ "path": path.to_string_lossy(), "changes": count }))
+            .collect();
+        serde_json::json!({
+            "event": "summary",
+            "window_secs": window.as_secs(),
+            "events": self.events_by_kind,
+            "alerts": self.alerts,
+            "alerts_by_severity": self.alerts_by_severity.to_json(),
+            "suppressed": self.suppressed,
+            "exports": self.exports,
+            "last_export": self.last_export.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+            "top_changed": top,
+        })
+    }
+}
+
+impl std::fmt::Display for IntervalSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let events: Vec<String> = self.events_by_kind.iter().map(|(k, n)| format!("{}={}", k, n)).collect();
+        let top: Vec<String> = self
+            .top_changed(SUMMARY_TOP_PATHS)
+            .into_iter()
+            .map(|(path, count)| format!("{} ({})", path.display(), count))
+            .collect();
+        let last_export = self
+            .last_export
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_else(|| "never".to_string());
+        write!(
+            f,
+            "events: {}; alerts: {} ({}); suppressed: {}; exports: {}; last export: {}; top changed: {}",
+            if events.is_empty() { "none".to_string() } else { events.join(" ") },
+            self.alerts,
+            self.alerts_by_severity,
+            self.suppressed,
+            self.exports,
+            last_export,
+            if top.is_empty() { "none".to_string() } else { top.join(", ") }
+        )
+    }
+}
+
+/// The part of a notify watcher the manager uses; a trait so tests can inject
+/// a backend that fails the way a host at its inotify limit does.
+pub trait WatchBackend: Send {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()>;
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()>;
+}
+
+impl WatchBackend for RecommendedWatcher {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        Watcher::watch(self, path, mode)
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        Watcher::unwatch(self, path)
+    }
 }
 
+/// Editor swap/backup files and atomic-save artifacts (vim's `4913` write
+/// probe among them) that are never tracked unless included by name.
+pub const DEFAULT_IGNORES: &[&str] = &["*.swp", "*.swo", "*~", ".#*", "4913", "*.tmp", ".DS_Store"];
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest the watch loop sleeps in the event channel before running its
+/// timers (summaries, polling, re-arming) and checking the shutdown flag.
+pub const IDLE_WAKEUP: Duration = Duration::from_secs(1);
+/// Scan results and control requests arrive on their own channels, so the
+/// loop wakes more often while either is active.
+const BUSY_WAKEUP: Duration = Duration::from_millis(250);
+
 pub struct WatchManager {
     pub files: HashMap<PathBuf, FileEntry>,
-    pub watcher: RecommendedWatcher,
+    pub watcher: Box<dyn WatchBackend>,
     pub rx: Receiver<Event>,
+    pub remediation: Option<Remediation>,
+    pub debounce: Duration,
+    pub pending: HashMap<PathBuf, PendingEvent>,
+    pub stats: WatchStats,
+    pub shutdown: Arc<AtomicBool>,
+    pub gate: RecoveryGate,
+    /// Every watched directory: included ones and those found below them.
+    pub dirs: HashSet<PathBuf>,
+    /// The directories named by `--include` or `ctl add`.
+    pub dir_roots: HashSet<PathBuf>,
+    /// Included directories held by a single recursive watch; nothing below
+    /// them needs a watch of its own.
+    pub native_roots: HashSet<PathBuf>,
+    /// How many directory levels below an included directory are watched;
+    /// `None` watches the whole tree.
+    pub max_depth: Option<usize>,
+    pub excludes: Vec<glob::Pattern>,
+    /// Like `excludes`, but events for these are also dropped (and counted)
+    /// before debouncing. Starts out as `DEFAULT_IGNORES`.
+    pub ignores: Vec<glob::Pattern>,
+    pub output_path: PathBuf,
+    pub tamper_action: TamperAction,
+    /// Level given to includes that do not name one.
+    pub default_severity: Severity,
+    /// Changes below this level are reported but never trigger the tamper action.
+    pub tamper_threshold: Severity,
+    /// Per-path alert limit; `None` reports every change.
+    pub alert_limiter: Option<AlertLimiter>,
+    /// Approved hashes; a change back to one is downgraded to info.
+    pub baseline: Option<KdvVerifier>,
+    pub unbaselined: UnbaselinedPolicy,
+    /// HMAC-chained ndjson copy of every emitted event (`--audit-log`).
+    pub audit: Option<AuditLog>,
+    /// The watcher's own binary, config and baseline: always critical,
+    /// never debounced and not removable over the control socket.
+    pub protected: HashSet<PathBuf>,
+    /// Level inherited by files that appear later in a watched directory.
+    pub dir_severity: HashMap<PathBuf, Severity>,
+    pub webhooks: Vec<String>,
+    pub webhook_jobs: Vec<JoinHandle<()>>,
+    pub json: bool,
+    pub rearm_after: Option<Duration>,
+    pub rearm_requested: Arc<AtomicBool>,
+    /// Set while alerting is paused for maintenance.
+    pub paused: Option<Pause>,
+    pub paused_mode: PausedMode,
+    /// Resume on its own after this long paused (`--max-pause`).
+    pub max_pause: Option<Duration>,
+    /// Raised by SIGUSR1 and SIGUSR2 respectively.
+    pub pause_requested: Arc<AtomicBool>,
+    pub resume_requested: Arc<AtomicBool>,
+    pub check_metadata: bool,
+    /// Watch symlink targets instead of refusing links.
+    pub follow_symlinks: bool,
+    /// Streamed hashing for files above a size (`--large-file-threshold`).
+    pub large_file: Option<LargeFilePolicy>,
+    /// Followed links and the target each one resolved to last.
+    pub links: HashMap<PathBuf, PathBuf>,
+    /// Heartbeat period; when set, exports are batched into each heartbeat.
+    pub summary_interval: Option<Duration>,
+    pub interval: IntervalSummary,
+    pub last_summary: Instant,
+    export_pending: bool,
+    /// Files that could not get a native watch and are re-hashed instead.
+    pub polled: HashSet<PathBuf>,
+    pub poll_interval: Duration,
+    pub last_poll: Instant,
+    watch_limit_warned: bool,
+    /// Initial fingerprinting still running in the background.
+    pub scan: Option<InitialScan>,
+    /// Caps what the initial fingerprint pass reads per second.
+    pub scan_throttle: Option<Arc<Throttle>>,
+    /// Priority the initial fingerprint pass runs at.
+    pub scan_priority: Priority,
+    /// Rewritten atomically every `status_interval` when set.
+    pub status_file: Option<PathBuf>,
+    pub status_interval: Duration,
+    pub last_status: Instant,
+    /// Commands from the `--control-socket` listener thread.
+    pub control: Option<Receiver<ControlRequest>>,
+    /// Violations from the HFS monitor thread (`[hfs]`).
+    pub violations: Option<tokio::sync::mpsc::UnboundedReceiver<Violation>>,
+    /// Makes the HFS monitor check its protected files at the next scan.
+    pub hfs_fd_scan: Option<Arc<AtomicBool>>,
+    /// The `--kdv-daemon` thread, for the control socket to drive.
+    pub kdv_daemon: Option<KdvDaemonHandle>,
+    /// What the kdv daemon's rounds found.
+    pub kdv_events: Option<tokio::sync::mpsc::UnboundedReceiver<KdvDaemonEvent>>,
+    pub violation_response: ViolationResponse,
+    /// `on_violation.run` commands still running.
+    pub reaction_jobs: Vec<JoinHandle<()>>,
+    handlers: Vec<RegisteredHandler>,
 }
 
 impl WatchManager {
@@ -115,89 +1090,2923 @@ impl WatchManager {
                 tx.send(event).unwrap();
             }
         }).unwrap();
-        Self {
+        Self::with_backend(Box::new(watcher), rx)
+    }
+
+    /// Builds a manager around any watch backend; `rx` receives its events.
+    pub fn with_backend(watcher: Box<dyn WatchBackend>, rx: Receiver<Event>) -> Self {
+        let mut wm = Self {
             files: HashMap::new(),
             watcher,
             rx,
-        }
+            remediation: None,
+            debounce: Duration::ZERO,
+            pending: HashMap::new(),
+            stats: WatchStats::default(),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            gate: RecoveryGate::Closed,
+            dirs: HashSet::new(),
+            dir_roots: HashSet::new(),
+            native_roots: HashSet::new(),
+            max_depth: None,
+            excludes: Vec::new(),
+            ignores: DEFAULT_IGNORES
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern).expect("valid default ignore"))
+                .collect(),
+            output_path: PathBuf::from("output.pself"),
+            tamper_action: TamperAction::Exit,
+            default_severity: Severity::Critical,
+            tamper_threshold: Severity::Critical,
+            alert_limiter: Some(AlertLimiter::new(DEFAULT_ALERT_RATE)),
+            baseline: None,
+            unbaselined: UnbaselinedPolicy::Alert,
+            audit: None,
+            protected: HashSet::new(),
+            dir_severity: HashMap::new(),
+            webhooks: Vec::new(),
+            webhook_jobs: Vec::new(),
+            json: false,
+            rearm_after: None,
+            rearm_requested: Arc::new(AtomicBool::new(false)),
+            paused: None,
+            paused_mode: PausedMode::Rebaseline,
+            max_pause: None,
+            pause_requested: Arc::new(AtomicBool::new(false)),
+            resume_requested: Arc::new(AtomicBool::new(false)),
+            check_metadata: false,
+            follow_symlinks: false,
+            large_file: None,
+            links: HashMap::new(),
+            summary_interval: None,
+            interval: IntervalSummary::default(),
+            last_summary: Instant::now(),
+            export_pending: false,
+            polled: HashSet::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            last_poll: Instant::now(),
+            watch_limit_warned: false,
+            scan: None,
+            scan_throttle: None,
+            scan_priority: Priority::default(),
+            status_file: None,
+            status_interval: DEFAULT_STATUS_INTERVAL,
+            last_status: Instant::now(),
+            control: None,
+            violations: None,
+            hfs_fd_scan: None,
+            kdv_daemon: None,
+            kdv_events: None,
+            violation_response: ViolationResponse::default(),
+            reaction_jobs: Vec::new(),
+            handlers: Vec::new(),
+        };
+        wm.install_default_handlers();
+        wm
     }
 
-    pub fn add_path(&mut self, path: &Path) {
-        if path.is_file() {
-            self.add_file(path.to_path_buf(), None);
-        } else if path.is_dir() {
-            for entry in fs::read_dir(path).unwrap() {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() {
-                    self.add_file(path, None);
-                }
-            }
-        }
+    /// The CLI behaviour: report the change, raise the alert, then run the
+    /// recovery gate and the tamper action.
+    pub fn install_default_handlers(&mut self) {
+        self.on_any(Self::report_change);
+        self.on_any(Self::raise_alert);
+        self.on_any(Self::check_tamper);
     }
 
-    pub fn add_file(&mut self, path: PathBuf, liner: Option<LineWatch>) {
-        if self.files.contains_key(&path) {
+    /// Runs `handler` for changes to `path` only.
+    pub fn on_change<F>(&mut self, path: impl Into<PathBuf>, handler: F)
+    where
+        F: FnMut(&mut WatchManager, &ChangeEvent) + Send + 'static,
+    {
+        self.handlers.push(RegisteredHandler {
+            path: Some(path.into()),
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Runs `handler` for every change.
+    pub fn on_any<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut WatchManager, &ChangeEvent) + Send + 'static,
+    {
+        self.handlers.push(RegisteredHandler {
+            path: None,
+            handler: Box::new(handler),
+        });
+    }
+
+    /// Calls matching handlers in registration order, the defaults first. The handler list is taken out of `self` for the duration, so a
+    /// handler may call back into the manager; a panicking handler is reported
+    /// and skipped without stopping the others or the watch loop.
+    pub fn dispatch(&mut self, event: &ChangeEvent) {
+        if self.paused_mode == PausedMode::Rebaseline {
+            if let Some(pause) = self.paused.as_mut() {
+                pause.absorbed += 1;
+                self.refresh_snapshot(&event.path);
+                return;
+            }
+        }
+        if event.baseline == Some(BaselineCheck::Unknown) && self.unbaselined == UnbaselinedPolicy::Ignore {
             return;
         }
-        let mut entry = FileEntry::from_path(&path);
-        if let Some(liner_mode) = liner {
-            entry.set_liner_watch(liner_mode);
+        if event.kind != ChangeKind::Added {
+            *self.interval.changes.entry(event.path.clone()).or_insert(0) += 1;
+        }
+        let now = Instant::now();
+        self.report_suppressed(now);
+        let mut event = event.clone();
+        if let Some(limiter) = self.alert_limiter.as_mut() {
+            event.suppressed |= !limiter.allow(&event.path, now);
         }
-        self.watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
-        self.files.insert(path.clone(), entry);
-        println!("Included: {}", path.display());
-    }
-
-    pub fn update_if_needed(&mut self, path: &PathBuf) {
-        if let Some(entry) = self.files.get_mut(path) {
-            if entry.update() {
-                println!("[MODIFIED] {}", path.display());
-                is::itdefine::trigger(&path.to_string_lossy());
 
-                if !is::itdefine::pass_recovery_gate() {
-                    println!("[CRITICAL] Unauthorized tampering confirmed. Exiting.");
-                    std::process::exit(1337);
-                }
+        let mut handlers = std::mem::take(&mut self.handlers);
+        for registered in handlers.iter_mut() {
+            if registered.path.as_ref().is_some_and(|path| path != &event.path) {
+                continue;
+            }
+            let handler = &mut registered.handler;
+            if panic::catch_unwind(AssertUnwindSafe(|| handler(self, &event))).is_err() {
+                eprintln!("[WARN] Change handler panicked on {}", event.path.display());
             }
         }
+        // Keep anything registered by a handler while we were dispatching.
+        handlers.append(&mut self.handlers);
+        self.handlers = handlers;
+    }
 
-        self.export_pself().unwrap_or_else(|e| {
-            eprintln!("Failed to export pself: {}", e);
-        });
+    /// Builds an event stamped now, at the level configured for `path` (or
+    /// for the directory it is in). Content changes that land on the approved
+    /// baseline are downgraded to info.
+    pub fn change_event(
+        &self,
+        path: &Path,
+        kind: ChangeKind,
+        diff: Option<FingerprintDiff>,
+        metadata: Vec<MetadataChange>,
+    ) -> ChangeEvent {
+        let baseline = match (&self.baseline, kind) {
+            (Some(verifier), ChangeKind::Modified | ChangeKind::Deleted | ChangeKind::Retargeted)
+                if self.files.contains_key(path) =>
+            {
+                Some(verifier.check_file(path))
+            }
+            _ => None,
+        };
+        let severity = match baseline {
+            Some(BaselineCheck::Matches) => Severity::Info,
+            _ => self.severity_of(path),
+        };
+        ChangeEvent {
+            path: path.to_path_buf(),
+            kind,
+            severity,
+            timestamp: SystemTime::now(),
+            diff,
+            metadata,
+            suppressed: false,
+            baseline,
+        }
     }
 
-    pub fn export_pself(&self) -> std::io::Result<()> {
-        let paths: Vec<PathBuf> = self.files.keys().cloned().collect();
-        let included_files = serialk::SerialK::load_included_files(&paths)?;
-        let output_path = PathBuf::from("output.pself");
-        serialk::SerialK::create_pself(&included_files, &output_path)?;
-        println!("PSelf file updated: {}", output_path.display());
+    pub fn severity_of(&self, path: &Path) -> Severity {
+        if let Some(entry) = self.files.get(path) {
+            return entry.severity;
+        }
+        self.dir_severity
+            .get(path)
+            .or_else(|| path.parent().and_then(|parent| self.dir_severity.get(parent)))
+            .or_else(|| self.tree_root(path).and_then(|root| self.dir_severity.get(root)))
+            .copied()
+            .unwrap_or(self.default_severity)
+    }
+
+    /// Emits one "further alerts suppressed" line per path whose rate limit
+    /// window is over. Returns how many lines were emitted.
+    pub fn report_suppressed(&mut self, now: Instant) -> usize {
+        let done = match self.alert_limiter.as_mut() {
+            Some(limiter) => limiter.take_suppressed(now),
+            None => return 0,
+        };
+        for (path, count) in &done {
+            self.emit_suppressed(path, *count);
+        }
+        done.len()
+    }
+
+    fn emit_suppressed(&mut self, path: &Path, count: usize) {
+        self.emit(
+            "NOTICE",
+            path,
+            &format!("{} further alerts suppressed for {}", count, path.display()),
+        );
+    }
+
+    fn report_change(&mut self, event: &ChangeEvent) {
+        if event.suppressed {
+            return;
+        }
+        let message = self.describe(event);
+        self.emit_event(&event.kind.as_str().to_uppercase(), &event.path, &message, event.timestamp, Some(event.severity));
+    }
+
+    /// The message printed for `event` by the default report handler.
+    pub fn describe(&self, event: &ChangeEvent) -> String {
+        let path = &event.path;
+        let mut message = match (event.kind, &event.diff) {
+            (ChangeKind::Deleted, _) if self.files.contains_key(path) => path.display().to_string(),
+            (ChangeKind::Deleted, _) => format!("Watched directory removed: {}", path.display()),
+            (ChangeKind::Modified, Some(diff)) => format!("{} ({})", path.display(), diff),
+            _ => path.display().to_string(),
+        };
+        if !event.metadata.is_empty() {
+            let changes: Vec<String> = event.metadata.iter().map(|c| c.to_string()).collect();
+            message.push_str(&format!(": {}", changes.join(", ")));
+        }
+        if let Some(baseline) = event.baseline {
+            message.push_str(&format!(" [{}]", baseline.as_str()));
+        }
+        message
+    }
+
+    fn raise_alert(&mut self, event: &ChangeEvent) {
+        if event.kind == ChangeKind::Added {
+            return;
+        }
+        if event.suppressed {
+            self.stats.suppressed += 1;
+            self.interval.suppressed += 1;
+            if let Some(entry) = self.files.get_mut(&event.path) {
+                entry.stats.suppressed += 1;
+            }
+            return;
+        }
+        self.stats.alerts += 1;
+        self.stats.alerts_by_severity.record(event.severity);
+        self.interval.alerts += 1;
+        self.interval.alerts_by_severity.record(event.severity);
+        if let Some(entry) = self.files.get_mut(&event.path) {
+            entry.stats.alerts += 1;
+        }
+        is::itdefine::trigger(&event.path.to_string_lossy());
+        self.notify_webhooks(event.kind.as_str(), &event.path, event.severity);
+    }
+
+    /// Only tracked files go through the gate; a removed directory is alerted on
+    /// but has nothing to restore.
+    fn check_tamper(&mut self, event: &ChangeEvent) {
+        if event.kind == ChangeKind::Added || !self.files.contains_key(&event.path) {
+            return;
+        }
+        if event.baseline == Some(BaselineCheck::Matches) {
+            self.emit(
+                "NOTICE",
+                &event.path,
+                &format!("{} matches the approved baseline; no tamper action taken", event.path.display()),
+            );
+            return;
+        }
+        if event.severity < self.tamper_threshold {
+            self.emit(
+                "NOTICE",
+                &event.path,
+                &format!(
+                    "{} is {} (below the {} threshold); no tamper action taken",
+                    event.path.display(),
+                    event.severity,
+                    self.tamper_threshold
+                ),
+            );
+            return;
+        }
+        if self.gate.open(&event.path) == GateVerdict::Denied {
+            self.respond_to_tamper(&event.path);
+        }
+    }
+
+    /// Responds to the gate's challenges that went unanswered or were
+    /// answered wrongly since the last tick.
+    fn process_gate(&mut self, now: Instant, shutdown: bool) {
+        for (path, authorized) in self.gate.poll(now, shutdown) {
+            if !authorized {
+                self.respond_to_tamper(&path);
+            }
+        }
+    }
+
+    /// Prints one event line, either as `[KIND] message` or as a JSON object,
+    /// and appends it to the audit log.
+    pub fn emit(&mut self, kind: &str, path: &Path, message: &str) {
+        self.emit_event(kind, path, message, SystemTime::now(), None);
+    }
+
+    /// Like `emit`, for a change event: carries its time and severity.
+    pub fn emit_event(&mut self, kind: &str, path: &Path, message: &str, at: SystemTime, severity: Option<Severity>) {
+        let timestamp = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut line = serde_json::json!({
+            "event": kind.to_lowercase(),
+            "message": message,
+            "timestamp": timestamp,
+        });
+        // Watcher-wide events such as pause and resume have no path.
+        if !path.as_os_str().is_empty() {
+            line["path"] = path.to_string_lossy().into();
+        }
+        if let Some(severity) = severity {
+            line["severity"] = severity.as_str().into();
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            // Critical events must survive a crash or a kill right after them.
+            if let Err(e) = audit.append(line.clone(), severity == Some(Severity::Critical)) {
+                eprintln!("[WARN] Cannot write audit log: {}", e);
+            }
+        }
+
+        if self.json {
+            println!("{}", line);
+        } else if let Some(severity) = severity {
+            println!("[{}] {} severity={}", kind, message, severity);
+        } else {
+            println!("[{}] {}", kind, message);
+        }
+    }
+
+    /// Stops alerting until `resume`. Returns false if already paused.
+    pub fn pause(&mut self, reason: &str) -> bool {
+        if self.paused.is_some() {
+            return false;
+        }
+        self.paused = Some(Pause {
+            since: Instant::now(),
+            absorbed: 0,
+        });
+        let limit = self.max_pause.map(|max| format!(", auto-resume in {}s", max.as_secs())).unwrap_or_default();
+        self.emit(
+            "PAUSED",
+            Path::new(""),
+            &format!("Watching paused ({}, mode {}{})", reason, self.paused_mode.as_str(), limit),
+        );
+        true
+    }
+
+    /// Ends a pause. In freeze mode the held events are evaluated on the next
+    /// pass of the loop. Returns false if not paused.
+    pub fn resume(&mut self, reason: &str) -> bool {
+        let Some(pause) = self.paused.take() else {
+            return false;
+        };
+        let outcome = match self.paused_mode {
+            PausedMode::Rebaseline => format!("{} changes absorbed into the baseline", pause.absorbed),
+            PausedMode::Freeze => format!("{} held paths to evaluate", self.pending.len()),
+        };
+        self.emit(
+            "RESUMED",
+            Path::new(""),
+            &format!(
+                "Watching resumed ({}) after {}s, {}",
+                reason,
+                pause.since.elapsed().as_secs(),
+                outcome
+            ),
+        );
+        true
+    }
+
+    /// Acts on SIGUSR1/SIGUSR2 and ends a pause that outlived `max_pause`.
+    pub fn pause_due(&mut self, now: Instant) {
+        if self.pause_requested.swap(false, Ordering::SeqCst) {
+            self.pause("SIGUSR1");
+        }
+        if self.resume_requested.swap(false, Ordering::SeqCst) {
+            self.resume("SIGUSR2");
+        }
+        let expired = self
+            .paused
+            .as_ref()
+            .zip(self.max_pause)
+            .is_some_and(|(pause, max)| now.saturating_duration_since(pause.since) >= max);
+        if expired {
+            self.resume("--max-pause reached");
+        }
+    }
+
+    fn frozen(&self) -> bool {
+        self.paused.is_some() && self.paused_mode == PausedMode::Freeze
+    }
+
+    /// Takes a new quarantine snapshot of a file changed during a
+    /// rebaseline pause, so a later restore brings back the new state.
+    fn refresh_snapshot(&mut self, path: &Path) {
+        let Some(remediation) = self.remediation.as_ref() else {
+            return;
+        };
+        let Some(entry) = self.files.get_mut(path) else {
+            return;
+        };
+        match take_snapshot(path, remediation) {
+            Ok(snapshot) => entry.snapshot = Some(snapshot),
+            Err(e) => eprintln!("[WARN] Could not snapshot {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn set_debounce(&mut self, window: Duration) {
+        self.debounce = window;
+    }
+
+    /// Quarantine tampered files and restore their baseline instead of exiting.
+    /// Must be called before files are added so that snapshots are taken.
+    pub fn enable_quarantine(&mut self, quarantine_dir: PathBuf, snapshot_dir: Option<PathBuf>) -> io::Result<()> {
+        fs::create_dir_all(&quarantine_dir)?;
+        if let Some(dir) = &snapshot_dir {
+            fs::create_dir_all(dir)?;
+        }
+        self.remediation = Some(Remediation {
+            quarantine_dir,
+            snapshot_dir,
+            memory_limit: MEMORY_SNAPSHOT_LIMIT,
+        });
+        self.tamper_action = TamperAction::Quarantine;
         Ok(())
     }
 
-    pub fn watch_loop(&mut self) {
-        loop {
-            while let Ok(event) = self.rx.try_recv() {
-                for path in event.paths {
-                    self.update_if_needed(&path);
+    pub fn add_exclude(&mut self, pattern: &str) -> Result<(), glob::PatternError> {
+        self.excludes.push(glob::Pattern::new(pattern)?);
+        Ok(())
+    }
+
+    pub fn add_ignore(&mut self, pattern: &str) -> Result<(), glob::PatternError> {
+        self.ignores.push(glob::Pattern::new(pattern)?);
+        Ok(())
+    }
+
+    /// Exclude globs match either the full path or just the file name.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        matches_any(&self.excludes, path) || self.is_ignored(path)
+    }
+
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        matches_any(&self.ignores, path)
+    }
+
+    /// `add_path_as` at the default severity, for tests.
+    #[cfg(test)]
+    pub fn add_path(&mut self, path: &Path) -> Result<(), WatcherError> {
+        self.add_path_as(path, self.default_severity)
+    }
+
+    /// Adds a file or every file of a directory at `severity`; a directory
+    /// passes it on to files created in it later. Unreadable entries inside a
+    /// directory are skipped with a warning; only `path` itself is an error.
+    pub fn add_path_as(&mut self, path: &Path, severity: Severity) -> Result<(), WatcherError> {
+        match self.expand_path(path)? {
+            None => self.add_file_as(path.to_path_buf(), None, severity),
+            Some(children) => {
+                self.dir_severity.insert(canonical_path(path), severity);
+                for child in children {
+                    if let Err(e) = self.add_file_as(child, None, severity) {
+                        eprintln!("[WARN] Skipping {}", e);
+                    }
                 }
+                Ok(())
             }
-            std::thread::sleep(Duration::from_millis(100));
         }
     }
-}
 
-pub fn parse_liner_street(arg: &str) -> (PathBuf, LineWatch) {
-    let mut parts = arg.splitn(2, ':');
-    let path = PathBuf::from(parts.next().unwrap());
-    let value = parts.next().unwrap_or("1");
+    /// Returns `None` for a plain file. For a directory, starts watching its
+    /// tree and returns the files in it that pass the exclude, depth and
+    /// symlink rules.
+    fn expand_path(&mut self, path: &Path) -> Result<Option<Vec<PathBuf>>, WatcherError> {
+        let link_meta = fs::symlink_metadata(path).map_err(|e| WatcherError::from_io(path, e))?;
+        if link_meta.file_type().is_symlink() && !self.follow_symlinks {
+            return Err(WatcherError::Symlink(path.to_path_buf()));
+        }
+        let meta = fs::metadata(path).map_err(|e| WatcherError::from_io(path, e))?;
+        if !meta.is_dir() {
+            return Ok(None);
+        }
 
-    let watch = if value == "forever-all-day" {
-        LineWatch::Forever
-    } else {
-        LineWatch::Count(value.parse::<usize>().unwrap_or(1))
-    };
+        let root = fs::canonicalize(path).map_err(|e| WatcherError::from_io(path, e))?;
+        // One recursive watch covers an unbounded tree, including directories
+        // created later; a depth limit or a backend without recursive support
+        // gets one watch per directory instead.
+        if self.max_depth.is_none() && !self.natively_covered(&root) {
+            match self.watcher.watch(&root, RecursiveMode::Recursive) {
+                Ok(()) => {
+                    self.native_roots.insert(root.clone());
+                }
+                Err(e) => eprintln!(
+                    "[WARN] No recursive watch for {} ({}); watching its directories one by one",
+                    path.display(),
+                    WatcherError::from_notify(path, e)
+                ),
+            }
+        }
+        self.dir_roots.insert(root.clone());
+        self.walk_dir(path, &root, 0).map(Some)
+    }
+
+    /// Watches `dir` and the directories below it down to `max_depth` levels
+    /// (`level` is the depth of `dir` under `root`) and returns the files
+    /// found. Only `dir` itself failing is an error.
+    fn walk_dir(&mut self, dir: &Path, root: &Path, level: usize) -> Result<Vec<PathBuf>, WatcherError> {
+        let mut children = Vec::new();
+        let mut stack = vec![(dir.to_path_buf(), level)];
+        while let Some((current, level)) = stack.pop() {
+            let top = current == dir;
+            let entries = fs::read_dir(&current)
+                .map_err(|e| WatcherError::from_io(&current, e))
+                .and_then(|entries| self.watch_dir(&current).map(|()| entries));
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(e) if top => return Err(e),
+                Err(e) => {
+                    eprintln!("[WARN] Skipping {}", e);
+                    continue;
+                }
+            };
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        eprintln!("[WARN] {}", WatcherError::from_io(&current, e));
+                        continue;
+                    }
+                };
+                let child = entry.path();
+                // Symlinked directories are never descended into, so the walk cannot loop.
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    if self.max_depth.is_none_or(|max| level < max) && !self.is_excluded(&child) {
+                        stack.push((child, level + 1));
+                    }
+                    continue;
+                }
+                if !child.is_file() || self.is_excluded(&child) {
+                    continue;
+                }
+                if entry.file_type().is_ok_and(|t| t.is_symlink()) {
+                    if !self.follow_symlinks {
+                        eprintln!("[WARN] Skipping {}", WatcherError::Symlink(child));
+                        continue;
+                    }
+                    // A followed link must not lead the walk out of the tree it was asked for.
+                    match fs::canonicalize(&child) {
+                        Ok(target) if target.starts_with(root) => {}
+                        Ok(target) => {
+                            eprintln!(
+                                "[WARN] Skipping {}: link target {} is outside {}",
+                                child.display(),
+                                target.display(),
+                                root.display()
+                            );
+                            continue;
+                        }
+                        Err(e) => {
+                            eprintln!("[WARN] Skipping {}", WatcherError::from_io(&child, e));
+                            continue;
+                        }
+                    }
+                }
+                children.push(child);
+            }
+        }
+        children.sort();
+        Ok(children)
+    }
+
+    /// Starts watching one directory so files created in it later are
+    /// picked up, unless a recursive watch already covers it.
+    fn watch_dir(&mut self, dir: &Path) -> Result<(), WatcherError> {
+        let key = canonical_path(dir);
+        if !self.natively_covered(&key) {
+            self.watcher
+                .watch(&key, RecursiveMode::NonRecursive)
+                .map_err(|e| WatcherError::from_notify(dir, e))?;
+        }
+        self.dirs.insert(key);
+        Ok(())
+    }
+
+    /// True if `path` lies under (or is) a directory with a native recursive watch.
+    fn natively_covered(&self, path: &Path) -> bool {
+        path.ancestors().any(|dir| self.native_roots.contains(dir))
+    }
+
+    /// The included directory `path` was found under, if any.
+    fn tree_root(&self, path: &Path) -> Option<&PathBuf> {
+        path.ancestors().find_map(|dir| self.dir_roots.get(dir))
+    }
+
+    /// Turns include paths and line-watched files into scan jobs, expanding
+    /// directories. Errors for the named paths themselves are returned.
+    /// Entries without a severity get `default_severity`.
+    pub fn plan_scan(
+        &mut self,
+        includes: &[(PathBuf, Option<Severity>)],
+        liner: &[(PathBuf, LineWatch, Option<Severity>)],
+    ) -> (Vec<ScanJob>, Vec<WatcherError>) {
+        let mut jobs = Vec::new();
+        let mut failures = Vec::new();
+        for (path, severity) in includes {
+            let severity = severity.unwrap_or(self.default_severity);
+            match self.expand_path(path) {
+                Ok(None) => jobs.push(ScanJob {
+                    path: path.clone(),
+                    liner: None,
+                    severity,
+                    top_level: true,
+                }),
+                Ok(Some(children)) => {
+                    self.dir_severity.insert(canonical_path(path), severity);
+                    jobs.extend(children.into_iter().map(|path| ScanJob {
+                        path,
+                        liner: None,
+                        severity,
+                        top_level: false,
+                    }));
+                }
+                Err(e) => failures.push(e),
+            }
+        }
+        for (path, watch, severity) in liner {
+            jobs.push(ScanJob {
+                path: path.clone(),
+                liner: Some(watch.clone()),
+                severity: severity.unwrap_or(self.default_severity),
+                top_level: true,
+            });
+        }
+        (jobs, failures)
+    }
+
+    fn entry_options(&self) -> EntryOptions {
+        EntryOptions {
+            follow_symlinks: self.follow_symlinks,
+            check_metadata: self.check_metadata,
+            snapshot: self.remediation.clone(),
+            large_file: self.large_file,
+        }
+    }
 
-    (path, watch)
+    /// Fingerprints `jobs` on `threads` workers. Finished entries are
+    /// registered by `drain_scan`, from the watch loop or a blocking wait.
+    pub fn start_initial_scan(&mut self, jobs: Vec<ScanJob>, threads: usize) {
+        let (throttle, priority) = (self.scan_throttle.clone(), self.scan_priority);
+        self.scan = Some(InitialScan::spawn(jobs, self.entry_options(), threads, throttle, priority));
+    }
+
+    pub fn scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    /// Registers finished scan results and returns failures of explicitly
+    /// named paths; failures inside directories only warn. With `wait` it
+    /// blocks until the whole scan is done.
+    pub fn drain_scan(&mut self, wait: bool) -> Vec<WatcherError> {
+        let mut failures = Vec::new();
+        let Some(mut scan) = self.scan.take() else {
+            return failures;
+        };
+
+        while !scan.is_complete() {
+            let result = if wait {
+                match scan.rx.recv() {
+                    Ok(result) => result,
+                    Err(_) => break,
+                }
+            } else {
+                match scan.rx.try_recv() {
+                    Ok(result) => result,
+                    Err(_) => break,
+                }
+            };
+            scan.done += 1;
+
+            let registered = result
+                .entry
+                .and_then(|(entry, is_link)| self.register_entry(entry, is_link));
+            match registered {
+                Ok(()) => {}
+                Err(e) if result.job.top_level => failures.push(e),
+                Err(e) => eprintln!("[WARN] Skipping {}", e),
+            }
+
+            if scan.last_progress.elapsed() >= Duration::from_secs(1) {
+                scan.last_progress = Instant::now();
+                eprintln!("{}", scan.progress());
+            }
+        }
+
+        if scan.is_complete() {
+            if scan.total >= serialk_scan::BACKGROUND_SCAN_THRESHOLD {
+                eprintln!(
+                    "[SCAN] Fingerprinted {} files in {:.1}s",
+                    scan.total,
+                    scan.started.elapsed().as_secs_f64()
+                );
+            }
+        } else {
+            self.scan = Some(scan);
+        }
+        failures
+    }
+
+    /// Watches a single file natively, falling back to polling once the OS
+    /// watch limit has been reached.
+    fn watch_file(&mut self, path: &Path) -> Result<(), WatcherError> {
+        match self.watcher.watch(path, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                self.polled.remove(path);
+                Ok(())
+            }
+            Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                if !self.watch_limit_warned {
+                    self.watch_limit_warned = true;
+                    eprintln!(
+                        "[WARN] Watch limit reached; polling remaining files every {}s. \
+                         Raise it with: sysctl fs.inotify.max_user_watches=524288",
+                        self.poll_interval.as_secs()
+                    );
+                }
+                self.polled.insert(path.to_path_buf());
+                Ok(())
+            }
+            Err(e) => Err(WatcherError::from_notify(path, e)),
+        }
+    }
+
+    /// Re-hashes polled files through the normal update path, then tries to
+    /// move them back to native watching in case watches were freed.
+    pub fn poll_due(&mut self, now: Instant) {
+        if self.polled.is_empty() || self.frozen() || now.saturating_duration_since(self.last_poll) < self.poll_interval {
+            return;
+        }
+        self.last_poll = now;
+
+        let mut polled: Vec<PathBuf> = self.polled.iter().cloned().collect();
+        polled.sort();
+        for path in &polled {
+            let changed = self
+                .files
+                .get(path)
+                .is_some_and(|entry| !entry.is_exhausted() && entry.differs_from_disk());
+            if changed {
+                self.handle_path(path);
+            }
+        }
+
+        for path in polled {
+            if self.polled.contains(&path) && self.watcher.watch(&path, RecursiveMode::NonRecursive).is_ok() {
+                self.polled.remove(&path);
+                self.emit("NOTICE", &path, &format!("Native watching resumed for: {}", path.display()));
+            }
+        }
+    }
+
+    /// Watches the running executable plus `files` (config, baseline) as
+    /// protected entries, then checks the executable against the kdv
+    /// baseline recorded for it, if there is one.
+    pub fn protect_self(&mut self, files: &[PathBuf]) {
+        let mut targets = Vec::new();
+        match std::env::current_exe() {
+            Ok(exe) => targets.push(exe),
+            Err(e) => eprintln!("[WARN] Cannot locate the running executable: {}", e),
+        }
+        targets.extend(files.iter().cloned());
+        for path in &targets {
+            if let Err(e) = self.protect_file(path) {
+                eprintln!("[WARN] Cannot self-protect {}", e);
+            }
+        }
+
+        let Some(exe) = targets.first().and_then(|exe| fs::canonicalize(exe).ok()) else {
+            return;
+        };
+        if self.baseline.is_none() || !self.files.contains_key(&exe) {
+            return;
+        }
+        let event = self.change_event(&exe, ChangeKind::Modified, None, Vec::new());
+        if event.baseline == Some(BaselineCheck::Diverges) {
+            self.emit_event(
+                "CRITICAL",
+                &exe,
+                &format!("Running executable {} does not match its baseline hash", exe.display()),
+                SystemTime::now(),
+                Some(Severity::Critical),
+            );
+            self.dispatch(&event);
+        }
+    }
+
+    /// Watches one of the watcher's own files at critical severity. Its
+    /// directory is watched too, so replacing the file is noticed; if no
+    /// native watch is possible (e.g. some read-only or network mounts) the
+    /// file is re-hashed on the poll interval instead.
+    pub fn protect_file(&mut self, path: &Path) -> Result<(), WatcherError> {
+        let path = fs::canonicalize(path).map_err(|e| WatcherError::from_io(path, e))?;
+        if self.protected.insert(path.clone()) {
+            self.on_change(path.clone(), |wm, event| {
+                let message = format!("The watcher's own file {} was {}", event.path.display(), event.kind.as_str());
+                wm.emit_event("SELF", &event.path, &message, event.timestamp, Some(event.severity));
+            });
+        }
+        if let Some(entry) = self.files.get_mut(&path) {
+            entry.severity = Severity::Critical;
+            return Ok(());
+        }
+
+        let (entry, _) = build_entry(&path, None, Severity::Critical, &self.entry_options())?;
+        if let Err(e) = self.watcher.watch(&path, RecursiveMode::NonRecursive) {
+            eprintln!(
+                "[WARN] Cannot watch {} ({}); re-hashing it every {}s",
+                path.display(),
+                WatcherError::from_notify(&path, e),
+                self.poll_interval.as_secs()
+            );
+            self.polled.insert(path.clone());
+        }
+        if let Some(parent) = path.parent() {
+            let _ = self.watcher.watch(parent, RecursiveMode::NonRecursive);
+        }
+        self.files.insert(path.clone(), entry);
+        println!("Protected: {}", path.display());
+        Ok(())
+    }
+
+    /// Maps any spelling of a path to the key it is (or would be) tracked under.
+    pub fn tracked_key(&self, path: &Path) -> PathBuf {
+        if self.files.contains_key(path) || self.dirs.contains(path) {
+            return path.to_path_buf();
+        }
+        canonical_path(path)
+    }
+
+    /// Stops watching a tracked file. Returns false if it was not tracked.
+    pub fn remove_file(&mut self, path: &Path) -> bool {
+        let path = &self.tracked_key(path);
+        if self.files.remove(path).is_none() {
+            return false;
+        }
+        let _ = self.watcher.unwatch(path);
+        self.polled.remove(path);
+        self.links.remove(path);
+        self.pending.remove(path);
+        if let Some(limiter) = self.alert_limiter.as_mut() {
+            limiter.forget(path);
+        }
+        println!("Removed: {}", path.display());
+        true
+    }
+
+    fn in_watched_dir(&self, path: &Path) -> bool {
+        path.parent().is_some_and(|parent| self.dirs.contains(parent))
+    }
+
+    /// `add_file_as` at the default severity, for tests.
+    #[cfg(test)]
+    pub fn add_file(&mut self, path: PathBuf, liner: Option<LineWatch>) -> Result<(), WatcherError> {
+        self.add_file_as(path, liner, self.default_severity)
+    }
+
+    /// Files are keyed by their canonical path, so `./a`, `a` and a path
+    /// through a symlinked directory all end up as the same entry.
+    pub fn add_file_as(&mut self, path: PathBuf, liner: Option<LineWatch>, severity: Severity) -> Result<(), WatcherError> {
+        if self.files.contains_key(&canonical_path(&path)) {
+            return Ok(());
+        }
+        let (entry, is_link) = build_entry(&path, liner, severity, &self.entry_options())?;
+        self.register_entry(entry, is_link)
+    }
+
+    /// Starts watching a fingerprinted entry. An entry for a path that is
+    /// already tracked is dropped, so the first registration wins.
+    fn register_entry(&mut self, entry: FileEntry, is_link: bool) -> Result<(), WatcherError> {
+        let path = entry.path.clone();
+        if self.files.contains_key(&path) {
+            return Ok(());
+        }
+        if !self.natively_covered(&path) {
+            self.watch_file(&path)?;
+        }
+        if is_link {
+            let target = fs::canonicalize(&path).map_err(|e| WatcherError::from_io(&path, e))?;
+            // Retargeting replaces the link inside its directory, so watch that too.
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                self.watcher
+                    .watch(parent, RecursiveMode::NonRecursive)
+                    .map_err(|e| WatcherError::from_notify(parent, e))?;
+            }
+            self.links.insert(path.clone(), target);
+        }
+        if entry.presented == path {
+            println!("Included: {}", path.display());
+        } else {
+            println!("Included: {} (as {})", path.display(), entry.presented.display());
+        }
+        self.files.insert(path, entry);
+        Ok(())
+    }
+
+    pub fn update_if_needed(&mut self, path: &Path) {
+        let path = &self.tracked_key(path);
+        self.expire_due(Instant::now());
+        let (modified, mut metadata) = match self.files.get_mut(path) {
+            Some(entry) => (entry.update(), entry.update_metadata()),
+            None => (false, Vec::new()),
+        };
+        if modified {
+            metadata.retain(|change| !change.follows_content());
+        }
+
+        if modified {
+            self.stats.modified += 1;
+            let (diff, exhausted) = match self.files.get(path) {
+                Some(entry) => (entry.last_diff.clone(), entry.is_exhausted()),
+                None => (None, false),
+            };
+            let kind = if path.exists() { ChangeKind::Modified } else { ChangeKind::Deleted };
+            self.dispatch(&self.change_event(path, kind, diff, metadata));
+            if exhausted {
+                self.emit("NOTICE", path, &format!("Reached watch limit for: {}", path.display()));
+                // Exhausted entries stay tracked for re-arming but stop receiving events.
+                let _ = self.watcher.unwatch(path);
+                self.polled.remove(path);
+            }
+        } else if !metadata.is_empty() {
+            self.dispatch(&self.change_event(path, ChangeKind::Metadata, None, metadata));
+        }
+
+        self.export();
+    }
+
+    /// Closes every `Duration` watch window that has ended.
+    pub fn expire_due(&mut self, now: Instant) {
+        let mut expired: Vec<PathBuf> = self
+            .files
+            .iter_mut()
+            .filter_map(|(path, entry)| entry.expire_if_due(now).then(|| path.clone()))
+            .collect();
+        expired.sort();
+        for path in expired {
+            self.emit("NOTICE", &path, &format!("Watch window expired, back to normal watching: {}", path.display()));
+        }
+    }
+
+    /// Re-arms an exhausted `Count` watch and resumes watching the file.
+    pub fn rearm(&mut self, path: &Path) -> bool {
+        let Some(entry) = self.files.get_mut(path) else {
+            return false;
+        };
+        let was_exhausted = entry.is_exhausted();
+        entry.rearm();
+        if was_exhausted {
+            if let Err(e) = self.watch_file(path) {
+                eprintln!("[WARN] {}", e);
+            }
+            self.emit("REARMED", path, &format!("Watch limit reset for: {}", path.display()));
+        }
+        true
+    }
+
+    pub fn rearm_all(&mut self) {
+        let paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        for path in paths {
+            self.rearm(&path);
+        }
+    }
+
+    /// Re-arms entries that have been exhausted for longer than `rearm_after`.
+    pub fn rearm_due(&mut self, now: Instant) {
+        let Some(after) = self.rearm_after else {
+            return;
+        };
+        let due: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(_, entry)| entry.exhausted_at.is_some_and(|at| now.saturating_duration_since(at) >= after))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in due {
+            self.rearm(&path);
+        }
+    }
+
+    /// Records an event without acting on it; duplicates for the same path
+    /// are coalesced until the path has been quiet for the debounce window.
+    pub fn queue_event(&mut self, event: Event, now: Instant) {
+        self.stats.events += 1;
+        self.interval.record_event(&event.kind);
+        let removal = matches!(event.kind, EventKind::Remove(_));
+        for path in event.paths {
+            // Events through another spelling (e.g. a symlinked watched
+            // directory) are coalesced with those for the tracked path.
+            let path = self.tracked_key(&path);
+            // Files included by name are watched even if they look like temp files.
+            if !self.files.contains_key(&path) && !self.dirs.contains(&path) {
+                if !self.in_watched_dir(&path) && !self.natively_covered(&path) {
+                    continue;
+                }
+                if self.is_excluded(&path) {
+                    self.stats.ignored += 1;
+                    continue;
+                }
+            }
+            if let Some(entry) = self.files.get_mut(&path) {
+                entry.stats.events += 1;
+                entry.stats.last_event = Some(SystemTime::now());
+            }
+            let pending = self.pending.entry(path).or_insert(PendingEvent {
+                first_seen: now,
+                last_seen: now,
+                removal,
+            });
+            pending.last_seen = now;
+            pending.removal |= removal;
+        }
+    }
+
+    /// Processes every queued path whose debounce window has elapsed.
+    pub fn process_pending(&mut self, now: Instant) {
+        if self.frozen() {
+            return;
+        }
+        let mut due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(path, pending)| pending.is_due(now, self.debounce_for(path)))
+            .map(|(path, _)| path.clone())
+            .collect();
+        due.sort();
+
+        for path in due {
+            self.pending.remove(&path);
+            self.handle_path(&path);
+        }
+    }
+
+    /// Routes a settled event: watched directory removal, a new file inside a
+    /// watched directory, a retargeted symlink, or a change to a tracked file.
+    pub fn handle_path(&mut self, path: &PathBuf) {
+        if self.links.contains_key(path) && self.check_retarget(path) {
+            return;
+        }
+
+        if self.dirs.contains(path) && !path.exists() {
+            self.dirs.remove(path);
+            self.dispatch(&self.change_event(path, ChangeKind::Deleted, None, Vec::new()));
+            self.dir_severity.remove(path);
+            self.dir_roots.remove(path);
+            self.native_roots.remove(path);
+            return;
+        }
+
+        if !self.dirs.contains(path) && path.is_dir() && self.in_watched_dir(path) {
+            self.add_new_dir(path);
+            return;
+        }
+
+        if !self.files.contains_key(path) && path.is_file() && self.in_watched_dir(path) {
+            if self.is_excluded(path) {
+                return;
+            }
+            if let Err(e) = self.add_file_as(path.clone(), None, self.severity_of(path)) {
+                eprintln!("[WARN] Cannot track new file {}", e);
+                return;
+            }
+            self.dispatch(&self.change_event(path, ChangeKind::Added, None, Vec::new()));
+            self.export();
+            return;
+        }
+
+        // Siblings of a followed link show up because its directory is watched.
+        if !self.files.contains_key(path) {
+            return;
+        }
+        self.update_if_needed(path);
+    }
+
+    /// Starts watching a directory created under a watched one and tracks
+    /// the files already in it, each reported as added.
+    fn add_new_dir(&mut self, dir: &Path) {
+        let Some(root) = self.tree_root(dir).cloned() else {
+            return;
+        };
+        let level = dir.strip_prefix(&root).map_or(0, |rel| rel.components().count());
+        if self.is_excluded(dir) || self.max_depth.is_some_and(|max| level > max) {
+            return;
+        }
+        // The backend adds watches for new subdirectories on its own, but
+        // `mkdir -p` can outrun it; watching the new subtree again closes the gap.
+        if self.natively_covered(dir) {
+            if let Err(e) = self.watcher.watch(dir, RecursiveMode::Recursive) {
+                eprintln!("[WARN] Cannot watch new directory {}", WatcherError::from_notify(dir, e));
+            }
+        }
+        let files = match self.walk_dir(dir, &root, level) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("[WARN] Cannot watch new directory {}", e);
+                return;
+            }
+        };
+        for file in files {
+            let key = canonical_path(&file);
+            if self.files.contains_key(&key) {
+                continue;
+            }
+            if let Err(e) = self.add_file_as(file, None, self.severity_of(&key)) {
+                eprintln!("[WARN] Cannot track new file {}", e);
+                continue;
+            }
+            self.dispatch(&self.change_event(&key, ChangeKind::Added, None, Vec::new()));
+        }
+        self.export();
+    }
+
+    /// Alerts when a followed link now resolves somewhere else and moves the
+    /// watch to the new target. Returns false if the link still points at the
+    /// same file (or is gone, which the normal removal path reports).
+    fn check_retarget(&mut self, link: &PathBuf) -> bool {
+        let Ok(current) = fs::canonicalize(link) else {
+            return false;
+        };
+        let Some(previous) = self.links.get(link).cloned() else {
+            return false;
+        };
+        if current == previous {
+            return false;
+        }
+
+        let _ = self.watcher.unwatch(link);
+        if let Err(e) = self.watch_file(link) {
+            eprintln!("[WARN] {}", e);
+        }
+        self.links.insert(link.clone(), current.clone());
+        let diff = self
+            .files
+            .get_mut(link)
+            .and_then(|entry| if entry.update() { entry.last_diff.clone() } else { None });
+
+        let target = MetadataChange {
+            attribute: "target",
+            old: previous.display().to_string(),
+            new: current.display().to_string(),
+        };
+        self.dispatch(&self.change_event(link, ChangeKind::Retargeted, diff, vec![target]));
+        self.export();
+        true
+    }
+
+    fn respond_to_tamper(&mut self, path: &Path) {
+        match self.tamper_action {
+            TamperAction::Exit => {
+                println!("[CRITICAL] Unauthorized tampering confirmed. Exiting.");
+                std::process::exit(1337);
+            }
+            TamperAction::Log => {
+                println!("[CRITICAL] Unauthorized tampering confirmed in {}", path.display());
+            }
+            TamperAction::Quarantine => {
+                println!("[CRITICAL] Unauthorized tampering confirmed. Restoring baseline.");
+                if let Err(e) = self.quarantine_and_restore(path) {
+                    if e.kind() == ErrorKind::PermissionDenied {
+                        println!("[CRITICAL] Restore of {} failed: permission denied ({})", path.display(), e);
+                    } else {
+                        println!("[CRITICAL] Restore of {} failed: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Responds to every violation the HFS monitor has sent since the last tick.
+    pub fn process_violations(&mut self) {
+        let mut received = Vec::new();
+        if let Some(rx) = self.violations.as_mut() {
+            loop {
+                match rx.try_recv() {
+                    Ok(violation) => received.push(violation),
+                    Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                    Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                        eprintln!("[WARN] The HFS monitor has stopped.");
+                        self.violations = None;
+                        break;
+                    }
+                }
+            }
+        }
+        for violation in &received {
+            self.respond_to_violation(violation);
+        }
+    }
+
+    /// Reports a violation and reacts as `violation_response` says.
+    pub fn respond_to_violation(&mut self, violation: &Violation) {
+        let message = violation.to_string();
+        let message = message.trim_start_matches("[HFS] ");
+        self.emit_event("HFS", Path::new(""), message, SystemTime::now(), violation.severity);
+        let response = self.violation_response.clone();
+        if response.verify {
+            self.verify_all();
+        }
+        if response.export {
+            self.export_now();
+        }
+        if let Some(command) = &response.run {
+            self.run_reaction(command, violation);
+        }
+    }
+
+    /// Reports what the kdv daemon found since the last tick: an entry that
+    /// stopped verifying as critical, one that verifies again as info.
+    pub fn process_kdv_events(&mut self) {
+        let mut received = Vec::new();
+        if let Some(rx) = self.kdv_events.as_mut() {
+            loop {
+                match rx.try_recv() {
+                    Ok(event) => received.push(event),
+                    Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                    Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                        eprintln!("[WARN] The kdv daemon has stopped.");
+                        self.kdv_events = None;
+                        break;
+                    }
+                }
+            }
+        }
+        for event in received {
+            let KdvDaemonEvent::Round { report, .. } = event else {
+                self.emit("NOTICE", Path::new(""), "kdv round skipped; the previous one is still running");
+                continue;
+            };
+            for (name, result) in &report.results {
+                let (message, severity) = match result {
+                    VerifyResult::Verified => (format!("{} matches the kdv baseline again", name), Severity::Info),
+                    result => (format!("{} diverges from the kdv baseline: {}", name, result), Severity::Critical),
+                };
+                self.emit_event("KDV", Path::new(name), &message, SystemTime::now(), Some(severity));
+                self.notify_webhooks("kdv", Path::new(name), severity);
+            }
+        }
+    }
+
+    /// Re-hashes every tracked file now, settling changes that are still
+    /// debounced or that no event was seen for. Returns how many differed.
+    pub fn verify_all(&mut self) -> usize {
+        let changed: Vec<PathBuf> = self
+            .export_paths()
+            .into_iter()
+            .filter(|path| self.files.get(path).is_some_and(|entry| !entry.is_exhausted() && entry.differs_from_disk()))
+            .collect();
+        for path in &changed {
+            self.pending.remove(path);
+            self.handle_path(path);
+        }
+        let message = format!("Verified {} files, {} changed", self.files.len(), changed.len());
+        self.emit("NOTICE", Path::new(""), &message);
+        changed.len()
+    }
+
+    fn run_reaction(&mut self, command: &str, violation: &Violation) {
+        match hfs::reaction_command(command, violation).spawn() {
+            Ok(mut child) => self.reaction_jobs.push(std::thread::spawn(move || {
+                let _ = child.wait();
+            })),
+            Err(e) => eprintln!("[WARN] Cannot run on_violation command {:?}: {}", command, e),
+        }
+        self.reaction_jobs.retain(|job| !job.is_finished());
+    }
+
+    pub fn notify_webhooks(&mut self, event: &str, path: &Path, severity: Severity) {
+        if self.webhooks.is_empty() {
+            return;
+        }
+        let body = serde_json::json!({
+            "event": event,
+            "path": path.to_string_lossy(),
+            "severity": severity.as_str(),
+        })
+        .to_string();
+        for url in &self.webhooks {
+            self.webhook_jobs.push(serialk_webhook::spawn_post(url.clone(), body.clone()));
+        }
+        self.webhook_jobs.retain(|job| !job.is_finished());
+    }
+
+    /// Moves the tampered file into the quarantine directory and atomically puts
+    /// the snapshotted baseline back in its place.
+    pub fn quarantine_and_restore(&mut self, path: &Path) -> io::Result<PathBuf> {
+        let remediation = self.remediation.as_ref().ok_or_else(|| {
+            io::Error::other("quarantine is not enabled")
+        })?;
+        let entry = self.files.get(path).ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "file is not watched")
+        })?;
+        let snapshot = entry
+            .snapshot
+            .as_ref()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no baseline snapshot"))?;
+        let baseline = snapshot.content()?;
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let quarantined = remediation
+            .quarantine_dir
+            .join(format!("{}.{}.quarantine", file_name, stamp));
+
+        // The watch follows the inode, so drop it before the tampered file moves away.
+        let _ = self.watcher.unwatch(path);
+
+        if fs::rename(path, &quarantined).is_err() {
+            fs::copy(path, &quarantined)?;
+            fs::remove_file(path)?;
+        }
+        println!("[QUARANTINE] {} -> {}", path.display(), quarantined.display());
+
+        snapshot.restore_to(path, &baseline)?;
+        println!("[RESTORED] {}", path.display());
+
+        // Re-baseline before re-watching so the restore's own events compare equal.
+        if let Some(entry) = self.files.get_mut(path) {
+            entry.last_stat = stat(path);
+            entry.fingerprint = Fingerprint::from_file(path, entry.large_file)?;
+            if entry.metadata.is_some() {
+                entry.metadata = Some(FileMetadata::capture(path)?);
+            }
+        }
+        self.watch_file(path).map_err(|e| io::Error::other(e.to_string()))?;
+
+        Ok(quarantined)
+    }
+
+    /// Totals plus one entry per tracked file, sorted by path. Served by the
+    /// control socket and written to `--status-file`.
+    pub fn status_json(&self) -> serde_json::Value {
+        let entries: Vec<serde_json::Value> = self
+            .export_paths()
+            .iter()
+            .filter_map(|path| self.files.get(path))
+            .map(FileEntry::status_json)
+            .collect();
+        serde_json::json!({
+            "files": self.files.len(),
+            "dirs": self.dirs.len(),
+            "polled": self.polled.len(),
+            "events": self.stats.events,
+            "ignored": self.stats.ignored,
+            "modified": self.stats.modified,
+            "alerts": self.stats.alerts,
+            "alerts_by_severity": self.stats.alerts_by_severity.to_json(),
+            "suppressed": self.stats.suppressed,
+            "exports": self.stats.exports,
+            "paused": self.paused.as_ref().map(|pause| serde_json::json!({
+                "mode": self.paused_mode.as_str(),
+                "secs": pause.since.elapsed().as_secs(),
+                "absorbed": pause.absorbed,
+            })),
+            "entries": entries,
+        })
+    }
+
+    /// Writes the status file via a temporary file and rename so readers
+    /// never see a partial document.
+    pub fn write_status(&self) -> io::Result<()> {
+        let Some(path) = &self.status_file else {
+            return Ok(());
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.tmp", name));
+        fs::write(&tmp, serde_json::to_vec_pretty(&self.status_json()).map_err(io::Error::other)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn write_status_due(&mut self, now: Instant) {
+        if self.status_file.is_none() || now.saturating_duration_since(self.last_status) < self.status_interval {
+            return;
+        }
+        self.last_status = now;
+        if let Err(e) = self.write_status() {
+            eprintln!("[WARN] Cannot write status file: {}", e);
+        }
+    }
+
+    /// Tracked files in the order they are written to the pself.
+    pub fn export_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.files.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Exports now, or with `--summary-interval` marks the pself for the next heartbeat.
+    pub fn export(&mut self) {
+        if self.summary_interval.is_some() {
+            self.export_pending = true;
+        } else {
+            self.export_now();
+        }
+    }
+
+    pub fn export_now(&mut self) {
+        match self.export_pself() {
+            Ok(()) => {
+                self.stats.exports += 1;
+                self.interval.exports += 1;
+                self.interval.last_export = Some(SystemTime::now());
+            }
+            Err(e) => eprintln!("Failed to export pself: {}", e),
+        }
+    }
+
+    /// Flushes a batched export and returns the interval's summary once the
+    /// heartbeat period has elapsed, starting a new interval.
+    pub fn take_summary_if_due(&mut self, now: Instant) -> Option<IntervalSummary> {
+        let period = self.summary_interval?;
+        if now.saturating_duration_since(self.last_summary) < period {
+            return None;
+        }
+        Some(self.take_summary(now))
+    }
+
+    fn take_summary(&mut self, now: Instant) -> IntervalSummary {
+        if std::mem::take(&mut self.export_pending) {
+            self.export_now();
+        }
+        self.last_summary = now;
+        // The last export time carries over so a quiet interval still reports it.
+        let last_export = self.interval.last_export;
+        let summary = std::mem::take(&mut self.interval);
+        self.interval.last_export = last_export;
+        summary
+    }
+
+    fn print_summary(&self, summary: &IntervalSummary) {
+        let window = self.summary_interval.unwrap_or_default();
+        let polled = self.polled.len();
+        let native = self.files.len() - polled;
+        if self.json {
+            let mut json = summary.to_json(window);
+            json["native"] = native.into();
+            json["polled"] = polled.into();
+            println!("{}", json);
+        } else {
+            println!(
+                "[HEARTBEAT] last {}s: {}; native: {}, polled: {}",
+                window.as_secs(),
+                summary,
+                native,
+                polled
+            );
+        }
+    }
+
+    pub fn export_pself(&self) -> std::io::Result<()> {
+        let paths = self.export_paths();
+        let included_files = serialk::SerialK::load_included_files(&paths)?;
+        serialk::SerialK::create_pself(&included_files, &self.output_path)?;
+        if self.summary_interval.is_none() {
+            println!("PSelf file updated: {}", self.output_path.display());
+        }
+        Ok(())
+    }
+
+    /// Sleeps in the event channel until an event arrives or the next timer
+    /// is due, then runs one `tick`.
+    pub fn watch_loop(&mut self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            let timeout = self.next_wakeup(Instant::now());
+            let mut events = Vec::new();
+            match self.rx.recv_timeout(timeout) {
+                Ok(event) => {
+                    events.push(event);
+                    events.extend(self.rx.try_iter());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // No backend left to send events; keep the timers running.
+                Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+            }
+            self.tick(events, Instant::now());
+        }
+        self.finish();
+    }
+
+    /// One pass of the watch loop: queue `events`, settle what is due and
+    /// run the periodic work.
+    pub fn tick(&mut self, events: impl IntoIterator<Item = Event>, now: Instant) {
+        for e in self.drain_scan(false) {
+            eprintln!("[SKIPPED] {}", e);
+        }
+        for event in events {
+            self.queue_event(event, now);
+        }
+        self.pause_due(now);
+        self.process_pending(now);
+        self.process_gate(now, false);
+        self.process_control();
+        self.process_violations();
+        self.process_kdv_events();
+        if self.rearm_requested.swap(false, Ordering::SeqCst) {
+            self.rearm_all();
+        }
+        self.rearm_due(now);
+        self.expire_due(now);
+        self.poll_due(now);
+        self.write_status_due(now);
+        self.report_suppressed(now);
+        if let Some(summary) = self.take_summary_if_due(now) {
+            self.print_summary(&summary);
+        }
+    }
+
+    /// How long the loop may block: until the first debounced path is due,
+    /// capped by `IDLE_WAKEUP`.
+    pub fn next_wakeup(&self, now: Instant) -> Duration {
+        let cap = if self.scan.is_some()
+            || self.control.is_some()
+            || self.violations.is_some()
+            || self.kdv_events.is_some()
+            || self.gate.is_waiting()
+        {
+            BUSY_WAKEUP
+        } else {
+            IDLE_WAKEUP
+        };
+        // Held events wait for the resume, not for their debounce window.
+        if self.frozen() {
+            return cap;
+        }
+        self.pending
+            .iter()
+            .map(|(path, pending)| pending.due_at(self.debounce_for(path)).saturating_duration_since(now))
+            .fold(cap, Duration::min)
+    }
+
+    fn debounce_for(&self, path: &Path) -> Duration {
+        if self.protected.contains(path) {
+            Duration::ZERO
+        } else {
+            self.debounce
+        }
+    }
+
+    /// Final pass on shutdown: settle queued events, export once more and
+    /// print what this session observed.
+    pub fn finish(&mut self) {
+        println!("[SHUTDOWN] Stopping watcher...");
+        let pending: Vec<PathBuf> = self.pending.drain().map(|(path, _)| path).collect();
+        for path in pending {
+            self.handle_path(&path);
+        }
+        self.process_gate(Instant::now(), true);
+        self.export_pending = false;
+        self.export_now();
+        let suppressed = self.alert_limiter.as_mut().map(AlertLimiter::drain_suppressed).unwrap_or_default();
+        for (path, count) in suppressed {
+            self.emit_suppressed(&path, count);
+        }
+        if self.summary_interval.is_some() {
+            let summary = self.take_summary(Instant::now());
+            self.print_summary(&summary);
+        }
+        for job in self.webhook_jobs.drain(..).chain(self.reaction_jobs.drain(..)) {
+            let _ = job.join();
+        }
+        if let Err(e) = self.write_status() {
+            eprintln!("[WARN] Cannot write status file: {}", e);
+        }
+        println!("{}", self.summary());
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "[SUMMARY] files watched: {} (native: {}, polled: {}), events seen: {}, ignored: {}, modifications: {}, alerts raised: {} ({}), alerts suppressed: {}, exports: {}",
+            self.files.len(),
+            self.files.len() - self.polled.len(),
+            self.polled.len(),
+            self.stats.events,
+            self.stats.ignored,
+            self.stats.modified,
+            self.stats.alerts,
+            self.stats.alerts_by_severity,
+            self.stats.suppressed,
+            self.stats.exports
+        )
+    }
+}
+
+/// The key a path is tracked under: absolute, with `.`, `..` and symlinked
+/// directories resolved. A symlink in the last component is kept so a
+/// followed link can still be checked for retargeting. Paths that do not
+/// exist (yet) are normalized lexically below their canonical parent.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    let is_link = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink());
+    if !is_link {
+        if let Ok(canonical) = fs::canonicalize(path) {
+            return canonical;
+        }
+    }
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()).components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    match (normalized.parent().map(fs::canonicalize), normalized.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => normalized,
+    }
+}
+
+pub fn matches_any(patterns: &[glob::Pattern], path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    patterns.iter().any(|pattern| {
+        pattern.matches_path(path) || name.as_deref().is_some_and(|n| pattern.matches(n))
+    })
+}
+
+/// SIGINT/SIGTERM set `flag` for a graceful stop; a second signal exits immediately.
+pub fn install_signal_handlers(flag: &Arc<AtomicBool>) -> io::Result<()> {
+    for sig in signal_hook::consts::TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, Arc::clone(flag))?;
+        signal_hook::flag::register(*sig, Arc::clone(flag))?;
+    }
+    Ok(())
+}
+
+/// SIGUSR1 pauses alerting, SIGUSR2 resumes it.
+#[cfg(unix)]
+pub fn install_pause_handlers(pause: &Arc<AtomicBool>, resume: &Arc<AtomicBool>) -> io::Result<()> {
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(pause))?;
+    signal_hook::flag::register(signal_hook::consts::SIGUSR2, Arc::clone(resume))?;
+    Ok(())
+}
+
+/// SIGHUP re-arms every exhausted `Count` watch.
+#[cfg(unix)]
+pub fn install_rearm_handler(flag: &Arc<AtomicBool>) -> io::Result<()> {
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(flag))?;
+    Ok(())
+}
+
+pub fn take_snapshot(path: &Path, remediation: &Remediation) -> io::Result<Snapshot> {
+    let meta = fs::metadata(path)?;
+    let spill_dir;
+    let snapshot_dir = match &remediation.snapshot_dir {
+        Some(dir) => Some(dir.as_path()),
+        None if meta.len() > remediation.memory_limit => {
+            spill_dir = remediation.quarantine_dir.join(".snapshots");
+            private_dir(&spill_dir)?;
+            Some(spill_dir.as_path())
+        }
+        None => None,
+    };
+    let data = match snapshot_dir {
+        Some(dir) => {
+            let key = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let target = dir.join(format!("{}-{}", &key[..16], name));
+            // Not fs::copy: that would carry over a world-readable mode.
+            let _ = fs::remove_file(&target);
+            io::copy(&mut fs::File::open(path)?, &mut private_file(&target)?)?;
+            SnapshotData::Disk(target)
+        }
+        None => SnapshotData::Memory(fs::read(path)?),
+    };
+    Ok(Snapshot {
+        data,
+        permissions: meta.permissions(),
+        #[cfg(unix)]
+        owner: (std::os::unix::fs::MetadataExt::uid(&meta), std::os::unix::fs::MetadataExt::gid(&meta)),
+    })
+}
+
+fn stat(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(path).ok().map(|meta| (meta.len(), meta.modified().ok()))
+}
+
+/// Parses `PATH[:COUNT|:forever-all-day|:DURATION]` where DURATION is a
+/// number followed by `s`, `m` or `h`.
+pub fn parse_liner_street(arg: &str) -> Result<(PathBuf, LineWatch), String> {
+    let (path, value) = match arg.rsplit_once(':') {
+        Some((path, value)) => (path, value),
+        None => (arg, "1"),
+    };
+    if path.is_empty() {
+        return Err(format!("Malformed liner-street spec {:?}: missing path", arg));
+    }
+    let watch = parse_line_watch(value).map_err(|e| format!("Malformed liner-street spec {:?}: {}", arg, e))?;
+    Ok((PathBuf::from(path), watch))
+}
+
+/// Parses the part after the colon: a count, `forever-all-day` or a duration.
+pub fn parse_line_watch(value: &str) -> Result<LineWatch, String> {
+    match value {
+        "" => Err("missing value after ':'".to_string()),
+        "forever-all-day" => Ok(LineWatch::Forever),
+        v if v.bytes().all(|b| b.is_ascii_digit()) => match v.parse::<usize>() {
+            Ok(0) | Err(_) => Err("count must be at least 1".to_string()),
+            Ok(count) => Ok(LineWatch::Count(count)),
+        },
+        v => parse_duration(v).map(LineWatch::Duration),
+    }
+}
+
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", value))?;
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return Err(format!("invalid duration unit in {:?} (use s, m or h)", value)),
+    };
+    let secs = number
+        .checked_mul(scale)
+        .ok_or_else(|| format!("duration {:?} is too long", value))?;
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine_restores_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("protected.conf");
+        fs::write(&file, "original\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), Some(dir.path().join("snapshots")))
+            .unwrap();
+        wm.add_file(file.clone(), None).unwrap();
+
+        fs::write(&file, "tampered\n").unwrap();
+        wm.update_if_needed(&file);
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "original\n");
+        let quarantined: Vec<_> = fs::read_dir(dir.path().join("quarantine"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(fs::read_to_string(&quarantined[0]).unwrap(), "tampered\n");
+
+        // The restore itself must not be seen as another modification.
+        assert!(!wm.files.get_mut(&file).unwrap().update());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_keeps_mode_and_owner_and_snapshots_are_private() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("protected.conf");
+        fs::write(&file, "original\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        let owner = fs::metadata(&file).map(|meta| (meta.uid(), meta.gid())).unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), Some(dir.path().join("snapshots")))
+            .unwrap();
+        wm.add_file(file.clone(), None).unwrap();
+        for snapshot in fs::read_dir(dir.path().join("snapshots")).unwrap() {
+            assert_eq!(snapshot.unwrap().metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::write(&file, "tampered\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o666)).unwrap();
+        wm.update_if_needed(&file);
+
+        let meta = fs::metadata(&file).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "original\n");
+        assert_eq!(meta.permissions().mode() & 0o7777, 0o640);
+        assert_eq!((meta.uid(), meta.gid()), owner);
+    }
+
+    #[test]
+    fn test_large_snapshots_go_to_disk_without_a_snapshot_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small.conf");
+        let large = dir.path().join("large.bin");
+        fs::write(&small, "tiny").unwrap();
+        fs::write(&large, "much larger than the limit").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.remediation.as_mut().unwrap().memory_limit = 8;
+        wm.add_file(small.clone(), None).unwrap();
+        wm.add_file(large.clone(), None).unwrap();
+        assert!(matches!(wm.files[&small].snapshot.as_ref().unwrap().data, SnapshotData::Memory(_)));
+        assert!(matches!(wm.files[&large].snapshot.as_ref().unwrap().data, SnapshotData::Disk(_)));
+        assert_eq!(fs::read_dir(dir.path().join("quarantine/.snapshots")).unwrap().count(), 1);
+
+        fs::write(&large, "tampered").unwrap();
+        wm.update_if_needed(&large);
+        assert_eq!(fs::read_to_string(&large).unwrap(), "much larger than the limit");
+    }
+
+    #[test]
+    fn test_warning_change_is_alerted_but_not_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        let shadow = dir.path().join("shadow");
+        fs::write(&log, "started\n").unwrap();
+        fs::write(&shadow, "root:!:19000\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.add_file_as(log.clone(), None, Severity::Warning).unwrap();
+        wm.add_file(shadow.clone(), None).unwrap();
+
+        fs::write(&log, "started\nrotated\n").unwrap();
+        wm.update_if_needed(&log);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "started\nrotated\n");
+        assert_eq!(wm.stats.alerts_by_severity.warning, 1);
+
+        fs::write(&shadow, "root::19000\n").unwrap();
+        wm.update_if_needed(&shadow);
+        assert_eq!(fs::read_to_string(&shadow).unwrap(), "root:!:19000\n");
+
+        assert_eq!(
+            wm.stats.alerts_by_severity,
+            SeverityCounts { critical: 1, warning: 1, info: 0 }
+        );
+        assert!(wm.summary().contains("alerts raised: 2 (critical: 1, warning: 1, info: 0)"));
+
+        // Lowering the threshold brings warning-level files under the tamper action.
+        wm.tamper_threshold = Severity::Warning;
+        fs::write(&log, "tampered\n").unwrap();
+        wm.update_if_needed(&log);
+        assert_eq!(fs::read_to_string(&log).unwrap(), "started\n");
+    }
+
+    #[test]
+    fn test_alert_flood_is_rate_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("busy.conf");
+        fs::write(&file, "0\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.add_file(file.clone(), None).unwrap();
+        let tamper_checks = Arc::new(std::sync::Mutex::new(0));
+        let reported = Arc::new(std::sync::Mutex::new(0));
+        let (checks, shown) = (Arc::clone(&tamper_checks), Arc::clone(&reported));
+        wm.on_any(move |_, event| {
+            *checks.lock().unwrap() += 1;
+            if !event.suppressed {
+                *shown.lock().unwrap() += 1;
+            }
+        });
+
+        for _ in 0..100 {
+            let event = wm.change_event(&file, ChangeKind::Modified, None, Vec::new());
+            wm.dispatch(&event);
+        }
+
+        assert_eq!(*tamper_checks.lock().unwrap(), 100);
+        assert_eq!(*reported.lock().unwrap(), DEFAULT_ALERT_RATE as usize);
+        assert_eq!(wm.stats.alerts, 5);
+        assert_eq!(wm.stats.suppressed, 95);
+        assert_eq!(wm.files[&file].stats.suppressed, 95);
+        assert_eq!(wm.report_suppressed(Instant::now()), 0);
+        assert_eq!(wm.report_suppressed(Instant::now() + Duration::from_secs(12)), 1);
+        assert_eq!(wm.report_suppressed(Instant::now() + Duration::from_secs(60)), 0);
+    }
+
+    #[test]
+    fn test_kdv_baseline_downgrades_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        let scratch = dir.path().join("scratch.conf");
+        fs::write(&file, "approved\n").unwrap();
+        fs::write(&scratch, "x\n").unwrap();
+        let manifest = dir.path().join("baseline.sha256");
+        fs::write(
+            &manifest,
+            format!("{}  {}\n", hex::encode(KdvVerifier::compute_hash(b"approved\n")), file.display()),
+        )
+        .unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.baseline = Some(KdvVerifier::from_manifest(&manifest).unwrap());
+        wm.unbaselined = UnbaselinedPolicy::Ignore;
+        wm.add_file(file.clone(), None).unwrap();
+        wm.add_file(scratch.clone(), None).unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        wm.on_any(move |_, event| log.lock().unwrap().push((event.severity, event.baseline)));
+        let quarantined = || fs::read_dir(dir.path().join("quarantine")).unwrap().count();
+
+        // Diverging from the baseline alerts and gets the tamper action.
+        fs::write(&file, "evil\n").unwrap();
+        wm.update_if_needed(&file);
+        assert_eq!(quarantined(), 1);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "approved\n");
+
+        // An operator edits with the tamper action off, then puts the
+        // approved content back: that change is informational only.
+        wm.tamper_action = TamperAction::Log;
+        fs::write(&file, "edited\n").unwrap();
+        wm.update_if_needed(&file);
+        wm.tamper_action = TamperAction::Quarantine;
+        wm.tamper_threshold = Severity::Info;
+        fs::write(&file, "approved\n").unwrap();
+        wm.update_if_needed(&file);
+        assert_eq!(quarantined(), 1);
+
+        // Files outside the baseline are ignored under this policy.
+        fs::write(&scratch, "y\n").unwrap();
+        wm.update_if_needed(&scratch);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (Severity::Critical, Some(BaselineCheck::Diverges)),
+                (Severity::Critical, Some(BaselineCheck::Diverges)),
+                (Severity::Info, Some(BaselineCheck::Matches)),
+            ]
+        );
+        assert_eq!(wm.stats.alerts_by_severity.info, 1);
+    }
+
+    #[test]
+    fn test_split_severity_suffix() {
+        assert_eq!(split_severity("/etc/shadow=critical"), ("/etc/shadow", Some(Severity::Critical)));
+        assert_eq!(split_severity("/var/log/*.log=Warning"), ("/var/log/*.log", Some(Severity::Warning)));
+        assert_eq!(split_severity("/srv/a=b.conf"), ("/srv/a=b.conf", None));
+        assert_eq!(split_severity("=info"), ("=info", None));
+    }
+
+    #[test]
+    fn test_debounce_coalesces_burst() {
+        use notify::event::{DataChange, MetadataKind, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "a=1\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.set_debounce(Duration::from_millis(200));
+        wm.add_file(file.clone(), None).unwrap();
+
+        fs::write(&file, "a=2\n").unwrap();
+        let start = Instant::now();
+        let burst = [
+            EventKind::Modify(ModifyKind::Data(DataChange::Size)),
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+        ];
+        for (i, kind) in burst.into_iter().enumerate() {
+            let at = start + Duration::from_millis(i as u64 * 10);
+            wm.queue_event(Event::new(kind).add_path(file.clone()), at);
+        }
+
+        wm.process_pending(start + Duration::from_millis(100));
+        assert_eq!(wm.stats.modified, 0);
+
+        wm.process_pending(start + Duration::from_millis(300));
+        wm.process_pending(start + Duration::from_millis(600));
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.exports, 1);
+    }
+
+    #[test]
+    fn test_new_file_in_watched_dir_is_tracked() {
+        use notify::event::CreateKind;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("existing.conf"), "x=1\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.add_exclude("*.swp").unwrap();
+        wm.add_path(dir.path()).unwrap();
+        assert_eq!(wm.files.len(), 1);
+
+        let dropped = dir.path().join("evil.conf");
+        let swap = dir.path().join(".evil.conf.swp");
+        fs::write(&dropped, "x=2\n").unwrap();
+        fs::write(&swap, "junk").unwrap();
+        let now = Instant::now();
+        for path in [&dropped, &swap] {
+            wm.queue_event(Event::new(EventKind::Create(CreateKind::File)).add_path(path.clone()), now);
+        }
+        wm.process_pending(now);
+
+        assert!(wm.files.contains_key(&dropped));
+        assert!(!wm.files.contains_key(&swap));
+        assert!(wm.export_paths().contains(&dropped));
+        assert_eq!(wm.stats.exports, 1);
+    }
+
+    #[test]
+    fn test_recursive_watch_catches_new_nested_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::write(root.join("top.conf"), "x=1\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = root.join("out.pself");
+        wm.set_debounce(Duration::ZERO);
+        wm.add_path(&root).unwrap();
+        assert!(wm.native_roots.contains(&root));
+
+        let pump = |wm: &mut WatchManager, what: &str, done: &dyn Fn(&WatchManager) -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !done(wm) {
+                assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+                let events: Vec<Event> = wm.rx.recv_timeout(Duration::from_millis(50)).into_iter().collect();
+                wm.tick(events, Instant::now());
+            }
+        };
+
+        let deep = root.join("a").join("b").join("c").join("deep.conf");
+        fs::create_dir_all(deep.parent().unwrap()).unwrap();
+        fs::write(&deep, "x=1\n").unwrap();
+        pump(&mut wm, "new file three levels down", &|wm| wm.files.contains_key(&deep));
+        assert!(wm.dirs.contains(deep.parent().unwrap()));
+
+        fs::write(&deep, "x=2\n").unwrap();
+        pump(&mut wm, "change three levels down", &|wm| wm.stats.modified == 1);
+    }
+
+    #[test]
+    fn test_rebaseline_pause_absorbs_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("nginx.conf");
+        fs::write(&file, "worker_processes 1;\n").unwrap();
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.add_file(file.clone(), None).unwrap();
+
+        assert!(wm.pause("test"));
+        assert!(!wm.pause("test"));
+        fs::write(&file, "worker_processes 4;\n").unwrap();
+        wm.update_if_needed(&file);
+        assert_eq!(wm.stats.alerts, 0);
+        assert_eq!(wm.status_json()["paused"]["absorbed"], 1);
+
+        // After the resume the upgraded file is the baseline a tamper is restored to.
+        assert!(wm.resume("test"));
+        assert!(wm.status_json()["paused"].is_null());
+        wm.update_if_needed(&file);
+        assert_eq!(wm.stats.alerts, 0);
+        fs::write(&file, "load_module evil.so;\n").unwrap();
+        wm.update_if_needed(&file);
+        assert_eq!(wm.stats.alerts, 1);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "worker_processes 4;\n");
+    }
+
+    #[test]
+    fn test_freeze_pause_holds_events_until_resume() {
+        use notify::event::{DataChange, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "a=1\n").unwrap();
+        let mut wm = WatchManager::with_backend(Box::new(NullBackend), channel().1);
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.paused_mode = PausedMode::Freeze;
+        wm.max_pause = Some(Duration::from_secs(600));
+        wm.add_file(file.clone(), None).unwrap();
+
+        let start = Instant::now();
+        wm.pause_requested.store(true, Ordering::SeqCst);
+        wm.tick([], start);
+        assert!(wm.paused.is_some());
+
+        fs::write(&file, "a=2\n").unwrap();
+        let modify = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(file.clone());
+        wm.tick([modify], start);
+        wm.tick([], start + Duration::from_secs(5));
+        assert_eq!(wm.stats.modified, 0);
+        assert_eq!(wm.pending.len(), 1);
+        assert_eq!(wm.next_wakeup(start), IDLE_WAKEUP);
+
+        // --max-pause resumes on its own, and the held change is evaluated
+        // against the pre-pause baseline.
+        wm.tick([], start + Duration::from_secs(601));
+        assert!(wm.paused.is_none());
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.alerts, 1);
+    }
+
+    /// Records watches and rejects recursive ones, like a backend without
+    /// recursive support.
+    struct FlatBackend(Arc<std::sync::Mutex<Vec<PathBuf>>>);
+
+    impl WatchBackend for FlatBackend {
+        fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()> {
+            if mode == RecursiveMode::Recursive {
+                return Err(notify::Error::generic("recursive watches unsupported"));
+            }
+            self.0.lock().unwrap().push(path.to_path_buf());
+            Ok(())
+        }
+
+        fn unwatch(&mut self, _path: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_depth_limit_and_flat_backend_fallback() {
+        use notify::event::CreateKind;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::create_dir(root.join("skip")).unwrap();
+        for file in ["l1.conf", "a/l2.conf", "a/b/l3.conf", "skip/x.conf"] {
+            fs::write(root.join(file), "x=1\n").unwrap();
+        }
+
+        let watched = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut wm = WatchManager::with_backend(Box::new(FlatBackend(Arc::clone(&watched))), channel().1);
+        wm.add_exclude("skip").unwrap();
+        wm.add_path(&root).unwrap();
+        assert!(wm.native_roots.is_empty());
+        assert_eq!(wm.files.len(), 3);
+        let watched_now = watched.lock().unwrap().clone();
+        for path in [root.clone(), root.join("a/b"), root.join("a/b/l3.conf")] {
+            assert!(watched_now.contains(&path), "{} not watched", path.display());
+        }
+
+        let mut wm = WatchManager::with_backend(Box::new(FlatBackend(Arc::clone(&watched))), channel().1);
+        wm.add_exclude("skip").unwrap();
+        wm.max_depth = Some(1);
+        wm.add_path(&root).unwrap();
+        assert_eq!(wm.files.len(), 2);
+        assert_eq!(wm.dirs, HashSet::from([root.clone(), root.join("a")]));
+
+        // Paths below the depth limit, in excluded directories or matching an
+        // ignore never reach the debounce queue.
+        let now = Instant::now();
+        for path in ["a/b/new.conf", "skip/y.conf", "a/.l2.conf.swp"] {
+            wm.queue_event(Event::new(EventKind::Create(CreateKind::File)).add_path(root.join(path)), now);
+        }
+        assert!(wm.pending.is_empty());
+        assert_eq!(wm.stats.ignored, 1);
+    }
+
+    #[test]
+    fn test_vim_save_yields_one_modification() {
+        use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "a=1\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.set_debounce(Duration::from_millis(100));
+        wm.add_path(dir.path()).unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        wm.on_any(move |_, event| log.lock().unwrap().push((event.kind, event.path.clone())));
+
+        // What vim does on :w with the default backup settings.
+        let probe = dir.path().join("4913");
+        let swap = dir.path().join(".app.conf.swp");
+        let backup = dir.path().join("app.conf~");
+        let start = Instant::now();
+        let mut events = Vec::new();
+        fs::write(&swap, "swap").unwrap();
+        events.push((EventKind::Create(CreateKind::File), swap.clone()));
+        fs::write(&probe, "").unwrap();
+        events.push((EventKind::Create(CreateKind::File), probe.clone()));
+        fs::remove_file(&probe).unwrap();
+        events.push((EventKind::Remove(RemoveKind::File), probe.clone()));
+        fs::rename(&file, &backup).unwrap();
+        events.push((EventKind::Modify(ModifyKind::Name(RenameMode::From)), file.clone()));
+        events.push((EventKind::Modify(ModifyKind::Name(RenameMode::To)), backup.clone()));
+        fs::write(&file, "a=2\n").unwrap();
+        events.push((EventKind::Create(CreateKind::File), file.clone()));
+        events.push((EventKind::Modify(ModifyKind::Data(DataChange::Content)), file.clone()));
+        fs::remove_file(&backup).unwrap();
+        events.push((EventKind::Remove(RemoveKind::File), backup.clone()));
+        fs::write(&swap, "swap2").unwrap();
+        events.push((EventKind::Modify(ModifyKind::Data(DataChange::Content)), swap.clone()));
+
+        for (i, (kind, path)) in events.into_iter().enumerate() {
+            wm.queue_event(Event::new(kind).add_path(path), start + Duration::from_millis(i as u64));
+        }
+        wm.process_pending(start + Duration::from_millis(200));
+
+        assert_eq!(*seen.lock().unwrap(), [(ChangeKind::Modified, file.clone())]);
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.exports, 1);
+        assert_eq!(wm.stats.ignored, 6);
+        assert_eq!(wm.files.len(), 1);
+
+        // Without the defaults the swap file shows up as a new file.
+        let mut wm = WatchManager::new();
+        wm.ignores.clear();
+        wm.add_path(dir.path()).unwrap();
+        assert!(wm.files.contains_key(&swap));
+    }
+
+    #[test]
+    fn test_binary_change_is_attributed_to_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.bin");
+        let mut blob = b"\x7fELF\x02\x01\x01\x00".to_vec();
+        blob.extend((0..200_000u32).map(|i| (i % 251) as u8));
+        fs::write(&file, &blob).unwrap();
+
+        let mut entry = FileEntry::from_path_with(&file, None).unwrap();
+        assert!(matches!(entry.fingerprint, Fingerprint::Chunks { .. }));
+
+        blob[100_000] ^= 0xff;
+        fs::write(&file, &blob).unwrap();
+
+        assert!(entry.update());
+        match entry.last_diff.as_ref().unwrap() {
+            FingerprintDiff::Chunks(ranges) => {
+                assert_eq!(ranges.len(), 1);
+                assert_eq!(ranges[0], CHUNK_SIZE..2 * CHUNK_SIZE);
+            }
+            other => panic!("unexpected diff {:?}", other),
+        }
+        assert!(!entry.update());
+    }
+
+    #[test]
+    fn test_missing_path_is_an_error_not_a_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.conf");
+        fs::write(&good, "ok").unwrap();
+        let missing = dir.path().join("missing.conf");
+
+        let mut wm = WatchManager::new();
+        assert!(matches!(wm.add_path(&missing), Err(WatcherError::NotFound(_))));
+        assert!(matches!(wm.add_file(missing, None), Err(WatcherError::NotFound(_))));
+        assert!(wm.add_path(&good).is_ok());
+        assert_eq!(wm.files.len(), 1);
+    }
+
+    #[test]
+    fn test_count_watch_lifecycle_and_rearm() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("limits.conf");
+        fs::write(&file, "v0\n").unwrap();
+
+        let mut entry = FileEntry::from_path_with(&file, None).unwrap();
+        entry.set_liner_watch(LineWatch::Count(2));
+
+        fs::write(&file, "v1\n").unwrap();
+        assert!(entry.update());
+        assert!(!entry.is_exhausted());
+        // The baseline moved, so the same content is not reported again.
+        assert!(!entry.update());
+
+        fs::write(&file, "v2\n").unwrap();
+        assert!(entry.update());
+        assert!(entry.is_exhausted());
+
+        fs::write(&file, "v3\n").unwrap();
+        assert!(!entry.update());
+
+        entry.rearm();
+        assert!(matches!(entry.liner_watch, Some(LineWatch::Count(2))));
+        fs::write(&file, "v4\n").unwrap();
+        assert!(entry.update());
+        assert_eq!(entry.last_diff, Some(FingerprintDiff::Lines(vec![1])));
+    }
+
+    #[test]
+    fn test_rearm_after_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("limits.conf");
+        fs::write(&file, "v0\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.rearm_after = Some(Duration::from_secs(60));
+        wm.add_file(file.clone(), Some(LineWatch::Count(1))).unwrap();
+
+        fs::write(&file, "v1\n").unwrap();
+        wm.update_if_needed(&file);
+        assert!(wm.files[&file].is_exhausted());
+
+        wm.rearm_due(Instant::now());
+        assert!(wm.files[&file].is_exhausted());
+        wm.rearm_due(Instant::now() + Duration::from_secs(61));
+        assert!(!wm.files[&file].is_exhausted());
+    }
+
+    #[test]
+    fn test_handlers_run_in_order_and_survive_panics() {
+        use std::sync::Mutex;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        let other = dir.path().join("other.conf");
+        fs::write(&file, "a=1\n").unwrap();
+        fs::write(&other, "b=1\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.add_file(file.clone(), None).unwrap();
+        wm.add_file(other.clone(), None).unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        wm.on_change(file.clone(), move |wm, event| {
+            // Handlers may call back into the manager.
+            assert!(wm.files.contains_key(&event.path));
+            log.lock().unwrap().push(format!("path:{}", event.kind.as_str()));
+        });
+        wm.on_any(|_, _| panic!("broken handler"));
+        let log = Arc::clone(&seen);
+        wm.on_any(move |_, event| {
+            let name = event.path.file_name().unwrap().to_string_lossy().into_owned();
+            log.lock().unwrap().push(format!("any:{}", name));
+        });
+
+        fs::write(&file, "a=2\n").unwrap();
+        wm.update_if_needed(&file);
+        fs::write(&other, "b=2\n").unwrap();
+        wm.update_if_needed(&other);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["path:modified", "any:app.conf", "any:other.conf"]
+        );
+        // The default alert handler still ran for both changes.
+        assert_eq!(wm.stats.alerts, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_chmod_raises_metadata_alert() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::Mutex;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("sshd_config");
+        fs::write(&file, "PermitRootLogin no\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.check_metadata = true;
+        wm.add_file(file.clone(), None).unwrap();
+
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&alerts);
+        wm.on_any(move |wm, event| log.lock().unwrap().push((event.kind, wm.describe(event))));
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o777)).unwrap();
+        wm.update_if_needed(&file);
+
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, ChangeKind::Metadata);
+        assert!(alerts[0].1.contains("mode 0644 -> 0777"), "{}", alerts[0].1);
+        assert_eq!(wm.stats.modified, 0);
+        assert_eq!(wm.stats.alerts, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_follow_and_no_follow() {
+        use std::os::unix::fs::symlink;
+        use std::sync::Mutex;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        let a = tree.join("a.conf");
+        let b = tree.join("b.conf");
+        fs::write(&a, "a\n").unwrap();
+        fs::write(&b, "b\n").unwrap();
+        let outside = dir.path().join("outside.conf");
+        fs::write(&outside, "x\n").unwrap();
+        let link = tree.join("current.conf");
+        symlink(&a, &link).unwrap();
+        symlink(&outside, tree.join("escape.conf")).unwrap();
+
+        let mut strict = WatchManager::new();
+        assert!(matches!(strict.add_file(link.clone(), None), Err(WatcherError::Symlink(_))));
+        strict.add_path(&tree).unwrap();
+        assert!(!strict.files.contains_key(&link));
+        assert_eq!(strict.files.len(), 2);
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.follow_symlinks = true;
+        wm.add_path(&tree).unwrap();
+        assert!(wm.files.contains_key(&link));
+        assert!(!wm.files.contains_key(&tree.join("escape.conf")));
+
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&alerts);
+        wm.on_any(move |wm, event| log.lock().unwrap().push((event.kind, wm.describe(event))));
+
+        fs::remove_file(&link).unwrap();
+        symlink(&b, &link).unwrap();
+        wm.handle_path(&link);
+
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, ChangeKind::Retargeted);
+        assert_eq!(alerts[0].0.as_str().to_uppercase(), "RETARGETED");
+        assert!(alerts[0].1.contains("b.conf"), "{}", alerts[0].1);
+        assert_eq!(wm.links[&link], fs::canonicalize(&b).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_spellings_of_one_file_share_an_entry() {
+        use notify::event::{DataChange, ModifyKind};
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir(&src).unwrap();
+        let file = src.join("main.rs");
+        fs::write(&file, "fn main() {}\n").unwrap();
+        symlink(&src, dir.path().join("alias")).unwrap();
+        let spellings = [
+            file.clone(),
+            src.join(".").join("main.rs"),
+            dir.path().join("alias").join("..").join("src").join("main.rs"),
+            dir.path().join("alias").join("main.rs"),
+        ];
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        for path in &spellings {
+            wm.add_file(path.clone(), None).unwrap();
+        }
+        assert_eq!(wm.files.len(), 1);
+        let entry = &wm.files[&fs::canonicalize(&file).unwrap()];
+        assert_eq!(entry.presented, file);
+
+        fs::write(&file, "fn main() { todo!() }\n").unwrap();
+        let now = Instant::now();
+        for path in &spellings {
+            let modify = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(path.clone());
+            wm.queue_event(modify, now);
+        }
+        assert_eq!(wm.pending.len(), 1);
+        wm.process_pending(now + Duration::from_secs(1));
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.alerts, 1);
+
+        assert!(wm.remove_file(&spellings[3]));
+        assert!(wm.files.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hfs_violation_triggers_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        let marker = dir.path().join("reaction");
+        fs::write(&file, "original\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.summary_interval = Some(Duration::from_secs(3600));
+        wm.add_file(file.clone(), None).unwrap();
+        let run = format!("echo \"$SERIALK_HFS_KIND $SERIALK_HFS_PID $SERIALK_HFS_PATTERN\" > '{}'", marker.display());
+        wm.violation_response = ViolationResponse {
+            verify: true,
+            export: true,
+            run: Some(run),
+        };
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        wm.violations = Some(rx);
+
+        // No file event is queued: only the violation makes the watcher look.
+        fs::write(&file, "tampered\n").unwrap();
+        wm.tick(Vec::new(), Instant::now());
+        assert_eq!(wm.stats.modified, 0);
+
+        let frida = crate::hfs::ProcessInfo {
+            pid: 4242,
+            command: "frida-server".to_string(),
+            cmdline: "frida-server -l 0.0.0.0".to_string(),
+            uid: Some(0),
+            user: Some("root".to_string()),
+            start_time: None,
+            session: None,
+            path: None,
+        };
+        let violation = Violation::new(crate::hfs::ViolationKind::ForbiddenProcess, &frida, Some("frida"), 200);
+        tx.send(violation).unwrap();
+        wm.tick(Vec::new(), Instant::now());
+
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.alerts, 1);
+        // Exported at once despite the hour-long summary interval.
+        assert_eq!(wm.stats.exports, 1);
+        for job in wm.reaction_jobs.drain(..) {
+            job.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&marker).unwrap(), "process 4242 frida\n");
+
+        drop(tx);
+        wm.tick(Vec::new(), Instant::now());
+        assert!(wm.violations.is_none());
+    }
+
+    #[test]
+    fn test_summary_interval_aggregates_burst() {
+        use notify::event::{CreateKind, DataChange, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let hot = dir.path().join("hot.conf");
+        let warm = dir.path().join("warm.conf");
+        fs::write(&hot, "0\n").unwrap();
+        fs::write(&warm, "0\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.summary_interval = Some(Duration::from_secs(60));
+        wm.add_file(hot.clone(), None).unwrap();
+        wm.add_file(warm.clone(), None).unwrap();
+        let start = Instant::now();
+        wm.last_summary = start;
+
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        for round in 1..=3 {
+            fs::write(&hot, format!("{}\n", round)).unwrap();
+            wm.queue_event(Event::new(modify).add_path(hot.clone()), start);
+            if round == 1 {
+                fs::write(&warm, "1\n").unwrap();
+                wm.queue_event(Event::new(modify).add_path(warm.clone()), start);
+            }
+            wm.process_pending(start);
+        }
+        wm.queue_event(Event::new(EventKind::Create(CreateKind::File)).add_path(dir.path().join("x")), start);
+
+        // Exports are batched until the heartbeat.
+        assert_eq!(wm.stats.exports, 0);
+        assert!(wm.take_summary_if_due(start + Duration::from_secs(30)).is_none());
+
+        let summary = wm.take_summary_if_due(start + Duration::from_secs(60)).unwrap();
+        assert_eq!(summary.events_by_kind["modify"], 4);
+        assert_eq!(summary.events_by_kind["create"], 1);
+        assert_eq!(summary.alerts, 4);
+        assert_eq!(summary.exports, 1);
+        assert!(summary.last_export.is_some());
+        assert_eq!(summary.top_changed(1), vec![(hot.clone(), 3)]);
+        assert_eq!(summary.top_changed(5), vec![(hot, 3), (warm, 1)]);
+
+        // The next interval starts empty but remembers the last export.
+        assert_eq!(wm.interval.alerts, 0);
+        assert!(wm.interval.changes.is_empty());
+        assert_eq!(wm.interval.last_export, summary.last_export);
+    }
+
+    struct LimitedBackend {
+        at_limit: Arc<AtomicBool>,
+    }
+
+    impl WatchBackend for LimitedBackend {
+        fn watch(&mut self, _path: &Path, _mode: RecursiveMode) -> notify::Result<()> {
+            if self.at_limit.load(Ordering::SeqCst) {
+                Err(notify::Error::new(notify::ErrorKind::MaxFilesWatch))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn unwatch(&mut self, _path: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_watch_limit_falls_back_to_polling() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("big-tree.conf");
+        fs::write(&file, "a=1\n").unwrap();
+
+        let at_limit = Arc::new(AtomicBool::new(true));
+        let backend = LimitedBackend { at_limit: Arc::clone(&at_limit) };
+        let mut wm = WatchManager::with_backend(Box::new(backend), channel().1);
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.poll_interval = Duration::from_secs(2);
+        let start = Instant::now();
+        wm.last_poll = start;
+
+        wm.add_file(file.clone(), None).unwrap();
+        assert!(wm.polled.contains(&file));
+        assert!(wm.summary().contains("(native: 0, polled: 1)"), "{}", wm.summary());
+
+        fs::write(&file, "a=2\n").unwrap();
+        wm.poll_due(start + Duration::from_secs(1));
+        assert_eq!(wm.stats.modified, 0);
+        wm.poll_due(start + Duration::from_secs(2));
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.stats.alerts, 1);
+
+        // Nothing changed: polling must not alert again.
+        wm.poll_due(start + Duration::from_secs(4));
+        assert_eq!(wm.stats.modified, 1);
+        assert!(wm.polled.contains(&file));
+
+        at_limit.store(false, Ordering::SeqCst);
+        wm.poll_due(start + Duration::from_secs(6));
+        assert!(wm.polled.is_empty());
+        assert!(wm.summary().contains("(native: 1, polled: 0)"), "{}", wm.summary());
+    }
+
+    #[test]
+    fn test_per_file_stats_survive_rebaseline() {
+        use notify::event::{DataChange, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("hosts");
+        let other = dir.path().join("quiet.conf");
+        fs::write(&file, "127.0.0.1 localhost\n").unwrap();
+        fs::write(&other, "q\n").unwrap();
+
+        let mut wm = WatchManager::new();
+        wm.enable_quarantine(dir.path().join("quarantine"), None).unwrap();
+        wm.output_path = dir.path().join("out.pself");
+        wm.add_file(file.clone(), Some(LineWatch::Count(3))).unwrap();
+        wm.add_file(other.clone(), None).unwrap();
+
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        for round in 0..2 {
+            fs::write(&file, format!("10.0.0.{} evil\n", round)).unwrap();
+            wm.queue_event(Event::new(modify).add_path(file.clone()), Instant::now());
+            wm.process_pending(Instant::now());
+        }
+
+        // Each tamper was quarantined and the baseline reloaded; counters stay.
+        let entry = &wm.files[&file];
+        assert_eq!(entry.stats.events, 2);
+        assert_eq!(entry.stats.alerts, 2);
+        assert!(entry.stats.last_event.is_some());
+        assert_eq!(entry.watch_state(), "count 1/3");
+        assert_eq!(wm.files[&other].stats.events, 0);
+
+        let status = wm.status_json();
+        let keys: Vec<&str> = status["entries"][0].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, [
+                "added_at",
+                "alerts",
+                "events",
+                "fingerprint",
+                "last_event",
+                "path",
+                "presented",
+                "severity",
+                "suppressed",
+                "watch"
+            ]);
+        let totals: Vec<&str> = status.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(
+            totals,
+            [
+                "alerts",
+                "alerts_by_severity",
+                "dirs",
+                "entries",
+                "events",
+                "exports",
+                "files",
+                "ignored",
+                "modified",
+                "paused",
+                "polled",
+                "suppressed"
+            ]
+        );
+
+        wm.status_file = Some(dir.path().join("status.json"));
+        wm.write_status().unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("status.json")).unwrap()).unwrap();
+        assert_eq!(written["entries"][0]["alerts"], 2);
+    }
+
+    #[test]
+    fn test_parallel_scan_registers_every_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut expected = Vec::new();
+        for i in 0..300 {
+            let path = dir.path().join(format!("f{:03}.conf", i));
+            // Uneven sizes so workers finish out of order.
+            fs::write(&path, "x\n".repeat((i * 37) % 500)).unwrap();
+            expected.push(path);
+        }
+        let first = expected[0].clone();
+
+        let mut wm = WatchManager::new();
+        let (mut jobs, failures) = wm.plan_scan(&[(dir.path().to_path_buf(), None)], &[(first.clone(), LineWatch::Count(2), None)]);
+        assert!(failures.is_empty());
+        assert_eq!(jobs.len(), 301);
+        jobs.reverse();
+        jobs.push(ScanJob {
+            path: dir.path().join("missing.conf"),
+            liner: None,
+            severity: Severity::Critical,
+            top_level: true,
+        });
+
+        wm.start_initial_scan(jobs, 8);
+        let mut failures = Vec::new();
+        while wm.scanning() {
+            failures.extend(wm.drain_scan(false));
+            std::thread::yield_now();
+        }
+
+        let mut tracked = wm.export_paths();
+        tracked.sort();
+        assert_eq!(tracked, expected);
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0], WatcherError::NotFound(_)));
+        // After reversal the liner entry came first, so it decides the mode.
+        assert!(matches!(wm.files[&first].liner_watch, Some(LineWatch::Count(2))));
+        assert!(wm.dirs.contains(dir.path()));
+    }
+
+    #[test]
+    fn test_parse_liner_street_units() {
+        let cases = [
+            ("/etc/a:45s", Duration::from_secs(45)),
+            ("/etc/a:30m", Duration::from_secs(30 * 60)),
+            ("/etc/a:2h", Duration::from_secs(2 * 3600)),
+        ];
+        for (spec, expected) in cases {
+            match parse_liner_street(spec).unwrap() {
+                (path, LineWatch::Duration(d)) => {
+                    assert_eq!(path, PathBuf::from("/etc/a"));
+                    assert_eq!(d, expected);
+                }
+                other => panic!("{} parsed as {:?}", spec, other),
+            }
+        }
+        assert!(matches!(parse_liner_street("/etc/a:3").unwrap().1, LineWatch::Count(3)));
+        assert!(matches!(parse_liner_street("/etc/a").unwrap().1, LineWatch::Count(1)));
+        assert!(matches!(parse_liner_street("/etc/a:forever-all-day").unwrap().1, LineWatch::Forever));
+
+        for bad in ["/etc/a:", ":3", "/etc/a:10x", "/etc/a:0", "/etc/a:m"] {
+            assert!(parse_liner_street(bad).is_err(), "{} should be rejected", bad);
+        }
+        assert_eq!(parse_duration("18446744073709551615s"), Ok(Duration::from_secs(u64::MAX)));
+        assert!(parse_duration("18446744073709551615m").unwrap_err().contains("too long"));
+        assert!(parse_duration("5124095576030432h").is_err());
+    }
+
+    #[test]
+    fn test_duration_watch_expires_to_normal_watching() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("deploy.conf");
+        fs::write(&file, "v0\n").unwrap();
+
+        let start = Instant::now();
+        let mut entry = FileEntry::from_path_with(&file, None).unwrap();
+        entry.set_liner_watch_at(LineWatch::Duration(Duration::from_secs(1800)), start);
+
+        assert!(!entry.expire_if_due(start + Duration::from_secs(1799)));
+        assert!(matches!(entry.liner_watch, Some(LineWatch::Duration(_))));
+
+        assert!(entry.expire_if_due(start + Duration::from_secs(1800)));
+        assert!(entry.liner_watch.is_none());
+        assert!(!entry.expire_if_due(start + Duration::from_secs(3600)));
+
+        fs::write(&file, "v1\n").unwrap();
+        assert!(entry.update());
+    }
+
+    #[test]
+    fn test_debounce_does_not_delay_removal() {
+        let window = Duration::from_millis(200);
+        let start = Instant::now();
+        let pending = PendingEvent {
+            first_seen: start,
+            last_seen: start + Duration::from_millis(190),
+            removal: true,
+        };
+        assert!(pending.is_due(start + window, window));
+    }
+
+    #[test]
+    fn test_protected_binary_change_is_critical() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("serialkiller");
+        fs::copy(std::env::current_exe().unwrap(), &exe).unwrap();
+        let mut wm = WatchManager::new();
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.default_severity = Severity::Info;
+        wm.protect_file(&exe).unwrap();
+        let exe = fs::canonicalize(&exe).unwrap();
+        assert_eq!(wm.files[&exe].severity, Severity::Critical);
+        let handlers = wm.handlers.len();
+        wm.protect_file(&exe).unwrap();
+        assert_eq!(wm.handlers.len(), handlers, "one own-file handler per path");
+        assert_eq!(wm.handlers.last().unwrap().path.as_ref(), Some(&exe));
+
+        fs::write(&exe, b"#!/bin/sh\nexit 0\n").unwrap();
+        wm.update_if_needed(&exe);
+        assert_eq!(wm.stats.alerts_by_severity.critical, 1);
+
+        // Protected files are reported without waiting for the debounce window.
+        wm.set_debounce(Duration::from_secs(60));
+        let now = Instant::now();
+        wm.pending.insert(
+            exe.clone(),
+            PendingEvent { first_seen: now, last_seen: now, removal: false },
+        );
+        assert_eq!(wm.next_wakeup(now), Duration::ZERO);
+    }
+
+    struct NullBackend;
+
+    impl WatchBackend for NullBackend {
+        fn watch(&mut self, _path: &Path, _mode: RecursiveMode) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn unwatch(&mut self, _path: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tick_drives_loop_without_backend() {
+        use notify::event::{DataChange, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "a=1\n").unwrap();
+        let mut wm = WatchManager::with_backend(Box::new(NullBackend), channel().1);
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.set_debounce(Duration::from_millis(300));
+        wm.add_file(file.clone(), None).unwrap();
+        assert_eq!(wm.next_wakeup(Instant::now()), IDLE_WAKEUP);
+
+        fs::write(&file, "a=2\n").unwrap();
+        let start = Instant::now();
+        let modify = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(file.clone());
+        wm.tick([modify], start);
+        assert_eq!(wm.stats.modified, 0);
+        assert_eq!(wm.next_wakeup(start), Duration::from_millis(300));
+
+        wm.tick([], start + Duration::from_millis(300));
+        assert_eq!(wm.stats.modified, 1);
+        assert_eq!(wm.next_wakeup(start), IDLE_WAKEUP);
+    }
+
+    #[test]
+    fn test_injected_event_is_detected_within_50ms() {
+        use notify::event::{DataChange, ModifyKind};
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("app.conf");
+        fs::write(&file, "a=1\n").unwrap();
+        let (tx, rx) = channel();
+        let mut wm = WatchManager::with_backend(Box::new(NullBackend), rx);
+        wm.tamper_action = TamperAction::Log;
+        wm.output_path = dir.path().join("out.pself");
+        wm.add_file(file.clone(), None).unwrap();
+        let (seen_tx, seen_rx) = channel();
+        wm.on_any(move |_, _| seen_tx.send(Instant::now()).unwrap());
+        let shutdown = Arc::clone(&wm.shutdown);
+        let worker = std::thread::spawn(move || wm.watch_loop());
+
+        // Let the loop settle into its blocking receive first.
+        std::thread::sleep(Duration::from_millis(200));
+        fs::write(&file, "a=2\n").unwrap();
+        let sent = Instant::now();
+        tx.send(Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(file.clone()))
+            .unwrap();
+        let detected = seen_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(detected - sent < Duration::from_millis(50), "took {:?}", detected - sent);
+
+        shutdown.store(true, Ordering::SeqCst);
+        worker.join().unwrap();
+    }
 }
@@ -0,0 +1,50 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Posts a JSON body to a plain `http://host[:port]/path` endpoint.
+/// TLS endpoints are expected to sit behind a local relay.
+pub fn post_json(url: &str, body: &str) -> io::Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported webhook URL: {}", url)))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+
+    let mut status = [0u8; 12];
+    stream.read_exact(&mut status)?;
+    let code = String::from_utf8_lossy(&status[9..12]).to_string();
+    if !code.starts_with('2') {
+        return Err(io::Error::other(format!("webhook {} answered {}", url, code)));
+    }
+    Ok(())
+}
+
+/// Delivers in the background so a slow endpoint never stalls the watch loop.
+pub fn spawn_post(url: String, body: String) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = post_json(&url, &body) {
+            eprintln!("[WARN] Webhook delivery to {} failed: {}", url, e);
+        }
+    })
+}
@@ -1,29 +1,92 @@
 mod format;
 mod runner;
 mod hfs;
+mod hfs_log;
+mod hash_pool;
 mod kdv;
+mod kdv_bench;
+mod kdv_daemon;
+mod kdv_fetch;
+mod kdv_merkle;
+mod kdv_sums;
 mod serialk;
 mod serialk_watcher;
+mod serialk_gate;
+mod serialk_config;
+mod serialk_webhook;
+mod serialk_control;
+mod serialk_scan;
+mod serialk_rate;
+mod serialk_audit;
+mod serialk_baseline;
+#[cfg(unix)]
+mod serialk_daemon;
 mod permission_manager;
+mod permission_audit;
+mod permission_lockout;
+mod permission_privilege;
+mod permission_prompt;
+mod permission_session;
+mod permission_capability;
+mod permission_group;
+mod permission_state;
+mod permission_users;
+#[cfg(test)]
+mod test_support;
 
-use crate::serialk_watcher::{WatchManager, parse_liner_street};
-use crate::permission_manager::PermissionManager;
+use crate::serialk_config::{LinerStreetEntry, WatcherConfig};
+#[cfg(unix)]
+use crate::serialk_watcher::{install_pause_handlers, install_rearm_handler};
+use crate::serialk_watcher::{
+    install_signal_handlers, parse_liner_street, split_severity, LargeFileMode, PausedMode, Severity, TamperAction,
+    UnbaselinedPolicy, WatchManager,
+};
+use crate::serialk_gate::{Challenge, HmacGate, RecoveryGate};
+use crate::permission_audit::{Auditor, Requester};
+use crate::permission_capability::Capability;
+use crate::permission_group::{RequiredGroup, SystemGroups};
+use crate::permission_privilege::Privilege;
+use crate::permission_lockout::{Lockout, LockoutPolicy, SystemClock};
+use crate::permission_manager::{AuthError, PermissionManager};
+use crate::permission_prompt::{Password, PasswordSource};
+use crate::permission_session::{SessionStatus, SessionToken, Sessions};
+use crate::permission_state::StateFile;
+use crate::hfs::{HfsAction, HfsConfig, HfsOptions, LiteralMatch, MapsScope, MatchTarget, PatternOptions, PatternRule};
 
-use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use clap::{Arg, Command as ClapCommand};
 use tokio::time::sleep;
 
 fn print_serialkiller_usage() {
     println!("Usage:");
-    println!("  serialkiller hfs <pattern1> [pattern2 ...]     # Process monitor");
-    println!("  serialkiller kdv <file1> [file2 ...]           # Integrity check");
-    println!("  serialkiller run <pself-file>                  # Run pself executable");
+    println!("  serialkiller hfs [--action log|kill|suspend] <regex[=action]> [...]     # Process monitor");
+    println!("  serialkiller kdv init -b baseline.json <file1> [file2 ...]             # Fingerprint files");
+    println!("  serialkiller kdv check -b baseline.json [file1 ...]                    # Integrity check");
+    println!("  serialkiller kdv init -b baseline.json --with-metadata <dir>           # Owner and mode too");
+    println!("  serialkiller kdv bench / kdv init --algo auto -b baseline.json <dir>   # Pick the fastest digest");
+    println!("  serialkiller kdv check -b baseline.json [--null] - < list              # Paths from stdin");
+    println!("  serialkiller kdv diff old.json new.json                                # Compare two baselines");
+    println!("  serialkiller kdv import sums.txt -b baseline.json                      # Baseline from sha256sum");
+    println!("  serialkiller kdv export -b baseline.json > sums.txt                    # sha256sum lines");
+    println!("  serialkiller kdv dupes [--min-size BYTES] [--json] <dir> [...]         # Identical files");
+    println!("  serialkiller kdv update -b baseline.json [--only GLOB] [--yes]         # Re-trust drift after review");
+    println!("  serialkiller kdv check -b baseline.json --watch --interval 300         # Re-check on a timer");
+    println!("  serialkiller kdv check -b baseline.json -j 2 --max-bandwidth 50000000  # Go easy on the host");
+    println!("  serialkiller kdv keygen kdv.key / kdv init --sign-key kdv.key ...      # Signed baseline");
+    println!("  serialkiller kdv check -b baseline.json --pubkey kdv.key.pub           # Refuse unsigned baselines");
+    println!("  serialkiller kdv check --embedded [--root DIR]                         # Baseline built into the binary");
+    println!("  serialkiller kdv prove -b baseline.json FILE > proof.json              # Proof of one entry");
+    println!("  serialkiller kdv verify-proof proof.json --merkle-root HEX             # Check it without the baseline");
+    println!("  serialkiller kdv seal FILE / kdv check --xattr FILE                    # Keep the digest with the file");
+    println!("  serialkiller kdv --pself app.pself [--export-baseline out.json]        # Verify pself sections");
+    println!("  serialkiller run <pself-file> --token-file FILE                        # Run pself executable");
 }
 
 #[tokio::main]
@@ -48,48 +111,1784 @@ async fn main() {
 }
 
 fn handle_serialk_watcher(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("ctl") => return handle_watcher_ctl(&args[1..]),
+        Some("status") => return handle_watcher_status(&args[1..]),
+        Some("audit-verify") => return handle_watcher_audit_verify(&args[1..]),
+        _ => {}
+    }
     let matches = ClapCommand::new("SerialK Watcher")
+        .no_binary_name(true)
         .version("1.0")
         .author("Zaman Huseyinli")
         .about("Universal memory & integrity monitor for executable systems")
         .arg(
-            Arg::new("include")
-                .short('i')
-                .long("include")
-                .value_name("PATH")
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .help("Load watch lists and actions from a TOML config; flags override it"),
+        )
+        .arg(
+            Arg::new("include")
+                .short('i')
+                .long("include")
+                .value_name("PATH[=SEVERITY]")
+                .num_args(1..)
+                .help("Include file or directory recursively, optionally at info, warning or critical"),
+        )
+        .arg(
+            Arg::new("include_from")
+                .long("include-from")
+                .value_name("FILE")
+                .action(clap::ArgAction::Append)
+                .help("Read includes (one path, glob or PATH:COUNT per line) from FILE, or stdin for -"),
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("GLOB")
+                .num_args(1..)
+                .help("Skip files matching GLOB (full path or file name)"),
+        )
+        .arg(
+            Arg::new("ignore")
+                .long("ignore")
+                .value_name("PATTERN")
+                .num_args(1..)
+                .help("Drop events for files matching PATTERN, in addition to the editor temp file defaults"),
+        )
+        .arg(
+            Arg::new("no_default_ignores")
+                .long("no-default-ignores")
+                .action(clap::ArgAction::SetTrue)
+                .help("Do not ignore *.swp, *.swo, *~, .#*, 4913, *.tmp and .DS_Store"),
+        )
+        .arg(
+            Arg::new("liner_street")
+                .long("liner-street")
+                .value_name("PATH:COUNT|forever-all-day|DURATION[=SEVERITY]")
+                .num_args(1..)
+                .help("Enable line-based watching"),
+        )
+        .arg(
+            Arg::new("debounce")
+                .long("debounce")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Wait until a path has been quiet for MS milliseconds before checking it"),
+        )
+        .arg(
+            Arg::new("recovery_key")
+                .long("recovery-key")
+                .value_name("FILE")
+                .conflicts_with("insecure_env_gate")
+                .help("Secret key (mode 0600) used to authorize changes via HMAC challenge"),
+        )
+        .arg(
+            Arg::new("recovery_response")
+                .long("recovery-response")
+                .value_name("FILE")
+                .requires("recovery_key")
+                .help("File the operator writes the challenge response to [default: <key>.response]"),
+        )
+        .arg(
+            Arg::new("recovery_timeout")
+                .long("recovery-timeout")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .help("How long to wait for a challenge response"),
+        )
+        .arg(
+            Arg::new("respond")
+                .long("respond")
+                .value_names(["PATH", "TS"])
+                .num_args(2)
+                .requires("recovery_key")
+                .help("Answer a recovery challenge by writing its response file, then exit"),
+        )
+        .arg(
+            Arg::new("insecure_env_gate")
+                .long("insecure-env-gate")
+                .action(clap::ArgAction::SetTrue)
+                .help("Accept SERIALK_KEY=AUTHORIZED as recovery (insecure, legacy)"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(clap::ArgAction::SetTrue)
+                .help("Abort if any path cannot be watched instead of skipping it"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print events as JSON lines"),
+        )
+        .arg(
+            Arg::new("check_metadata")
+                .long("check-metadata")
+                .action(clap::ArgAction::SetTrue)
+                .help("Also alert on permission, ownership and mtime changes"),
+        )
+        .arg(
+            Arg::new("follow_symlinks")
+                .long("follow-symlinks")
+                .action(clap::ArgAction::SetTrue)
+                .help("Watch symlink targets and alert when a link is retargeted (default: refuse symlinks)"),
+        )
+        .arg(
+            Arg::new("max_depth")
+                .long("max-depth")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Watch at most N directory levels below an included directory (0: its own files only)"),
+        )
+        .arg(
+            Arg::new("large_file_threshold")
+                .long("large-file-threshold")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64))
+                .help("Hash files above BYTES in streamed chunks instead of reading them into memory"),
+        )
+        .arg(
+            Arg::new("scan_jobs")
+                .long("scan-jobs")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("Fingerprint N files at once on startup [default: one per core, at most 4]"),
+        )
+        .arg(
+            Arg::new("scan_max_bandwidth")
+                .long("scan-max-bandwidth")
+                .value_name("BYTES_PER_SEC")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Read at most BYTES_PER_SEC while fingerprinting on startup"),
+        )
+        .arg(
+            Arg::new("scan_nice")
+                .long("scan-nice")
+                .value_name("N")
+                .value_parser(clap::value_parser!(i32).range(0..=19))
+                .help("Fingerprint on startup at niceness N (Linux)"),
+        )
+        .arg(
+            Arg::new("scan_ionice")
+                .long("scan-ionice")
+                .value_name("CLASS")
+                .value_parser(["idle", "best-effort"])
+                .help("Fingerprint on startup in the idle or lowest best-effort I/O class (Linux)"),
+        )
+        .arg(
+            Arg::new("large_file_mode")
+                .long("large-file-mode")
+                .value_name("MODE")
+                .value_parser(["hash", "stat"])
+                .requires("large_file_threshold")
+                .help("hash: re-hash large files on every event; stat: only when size or mtime changed [default: hash]"),
+        )
+        .arg(
+            Arg::new("paused_mode")
+                .long("paused-mode")
+                .value_name("MODE")
+                .value_parser(["rebaseline", "freeze"])
+                .help("While paused (SIGUSR1 / ctl pause): absorb changes into the baseline, or hold them until resume [default: rebaseline]"),
+        )
+        .arg(
+            Arg::new("max_pause")
+                .long("max-pause")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Resume automatically after a pause of SECONDS"),
+        )
+        .arg(
+            Arg::new("summary_interval")
+                .long("summary-interval")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Print a summary every SECONDS and batch pself exports into it"),
+        )
+        .arg(
+            Arg::new("poll_interval")
+                .long("poll-interval")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Re-hash files that exceed the OS watch limit every SECONDS [default: 5]"),
+        )
+        .arg(
+            Arg::new("rearm_after")
+                .long("rearm-after")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64))
+                .help("Re-arm exhausted --liner-street counts after SECONDS (SIGHUP re-arms immediately)"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("PATH")
+                .help("Where to export the pself [default: output.pself]"),
+        )
+        .arg(
+            Arg::new("tamper_action")
+                .long("tamper-action")
+                .value_name("ACTION")
+                .value_parser(["exit", "quarantine", "log"])
+                .help("What to do once tampering is confirmed"),
+        )
+        .arg(
+            Arg::new("tamper_threshold")
+                .long("tamper-threshold")
+                .value_name("SEVERITY")
+                .value_parser(["info", "warning", "critical"])
+                .help("Lowest severity that triggers the tamper action [default: critical]"),
+        )
+        .arg(
+            Arg::new("alert_rate")
+                .long("alert-rate")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+                .help("Report at most N alerts per minute for each path, 0 for no limit [default: 5]"),
+        )
+        .arg(
+            Arg::new("audit_log")
+                .long("audit-log")
+                .value_name("FILE")
+                .requires("audit_key")
+                .help("Append every event as an HMAC-chained JSON line (check with audit-verify)"),
+        )
+        .arg(
+            Arg::new("audit_key")
+                .long("audit-key")
+                .value_name("FILE")
+                .help("Secret key (mode 0600) for the audit log chain"),
+        )
+        .arg(
+            Arg::new("audit_max_size")
+                .long("audit-max-size")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64))
+                .help("Rotate the audit log before it grows past BYTES"),
+        )
+        .arg(
+            Arg::new("audit_keep")
+                .long("audit-keep")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Rotated audit logs to keep [default: 5]"),
+        )
+        .arg(
+            Arg::new("no_self_protect")
+                .long("no-self-protect")
+                .action(clap::ArgAction::SetTrue)
+                .help("Do not watch this executable, the config file and the kdv baseline"),
+        )
+        .arg(
+            Arg::new("kdv_baseline")
+                .long("kdv-baseline")
+                .value_name("FILE")
+                .help("Check changes against approved hashes (sha256sum format); matching changes are downgraded"),
+        )
+        .arg(
+            Arg::new("kdv_daemon")
+                .long("kdv-daemon")
+                .value_name("BASELINE")
+                .help("Re-check every file of this kdv baseline periodically and alert on files that stop matching"),
+        )
+        .arg(
+            Arg::new("kdv_daemon_interval")
+                .long("kdv-daemon-interval")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("kdv_daemon")
+                .help("Time between kdv daemon rounds [default: 3600]"),
+        )
+        .arg(
+            Arg::new("require_signed_baseline")
+                .long("require-signed-baseline")
+                .value_name("KEY.pub")
+                .requires("kdv_baseline")
+                .conflicts_with("write_baseline")
+                .help("Refuse to start unless --kdv-baseline is signed by KEY.pub and every watched file matches it"),
+        )
+        .arg(
+            Arg::new("write_baseline")
+                .long("write-baseline")
+                .value_name("FILE")
+                .requires("baseline_signing_key")
+                .help("Trust on first use: write the startup hashes of all watched files to FILE and sign it"),
+        )
+        .arg(
+            Arg::new("baseline_signing_key")
+                .long("baseline-signing-key")
+                .value_name("FILE")
+                .help("ed25519 key for --write-baseline; generated (with FILE.pub) if missing"),
+        )
+        .arg(
+            Arg::new("unbaselined")
+                .long("unbaselined")
+                .value_name("POLICY")
+                .value_parser(["alert", "ignore"])
+                .help("What to do with changes to files not in --kdv-baseline [default: alert]"),
+        )
+        .arg(
+            Arg::new("webhook")
+                .long("webhook")
+                .value_name("URL")
+                .num_args(1..)
+                .help("POST alerts as JSON to URL"),
+        )
+        .arg(
+            Arg::new("quarantine_dir")
+                .long("quarantine-dir")
+                .value_name("DIR")
+                .help("Move tampered files here and restore their baseline instead of exiting"),
+        )
+        .arg(
+            Arg::new("snapshot_dir")
+                .long("snapshot-dir")
+                .value_name("DIR")
+                .help("Keep baseline copies on disk instead of in memory"),
+        )
+        .arg(
+            Arg::new("status_file")
+                .long("status-file")
+                .value_name("PATH")
+                .help("Rewrite a JSON status document with per-file statistics"),
+        )
+        .arg(
+            Arg::new("status_interval")
+                .long("status-interval")
+                .value_name("SECONDS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("status_file")
+                .help("How often --status-file is rewritten [default: 10]"),
+        )
+        .arg(
+            Arg::new("hfs_config")
+                .long("hfs-config")
+                .value_name("FILE")
+                .help("Also run the HFS process monitor from an hfs.toml and react to its violations ([on_violation])"),
+        )
+        .arg(
+            Arg::new("control_socket")
+                .long("control-socket")
+                .value_name("PATH")
+                .help("Accept add/remove/list/status/rearm/export-now/pause/resume/scan-fds/kdv-verify/kdv-reload commands on this Unix socket (mode 0600), each from a permission-manager session with its capability"),
+        )
+        .arg(state_file_arg().help("Encrypted file holding the sessions control commands are checked against"))
+        .arg(machine_secret_arg())
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("stop")
+                .help("Detach and run in the background (Unix only)"),
+        )
+        .arg(
+            Arg::new("pid_file")
+                .long("pid-file")
+                .value_name("PATH")
+                .default_value("/run/serialk.pid")
+                .help("Pid file written and locked by --daemon, read by --stop"),
+        )
+        .arg(
+            Arg::new("log_file")
+                .long("log-file")
+                .value_name("PATH")
+                .requires("daemon")
+                .help("Where the daemon's output goes [default: discarded]"),
+        )
+        .arg(
+            Arg::new("stop")
+                .long("stop")
+                .action(clap::ArgAction::SetTrue)
+                .help("Stop the daemon recorded in --pid-file and wait for it to exit"),
+        )
+        .get_matches_from(args);
+
+    if let Some(respond) = matches.get_many::<String>("respond") {
+        let key = matches.get_one::<String>("recovery_key").unwrap();
+        let response = recovery_response_path(&matches, key);
+        write_recovery_response(key, &response, respond.collect());
+        return;
+    }
+
+    let pid_file = PathBuf::from(matches.get_one::<String>("pid_file").unwrap());
+    let daemon = matches.get_flag("daemon");
+    if matches.get_flag("stop") {
+        stop_daemon(&pid_file);
+        return;
+    }
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    if let Err(e) = install_signal_handlers(&shutdown) {
+        eprintln!("Failed to install signal handlers: {}", e);
+    }
+
+    let mut config = match matches.get_one::<String>("config") {
+        Some(path) => match WatcherConfig::load(&PathBuf::from(path)) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => WatcherConfig::default(),
+    };
+
+    // Command-line values override or extend the config file.
+    if let Some(paths) = matches.get_many::<String>("include") {
+        config.include.extend(paths.cloned());
+    }
+    for source in matches.get_many::<String>("include_from").into_iter().flatten() {
+        if let Err(e) = config.read_include_list(source) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(patterns) = matches.get_many::<String>("exclude") {
+        config.exclude.extend(patterns.cloned());
+    }
+    if let Some(patterns) = matches.get_many::<String>("ignore") {
+        config.ignore.extend(patterns.cloned());
+    }
+    if matches.get_flag("no_default_ignores") {
+        config.no_default_ignores = true;
+    }
+    if let Some(entries) = matches.get_many::<String>("liner_street") {
+        for entry in entries {
+            let (spec, severity) = split_severity(entry);
+            match parse_liner_street(spec) {
+                Ok((path, mode)) => config.liner_street.push(LinerStreetEntry {
+                    severity,
+                    ..LinerStreetEntry::from_watch(path, &mode)
+                }),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(output) = matches.get_one::<String>("output") {
+        config.output = Some(PathBuf::from(output));
+    }
+    if let Some(ms) = matches.get_one::<u64>("debounce") {
+        config.debounce_ms = Some(*ms);
+    }
+    if let Some(dir) = matches.get_one::<String>("quarantine_dir") {
+        config.quarantine_dir = Some(PathBuf::from(dir));
+        config.tamper_action = Some(TamperAction::Quarantine);
+    }
+    if let Some(dir) = matches.get_one::<String>("snapshot_dir") {
+        config.snapshot_dir = Some(PathBuf::from(dir));
+    }
+    if let Some(action) = matches.get_one::<String>("tamper_action") {
+        config.tamper_action = match action.as_str() {
+            "exit" => Some(TamperAction::Exit),
+            "quarantine" => Some(TamperAction::Quarantine),
+            _ => Some(TamperAction::Log),
+        };
+    }
+    if let Some(threshold) = matches.get_one::<String>("tamper_threshold") {
+        config.tamper_threshold = threshold.parse::<Severity>().ok();
+    }
+    if let Some(path) = matches.get_one::<String>("audit_log") {
+        config.audit_log = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.get_one::<String>("audit_key") {
+        config.audit_key = Some(PathBuf::from(path));
+    }
+    if let Some(bytes) = matches.get_one::<u64>("audit_max_size") {
+        config.audit_max_size = Some(*bytes);
+    }
+    if let Some(keep) = matches.get_one::<usize>("audit_keep") {
+        config.audit_keep = Some(*keep);
+    }
+    if matches.get_flag("no_self_protect") {
+        config.no_self_protect = true;
+    }
+    if let Some(path) = matches.get_one::<String>("kdv_baseline") {
+        config.kdv_baseline = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.get_one::<String>("kdv_daemon") {
+        config.kdv_daemon = Some(PathBuf::from(path));
+    }
+    if let Some(secs) = matches.get_one::<u64>("kdv_daemon_interval") {
+        config.kdv_daemon_interval_secs = Some(*secs);
+    }
+    if let Some(path) = matches.get_one::<String>("require_signed_baseline") {
+        config.require_signed_baseline = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.get_one::<String>("write_baseline") {
+        config.write_baseline = Some(PathBuf::from(path));
+    }
+    if let Some(path) = matches.get_one::<String>("baseline_signing_key") {
+        config.baseline_signing_key = Some(PathBuf::from(path));
+    }
+    if let Some(policy) = matches.get_one::<String>("unbaselined") {
+        config.unbaselined = Some(match policy.as_str() {
+            "ignore" => UnbaselinedPolicy::Ignore,
+            _ => UnbaselinedPolicy::Alert,
+        });
+    }
+    if let Some(bytes) = matches.get_one::<u64>("large_file_threshold") {
+        config.large_file_threshold = Some(*bytes);
+    }
+    if let Some(jobs) = matches.get_one::<u16>("scan_jobs") {
+        config.scan_jobs = Some(usize::from(*jobs));
+    }
+    if let Some(rate) = matches.get_one::<u64>("scan_max_bandwidth") {
+        config.scan_max_bandwidth = Some(*rate);
+    }
+    if let Some(nice) = matches.get_one::<i32>("scan_nice") {
+        config.scan_nice = Some(*nice);
+    }
+    if let Some(class) = matches.get_one::<String>("scan_ionice") {
+        config.scan_ionice = Some(class.parse().unwrap());
+    }
+    if let Some(mode) = matches.get_one::<String>("large_file_mode") {
+        config.large_file_mode = Some(match mode.as_str() {
+            "stat" => LargeFileMode::Stat,
+            _ => LargeFileMode::Hash,
+        });
+    }
+    if let Some(mode) = matches.get_one::<String>("paused_mode") {
+        config.paused_mode = Some(match mode.as_str() {
+            "freeze" => PausedMode::Freeze,
+            _ => PausedMode::Rebaseline,
+        });
+    }
+    if let Some(secs) = matches.get_one::<u64>("max_pause") {
+        config.max_pause_secs = Some(*secs);
+    }
+    if let Some(depth) = matches.get_one::<usize>("max_depth") {
+        config.max_depth = Some(*depth);
+    }
+    if let Some(rate) = matches.get_one::<u32>("alert_rate") {
+        config.alert_rate = Some(*rate);
+    }
+    if let Some(urls) = matches.get_many::<String>("webhook") {
+        config.webhooks.extend(urls.cloned());
+    }
+    if matches.get_flag("strict") {
+        config.strict = true;
+    }
+    if matches.get_flag("json") {
+        config.json = true;
+    }
+    if matches.get_flag("check_metadata") {
+        config.check_metadata = true;
+    }
+    if matches.get_flag("follow_symlinks") {
+        config.follow_symlinks = true;
+    }
+    if let Some(secs) = matches.get_one::<u64>("rearm_after") {
+        config.rearm_after_secs = Some(*secs);
+    }
+    if let Some(secs) = matches.get_one::<u64>("summary_interval") {
+        config.summary_interval_secs = Some(*secs);
+    }
+    if let Some(secs) = matches.get_one::<u64>("poll_interval") {
+        config.poll_interval_secs = Some(*secs);
+    }
+    if let Some(path) = matches.get_one::<String>("status_file") {
+        config.status_file = Some(PathBuf::from(path));
+    }
+    if let Some(secs) = matches.get_one::<u64>("status_interval") {
+        config.status_interval_secs = Some(*secs);
+    }
+    if let Some(path) = matches.get_one::<String>("hfs_config") {
+        match HfsConfig::load(Path::new(path)) {
+            Ok(hfs) => config.hfs = Some(hfs),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if daemon {
+        // Fork before the watcher starts its threads; config errors above still reach the terminal.
+        start_daemon(&pid_file, matches.get_one::<String>("log_file").map(PathBuf::from));
+    }
+
+    let mut wm = match WatchManager::from_config(&config) {
+        Ok(wm) => wm,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    wm.shutdown = shutdown;
+    #[cfg(unix)]
+    if let Err(e) = install_rearm_handler(&wm.rearm_requested) {
+        eprintln!("Failed to install SIGHUP handler: {}", e);
+    }
+    #[cfg(unix)]
+    if let Err(e) = install_pause_handlers(&wm.pause_requested, &wm.resume_requested) {
+        eprintln!("Failed to install SIGUSR1/SIGUSR2 handlers: {}", e);
+    }
+
+    if let Some(key) = matches.get_one::<String>("recovery_key") {
+        let response = recovery_response_path(&matches, key);
+        let timeout = Duration::from_secs(*matches.get_one::<u64>("recovery_timeout").unwrap());
+
+        match HmacGate::from_key_file(&PathBuf::from(key), response, timeout) {
+            Ok(gate) => wm.gate = RecoveryGate::Hmac(gate),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else if matches.get_flag("insecure_env_gate") {
+        eprintln!("[WARN] Using the insecure SERIALK_KEY environment gate.");
+        wm.gate = RecoveryGate::InsecureEnv;
+    }
+
+    let control_socket = matches.get_one::<String>("control_socket").map(PathBuf::from);
+    if let Some(socket) = &control_socket {
+        start_control_socket(&mut wm, socket, &matches);
+    }
+
+    if let Some(hfs) = &config.hfs {
+        start_hfs_bridge(&mut wm, hfs);
+    }
+    if let Some(baseline) = &config.kdv_daemon {
+        let interval = config.kdv_daemon_interval_secs.map_or(kdv_daemon::DEFAULT_INTERVAL, Duration::from_secs);
+        start_kdv_daemon(&mut wm, baseline, interval);
+    }
+
+    if wm.files.is_empty() && wm.dirs.is_empty() && !wm.scanning() && control_socket.is_none() && wm.kdv_daemon.is_none() {
+        eprintln!("Please specify files using --include, --include-from or --liner-street.");
+        std::process::exit(1);
+    }
+
+    if !config.no_self_protect {
+        let mut own_files: Vec<PathBuf> = matches.get_one::<String>("config").map(PathBuf::from).into_iter().collect();
+        own_files.extend(config.kdv_baseline.clone());
+        wm.protect_self(&own_files);
+    }
+    if let (Some(manifest), Some(_)) = (&config.kdv_baseline, &config.require_signed_baseline) {
+        if let Err(e) = wm.check_signed_baseline(manifest) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let (Some(manifest), Some(key)) = (&config.write_baseline, &config.baseline_signing_key) {
+        let written = serialk_baseline::load_or_create_signing_key(key).and_then(|key| wm.write_baseline(manifest, &key));
+        if let Err(e) = written {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    wm.watch_loop();
+    if let Some(socket) = &control_socket {
+        let _ = fs::remove_file(socket);
+    }
+
+    if daemon {
+        #[cfg(unix)]
+        serialk_daemon::remove_pid_file(&pid_file);
+        // The forked child has no tokio workers to join, so skip the runtime teardown.
+        std::process::exit(0);
+    }
+}
+
+/// Runs the HFS monitor on its own thread, feeding the watch loop.
+fn start_hfs_bridge(wm: &mut WatchManager, hfs: &HfsConfig) {
+    let mut protect = hfs.protect.clone();
+    if hfs.protect_watched {
+        protect.extend(wm.export_paths());
+    }
+    let options = HfsOptions {
+        interval: hfs.interval_secs.map_or(hfs::DEFAULT_SCAN_INTERVAL, Duration::from_secs),
+        jitter: hfs.jitter_secs.map_or(Duration::ZERO, Duration::from_secs),
+        escalation: hfs.escalation.clone(),
+        protect,
+        fds_every: hfs.fds_every.unwrap_or(hfs::DEFAULT_FDS_EVERY),
+        cooldown: hfs.cooldown.unwrap_or_default(),
+        ..Default::default()
+    };
+    wm.hfs_fd_scan = Some(options.fd_scan_requested.clone());
+    match hfs::spawn_hfs_monitor(&hfs.patterns, &hfs.allow, options) {
+        Ok(rx) => wm.violations = Some(rx),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the kdv daemon on its own thread, feeding the watch loop.
+fn start_kdv_daemon(wm: &mut WatchManager, baseline: &Path, interval: Duration) {
+    let (alerts, rx) = tokio::sync::mpsc::unbounded_channel();
+    let daemon = kdv_daemon::KdvDaemon {
+        interval,
+        baseline: baseline.to_path_buf(),
+        hashing: kdv::Hashing::default(),
+        alerts,
+    };
+    match daemon.spawn() {
+        Ok(handle) => {
+            wm.kdv_daemon = Some(handle);
+            wm.kdv_events = Some(rx);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn start_control_socket(wm: &mut WatchManager, socket: &Path, matches: &clap::ArgMatches) {
+    let state_file = PathBuf::from(matches.get_one::<String>("state_file").unwrap());
+    let machine_secret = PathBuf::from(matches.get_one::<String>("machine_secret").unwrap());
+    let sessions: serialk_control::OpenSessions = Box::new(move || {
+        StateFile::open(&state_file, &machine_secret).and_then(|file| PermissionManager::new().with_state_file(file))
+    });
+    match serialk_control::listen(socket, sessions) {
+        Ok(rx) => wm.control = Some(rx),
+        Err(e) => {
+            eprintln!("Cannot open control socket {}: {}", socket.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn start_control_socket(_wm: &mut WatchManager, _socket: &Path, _matches: &clap::ArgMatches) {
+    eprintln!("--control-socket is only supported on Unix.");
+    std::process::exit(1);
+}
+
+/// `serialk-watcher ctl --socket PATH <command> [path]`: the control socket client.
+#[cfg(unix)]
+fn handle_watcher_ctl(args: &[String]) {
+    let matches = ClapCommand::new("SerialK Watcher control")
+        .no_binary_name(true)
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .value_name("PATH")
+                .required(true)
+                .help("The watcher's --control-socket"),
+        )
+        .arg(
+            Arg::new("command")
+                .value_parser([
+                    "add", "remove", "list", "status", "rearm", "export-now", "pause", "resume", "scan-fds", "kdv-verify",
+                    "kdv-reload",
+                ])
+                .required(true),
+        )
+        .arg(Arg::new("path").help("File for add/remove/rearm"))
+        .arg(
+            Arg::new("liner")
+                .long("liner")
+                .value_name("COUNT|forever-all-day|DURATION")
+                .help("Line-watch mode for add"),
+        )
+        .arg(
+            Arg::new("severity")
+                .long("severity")
+                .value_parser(["info", "warning", "critical"])
+                .help("Severity for add"),
+        )
+        .args(token_args())
+        .get_matches_from(args);
+
+    let token = presented_token(&matches).unwrap_or_else(|e| {
+        eprintln!("[ERROR] {}", e);
+        std::process::exit(1);
+    });
+    let command = matches.get_one::<String>("command").unwrap();
+    let mut request = serde_json::json!({ "cmd": command, "token": token.to_string() });
+    if let Some(path) = matches.get_one::<String>("path") {
+        request["path"] = path.as_str().into();
+    }
+    if let Some(liner) = matches.get_one::<String>("liner") {
+        request["liner"] = liner.as_str().into();
+    }
+    if let Some(severity) = matches.get_one::<String>("severity") {
+        request["severity"] = severity.as_str().into();
+    }
+
+    let socket = PathBuf::from(matches.get_one::<String>("socket").unwrap());
+    match serialk_control::send(&socket, &request) {
+        Ok(response) => {
+            println!("{}", response);
+            if response["ok"] != true {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Cannot reach watcher at {}: {}", socket.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `serialk-watcher status (--socket PATH | --status-file PATH)`: per-file table.
+fn handle_watcher_status(args: &[String]) {
+    let matches = ClapCommand::new("SerialK Watcher status")
+        .no_binary_name(true)
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .value_name("PATH")
+                .conflicts_with("status_file")
+                .required_unless_present("status_file")
+                .help("Ask a running watcher over its --control-socket"),
+        )
+        .arg(
+            Arg::new("status_file")
+                .long("status-file")
+                .value_name("PATH")
+                .help("Read a watcher's --status-file"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the raw status document"),
+        )
+        .args(token_args().map(|arg| arg.requires("socket")))
+        .get_matches_from(args);
+
+    let status = match (matches.get_one::<String>("socket"), matches.get_one::<String>("status_file")) {
+        (Some(socket), _) => presented_token(&matches).and_then(|token| query_status(&PathBuf::from(socket), &token)),
+        (None, Some(file)) => fs::read_to_string(file)
+            .map_err(|e| format!("Cannot read {}: {}", file, e))
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| format!("Invalid status file {}: {}", file, e))),
+        (None, None) => unreachable!("clap requires one source"),
+    };
+    match status {
+        Ok(status) if matches.get_flag("json") => println!("{}", status),
+        Ok(status) => print!("{}", serialk_control::render_status(&status)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `serialk-watcher audit-verify FILE... --key K`: checks the HMAC chain of
+/// an audit log, rotated files given oldest first.
+fn handle_watcher_audit_verify(args: &[String]) {
+    let matches = ClapCommand::new("SerialK Watcher audit-verify")
+        .no_binary_name(true)
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
                 .num_args(1..)
-                .help("Include file or directory recursively"),
+                .required(true)
+                .help("Audit log files, oldest rotation first (e.g. audit.log.2 audit.log.1 audit.log)"),
         )
         .arg(
-            Arg::new("liner_street")
-                .long("liner-street")
-                .value_name("PATH:COUNT|forever-all-day")
-                .num_args(1..)
-                .help("Enable line-based watching"),
+            Arg::new("key")
+                .long("key")
+                .value_name("FILE")
+                .required(true)
+                .help("The watcher's --audit-key"),
         )
         .get_matches_from(args);
 
-    let mut wm = WatchManager::new();
+    let key = match serialk_gate::read_key_file(&PathBuf::from(matches.get_one::<String>("key").unwrap()), "audit key") {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let files: Vec<PathBuf> = matches.get_many::<String>("files").unwrap().map(PathBuf::from).collect();
+    match serialk_audit::verify_files(&files, &key) {
+        Ok(lines) => println!("[AUDIT] OK: {} lines in {} file(s)", lines, files.len()),
+        Err(e) => {
+            println!("[AUDIT] FAILED at {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn query_status(socket: &Path, token: &SessionToken) -> Result<serde_json::Value, String> {
+    serialk_control::send(socket, &serde_json::json!({ "cmd": "status", "token": token.to_string() }))
+        .map_err(|e| format!("Cannot reach watcher at {}: {}", socket.display(), e))
+}
 
-    if let Some(paths) = matches.get_many::<String>("include") {
-        for path in paths {
-            wm.add_path(&PathBuf::from(path));
+#[cfg(not(unix))]
+fn query_status(_socket: &Path, _token: &SessionToken) -> Result<serde_json::Value, String> {
+    Err("--socket is only supported on Unix.".to_string())
+}
+
+#[cfg(not(unix))]
+fn handle_watcher_ctl(_args: &[String]) {
+    eprintln!("ctl is only supported on Unix.");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+fn start_daemon(pid_file: &Path, log_file: Option<PathBuf>) {
+    let options = serialk_daemon::DaemonOptions {
+        name: "serialk-watcher",
+        pid_file: pid_file.to_path_buf(),
+        log_file,
+    };
+    if let Err(e) = serialk_daemon::daemonize(&options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn start_daemon(_pid_file: &Path, _log_file: Option<PathBuf>) {
+    eprintln!("--daemon is only supported on Unix.");
+    std::process::exit(1);
+}
+
+/// Detaches `serialkiller hfs` and runs the monitor until SIGTERM on a
+/// thread with a runtime of its own, as the forked child has none that
+/// works. Bad patterns are reported before forking, while there is a terminal.
+#[cfg(unix)]
+fn start_hfs_daemon(config: HfsConfig, mut options: HfsOptions, pid_file: &Path, log_file: hfs_log::RotatingFile) -> ! {
+    if let Err(e) = hfs::check_hfs_monitor(&config.patterns, &config.allow, &options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let daemon = serialk_daemon::DaemonOptions {
+        name: "serialkiller hfs",
+        pid_file: pid_file.to_path_buf(),
+        log_file: None,
+    };
+    if let Err(e) = serialk_daemon::daemonize(&daemon) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let (log, writer) = hfs_log::HfsLog::to_file(log_file, hfs_log::LOG_QUEUE);
+    log.line(&format!("[HFS] Daemon started (pid {}), {} patterns", std::process::id(), config.patterns.len()));
+    options.log = Some(log.clone());
+    let monitor_log = log.clone();
+    let monitor = std::thread::spawn(move || {
+        match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            // Returns on SIGTERM, after logging the scan summary.
+            Ok(runtime) => runtime.block_on(hfs::start_hfs_monitor(&config.patterns, &config.allow, options)),
+            Err(e) => monitor_log.line(&format!("[HFS] Cannot start the monitor: {}", e)),
         }
+    });
+    let _ = monitor.join();
+    log.line("[HFS] Daemon stopped");
+    // The writer finishes the queue once the last sender is gone.
+    drop(log);
+    let _ = writer.join();
+    serialk_daemon::remove_pid_file(pid_file);
+    std::process::exit(0);
+}
+
+#[cfg(not(unix))]
+fn start_hfs_daemon(_config: HfsConfig, _options: HfsOptions, _pid_file: &Path, _log_file: hfs_log::RotatingFile) -> ! {
+    eprintln!("--daemon is only supported on Unix.");
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+fn stop_daemon(pid_file: &Path) {
+    if let Err(e) = serialk_daemon::stop(pid_file, Duration::from_secs(30)) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
+}
 
-    if let Some(entries) = matches.get_many::<String>("liner_street") {
-        for entry in entries {
-            let (path, mode) = parse_liner_street(entry);
-            wm.add_file(path, Some(mode));
+#[cfg(not(unix))]
+fn stop_daemon(_pid_file: &Path) {
+    eprintln!("--stop is only supported on Unix.");
+    std::process::exit(1);
+}
+
+fn recovery_response_path(matches: &clap::ArgMatches, key: &str) -> PathBuf {
+    matches
+        .get_one::<String>("recovery_response")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("{}.response", key)))
+}
+
+fn write_recovery_response(key: &str, response: &PathBuf, respond: Vec<&String>) {
+    let path = PathBuf::from(respond[0]);
+    let timestamp = match respond[1].parse::<u128>() {
+        Ok(ts) => ts,
+        Err(_) => {
+            eprintln!("Challenge timestamp must be a number.");
+            std::process::exit(1);
+        }
+    };
+    let key = match fs::read(key) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Cannot read recovery key {}: {}", key, e);
+            std::process::exit(1);
+        }
+    };
+    let tag = HmacGate::sign(&key, &Challenge { path, timestamp });
+    if let Err(e) = fs::write(response, tag) {
+        eprintln!("Cannot write {}: {}", response.display(), e);
+        std::process::exit(1);
+    }
+    println!("Response written to {}", response.display());
+}
+
+/// The globs given to `--<id>`; exits on one that does not parse.
+fn glob_patterns(matches: &clap::ArgMatches, id: &str) -> Vec<glob::Pattern> {
+    let mut patterns = Vec::new();
+    for pattern in matches.get_many::<String>(id).into_iter().flatten() {
+        match glob::Pattern::new(pattern) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => {
+                eprintln!("Invalid {} pattern '{}': {}", id, pattern, e);
+                std::process::exit(1);
+            }
         }
     }
+    patterns
+}
 
-    if wm.files.is_empty() {
-        eprintln!("Please specify files using --include or --liner-street.");
+fn handle_kdv(args: &[String]) {
+    let format_arg = || {
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .value_parser(kdv_sums::SumsFormat::NAMES)
+            .default_value("sha256sum")
+            .help("<hex>  <path> lines as written by sha256sum, sha512sum or b3sum")
+    };
+    let sign_key_arg = || {
+        Arg::new("sign_key")
+            .long("sign-key")
+            .value_name("KEY")
+            .help("Sign the baseline with this key from kdv keygen")
+    };
+    let bench_file_arg = || {
+        Arg::new("bench_file")
+            .long("bench-file")
+            .value_name("FILE")
+            .default_value(kdv_bench::DEFAULT_BENCH_FILE)
+            .help("Where kdv bench keeps its results for init --algo auto")
+    };
+    let walk_args = || {
+        [
+            Arg::new("baseline")
+                .short('b')
+                .long("baseline")
+                .value_name("FILE")
+                .required(true)
+                .help("The JSON manifest of fingerprints"),
+            Arg::new("root")
+                .long("root")
+                .value_name("DIR")
+                .help("Record paths relative to DIR, so the baseline also fits a tree mounted elsewhere"),
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .action(clap::ArgAction::Append)
+                .help("Skip files and directories matching GLOB (full path or name)"),
+            Arg::new("max_depth")
+                .long("max-depth")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Descend at most N directory levels below each given directory"),
+            Arg::new("skip_symlinks")
+                .long("skip-symlinks")
+                .action(clap::ArgAction::SetTrue)
+                .help("Leave symlinks out instead of fingerprinting their targets"),
+            Arg::new("key_file")
+                .long("key-file")
+                .value_name("FILE")
+                .help("HMAC-SHA256 key (mode 0600), so a rewritten baseline cannot vouch for modified files"),
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("Hash N files at once [default: one per core, at most 4]"),
+            Arg::new("max_bandwidth")
+                .long("max-bandwidth")
+                .value_name("BYTES_PER_SEC")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("Read at most BYTES_PER_SEC across all hashing threads"),
+            Arg::new("nice")
+                .long("nice")
+                .value_name("N")
+                .value_parser(clap::value_parser!(i32).range(0..=19))
+                .help("Hash at niceness N (Linux)"),
+            Arg::new("ionice")
+                .long("ionice")
+                .value_name("CLASS")
+                .value_parser(["idle", "best-effort"])
+                .help("Hash in the idle or lowest best-effort I/O class (Linux)"),
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .action(clap::ArgAction::SetTrue)
+                .help("Do not show progress on stderr (only shown on a terminal anyway)"),
+            Arg::new("null")
+                .long("null")
+                .action(clap::ArgAction::SetTrue)
+                .help("Paths read for a - argument are separated by NUL bytes, as find -print0 writes them"),
+        ]
+    };
+    let matches = ClapCommand::new("serialkiller kdv")
+        .no_binary_name(true)
+        .about("Fingerprint files and check them against the fingerprints later")
+        .arg_required_else_help(true)
+        .args_conflicts_with_subcommands(true)
+        .arg(
+            Arg::new("pself")
+                .long("pself")
+                .value_name("FILE")
+                .help("Verify each section of a pself container against the hash in its section table"),
+        )
+        .arg(
+            Arg::new("export_baseline")
+                .long("export-baseline")
+                .value_name("OUT")
+                .requires("pself")
+                .help("Also write the pself's section hashes to OUT as a kdv baseline"),
+        )
+        .arg(
+            Arg::new("fail_fast")
+                .long("fail-fast")
+                .action(clap::ArgAction::SetTrue)
+                .requires("pself")
+                .help("Stop at the first section that does not verify"),
+        )
+        .arg(
+            Arg::new("pself_json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)
+                .requires("pself")
+                .help("Print the pself report as JSON, with the bytes hashed and the time taken"),
+        )
+        .subcommand(
+            ClapCommand::new("init")
+                .about("Write a baseline of the files' sizes and digests, walking directories")
+                .args(walk_args())
+                .args(token_args().map(|arg| arg.help("Session of a user who may update baselines; not needed for a new baseline")))
+                .arg(state_file_arg())
+                .arg(machine_secret_arg())
+                .arg(
+                    Arg::new("algo")
+                        .long("algo")
+                        .value_name("ALGO")
+                        .value_parser(["sha256", "sha512", "blake3", "auto"])
+                        .default_value("sha256")
+                        .conflicts_with("key_file")
+                        .help("Digest to record, or auto for the one kdv bench recommended; check uses whatever each entry was recorded with"),
+                )
+                .arg(bench_file_arg())
+                .arg(
+                    Arg::new("by_section")
+                        .long("by-section")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Also fingerprint each code and data section of ELF, PE and Mach-O files, so check can tell which changed"),
+                )
+                .arg(
+                    Arg::new("with_metadata")
+                        .long("with-metadata")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Also record each file's mode, owner and group (read-only flag on Windows), so check catches permission changes"),
+                )
+                .arg(sign_key_arg())
+                .arg(Arg::new("paths").value_name("PATH").num_args(1..).required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("check")
+                .about("Re-hash files and report those changed, missing or not in the baseline")
+                .after_help("A - PATH reads the paths to check from stdin, one per line.\n\nExit status: 0 all verified, 2 files changed or added, 3 files missing, 4 no paths on stdin, 5 owner or permissions changed, 1 errors\n\nA file that gained a setuid or setgid bit is reported as METADATA even if the baseline was made without --with-metadata.")
+                .args(walk_args())
+                .mut_arg("baseline", |arg| {
+                    arg.required(false)
+                        .required_unless_present_any(["xattr", "embedded"])
+                        .help("The JSON manifest of fingerprints or a sha256sum/sha512sum file, or an http(s) URL to fetch it from")
+                })
+                .arg(
+                    Arg::new("pin_sha256")
+                        .long("pin-sha256")
+                        .value_name("HEX")
+                        .help("SHA-256 the manifest fetched from a -b URL must hash to (required with a URL)"),
+                )
+                .arg(
+                    Arg::new("pubkey")
+                        .long("pubkey")
+                        .value_name("FILE")
+                        .help("Refuse a baseline not signed with the key this ed25519 public key belongs to; for a -b URL, URL.sig must verify instead"),
+                )
+                .arg(
+                    Arg::new("insecure")
+                        .long("insecure")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("pubkey")
+                        .help("Check against a baseline whose signature fails --pubkey, with a warning"),
+                )
+                .arg(
+                    Arg::new("cache_dir")
+                        .long("cache-dir")
+                        .value_name("DIR")
+                        .default_value(kdv_fetch::DEFAULT_CACHE_DIR)
+                        .help("Where verified copies of fetched manifests are kept"),
+                )
+                .arg(
+                    Arg::new("max_age")
+                        .long("max-age")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("3600")
+                        .help("Use a cached manifest younger than SECS without fetching it again"),
+                )
+                .arg(
+                    Arg::new("allow_stale")
+                        .long("allow-stale")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("If the server cannot be reached, use the cached manifest whatever its age"),
+                )
+                .arg(
+                    Arg::new("xattr")
+                        .long("xattr")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("paths")
+                        .conflicts_with_all(["baseline", "watch", "quick"])
+                        .help("Check the given files against the seals kdv seal stored with them"),
+                )
+                .arg(
+                    Arg::new("embedded")
+                        .long("embedded")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["baseline", "xattr", "watch", "pubkey"])
+                        .help("Check against the baseline compiled into this binary (see build.rs)"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print every file's status and the summary as JSON, or with --watch each alert"),
+                )
+                .arg(
+                    Arg::new("quick")
+                        .long("quick")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Skip hashing files whose size and mtime match the baseline"),
+                )
+                .arg(
+                    Arg::new("paranoid")
+                        .long("paranoid")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("quick")
+                        .help("Hash every file, whatever its size and mtime [default]"),
+                )
+                .arg(
+                    Arg::new("fail_fast")
+                        .long("fail-fast")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["quick", "incremental", "watch", "xattr"])
+                        .help("Stop at the first file that does not verify; the rest are counted as skipped"),
+                )
+                .arg(
+                    Arg::new("allow_unknown")
+                        .long("allow-unknown")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["quick", "incremental", "watch", "xattr"])
+                        .help("Still list files not in the baseline as ADDED, but do not fail the check for them"),
+                )
+                .arg(
+                    Arg::new("incremental")
+                        .long("incremental")
+                        .value_name("STATE")
+                        .conflicts_with_all(["quick", "paranoid", "watch", "xattr"])
+                        .help("Rehash only files whose size, mtime or inode changed since they last verified, as kept in STATE"),
+                )
+                .arg(
+                    Arg::new("full_every")
+                        .long("full-every")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .requires("incremental")
+                        .help("Make every Nth --incremental run rehash every file"),
+                )
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Check again every --interval until stopped, alerting only when a file's status changes"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("300")
+                        .requires("watch")
+                        .help("Seconds between the rounds of --watch"),
+                )
+                .arg(
+                    Arg::new("state_file")
+                        .long("state-file")
+                        .value_name("FILE")
+                        .requires("watch")
+                        .help("Keep the --watch state in FILE so a restart does not alert again"),
+                )
+                .arg(
+                    Arg::new("webhook")
+                        .long("webhook")
+                        .value_name("URL")
+                        .action(clap::ArgAction::Append)
+                        .requires("watch")
+                        .help("POST --watch alerts as JSON to URL"),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .value_name("PATH")
+                        .num_args(1..)
+                        .help("Check only these files and directories [default: every file in the baseline]"),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("update")
+                .about("Check the files, list the drift and, once confirmed, rewrite the baseline to trust it")
+                .after_help("Exit status: 0 updated or nothing to update, 2 update declined, 1 errors")
+                .args(walk_args())
+                .args(token_args().map(|arg| arg.help("Session of a user who may update baselines")))
+                .arg(state_file_arg())
+                .arg(machine_secret_arg())
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("GLOB")
+                        .action(clap::ArgAction::Append)
+                        .help("Take in only changes to files matching GLOB (full path or name); other drift stays untrusted"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Rewrite the baseline without asking"),
+                )
+                .arg(sign_key_arg())
+                .arg(
+                    Arg::new("paths")
+                        .value_name("PATH")
+                        .num_args(1..)
+                        .help("Update only these files and directories [default: every file in the baseline]"),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("import")
+                .about("Build a baseline from a checksum file, or merge one into it")
+                .arg(
+                    Arg::new("baseline")
+                        .short('b')
+                        .long("baseline")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("The JSON manifest to write or merge into"),
+                )
+                .arg(format_arg())
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .help("Record paths relative to DIR [default: the baseline's root, else absolute]"),
+                )
+                .arg(Arg::new("sums").value_name("SUMS").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("export")
+                .about("Print a baseline as a checksum file")
+                .arg(
+                    Arg::new("baseline")
+                        .short('b')
+                        .long("baseline")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("The JSON manifest of fingerprints"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            ClapCommand::new("dupes")
+                .about("Hash files, walking directories, and list those with the same content")
+                .args(walk_args().into_iter().filter(|arg| !matches!(arg.get_id().as_str(), "baseline" | "root")))
+                .arg(
+                    Arg::new("min_size")
+                        .long("min-size")
+                        .value_name("BYTES")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("1")
+                        .help("Leave out files smaller than BYTES"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the groups of duplicates as JSON"),
+                )
+                .arg(Arg::new("paths").value_name("PATH").num_args(1..).required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("bench")
+                .about("Measure how fast each algorithm hashes in memory here and recommend one for init --algo auto")
+                .arg(
+                    Arg::new("size")
+                        .long("size")
+                        .value_name("BYTES")
+                        .value_parser(clap::value_parser!(u64).range(1..=1 << 30))
+                        .default_value("1048576")
+                        .help("Hash buffers of BYTES"),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .long("duration")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(f64))
+                        .default_value("1")
+                        .help("Keep hashing for SECS per algorithm and thread count"),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Threads for the multi-thread run [default: as many as init would hash with]"),
+                )
+                .arg(bench_file_arg()),
+        )
+        .subcommand(
+            ClapCommand::new("keygen")
+                .about("Write a new ed25519 signing key for init --sign-key, and KEY.pub for check --pubkey")
+                .arg(Arg::new("key").value_name("KEY").required(true)),
+        )
+        .subcommand(
+            ClapCommand::new("prove")
+                .about("Print the Merkle inclusion proof of one baseline entry as JSON")
+                .arg(
+                    Arg::new("baseline")
+                        .short('b')
+                        .long("baseline")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("The JSON manifest of fingerprints"),
+                )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .value_name("DIR")
+                        .help("Directory PATH is under [default: the baseline's root]"),
+                )
+                .arg(Arg::new("path").value_name("PATH").required(true).help("The entry path or the file it names")),
+        )
+        .subcommand(
+            ClapCommand::new("verify-proof")
+                .about("Check a proof from kdv prove against a Merkle root, without the baseline")
+                .after_help("Exit status: 0 the proof holds, 2 it does not, 1 errors")
+                .arg(Arg::new("proof").value_name("PROOF").required(true))
+                .arg(
+                    Arg::new("merkle_root")
+                        .long("merkle-root")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("The Merkle root init or check printed for the trusted baseline"),
+                ),
+        )
+        .subcommands(["seal", "reseal"].map(|name| {
+            let about = match name {
+                "seal" => "Store each file's digest in a user.serialk.sha256 extended attribute",
+                _ => "Replace the seals of files that were sealed before",
+            };
+            ClapCommand::new(name)
+                .about(about)
+                .arg(
+                    Arg::new("algo")
+                        .long("algo")
+                        .value_name("ALGO")
+                        .value_parser(["sha256", "sha512", "blake3"])
+                        .default_value("sha256")
+                        .help("Digest to seal with"),
+                )
+                .arg(Arg::new("paths").value_name("FILE").num_args(1..).required(true))
+        }))
+        .subcommand(
+            ClapCommand::new("diff")
+                .about("Compare two baselines entry by entry, without touching the files")
+                .after_help("Exit status: 0 no differences, 2 differences, 1 errors")
+                .arg(Arg::new("old").value_name("OLD").required(true))
+                .arg(Arg::new("new").value_name("NEW").required(true))
+                .arg(
+                    Arg::new("ignore")
+                        .long("ignore")
+                        .value_name("GLOB")
+                        .action(clap::ArgAction::Append)
+                        .help("Leave out entries matching GLOB (full path or name) on both sides"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Print the differences as JSON"),
+                ),
+        )
+        .get_matches_from(args);
+    let Some((command, matches)) = matches.subcommand() else {
+        let pself = PathBuf::from(matches.get_one::<String>("pself").unwrap());
+        let export = matches.get_one::<String>("export_baseline").map(PathBuf::from);
+        let options = kdv::VerifyOptions {
+            fail_fast: matches.get_flag("fail_fast"),
+            jobs: Some(1),
+            ..Default::default()
+        };
+        std::process::exit(kdv::run_kdv_pself(&pself, export.as_deref(), &options, matches.get_flag("pself_json")));
+    };
+    if command == "diff" {
+        let old = PathBuf::from(matches.get_one::<String>("old").unwrap());
+        let new = PathBuf::from(matches.get_one::<String>("new").unwrap());
+        let ignore = glob_patterns(matches, "ignore");
+        std::process::exit(kdv::run_kdv_diff(&old, &new, &ignore, matches.get_flag("json")));
+    }
+    if command == "prove" {
+        let baseline = PathBuf::from(matches.get_one::<String>("baseline").unwrap());
+        let root = matches.get_one::<String>("root").map(PathBuf::from);
+        let path = matches.get_one::<String>("path").unwrap();
+        std::process::exit(kdv::run_kdv_prove(&baseline, path, root.as_deref()));
+    }
+    if command == "import" || command == "export" {
+        let baseline = PathBuf::from(matches.get_one::<String>("baseline").unwrap());
+        let format = matches.get_one::<String>("format").unwrap().parse().unwrap();
+        std::process::exit(match command {
+            "import" => {
+                let sums = PathBuf::from(matches.get_one::<String>("sums").unwrap());
+                let root = matches.get_one::<String>("root").map(PathBuf::from);
+                kdv::run_kdv_import(&sums, format, &baseline, root.as_deref())
+            }
+            _ => kdv::run_kdv_export(&baseline, format),
+        });
+    }
+    if command == "bench" {
+        let size = *matches.get_one::<u64>("size").unwrap() as usize;
+        let duration = match Duration::try_from_secs_f64(*matches.get_one::<f64>("duration").unwrap()) {
+            Ok(duration) => duration,
+            Err(_) => {
+                eprintln!("[ERROR] --duration must be a number of seconds");
+                std::process::exit(1);
+            }
+        };
+        let threads = matches.get_one::<u16>("jobs").map_or_else(hash_pool::default_jobs, |&jobs| usize::from(jobs));
+        let path = PathBuf::from(matches.get_one::<String>("bench_file").unwrap());
+        std::process::exit(kdv_bench::run_kdv_bench(size, duration, threads, &path));
+    }
+    if command == "keygen" {
+        let key = PathBuf::from(matches.get_one::<String>("key").unwrap());
+        std::process::exit(kdv::run_kdv_keygen(&key));
+    }
+    if command == "verify-proof" {
+        let proof = PathBuf::from(matches.get_one::<String>("proof").unwrap());
+        let root = matches.get_one::<String>("merkle_root").unwrap();
+        std::process::exit(kdv::run_kdv_verify_proof(&proof, root));
+    }
+    let paths: Vec<PathBuf> = matches.get_many::<String>("paths").into_iter().flatten().map(PathBuf::from).collect();
+    if command == "seal" || command == "reseal" {
+        let algo = matches.get_one::<String>("algo").unwrap().parse().unwrap();
+        std::process::exit(kdv::run_kdv_seal(&paths, algo, command == "reseal"));
+    }
+    if command == "check" && matches.get_flag("xattr") {
+        std::process::exit(kdv::run_kdv_check_seals(&paths, matches.get_flag("json")));
+    }
+    let root = matches.try_get_one::<String>("root").ok().flatten().map(PathBuf::from);
+    let walk = kdv::WalkOptions {
+        excludes: glob_patterns(matches, "exclude"),
+        max_depth: matches.get_one::<usize>("max_depth").copied(),
+        skip_symlinks: matches.get_flag("skip_symlinks"),
+    };
+    let paths = match paths_from_stdin(paths, matches.get_flag("null"), &walk) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("[ERROR] Cannot read paths from stdin: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if paths.is_empty() && matches.get_many::<String>("paths").is_some() {
+        println!("[KDV] No paths on stdin; nothing to {}", command);
+        std::process::exit(4);
+    }
+    let mut hashing = kdv::Hashing {
+        jobs: matches.get_one::<u16>("jobs").map(|&jobs| usize::from(jobs)),
+        progress: !matches.get_flag("quiet") && io::stderr().is_terminal(),
+        quick: command == "check" && matches.get_flag("quick"),
+        by_section: command == "init" && matches.get_flag("by_section"),
+        with_metadata: command == "init" && matches.get_flag("with_metadata"),
+        incremental: match command {
+            "check" => matches.get_one::<String>("incremental").map(|state| kdv::Incremental {
+                state_file: PathBuf::from(state),
+                full_every: matches.get_one::<u32>("full_every").copied(),
+            }),
+            _ => None,
+        },
+        throttle: matches.get_one::<u64>("max_bandwidth").map(|&rate| Arc::new(hash_pool::Throttle::new(rate))),
+        ..Default::default()
+    };
+    // Set before the hashing threads start, which inherit it.
+    let priority = hash_pool::Priority {
+        nice: matches.get_one::<i32>("nice").copied(),
+        ionice: matches.get_one::<String>("ionice").map(|class| class.parse().unwrap()),
+    };
+    if let Err(e) = priority.apply() {
+        eprintln!("[ERROR] {}", e);
         std::process::exit(1);
     }
+    if let Some(path) = matches.get_one::<String>("key_file") {
+        match serialk_gate::read_key_file(Path::new(path), "kdv key") {
+            Ok(key) => hashing.key = Some(key),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if command == "dupes" {
+        let min_size = *matches.get_one::<u64>("min_size").unwrap();
+        std::process::exit(kdv::run_kdv_dupes(&paths, &walk, &hashing, min_size, matches.get_flag("json")));
+    }
+    let options = kdv::VerifyOptions {
+        fail_fast: command == "check" && matches.get_flag("fail_fast"),
+        jobs: hashing.jobs,
+        allow_unknown: command == "check" && matches.get_flag("allow_unknown"),
+    };
+    if command == "check" && matches.get_flag("embedded") {
+        let json = matches.get_flag("json");
+        std::process::exit(kdv::run_kdv_check_embedded(&paths, &walk, root.as_deref(), &hashing, &options, json));
+    }
+    let baseline = matches.get_one::<String>("baseline").unwrap();
+    let fetched = kdv_fetch::is_url(baseline);
+    let baseline = match fetched {
+        true if command == "check" => match fetch_baseline(matches, baseline) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                std::process::exit(1);
+            }
+        },
+        true => {
+            eprintln!("[ERROR] kdv {} writes its baseline locally; -b cannot be a URL", command);
+            std::process::exit(1);
+        }
+        false if command == "check" && matches.contains_id("pin_sha256") => {
+            eprintln!("[ERROR] --pin-sha256 applies to a -b URL only");
+            std::process::exit(1);
+        }
+        false => PathBuf::from(baseline),
+    };
+    // A fetched baseline was verified against --pubkey through URL.sig.
+    let mut trust = kdv::BaselineTrust::default();
+    if command == "check" && !fetched {
+        trust.insecure = matches.get_flag("insecure");
+        if let Some(path) = matches.get_one::<String>("pubkey") {
+            match serialk_baseline::load_public_key(Path::new(path)) {
+                Ok(key) => trust.public_key = Some(key),
+                Err(e) => {
+                    eprintln!("[ERROR] {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    let sign_key = match matches.try_get_one::<String>("sign_key").ok().flatten() {
+        Some(path) => match serialk_baseline::load_signing_key(Path::new(path)) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    // Someone has to write the first baseline; replacing one takes a session.
+    let _session = (command == "update" || (command == "init" && baseline.exists())).then(|| {
+        presented_session(matches, kdv::UPDATE_CAPABILITY).unwrap_or_else(|e| {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
+        })
+    });
+    let code = match command {
+        "init" => {
+            let algo = match matches.get_one::<String>("algo").unwrap().as_str() {
+                "auto" => kdv_bench::recommended(Path::new(matches.get_one::<String>("bench_file").unwrap())),
+                algo => algo.parse().unwrap(),
+            };
+            kdv::run_kdv_init(&baseline, &paths, &walk, root.as_deref(), algo, &hashing, sign_key.as_ref())
+        }
+        "update" => {
+            let only = glob_patterns(matches, "only");
+            let yes = matches.get_flag("yes");
+            kdv::run_kdv_update(&baseline, &paths, &walk, root.as_deref(), &hashing, &only, yes, sign_key.as_ref())
+        }
+        _ if matches.get_flag("watch") => {
+            let shutdown = Arc::new(AtomicBool::new(false));
+            if let Err(e) = install_signal_handlers(&shutdown) {
+                eprintln!("Failed to install signal handlers: {}", e);
+            }
+            let options = kdv::WatchOptions {
+                interval: Duration::from_secs(*matches.get_one::<u64>("interval").unwrap()),
+                state_file: matches.get_one::<String>("state_file").map(PathBuf::from),
+                json: matches.get_flag("json"),
+                webhooks: matches.get_many::<String>("webhook").into_iter().flatten().cloned().collect(),
+            };
+            kdv::run_kdv_watch(&baseline, &paths, &walk, root.as_deref(), &hashing, &trust, &options, &shutdown)
+        }
+        _ => {
+            let json = matches.get_flag("json");
+            kdv::run_kdv_check(&baseline, &paths, &walk, root.as_deref(), &hashing, &trust, &options, json)
+        }
+    };
+    std::process::exit(code);
+}
 
-    wm.watch_loop();
+/// `paths` with a `-` replaced by the paths listed on stdin.
+fn paths_from_stdin(paths: Vec<PathBuf>, null: bool, walk: &kdv::WalkOptions) -> io::Result<Vec<PathBuf>> {
+    if !paths.iter().any(|path| path.as_os_str() == "-") {
+        return Ok(paths);
+    }
+    let listed = kdv::read_paths(io::stdin().lock(), null, walk)?;
+    let mut expanded = Vec::new();
+    for path in paths {
+        match path.as_os_str() == "-" {
+            true => expanded.extend(listed.iter().cloned()),
+            false => expanded.push(path),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Downloads (or takes from the cache) the manifest at `url` for
+/// `kdv check`, returning the path of the verified copy.
+fn fetch_baseline(matches: &clap::ArgMatches, url: &str) -> Result<PathBuf, String> {
+    let pin = matches
+        .get_one::<String>("pin_sha256")
+        .ok_or_else(|| format!("Baseline {} is fetched over the network; pass --pin-sha256", url))?;
+    let remote = kdv_fetch::RemoteBaseline {
+        url: url.to_string(),
+        pin: kdv_fetch::RemoteBaseline::parse_pin(pin)?,
+        public_key: match matches.get_one::<String>("pubkey") {
+            Some(path) => Some(serialk_baseline::load_public_key(Path::new(path))?),
+            None => None,
+        },
+        cache_dir: PathBuf::from(matches.get_one::<String>("cache_dir").unwrap()),
+        max_age: Duration::from_secs(*matches.get_one::<u64>("max_age").unwrap()),
+        allow_stale: matches.get_flag("allow_stale"),
+    };
+    remote.fetch()
 }
 
 async fn handle_serialkiller(args: &[String]) {
@@ -100,37 +1899,406 @@ async fn handle_serialkiller(args: &[String]) {
 
     match args[0].as_str() {
         "hfs" => {
-            if args.len() < 2 {
-                eprintln!("Please provide at least one forbidden pattern.");
+            let matches = ClapCommand::new("serialkiller hfs")
+                .no_binary_name(true)
+                .about("Watch the process table for forbidden tools")
+                .arg(
+                    Arg::new("config")
+                        .short('c')
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Load patterns and allow rules from a TOML config; flags extend it"),
+                )
+                .arg(
+                    Arg::new("patterns_file")
+                        .long("patterns-file")
+                        .value_name("FILE")
+                        .help("Read forbidden patterns from FILE, one per line ('#' comments), reloading on change"),
+                )
+                .arg(
+                    Arg::new("allow_pid")
+                        .long("allow-pid")
+                        .value_name("PID")
+                        .value_parser(clap::value_parser!(i32))
+                        .action(clap::ArgAction::Append)
+                        .help("Never report or act on PID"),
+                )
+                .arg(
+                    Arg::new("allow_user")
+                        .long("allow-user")
+                        .value_name("USER|UID")
+                        .action(clap::ArgAction::Append)
+                        .help("Never report or act on processes run by USER"),
+                )
+                .arg(
+                    Arg::new("allow_pattern")
+                        .long("allow-pattern")
+                        .value_name("REGEX")
+                        .action(clap::ArgAction::Append)
+                        .help("Never report or act on commands matching REGEX"),
+                )
+                .arg(
+                    Arg::new("action")
+                        .long("action")
+                        .value_name("ACTION")
+                        .value_parser(["log", "kill", "suspend"])
+                        .default_value("log")
+                        .help("What to do with a matching process, unless its pattern names an action"),
+                )
+                .arg(
+                    Arg::new("match_target")
+                        .long("match-target")
+                        .value_name("TARGET")
+                        .value_parser(["comm", "cmdline", "path", "both"])
+                        .default_value("both")
+                        .help("Match against the executable name, command line or path; 'both' tries all three"),
+                )
+                .arg(
+                    Arg::new("cmdline_width")
+                        .long("cmdline-width")
+                        .value_name("CHARS")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("200")
+                        .help("Truncate command lines in reports to CHARS characters"),
+                )
+                .arg(
+                    Arg::new("scan_maps")
+                        .long("scan-maps")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Also match libraries mapped into processes (/proc/PID/maps) against the patterns"),
+                )
+                .arg(
+                    Arg::new("self_only")
+                        .long("self-only")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("scan_maps")
+                        .help("Scan only this process's own maps"),
+                )
+                .arg(
+                    Arg::new("scan_env")
+                        .long("scan-env")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Flag LD_PRELOAD/LD_AUDIT/DYLD_INSERT_LIBRARIES values matching the patterns (Linux)"),
+                )
+                .arg(
+                    Arg::new("strict_env")
+                        .long("strict-env")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("scan_env")
+                        .help("Flag those variables whenever they are set"),
+                )
+                .arg(
+                    Arg::new("scan_ports")
+                        .long("scan-ports")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Flag whatever listens on a debug server port, however it is named (Linux, Windows)"),
+                )
+                .arg(
+                    Arg::new("debug_ports")
+                        .long("debug-ports")
+                        .value_name("PORT,...")
+                        .value_parser(clap::value_parser!(u16))
+                        .value_delimiter(',')
+                        .requires("scan_ports")
+                        .help("Ports --scan-ports checks [default: 1234,2345,27042,23946]"),
+                )
+                .arg(
+                    Arg::new("maps_every")
+                        .long("maps-every")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("6")
+                        .help("Scan maps on every Nth process scan only"),
+                )
+                .arg(
+                    Arg::new("protect")
+                        .long("protect")
+                        .value_name("PATH")
+                        .action(clap::ArgAction::Append)
+                        .help("Flag other processes holding PATH open, with the fd's mode (Linux)"),
+                )
+                .arg(
+                    Arg::new("fds_every")
+                        .long("fds-every")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .help("Check --protect files on every Nth process scan only [default: 6]"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Seconds between process scans [default: 5]"),
+                )
+                .arg(
+                    Arg::new("jitter")
+                        .long("jitter")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Add up to SECS at random to each wait between scans [default: 0]"),
+                )
+                .arg(
+                    Arg::new("remind_after")
+                        .long("remind-after")
+                        .value_name("SECS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Report a process still matching SECS after its first report once more (0 never) [default: 600]"),
+                )
+                .arg(
+                    Arg::new("kill_retries")
+                        .long("kill-retries")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Retry a kill or suspend refused with EPERM N times, backing off, then raise the severity [default: 3]"),
+                )
+                .arg(
+                    Arg::new("chain_depth")
+                        .long("chain-depth")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                        .help("Report up to N ancestors of each offending process (0 to skip)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("Print violations as message lines or as one JSON object each"),
+                )
+                .arg(
+                    Arg::new("events")
+                        .long("events")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("once")
+                        .help("Check each exec as it happens via the proc connector (Linux, needs CAP_NET_ADMIN)"),
+                )
+                .arg(
+                    Arg::new("once")
+                        .long("once")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Scan once and exit: 0 if clean, 2 if violations were found, 1 if the scan failed"),
+                )
+                .arg(
+                    Arg::new("word_boundary")
+                        .long("word-boundary")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["substring", "exact"])
+                        .help("Match plain-text patterns as whole words, so gdb misses gdbus (the default)"),
+                )
+                .arg(
+                    Arg::new("substring")
+                        .long("substring")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("exact")
+                        .help("Match plain-text patterns anywhere, as before word matching was the default"),
+                )
+                .arg(
+                    Arg::new("exact")
+                        .long("exact")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Match plain-text patterns only against the executable's basename"),
+                )
+                .arg(
+                    Arg::new("case_sensitive")
+                        .long("case-sensitive")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Match plain-text patterns with case; regexes use (?i) to ignore it"),
+                )
+                .arg(
+                    Arg::new("literal")
+                        .long("literal")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Match patterns that are not valid regexes as plain text"),
+                )
+                .arg(
+                    Arg::new("daemon")
+                        .long("daemon")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with_all(["stop", "once"])
+                        .help("Detach and log to --log-file instead of stdout (Unix only)"),
+                )
+                .arg(
+                    Arg::new("pid_file")
+                        .long("pid-file")
+                        .value_name("PATH")
+                        .default_value("/run/serialkiller-hfs.pid")
+                        .help("Pid file written and locked by --daemon, read by --stop"),
+                )
+                .arg(
+                    Arg::new("log_file")
+                        .long("log-file")
+                        .value_name("PATH")
+                        .requires("daemon")
+                        .help("Where the daemon logs, one timestamped line per event [default: /var/log/serialkiller-hfs.log]"),
+                )
+                .arg(
+                    Arg::new("log_max_size")
+                        .long("log-max-size")
+                        .value_name("BYTES")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .requires("daemon")
+                        .help("Rotate the log before it grows past BYTES [default: 10 MiB]"),
+                )
+                .arg(
+                    Arg::new("log_keep")
+                        .long("log-keep")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .requires("daemon")
+                        .help("Rotated logs to keep [default: 5]"),
+                )
+                .arg(
+                    Arg::new("stop")
+                        .long("stop")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Stop the daemon recorded in --pid-file and wait for it to exit"),
+                )
+                .arg(
+                    Arg::new("patterns")
+                        .value_name("REGEX[=ACTION[@SEVERITY]]")
+                        .num_args(1..)
+                        .help("Forbidden command patterns, e.g. '^gdb$', 'frida=kill' or 'ghidra=command:alert.sh'"),
+                )
+                .get_matches_from(&args[1..]);
+            let pid_file = PathBuf::from(matches.get_one::<String>("pid_file").unwrap());
+            if matches.get_flag("stop") {
+                stop_daemon(&pid_file);
                 return;
             }
-            hfs::start_hfs_monitor(&args[1..]);
-        }
-        "kdv" => {
-            if args.len() < 2 {
-                eprintln!("Please provide at least one file to verify.");
-                return;
-            }
-            kdv::run_kdv(&args[1..]);
-        }
-        "run" => {
-            if args.len() != 2 {
-                eprintln!("Please specify the pself file to run.");
-                return;
+            let mut config = match matches.get_one::<String>("config") {
+                Some(path) => match HfsConfig::load(Path::new(path)) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                },
+                None => HfsConfig::default(),
+            };
+            let patterns = matches.get_many::<String>("patterns").into_iter().flatten();
+            config.patterns.extend(patterns.map(|spec| PatternRule::parse(spec)));
+            config.allow.pids.extend(matches.get_many::<i32>("allow_pid").into_iter().flatten());
+            config.allow.users.extend(matches.get_many::<String>("allow_user").into_iter().flatten().cloned());
+            config.allow.patterns.extend(matches.get_many::<String>("allow_pattern").into_iter().flatten().cloned());
+            config.protect.extend(matches.get_many::<String>("protect").into_iter().flatten().map(PathBuf::from));
+            let patterns_file = matches.get_one::<String>("patterns_file").map(PathBuf::from);
+            let cooldown = config.cooldown.unwrap_or_default();
+            let seconds = |arg: &str, config: Option<u64>| matches.get_one::<u64>(arg).copied().or(config).map(Duration::from_secs);
+            let options = HfsOptions {
+                interval: seconds("interval", config.interval_secs).unwrap_or(hfs::DEFAULT_SCAN_INTERVAL),
+                jitter: seconds("jitter", config.jitter_secs).unwrap_or_default(),
+                action: matches.get_one::<String>("action").unwrap().parse().unwrap_or(HfsAction::LogOnly),
+                matching: PatternOptions {
+                    literal: matches.get_flag("literal"),
+                    literal_match: if matches.get_flag("exact") {
+                        LiteralMatch::Exact
+                    } else if matches.get_flag("substring") {
+                        LiteralMatch::Substring
+                    } else {
+                        LiteralMatch::WordBoundary
+                    },
+                    case_sensitive: matches.get_flag("case_sensitive"),
+                },
+                match_target: matches.get_one::<String>("match_target").unwrap().parse().unwrap_or(MatchTarget::Both),
+                cmdline_width: *matches.get_one::<usize>("cmdline_width").unwrap(),
+                scan_maps: match (matches.get_flag("scan_maps"), matches.get_flag("self_only")) {
+                    (false, _) => MapsScope::Off,
+                    (true, false) => MapsScope::All,
+                    (true, true) => MapsScope::SelfOnly,
+                },
+                maps_every: *matches.get_one::<u64>("maps_every").unwrap(),
+                chain_depth: *matches.get_one::<usize>("chain_depth").unwrap(),
+                json: matches.get_one::<String>("format").is_some_and(|format| format == "json"),
+                once: matches.get_flag("once"),
+                events: matches.get_flag("events"),
+                patterns_file,
+                escalation: config.escalation.clone(),
+                scan_env: matches.get_flag("scan_env"),
+                strict_env: matches.get_flag("strict_env"),
+                scan_ports: match matches.get_many::<u16>("debug_ports") {
+                    _ if !matches.get_flag("scan_ports") => Vec::new(),
+                    Some(ports) => ports.copied().collect(),
+                    None => hfs::DEFAULT_DEBUG_PORTS.to_vec(),
+                },
+                protect: config.protect.clone(),
+                fds_every: matches
+                    .get_one::<u64>("fds_every")
+                    .copied()
+                    .or(config.fds_every)
+                    .unwrap_or(hfs::DEFAULT_FDS_EVERY),
+                cooldown: hfs::HfsCooldown {
+                    remind_after_secs: matches
+                        .get_one::<u64>("remind_after")
+                        .copied()
+                        .unwrap_or(cooldown.remind_after_secs),
+                    retries: matches.get_one::<u32>("kill_retries").copied().unwrap_or(cooldown.retries),
+                    ..cooldown
+                },
+                log: None,
+                ..Default::default()
+            };
+            if matches.get_flag("daemon") {
+                let log_file = matches.get_one::<String>("log_file").map_or(hfs_log::DEFAULT_LOG_FILE, String::as_str);
+                let max_size = matches.get_one::<u64>("log_max_size").copied().unwrap_or(hfs_log::DEFAULT_LOG_MAX_SIZE);
+                let keep = matches.get_one::<usize>("log_keep").copied().unwrap_or(hfs_log::DEFAULT_LOG_KEEP);
+                let log = match hfs_log::RotatingFile::open(Path::new(log_file), max_size, keep) {
+                    Ok(log) => log,
+                    Err(e) => {
+                        eprintln!("Cannot open log file {}: {}", log_file, e);
+                        std::process::exit(1);
+                    }
+                };
+                start_hfs_daemon(config, options, &pid_file, log);
             }
-            crate::runner::run_pself(&args[1]);
+            hfs::start_hfs_monitor(&config.patterns, &config.allow, options).await;
         }
+        "kdv" => handle_kdv(&args[1..]),
+        "run" => handle_run(&args[1..]),
         _ => {
             print_serialkiller_usage();
         }
     }
 }
 
+/// `serialkiller run FILE`: runs a pself file under a session from
+/// `permission-manager`.
+fn handle_run(args: &[String]) {
+    let matches = ClapCommand::new("serialkiller run")
+        .no_binary_name(true)
+        .about("Run a pself executable")
+        .arg(Arg::new("file").value_name("PSELF").required(true).help("pself file to run"))
+        .args(token_args())
+        .arg(state_file_arg())
+        .arg(machine_secret_arg())
+        .get_matches_from(args);
+
+    let _session = presented_session(&matches, crate::runner::REQUIRED_CAPABILITY).unwrap_or_else(|e| {
+        eprintln!("[ERROR] {}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = crate::runner::run_pself(matches.get_one::<String>("file").unwrap()) {
+        eprintln!("[ERROR] {}", e);
+        std::process::exit(1);
+    }
+}
+
 fn handle_permission_manager(args: &[String]) {
+    match args.first().map(String::as_str) {
+        Some("add-user") => return handle_permission_add_user(&args[1..]),
+        Some("unlock") => return handle_permission_unlock(&args[1..]),
+        Some("revoke") => return handle_permission_revoke(&args[1..]),
+        Some("audit-verify") => return handle_permission_audit_verify(&args[1..]),
+        _ => {}
+    }
     let matches = ClapCommand::new("permission-cli")
+        .no_binary_name(true)
         .version("1.0")
         .author("Your Name")
         .about("Root permission and password verification CLI")
+        .after_help(permission_manager_help())
         .arg(
             Arg::new("user")
                 .short('u')
@@ -139,28 +2307,158 @@ fn handle_permission_manager(args: &[String]) {
                 .required(true)
                 .help("Specify username"),
         )
+        .arg(
+            Arg::new("users_file")
+                .long("users-file")
+                .value_name("FILE")
+                .help("Check the password against this users file instead of PAM"),
+        )
+        .arg(capability_arg().default_value("read-status").help("Capability the session is granted (repeatable); see below for what needs which"))
+        .arg(
+            Arg::new("required_group")
+                .long("required-group")
+                .value_name("GROUP")
+                .help("Only grant members of this group (default: the users file's required_group, else serialk-admins); empty for none"),
+        )
+        .arg(password_stdin_arg())
+        .arg(state_file_arg())
+        .arg(machine_secret_arg())
+        .arg(
+            Arg::new("max_failures")
+                .long("max-failures")
+                .value_name("N")
+                .default_value("2")
+                .value_parser(clap::value_parser!(usize))
+                .help("Wrong passwords within --failure-window that lock the user out"),
+        )
+        .arg(
+            Arg::new("failure_window")
+                .long("failure-window")
+                .value_name("SECS")
+                .default_value("900")
+                .value_parser(clap::value_parser!(u64))
+                .help("How far back failures count towards a lockout"),
+        )
+        .arg(
+            Arg::new("lock_duration")
+                .long("lock-duration")
+                .value_name("SECS")
+                .default_value("900")
+                .value_parser(clap::value_parser!(u64))
+                .help("How long a lockout lasts"),
+        )
+        .arg(
+            Arg::new("audit_log")
+                .long("audit-log")
+                .value_name("FILE")
+                .default_value(permission_audit::DEFAULT_AUDIT_LOG)
+                .help("HMAC-chained record of every request and its outcome"),
+        )
+        .arg(audit_key_arg().help("Key of the audit log's HMAC chain; a random one is made if missing"))
+        .arg(
+            Arg::new("session_ttl")
+                .long("session-ttl")
+                .value_name("SECS")
+                .default_value("900")
+                .value_parser(clap::value_parser!(u64))
+                .help("How long the session token of a grant stays valid"),
+        )
+        .arg(
+            Arg::new("token_file")
+                .long("token-file")
+                .value_name("FILE")
+                .help("Write the session token to this file, readable by its owner only, instead of printing it"),
+        )
+        .arg(
+            Arg::new("allow_unlogged")
+                .long("allow-unlogged")
+                .action(clap::ArgAction::SetTrue)
+                .help("Grant permission even if the audit log cannot be written"),
+        )
         .get_matches_from(args);
 
     let user = matches.get_one::<String>("user").expect("Username is required");
 
-    if !PermissionManager::is_root_user() {
-        eprintln!("Error: You must run this as root!");
+    if let Err(e) = permission_privilege::check_privileges(PERMISSION_MANAGER_PRIVILEGES) {
+        eprintln!("Error: {}.", e);
         std::process::exit(1);
     }
 
-    let manager = PermissionManager::new();
-
-    for attempt in 1..=2 {
-        print!("Enter password for user {} (attempt {}/2): ", user, attempt);
-        io::stdout().flush().unwrap();
+    let manager = match matches.get_one::<String>("users_file") {
+        Some(path) => PermissionManager::with_users_file(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
+        }),
+        None => PermissionManager::new(),
+    };
+    let policy = LockoutPolicy {
+        max_failures: *matches.get_one::<usize>("max_failures").unwrap(),
+        window: Duration::from_secs(*matches.get_one::<u64>("failure_window").unwrap()),
+        duration: Duration::from_secs(*matches.get_one::<u64>("lock_duration").unwrap()),
+    };
+    let lockout = Lockout::new(policy, Box::new(SystemClock));
+    let manager = match matches.get_one::<String>("required_group").map(String::as_str) {
+        None => manager,
+        Some("") => manager.with_required_group(None),
+        Some(group) => manager.with_required_group(RequiredGroup::new(group, Box::new(SystemGroups))),
+    };
+    let ttl = Duration::from_secs(*matches.get_one::<u64>("session_ttl").unwrap());
+    let manager = manager.with_lockout(lockout).with_sessions(Sessions::new(ttl, Box::new(SystemClock)));
+    let manager = open_state_file(&matches).and_then(|file| manager.with_state_file(file)).unwrap_or_else(|e| {
+        eprintln!("[ERROR] {}", e);
+        std::process::exit(1);
+    });
+    let allow_unlogged = matches.get_flag("allow_unlogged");
+    let audit_log = permission_audit::open_log(
+        Path::new(matches.get_one::<String>("audit_log").unwrap()),
+        Path::new(matches.get_one::<String>("audit_key").unwrap()),
+    );
+    let manager = match audit_log {
+        Ok(log) => manager.with_audit(Auditor::new(Box::new(log), Requester::current(), allow_unlogged)),
+        Err(e) if allow_unlogged => {
+            eprintln!("[WARN] {}; requests are not recorded", e);
+            manager
+        }
+        Err(e) => {
+            eprintln!("[ERROR] {}; refusing to grant permission without a record (see --allow-unlogged)", e);
+            std::process::exit(1);
+        }
+    };
+    let source = password_source(&matches);
+    let requested: Vec<Capability> = matches.get_many::<Capability>("capability").unwrap_or_default().copied().collect();
 
-        let mut password = String::new();
-        io::stdin().read_line(&mut password).unwrap();
-        let password = password.trim();
+    for attempt in 1..=permission_manager::MAX_ATTEMPTS {
+        let password = read_password(
+            source,
+            &format!("Enter password for user {} (attempt {}/{}): ", user, attempt, permission_manager::MAX_ATTEMPTS),
+        );
 
-        if manager.request_permission(user, password) {
-            println!("Permission granted.");
-            return;
+        match manager.request_permission(user, &password, &requested) {
+            Ok(token) => {
+                let expires = format!("expires in {}", permission_lockout::remaining(ttl));
+                match matches.get_one::<String>("token_file") {
+                    Some(path) => match write_token_file(Path::new(path), &token) {
+                        Ok(()) => println!("Permission granted. Session token written to {} ({}).", path, expires),
+                        Err(e) => {
+                            eprintln!("[ERROR] Cannot write session token to {}: {}", path, e);
+                            std::process::exit(1);
+                        }
+                    },
+                    None => println!("Permission granted.\nSession token: {} ({})", token, expires),
+                }
+                return;
+            }
+            // Another try may get it right; nothing else changes by retrying.
+            Err(AuthError::AuthFailed) => eprintln!("Permission denied: {}.", AuthError::AuthFailed),
+            Err(AuthError::Locked { until }) => {
+                let left = until.duration_since(SystemTime::now()).unwrap_or_default();
+                eprintln!("Permission denied: too many failed attempts; try again in {}.", permission_lockout::remaining(left));
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Permission denied: {}.", e);
+                std::process::exit(1);
+            }
         }
     }
 
@@ -168,41 +2466,297 @@ fn handle_permission_manager(args: &[String]) {
     std::process::exit(1);
 }
 
-// -- Utilities --
+/// Root, and not in a container that took away root's access to files it
+/// does not own, such as the users and state files.
+const PERMISSION_MANAGER_PRIVILEGES: &[Privilege] = &[Privilege::Root, Privilege::DacOverride];
+
+fn handle_permission_add_user(args: &[String]) {
+    let matches = ClapCommand::new("permission-manager add-user")
+        .no_binary_name(true)
+        .about("Add a user to a users file, or change their password")
+        .arg(Arg::new("name").value_name("NAME").required(true).help("User to add"))
+        .arg(
+            Arg::new("users_file")
+                .long("users-file")
+                .value_name("FILE")
+                .default_value(permission_users::DEFAULT_USERS_FILE)
+                .help("Users file to write; created with mode 0600 if missing"),
+        )
+        .arg(
+            Arg::new("role")
+                .long("role")
+                .value_name("ROLE")
+                .action(clap::ArgAction::Append)
+                .help("Role of the user (repeatable); an existing user keeps theirs if none is given"),
+        )
+        .arg(capability_arg().help(
+            "Capability the user may be granted (repeatable); a new user without any may be granted all, an existing one keeps theirs",
+        ))
+        .arg(password_stdin_arg())
+        .args(token_args().map(|arg| arg.help("Session of a user who may manage users; not needed for the first user of a users file")))
+        .arg(state_file_arg())
+        .arg(machine_secret_arg())
+        .get_matches_from(args);
+
+    let name = matches.get_one::<String>("name").unwrap();
+    let path = Path::new(matches.get_one::<String>("users_file").unwrap());
+    let roles: Vec<String> = matches.get_many::<String>("role").unwrap_or_default().cloned().collect();
+    let capabilities: Option<Vec<Capability>> = matches.get_many::<Capability>("capability").map(|given| given.copied().collect());
 
-struct KdvVerifier {
-    fingerprints: HashMap<String, Vec<u8>>,
+    let mut file = permission_users::UsersFile::load_or_default(path).unwrap_or_else(|e| {
+        eprintln!("[ERROR] {}", e);
+        std::process::exit(1);
+    });
+    // Someone has to be first.
+    let _session = (!file.users.is_empty()).then(|| {
+        presented_session(&matches, permission_users::REQUIRED_CAPABILITY).unwrap_or_else(|e| {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
+        })
+    });
+    let source = password_source(&matches);
+    let password = read_password(source, &format!("New password for {}: ", name));
+    if password.is_empty() {
+        eprintln!("[ERROR] Empty password, nothing written");
+        std::process::exit(1);
+    }
+    // A script sends the password once.
+    let confirm = !matches.get_flag("password_stdin");
+    if confirm && read_password(source, &format!("Retype new password for {}: ", name)) != password {
+        eprintln!("[ERROR] Passwords do not match, nothing written");
+        std::process::exit(1);
+    }
+    let added = file.set_password(name, &password, &roles, capabilities.as_deref()).and_then(|added| {
+        file.save(path).map_err(|e| format!("Cannot write users file {}: {}", path.display(), e))?;
+        Ok(added)
+    });
+    match added {
+        Ok(true) => println!("Added user {} to {}", name, path.display()),
+        Ok(false) => println!("Changed the password of {} in {}", name, path.display()),
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-impl KdvVerifier {
-    fn new() -> Self {
-        Self {
-            fingerprints: HashMap::new(),
+fn handle_permission_unlock(args: &[String]) {
+    let matches = ClapCommand::new("permission-manager unlock")
+        .no_binary_name(true)
+        .about("Lift a user's lockout and forget their failed attempts")
+        .arg(Arg::new("name").value_name("NAME").required(true).help("User to unlock"))
+        .arg(state_file_arg())
+        .arg(machine_secret_arg())
+        .get_matches_from(args);
+
+    if let Err(e) = permission_privilege::check_privileges(PERMISSION_MANAGER_PRIVILEGES) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+    let name = matches.get_one::<String>("name").unwrap();
+    let unlocked = open_state_file(&matches)
+        .and_then(|file| PermissionManager::new().with_state_file(file))
+        .and_then(|manager| manager.unlock(name));
+    match unlocked {
+        Ok(true) => println!("Unlocked {}", name),
+        Ok(false) => println!("{} has no failed attempts on record", name),
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
         }
     }
+}
+
+/// `permission-manager revoke --token T`: ends a session before its TTL.
+fn handle_permission_revoke(args: &[String]) {
+    let matches = ClapCommand::new("permission-manager revoke")
+        .no_binary_name(true)
+        .about("End a session before its TTL")
+        .args(token_args())
+        .group(clap::ArgGroup::new("session").args(["token", "token_file"]).required(true))
+        .arg(state_file_arg())
+        .arg(machine_secret_arg())
+        .get_matches_from(args);
 
-    fn load_initial_fingerprints(&mut self, sections: &[(String, Vec<u8>)]) {
-        for (name, content) in sections {
-            println!("Loading fingerprint for {}", name);
-            self.fingerprints.insert(name.clone(), content.clone());
+    if let Err(e) = permission_privilege::check_privileges(PERMISSION_MANAGER_PRIVILEGES) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+    let revoked = presented_token(&matches).and_then(|token| Ok(session_manager(&matches)?.revoke(&token)));
+    match revoked {
+        Ok(true) => println!("Revoked the session"),
+        Ok(false) => println!("No live session has that token"),
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            std::process::exit(1);
         }
     }
+}
 
-    fn verify(&self, name: &str, content: &[u8]) {
-        match self.fingerprints.get(name) {
-            Some(fingerprint) if fingerprint == content => {
-                println!("[VERIFY] {} matches fingerprint ✅", name);
-            }
-            Some(_) => {
-                println!("[VERIFY] {} does NOT match fingerprint ❌", name);
-            }
-            None => {
-                println!("[VERIFY] No fingerprint found for {}", name);
-            }
+/// `permission-manager audit-verify FILE...`: checks the HMAC chain of the
+/// permission audit log, rotated files given oldest first.
+fn handle_permission_audit_verify(args: &[String]) {
+    let matches = ClapCommand::new("permission-manager audit-verify")
+        .no_binary_name(true)
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
+                .num_args(1..)
+                .default_value(permission_audit::DEFAULT_AUDIT_LOG)
+                .help("Audit log files, oldest rotation first"),
+        )
+        .arg(audit_key_arg().help("The key given to --audit-key"))
+        .get_matches_from(args);
+
+    let key = match serialk_gate::read_key_file(Path::new(matches.get_one::<String>("audit_key").unwrap()), "audit key") {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let files: Vec<PathBuf> = matches.get_many::<String>("files").unwrap().map(PathBuf::from).collect();
+    match serialk_audit::verify_files(&files, &key) {
+        Ok(lines) => println!("[AUDIT] OK: {} lines in {} file(s)", lines, files.len()),
+        Err(e) => {
+            println!("[AUDIT] FAILED at {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn audit_key_arg() -> Arg {
+    Arg::new("audit_key")
+        .long("audit-key")
+        .value_name("FILE")
+        .default_value(permission_audit::DEFAULT_AUDIT_KEY)
+}
+
+fn permission_manager_help() -> String {
+    let mut help = "Passwords are checked by PAM's serialkiller service; install pam.d/serialkiller as /etc/pam.d/serialkiller. Linux only.\n\nWith --users-file they are checked against the argon2id hashes of that file instead; see permission-manager add-user. Its users may only be granted the capabilities listed for them.\n\nCapabilities:".to_string();
+    for (command, capability) in permission_capability::required_by() {
+        help.push_str(&format!("\n  {:<18} {}", capability, command));
+    }
+    help
+}
+
+fn capability_arg() -> Arg {
+    Arg::new("capability")
+        .long("capability")
+        .value_name("CAPABILITY")
+        .action(clap::ArgAction::Append)
+        .value_parser(|name: &str| name.parse::<Capability>())
+}
+
+/// Replaces `path` with `token`, readable by its owner only from the start.
+fn write_token_file(path: &Path, token: &SessionToken) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let _ = fs::remove_file(&temporary);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&temporary)?.write_all(format!("{}\n", token).as_bytes())?;
+    fs::rename(&temporary, path)
+}
+
+/// `--token` and `--token-file`: the session from `permission-manager` a
+/// privileged command runs under.
+fn token_args() -> [Arg; 2] {
+    [
+        Arg::new("token")
+            .long("token")
+            .value_name("TOKEN")
+            .conflicts_with("token_file")
+            .help("Session token printed by permission-manager"),
+        Arg::new("token_file")
+            .long("token-file")
+            .value_name("FILE")
+            .help("File permission-manager --token-file wrote the session token to"),
+    ]
+}
+
+fn presented_token(matches: &clap::ArgMatches) -> Result<SessionToken, String> {
+    let token = match (matches.get_one::<String>("token"), matches.get_one::<String>("token_file")) {
+        (Some(token), _) => token.clone(),
+        (None, Some(path)) => fs::read_to_string(path).map_err(|e| format!("Cannot read session token {}: {}", path, e))?,
+        (None, None) => return Err("No session; get one from permission-manager and pass --token or --token-file".to_string()),
+    };
+    token.parse()
+}
+
+/// The sessions in the state file, pruned for as long as the command runs.
+fn session_manager(matches: &clap::ArgMatches) -> Result<Arc<PermissionManager>, String> {
+    let manager = Arc::new(open_state_file(matches).and_then(|file| PermissionManager::new().with_state_file(file))?);
+    permission_session::spawn_pruner(&manager, permission_session::PRUNE_INTERVAL);
+    Ok(manager)
+}
+
+/// The session given with `--token` or `--token-file`, if it is live and
+/// was granted `capability`, and the manager that checked it.
+fn presented_session(matches: &clap::ArgMatches, capability: Capability) -> Result<(Arc<PermissionManager>, SessionToken), String> {
+    let token = presented_token(matches)?;
+    let manager = session_manager(matches)?;
+    match manager.validate(&token) {
+        SessionStatus::Valid { .. } => {}
+        SessionStatus::Expired => return Err("The session has expired; get a new one from permission-manager".to_string()),
+        SessionStatus::Unknown => return Err("No live session has that token".to_string()),
+    }
+    manager.check_capability(&token, capability).map_err(|e| format!("Permission denied: {}", e))?;
+    Ok((manager, token))
+}
+
+fn state_file_arg() -> Arg {
+    Arg::new("state_file")
+        .long("state-file")
+        .value_name("FILE")
+        .default_value(permission_state::DEFAULT_STATE_FILE)
+        .help("Encrypted file where failed attempts, lockouts and sessions are kept across runs")
+}
+
+fn machine_secret_arg() -> Arg {
+    Arg::new("machine_secret")
+        .long("machine-secret")
+        .value_name("FILE")
+        .default_value(permission_state::DEFAULT_MACHINE_SECRET)
+        .help("Root-only secret the state file's key is derived from; a random one is made if missing")
+}
+
+fn open_state_file(matches: &clap::ArgMatches) -> Result<StateFile, String> {
+    StateFile::open(
+        Path::new(matches.get_one::<String>("state_file").unwrap()),
+        Path::new(matches.get_one::<String>("machine_secret").unwrap()),
+    )
+}
+
+fn password_stdin_arg() -> Arg {
+    Arg::new("password_stdin")
+        .long("password-stdin")
+        .action(clap::ArgAction::SetTrue)
+        .help("Read passwords from stdin, one per line, without prompting")
+}
+
+/// Where the command's passwords come from.
+fn password_source(matches: &clap::ArgMatches) -> PasswordSource {
+    PasswordSource::detect(matches.get_flag("password_stdin"))
+}
+
+fn read_password(source: PasswordSource, prompt: &str) -> Password {
+    match source.read(prompt) {
+        Ok(password) => password,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => std::process::exit(130),
+        Err(e) => {
+            eprintln!("[ERROR] Cannot read password: {}", e);
+            std::process::exit(1);
         }
     }
 }
 
+// -- Utilities --
+
 struct HfsHunter<F>
 where
     F: Fn(String) + Send + Sync + 'static,
@@ -235,30 +2789,21 @@ where
     }
 }
 
-fn load_files_as_sections(paths: &[String]) -> Vec<(String, Vec<u8>)> {
-    paths.iter()
-        .filter_map(|path| {
-            fs::read(path)
-                .map(|content| (path.clone(), content))
-                .map_err(|e| {
-                    eprintln!("Error reading {}: {}", path, e);
-                    e
-                })
-                .ok()
-        })
-        .collect()
-}
-
 #[allow(dead_code)]
 async fn example_kdv_and_hfs_flow(paths: &[String]) {
-    let sections = load_files_as_sections(paths);
-
-    let mut verifier = KdvVerifier::new();
-    verifier.load_initial_fingerprints(&sections);
+    let mut verifier = kdv::KdvVerifier::new();
+    for path in paths {
+        if let Err(e) = verifier.add_file(Path::new(path)) {
+            eprintln!("Error reading {}: {}", path, e);
+        }
+    }
 
     println!("\n[VERIFYING AGAIN]");
-    for (name, content) in &sections {
-        verifier.verify(name, content);
+    for path in paths {
+        match verifier.verify_path(Path::new(path)) {
+            Ok(result) => println!("[VERIFY] {} {}", path, result),
+            Err(e) => eprintln!("Error reading {}: {}", path, e),
+        }
     }
 
     let hunter = HfsHunter::new(
@@ -0,0 +1,65 @@
+//! Fixtures shared by the permission modules' tests.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::permission_lockout::{Clock, Lockout, LockoutPolicy};
+use crate::permission_manager::{AuthError, Authenticator, PermissionManager};
+use crate::permission_session::Sessions;
+use crate::permission_state::StateFile;
+
+/// A clock that only moves when told to.
+#[derive(Clone)]
+pub struct MockClock(Arc<Mutex<SystemTime>>);
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000))))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Knows every user's password is "correct horse".
+pub struct Fixed;
+
+impl Authenticator for Fixed {
+    fn authenticate(&self, _user: &str, password: &str) -> Result<(), AuthError> {
+        match password == "correct horse" {
+            true => Ok(()),
+            false => Err(AuthError::AuthFailed),
+        }
+    }
+}
+
+/// The state file of a manager started on `dir`.
+pub fn state_file(dir: &Path) -> StateFile {
+    StateFile::open(&dir.join("state/permission-state"), &dir.join("machine-secret")).unwrap()
+}
+
+/// A manager as a new process starts one: passwords checked by
+/// `authenticator`, lockouts under `policy` and 60-second sessions on
+/// `clock`, continuing from the state in `dir` if given.
+pub fn start_manager(
+    authenticator: impl Authenticator + 'static,
+    policy: LockoutPolicy,
+    clock: &MockClock,
+    dir: Option<&Path>,
+) -> PermissionManager {
+    let manager = PermissionManager::with_authenticator(Box::new(authenticator))
+        .with_lockout(Lockout::new(policy, Box::new(clock.clone())))
+        .with_sessions(Sessions::new(Duration::from_secs(60), Box::new(clock.clone())));
+    match dir {
+        Some(dir) => manager.with_state_file(state_file(dir)).unwrap(),
+        None => manager,
+    }
+}
@@ -0,0 +1,16 @@
+# Source of sections.elf, a static x86-64 ELF with one byte-aligned
+# .text, .rodata and .data each and no symbols:
+#   gcc -nostdlib -static -no-pie -Wl,--build-id=none -Wl,-N -s -o sections.elf sections.s
+# .text starts at file offset 0x78 and is 9 bytes long.
+    .globl _start
+    .text
+_start:
+    mov $60, %eax
+    xor %edi, %edi
+    syscall
+    .section .rodata
+greeting:
+    .ascii "serialk fixture\n"
+    .data
+counter:
+    .quad 42
@@ -0,0 +1,308 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn permission_manager(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .arg("permission-manager")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn is_root() -> bool {
+    Command::new("id").arg("-u").output().is_ok_and(|output| output.stdout == b"0\n")
+}
+
+#[test]
+fn add_user_writes_a_private_argon2id_users_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let add = ["add-user", "alice", "--users-file", "etc/users.toml", "--role", "admin"];
+
+    let mismatch = permission_manager(root, &add, b"correct horse\nbattery\n");
+    assert_eq!(mismatch.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&mismatch.stderr).contains("Passwords do not match"));
+    assert!(!root.join("etc/users.toml").exists());
+
+    let added = permission_manager(root, &add, b"correct horse\ncorrect horse\n");
+    assert_eq!(added.status.code(), Some(0), "{}", String::from_utf8_lossy(&added.stderr));
+    assert!(String::from_utf8_lossy(&added.stdout).contains("Added user alice"));
+    assert!(String::from_utf8_lossy(&added.stderr).contains("[WARN] stdin is not a terminal"));
+    let path = root.join("etc/users.toml");
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    let file: toml::Value = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let user = &file["user"][0];
+    assert_eq!(user["name"].as_str(), Some("alice"));
+    assert!(user["hash"].as_str().unwrap().starts_with("$argon2id$"));
+    assert_eq!(user["roles"][0].as_str(), Some("admin"));
+    assert!(!fs::read_to_string(&path).unwrap().contains("correct horse"));
+
+    // Checking passwords needs root.
+    if is_root() {
+        let login = [
+            "-u",
+            "alice",
+            "--users-file",
+            "etc/users.toml",
+            "--required-group",
+            "",
+            "--state-file",
+            "permission.state",
+            "--machine-secret",
+            "machine.secret",
+            "--max-failures",
+            "3",
+            "--audit-log",
+            "audit.log",
+            "--audit-key",
+            "audit.key",
+        ];
+        let granted = permission_manager(root, &login, b"correct horse\n");
+        assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+        let stdout = String::from_utf8_lossy(&granted.stdout);
+        assert!(stdout.contains("Permission granted."));
+        let token = stdout.lines().find_map(|line| line.strip_prefix("Session token: ")).unwrap();
+        assert_eq!(token.split(' ').next().unwrap().len(), 32, "{}", token);
+        assert!(token.ends_with("(expires in 15m 00s)"));
+
+        let mut to_file = login.to_vec();
+        to_file.extend(["--token-file", "token", "--session-ttl", "60", "--capability", "manage-users"]);
+        let written = permission_manager(root, &to_file, b"correct horse\n");
+        assert_eq!(written.status.code(), Some(0), "{}", String::from_utf8_lossy(&written.stderr));
+        assert!(String::from_utf8_lossy(&written.stdout).contains("written to token (expires in 1m 00s)"));
+        assert_eq!(fs::metadata(root.join("token")).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(root.join("token")).unwrap().trim().len(), 32);
+
+        let wrong = permission_manager(root, &login, b"battery\nstaple\n");
+        let mut unknown = login;
+        unknown[1] = "mallory";
+        let unknown = permission_manager(root, &unknown, b"battery\nstaple\n");
+        assert_eq!(wrong.status.code(), Some(1));
+        assert_eq!(wrong.stderr, unknown.stderr);
+    }
+
+    // Once there is a user, adding another takes a session.
+    let unsessioned = permission_manager(root, &["add-user", "carol", "--users-file", "etc/users.toml", "--password-stdin"], b"staple\n");
+    assert_eq!(unsessioned.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unsessioned.stderr).contains("No session"));
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o664)).unwrap();
+    let refused = permission_manager(root, &add, b"staple\nstaple\n");
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("must not be writable by group or others"));
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    // Alice's session came from the root-only login above.
+    if !is_root() {
+        return;
+    }
+    let session = ["--token-file", "token", "--state-file", "permission.state", "--machine-secret", "machine.secret"];
+    let mut add_carol = vec!["add-user", "carol", "--users-file", "etc/users.toml", "--capability", "read-status", "--password-stdin"];
+    add_carol.extend(session);
+    let limited = permission_manager(root, &add_carol, b"staple\n");
+    assert_eq!(limited.status.code(), Some(0), "{}", String::from_utf8_lossy(&limited.stderr));
+    assert!(fs::read_to_string(&path).unwrap().contains(r#"capabilities = ["read-status"]"#));
+    let login = ["-u", "carol", "--users-file", "etc/users.toml", "--required-group", "", "--state-file", "permission.state", "--machine-secret", "machine.secret", "--audit-log", "audit.log", "--audit-key", "audit.key"];
+    let mut baseline = login.to_vec();
+    baseline.extend(["--capability", "update-baseline"]);
+    let denied = permission_manager(root, &baseline, b"staple\n");
+    assert_eq!(denied.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&denied.stderr).contains("Permission denied: missing capability update-baseline."));
+    let granted = permission_manager(root, &login, b"staple\n");
+    assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+
+    // A script says so and sends the password once, without prompts or warnings.
+    let mut add_bob = vec!["add-user", "bob", "--users-file", "etc/users.toml", "--password-stdin"];
+    add_bob.extend(session);
+    let scripted = permission_manager(root, &add_bob, b"staple\n");
+    assert_eq!(scripted.status.code(), Some(0), "{}", String::from_utf8_lossy(&scripted.stderr));
+    assert!(scripted.stderr.is_empty(), "{}", String::from_utf8_lossy(&scripted.stderr));
+    assert_eq!(String::from_utf8_lossy(&scripted.stdout).trim(), "Added user bob to etc/users.toml");
+
+    let revoke = ["revoke", "--token-file", "token", "--state-file", "permission.state", "--machine-secret", "machine.secret"];
+    let revoked = permission_manager(root, &revoke, b"");
+    assert_eq!(String::from_utf8_lossy(&revoked.stdout).trim(), "Revoked the session");
+    let again = permission_manager(root, &revoke, b"");
+    assert_eq!(String::from_utf8_lossy(&again.stdout).trim(), "No live session has that token");
+    let mut add_dave = vec!["add-user", "dave", "--users-file", "etc/users.toml", "--password-stdin"];
+    add_dave.extend(session);
+    let revoked_session = permission_manager(root, &add_dave, b"staple\n");
+    assert_eq!(revoked_session.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&revoked_session.stderr).contains("No live session has that token"));
+}
+
+#[test]
+fn lockout_holds_across_runs_until_unlocked() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let added = permission_manager(root, &["add-user", "alice", "--users-file", "users.toml"], b"correct horse\ncorrect horse\n");
+    assert_eq!(added.status.code(), Some(0), "{}", String::from_utf8_lossy(&added.stderr));
+    let login = ["-u", "alice", "--users-file", "users.toml", "--required-group", "", "--state-file", "permission.state", "--machine-secret", "machine.secret", "--audit-log", "audit.log", "--audit-key", "audit.key"];
+
+    let locked = permission_manager(root, &login, b"battery\nstaple\n");
+    assert_eq!(locked.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&locked.stderr);
+    assert!(stderr.contains("too many failed attempts; try again in 1"), "{}", stderr);
+    let state = fs::read(root.join("permission.state")).unwrap();
+    assert!(!state.windows(5).any(|window| window == b"alice"), "the state file is encrypted");
+    assert_eq!(fs::metadata(root.join("machine.secret")).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let still = permission_manager(root, &login, b"correct horse\n");
+    assert_eq!(still.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&still.stderr).contains("too many failed attempts"));
+
+    let unlock = permission_manager(root, &["unlock", "alice", "--state-file", "permission.state", "--machine-secret", "machine.secret"], b"");
+    assert!(String::from_utf8_lossy(&unlock.stdout).contains("Unlocked alice"));
+    let granted = permission_manager(root, &login, b"correct horse\n");
+    assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+}
+
+#[test]
+fn requests_are_audited_and_unrecordable_grants_refused() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let added = permission_manager(root, &["add-user", "alice", "--users-file", "users.toml"], b"correct horse\ncorrect horse\n");
+    assert_eq!(added.status.code(), Some(0), "{}", String::from_utf8_lossy(&added.stderr));
+    let login = |log: &'static str| {
+        ["-u", "alice", "--users-file", "users.toml", "--required-group", "", "--state-file", "permission.state", "--machine-secret", "machine.secret", "--audit-log", log, "--audit-key", "audit.key", "--max-failures", "5"]
+    };
+
+    let granted = permission_manager(root, &login("log/audit.log"), b"battery\ncorrect horse\n");
+    assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+    let audit = fs::read_to_string(root.join("log/audit.log")).unwrap();
+    let outcomes: Vec<String> = audit
+        .lines()
+        .skip(1)
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["outcome"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(outcomes, ["denied", "granted"]);
+    assert_eq!(fs::metadata(root.join("audit.key")).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let verified = permission_manager(root, &["audit-verify", "log/audit.log", "--audit-key", "audit.key"], b"");
+    assert_eq!(verified.status.code(), Some(0), "{}", String::from_utf8_lossy(&verified.stdout));
+    assert!(String::from_utf8_lossy(&verified.stdout).contains("[AUDIT] OK: 3 lines in 1 file(s)"));
+    fs::write(root.join("log/audit.log"), audit.replace("\"denied\"", "\"granted\"")).unwrap();
+    let tampered = permission_manager(root, &["audit-verify", "log/audit.log", "--audit-key", "audit.key"], b"");
+    assert_eq!(tampered.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&tampered.stdout).contains("[AUDIT] FAILED at log/audit.log:2"));
+
+    // Even root cannot create a log below a regular file.
+    fs::write(root.join("not-a-dir"), "").unwrap();
+    let refused = permission_manager(root, &login("not-a-dir/audit.log"), b"correct horse\n");
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("refusing to grant permission without a record"));
+    let mut unlogged = login("not-a-dir/audit.log").to_vec();
+    unlogged.push("--allow-unlogged");
+    let allowed = permission_manager(root, &unlogged, b"correct horse\n");
+    assert_eq!(allowed.status.code(), Some(0), "{}", String::from_utf8_lossy(&allowed.stderr));
+    assert!(String::from_utf8_lossy(&allowed.stderr).contains("requests are not recorded"));
+}
+
+#[test]
+fn only_members_of_the_required_group_are_granted() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let added = permission_manager(root, &["add-user", "root", "--users-file", "users.toml"], b"correct horse\ncorrect horse\n");
+    assert_eq!(added.status.code(), Some(0), "{}", String::from_utf8_lossy(&added.stderr));
+    let login = ["-u", "root", "--users-file", "users.toml", "--state-file", "permission.state", "--machine-secret", "machine.secret", "--audit-log", "audit.log", "--audit-key", "audit.key"];
+
+    // Without a serialk-admins group nobody is in it.
+    let refused = permission_manager(root, &login, b"correct horse\n");
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Permission denied: not a member of the required group."));
+
+    let mut in_root = login.to_vec();
+    in_root.extend(["--required-group", "root"]);
+    let granted = permission_manager(root, &in_root, b"correct horse\n");
+    assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+
+    let mut file = fs::read_to_string(root.join("users.toml")).unwrap();
+    file.insert_str(0, "required_group = \"root\"\n");
+    fs::write(root.join("users.toml"), file).unwrap();
+    let from_file = permission_manager(root, &login, b"correct horse\n");
+    assert_eq!(from_file.status.code(), Some(0), "{}", String::from_utf8_lossy(&from_file.stderr));
+
+    let audit: Vec<serde_json::Value> =
+        fs::read_to_string(root.join("audit.log")).unwrap().lines().skip(1).map(|line| serde_json::from_str(line).unwrap()).collect();
+    let checks: Vec<_> = audit.iter().map(|line| (line["group"].as_str().unwrap(), line["in_group"].as_bool().unwrap())).collect();
+    assert_eq!(checks, [("serialk-admins", false), ("root", true), ("root", true)]);
+}
+
+#[test]
+fn privileged_commands_refuse_a_session_without_their_capability() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let added = permission_manager(root, &["add-user", "carol", "--users-file", "users.toml", "--capability", "read-status", "--password-stdin"], b"staple\n");
+    assert_eq!(added.status.code(), Some(0), "{}", String::from_utf8_lossy(&added.stderr));
+    let state = ["--state-file", "permission.state", "--machine-secret", "machine.secret"];
+    let mut login = vec!["-u", "carol", "--users-file", "users.toml", "--required-group", "", "--audit-log", "audit.log", "--audit-key", "audit.key", "--token-file", "carol.token"];
+    login.extend(["--capability", "read-status"]);
+    login.extend(state);
+    let granted = permission_manager(root, &login, b"staple\n");
+    assert_eq!(granted.status.code(), Some(0), "{}", String::from_utf8_lossy(&granted.stderr));
+
+    let pself = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sections.pself");
+    let run = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialkiller", "run", pself.to_str().unwrap(), "--token-file", "carol.token"])
+        .args(state)
+        .output()
+        .unwrap();
+    assert_eq!(run.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("Permission denied: missing capability execute-pself"));
+
+    let mut add_dave = vec!["add-user", "dave", "--users-file", "users.toml", "--password-stdin", "--token-file", "carol.token"];
+    add_dave.extend(state);
+    let refused = permission_manager(root, &add_dave, b"staple\n");
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Permission denied: missing capability manage-users"));
+    assert!(!fs::read_to_string(root.join("users.toml")).unwrap().contains("dave"));
+
+    // The first baseline needs no session; replacing or updating it does.
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    let kdv = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+            .current_dir(root)
+            .args(["serialkiller", "kdv"])
+            .args(args)
+            .args(state)
+            .output()
+            .unwrap()
+    };
+    let init = kdv(&["init", "-b", "baseline.json", "app.conf"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    let baseline = fs::read_to_string(root.join("baseline.json")).unwrap();
+    fs::write(root.join("app.conf"), "port = 8081\n").unwrap();
+    let anonymous = kdv(&["init", "-b", "baseline.json", "app.conf"]);
+    assert_eq!(anonymous.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&anonymous.stderr).contains("No session"));
+    for command in [&["init", "app.conf"][..], &["update", "--yes", "app.conf"]] {
+        let refused = kdv(&[command, &["-b", "baseline.json", "--token-file", "carol.token"]].concat());
+        assert_eq!(refused.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&refused.stderr).contains("Permission denied: missing capability update-baseline"));
+    }
+    assert_eq!(fs::read_to_string(root.join("baseline.json")).unwrap(), baseline);
+}
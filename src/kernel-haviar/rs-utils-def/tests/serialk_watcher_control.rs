@@ -0,0 +1,217 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Where the watcher checks control sessions, in the test's directory.
+const STATE: [&str; 4] = ["--state-file", "permission.state", "--machine-secret", "machine.secret"];
+
+fn is_root() -> bool {
+    Command::new("id").arg("-u").output().is_ok_and(|output| output.stdout == b"0\n")
+}
+
+/// Adds `user` with `capabilities` to a users file in `dir` and logs them
+/// in, leaving their session in `<user>.token`.
+fn login(dir: &Path, user: &str, capabilities: &[&str]) {
+    let permission_manager = |args: Vec<&str>| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+            .current_dir(dir)
+            .arg("permission-manager")
+            .args(args)
+            .args(STATE)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"staple\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    };
+    let granted: Vec<&str> = capabilities.iter().flat_map(|capability| ["--capability", capability]).collect();
+    let mut add = vec!["add-user", user, "--users-file", "users.toml", "--password-stdin"];
+    add.extend(&granted);
+    permission_manager(add);
+    let token_file = format!("{}.token", user);
+    let mut login = vec!["-u", user, "--users-file", "users.toml", "--required-group", "", "--token-file", &token_file];
+    login.extend(["--audit-log", "audit.log", "--audit-key", "audit.key"]);
+    login.extend(granted);
+    permission_manager(login);
+}
+
+fn ctl(dir: &Path, user: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .args(["serialk-watcher", "ctl", "--socket", "control.sock", "--token-file", &format!("{}.token", user)])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn control_socket_adds_lists_and_removes() {
+    // Logging in checks a password, which needs root.
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    login(root, "operator", &["read-status", "modify-watch-set"]);
+    fs::write(root.join("a.conf"), "a\n").unwrap();
+    fs::write(root.join("b.conf"), "b\n").unwrap();
+    let socket = root.join("control.sock");
+
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialk-watcher", "--include", "a.conf", "--control-socket", "control.sock"])
+        .args(STATE)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !socket.exists() {
+        assert!(Instant::now() < deadline, "control socket never appeared");
+        sleep(Duration::from_millis(50));
+    }
+    assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+
+    // Paths are sent as given and resolved relative to the watcher's directory.
+    assert!(stdout(&ctl(root, "operator", &["add", "b.conf", "--liner", "2"])).contains("\"ok\":true"));
+
+    let listed = stdout(&ctl(root, "operator", &["list"]));
+    assert!(listed.contains("a.conf") && listed.contains("b.conf"), "{}", listed);
+
+    stdout(&ctl(root, "operator", &["remove", "a.conf"]));
+    let listed = stdout(&ctl(root, "operator", &["list"]));
+    assert!(!listed.contains("a.conf") && listed.contains("b.conf"), "{}", listed);
+
+    let missing = ctl(root, "operator", &["remove", "a.conf"]);
+    assert_eq!(missing.status.code(), Some(1));
+
+    Command::new("kill")
+        .args(["-TERM", &watcher.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(watcher.wait().unwrap().success());
+    assert!(!socket.exists());
+}
+
+#[test]
+fn control_socket_refuses_commands_the_session_was_not_granted() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a.conf"), "a\n").unwrap();
+    login(root, "carol", &["read-status"]);
+
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialk-watcher", "--include", "a.conf", "--control-socket", "control.sock"])
+        .args(STATE)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !root.join("control.sock").exists() {
+        assert!(Instant::now() < deadline, "control socket never appeared");
+        sleep(Duration::from_millis(50));
+    }
+
+    assert!(stdout(&ctl(root, "carol", &["list"])).contains("a.conf"));
+    for command in [&["add", "b.conf"][..], &["kdv-reload"]] {
+        let refused = ctl(root, "carol", command);
+        assert_eq!(refused.status.code(), Some(1));
+        let answer = String::from_utf8_lossy(&refused.stdout);
+        assert!(answer.contains("Permission denied: missing capability modify-watch-set"), "{}", answer);
+    }
+    let listed = stdout(&ctl(root, "carol", &["list"]));
+    assert!(!listed.contains("b.conf"), "{}", listed);
+
+    let anonymous = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialk-watcher", "ctl", "--socket", "control.sock", "list"])
+        .output()
+        .unwrap();
+    assert_eq!(anonymous.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&anonymous.stderr).contains("No session"));
+
+    Command::new("kill")
+        .args(["-TERM", &watcher.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(watcher.wait().unwrap().success());
+}
+
+#[test]
+fn kdv_daemon_alerts_once_through_the_json_sink() {
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    login(root, "operator", &["read-status", "modify-watch-set"]);
+    fs::write(root.join("watched.txt"), "w\n").unwrap();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    let init = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialkiller", "kdv", "init", "-b", "baseline.json", "app.conf"])
+        .output()
+        .unwrap();
+    assert!(init.status.success(), "{}", String::from_utf8_lossy(&init.stderr));
+
+    let mut watcher = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialk-watcher", "--no-self-protect", "--json", "--include", "watched.txt"])
+        .args(["--kdv-daemon", "baseline.json", "--kdv-daemon-interval", "1", "--control-socket", "control.sock"])
+        .args(STATE)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let output = watcher.stdout.take().unwrap();
+    let (tx, lines) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let _ = tx.send(line.unwrap());
+        }
+    });
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !root.join("control.sock").exists() {
+        assert!(Instant::now() < deadline, "control socket never appeared");
+        sleep(Duration::from_millis(50));
+    }
+
+    fs::write(root.join("app.conf"), "port = 8081\n").unwrap();
+    let alert = loop {
+        let line = lines.recv_timeout(deadline.saturating_duration_since(Instant::now())).expect("no kdv alert");
+        if line.contains(r#""event":"kdv""#) {
+            break line;
+        }
+    };
+    let alert: serde_json::Value = serde_json::from_str(&alert).unwrap();
+    assert!(alert["path"].as_str().unwrap().ends_with("app.conf"), "{}", alert);
+    assert_eq!(alert["severity"], "critical");
+
+    // More rounds, on the timer and on demand, find the same divergence.
+    assert!(stdout(&ctl(root, "operator", &["kdv-verify"])).contains("\"scheduled\":true"));
+    assert!(stdout(&ctl(root, "operator", &["kdv-reload"])).contains("\"entries\":1"));
+    sleep(Duration::from_millis(2500));
+    Command::new("kill")
+        .args(["-TERM", &watcher.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(watcher.wait().unwrap().success());
+    let later: Vec<String> = lines.try_iter().filter(|line| line.contains(r#""event":"kdv""#)).collect();
+    assert!(later.is_empty(), "{:?}", later);
+}
@@ -0,0 +1,71 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn watcher(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .arg("serialk-watcher")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn daemon_writes_pid_file_and_stops_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("watched.txt"), "hello\n").unwrap();
+    let pid_file = root.join("serialk.pid");
+    let log_file = root.join("serialk.log");
+
+    let started = watcher(
+        root,
+        &["--daemon", "--pid-file", "serialk.pid", "--log-file", "serialk.log", "--include", "watched.txt"],
+    );
+    assert!(started.status.success(), "{}", String::from_utf8_lossy(&started.stderr));
+
+    wait_for("pid file", || {
+        fs::read_to_string(&pid_file).is_ok_and(|pid| pid.trim().parse::<u32>().is_ok())
+    });
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+    assert!(Command::new("kill").args(["-0", &pid]).status().unwrap().success());
+    wait_for("watcher start", || {
+        fs::read_to_string(&log_file).is_ok_and(|log| log.contains("Included:"))
+    });
+
+    let second = watcher(root, &["--daemon", "--pid-file", "serialk.pid", "--include", "watched.txt"]);
+    assert_eq!(second.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&second.stderr).contains("already running"));
+
+    let stopped = watcher(root, &["--stop", "--pid-file", "serialk.pid"]);
+    assert!(stopped.status.success(), "{}", String::from_utf8_lossy(&stopped.stderr));
+    assert!(!pid_file.exists());
+    let log = fs::read_to_string(&log_file).unwrap();
+    // The watched file plus the watcher's own executable.
+    assert!(log.contains("[SUMMARY] files watched: 2"), "log was: {}", log);
+}
+
+#[test]
+fn stop_removes_stale_pid_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join("stale.pid");
+    fs::write(&pid_file, "999999\n").unwrap();
+
+    let stopped = watcher(dir.path(), &["--stop", "--pid-file", "stale.pid"]);
+    assert!(stopped.status.success());
+    assert!(String::from_utf8_lossy(&stopped.stdout).contains("stale"));
+    assert!(!pid_file.exists());
+}
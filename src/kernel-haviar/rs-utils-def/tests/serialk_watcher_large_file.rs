@@ -0,0 +1,57 @@
+#![cfg(target_os = "linux")]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const GIB: u64 = 1 << 30;
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        sleep(Duration::from_millis(50));
+    }
+}
+
+/// Peak resident set size of `pid` in kilobytes.
+fn peak_rss_kb(pid: u32) -> u64 {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn large_file_update_keeps_memory_bounded() {
+    let dir = tempfile::tempdir().unwrap();
+    let big = dir.path().join("db.bin");
+    // Sparse, so the test costs no disk space.
+    File::create(&big).unwrap().set_len(2 * GIB).unwrap();
+    let log_file = dir.path().join("serialk.log");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir.path())
+        .args(["serialk-watcher", "--no-self-protect", "--tamper-action", "log"])
+        .args(["--large-file-threshold", "1048576", "--include"])
+        .arg(&big)
+        .stdout(Stdio::from(File::create(&log_file).unwrap()))
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let log = || fs::read_to_string(&log_file).unwrap_or_default();
+    wait_for("watcher start", || log().contains("Included:"));
+
+    let mut file = OpenOptions::new().write(true).open(&big).unwrap();
+    file.seek(SeekFrom::Start(GIB)).unwrap();
+    file.write_all(&[0xa5; 4096]).unwrap();
+    drop(file);
+    wait_for("modification", || log().contains("[MODIFIED]"));
+
+    let peak = peak_rss_kb(child.id());
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(log().contains("bytes 0x40000000.."), "log was: {}", log());
+    assert!(peak < 256 * 1024, "peak RSS {} kB for a 2 GiB file", peak);
+}
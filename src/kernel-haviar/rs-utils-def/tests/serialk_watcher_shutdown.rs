@@ -0,0 +1,39 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+
+#[test]
+fn sigterm_prints_summary_and_exits_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("watched.txt");
+    fs::write(&file, "hello\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir.path())
+        .args(["serialk-watcher", "--no-self-protect", "--include"])
+        .arg(&file)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    while stdout.read_line(&mut line).unwrap() > 0 && !line.contains("Included:") {
+        line.clear();
+    }
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let exit = child.wait().unwrap();
+
+    assert!(rest.contains("[SUMMARY] files watched: 1"), "output was: {}", rest);
+    assert_eq!(exit.code(), Some(0));
+}
@@ -0,0 +1,75 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn hfs(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .args(["serialkiller", "hfs"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn daemon_logs_violations_and_stops_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let pid_file = root.join("hfs.pid");
+    let log_file = root.join("hfs.log");
+    // A process under a name only this test uses.
+    let target = root.join("hfsdaemontarget");
+    fs::copy("/bin/sleep", &target).unwrap();
+    let mut sleeper = Command::new(&target).arg("30").spawn().unwrap();
+
+    let daemon = ["--daemon", "--pid-file", "hfs.pid", "--log-file", "hfs.log", "^hfsdaemontarget$"];
+    let started = hfs(root, &daemon);
+    assert!(started.status.success(), "{}", String::from_utf8_lossy(&started.stderr));
+    assert!(started.stdout.is_empty());
+
+    wait_for("pid file", || {
+        fs::read_to_string(&pid_file).is_ok_and(|pid| pid.trim().parse::<u32>().is_ok())
+    });
+    let pid = fs::read_to_string(&pid_file).unwrap().trim().to_string();
+    assert!(Command::new("kill").args(["-0", &pid]).status().unwrap().success());
+    let violation = format!("PID={}", sleeper.id());
+    wait_for("violation", || fs::read_to_string(&log_file).is_ok_and(|log| log.contains(&violation)));
+
+    let second = hfs(root, &daemon);
+    assert_eq!(second.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&second.stderr).contains("serialkiller hfs is already running"));
+
+    let stopped = hfs(root, &["--stop", "--pid-file", "hfs.pid"]);
+    assert!(stopped.status.success(), "{}", String::from_utf8_lossy(&stopped.stderr));
+    assert!(!pid_file.exists());
+    sleeper.kill().unwrap();
+    sleeper.wait().unwrap();
+
+    let log = fs::read_to_string(&log_file).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines[0].contains(&format!("[HFS] Daemon started (pid {})", pid)), "log was: {}", log);
+    assert!(lines.last().unwrap().ends_with("[HFS] Daemon stopped"), "log was: {}", log);
+    // Every line starts with a UTC timestamp.
+    assert!(lines.iter().all(|line| line.len() > 21 && &line[10..11] == "T" && &line[19..21] == "Z "), "{}", log);
+}
+
+#[test]
+fn bad_pattern_is_reported_before_detaching() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = hfs(dir.path(), &["--daemon", "--pid-file", "hfs.pid", "--log-file", "hfs.log", "(unclosed"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output.stderr.is_empty());
+    assert!(!dir.path().join("hfs.pid").exists());
+}
@@ -0,0 +1,63 @@
+#![cfg(target_os = "linux")]
+
+use std::ffi::CString;
+use std::io::{BufRead, BufReader};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Any small shared library the test binary does not already link.
+const DONORS: &[&str] = &[
+    "/lib/x86_64-linux-gnu/libz.so.1",
+    "/usr/lib/x86_64-linux-gnu/libz.so.1",
+    "/lib/aarch64-linux-gnu/libz.so.1",
+    "/usr/lib64/libz.so.1",
+    "/usr/lib/libz.so.1",
+];
+
+#[test]
+fn scan_maps_reports_library_loaded_into_another_process() {
+    let Some(donor) = DONORS.iter().map(Path::new).find(|path| path.exists()) else {
+        eprintln!("no libz to copy; skipping");
+        return;
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let library = dir.path().join("libfrida-agent-hfstest.so");
+    std::fs::copy(donor, &library).unwrap();
+    let name = CString::new(library.as_os_str().as_bytes()).unwrap();
+    // SAFETY: a copy of a plain system library; its constructors are harmless.
+    let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW) };
+    assert!(!handle.is_null(), "dlopen {} failed", library.display());
+
+    let mut hfs = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .args(["serialkiller", "hfs", "--scan-maps", "--maps-every", "1", "frida-agent-hfstest"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = hfs.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let expected = format!("LIB={}, PID={},", library.display(), std::process::id());
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut seen = Vec::new();
+    let found = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(left) {
+            Ok(line) if line.contains(&expected) => break true,
+            Ok(line) => seen.push(line),
+            Err(_) => break false,
+        }
+    };
+    hfs.kill().unwrap();
+    hfs.wait().unwrap();
+    assert!(found, "no report for {}; output: {:?}", expected, seen);
+}
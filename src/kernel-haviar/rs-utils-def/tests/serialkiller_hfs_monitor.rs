@@ -0,0 +1,45 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+
+#[test]
+fn monitor_reports_until_interrupted_then_prints_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    // A process under a name only this test uses.
+    let target = dir.path().join("hfsmonitortarget");
+    fs::copy("/bin/sleep", &target).unwrap();
+    let mut sleeper = Command::new(&target).arg("60").spawn().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .args(["serialkiller", "hfs", "^hfsmonitortarget$"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let violation = format!("[HFS] Unauthorized process detected: PID={}", sleeper.id());
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    while !line.contains(&violation) {
+        line.clear();
+        assert!(stdout.read_line(&mut line).unwrap() > 0, "monitor exited before reporting");
+    }
+    assert!(child.try_wait().unwrap().is_none(), "monitor exited after reporting");
+
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let exit = child.wait().unwrap();
+    sleeper.kill().unwrap();
+    sleeper.wait().unwrap();
+
+    assert!(rest.contains("[SUMMARY] scans: "), "output was: {}", rest);
+    assert!(rest.contains(", violations: 1,"), "output was: {}", rest);
+    assert_eq!(exit.code(), Some(0));
+}
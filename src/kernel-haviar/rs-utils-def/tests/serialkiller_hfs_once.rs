@@ -0,0 +1,66 @@
+#![cfg(unix)]
+
+use std::process::{Command, Output};
+
+fn hfs_once(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .args(["serialkiller", "hfs", "--once"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// This test binary's name as the process table shows it.
+fn own_name() -> String {
+    let exe = std::env::current_exe().unwrap();
+    let name = exe.file_name().unwrap().to_str().unwrap();
+    // ps on macOS and comm on Linux may shorten it; its start is enough.
+    name[..name.len().min(15)].to_string()
+}
+
+#[test]
+fn clean_scan_exits_zero() {
+    let output = hfs_once(&["^no-such-process-hfs-once$"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn violation_exits_two_and_prints_json() {
+    let pattern = format!("^{}", regex_escape(&own_name()));
+    let output = hfs_once(&["--format", "json", &pattern]);
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let pid = std::process::id() as i64;
+    let ours = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|violation| violation["pid"] == pid)
+        .unwrap_or_else(|| panic!("no violation for pid {}: {}", pid, stdout));
+    assert_eq!(ours["kind"], "process");
+    assert_eq!(ours["pattern"], pattern.as_str());
+}
+
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c.to_string() } else { format!("\\{}", c) })
+        .collect()
+}
+
+#[test]
+fn monitor_never_matches_its_own_tree() {
+    // A copy under a name of its own, so the other tests' monitors cannot match.
+    let dir = tempfile::tempdir().unwrap();
+    let monitor = dir.path().join("hfs-self-test");
+    std::fs::copy(env!("CARGO_BIN_EXE_serialkiller-rs-stable"), &monitor).unwrap();
+    for action in ["log", "kill"] {
+        let output = Command::new(&monitor)
+            .args(["serialkiller", "hfs", "--once", "--action", action, "^hfs-self-test$"])
+            .output()
+            .unwrap();
+        // Killed by itself, it would have no exit code at all.
+        assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stdout));
+        assert!(output.stdout.is_empty(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+}
@@ -0,0 +1,841 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn kdv(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .args(["serialkiller", "kdv"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Where `kdv update` checks its session, in the test's directory.
+const STATE: [&str; 4] = ["--state-file", "permission.state", "--machine-secret", "machine.secret"];
+
+fn is_root() -> bool {
+    Command::new("id").arg("-u").output().is_ok_and(|output| output.stdout == b"0\n")
+}
+
+/// Adds `user` with `capabilities` to a users file in `dir` and logs them
+/// in, leaving their session in `<user>.token`.
+fn login(dir: &Path, user: &str, capabilities: &[&str]) {
+    let permission_manager = |args: Vec<&str>| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+            .current_dir(dir)
+            .arg("permission-manager")
+            .args(args)
+            .args(STATE)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"staple\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    };
+    let granted: Vec<&str> = capabilities.iter().flat_map(|capability| ["--capability", capability]).collect();
+    let mut add = vec!["add-user", user, "--users-file", "users.toml", "--password-stdin"];
+    add.extend(&granted);
+    permission_manager(add);
+    let token_file = format!("{}.token", user);
+    let mut login = vec!["-u", user, "--users-file", "users.toml", "--required-group", "", "--token-file", &token_file];
+    login.extend(["--audit-log", "audit.log", "--audit-key", "audit.key"]);
+    login.extend(granted);
+    permission_manager(login);
+}
+
+#[test]
+fn modification_between_init_and_check_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    fs::write(root.join("app.bin"), "\x7fELF....").unwrap();
+
+    let init = kdv(root, &["init", "-b", "baseline.json", "app.conf", "app.bin"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("baseline.json")).unwrap()).unwrap();
+    assert_eq!(manifest["version"], 1);
+    assert_eq!(manifest["entries"].as_array().unwrap().len(), 2);
+
+    let clean = kdv(root, &["check", "-b", "baseline.json"]);
+    assert_eq!(clean.status.code(), Some(0), "{}", String::from_utf8_lossy(&clean.stdout));
+    assert!(String::from_utf8_lossy(&clean.stdout).contains("[KDV] 2 verified, 0 changed"));
+
+    fs::write(root.join("app.conf"), "port = 8081\n").unwrap();
+    let tampered = kdv(root, &["check", "-b", "baseline.json"]);
+    let stdout = String::from_utf8_lossy(&tampered.stdout);
+    assert_eq!(tampered.status.code(), Some(2), "{}", stdout);
+    let conf = fs::canonicalize(root.join("app.conf")).unwrap();
+    assert!(stdout.contains(&format!("[CHANGED] {}", conf.display())), "{}", stdout);
+    assert!(stdout.contains("[KDV] 1 verified, 1 changed, 0 missing, 0 added, 0 errors"), "{}", stdout);
+}
+
+#[test]
+fn check_reports_missing_and_added_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a"), "a").unwrap();
+    fs::write(root.join("b"), "b").unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "a"]).status.success());
+
+    fs::remove_file(root.join("a")).unwrap();
+    let output = kdv(root, &["check", "-b", "baseline.json", "a", "b"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout.contains("[MISSING] ") && stdout.contains("[ADDED] "), "{}", stdout);
+}
+
+#[test]
+fn check_can_stop_at_the_first_failure_and_allow_unknown_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for name in ["a", "b", "c", "new"] {
+        fs::write(root.join(name), name).unwrap();
+    }
+    assert!(kdv(root, &["init", "-b", "baseline.json", "a", "b", "c"]).status.success());
+
+    let lenient = kdv(root, &["check", "-b", "baseline.json", "--allow-unknown", "a", "b", "c", "new"]);
+    let stdout = String::from_utf8_lossy(&lenient.stdout);
+    assert_eq!(lenient.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("[ADDED] ") && stdout.contains("[KDV] 3 verified"), "{}", stdout);
+
+    fs::write(root.join("a"), "edited").unwrap();
+    let stopped = kdv(root, &["check", "-b", "baseline.json", "--fail-fast", "--jobs", "1"]);
+    let stdout = String::from_utf8_lossy(&stopped.stdout);
+    assert_eq!(stopped.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("[KDV] 0 verified, 1 changed") && stdout.contains(", 2 skipped"), "{}", stdout);
+    assert!(stdout.contains("[KDV] Read "), "{}", stdout);
+}
+
+#[test]
+fn directory_baseline_validates_under_another_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("srv/app/logs")).unwrap();
+    fs::write(root.join("srv/app/run.sh"), "#!/bin/sh\n").unwrap();
+    fs::write(root.join("srv/app/logs/today.log"), "noise\n").unwrap();
+
+    let init = kdv(root, &["init", "-b", "baseline.json", "--root", "srv", "--exclude", "logs", "srv/app"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("baseline.json")).unwrap()).unwrap();
+    let paths: Vec<&str> = manifest["entries"].as_array().unwrap().iter().map(|e| e["path"].as_str().unwrap()).collect();
+    assert_eq!(paths, ["app/run.sh"]);
+
+    fs::rename(root.join("srv"), root.join("mnt")).unwrap();
+    let check = kdv(root, &["check", "-b", "baseline.json", "--root", "mnt"]);
+    assert_eq!(check.status.code(), Some(0), "{}", String::from_utf8_lossy(&check.stdout));
+}
+
+#[test]
+fn each_algorithm_round_trips_through_init_and_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    for algo in ["sha256", "sha512", "blake3"] {
+        let baseline = format!("{}.json", algo);
+        let init = kdv(root, &["init", "-b", &baseline, "--algo", algo, "app.conf"]);
+        assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join(&baseline)).unwrap()).unwrap();
+        assert_eq!(manifest["entries"][0]["algorithm"], algo);
+        let check = kdv(root, &["check", "-b", &baseline]);
+        assert_eq!(check.status.code(), Some(0), "{}: {}", algo, String::from_utf8_lossy(&check.stdout));
+    }
+    let unknown = kdv(root, &["init", "-b", "baseline.json", "--algo", "md5", "app.conf"]);
+    assert_eq!(unknown.status.code(), Some(2));
+}
+
+#[test]
+fn keyed_baseline_checks_only_with_its_key() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    for (name, secret) in [("right.key", "correct horse"), ("wrong.key", "battery staple")] {
+        fs::write(root.join(name), secret).unwrap();
+        fs::set_permissions(root.join(name), fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    let init = kdv(root, &["init", "-b", "baseline.json", "--key-file", "right.key", "app.conf"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("baseline.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][0]["algorithm"], "hmac-sha256");
+
+    let right = kdv(root, &["check", "-b", "baseline.json", "--key-file", "right.key"]);
+    assert_eq!(right.status.code(), Some(0), "{}", String::from_utf8_lossy(&right.stdout));
+    let wrong = kdv(root, &["check", "-b", "baseline.json", "--key-file", "wrong.key"]);
+    assert_eq!(wrong.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&wrong.stdout).contains("[KDV] 0 verified, 1 changed"));
+    let missing = kdv(root, &["check", "-b", "baseline.json"]);
+    assert_eq!(missing.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("pass --key-file"));
+
+    fs::set_permissions(root.join("right.key"), fs::Permissions::from_mode(0o644)).unwrap();
+    let exposed = kdv(root, &["check", "-b", "baseline.json", "--key-file", "right.key"]);
+    assert_eq!(exposed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&exposed.stderr).contains("it must be 0600"));
+}
+
+#[test]
+fn exit_code_tells_violations_from_missing_files_and_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a"), "a").unwrap();
+    fs::write(root.join("b"), "b").unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "a", "b"]).status.success());
+    let check = |args: &[&str]| {
+        let output = kdv(root, &[&["check", "-b", "baseline.json"][..], args].concat());
+        (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    assert_eq!(check(&[]).0, Some(0));
+    fs::remove_file(root.join("b")).unwrap();
+    assert_eq!(check(&[]).0, Some(3));
+    fs::write(root.join("a"), "A").unwrap();
+    // Tampering outranks the missing file.
+    let (code, stdout) = check(&["--json"]);
+    assert_eq!(code, Some(2));
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(report["summary"]["changed"], 1);
+    assert_eq!(report["summary"]["missing"], 1);
+    let statuses: Vec<&str> = report["files"].as_array().unwrap().iter().map(|f| f["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, ["changed", "missing"]);
+    assert!(report["files"][0]["path"].as_str().unwrap().ends_with("/a"));
+
+    fs::write(root.join("baseline.json"), "{ not json").unwrap();
+    assert_eq!(check(&[]).0, Some(1));
+}
+
+#[test]
+fn diff_compares_two_baselines_without_the_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.bin"), "v1").unwrap();
+    fs::write(root.join("app.conf"), "port = 1").unwrap();
+    assert!(kdv(root, &["init", "-b", "old.json", "app.bin", "app.conf"]).status.success());
+    fs::write(root.join("app.bin"), "v2").unwrap();
+    assert!(kdv(root, &["init", "-b", "new.json", "app.bin", "app.conf"]).status.success());
+    fs::remove_file(root.join("app.bin")).unwrap();
+
+    let same = kdv(root, &["diff", "old.json", "old.json"]);
+    assert_eq!(same.status.code(), Some(0));
+    let drift = kdv(root, &["diff", "old.json", "new.json"]);
+    let stdout = String::from_utf8_lossy(&drift.stdout);
+    assert_eq!(drift.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("[HASH] ") && stdout.contains("app.bin: "), "{}", stdout);
+    assert!(stdout.contains("[KDV] 1 unchanged, 0 added, 0 removed, 1 hash changed"), "{}", stdout);
+
+    let ignored = kdv(root, &["diff", "old.json", "new.json", "--ignore", "*.bin", "--json"]);
+    assert_eq!(ignored.status.code(), Some(0));
+    let report: serde_json::Value = serde_json::from_slice(&ignored.stdout).unwrap();
+    assert_eq!(report["unchanged"], 1);
+    assert_eq!(report["differences"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn quick_check_says_how_many_files_it_did_not_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a"), "a").unwrap();
+    fs::write(root.join("b"), "b").unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "a", "b"]).status.success());
+
+    let quick = kdv(root, &["check", "-b", "baseline.json", "--quick"]);
+    assert_eq!(quick.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&quick.stdout).contains(", 2 quick-passed"));
+    let paranoid = kdv(root, &["check", "-b", "baseline.json", "--paranoid"]);
+    assert!(String::from_utf8_lossy(&paranoid.stdout).contains("[KDV] 2 verified, 0 changed"));
+}
+
+#[test]
+fn pself_check_flags_exactly_the_corrupted_section() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    // Sections linux, windows and macos, 8, 8 and 4 bytes long.
+    let mut data = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sections.pself")).unwrap();
+    fs::write(root.join("app.pself"), &data).unwrap();
+    let clean = kdv(root, &["--pself", "app.pself", "--export-baseline", "sections.json"]);
+    let stdout = String::from_utf8_lossy(&clean.stdout);
+    assert_eq!(clean.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("[KDV] 3 sections verified, 0 mismatched, 0 out of range"), "{}", stdout);
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("sections.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][1]["path"], "windows");
+
+    // Second byte of the "windows" section.
+    let at = 12 + 3 * 73 + 8 + 1;
+    data[at] ^= 0xff;
+    fs::write(root.join("app.pself"), &data).unwrap();
+    let corrupt = kdv(root, &["--pself", "app.pself"]);
+    let stdout = String::from_utf8_lossy(&corrupt.stdout);
+    assert_eq!(corrupt.status.code(), Some(2), "{}", stdout);
+    let flagged: Vec<&str> = stdout.lines().filter(|line| !line.starts_with("[KDV]")).collect();
+    assert_eq!(flagged, ["[MISMATCH] windows"]);
+
+    let fast = kdv(root, &["--pself", "app.pself", "--fail-fast", "--json"]);
+    assert_eq!(fast.status.code(), Some(2));
+    let report: serde_json::Value = serde_json::from_slice(&fast.stdout).unwrap();
+    assert_eq!(report["results"][0], serde_json::json!({ "name": "linux", "status": "verified" }));
+    assert_eq!(report["results"][1]["status"], "mismatch");
+    assert_eq!((report["results"].as_array().unwrap().len(), report["skipped"].as_u64()), (2, Some(1)));
+    assert_eq!(report["bytes_hashed"], 16);
+}
+
+#[test]
+fn watch_alerts_once_per_transition() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    assert_eq!(kdv(root, &["init", "-b", "baseline.json", "app.conf"]).status.code(), Some(0));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(root)
+        .args(["serialkiller", "kdv", "check", "-b", "baseline.json", "--watch", "--interval", "1"])
+        .args(["--state-file", "state.json"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The state file is written at the end of every round.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !root.join("state.json").exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+    fs::write(root.join("app.conf"), "port = 8081\n").unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    let conf = fs::canonicalize(root.join("app.conf")).unwrap();
+    assert_eq!(line.trim_end(), format!("[CHANGED] {} (was OK)", conf.display()));
+    // Two more rounds with the file still changed.
+    thread::sleep(Duration::from_millis(2500));
+
+    let status = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let exit = child.wait().unwrap();
+
+    assert!(!rest.contains("[CHANGED]"), "output was: {}", rest);
+    assert!(rest.contains("rounds and 1 alerts; last round: 0 verified, 1 changed"), "output was: {}", rest);
+    assert_eq!(exit.code(), Some(2));
+    let state: serde_json::Value = serde_json::from_slice(&fs::read(root.join("state.json")).unwrap()).unwrap();
+    assert_eq!(state["files"][conf.to_str().unwrap()], "CHANGED");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn seal_travels_with_the_file_and_catches_tampering() {
+    // tmpfs keeps user extended attributes.
+    let dir = tempfile::tempdir_in("/dev/shm").unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.bin"), "\x7fELF....").unwrap();
+
+    let seal = kdv(root, &["seal", "app.bin"]);
+    assert_eq!(seal.status.code(), Some(0), "{}", String::from_utf8_lossy(&seal.stderr));
+    let clean = kdv(root, &["check", "--xattr", "app.bin"]);
+    assert_eq!(clean.status.code(), Some(0), "{}", String::from_utf8_lossy(&clean.stdout));
+
+    fs::write(root.join("app.bin"), "\x7fELF..!.").unwrap();
+    let tampered = kdv(root, &["check", "--xattr", "app.bin"]);
+    let stdout = String::from_utf8_lossy(&tampered.stdout);
+    assert_eq!(tampered.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("[CHANGED] app.bin"), "{}", stdout);
+
+    // Sealing again must be asked for by name.
+    let again = kdv(root, &["seal", "app.bin"]);
+    assert_eq!(again.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&again.stderr).contains("use kdv reseal"));
+    assert_eq!(kdv(root, &["check", "--xattr", "app.bin"]).status.code(), Some(2));
+    assert_eq!(kdv(root, &["reseal", "--algo", "blake3", "app.bin"]).status.code(), Some(0));
+    assert_eq!(kdv(root, &["check", "--xattr", "app.bin"]).status.code(), Some(0));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn seal_explains_filesystems_without_extended_attributes() {
+    let dir = tempfile::tempdir().unwrap();
+    // procfs refuses user extended attributes, like FAT does.
+    let seal = kdv(dir.path(), &["seal", "/proc/version"]);
+    let stderr = String::from_utf8_lossy(&seal.stderr);
+    assert_eq!(seal.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("/proc/version: the filesystem does not support extended attributes"), "{}", stderr);
+
+    let check = kdv(dir.path(), &["check", "--xattr", "/proc/version"]);
+    let stdout = String::from_utf8_lossy(&check.stdout);
+    assert_eq!(check.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.contains("[ERROR] /proc/version: the filesystem does not support extended attributes"), "{}", stdout);
+}
+
+#[test]
+fn by_section_baseline_names_the_patched_section() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let mut elf = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sections.elf")).unwrap();
+    fs::write(root.join("app.elf"), &elf).unwrap();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    let init = kdv(root, &["init", "-b", "baseline.json", "--root", ".", "--by-section", "app.elf", "app.conf"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+
+    // Inside .text, which starts at 0x78; see sections.s.
+    elf[0x79] ^= 0xff;
+    fs::write(root.join("app.elf"), &elf).unwrap();
+    fs::write(root.join("app.conf"), "port = 8081\n").unwrap();
+    let check = kdv(root, &["check", "-b", "baseline.json", "--root", "."]);
+    let stdout = String::from_utf8_lossy(&check.stdout);
+    assert_eq!(check.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("[CHANGED] app.elf: .text changed; .rodata, .data intact"), "{}", stdout);
+    assert!(stdout.contains("[CHANGED] app.conf\n"), "{}", stdout);
+
+    let json = kdv(root, &["check", "-b", "baseline.json", "--root", ".", "--json"]);
+    let report: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    let elf_report = report["files"].as_array().unwrap().iter().find(|f| f["path"] == "app.elf").unwrap();
+    assert_eq!(elf_report["status"], "changed");
+    assert_eq!(elf_report["detail"]["changed"], serde_json::json!([".text"]));
+}
+
+/// Serves `body` to every request on a local port; returns its URL.
+fn serve(body: Vec<u8>) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/baseline.json", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = BufReader::new(&stream);
+            let mut line = String::new();
+            while request.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+            std::io::Write::write_all(&mut stream, &[head.as_bytes(), &body].concat()).unwrap();
+        }
+    });
+    url
+}
+
+#[test]
+fn fetched_baseline_must_match_its_pin() {
+    use sha2::{Digest, Sha256};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    assert_eq!(kdv(root, &["init", "-b", "baseline.json", "app.conf"]).status.code(), Some(0));
+    let manifest = fs::read(root.join("baseline.json")).unwrap();
+    let pin = hex::encode(Sha256::digest(&manifest));
+    let url = serve(manifest);
+    let check = |args: &[&str]| kdv(root, &[&["check", "-b", &url, "--cache-dir", "cache"], args].concat());
+
+    let good = check(&["--pin-sha256", &pin]);
+    assert_eq!(good.status.code(), Some(0), "{}", String::from_utf8_lossy(&good.stderr));
+    assert!(String::from_utf8_lossy(&good.stdout).contains("[KDV] 1 verified"));
+
+    let unpinned = check(&[]);
+    assert_eq!(unpinned.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unpinned.stderr).contains("pass --pin-sha256"));
+
+    let wrong = hex::encode(Sha256::digest(b"another manifest"));
+    let bad = kdv(root, &["check", "-b", &url, "--cache-dir", "other-cache", "--pin-sha256", &wrong]);
+    let stderr = String::from_utf8_lossy(&bad.stderr);
+    assert_eq!(bad.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("does not match --pin-sha256; refusing to use it"), "{}", stderr);
+    assert!(!root.join("other-cache").exists());
+}
+
+#[test]
+fn stale_cached_baseline_is_used_only_when_allowed() {
+    use sha2::{Digest, Sha256};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    assert_eq!(kdv(root, &["init", "-b", "baseline.json", "app.conf"]).status.code(), Some(0));
+    let manifest = fs::read(root.join("baseline.json")).unwrap();
+    let pin = hex::encode(Sha256::digest(&manifest));
+    let url = serve(manifest);
+    let fetched = kdv(root, &["check", "-b", &url, "--cache-dir", "cache", "--pin-sha256", &pin]);
+    assert_eq!(fetched.status.code(), Some(0), "{}", String::from_utf8_lossy(&fetched.stderr));
+
+    // The same cache, keyed by URL, for a server that is gone.
+    let gone = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let dead_url = format!("http://{}/baseline.json", gone);
+    let cached = fs::read_dir(root.join("cache")).unwrap().next().unwrap().unwrap().path();
+    let dead_cache = root.join("dead-cache");
+    fs::create_dir(&dead_cache).unwrap();
+    let name = format!("{}.json", hex::encode(&Sha256::digest(dead_url.as_bytes())[..8]));
+    fs::copy(cached, dead_cache.join(name)).unwrap();
+    let check = |args: &[&str]| {
+        kdv(root, &[&["check", "-b", &dead_url, "--cache-dir", "dead-cache", "--pin-sha256", &pin], args].concat())
+    };
+
+    let fresh = check(&[]);
+    assert_eq!(fresh.status.code(), Some(0), "{}", String::from_utf8_lossy(&fresh.stderr));
+    let refused = check(&["--max-age", "0"]);
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert_eq!(refused.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("Cannot fetch baseline"), "{}", stderr);
+    let stale = check(&["--max-age", "0", "--allow-stale"]);
+    let stderr = String::from_utf8_lossy(&stale.stderr);
+    assert_eq!(stale.status.code(), Some(0), "{}", stderr);
+    assert!(stderr.contains("using the copy cached"), "{}", stderr);
+}
+
+#[test]
+fn update_takes_in_only_the_selected_drift_and_keeps_a_backup() {
+    // Logging in checks a password, which needs root.
+    if !is_root() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    login(root, "operator", &["update-baseline"]);
+    fs::create_dir(root.join("etc")).unwrap();
+    fs::write(root.join("etc/a.conf"), "a = 1\n").unwrap();
+    fs::write(root.join("etc/b.conf"), "b = 1\n").unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "etc"]).status.success());
+    let original = fs::read(root.join("baseline.json")).unwrap();
+
+    fs::write(root.join("etc/a.conf"), "a = 2\n").unwrap();
+    fs::write(root.join("etc/b.conf"), "b = 2\n").unwrap();
+    fs::write(root.join("etc/new.conf"), "new = 1\n").unwrap();
+    let session = [&["--token-file", "operator.token"][..], &STATE].concat();
+    let update = [&["update", "-b", "baseline.json", "--only", "a.conf", "--only", "new.conf", "etc"][..], &session].concat();
+
+    let declined = kdv(root, &update);
+    let stdout = String::from_utf8_lossy(&declined.stdout);
+    assert_eq!(declined.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("a.conf") && stdout.contains("[ADD] ") && !stdout.contains("b.conf"), "{}", stdout);
+    assert!(stdout.contains("1 other changes do not match --only"), "{}", stdout);
+    assert_eq!(fs::read(root.join("baseline.json")).unwrap(), original);
+
+    let confirmed = kdv(root, &[&update[..], &["--yes"]].concat());
+    assert_eq!(confirmed.status.code(), Some(0), "{}", String::from_utf8_lossy(&confirmed.stderr));
+    let backups: Vec<_> = fs::read_dir(root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("baseline.json.bak."))
+        .collect();
+    assert_eq!(backups.len(), 1, "{:?}", backups);
+    assert_eq!(fs::read(root.join(&backups[0])).unwrap(), original);
+
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("baseline.json")).unwrap()).unwrap();
+    let record = &manifest["updates"][0];
+    assert_eq!((record["changed"].as_u64(), record["added"].as_u64(), record["removed"].as_u64()), (Some(1), Some(1), Some(0)));
+    assert!(record["by"].is_string() && record["at"].is_string());
+
+    let check = kdv(root, &["check", "-b", "baseline.json"]);
+    let stdout = String::from_utf8_lossy(&check.stdout);
+    assert_eq!(check.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("[KDV] 2 verified, 1 changed, 0 missing, 0 added"), "{}", stdout);
+    assert!(stdout.contains("b.conf"), "{}", stdout);
+}
+
+#[test]
+fn prove_and_verify_proof_attest_one_file_against_the_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for name in ["a", "b", "c"] {
+        fs::write(root.join(name), name).unwrap();
+    }
+    let init = kdv(root, &["init", "-b", "baseline.json", "--root", ".", "a", "b", "c"]);
+    let stdout = String::from_utf8_lossy(&init.stdout);
+    let merkle_root = stdout.lines().find_map(|line| line.strip_prefix("[KDV] Merkle root: ")).unwrap().to_string();
+    let check = kdv(root, &["check", "-b", "baseline.json"]);
+    assert!(String::from_utf8_lossy(&check.stdout).contains(&format!("Merkle root: {}", merkle_root)));
+
+    let prove = kdv(root, &["prove", "-b", "baseline.json", "b"]);
+    assert_eq!(prove.status.code(), Some(0), "{}", String::from_utf8_lossy(&prove.stderr));
+    fs::write(root.join("proof.json"), &prove.stdout).unwrap();
+    let verified = kdv(root, &["verify-proof", "proof.json", "--merkle-root", &merkle_root]);
+    assert_eq!(verified.status.code(), Some(0), "{}", String::from_utf8_lossy(&verified.stdout));
+
+    let other = "0".repeat(64);
+    assert_eq!(kdv(root, &["verify-proof", "proof.json", "--merkle-root", &other]).status.code(), Some(2));
+    assert_eq!(kdv(root, &["prove", "-b", "baseline.json", "d"]).status.code(), Some(1));
+}
+
+#[test]
+fn check_with_pubkey_refuses_unsigned_edited_and_foreign_baselines() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 80\n").unwrap();
+    for key in ["kdv.key", "other.key"] {
+        assert_eq!(kdv(root, &["keygen", key]).status.code(), Some(0));
+    }
+    assert_eq!(kdv(root, &["keygen", "kdv.key"]).status.code(), Some(1));
+    let init = kdv(root, &["init", "-b", "signed.json", "--sign-key", "kdv.key", "app.conf"]);
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    assert!(kdv(root, &["init", "-b", "unsigned.json", "app.conf"]).status.success());
+
+    let check = |baseline: &str, key: &str, extra: &[&str]| {
+        let output = kdv(root, &[&["check", "-b", baseline, "--pubkey", key][..], extra].concat());
+        (output.status.code(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+    assert_eq!(check("signed.json", "kdv.key.pub", &[]).0, Some(0));
+    let (code, stderr) = check("signed.json", "other.key.pub", &[]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("is signed by another key"), "{}", stderr);
+    let (code, stderr) = check("unsigned.json", "kdv.key.pub", &[]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("is not signed; refusing"), "{}", stderr);
+
+    let text = fs::read_to_string(root.join("signed.json")).unwrap();
+    let created = serde_json::from_str::<serde_json::Value>(&text).unwrap()["created_at"].as_str().unwrap().to_string();
+    fs::write(root.join("signed.json"), text.replace(&created, "2020-01-01T00:00:00Z")).unwrap();
+    let (code, stderr) = check("signed.json", "kdv.key.pub", &[]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("edited after it was signed"), "{}", stderr);
+    let (code, stderr) = check("signed.json", "kdv.key.pub", &["--insecure"]);
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains("[WARN]") && stderr.contains("--insecure"), "{}", stderr);
+}
+
+#[test]
+fn incremental_check_rehashes_the_modified_file_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for name in ["a", "b", "c"] {
+        fs::write(root.join(name), name).unwrap();
+    }
+    assert!(kdv(root, &["init", "-b", "baseline.json", "a", "b", "c"]).status.success());
+    let check = || {
+        let output = kdv(root, &["check", "-b", "baseline.json", "--incremental", "state.json"]);
+        (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let (code, stdout) = check();
+    assert_eq!(code, Some(0), "{}", stdout);
+    assert!(stdout.contains("Rehashed 3 of 3 files"), "{}", stdout);
+
+    fs::write(root.join("b"), "bb").unwrap();
+    let (code, stdout) = check();
+    assert_eq!(code, Some(2), "{}", stdout);
+    assert!(stdout.contains("Rehashed 1 of 3 files"), "{}", stdout);
+    assert!(stdout.contains("[CHANGED] ") && stdout.contains("2 quick-passed"), "{}", stdout);
+
+    let refused = kdv(root, &["check", "-b", "baseline.json", "--incremental", "baseline.json"]);
+    assert_eq!(refused.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("is not a kdv check state file"));
+}
+
+fn kdv_stdin(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir)
+        .args(["serialkiller", "kdv"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn paths_from_stdin_are_checked_as_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let names = ["with space.conf", "ünïcödé.conf", "plain.conf", "noise.log"];
+    for name in names {
+        fs::write(root.join(name), name).unwrap();
+    }
+    let init = kdv_stdin(root, &["init", "-b", "baseline.json", "-"], names.join("\n").as_bytes());
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    assert!(String::from_utf8_lossy(&init.stdout).contains("4 files fingerprinted"));
+
+    fs::write(root.join("with space.conf"), "changed").unwrap();
+    fs::remove_file(root.join("plain.conf")).unwrap();
+    let listed = ["with space.conf", "ünïcödé.conf", "plain.conf", "noise.log"];
+    for (separator, extra) in [("\n", &[][..]), ("\0", &["--null"][..])] {
+        let input = listed.join(separator) + separator;
+        let args = [&["check", "-b", "baseline.json", "--exclude", "*.log"][..], extra, &["-"]].concat();
+        let output = kdv_stdin(root, &args, input.as_bytes());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(output.status.code(), Some(2), "{}", stdout);
+        assert!(stdout.contains("[CHANGED] ") && stdout.contains("with space.conf"), "{}", stdout);
+        assert!(stdout.contains("[MISSING] ") && stdout.contains("plain.conf"), "{}", stdout);
+        assert!(stdout.contains("[KDV] 1 verified, 1 changed, 1 missing, 0 added, 0 errors"), "{}", stdout);
+    }
+
+    let empty = kdv_stdin(root, &["check", "-b", "baseline.json", "-"], b"");
+    assert_eq!(empty.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&empty.stdout).contains("No paths on stdin"));
+}
+
+#[test]
+fn dupes_reports_identical_files_as_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("tree/cache")).unwrap();
+    for (name, content) in [("tree/one", "same"), ("tree/two", "same"), ("tree/cache/three", "same"), ("tree/other", "diff")] {
+        fs::write(root.join(name), content).unwrap();
+    }
+    let output = kdv(root, &["dupes", "--json", "--exclude", "cache", "tree"]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["wasted"], 4);
+    let groups = report["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 1);
+    let paths: Vec<&str> = groups[0]["paths"].as_array().unwrap().iter().map(|path| path.as_str().unwrap()).collect();
+    assert!(paths.len() == 2 && paths[0].ends_with("tree/one") && paths[1].ends_with("tree/two"), "{:?}", paths);
+
+    let text = kdv(root, &["dupes", "--min-size", "5", "tree"]);
+    assert!(String::from_utf8_lossy(&text.stdout).contains("[KDV] 0 files hashed, 0 sets of duplicates, 0 bytes wasted"));
+}
+
+#[test]
+fn sha256sum_files_round_trip_through_import_and_export() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sums");
+    for entry in fs::read_dir(&fixtures).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), root.join(entry.file_name())).unwrap();
+    }
+    let sums = fs::read_to_string(root.join("SHA256SUMS")).unwrap();
+
+    let import = kdv(root, &["import", "--format", "sha256sum", "SHA256SUMS", "-b", "baseline.json", "--root", "."]);
+    assert_eq!(import.status.code(), Some(0), "{}", String::from_utf8_lossy(&import.stderr));
+    let export = kdv(root, &["export", "--format", "sha256sum", "-b", "baseline.json"]);
+    assert_eq!(String::from_utf8_lossy(&export.stdout), sums);
+    assert_eq!(kdv(root, &["check", "-b", "baseline.json"]).status.code(), Some(0));
+
+    // A sums file stands in for a baseline.
+    let clean = kdv(root, &["check", "-b", "SHA256SUMS"]);
+    assert_eq!(clean.status.code(), Some(0), "{}", String::from_utf8_lossy(&clean.stdout));
+    fs::write(root.join("with space.txt"), "tampered\n").unwrap();
+    let tampered = kdv(root, &["check", "-b", "SHA256SUMS"]);
+    let stdout = String::from_utf8_lossy(&tampered.stdout);
+    assert_eq!(tampered.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("with space.txt"), "{}", stdout);
+
+    let blake3 = kdv(root, &["export", "--format", "b3sum", "-b", "baseline.json"]);
+    assert_eq!(blake3.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&blake3.stderr).contains("is sha256; a b3sum file holds blake3 digests only"));
+}
+
+#[test]
+fn sha256sum_import_refuses_a_keyed_baseline() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("app.conf"), "port = 8080\n").unwrap();
+    fs::write(root.join("kdv.key"), "correct horse").unwrap();
+    fs::set_permissions(root.join("kdv.key"), fs::Permissions::from_mode(0o600)).unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "--key-file", "kdv.key", "app.conf"]).status.success());
+    let before = fs::read(root.join("baseline.json")).unwrap();
+
+    let sums = Command::new("sha256sum").arg("app.conf").current_dir(root).output().unwrap();
+    fs::write(root.join("SHA256SUMS"), &sums.stdout).unwrap();
+    let import = kdv(root, &["import", "SHA256SUMS", "-b", "baseline.json"]);
+    assert_eq!(import.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&import.stderr).contains("holds hmac-sha256 entries"));
+    assert_eq!(fs::read(root.join("baseline.json")).unwrap(), before);
+}
+
+#[test]
+fn max_bandwidth_caps_check_and_reports_throughput() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("a"), vec![1; 1 << 20]).unwrap();
+    fs::write(root.join("b"), vec![2; 1 << 20]).unwrap();
+    let init = kdv(root, &["init", "-b", "baseline.json", "a", "b"]);
+    assert!(String::from_utf8_lossy(&init.stdout).contains("[KDV] Read 2.0 MiB in "));
+
+    let started = Instant::now();
+    let check = kdv(root, &["check", "-b", "baseline.json", "--json", "--max-bandwidth", "4194304"]);
+    let elapsed = started.elapsed();
+    assert_eq!(check.status.code(), Some(0), "{}", String::from_utf8_lossy(&check.stderr));
+    assert!(elapsed >= Duration::from_millis(400), "2 MiB at 4 MiB/s took {:?}", elapsed);
+    let report: serde_json::Value = serde_json::from_slice(&check.stdout).unwrap();
+    assert_eq!(report["throughput"]["bytes"], 2 << 20);
+    assert!(report["throughput"]["bytes_per_sec"].as_u64().unwrap() <= 4_600_000, "{}", report["throughput"]);
+}
+
+#[test]
+fn check_embedded_needs_a_baseline_built_in() {
+    if option_env!("SERIALK_EMBED_BASELINE").is_some() || option_env!("SERIALK_EMBED_DIR").is_some() {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let output = kdv(dir.path(), &["check", "--embedded"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No baseline was embedded"));
+    let conflicting = kdv(dir.path(), &["check", "--embedded", "-b", "baseline.json"]);
+    assert_eq!(conflicting.status.code(), Some(2));
+}
+
+#[test]
+fn check_with_metadata_flags_chmod_and_chown() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("etc")).unwrap();
+    fs::write(root.join("etc/sshd_config"), "PermitRootLogin no\n").unwrap();
+    fs::set_permissions(root.join("etc/sshd_config"), fs::Permissions::from_mode(0o600)).unwrap();
+    assert!(kdv(root, &["init", "-b", "baseline.json", "--root", ".", "--with-metadata", "etc"]).status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("baseline.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][0]["metadata"]["mode"], 0o600);
+
+    fs::set_permissions(root.join("etc/sshd_config"), fs::Permissions::from_mode(0o666)).unwrap();
+    // Root can hand the file to someone else too; others only chmod.
+    let root_user = unsafe { libc::geteuid() } == 0;
+    if root_user {
+        std::os::unix::fs::chown(root.join("etc/sshd_config"), Some(65534), None).unwrap();
+    }
+    let output = kdv(root, &["check", "-b", "baseline.json"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(5), "{}", stdout);
+    assert!(stdout.contains("[METADATA] etc/sshd_config: mode 0600 -> 0666"), "{}", stdout);
+    assert_eq!(stdout.contains("uid 0 -> 65534"), root_user, "{}", stdout);
+    assert!(stdout.contains("0 changed, 0 missing, 0 added, 0 errors, 1 metadata changed"), "{}", stdout);
+
+    let json = kdv(root, &["check", "-b", "baseline.json", "--json"]);
+    let report: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    assert_eq!(report["summary"]["metadata"], 1);
+    assert_eq!(report["files"][0]["status"], "metadata");
+    assert_eq!(report["files"][0]["detail"][0]["attribute"], "mode");
+}
+
+#[test]
+fn bench_table_feeds_init_algo_auto() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("file"), "content").unwrap();
+
+    let bench = kdv(root, &["bench", "--size", "65536", "--duration", "0.05", "-j", "2", "--bench-file", "bench.json"]);
+    let stdout = String::from_utf8_lossy(&bench.stdout);
+    assert_eq!(bench.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("ALGORITHM") && stdout.contains("2 THREADS"), "{}", stdout);
+    let results: serde_json::Value = serde_json::from_slice(&fs::read(root.join("bench.json")).unwrap()).unwrap();
+    for algorithm in ["sha256", "sha512", "blake3"] {
+        assert!(stdout.lines().any(|line| line.starts_with(algorithm)), "{}", stdout);
+        let result = results["results"].as_array().unwrap().iter().find(|result| result["algorithm"] == algorithm).unwrap();
+        assert!(result["single_thread"].as_f64().unwrap() > 0.0 && result["multi_thread"].as_f64().unwrap() > 0.0);
+    }
+    let recommended = results["recommended"].as_str().unwrap();
+    assert!(stdout.contains(&format!("[KDV] Recommended: {}", recommended)), "{}", stdout);
+
+    let init = kdv(root, &["init", "-b", "auto.json", "--algo", "auto", "--bench-file", "bench.json", "file"]);
+    assert!(init.status.success(), "{}", String::from_utf8_lossy(&init.stderr));
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("auto.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][0]["algorithm"], recommended);
+
+    // Whatever the cache says goes, and without one init falls back to sha256.
+    let mut pinned = results.clone();
+    pinned["recommended"] = "sha512".into();
+    fs::write(root.join("bench.json"), pinned.to_string()).unwrap();
+    assert!(kdv(root, &["init", "-b", "pinned.json", "--algo", "auto", "--bench-file", "bench.json", "file"]).status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(root.join("pinned.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][0]["algorithm"], "sha512");
+    let fallback = kdv(root, &["init", "-b", "fallback.json", "--algo", "auto", "--bench-file", "none.json", "file"]);
+    assert!(fallback.status.success());
+    assert!(String::from_utf8_lossy(&fallback.stderr).contains("run kdv bench first. Using sha256"));
+}
@@ -0,0 +1,46 @@
+#![cfg(unix)]
+
+//! Kept apart from the other kdv tests: the peak RSS of waited-for children
+//! is per test process, so nothing else may spawn children here.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::Command;
+
+/// The largest peak resident set size of any waited-for child, in bytes.
+fn children_peak_rss() -> u64 {
+    // SAFETY: getrusage only writes the struct it is given.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) }, 0);
+    // Kilobytes on Linux, bytes on macOS.
+    let peak = usage.ru_maxrss as u64;
+    if cfg!(target_os = "macos") {
+        peak
+    } else {
+        peak * 1024
+    }
+}
+
+#[test]
+#[ignore = "hashes 2 GiB; run with --ignored"]
+fn hashing_a_file_larger_than_memory_allows_streams_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("disk.img");
+    let mut file = File::create(&image).unwrap();
+    // Sparse: 2 GiB of zeros that take no disk space.
+    file.set_len(2 << 30).unwrap();
+    file.write_all(b"boot").unwrap();
+    drop(file);
+
+    let init = Command::new(env!("CARGO_BIN_EXE_serialkiller-rs-stable"))
+        .current_dir(dir.path())
+        .args(["serialkiller", "kdv", "init", "--algo", "blake3", "-b", "baseline.json", "disk.img"])
+        .output()
+        .unwrap();
+    assert_eq!(init.status.code(), Some(0), "{}", String::from_utf8_lossy(&init.stderr));
+    let manifest: serde_json::Value = serde_json::from_slice(&fs::read(dir.path().join("baseline.json")).unwrap()).unwrap();
+    assert_eq!(manifest["entries"][0]["size"], 2u64 << 30);
+
+    let peak = children_peak_rss();
+    assert!(peak < 128 << 20, "kdv init peaked at {} bytes for a 2 GiB file", peak);
+}